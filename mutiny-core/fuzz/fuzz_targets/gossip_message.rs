@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mutiny_core::fuzz::FuzzHarness;
+
+fuzz_target!(|data: &[u8]| {
+    FuzzHarness::new().gossip_message(data);
+});
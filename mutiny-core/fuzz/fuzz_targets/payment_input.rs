@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mutiny_core::fuzz::parse_payment_input;
+
+fuzz_target!(|data: &[u8]| {
+    parse_payment_input(data);
+});
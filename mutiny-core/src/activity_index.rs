@@ -0,0 +1,230 @@
+//! An index of the wallet's lightning activity, updated as payments and
+//! channel closures are saved, so a page of activity only reads the entries
+//! on it instead of scanning every payment.
+//!
+//! Entries are grouped by the month they were last updated in, newest first.
+//! Each entry also records its timestamp on its own so an update can find
+//! and move it without looking through every month.
+//!
+//! Activity for a label or contact is found through the invoices already
+//! tracked by each [crate::labels::LabelItem].
+
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::sha256;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+pub const ACTIVITY_MONTH_PREFIX: &str = "activity_month/";
+const ACTIVITY_ENTRY_PREFIX: &str = "activity_entry/";
+const ACTIVITY_INDEX_VERSION_KEY: &str = "activity_index_version";
+/// Bump to rebuild the index from scratch on the next load
+const ACTIVITY_INDEX_VERSION: u32 = 1;
+
+/// Points to an item of activity in storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ActivityRef {
+    Lightning {
+        payment_hash: sha256::Hash,
+        inbound: bool,
+    },
+    ChannelClosed(u128),
+}
+
+impl ActivityRef {
+    fn key(&self) -> String {
+        match self {
+            ActivityRef::Lightning {
+                payment_hash,
+                inbound,
+            } => {
+                let direction = if *inbound { "in" } else { "out" };
+                format!(
+                    "{ACTIVITY_ENTRY_PREFIX}ln_{direction}_{}",
+                    payment_hash.to_hex()
+                )
+            }
+            ActivityRef::ChannelClosed(id) => {
+                format!(
+                    "{ACTIVITY_ENTRY_PREFIX}closure_{}",
+                    id.to_be_bytes().to_hex()
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityIndexEntry {
+    pub activity: ActivityRef,
+    pub timestamp: u64,
+}
+
+/// The month an entry is grouped in, formatted so months sort by time
+fn month_key(timestamp: u64) -> String {
+    let month = NaiveDateTime::from_timestamp_opt(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m");
+    format!("{ACTIVITY_MONTH_PREFIX}{month}")
+}
+
+pub trait ActivityIndexStorage {
+    /// Adds an item to the index, or moves it if it was updated
+    fn index_activity(&self, activity: ActivityRef, timestamp: u64) -> Result<(), MutinyError>;
+    /// Whether the index has been built for the existing activity
+    fn is_activity_index_built(&self) -> Result<bool, MutinyError>;
+    fn set_activity_index_built(&self) -> Result<(), MutinyError>;
+    /// The keys of the months with activity, newest first
+    fn list_activity_months(&self) -> Result<Vec<String>, MutinyError>;
+    fn get_activity_month(&self, month: &str) -> Result<Vec<ActivityIndexEntry>, MutinyError>;
+
+    /// Gets up to `limit` entries older than `before`, newest first.
+    /// Only reads the months needed to fill the page.
+    fn activity_page(
+        &self,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<ActivityIndexEntry>, MutinyError> {
+        let first_month = before.map(month_key);
+        let mut page = Vec::with_capacity(limit);
+        for month in self.list_activity_months()? {
+            if page.len() >= limit {
+                break;
+            }
+            if first_month.as_ref().is_some_and(|first| month > *first) {
+                continue;
+            }
+
+            let entries = self.get_activity_month(&month)?;
+            page.extend(
+                entries
+                    .into_iter()
+                    .filter(|e| before.map_or(true, |b| e.timestamp < b))
+                    .take(limit - page.len()),
+            );
+        }
+
+        Ok(page)
+    }
+}
+
+impl<S: MutinyStorage> ActivityIndexStorage for S {
+    fn index_activity(&self, activity: ActivityRef, timestamp: u64) -> Result<(), MutinyError> {
+        let entry_key = activity.key();
+        let previous: Option<u64> = self.get_data(&entry_key)?;
+        if previous == Some(timestamp) {
+            return Ok(());
+        }
+
+        // take it out of the month it was in
+        if let Some(previous) = previous {
+            let key = month_key(previous);
+            let mut entries = self.get_activity_month(&key)?;
+            entries.retain(|e| e.activity != activity);
+            if entries.is_empty() {
+                self.delete(&[key])?;
+            } else {
+                self.set_data(key, entries, None)?;
+            }
+        }
+
+        let key = month_key(timestamp);
+        let mut entries = self.get_activity_month(&key)?;
+        entries.retain(|e| e.activity != activity);
+        let index = entries.partition_point(|e| e.timestamp >= timestamp);
+        entries.insert(
+            index,
+            ActivityIndexEntry {
+                activity,
+                timestamp,
+            },
+        );
+        self.set_data(key, entries, None)?;
+        self.set_data(entry_key, timestamp, None)
+    }
+
+    fn is_activity_index_built(&self) -> Result<bool, MutinyError> {
+        let version: Option<u32> = self.get_data(ACTIVITY_INDEX_VERSION_KEY)?;
+        Ok(version.is_some_and(|v| v >= ACTIVITY_INDEX_VERSION))
+    }
+
+    fn set_activity_index_built(&self) -> Result<(), MutinyError> {
+        self.set_data(ACTIVITY_INDEX_VERSION_KEY, ACTIVITY_INDEX_VERSION, None)
+    }
+
+    fn list_activity_months(&self) -> Result<Vec<String>, MutinyError> {
+        let mut months = self.scan_keys(ACTIVITY_MONTH_PREFIX, None)?;
+        months.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(months)
+    }
+
+    fn get_activity_month(&self, month: &str) -> Result<Vec<ActivityIndexEntry>, MutinyError> {
+        Ok(self.get_data(month)?.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // 2023-01-15 and 2023-02-15
+    const JANUARY: u64 = 1673740800;
+    const FEBRUARY: u64 = 1676419200;
+
+    fn payment(i: u8) -> ActivityRef {
+        ActivityRef::Lightning {
+            payment_hash: sha256::Hash::hash(&[i]),
+            inbound: true,
+        }
+    }
+
+    #[test]
+    fn test_index_activity() {
+        log!("test index activity");
+
+        let storage = MemoryStorage::default();
+        assert!(!storage.is_activity_index_built().unwrap());
+
+        storage.index_activity(payment(1), JANUARY).unwrap();
+        storage.index_activity(payment(2), JANUARY + 10).unwrap();
+        storage
+            .index_activity(ActivityRef::ChannelClosed(1), FEBRUARY)
+            .unwrap();
+        assert_eq!(
+            storage.list_activity_months().unwrap(),
+            vec![month_key(FEBRUARY), month_key(JANUARY)]
+        );
+
+        // pages are newest first and continue where the last one ended
+        let page = storage.activity_page(None, 2).unwrap();
+        let activity: Vec<_> = page.iter().map(|e| e.activity).collect();
+        assert_eq!(activity, vec![ActivityRef::ChannelClosed(1), payment(2)]);
+        let page = storage.activity_page(Some(page[1].timestamp), 2).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].activity, payment(1));
+
+        // updating an entry moves it, emptied months are removed
+        storage.index_activity(payment(1), FEBRUARY + 10).unwrap();
+        storage.index_activity(payment(2), FEBRUARY + 20).unwrap();
+        assert_eq!(
+            storage.list_activity_months().unwrap(),
+            vec![month_key(FEBRUARY)]
+        );
+        let page = storage.activity_page(None, 10).unwrap();
+        let activity: Vec<_> = page.iter().map(|e| e.activity).collect();
+        assert_eq!(
+            activity,
+            vec![payment(2), payment(1), ActivityRef::ChannelClosed(1)]
+        );
+
+        storage.set_activity_index_built().unwrap();
+        assert!(storage.is_activity_index_built().unwrap());
+    }
+}
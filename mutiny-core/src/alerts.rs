@@ -0,0 +1,330 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::ToHex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const ALERT_RULES_KEY: &str = "alert_rules";
+pub const ALERT_EVENTS_KEY: &str = "alert_events";
+pub const CHANNELS_OFFLINE_SINCE_KEY: &str = "channels_offline_since";
+
+/// Only keep the most recent alert events
+const MAX_ALERT_EVENTS: usize = 50;
+
+/// Only payments from the last day count towards the failure rate
+pub(crate) const FAILURE_RATE_WINDOW_SECS: u64 = 60 * 60 * 24;
+
+/// The failure rate of just a couple payments isn't meaningful
+const MIN_PAYMENTS_FOR_FAILURE_RATE: u64 = 3;
+
+/// What an alert watches for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The total on-chain and lightning balance is below this many sats
+    BalanceBelow { sats: u64 },
+    /// More than this percent of the outgoing payments in the last day failed
+    PaymentFailureRateAbove { percent: u8 },
+    /// A channel has been unusable because its peer is offline for this many hours
+    ChannelOfflineFor { hours: u64 },
+}
+
+impl AlertCondition {
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        match self {
+            AlertCondition::BalanceBelow { .. } => Ok(()),
+            AlertCondition::PaymentFailureRateAbove { percent } if *percent < 100 => Ok(()),
+            AlertCondition::ChannelOfflineFor { hours } if *hours > 0 => Ok(()),
+            _ => Err(MutinyError::InvalidArgumentsError),
+        }
+    }
+
+    pub(crate) fn is_met(&self, metrics: &AlertMetrics) -> bool {
+        match self {
+            AlertCondition::BalanceBelow { sats } => metrics.balance_sats < *sats,
+            AlertCondition::PaymentFailureRateAbove { percent } => metrics
+                .failure_rate_percent()
+                .is_some_and(|rate| rate > *percent as u64),
+            AlertCondition::ChannelOfflineFor { hours } => {
+                metrics.longest_offline_secs >= hours * 60 * 60
+            }
+        }
+    }
+
+    fn message(&self, metrics: &AlertMetrics) -> String {
+        match self {
+            AlertCondition::BalanceBelow { sats } => {
+                format!(
+                    "Balance is {} sats, below {sats} sats",
+                    metrics.balance_sats
+                )
+            }
+            AlertCondition::PaymentFailureRateAbove { percent } => format!(
+                "{} of {} payments failed in the last day, above {percent}%",
+                metrics.payments_failed,
+                metrics.payments_failed + metrics.payments_succeeded
+            ),
+            AlertCondition::ChannelOfflineFor { hours } => format!(
+                "A channel has been offline for {} hours, over {hours} hours",
+                metrics.longest_offline_secs / 60 / 60
+            ),
+        }
+    }
+}
+
+/// An alert the user has set up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: String,
+    pub condition: AlertCondition,
+    pub created_at: u64,
+    /// Whether the condition was met at the last check,
+    /// we only fire again once it stops being met
+    #[serde(default)]
+    triggered: bool,
+}
+
+impl AlertRule {
+    pub(crate) fn new(condition: AlertCondition, now: u64) -> Self {
+        let id: [u8; 16] = bitcoin::secp256k1::rand::random();
+        Self {
+            id: id.to_hex(),
+            condition,
+            created_at: now,
+            triggered: false,
+        }
+    }
+
+    /// Returns an event if the condition has just been met
+    pub(crate) fn evaluate(&mut self, metrics: &AlertMetrics, now: u64) -> Option<AlertEvent> {
+        let met = self.condition.is_met(metrics);
+        let fire = met && !self.triggered;
+        self.triggered = met;
+
+        if !fire {
+            return None;
+        }
+
+        Some(AlertEvent {
+            rule_id: self.id.clone(),
+            condition: self.condition.clone(),
+            message: self.condition.message(metrics),
+            created_at: now,
+        })
+    }
+}
+
+/// Emitted when an alert rule's condition is met
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub rule_id: String,
+    pub condition: AlertCondition,
+    pub message: String,
+    pub created_at: u64,
+}
+
+/// What the alert rules are checked against
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AlertMetrics {
+    pub balance_sats: u64,
+    /// Outgoing payments in the failure rate window
+    pub payments_succeeded: u64,
+    pub payments_failed: u64,
+    /// How long the channel that has been offline the longest has been offline
+    pub longest_offline_secs: u64,
+}
+
+impl AlertMetrics {
+    fn failure_rate_percent(&self) -> Option<u64> {
+        let total = self.payments_succeeded + self.payments_failed;
+        if total < MIN_PAYMENTS_FOR_FAILURE_RATE {
+            return None;
+        }
+
+        Some(self.payments_failed * 100 / total)
+    }
+}
+
+/// Updates when each channel went offline with the channels that are offline now.
+/// Returns how long the one that has been offline the longest has been offline.
+pub(crate) fn update_offline_since(
+    offline_since: &mut HashMap<String, u64>,
+    offline: &[String],
+    now: u64,
+) -> u64 {
+    offline_since.retain(|id, _| offline.contains(id));
+    for id in offline {
+        offline_since.entry(id.clone()).or_insert(now);
+    }
+
+    offline_since
+        .values()
+        .map(|since| now.saturating_sub(*since))
+        .max()
+        .unwrap_or(0)
+}
+
+pub trait AlertStorage {
+    fn get_alert_rules(&self) -> Result<Vec<AlertRule>, MutinyError>;
+    fn persist_alert_rules(&self, rules: Vec<AlertRule>) -> Result<(), MutinyError>;
+    fn get_alert_events(&self) -> Result<Vec<AlertEvent>, MutinyError>;
+    fn push_alert_events(&self, events: Vec<AlertEvent>) -> Result<(), MutinyError>;
+    fn clear_alert_events(&self) -> Result<(), MutinyError>;
+    /// When each unusable channel went offline, keyed by user channel id
+    fn get_channels_offline_since(&self) -> Result<HashMap<String, u64>, MutinyError>;
+    fn set_channels_offline_since(
+        &self,
+        offline_since: HashMap<String, u64>,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> AlertStorage for S {
+    fn get_alert_rules(&self) -> Result<Vec<AlertRule>, MutinyError> {
+        let rules: Option<Vec<AlertRule>> = self.get_data(ALERT_RULES_KEY)?;
+        Ok(rules.unwrap_or_default())
+    }
+
+    fn persist_alert_rules(&self, rules: Vec<AlertRule>) -> Result<(), MutinyError> {
+        self.set_data(ALERT_RULES_KEY, rules, None)
+    }
+
+    fn get_alert_events(&self) -> Result<Vec<AlertEvent>, MutinyError> {
+        let events: Option<Vec<AlertEvent>> = self.get_data(ALERT_EVENTS_KEY)?;
+        Ok(events.unwrap_or_default())
+    }
+
+    fn push_alert_events(&self, new_events: Vec<AlertEvent>) -> Result<(), MutinyError> {
+        let mut events = self.get_alert_events()?;
+        events.extend(new_events);
+        if events.len() > MAX_ALERT_EVENTS {
+            let start_index = events.len() - MAX_ALERT_EVENTS;
+            events.drain(..start_index);
+        }
+        self.set_data(ALERT_EVENTS_KEY, events, None)
+    }
+
+    fn clear_alert_events(&self) -> Result<(), MutinyError> {
+        self.delete(&[ALERT_EVENTS_KEY])
+    }
+
+    fn get_channels_offline_since(&self) -> Result<HashMap<String, u64>, MutinyError> {
+        let offline_since: Option<HashMap<String, u64>> =
+            self.get_data(CHANNELS_OFFLINE_SINCE_KEY)?;
+        Ok(offline_since.unwrap_or_default())
+    }
+
+    fn set_channels_offline_since(
+        &self,
+        offline_since: HashMap<String, u64>,
+    ) -> Result<(), MutinyError> {
+        self.set_data(CHANNELS_OFFLINE_SINCE_KEY, offline_since, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const HOUR: u64 = 60 * 60;
+
+    #[test]
+    fn test_alert_conditions() {
+        log!("test alert conditions");
+
+        let metrics = AlertMetrics {
+            balance_sats: 10_000,
+            payments_succeeded: 2,
+            payments_failed: 2,
+            longest_offline_secs: 3 * HOUR,
+        };
+
+        assert!(AlertCondition::BalanceBelow { sats: 20_000 }.is_met(&metrics));
+        assert!(!AlertCondition::BalanceBelow { sats: 10_000 }.is_met(&metrics));
+        assert!(AlertCondition::PaymentFailureRateAbove { percent: 40 }.is_met(&metrics));
+        assert!(!AlertCondition::PaymentFailureRateAbove { percent: 50 }.is_met(&metrics));
+        assert!(AlertCondition::ChannelOfflineFor { hours: 3 }.is_met(&metrics));
+        assert!(!AlertCondition::ChannelOfflineFor { hours: 4 }.is_met(&metrics));
+
+        // too few payments to have a failure rate
+        let metrics = AlertMetrics {
+            payments_failed: 2,
+            ..Default::default()
+        };
+        assert!(!AlertCondition::PaymentFailureRateAbove { percent: 0 }.is_met(&metrics));
+
+        assert!(AlertCondition::PaymentFailureRateAbove { percent: 100 }
+            .validate()
+            .is_err());
+        assert!(AlertCondition::ChannelOfflineFor { hours: 0 }
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_alert_rule_fires_once() {
+        log!("test alert rule fires once");
+
+        let mut rule = AlertRule::new(AlertCondition::BalanceBelow { sats: 1_000 }, 0);
+        let low = AlertMetrics {
+            balance_sats: 500,
+            ..Default::default()
+        };
+        let high = AlertMetrics {
+            balance_sats: 5_000,
+            ..Default::default()
+        };
+
+        let event = rule.evaluate(&low, 1).unwrap();
+        assert_eq!(event.rule_id, rule.id);
+        assert_eq!(event.created_at, 1);
+
+        // still low, already fired
+        assert!(rule.evaluate(&low, 2).is_none());
+
+        // fires again after recovering
+        assert!(rule.evaluate(&high, 3).is_none());
+        assert!(rule.evaluate(&low, 4).is_some());
+    }
+
+    #[test]
+    fn test_update_offline_since() {
+        log!("test update offline since");
+
+        let mut offline_since = HashMap::new();
+        let offline = vec!["a".to_string()];
+        assert_eq!(update_offline_since(&mut offline_since, &offline, 100), 0);
+        assert_eq!(
+            update_offline_since(&mut offline_since, &offline, 100 + HOUR),
+            HOUR
+        );
+
+        // back online
+        assert_eq!(update_offline_since(&mut offline_since, &[], 200 + HOUR), 0);
+        assert!(offline_since.is_empty());
+    }
+
+    #[test]
+    fn test_alert_storage() {
+        log!("test alert storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_alert_rules().unwrap().is_empty());
+        assert!(storage.get_alert_events().unwrap().is_empty());
+
+        let mut rule = AlertRule::new(AlertCondition::BalanceBelow { sats: 1_000 }, 0);
+        storage.persist_alert_rules(vec![rule.clone()]).unwrap();
+        assert_eq!(storage.get_alert_rules().unwrap(), vec![rule.clone()]);
+
+        let event = rule.evaluate(&AlertMetrics::default(), 1).unwrap();
+        let events = vec![event; MAX_ALERT_EVENTS + 1];
+        storage.push_alert_events(events).unwrap();
+        assert_eq!(storage.get_alert_events().unwrap().len(), MAX_ALERT_EVENTS);
+        storage.clear_alert_events().unwrap();
+        assert!(storage.get_alert_events().unwrap().is_empty());
+    }
+}
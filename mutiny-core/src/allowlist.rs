@@ -0,0 +1,92 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+
+pub const PEER_ALLOWLIST_KEY: &str = "peer_allowlist";
+
+/// A privacy mode where the node only talks to peers the user chose.
+///
+/// When enabled we only connect to, and accept connections from, our LSP
+/// and the listed peers. Everyone else is disconnected as soon as they identify
+/// themselves. Our channels are never announced, so no node announcement is
+/// ever broadcast for peers outside the list to find us by.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerAllowlist {
+    pub enabled: bool,
+    pub peers: Vec<PublicKey>,
+}
+
+impl PeerAllowlist {
+    /// Returns true if we can be connected to the given peer.
+    /// Our LSP is always allowed.
+    pub(crate) fn is_allowed(&self, node_id: &PublicKey, lsp_pubkey: Option<&PublicKey>) -> bool {
+        !self.enabled || lsp_pubkey == Some(node_id) || self.peers.contains(node_id)
+    }
+}
+
+pub trait PeerAllowlistStorage {
+    fn get_peer_allowlist(&self) -> Result<PeerAllowlist, MutinyError>;
+    fn set_peer_allowlist(&self, allowlist: PeerAllowlist) -> Result<(), MutinyError>;
+
+    /// Returns true if we can be connected to the given peer.
+    /// If the allowlist can't be read, only the LSP is allowed.
+    fn is_peer_allowed(&self, node_id: &PublicKey, lsp_pubkey: Option<&PublicKey>) -> bool {
+        match self.get_peer_allowlist() {
+            Ok(allowlist) => allowlist.is_allowed(node_id, lsp_pubkey),
+            Err(_) => lsp_pubkey == Some(node_id),
+        }
+    }
+}
+
+impl<S: MutinyStorage> PeerAllowlistStorage for S {
+    fn get_peer_allowlist(&self) -> Result<PeerAllowlist, MutinyError> {
+        let allowlist: Option<PeerAllowlist> = self.get_data(PEER_ALLOWLIST_KEY)?;
+        Ok(allowlist.unwrap_or_default())
+    }
+
+    fn set_peer_allowlist(&self, allowlist: PeerAllowlist) -> Result<(), MutinyError> {
+        self.set_data(PEER_ALLOWLIST_KEY, allowlist, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const FRIEND_PUBKEY: &str =
+        "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b";
+    const LSP_PUBKEY: &str = "03864ef025fde8fb587d989186ce6a4a186895ee44a926bfc370e2c366597a3f8f";
+    const STRANGER_PUBKEY: &str =
+        "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54";
+
+    #[test]
+    fn test_peer_allowlist() {
+        log!("test peer allowlist");
+
+        let friend = PublicKey::from_str(FRIEND_PUBKEY).unwrap();
+        let lsp = PublicKey::from_str(LSP_PUBKEY).unwrap();
+        let stranger = PublicKey::from_str(STRANGER_PUBKEY).unwrap();
+
+        let storage = MemoryStorage::default();
+        // everyone is allowed by default
+        assert!(storage.is_peer_allowed(&stranger, Some(&lsp)));
+
+        storage
+            .set_peer_allowlist(PeerAllowlist {
+                enabled: true,
+                peers: vec![friend],
+            })
+            .unwrap();
+        assert!(storage.is_peer_allowed(&friend, Some(&lsp)));
+        assert!(storage.is_peer_allowed(&lsp, Some(&lsp)));
+        assert!(!storage.is_peer_allowed(&stranger, Some(&lsp)));
+        assert!(!storage.is_peer_allowed(&lsp, None));
+    }
+}
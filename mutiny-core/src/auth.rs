@@ -1,14 +1,17 @@
 #![allow(dead_code)]
 use crate::{
     error::MutinyError,
+    http_client::HttpRequest,
     lnurlauth::{make_lnurl_auth_connection, AuthManager},
     logging::MutinyLogger,
     networking::websocket::{SimpleWebSocket, WebSocketImpl},
+    service_auth::ServiceKeys,
 };
 use jwt_compact::UntrustedToken;
 use lightning::util::logger::*;
 use lightning::{log_error, log_info};
 use lnurl::{lnurl::LnUrl, AsyncClient as LnUrlClient};
+use reqwest::header::CONTENT_TYPE;
 use reqwest::Client;
 use reqwest::{Method, StatusCode, Url};
 use serde::{Deserialize, Serialize};
@@ -26,6 +29,7 @@ pub struct MutinyAuthClient {
     lnurl_client: Arc<LnUrlClient>,
     url: String,
     http_client: Client,
+    service_keys: ServiceKeys,
     jwt: RwLock<Option<String>>,
     logger: Arc<MutinyLogger>,
 }
@@ -38,11 +42,13 @@ impl MutinyAuthClient {
         url: String,
     ) -> Self {
         let http_client = Client::new();
+        let service_keys = auth.service_keys();
         Self {
             auth,
             lnurl_client,
             url,
             http_client,
+            service_keys,
             jwt: RwLock::new(None),
             logger,
         }
@@ -89,7 +95,19 @@ impl MutinyAuthClient {
         url: Url,
         body: Option<Value>,
     ) -> Result<reqwest::Response, MutinyError> {
+        let body = body.map(|json| serde_json::to_vec(&json)).transpose()?;
+        // sign the exact bytes we send, so the service can check them
+        let signed = self.service_keys.sign_request(HttpRequest {
+            method: method.clone(),
+            url: url.to_string(),
+            headers: vec![],
+            body: body.clone(),
+        })?;
+
         let mut request = self.http_client.request(method, url);
+        for (name, value) in signed.headers {
+            request = request.header(name, value);
+        }
 
         let mut jwt = self.is_authenticated();
         if jwt.is_none() {
@@ -97,8 +115,8 @@ impl MutinyAuthClient {
         }
         request = request.bearer_auth(jwt.expect("either had one or retrieved new"));
 
-        if let Some(json) = body {
-            request = request.json(&json);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body);
         }
 
         request
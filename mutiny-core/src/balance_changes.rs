@@ -0,0 +1,294 @@
+use crate::error::MutinyError;
+use crate::nodemanager::TransactionDetails;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub const BALANCE_CHANGES_KEY: &str = "balance_changes";
+pub const BALANCE_CHANGE_TXIDS_KEY: &str = "balance_change_txids";
+
+/// Only keep the most recent balance changes
+const MAX_BALANCE_CHANGES: usize = 1_000;
+
+/// Why a balance changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BalanceChangeReason {
+    /// A payment we sent or received
+    Payment,
+    /// Fees paid to route a lightning payment
+    RoutingFee,
+    /// Fees paid for an on-chain transaction
+    OnChainFee,
+    /// Funds from a force closed channel swept back into the on-chain wallet
+    Sweep,
+    /// Fees paid to the LSP for receiving through a new channel
+    LspFee,
+    /// Funds moved between on-chain and lightning by opening or closing a channel
+    Swap,
+}
+
+/// Which balance changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BalanceLayer {
+    OnChain,
+    Lightning,
+}
+
+/// A single change to the wallet's balance.
+///
+/// Summing the changes for a layer explains how its balance got to where it is.
+/// Channel opens and closes are recorded as swaps on the on-chain side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub reason: BalanceChangeReason,
+    pub layer: BalanceLayer,
+    /// Positive if the balance went up, negative if it went down
+    pub amount_sats: i64,
+    /// The payment hash or txid that caused the change
+    pub reference: Option<String>,
+    pub timestamp: u64,
+}
+
+impl BalanceChange {
+    pub(crate) fn new(
+        reason: BalanceChangeReason,
+        layer: BalanceLayer,
+        amount_sats: i64,
+        reference: Option<String>,
+    ) -> Self {
+        Self {
+            reason,
+            layer,
+            amount_sats,
+            reference,
+            timestamp: utils::now().as_secs(),
+        }
+    }
+}
+
+/// Explains an on-chain wallet transaction as a list of balance changes.
+///
+/// `funding_outpoints` are the funding outputs of our channels and
+/// `channel_txids` are the transactions our channel monitors are watching,
+/// such as commitment and HTLC transactions.
+pub(crate) fn onchain_balance_changes(
+    details: &TransactionDetails,
+    funding_outpoints: &HashSet<OutPoint>,
+    channel_txids: &HashSet<Txid>,
+) -> Vec<BalanceChange> {
+    let reference = Some(details.txid.to_string());
+    let fee = details.fee.unwrap_or(0) as i64;
+    let received = details.received as i64;
+    let sent = details.sent as i64;
+
+    let (is_funding, is_close, is_sweep) = match details.transaction.as_ref() {
+        Some(tx) => {
+            let txid = tx.txid();
+            let is_funding = (0..tx.output.len())
+                .any(|vout| funding_outpoints.contains(&OutPoint::new(txid, vout as u32)));
+            let is_close = tx
+                .input
+                .iter()
+                .any(|i| funding_outpoints.contains(&i.previous_output));
+            let is_sweep = tx
+                .input
+                .iter()
+                .any(|i| channel_txids.contains(&i.previous_output.txid));
+            (is_funding, is_close, is_sweep)
+        }
+        None => (false, false, false),
+    };
+
+    let onchain = |reason, amount_sats| {
+        BalanceChange::new(
+            reason,
+            BalanceLayer::OnChain,
+            amount_sats,
+            reference.clone(),
+        )
+    };
+
+    let mut changes = vec![];
+    if sent > 0 {
+        // the fee is included in what we sent
+        let reason = if is_funding {
+            BalanceChangeReason::Swap
+        } else {
+            BalanceChangeReason::Payment
+        };
+        let amount = sent - received - fee;
+        if amount != 0 {
+            changes.push(onchain(reason, -amount));
+        }
+        if fee > 0 {
+            changes.push(onchain(BalanceChangeReason::OnChainFee, -fee));
+        }
+    } else if received > 0 {
+        let reason = if is_close {
+            BalanceChangeReason::Swap
+        } else if is_sweep {
+            BalanceChangeReason::Sweep
+        } else {
+            BalanceChangeReason::Payment
+        };
+        changes.push(onchain(reason, received));
+    }
+
+    changes
+}
+
+pub trait BalanceChangeStorage {
+    fn get_balance_changes(&self) -> Result<Vec<BalanceChange>, MutinyError>;
+    fn record_balance_changes(&self, changes: Vec<BalanceChange>) -> Result<(), MutinyError>;
+    /// The on-chain transactions we have already recorded balance changes for,
+    /// `None` if we have never checked.
+    fn get_balance_change_txids(&self) -> Result<Option<HashSet<Txid>>, MutinyError>;
+    fn set_balance_change_txids(&self, txids: HashSet<Txid>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> BalanceChangeStorage for S {
+    fn get_balance_changes(&self) -> Result<Vec<BalanceChange>, MutinyError> {
+        let changes: Option<Vec<BalanceChange>> = self.get_data(BALANCE_CHANGES_KEY)?;
+        Ok(changes.unwrap_or_default())
+    }
+
+    fn record_balance_changes(&self, changes: Vec<BalanceChange>) -> Result<(), MutinyError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut all = self.get_balance_changes()?;
+        all.extend(changes);
+        if all.len() > MAX_BALANCE_CHANGES {
+            let start_index = all.len() - MAX_BALANCE_CHANGES;
+            all.drain(..start_index);
+        }
+        self.set_data(BALANCE_CHANGES_KEY, all, None)
+    }
+
+    fn get_balance_change_txids(&self) -> Result<Option<HashSet<Txid>>, MutinyError> {
+        self.get_data(BALANCE_CHANGE_TXIDS_KEY)
+    }
+
+    fn set_balance_change_txids(&self, txids: HashSet<Txid>) -> Result<(), MutinyError> {
+        self.set_data(BALANCE_CHANGE_TXIDS_KEY, txids, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bdk::chain::ConfirmationTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{PackedLockTime, Script, Transaction, TxIn, TxOut};
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_details(tx: Transaction, sent: u64, received: u64, fee: u64) -> TransactionDetails {
+        TransactionDetails {
+            txid: tx.txid(),
+            transaction: Some(tx),
+            received,
+            sent,
+            fee: Some(fee),
+            confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+            labels: vec![],
+        }
+    }
+
+    fn dummy_tx(prev: OutPoint) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prev,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_onchain_balance_changes() {
+        log!("test onchain balance changes");
+
+        let prev = OutPoint::new(Txid::all_zeros(), 0);
+        let no_outpoints = HashSet::new();
+        let no_txids = HashSet::new();
+
+        // receive
+        let details = dummy_details(dummy_tx(prev), 0, 10_000, 0);
+        let changes = onchain_balance_changes(&details, &no_outpoints, &no_txids);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].reason, BalanceChangeReason::Payment);
+        assert_eq!(changes[0].amount_sats, 10_000);
+
+        // send with change
+        let details = dummy_details(dummy_tx(prev), 50_000, 30_000, 500);
+        let changes = onchain_balance_changes(&details, &no_outpoints, &no_txids);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].reason, BalanceChangeReason::Payment);
+        assert_eq!(changes[0].amount_sats, -19_500);
+        assert_eq!(changes[1].reason, BalanceChangeReason::OnChainFee);
+        assert_eq!(changes[1].amount_sats, -500);
+        let total: i64 = changes.iter().map(|c| c.amount_sats).sum();
+        assert_eq!(total, -20_000);
+
+        // channel funding
+        let tx = dummy_tx(prev);
+        let funding: HashSet<OutPoint> = [OutPoint::new(tx.txid(), 0)].into_iter().collect();
+        let details = dummy_details(tx, 50_000, 39_500, 500);
+        let changes = onchain_balance_changes(&details, &funding, &no_txids);
+        assert_eq!(changes[0].reason, BalanceChangeReason::Swap);
+        assert_eq!(changes[0].amount_sats, -10_000);
+
+        // cooperative close
+        let details = dummy_details(dummy_tx(prev), 0, 10_000, 0);
+        let funding: HashSet<OutPoint> = [prev].into_iter().collect();
+        let changes = onchain_balance_changes(&details, &funding, &no_txids);
+        assert_eq!(changes[0].reason, BalanceChangeReason::Swap);
+
+        // sweep from a force close
+        let details = dummy_details(dummy_tx(prev), 0, 10_000, 0);
+        let channel_txids: HashSet<Txid> = [prev.txid].into_iter().collect();
+        let changes = onchain_balance_changes(&details, &no_outpoints, &channel_txids);
+        assert_eq!(changes[0].reason, BalanceChangeReason::Sweep);
+    }
+
+    #[test]
+    fn test_balance_change_storage() {
+        log!("test balance change storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_balance_changes().unwrap().is_empty());
+        assert!(storage.get_balance_change_txids().unwrap().is_none());
+
+        let change = BalanceChange::new(
+            BalanceChangeReason::RoutingFee,
+            BalanceLayer::Lightning,
+            -1,
+            None,
+        );
+        for _ in 0..(MAX_BALANCE_CHANGES + 1) {
+            storage
+                .record_balance_changes(vec![change.clone()])
+                .unwrap();
+        }
+        assert_eq!(
+            storage.get_balance_changes().unwrap().len(),
+            MAX_BALANCE_CHANGES
+        );
+
+        let txids: HashSet<Txid> = [Txid::all_zeros()].into_iter().collect();
+        storage.set_balance_change_txids(txids.clone()).unwrap();
+        assert_eq!(storage.get_balance_change_txids().unwrap(), Some(txids));
+    }
+}
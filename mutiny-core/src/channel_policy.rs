@@ -0,0 +1,423 @@
+use crate::error::MutinyError;
+use crate::nodemanager::MutinyChannel;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, Network, OutPoint};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const CHANNEL_RULES_KEY: &str = "channel_rules";
+pub const CHANNEL_RULE_LOG_KEY: &str = "channel_rule_log";
+pub const CHANNEL_ACTIVITY_KEY: &str = "channel_activity";
+
+/// How many rule executions are kept in the log
+const MAX_RULE_LOG_ENTRIES: usize = 100;
+
+/// How long to wait before trying a rule that failed on a channel again
+const RULE_RETRY_SECS: u64 = 24 * 60 * 60;
+
+/// Something about a channel a rule can check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelCondition {
+    /// Our balance in the channel hasn't changed for this many days
+    InactiveDays { days: u64 },
+    /// The channel is smaller than this many sats
+    CapacityBelow { sats: u64 },
+    /// The channel is at least this many sats
+    CapacityAbove { sats: u64 },
+    /// Our balance in the channel is below this many sats
+    BalanceBelow { sats: u64 },
+    /// The channel is with this peer
+    Peer { pubkey: PublicKey },
+}
+
+impl ChannelCondition {
+    fn matches(&self, channel: &MutinyChannel, inactive_secs: u64) -> bool {
+        match self {
+            ChannelCondition::InactiveDays { days } => inactive_secs >= days * 24 * 60 * 60,
+            ChannelCondition::CapacityBelow { sats } => channel.size < *sats,
+            ChannelCondition::CapacityAbove { sats } => channel.size >= *sats,
+            ChannelCondition::BalanceBelow { sats } => channel.balance < *sats,
+            ChannelCondition::Peer { pubkey } => channel.peer == *pubkey,
+        }
+    }
+}
+
+/// What to do with a channel that matches a rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelAction {
+    /// Schedules a cooperative close, which waits for the fee rate
+    /// to be at or below `max_fee_rate` sat/vbyte if set
+    Close {
+        max_fee_rate: Option<f32>,
+        address: Option<Address>,
+    },
+}
+
+/// A rule that acts on every channel matching all of its conditions,
+/// for example closing small channels that have been unused for 90 days
+/// once fees are below 10 sat/vbyte:
+///
+/// ```json
+/// {
+///   "name": "close idle small channels",
+///   "conditions": [
+///     { "type": "inactive_days", "days": 90 },
+///     { "type": "capacity_below", "sats": 100000 }
+///   ],
+///   "action": { "type": "close", "max_fee_rate": 10.0, "address": null },
+///   "enabled": true
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelRule {
+    pub name: String,
+    pub conditions: Vec<ChannelCondition>,
+    pub action: ChannelAction,
+    /// Disabled rules are still shown in previews but never run
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ChannelRule {
+    pub(crate) fn matches(&self, channel: &MutinyChannel, inactive_secs: u64) -> bool {
+        self.conditions
+            .iter()
+            .all(|c| c.matches(channel, inactive_secs))
+    }
+}
+
+/// Checks the rules can be saved, every rule needs a unique name and at least
+/// one condition so a rule can't match every channel by accident.
+pub fn validate_rules(rules: &[ChannelRule], network: Network) -> Result<(), MutinyError> {
+    let mut names = HashSet::new();
+    for rule in rules {
+        if rule.name.trim().is_empty() || !names.insert(rule.name.as_str()) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        if rule.conditions.is_empty() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        match &rule.action {
+            ChannelAction::Close {
+                max_fee_rate,
+                address,
+            } => {
+                if max_fee_rate.is_some_and(|r| !r.is_finite() || r <= 0.0) {
+                    return Err(MutinyError::InvalidArgumentsError);
+                }
+                if let Some(address) = address {
+                    if !address.is_valid_for_network(network) {
+                        return Err(MutinyError::IncorrectNetwork(address.network));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A channel a rule matched, and what the rule would do with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleMatch {
+    pub rule: String,
+    pub outpoint: OutPoint,
+    pub action: ChannelAction,
+    /// False if the rule is disabled and so won't run
+    pub enabled: bool,
+}
+
+/// Finds the channels each rule matches. Channels that are still opening are
+/// skipped, and a channel is only matched by the first rule that matches it.
+pub(crate) fn evaluate_rules(
+    rules: &[ChannelRule],
+    channels: &[MutinyChannel],
+    activity: &HashMap<String, ChannelActivity>,
+    now: u64,
+) -> Vec<RuleMatch> {
+    channels
+        .iter()
+        .filter(|c| {
+            c.confirmations_required
+                .map_or(true, |r| c.confirmations >= r)
+        })
+        .filter_map(|c| {
+            let outpoint = c.outpoint?;
+            let inactive_secs = activity
+                .get(&outpoint.to_string())
+                .map_or(0, |a| now.saturating_sub(a.last_active));
+            let rule = rules.iter().find(|r| r.matches(c, inactive_secs))?;
+            Some(RuleMatch {
+                rule: rule.name.clone(),
+                outpoint,
+                action: rule.action.clone(),
+                enabled: rule.enabled,
+            })
+        })
+        .collect()
+}
+
+/// The last time a channel's balance changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelActivity {
+    pub balance: u64,
+    pub last_active: u64,
+}
+
+/// Records which channels' balances changed since last time, keyed by outpoint.
+/// Channels seen for the first time count as active now. Returns true if anything changed.
+pub(crate) fn update_channel_activity(
+    activity: &mut HashMap<String, ChannelActivity>,
+    channels: &[MutinyChannel],
+    now: u64,
+) -> bool {
+    let open: HashMap<String, u64> = channels
+        .iter()
+        .filter_map(|c| Some((c.outpoint?.to_string(), c.balance)))
+        .collect();
+
+    let len = activity.len();
+    activity.retain(|outpoint, _| open.contains_key(outpoint));
+    let mut changed = activity.len() != len;
+
+    for (outpoint, balance) in open {
+        match activity.get_mut(&outpoint) {
+            Some(a) if a.balance == balance => {}
+            Some(a) => {
+                a.balance = balance;
+                a.last_active = now;
+                changed = true;
+            }
+            None => {
+                let a = ChannelActivity {
+                    balance,
+                    last_active: now,
+                };
+                activity.insert(outpoint, a);
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+/// A rule that ran on a channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleExecution {
+    pub rule: String,
+    pub outpoint: OutPoint,
+    pub action: ChannelAction,
+    pub executed_at: u64,
+    /// Why the action failed, none if it succeeded
+    pub error: Option<String>,
+}
+
+/// Returns true if a rule already acted on the channel, or failed to recently
+pub(crate) fn already_handled(log: &[RuleExecution], outpoint: &OutPoint, now: u64) -> bool {
+    log.iter()
+        .filter(|e| e.outpoint == *outpoint)
+        .any(|e| e.error.is_none() || now < e.executed_at + RULE_RETRY_SECS)
+}
+
+pub trait ChannelPolicyStorage {
+    fn get_channel_rules(&self) -> Result<Vec<ChannelRule>, MutinyError>;
+    fn set_channel_rules(&self, rules: Vec<ChannelRule>) -> Result<(), MutinyError>;
+    fn get_channel_activity(&self) -> Result<HashMap<String, ChannelActivity>, MutinyError>;
+    fn set_channel_activity(
+        &self,
+        activity: HashMap<String, ChannelActivity>,
+    ) -> Result<(), MutinyError>;
+    /// The rules that ran, newest first
+    fn get_channel_rule_log(&self) -> Result<Vec<RuleExecution>, MutinyError>;
+    fn log_rule_execution(&self, execution: RuleExecution) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> ChannelPolicyStorage for S {
+    fn get_channel_rules(&self) -> Result<Vec<ChannelRule>, MutinyError> {
+        let rules: Option<Vec<ChannelRule>> = self.get_data(CHANNEL_RULES_KEY)?;
+        Ok(rules.unwrap_or_default())
+    }
+
+    fn set_channel_rules(&self, rules: Vec<ChannelRule>) -> Result<(), MutinyError> {
+        self.set_data(CHANNEL_RULES_KEY, rules, None)
+    }
+
+    fn get_channel_activity(&self) -> Result<HashMap<String, ChannelActivity>, MutinyError> {
+        let activity: Option<HashMap<String, ChannelActivity>> =
+            self.get_data(CHANNEL_ACTIVITY_KEY)?;
+        Ok(activity.unwrap_or_default())
+    }
+
+    fn set_channel_activity(
+        &self,
+        activity: HashMap<String, ChannelActivity>,
+    ) -> Result<(), MutinyError> {
+        self.set_data(CHANNEL_ACTIVITY_KEY, activity, None)
+    }
+
+    fn get_channel_rule_log(&self) -> Result<Vec<RuleExecution>, MutinyError> {
+        let log: Option<Vec<RuleExecution>> = self.get_data(CHANNEL_RULE_LOG_KEY)?;
+        Ok(log.unwrap_or_default())
+    }
+
+    fn log_rule_execution(&self, execution: RuleExecution) -> Result<(), MutinyError> {
+        let mut log = self.get_channel_rule_log()?;
+        log.insert(0, execution);
+        log.truncate(MAX_RULE_LOG_ENTRIES);
+        self.set_data(CHANNEL_RULE_LOG_KEY, log, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn dummy_channel(index: u32, size: u64, balance: u64) -> MutinyChannel {
+        MutinyChannel {
+            user_chan_id: index.to_string(),
+            balance,
+            size,
+            reserve: 0,
+            outpoint: Some(OutPoint::new(Txid::all_zeros(), index)),
+            peer: PublicKey::from_str(
+                "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+            )
+            .unwrap(),
+            confirmations_required: Some(1),
+            confirmations: 6,
+        }
+    }
+
+    fn idle_small_channels() -> ChannelRule {
+        ChannelRule {
+            name: "close idle small channels".to_string(),
+            conditions: vec![
+                ChannelCondition::InactiveDays { days: 90 },
+                ChannelCondition::CapacityBelow { sats: 100_000 },
+            ],
+            action: ChannelAction::Close {
+                max_fee_rate: Some(10.0),
+                address: None,
+            },
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        log!("test parse rules");
+
+        let json = r#"[{
+            "name": "close idle small channels",
+            "conditions": [
+                { "type": "inactive_days", "days": 90 },
+                { "type": "capacity_below", "sats": 100000 }
+            ],
+            "action": { "type": "close", "max_fee_rate": 10.0, "address": null }
+        }]"#;
+        let rules: Vec<ChannelRule> = serde_json::from_str(json).unwrap();
+        assert_eq!(rules, vec![idle_small_channels()]);
+        assert!(validate_rules(&rules, Network::Regtest).is_ok());
+
+        // a rule without conditions would match every channel
+        let rules = vec![ChannelRule {
+            conditions: vec![],
+            ..idle_small_channels()
+        }];
+        assert!(validate_rules(&rules, Network::Regtest).is_err());
+
+        let rules = vec![idle_small_channels(), idle_small_channels()];
+        assert!(validate_rules(&rules, Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_rules() {
+        log!("test evaluate rules");
+
+        let channels = vec![
+            dummy_channel(0, 50_000, 10_000),
+            dummy_channel(1, 500_000, 10_000),
+            dummy_channel(2, 50_000, 10_000),
+        ];
+        let mut activity = HashMap::new();
+        assert!(update_channel_activity(&mut activity, &channels, 0));
+        assert!(!update_channel_activity(&mut activity, &channels, DAY));
+
+        // channel 2 is used on day 10
+        let mut used = channels.clone();
+        used[2].balance = 20_000;
+        assert!(update_channel_activity(&mut activity, &used, DAY * 10));
+
+        let rules = vec![idle_small_channels()];
+        assert!(evaluate_rules(&rules, &used, &activity, DAY * 89).is_empty());
+
+        // only the small channel that hasn't been used in 90 days matches
+        let matches = evaluate_rules(&rules, &used, &activity, DAY * 95);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].outpoint, used[0].outpoint.unwrap());
+        assert_eq!(matches[0].rule, "close idle small channels");
+
+        // channels that are still opening are left alone
+        let mut opening = used.clone();
+        opening[0].confirmations = 0;
+        assert!(evaluate_rules(&rules, &opening, &activity, DAY * 95).is_empty());
+
+        // closed channels are forgotten
+        assert!(update_channel_activity(&mut activity, &used[1..], DAY * 95));
+        assert_eq!(activity.len(), 2);
+    }
+
+    #[test]
+    fn test_rule_log() {
+        log!("test rule log");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_channel_rule_log().unwrap().is_empty());
+
+        for i in 0..(MAX_RULE_LOG_ENTRIES as u64 + 5) {
+            let execution = RuleExecution {
+                rule: "close idle small channels".to_string(),
+                outpoint: OutPoint::new(Txid::all_zeros(), 0),
+                action: idle_small_channels().action,
+                executed_at: i,
+                error: None,
+            };
+            storage.log_rule_execution(execution).unwrap();
+        }
+
+        let log = storage.get_channel_rule_log().unwrap();
+        assert_eq!(log.len(), MAX_RULE_LOG_ENTRIES);
+        assert_eq!(log[0].executed_at, MAX_RULE_LOG_ENTRIES as u64 + 4);
+
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+        let other = OutPoint::new(Txid::all_zeros(), 1);
+        assert!(already_handled(&log, &outpoint, 0));
+        assert!(!already_handled(&log, &other, 0));
+
+        // failures are retried after a while
+        let failed = vec![RuleExecution {
+            error: Some("Channel not found.".to_string()),
+            ..log[0].clone()
+        }];
+        assert!(already_handled(&failed, &outpoint, 0));
+        assert!(!already_handled(&failed, &outpoint, RULE_RETRY_SECS * 2));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::error::MutinyError;
+use crate::ldkstorage::{
+    stale_vss_monitor_updates, ChecksummedBytes, CHANNEL_MANAGER_KEY, MONITORS_PREFIX_KEY,
+    MONITOR_UPDATES_PREFIX_KEY,
+};
+use crate::storage::{MutinyStorage, VersionedValue};
+use serde::{Deserialize, Serialize};
+
+/// Where the conflicts found by the last startup are saved for the app to show
+pub const STATE_CONFLICTS_KEY: &str = "state_conflicts";
+
+/// A piece of channel state where the remote backup is newer than what this device has.
+///
+/// This happens when another device used the wallet after this one last synced
+/// its state. Going online with the older state could broadcast a revoked
+/// commitment transaction and lose the channel's funds to a penalty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateConflict {
+    pub key: String,
+    /// The version we have locally, `None` if we don't have it at all
+    pub local_version: Option<u64>,
+    pub remote_version: u64,
+}
+
+/// Compares the channel manager, channel monitor and monitor update versions in
+/// the remote backup with the local ones, returning everything the remote has newer.
+pub(crate) async fn find_state_conflicts<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<StateConflict>, MutinyError> {
+    let Some(vss) = storage.vss_client() else {
+        return Ok(vec![]);
+    };
+
+    let keys = vss.list_key_versions(None).await?;
+    // updates already in the remote monitor are deleted at startup, they aren't newer state
+    let stale = stale_vss_monitor_updates(&keys);
+
+    let mut conflicts = vec![];
+    for kv in keys {
+        if !is_channel_state_key(&kv.key) || stale.contains(&kv) {
+            continue;
+        }
+
+        let remote_version = kv.version as u64;
+        let local_version = local_version(storage, &kv.key)?;
+        if local_version.map_or(true, |local| local < remote_version) {
+            conflicts.push(StateConflict {
+                key: kv.key,
+                local_version,
+                remote_version,
+            });
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Replaces our local channel state with the newer state from the remote backup,
+/// making this device the one that continues from where the other left off.
+pub(crate) async fn take_over_remote_state<S: MutinyStorage>(
+    storage: &S,
+    conflicts: &[StateConflict],
+) -> Result<(), MutinyError> {
+    let Some(vss) = storage.vss_client() else {
+        return Ok(());
+    };
+
+    for conflict in conflicts {
+        let obj = vss.get_object(&conflict.key).await?;
+        // don't pass a version, this is already what the remote has
+        storage.set_data(&conflict.key, obj.value, None)?;
+    }
+
+    storage.delete(&[STATE_CONFLICTS_KEY])
+}
+
+/// Saves the conflicts so the app can show them after startup refused to continue,
+/// clearing them when there are none.
+pub(crate) fn save_state_conflicts<S: MutinyStorage>(
+    storage: &S,
+    conflicts: &[StateConflict],
+) -> Result<(), MutinyError> {
+    if conflicts.is_empty() {
+        storage.delete(&[STATE_CONFLICTS_KEY])
+    } else {
+        storage.set_data(STATE_CONFLICTS_KEY, conflicts, None)
+    }
+}
+
+/// The conflicts found by the last startup, empty if it went online normally
+pub fn get_state_conflicts<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<StateConflict>, MutinyError> {
+    Ok(storage.get_data(STATE_CONFLICTS_KEY)?.unwrap_or_default())
+}
+
+fn is_channel_state_key(key: &str) -> bool {
+    key.starts_with(CHANNEL_MANAGER_KEY)
+        || key.starts_with(MONITORS_PREFIX_KEY)
+        || key.starts_with(MONITOR_UPDATES_PREFIX_KEY)
+}
+
+/// The version of the local channel state, the same versions that are written to VSS
+fn local_version<S: MutinyStorage>(storage: &S, key: &str) -> Result<Option<u64>, MutinyError> {
+    if key.starts_with(MONITOR_UPDATES_PREFIX_KEY) {
+        local_update_version(storage, key)
+    } else if key.starts_with(MONITORS_PREFIX_KEY) {
+        // monitors start with a version byte followed by the latest update id
+        let bytes: Option<ChecksummedBytes> = storage.get_data(key)?;
+        Ok(bytes.and_then(|bytes| {
+            let update_id: [u8; 8] = bytes.data().get(1..9)?.try_into().ok()?;
+            // update ids are capped at u32::MAX when written to VSS
+            Some(u64::from_be_bytes(update_id).min(u32::MAX as u64))
+        }))
+    } else {
+        let value: Option<VersionedValue> = storage.get_data(key)?;
+        Ok(value.map(|v| v.version as u64))
+    }
+}
+
+/// A monitor update is local state if we have it, or if our monitor already contains it
+fn local_update_version<S: MutinyStorage>(
+    storage: &S,
+    key: &str,
+) -> Result<Option<u64>, MutinyError> {
+    // monitor_updates/{txid}_{index}_{update_id}_{node_id}
+    let rest = key.trim_start_matches(MONITOR_UPDATES_PREFIX_KEY);
+    let parts: Vec<&str> = rest.splitn(4, '_').collect();
+    let [txid, index, update_id, node_id] = parts[..] else {
+        return Ok(None);
+    };
+    let Ok(update_id) = update_id.parse::<u64>() else {
+        return Ok(None);
+    };
+    let update_id = update_id.min(u32::MAX as u64);
+
+    if storage.get_data::<ChecksummedBytes>(key)?.is_some() {
+        return Ok(Some(update_id));
+    }
+
+    let monitor_key = format!("{MONITORS_PREFIX_KEY}{txid}_{index}_{node_id}");
+    Ok(local_version(storage, &monitor_key)?.filter(|version| *version >= update_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_local_version() {
+        log!("test local version");
+
+        let storage = MemoryStorage::default();
+        let manager_key = format!("{CHANNEL_MANAGER_KEY}_node");
+        let monitor_key = format!("{MONITORS_PREFIX_KEY}txid_0_node");
+        assert_eq!(local_version(&storage, &manager_key).unwrap(), None);
+        assert_eq!(local_version(&storage, &monitor_key).unwrap(), None);
+
+        let manager = VersionedValue {
+            version: 7,
+            value: serde_json::Value::String("00".to_string()),
+            checksum: None,
+        };
+        storage.set_data(&manager_key, manager, None).unwrap();
+        assert_eq!(local_version(&storage, &manager_key).unwrap(), Some(7));
+
+        let mut monitor = vec![1u8];
+        monitor.extend_from_slice(&42u64.to_be_bytes());
+        monitor.extend_from_slice(&[0; 16]);
+        storage
+            .set_data(&monitor_key, monitor.clone(), None)
+            .unwrap();
+        assert_eq!(local_version(&storage, &monitor_key).unwrap(), Some(42));
+        let checked = ChecksummedBytes::new(monitor);
+        storage.set_data(&monitor_key, checked, None).unwrap();
+        assert_eq!(local_version(&storage, &monitor_key).unwrap(), Some(42));
+
+        // an update our monitor contains is local state even once deleted
+        let update_key = format!("{MONITOR_UPDATES_PREFIX_KEY}txid_0_40_node");
+        assert_eq!(local_version(&storage, &update_key).unwrap(), Some(40));
+        let update_key = format!("{MONITOR_UPDATES_PREFIX_KEY}txid_0_43_node");
+        assert_eq!(local_version(&storage, &update_key).unwrap(), None);
+        storage.set_data(&update_key, vec![0u8], None).unwrap();
+        assert_eq!(local_version(&storage, &update_key).unwrap(), Some(43));
+
+        assert!(is_channel_state_key(&manager_key));
+        assert!(is_channel_state_key(&monitor_key));
+        assert!(is_channel_state_key(&update_key));
+        assert!(!is_channel_state_key("nodes"));
+    }
+
+    #[test]
+    fn test_save_state_conflicts() {
+        log!("test save state conflicts");
+
+        let storage = MemoryStorage::default();
+        assert!(get_state_conflicts(&storage).unwrap().is_empty());
+
+        let conflicts = vec![StateConflict {
+            key: format!("{CHANNEL_MANAGER_KEY}_node"),
+            local_version: Some(1),
+            remote_version: 2,
+        }];
+        save_state_conflicts(&storage, &conflicts).unwrap();
+        assert_eq!(get_state_conflicts(&storage).unwrap(), conflicts);
+
+        save_state_conflicts(&storage, &[]).unwrap();
+        assert!(get_state_conflicts(&storage).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::Network;
+use serde::{Deserialize, Serialize};
+use std::panic::PanicInfo;
+
+pub const CRASH_REPORTS_KEY: &str = "crash_reports";
+
+/// Only keep the most recent crash reports
+const MAX_CRASH_REPORTS: usize = 5;
+
+/// A summary of the wallet state at the time of a crash.
+/// This must never contain any secrets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSummary {
+    pub version: String,
+    pub network: Network,
+    pub node_count: usize,
+    pub channel_count: usize,
+    pub usable_channel_count: usize,
+    pub peer_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// Epoch time in seconds of when the crash happened
+    pub timestamp: u64,
+    pub message: String,
+    pub location: Option<String>,
+    pub recent_logs: Vec<String>,
+    /// Summary of the wallet state, this can be missing if
+    /// we could not get it without blocking
+    pub state: Option<StateSummary>,
+}
+
+impl CrashReport {
+    pub fn new(
+        info: &PanicInfo,
+        timestamp: u64,
+        recent_logs: Vec<String>,
+        state: Option<StateSummary>,
+    ) -> Self {
+        let payload = info.payload();
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+
+        Self {
+            timestamp,
+            message,
+            location: info.location().map(|l| l.to_string()),
+            recent_logs,
+            state,
+        }
+    }
+}
+
+pub trait CrashReportStorage {
+    fn get_crash_reports(&self) -> Result<Vec<CrashReport>, MutinyError>;
+    /// Saves the crash report, removing the oldest ones if we have too many
+    fn persist_crash_report(&self, report: CrashReport) -> Result<(), MutinyError>;
+    fn clear_crash_reports(&self) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> CrashReportStorage for S {
+    fn get_crash_reports(&self) -> Result<Vec<CrashReport>, MutinyError> {
+        let reports: Option<Vec<CrashReport>> = self.get_data(CRASH_REPORTS_KEY)?;
+        Ok(reports.unwrap_or_default())
+    }
+
+    fn persist_crash_report(&self, report: CrashReport) -> Result<(), MutinyError> {
+        let mut reports = self.get_crash_reports()?;
+        reports.push(report);
+        if reports.len() > MAX_CRASH_REPORTS {
+            let start_index = reports.len() - MAX_CRASH_REPORTS;
+            reports.drain(..start_index);
+        }
+        self.set_data(CRASH_REPORTS_KEY, reports, None)
+    }
+
+    fn clear_crash_reports(&self) -> Result<(), MutinyError> {
+        self.delete(&[CRASH_REPORTS_KEY])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_report(timestamp: u64) -> CrashReport {
+        CrashReport {
+            timestamp,
+            message: "oh no".to_string(),
+            location: Some("src/lib.rs:1:1".to_string()),
+            recent_logs: vec!["log".to_string()],
+            state: None,
+        }
+    }
+
+    #[test]
+    fn test_crash_report_storage() {
+        log!("test crash report storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_crash_reports().unwrap().is_empty());
+
+        for i in 0..(MAX_CRASH_REPORTS as u64 + 2) {
+            storage.persist_crash_report(dummy_report(i)).unwrap();
+        }
+
+        let reports = storage.get_crash_reports().unwrap();
+        assert_eq!(reports.len(), MAX_CRASH_REPORTS);
+        // oldest reports are removed first
+        assert_eq!(reports.first().unwrap().timestamp, 2);
+
+        storage.clear_crash_reports().unwrap();
+        assert!(storage.get_crash_reports().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+
+pub const DEVICE_REGISTRY_KEY: &str = "device_registry";
+
+/// What this version of the wallet can do, recorded so other devices know
+/// what a device in the registry supports.
+pub(crate) const DEVICE_CAPABILITIES: [&str; 4] =
+    ["onchain", "lightning", "nwc", BINARY_STORAGE_CAPABILITY];
+
+/// The device can read payments and the network graph saved in the binary format
+pub(crate) const BINARY_STORAGE_CAPABILITY: &str = "binary_storage";
+
+/// A device that has run this wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    /// Unix timestamp in seconds of when the device first ran the wallet
+    pub first_seen: u64,
+    /// Unix timestamp in seconds of when the device last started the wallet
+    pub last_seen: u64,
+    pub capabilities: Vec<String>,
+    /// A revoked device disables the NWC connections it created the next time it starts
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+/// Every device that has restored or synced this wallet.
+///
+/// This is saved to the remote store so each device can see, and revoke, the others.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRegistry {
+    pub devices: Vec<DeviceInfo>,
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl DeviceRegistry {
+    /// Records that the device started now, adding it if it is new.
+    /// Returns the device's updated entry.
+    pub(crate) fn record_seen(&mut self, id: &str, capabilities: &[&str], now: u64) -> DeviceInfo {
+        let capabilities: Vec<String> = capabilities.iter().map(|c| c.to_string()).collect();
+        match self.devices.iter_mut().find(|d| d.id == id) {
+            Some(device) => {
+                device.last_seen = now;
+                device.capabilities = capabilities;
+                device.clone()
+            }
+            None => {
+                let device = DeviceInfo {
+                    id: id.to_string(),
+                    first_seen: now,
+                    last_seen: now,
+                    capabilities,
+                    revoked: false,
+                };
+                self.devices.push(device.clone());
+                device
+            }
+        }
+    }
+}
+
+pub trait DeviceRegistryStorage {
+    fn get_device_registry(&self) -> Result<DeviceRegistry, MutinyError>;
+    fn set_device_registry(&self, registry: DeviceRegistry) -> Result<(), MutinyError>;
+
+    /// Records that this device started now, returning its entry in the registry
+    fn record_device_seen(&self, capabilities: &[&str]) -> Result<DeviceInfo, MutinyError>;
+
+    /// Marks a device as revoked
+    fn revoke_device(&self, id: &str) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> DeviceRegistryStorage for S {
+    fn get_device_registry(&self) -> Result<DeviceRegistry, MutinyError> {
+        let registry: Option<DeviceRegistry> = self.get_data(DEVICE_REGISTRY_KEY)?;
+        Ok(registry.unwrap_or_default())
+    }
+
+    fn set_device_registry(&self, mut registry: DeviceRegistry) -> Result<(), MutinyError> {
+        registry.version += 1;
+        let version = Some(registry.version);
+        self.set_data(DEVICE_REGISTRY_KEY, registry, version)
+    }
+
+    fn record_device_seen(&self, capabilities: &[&str]) -> Result<DeviceInfo, MutinyError> {
+        let id = self.get_device_id()?;
+        let mut registry = self.get_device_registry()?;
+        let device = registry.record_seen(&id, capabilities, utils::now().as_secs());
+        self.set_device_registry(registry)?;
+        Ok(device)
+    }
+
+    fn revoke_device(&self, id: &str) -> Result<(), MutinyError> {
+        let mut registry = self.get_device_registry()?;
+        let device = registry
+            .devices
+            .iter_mut()
+            .find(|d| d.id == id)
+            .ok_or(MutinyError::NotFound)?;
+        device.revoked = true;
+        self.set_device_registry(registry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_device_registry() {
+        log!("test device registry");
+
+        let storage = MemoryStorage::default();
+        let device = storage.record_device_seen(&DEVICE_CAPABILITIES).unwrap();
+        assert_eq!(device.id, storage.get_device_id().unwrap());
+        assert_eq!(device.first_seen, device.last_seen);
+        assert!(!device.revoked);
+
+        // seeing the device again doesn't add another entry
+        storage.record_device_seen(&DEVICE_CAPABILITIES).unwrap();
+        let registry = storage.get_device_registry().unwrap();
+        assert_eq!(registry.devices.len(), 1);
+        assert_eq!(registry.version, 2);
+
+        storage.revoke_device(&device.id).unwrap();
+        let device = storage.record_device_seen(&DEVICE_CAPABILITIES).unwrap();
+        assert!(device.revoked);
+
+        assert!(storage.revoke_device("unknown").is_err());
+    }
+}
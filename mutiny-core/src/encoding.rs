@@ -0,0 +1,416 @@
+//! A compact binary encoding for large values, like payments and the network
+//! graph. Values are encoded with CBOR and saved as a base64 string tagged
+//! with the format version, so they fit in the same storage as everything
+//! else and can be told apart from values saved as JSON.
+//!
+//! Values that compress well, like the network graph, can also be saved
+//! compressed with deflate, with a checksum to catch corrupted data.
+//!
+//! [MutinyStorage::get_data] reads every format, values saved as JSON before
+//! are rewritten once by [migrate_to_binary].
+//!
+//! Older versions of the wallet can only read JSON, so nothing is saved in the
+//! binary format until every device in the registry supports it.
+
+use crate::devices::{DeviceRegistryStorage, BINARY_STORAGE_CAPABILITY};
+use crate::error::{MutinyError, MutinyStorageError};
+use crate::event::PaymentInfo;
+use crate::ldkstorage::{
+    PAYMENT_HTLCS_PREFIX, PAYMENT_INBOUND_PREFIX_KEY, PAYMENT_OUTBOUND_PREFIX_KEY,
+};
+use crate::nodemanager::PaymentHtlc;
+use crate::storage::{decrypt_value, MutinyStorage};
+use anyhow::anyhow;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+
+/// Tag for version 1 of the binary format, CBOR encoded as base64
+const BINARY_V1_TAG: &str = "cbor1:";
+/// Tag for version 1 of the binary format compressed with deflate,
+/// prefixed with a checksum of the uncompressed data
+const COMPRESSED_V1_TAG: &str = "cborz1:";
+/// Bytes of the sha256 of the uncompressed data kept as a checksum
+const CHECKSUM_LEN: usize = 4;
+const COMPRESSION_LEVEL: u8 = 6;
+
+const STORAGE_FORMAT_VERSION_KEY: &str = "storage_format_version";
+/// Bump when more values should be migrated to the binary format
+const STORAGE_FORMAT_VERSION: u32 = 1;
+
+fn read_err(e: String) -> MutinyError {
+    MutinyError::read_err(MutinyStorageError::Other(anyhow!(e)))
+}
+
+fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, MutinyError> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).map_err(|e| {
+        MutinyError::write_err(MutinyStorageError::Other(anyhow!(
+            "Failed to encode value: {e}"
+        )))
+    })?;
+    Ok(bytes)
+}
+
+fn from_cbor<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, MutinyError> {
+    ciborium::de::from_reader(bytes).map_err(|e| read_err(format!("Failed to decode value: {e}")))
+}
+
+/// The first bytes of the sha256 of the data, enough to catch corruption
+pub(crate) fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = sha256::Hash::hash(bytes);
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LEN]);
+    checksum
+}
+
+/// Encodes a value in the binary format
+pub(crate) fn encode_binary<T: Serialize>(value: &T) -> Result<Value, MutinyError> {
+    let bytes = to_cbor(value)?;
+    Ok(Value::String(format!(
+        "{BINARY_V1_TAG}{}",
+        base64::encode(bytes)
+    )))
+}
+
+/// Encodes a value in the binary format and compresses it
+pub(crate) fn encode_compressed<T: Serialize>(value: &T) -> Result<Value, MutinyError> {
+    let bytes = to_cbor(value)?;
+    let mut data = checksum(&bytes).to_vec();
+    data.extend(compress_to_vec(&bytes, COMPRESSION_LEVEL));
+    Ok(Value::String(format!(
+        "{COMPRESSED_V1_TAG}{}",
+        base64::encode(data)
+    )))
+}
+
+/// Whether every device that hasn't been revoked can read the binary format.
+/// This device always can, even if it hasn't updated its registry entry yet.
+pub(crate) fn binary_storage_supported<S: MutinyStorage>(storage: &S) -> Result<bool, MutinyError> {
+    let id = storage.get_device_id()?;
+    let registry = storage.get_device_registry()?;
+    Ok(registry
+        .devices
+        .iter()
+        .filter(|d| d.id != id && !d.revoked)
+        .all(|d| {
+            d.capabilities
+                .iter()
+                .any(|c| c == BINARY_STORAGE_CAPABILITY)
+        }))
+}
+
+pub(crate) fn is_binary(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s.starts_with(BINARY_V1_TAG) || s.starts_with(COMPRESSED_V1_TAG))
+}
+
+/// Decodes a value saved in any of the binary formats or as JSON
+pub(crate) fn decode_value<T: DeserializeOwned>(value: Value) -> Result<T, MutinyError> {
+    match value {
+        Value::String(s) if s.starts_with(BINARY_V1_TAG) => {
+            let bytes = base64::decode(&s[BINARY_V1_TAG.len()..])
+                .map_err(|e| read_err(format!("Invalid binary value: {e}")))?;
+            from_cbor(&bytes)
+        }
+        Value::String(s) if s.starts_with(COMPRESSED_V1_TAG) => {
+            let data = base64::decode(&s[COMPRESSED_V1_TAG.len()..])
+                .map_err(|e| read_err(format!("Invalid compressed value: {e}")))?;
+            if data.len() < CHECKSUM_LEN {
+                return Err(read_err(
+                    "Compressed value is missing its checksum".to_string(),
+                ));
+            }
+            let (expected, compressed) = data.split_at(CHECKSUM_LEN);
+            let bytes = decompress_to_vec(compressed)
+                .map_err(|e| read_err(format!("Failed to decompress value: {e:?}")))?;
+            if checksum(&bytes) != expected {
+                return Err(read_err("Compressed value failed its checksum".to_string()));
+            }
+            from_cbor(&bytes)
+        }
+        value => Ok(serde_json::from_value(value)?),
+    }
+}
+
+/// Raw bytes, like an LDK encoded object. These are a CBOR byte string in
+/// the binary format and hex as JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Bytes(pub Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.0.to_hex())
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Bytes;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes or a hex string")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Bytes, E> {
+                Ok(Bytes(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Bytes, E> {
+                Ok(Bytes(v))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Bytes, E> {
+                Vec::from_hex(v).map(Bytes).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(Bytes(bytes))
+            }
+        }
+
+        deserializer.deserialize_any(BytesVisitor)
+    }
+}
+
+/// Rewrites the payments and their HTLCs that are still saved as JSON in the
+/// binary format. Only runs once per format version, and not until every
+/// device supports the binary format.
+///
+/// Returns the number of values that were rewritten.
+pub(crate) fn migrate_to_binary<S: MutinyStorage>(storage: &S) -> Result<usize, MutinyError> {
+    let version: Option<u32> = storage.get_data(STORAGE_FORMAT_VERSION_KEY)?;
+    if version.is_some_and(|v| v >= STORAGE_FORMAT_VERSION) {
+        return Ok(0);
+    }
+    if !binary_storage_supported(storage)? {
+        return Ok(0);
+    }
+
+    let mut migrated = migrate_prefix::<S, PaymentInfo>(storage, PAYMENT_INBOUND_PREFIX_KEY)?;
+    migrated += migrate_prefix::<S, PaymentInfo>(storage, PAYMENT_OUTBOUND_PREFIX_KEY)?;
+    migrated += migrate_prefix::<S, Vec<PaymentHtlc>>(storage, PAYMENT_HTLCS_PREFIX)?;
+
+    storage.set_data(STORAGE_FORMAT_VERSION_KEY, STORAGE_FORMAT_VERSION, None)?;
+    Ok(migrated)
+}
+
+/// Rewrites the values under `prefix` that are still saved as JSON in the
+/// binary format. They are read as `T` first, a JSON value has the human
+/// readable forms, like public keys as hex, that can't be read back from CBOR.
+fn migrate_prefix<S: MutinyStorage, T: Serialize + DeserializeOwned>(
+    storage: &S,
+    prefix: &str,
+) -> Result<usize, MutinyError> {
+    let mut migrated = 0;
+    for key in storage.scan_keys(prefix, None)? {
+        let Some(value) = storage.get::<Value>(&key)? else {
+            continue;
+        };
+        let value = decrypt_value(&key, value, storage.password())?;
+        if is_binary(&value) {
+            continue;
+        }
+        let value: T = serde_json::from_value(value)?;
+        storage.set_data(&key, encode_binary(&value)?, None)?;
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::DEVICE_CAPABILITIES;
+    use crate::event::{HTLCStatus, MillisatAmount};
+    use crate::nodemanager::HtlcState;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::secp256k1::PublicKey;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    struct Payment {
+        preimage: Option<[u8; 32]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fee_paid_msat: Option<u64>,
+        amt_msat: u64,
+    }
+
+    fn dummy_payment() -> Payment {
+        Payment {
+            preimage: Some([7; 32]),
+            fee_paid_msat: None,
+            amt_msat: 21_000,
+        }
+    }
+
+    fn dummy_payment_info() -> PaymentInfo {
+        let pubkey = PublicKey::from_str(
+            "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b",
+        )
+        .unwrap();
+        PaymentInfo {
+            preimage: Some([7; 32]),
+            secret: None,
+            status: HTLCStatus::Succeeded,
+            amt_msat: MillisatAmount(Some(21_000)),
+            fee_paid_msat: Some(1_000),
+            bolt11: None,
+            payee_pubkey: Some(pubkey),
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
+            last_update: 1_700_000_000,
+        }
+    }
+
+    fn dummy_htlc() -> PaymentHtlc {
+        PaymentHtlc::new(
+            false,
+            Some("123".to_string()),
+            Some(21_000),
+            HtlcState::Fulfilled,
+            1_700_000_000,
+        )
+    }
+
+    #[test]
+    fn test_binary_encoding() {
+        log!("test binary encoding");
+
+        let payment = dummy_payment();
+        let json = serde_json::to_value(&payment).unwrap();
+        let binary = encode_binary(&payment).unwrap();
+        assert!(is_binary(&binary));
+        assert!(!is_binary(&json));
+        assert!(binary.to_string().len() < json.to_string().len());
+
+        // both formats decode to the same value
+        assert_eq!(decode_value::<Payment>(binary).unwrap(), payment);
+        assert_eq!(decode_value::<Payment>(json).unwrap(), payment);
+
+        // bytes saved as hex are still readable
+        let bytes = Bytes(vec![1, 2, 3, 255]);
+        let hex = Value::String(bytes.0.to_hex());
+        assert_eq!(decode_value::<Bytes>(hex).unwrap(), bytes);
+        let binary = encode_binary(&bytes).unwrap();
+        assert_eq!(decode_value::<Bytes>(binary).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_compressed_encoding() {
+        log!("test compressed encoding");
+
+        let bytes = Bytes([1, 2, 3, 4].repeat(1_000));
+        let binary = encode_binary(&bytes).unwrap();
+        let compressed = encode_compressed(&bytes).unwrap();
+        assert!(is_binary(&compressed));
+        assert!(compressed.to_string().len() < binary.to_string().len() / 10);
+        assert_eq!(decode_value::<Bytes>(compressed.clone()).unwrap(), bytes);
+
+        // corrupted data is caught instead of decoding to the wrong value
+        let encoded = compressed.as_str().unwrap();
+        let mut data = base64::decode(&encoded[COMPRESSED_V1_TAG.len()..]).unwrap();
+        data[0] ^= 1;
+        let corrupted = Value::String(format!("{COMPRESSED_V1_TAG}{}", base64::encode(data)));
+        assert!(decode_value::<Bytes>(corrupted).is_err());
+    }
+
+    #[test]
+    fn test_migrate_to_binary() {
+        log!("test migrate to binary");
+
+        let storage = MemoryStorage::default();
+        let payment = dummy_payment_info();
+        let key_a = format!("{PAYMENT_OUTBOUND_PREFIX_KEY}a");
+        let key_b = format!("{PAYMENT_OUTBOUND_PREFIX_KEY}b");
+        storage.set_data(&key_a, &payment, None).unwrap();
+        storage.set_data_binary(&key_b, &payment, None).unwrap();
+        storage.set_data("other/a", &payment, None).unwrap();
+        let htlcs = vec![dummy_htlc()];
+        let key_htlcs = format!("{PAYMENT_HTLCS_PREFIX}a");
+        storage.set_data(&key_htlcs, &htlcs, None).unwrap();
+
+        assert_eq!(migrate_to_binary(&storage).unwrap(), 2);
+        let raw: Value = storage.get(&key_a).unwrap().unwrap();
+        assert!(is_binary(&raw));
+        let raw: Value = storage.get("other/a").unwrap().unwrap();
+        assert!(!is_binary(&raw));
+
+        // the payee's pubkey is read back from its binary form
+        let read: Option<PaymentInfo> = storage.get_data(&key_a).unwrap();
+        assert_eq!(read, Some(payment.clone()));
+        let read: HashMap<String, PaymentInfo> =
+            storage.scan(PAYMENT_OUTBOUND_PREFIX_KEY, None).unwrap();
+        assert_eq!(read.len(), 2);
+        assert!(read.values().all(|p| p == &payment));
+        let read: Option<Vec<PaymentHtlc>> = storage.get_data(&key_htlcs).unwrap();
+        assert_eq!(read, Some(htlcs));
+
+        // only runs once
+        let key_c = format!("{PAYMENT_OUTBOUND_PREFIX_KEY}c");
+        storage.set_data(&key_c, &payment, None).unwrap();
+        assert_eq!(migrate_to_binary(&storage).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_binary_storage_waits_for_all_devices() {
+        log!("test binary storage waits for all devices");
+
+        let storage = MemoryStorage::default();
+        let mut registry = storage.get_device_registry().unwrap();
+        registry.record_seen("old-device", &["onchain", "lightning", "nwc"], 0);
+        storage.set_device_registry(registry.clone()).unwrap();
+
+        let payment = dummy_payment_info();
+        let key_a = format!("{PAYMENT_OUTBOUND_PREFIX_KEY}a");
+        let key_b = format!("{PAYMENT_OUTBOUND_PREFIX_KEY}b");
+        storage.set_data(&key_a, &payment, None).unwrap();
+
+        // the old device can't read the binary format, so values stay JSON
+        assert!(!binary_storage_supported(&storage).unwrap());
+        assert_eq!(migrate_to_binary(&storage).unwrap(), 0);
+        storage.set_data_binary(&key_b, &payment, None).unwrap();
+        let raw: Value = storage.get(&key_b).unwrap().unwrap();
+        assert!(!is_binary(&raw));
+        let read: Option<PaymentInfo> = storage.get_data(&key_b).unwrap();
+        assert_eq!(read, Some(payment.clone()));
+
+        // bytes are saved as hex, like older versions did
+        storage
+            .set_data_compressed("graph", Bytes(vec![1, 2, 3]), None)
+            .unwrap();
+        let raw: Value = storage.get("graph").unwrap().unwrap();
+        assert_eq!(raw, Value::String("010203".to_string()));
+
+        // once the old device updates, everything is migrated
+        registry.record_seen("old-device", &DEVICE_CAPABILITIES, 1);
+        storage.set_device_registry(registry).unwrap();
+        assert!(binary_storage_supported(&storage).unwrap());
+        assert_eq!(migrate_to_binary(&storage).unwrap(), 2);
+        let raw: Value = storage.get(&key_b).unwrap().unwrap();
+        assert!(is_binary(&raw));
+    }
+}
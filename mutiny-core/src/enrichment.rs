@@ -0,0 +1,611 @@
+//! Enriches activity with display names and icons for who it was with.
+//!
+//! Resolvers look up what an activity entry is about, like the node that was paid or
+//! a contact's nostr profile. Results are cached in storage for the resolver's TTL.
+//! Reading enriched activity only uses the cache, anything missing or expired is
+//! looked up in the background and shows up the next time activity is read.
+
+use crate::error::MutinyError;
+use crate::gossip::decode_node_alias;
+use crate::labels::Contact;
+use crate::logging::MutinyLogger;
+use crate::node::NetworkGraph;
+use crate::nodemanager::ActivityItem;
+use crate::nostr::relays::{connect_relays, RelayUse};
+use crate::storage::MutinyStorage;
+use crate::utils;
+use async_trait::async_trait;
+use bitcoin::secp256k1::PublicKey;
+use core::time::Duration;
+use lightning::routing::gossip::NodeId;
+use lightning::util::logger::Logger;
+use lightning::{log_debug, log_warn};
+use lnurl::{AsyncClient as LnUrlClient, LnUrlResponse};
+use nostr::key::XOnlyPublicKey;
+use nostr::{Filter, Keys, Kind, Metadata};
+use nostr_sdk::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub const ENRICHMENT_PREFIX: &str = "enrichment/";
+
+const NODE_ALIAS_TTL_SECS: u64 = 60 * 60 * 24;
+const LNURL_METADATA_TTL_SECS: u64 = 60 * 60 * 24;
+const NOSTR_PROFILE_TTL_SECS: u64 = 60 * 60 * 6;
+const NOSTR_PROFILE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something an activity entry is about that a resolver can look up
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum EnrichmentSubject {
+    /// A lightning node that was paid or had a channel with us
+    Node(PublicKey),
+    /// The nostr profile of a contact on the activity
+    Nostr(XOnlyPublicKey),
+    /// The LNURL-pay url of a contact on the activity, from their lnurl or lightning address
+    LnUrl(String),
+}
+
+impl EnrichmentSubject {
+    fn key(&self) -> String {
+        match self {
+            EnrichmentSubject::Node(pk) => format!("{ENRICHMENT_PREFIX}node/{pk}"),
+            EnrichmentSubject::Nostr(npub) => format!("{ENRICHMENT_PREFIX}nostr/{npub}"),
+            EnrichmentSubject::LnUrl(url) => format!("{ENRICHMENT_PREFIX}lnurl/{url}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    pub display_name: Option<String>,
+    /// A url or data url of an image
+    pub icon_url: Option<String>,
+}
+
+/// A cached lookup, `info` is `None` if the resolver didn't find anything
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Enrichment {
+    pub info: Option<DisplayInfo>,
+    /// The name of the resolver that did the lookup
+    pub resolver: String,
+    pub expires_at: u64,
+}
+
+impl Enrichment {
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+}
+
+/// An activity entry with the display info of who it was with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnrichedActivityItem {
+    pub item: ActivityItem,
+    pub display_name: Option<String>,
+    pub icon_url: Option<String>,
+}
+
+/// Looks up display info for the subjects it handles
+#[async_trait(?Send)]
+pub trait EnrichmentResolver {
+    /// Stored with the results, should be unique
+    fn name(&self) -> &'static str;
+    /// How long results are cached for
+    fn ttl_secs(&self) -> u64;
+    fn handles(&self, subject: &EnrichmentSubject) -> bool;
+    /// Looks up all the subjects at once, subjects without a result can be left out
+    async fn resolve(
+        &self,
+        subjects: Vec<EnrichmentSubject>,
+    ) -> Result<HashMap<EnrichmentSubject, DisplayInfo>, MutinyError>;
+}
+
+/// The subjects of an activity entry, the ones from contacts come first
+/// as they were picked by the user
+pub(crate) fn activity_subjects(
+    item: &ActivityItem,
+    contacts: &HashMap<String, Contact>,
+) -> Vec<EnrichmentSubject> {
+    let mut subjects = vec![];
+    for label in item.labels() {
+        let Some(contact) = contacts.get(&label) else {
+            continue;
+        };
+        if let Some(npub) = contact.npub {
+            subjects.push(EnrichmentSubject::Nostr(npub));
+        }
+        if let Some(address) = contact.ln_address.as_ref() {
+            subjects.push(EnrichmentSubject::LnUrl(address.lnurl().url));
+        }
+        if let Some(lnurl) = contact.lnurl.as_ref() {
+            subjects.push(EnrichmentSubject::LnUrl(lnurl.url.clone()));
+        }
+    }
+
+    match item {
+        ActivityItem::Lightning(invoice) if !invoice.inbound => {
+            if let Some(payee) = invoice.payee_pubkey {
+                subjects.push(EnrichmentSubject::Node(payee));
+            }
+        }
+        ActivityItem::ChannelClosed(closure) => {
+            if let Some(node_id) = closure.node_id {
+                subjects.push(EnrichmentSubject::Node(node_id));
+            }
+        }
+        _ => {}
+    }
+
+    subjects
+}
+
+/// Gets the display info from LNURL-pay metadata.
+/// Uses the lightning address if there is one, otherwise the description.
+pub(crate) fn parse_pay_metadata(metadata: &str) -> DisplayInfo {
+    let entries: Vec<Vec<String>> = serde_json::from_str(metadata).unwrap_or_default();
+
+    let mut identifier = None;
+    let mut description = None;
+    let mut icon_url = None;
+    for entry in entries {
+        let [kind, value] = entry.as_slice() else {
+            continue;
+        };
+        match kind.as_str() {
+            "text/identifier" => identifier = Some(value.clone()),
+            "text/plain" => description = Some(value.clone()),
+            "image/png;base64" | "image/jpeg;base64" => {
+                let mime = kind.trim_end_matches(";base64");
+                icon_url = Some(format!("data:{mime};base64,{value}"));
+            }
+            _ => {}
+        }
+    }
+
+    DisplayInfo {
+        display_name: identifier.or(description).filter(|n| !n.is_empty()),
+        icon_url,
+    }
+}
+
+/// Uses the alias from a node's announcement in the network graph
+pub(crate) struct NodeAliasResolver {
+    pub network_graph: Arc<NetworkGraph>,
+}
+
+#[async_trait(?Send)]
+impl EnrichmentResolver for NodeAliasResolver {
+    fn name(&self) -> &'static str {
+        "node_alias"
+    }
+
+    fn ttl_secs(&self) -> u64 {
+        NODE_ALIAS_TTL_SECS
+    }
+
+    fn handles(&self, subject: &EnrichmentSubject) -> bool {
+        matches!(subject, EnrichmentSubject::Node(_))
+    }
+
+    async fn resolve(
+        &self,
+        subjects: Vec<EnrichmentSubject>,
+    ) -> Result<HashMap<EnrichmentSubject, DisplayInfo>, MutinyError> {
+        let graph = self.network_graph.read_only();
+        let mut found = HashMap::new();
+        for subject in subjects {
+            let EnrichmentSubject::Node(pk) = subject else {
+                continue;
+            };
+            let alias = graph
+                .node(&NodeId::from_pubkey(&pk))
+                .and_then(|n| n.announcement_info.as_ref())
+                .map(|a| decode_node_alias(&a.alias.0))
+                .filter(|a| !a.trim().is_empty());
+            if let Some(alias) = alias {
+                let info = DisplayInfo {
+                    display_name: Some(alias),
+                    icon_url: None,
+                };
+                found.insert(subject, info);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Uses the metadata of a LNURL-pay endpoint
+pub(crate) struct LnUrlMetadataResolver {
+    pub client: Arc<LnUrlClient>,
+}
+
+#[async_trait(?Send)]
+impl EnrichmentResolver for LnUrlMetadataResolver {
+    fn name(&self) -> &'static str {
+        "lnurl_metadata"
+    }
+
+    fn ttl_secs(&self) -> u64 {
+        LNURL_METADATA_TTL_SECS
+    }
+
+    fn handles(&self, subject: &EnrichmentSubject) -> bool {
+        matches!(subject, EnrichmentSubject::LnUrl(_))
+    }
+
+    async fn resolve(
+        &self,
+        subjects: Vec<EnrichmentSubject>,
+    ) -> Result<HashMap<EnrichmentSubject, DisplayInfo>, MutinyError> {
+        let mut found = HashMap::new();
+        for subject in subjects {
+            let EnrichmentSubject::LnUrl(url) = &subject else {
+                continue;
+            };
+            // one bad endpoint shouldn't stop the others
+            let response = self.client.make_request(url).await;
+            if let Ok(LnUrlResponse::LnUrlPayResponse(pay)) = response {
+                found.insert(subject, parse_pay_metadata(&pay.metadata));
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Uses the name and picture of a nostr profile
+pub(crate) struct NostrProfileResolver<S: MutinyStorage> {
+    pub storage: S,
+}
+
+#[async_trait(?Send)]
+impl<S: MutinyStorage> EnrichmentResolver for NostrProfileResolver<S> {
+    fn name(&self) -> &'static str {
+        "nostr_profile"
+    }
+
+    fn ttl_secs(&self) -> u64 {
+        NOSTR_PROFILE_TTL_SECS
+    }
+
+    fn handles(&self, subject: &EnrichmentSubject) -> bool {
+        matches!(subject, EnrichmentSubject::Nostr(_))
+    }
+
+    async fn resolve(
+        &self,
+        subjects: Vec<EnrichmentSubject>,
+    ) -> Result<HashMap<EnrichmentSubject, DisplayInfo>, MutinyError> {
+        let authors: Vec<String> = subjects
+            .iter()
+            .filter_map(|s| match s {
+                EnrichmentSubject::Nostr(npub) => Some(npub.to_string()),
+                _ => None,
+            })
+            .collect();
+        if authors.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let client = Client::new(&Keys::generate());
+        connect_relays(&self.storage, &client, RelayUse::Read, vec![]).await?;
+        let filter = Filter::new().authors(authors).kind(Kind::Metadata);
+        let events = client
+            .get_events_of(vec![filter], Some(NOSTR_PROFILE_TIMEOUT))
+            .await;
+        let _ = client.disconnect().await;
+
+        // keep the newest profile of each author
+        let mut newest = HashMap::new();
+        for event in events? {
+            let is_newer = newest
+                .get(&event.pubkey)
+                .map_or(true, |(created_at, _)| event.created_at > *created_at);
+            if is_newer {
+                newest.insert(event.pubkey, (event.created_at, event.content));
+            }
+        }
+
+        let mut found = HashMap::new();
+        for (npub, (_, content)) in newest {
+            // skip metadata we can't parse
+            let Ok(metadata) = Metadata::from_json(&content) else {
+                continue;
+            };
+            let info = DisplayInfo {
+                display_name: metadata.display_name.or(metadata.name),
+                icon_url: metadata.picture,
+            };
+            found.insert(EnrichmentSubject::Nostr(npub), info);
+        }
+
+        Ok(found)
+    }
+}
+
+/// Holds the registered resolvers and serves enrichments from the cache
+pub(crate) struct ActivityEnricher<S: MutinyStorage> {
+    storage: S,
+    resolvers: RwLock<Vec<Arc<dyn EnrichmentResolver>>>,
+    /// Only one background refresh runs at a time
+    refreshing: AtomicBool,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<S: MutinyStorage> ActivityEnricher<S> {
+    pub(crate) fn new(storage: S, logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            storage,
+            resolvers: RwLock::new(vec![]),
+            refreshing: AtomicBool::new(false),
+            logger,
+        }
+    }
+
+    /// Adds a resolver, replacing any with the same name.
+    /// Resolvers registered first are asked first.
+    pub(crate) fn register(&self, resolver: Arc<dyn EnrichmentResolver>) {
+        let mut resolvers = self.resolvers.write().expect("resolvers lock poisoned");
+        match resolvers.iter().position(|r| r.name() == resolver.name()) {
+            Some(index) => resolvers[index] = resolver,
+            None => resolvers.push(resolver),
+        }
+    }
+
+    /// Enriches an activity entry from the cache. Subjects that aren't cached
+    /// or have expired are added to `stale` so they can be refreshed.
+    pub(crate) fn enrich(
+        &self,
+        item: ActivityItem,
+        contacts: &HashMap<String, Contact>,
+        now: u64,
+        stale: &mut HashSet<EnrichmentSubject>,
+    ) -> Result<EnrichedActivityItem, MutinyError> {
+        let mut display_name = None;
+        let mut icon_url = None;
+        for subject in activity_subjects(&item, contacts) {
+            let cached = self.storage.get_enrichment(&subject)?;
+            if cached.as_ref().map_or(true, |e| e.is_expired(now)) {
+                stale.insert(subject);
+            }
+
+            // expired info is better than nothing until it is refreshed
+            if let Some(info) = cached.and_then(|e| e.info) {
+                display_name = display_name.or(info.display_name);
+                icon_url = icon_url.or(info.icon_url);
+            }
+        }
+
+        Ok(EnrichedActivityItem {
+            item,
+            display_name,
+            icon_url,
+        })
+    }
+
+    /// Looks up the subjects in the background, unless a refresh is already running
+    pub(crate) fn refresh_in_background(self: &Arc<Self>, subjects: HashSet<EnrichmentSubject>) {
+        if subjects.is_empty() || self.refreshing.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let enricher = self.clone();
+        utils::spawn(async move {
+            enricher.refresh(subjects).await;
+            enricher.refreshing.store(false, Ordering::Relaxed);
+        });
+    }
+
+    async fn refresh(&self, subjects: HashSet<EnrichmentSubject>) {
+        let resolvers = self
+            .resolvers
+            .read()
+            .expect("resolvers lock poisoned")
+            .clone();
+        let mut resolved: HashSet<EnrichmentSubject> = HashSet::new();
+        for resolver in resolvers {
+            let pending: Vec<EnrichmentSubject> = subjects
+                .iter()
+                .filter(|s| resolver.handles(s) && !resolved.contains(*s))
+                .cloned()
+                .collect();
+            if pending.is_empty() {
+                continue;
+            }
+
+            let mut found = match resolver.resolve(pending.clone()).await {
+                Ok(found) => found,
+                Err(e) => {
+                    log_warn!(self.logger, "Resolver {} failed: {e}", resolver.name());
+                    continue;
+                }
+            };
+            log_debug!(
+                self.logger,
+                "Resolver {} enriched {} of {} subjects",
+                resolver.name(),
+                found.len(),
+                pending.len()
+            );
+
+            let expires_at = utils::now().as_secs() + resolver.ttl_secs();
+            for subject in pending {
+                let info = found.remove(&subject);
+                if info.is_some() {
+                    resolved.insert(subject.clone());
+                }
+                let enrichment = Enrichment {
+                    info,
+                    resolver: resolver.name().to_string(),
+                    expires_at,
+                };
+                if let Err(e) = self.storage.persist_enrichment(&subject, enrichment) {
+                    log_warn!(self.logger, "Failed to cache enrichment: {e}");
+                }
+            }
+        }
+    }
+}
+
+pub trait EnrichmentStorage {
+    fn get_enrichment(
+        &self,
+        subject: &EnrichmentSubject,
+    ) -> Result<Option<Enrichment>, MutinyError>;
+    fn persist_enrichment(
+        &self,
+        subject: &EnrichmentSubject,
+        enrichment: Enrichment,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> EnrichmentStorage for S {
+    fn get_enrichment(
+        &self,
+        subject: &EnrichmentSubject,
+    ) -> Result<Option<Enrichment>, MutinyError> {
+        self.get_data(subject.key())
+    }
+
+    fn persist_enrichment(
+        &self,
+        subject: &EnrichmentSubject,
+        enrichment: Enrichment,
+    ) -> Result<(), MutinyError> {
+        self.set_data(subject.key(), enrichment, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodemanager::MutinyInvoice;
+    use crate::payment_metadata::PaymentMetadata;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::{sha256, Hash};
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const PUBKEY: &str = "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54";
+    const NPUB: &str = "e1ff3bfdd4e40315959b08b4fcc8245eaa514637e1d4ec2ae166b743341be1af";
+
+    fn dummy_payment(labels: Vec<String>) -> ActivityItem {
+        ActivityItem::Lightning(Box::new(MutinyInvoice {
+            bolt11: None,
+            description: None,
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            preimage: None,
+            payee_pubkey: Some(PublicKey::from_str(PUBKEY).unwrap()),
+            amount_sats: Some(1_000),
+            expire: 0,
+            paid: true,
+            fees_paid: None,
+            inbound: false,
+            labels,
+            order_id: None,
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
+            last_updated: 0,
+        }))
+    }
+
+    #[test]
+    fn test_activity_subjects() {
+        log!("test activity subjects");
+
+        let npub = XOnlyPublicKey::from_str(NPUB).unwrap();
+        let contact = Contact {
+            name: "Alice".to_string(),
+            npub: Some(npub),
+            ..Default::default()
+        };
+        let contacts = HashMap::from([("contact-id".to_string(), contact)]);
+
+        let item = dummy_payment(vec!["contact-id".to_string()]);
+        let subjects = activity_subjects(&item, &contacts);
+        let node = PublicKey::from_str(PUBKEY).unwrap();
+        assert_eq!(
+            subjects,
+            vec![
+                EnrichmentSubject::Nostr(npub),
+                EnrichmentSubject::Node(node)
+            ]
+        );
+
+        let item = dummy_payment(vec![]);
+        assert_eq!(
+            activity_subjects(&item, &contacts),
+            vec![EnrichmentSubject::Node(node)]
+        );
+    }
+
+    #[test]
+    fn test_parse_pay_metadata() {
+        log!("test parse pay metadata");
+
+        let metadata = r#"[["text/plain","Pay to Alice"],["text/identifier","alice@example.com"],["image/png;base64","iVBOR"]]"#;
+        let info = parse_pay_metadata(metadata);
+        assert_eq!(info.display_name, Some("alice@example.com".to_string()));
+        assert_eq!(
+            info.icon_url,
+            Some("data:image/png;base64,iVBOR".to_string())
+        );
+
+        let info = parse_pay_metadata(r#"[["text/plain","Pay to Alice"]]"#);
+        assert_eq!(info.display_name, Some("Pay to Alice".to_string()));
+        assert_eq!(info.icon_url, None);
+
+        assert_eq!(parse_pay_metadata("not json"), DisplayInfo::default());
+    }
+
+    #[test]
+    fn test_enrich_from_cache() {
+        log!("test enrich from cache");
+
+        let storage = MemoryStorage::default();
+        let logger = Arc::new(MutinyLogger::default());
+        let enricher = ActivityEnricher::new(storage.clone(), logger);
+        let node = EnrichmentSubject::Node(PublicKey::from_str(PUBKEY).unwrap());
+
+        // nothing cached yet
+        let mut stale = HashSet::new();
+        let enriched = enricher
+            .enrich(dummy_payment(vec![]), &HashMap::new(), 0, &mut stale)
+            .unwrap();
+        assert_eq!(enriched.display_name, None);
+        assert!(stale.contains(&node));
+
+        let enrichment = Enrichment {
+            info: Some(DisplayInfo {
+                display_name: Some("ACINQ".to_string()),
+                icon_url: None,
+            }),
+            resolver: "node_alias".to_string(),
+            expires_at: 100,
+        };
+        storage.persist_enrichment(&node, enrichment).unwrap();
+
+        let mut stale = HashSet::new();
+        let enriched = enricher
+            .enrich(dummy_payment(vec![]), &HashMap::new(), 50, &mut stale)
+            .unwrap();
+        assert_eq!(enriched.display_name, Some("ACINQ".to_string()));
+        assert!(stale.is_empty());
+
+        // expired entries are still used until they are refreshed
+        let enriched = enricher
+            .enrich(dummy_payment(vec![]), &HashMap::new(), 100, &mut stale)
+            .unwrap();
+        assert_eq!(enriched.display_name, Some("ACINQ".to_string()));
+        assert!(stale.contains(&node));
+    }
+}
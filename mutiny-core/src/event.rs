@@ -1,17 +1,34 @@
+use crate::balance_changes::{
+    BalanceChange, BalanceChangeReason, BalanceChangeStorage, BalanceLayer,
+};
+use crate::error::MutinyError;
 use crate::fees::MutinyFeeEstimator;
+use crate::hold_invoice::{HoldAction, HoldInvoiceStorage};
+use crate::inbound::{InboundRejection, InboundStorage};
 use crate::keymanager::PhantomKeysManager;
 use crate::ldkstorage::{MutinyNodePersister, PhantomChannelManager};
+use crate::lnurlchannel::LnUrlChannelStorage;
 use crate::logging::MutinyLogger;
-use crate::nodemanager::ChannelClosure;
+use crate::node::{ChainMonitor, Router};
+use crate::nodemanager::{
+    ChannelClosure, CustomTlv, FailedPath, ForceClosePostmortem, ForceCloseReason, HtlcState,
+    PaymentFailure, PaymentHtlc,
+};
+use crate::offline_receive::PendingReceiveStorage;
 use crate::onchain::OnChainWallet;
+use crate::payment_status::{failure_reason_message, PaymentState, PaymentSubscriptions};
 use crate::redshift::RedshiftStorage;
 use crate::storage::MutinyStorage;
+use crate::sweep::SweepDestinationStorage;
 use crate::utils::sleep;
-use anyhow::anyhow;
 use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::secp256k1::Secp256k1;
-use lightning::events::{Event, PaymentPurpose};
+use bitcoin::{OutPoint, Script};
+use lightning::events::{Event, HTLCDestination, PaymentPurpose};
+use lightning::ln::channelmanager::PaymentId;
+use lightning::ln::PaymentHash;
 use lightning::sign::SpendableOutputDescriptor;
 use lightning::{
     chain::chaininterface::{ConfirmationTarget, FeeEstimator},
@@ -21,6 +38,8 @@ use lightning::{
 };
 use lightning_invoice::Bolt11Invoice;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
@@ -38,9 +57,38 @@ pub(crate) struct PaymentInfo {
     pub bolt11: Option<Bolt11Invoice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payee_pubkey: Option<PublicKey>,
+    /// External reference for the payment, such as an order id from a merchant integration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    /// Custom TLV records sent with the payment, such as podcast boost metadata
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_tlvs: Vec<CustomTlv>,
+    /// Why an outbound payment, or some of its paths, failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<PaymentFailure>,
     pub last_update: u64,
 }
 
+impl PaymentInfo {
+    /// Drops the full invoice and payment secret, keeping a summary of the payment.
+    /// Returns true if anything was removed.
+    pub(crate) fn prune_details(&mut self) -> bool {
+        if self.bolt11.is_none() && self.secret.is_none() {
+            return false;
+        }
+
+        // keep the amount around once the invoice is gone
+        if self.amt_msat.is_none() {
+            self.amt_msat =
+                MillisatAmount(self.bolt11.as_ref().and_then(|i| i.amount_milli_satoshis()));
+        }
+        self.bolt11 = None;
+        self.secret = None;
+
+        true
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(crate) struct MillisatAmount(pub Option<u64>);
 
@@ -61,36 +109,411 @@ pub(crate) enum HTLCStatus {
 #[derive(Clone)]
 pub struct EventHandler<S: MutinyStorage> {
     channel_manager: Arc<PhantomChannelManager<S>>,
+    chain_monitor: Arc<ChainMonitor<S>>,
     fee_estimator: Arc<MutinyFeeEstimator<S>>,
     wallet: Arc<OnChainWallet<S>>,
     keys_manager: Arc<PhantomKeysManager<S>>,
     persister: Arc<MutinyNodePersister<S>>,
+    router: Arc<Router<S>>,
     lsp_client_pubkey: Option<PublicKey>,
     logger: Arc<MutinyLogger>,
+    generation: Arc<AtomicU64>,
+    /// Probes we are waiting on, with their result once it's known
+    probes: Arc<crate::utils::Mutex<HashMap<PaymentId, Option<bool>>>>,
+    /// Payments to us whose HTLCs we accepted and haven't been claimed or
+    /// failed yet, with the peer they came from
+    inbound_htlcs: Arc<crate::utils::Mutex<HashMap<PaymentHash, PublicKey>>>,
+    payment_subscriptions: Arc<PaymentSubscriptions>,
 }
 
 impl<S: MutinyStorage> EventHandler<S> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         channel_manager: Arc<PhantomChannelManager<S>>,
+        chain_monitor: Arc<ChainMonitor<S>>,
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
         wallet: Arc<OnChainWallet<S>>,
         keys_manager: Arc<PhantomKeysManager<S>>,
         persister: Arc<MutinyNodePersister<S>>,
+        router: Arc<Router<S>>,
         lsp_client_pubkey: Option<PublicKey>,
         logger: Arc<MutinyLogger>,
+        generation: Arc<AtomicU64>,
+        payment_subscriptions: Arc<PaymentSubscriptions>,
     ) -> Self {
         Self {
             channel_manager,
+            chain_monitor,
             fee_estimator,
             wallet,
             keys_manager,
             lsp_client_pubkey,
             persister,
+            router,
             logger,
+            generation,
+            probes: Arc::new(crate::utils::Mutex::new(HashMap::new())),
+            inbound_htlcs: Arc::new(crate::utils::Mutex::new(HashMap::new())),
+            payment_subscriptions,
+        }
+    }
+
+    /// Tells anyone subscribed to the payment that its state changed,
+    /// along with the order it is for if it was given one
+    pub(crate) fn notify_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        inbound: bool,
+        state: PaymentState,
+    ) {
+        let order_id = self
+            .persister
+            .read_payment_info(payment_hash, inbound, &self.logger)
+            .and_then(|info| info.order_id);
+        self.payment_subscriptions
+            .notify(&payment_hash.0, inbound, state, order_id);
+    }
+
+    /// Starts keeping the result of a probe we sent
+    pub(crate) fn track_probe(&self, payment_id: PaymentId) {
+        if let Ok(mut probes) = self.probes.lock() {
+            probes.insert(payment_id, None);
+        }
+    }
+
+    /// Returns the result of a tracked probe once it's known, true if it reached
+    /// the destination. The probe is no longer tracked after its result is taken.
+    pub(crate) fn take_probe_result(&self, payment_id: &PaymentId) -> Option<bool> {
+        let mut probes = self.probes.lock().ok()?;
+        let result = probes.get(payment_id).copied().flatten();
+        if result.is_some() {
+            probes.remove(payment_id);
+        }
+        result
+    }
+
+    /// Stops keeping the result of a probe we no longer wait on
+    pub(crate) fn forget_probe(&self, payment_id: &PaymentId) {
+        if let Ok(mut probes) = self.probes.lock() {
+            probes.remove(payment_id);
+        }
+    }
+
+    fn set_probe_result(&self, payment_id: PaymentId, success: bool) {
+        if let Ok(mut probes) = self.probes.lock() {
+            if let Some(result) = probes.get_mut(&payment_id) {
+                *result = Some(success);
+            }
+        }
+    }
+
+    /// Links the on-chain transactions for a force closed channel and refines
+    /// the close reason using what the channel monitor saw on-chain.
+    fn fill_postmortem(&self, channel_id: [u8; 32], postmortem: &mut ForceClosePostmortem) {
+        let Some(funding_txo) = self
+            .chain_monitor
+            .list_monitors()
+            .into_iter()
+            .find(|o| o.to_channel_id() == channel_id)
+        else {
+            return;
+        };
+        let Ok(monitor) = self.chain_monitor.get_monitor(funding_txo) else {
+            return;
+        };
+
+        postmortem.funding_txo = Some(funding_txo.into_bitcoin_outpoint());
+        let txids = monitor
+            .get_relevant_txids()
+            .into_iter()
+            .map(|(txid, _)| txid)
+            .collect();
+        postmortem.observe(
+            &monitor.get_claimable_balances(),
+            txids,
+            crate::utils::now().as_secs(),
+        );
+    }
+
+    /// Checks a payment to us against the inbound HTLC limits and counts the result.
+    /// Returns why it should be rejected, if it should be, otherwise the payment
+    /// counts as pending for its peer until it is claimed or failed.
+    fn check_inbound_htlc_limits(
+        &self,
+        payment_hash: PaymentHash,
+        amount_msat: u64,
+        via_channel_id: Option<[u8; 32]>,
+    ) -> Option<InboundRejection> {
+        let limits = match self.persister.storage.get_inbound_htlc_limits() {
+            Ok(limits) => limits,
+            Err(e) => {
+                log_error!(
+                    self.logger,
+                    "ERROR: could not read inbound htlc limits: {e}"
+                );
+                return None;
+            }
+        };
+
+        let peer = self
+            .channel_manager
+            .list_channels()
+            .into_iter()
+            .find(|c| Some(c.channel_id) == via_channel_id)
+            .map(|c| c.counterparty.node_id);
+
+        let Ok(mut inbound_htlcs) = self.inbound_htlcs.lock() else {
+            return None;
+        };
+        // a payment we already accepted isn't counted against itself
+        inbound_htlcs.remove(&payment_hash);
+        let pending_htlcs = peer.map_or(0, |peer| {
+            inbound_htlcs.values().filter(|p| **p == peer).count() + 1
+        });
+
+        let rejection = limits.check(amount_msat, pending_htlcs);
+        if let Err(e) = self.persister.storage.record_inbound_htlc(rejection) {
+            log_error!(
+                self.logger,
+                "ERROR: could not persist inbound htlc counters: {e}"
+            );
+        }
+        if let (None, Some(peer)) = (rejection, peer) {
+            inbound_htlcs.insert(payment_hash, peer);
+        }
+        rejection
+    }
+
+    /// Stops counting a payment to us as pending, once it was claimed or failed
+    fn inbound_htlcs_resolved(&self, payment_hash: &PaymentHash) {
+        if let Ok(mut inbound_htlcs) = self.inbound_htlcs.lock() {
+            inbound_htlcs.remove(payment_hash);
+        }
+    }
+
+    /// The invoice another one of our nodes saved for a phantom payment this
+    /// node received, along with that node's persister to update it with
+    fn read_phantom_payment_info(
+        &self,
+        payment_hash: &PaymentHash,
+    ) -> Option<(MutinyNodePersister<S>, PaymentInfo)> {
+        let owner = match self.persister.phantom_invoice_owner(payment_hash) {
+            Ok(owner) => owner?,
+            Err(e) => {
+                log_error!(self.logger, "ERROR: could not read phantom invoice: {e}");
+                return None;
+            }
+        };
+        let info = owner.read_payment_info(payment_hash, true, &self.logger)?;
+        log_debug!(
+            self.logger,
+            "EVENT: payment hash {} is for a phantom invoice of another node",
+            payment_hash.0.to_hex()
+        );
+        Some((owner, info))
+    }
+
+    /// Records the balance changes for a payment we received.
+    /// The LSP fee is taken out of the amount the sender paid before it reaches us.
+    fn record_received_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        amount_msat: u64,
+        lsp_fee_msat: Option<u64>,
+    ) {
+        let reference = Some(payment_hash.0.to_hex());
+        let lsp_fee_sats = lsp_fee_msat.unwrap_or(0) / 1_000;
+        let mut changes = vec![BalanceChange::new(
+            BalanceChangeReason::Payment,
+            BalanceLayer::Lightning,
+            (amount_msat / 1_000 + lsp_fee_sats) as i64,
+            reference.clone(),
+        )];
+        if lsp_fee_sats > 0 {
+            changes.push(BalanceChange::new(
+                BalanceChangeReason::LspFee,
+                BalanceLayer::Lightning,
+                -(lsp_fee_sats as i64),
+                reference,
+            ));
+        }
+        self.record_balance_changes(changes);
+    }
+
+    fn record_sent_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        amount_msat: u64,
+        fee_paid_msat: Option<u64>,
+    ) {
+        let reference = Some(payment_hash.0.to_hex());
+        let fee_sats = fee_paid_msat.unwrap_or(0) / 1_000;
+        let mut changes = vec![BalanceChange::new(
+            BalanceChangeReason::Payment,
+            BalanceLayer::Lightning,
+            -((amount_msat / 1_000) as i64),
+            reference.clone(),
+        )];
+        if fee_sats > 0 {
+            changes.push(BalanceChange::new(
+                BalanceChangeReason::RoutingFee,
+                BalanceLayer::Lightning,
+                -(fee_sats as i64),
+                reference,
+            ));
+        }
+        self.record_balance_changes(changes);
+    }
+
+    fn record_balance_changes(&self, changes: Vec<BalanceChange>) {
+        if let Err(e) = self.persister.storage.record_balance_changes(changes) {
+            log_error!(self.logger, "ERROR: could not record balance changes: {e}");
+        }
+    }
+
+    /// Holds, claims or fails the HTLCs of a payment to one of our hold invoices.
+    /// Returns an error if the payment isn't for a hold invoice.
+    fn handle_hold_invoice_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        amount_msat: u64,
+    ) -> Result<(), MutinyError> {
+        let hash = sha256::Hash::from_inner(payment_hash.0);
+        let mut hold_invoice = self
+            .persister
+            .storage
+            .get_hold_invoice(&hash)?
+            .ok_or(MutinyError::NotFound)?;
+
+        match hold_invoice.on_claimable(amount_msat) {
+            HoldAction::Hold => {
+                log_info!(
+                    self.logger,
+                    "EVENT: holding HTLCs for hold invoice {}",
+                    payment_hash.0.to_hex()
+                );
+                self.persister.storage.persist_hold_invoice(hold_invoice)?;
+            }
+            HoldAction::Claim(preimage) => self.channel_manager.claim_funds(preimage),
+            HoldAction::Fail => self.channel_manager.fail_htlc_backwards(payment_hash),
+        }
+
+        Ok(())
+    }
+
+    /// Marks a hold invoice as expired when LDK failed its held HTLCs back
+    /// on its own, so it isn't settled without anything to claim
+    fn expire_hold_invoice(&self, payment_hash: &PaymentHash) {
+        let hash = sha256::Hash::from_inner(payment_hash.0);
+        let mut hold_invoice = match self.persister.storage.get_hold_invoice(&hash) {
+            Ok(Some(hold_invoice)) => hold_invoice,
+            Ok(None) => return,
+            Err(e) => {
+                log_error!(self.logger, "ERROR: could not read hold invoice: {e}");
+                return;
+            }
+        };
+
+        if hold_invoice.on_failed() {
+            log_warn!(
+                self.logger,
+                "EVENT: held HTLCs for hold invoice {} were failed back",
+                payment_hash.0.to_hex()
+            );
+            if let Err(e) = self.persister.storage.persist_hold_invoice(hold_invoice) {
+                log_error!(self.logger, "ERROR: could not persist hold invoice: {e}");
+            }
+        }
+    }
+
+    /// Saves the custom TLVs of an inbound payment before it is claimed, they are
+    /// only given to us with the claimable event. Phantom payments are saved to the
+    /// node that created the invoice. Keysends don't have payment info yet so one
+    /// is created for them.
+    fn save_custom_tlvs(
+        &self,
+        payment_hash: &PaymentHash,
+        receiver_node_id: Option<PublicKey>,
+        custom_tlvs: Vec<CustomTlv>,
+    ) {
+        let saved = self.read_phantom_payment_info(payment_hash).or_else(|| {
+            self.persister
+                .read_payment_info(payment_hash, true, &self.logger)
+                .map(|info| (self.persister.as_ref().clone(), info))
+        });
+        let (persister, mut payment_info) = saved.unwrap_or_else(|| {
+            let info = PaymentInfo {
+                preimage: None,
+                secret: None,
+                status: HTLCStatus::Pending,
+                amt_msat: MillisatAmount(None),
+                fee_paid_msat: None,
+                bolt11: None,
+                payee_pubkey: receiver_node_id,
+                order_id: None,
+                custom_tlvs: vec![],
+                failure: None,
+                last_update: crate::utils::now().as_secs(),
+            };
+            (self.persister.as_ref().clone(), info)
+        });
+        // an invoice's order id comes from when it was created, a keysend can carry one
+        if payment_info.order_id.is_none() {
+            payment_info.order_id = CustomTlv::order_id(&custom_tlvs);
+        }
+        payment_info.custom_tlvs = custom_tlvs;
+
+        if let Err(e) = persister.persist_payment_info(payment_hash, &payment_info, true) {
+            log_error!(self.logger, "ERROR: could not persist custom tlvs: {e}");
+        }
+    }
+
+    fn add_payment_htlc(&self, payment_hash: &PaymentHash, htlc: PaymentHtlc) {
+        if let Err(e) = self.persister.add_payment_htlc(payment_hash, htlc) {
+            log_error!(self.logger, "ERROR: could not persist payment htlc: {e}");
+        }
+    }
+
+    /// Saves why a path of an outbound payment failed with the payment
+    fn record_path_failure(&self, payment_hash: &PaymentHash, path: FailedPath) {
+        let Some(mut payment_info) =
+            self.persister
+                .read_payment_info(payment_hash, false, &self.logger)
+        else {
+            log_warn!(
+                self.logger,
+                "WARN: path failed for a payment we don't have stored"
+            );
+            return;
+        };
+        payment_info
+            .failure
+            .get_or_insert_with(PaymentFailure::default)
+            .add_path(path);
+        if let Err(e) = self
+            .persister
+            .persist_payment_info(payment_hash, &payment_info, false)
+        {
+            log_error!(self.logger, "ERROR: could not persist payment info: {e}");
+        }
+    }
+
+    fn update_payment_htlcs(&self, payment_hash: &PaymentHash, inbound: bool, state: HtlcState) {
+        if let Err(e) = self
+            .persister
+            .update_payment_htlcs(payment_hash, inbound, state)
+        {
+            log_error!(self.logger, "ERROR: could not persist payment htlcs: {e}");
         }
     }
 
     pub async fn handle_event(&self, event: Event) {
+        self.process_event(event).await;
+        // let readers know our state may have changed
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    async fn process_event(&self, event: Event) {
         match event {
             Event::FundingGenerationReady {
                 temporary_channel_id,
@@ -201,10 +624,48 @@ impl<S: MutinyStorage> EventHandler<S> {
                 payment_hash,
                 purpose,
                 amount_msat,
+                via_channel_id,
+                onion_fields,
                 ..
             } => {
                 log_debug!(self.logger, "EVENT: PaymentReceived received payment from payment hash {} of {amount_msat} millisatoshis to {receiver_node_id:?}", payment_hash.0.to_hex());
 
+                if let Some(rejection) =
+                    self.check_inbound_htlc_limits(payment_hash, amount_msat, via_channel_id)
+                {
+                    log_warn!(
+                        self.logger,
+                        "WARN: rejecting payment {}: {rejection:?}",
+                        payment_hash.0.to_hex()
+                    );
+                    self.channel_manager.fail_htlc_backwards(&payment_hash);
+                    return;
+                }
+
+                let custom_tlvs: Vec<CustomTlv> = onion_fields
+                    .map(|f| {
+                        f.custom_tlvs()
+                            .iter()
+                            .map(|(key, value)| CustomTlv {
+                                key: *key,
+                                value: value.clone(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if !custom_tlvs.is_empty() {
+                    self.save_custom_tlvs(&payment_hash, receiver_node_id, custom_tlvs);
+                }
+
+                let htlc = PaymentHtlc::new(
+                    true,
+                    via_channel_id.map(|c| c.to_hex()),
+                    Some(amount_msat),
+                    HtlcState::Committed,
+                    crate::utils::now().as_secs(),
+                );
+                self.add_payment_htlc(&payment_hash, htlc);
+
                 if let Some(payment_preimage) = match purpose {
                     PaymentPurpose::InvoicePayment {
                         payment_preimage, ..
@@ -212,8 +673,13 @@ impl<S: MutinyStorage> EventHandler<S> {
                     PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
                 } {
                     self.channel_manager.claim_funds(payment_preimage);
+                    self.notify_payment(&payment_hash, true, PaymentState::InFlight);
+                } else if let Err(e) = self.handle_hold_invoice_payment(&payment_hash, amount_msat)
+                {
+                    log_error!(self.logger, "ERROR: No payment preimage found: {e}");
+                    self.channel_manager.fail_htlc_backwards(&payment_hash);
                 } else {
-                    log_error!(self.logger, "ERROR: No payment preimage found");
+                    self.notify_payment(&payment_hash, true, PaymentState::InFlight);
                 };
             }
             Event::PaymentClaimed {
@@ -223,6 +689,17 @@ impl<S: MutinyStorage> EventHandler<S> {
                 amount_msat,
             } => {
                 log_debug!(self.logger, "EVENT: PaymentClaimed claimed payment from payment hash {} of {} millisatoshis", payment_hash.0.to_hex(), amount_msat);
+                self.inbound_htlcs_resolved(&payment_hash);
+                self.update_payment_htlcs(&payment_hash, true, HtlcState::Fulfilled);
+
+                // the LSP may have held this one for us while we were offline
+                if let Err(e) = self
+                    .persister
+                    .storage
+                    .mark_pending_receive_received(&sha256::Hash::from_inner(payment_hash.0))
+                {
+                    log_warn!(self.logger, "Failed to update pending receive: {e}");
+                }
 
                 let (payment_preimage, payment_secret) = match purpose {
                     PaymentPurpose::InvoicePayment {
@@ -232,11 +709,23 @@ impl<S: MutinyStorage> EventHandler<S> {
                     } => (payment_preimage, Some(payment_secret)),
                     PaymentPurpose::SpontaneousPayment(preimage) => (Some(preimage), None),
                 };
-                match self
-                    .persister
-                    .read_payment_info(&payment_hash, true, &self.logger)
-                {
-                    Some(mut saved_payment_info) => {
+                self.notify_payment(
+                    &payment_hash,
+                    true,
+                    PaymentState::Settled {
+                        preimage: payment_preimage.map(|p| p.0.to_hex()),
+                        fee_msat: None,
+                    },
+                );
+                // a phantom invoice can be saved by another one of our nodes,
+                // check that first so a local record of it is never used instead
+                let saved = self.read_phantom_payment_info(&payment_hash).or_else(|| {
+                    self.persister
+                        .read_payment_info(&payment_hash, true, &self.logger)
+                        .map(|info| (self.persister.as_ref().clone(), info))
+                });
+                match saved {
+                    Some((persister, mut saved_payment_info)) => {
                         let payment_preimage = payment_preimage.map(|p| p.0);
                         let payment_secret = payment_secret.map(|p| p.0);
                         saved_payment_info.status = HTLCStatus::Succeeded;
@@ -244,7 +733,19 @@ impl<S: MutinyStorage> EventHandler<S> {
                         saved_payment_info.secret = payment_secret;
                         saved_payment_info.amt_msat = MillisatAmount(Some(amount_msat));
                         saved_payment_info.last_update = crate::utils::now().as_secs();
-                        match self.persister.persist_payment_info(
+                        self.record_received_payment(
+                            &payment_hash,
+                            amount_msat,
+                            saved_payment_info.fee_paid_msat,
+                        );
+                        if let Some(order_id) = saved_payment_info.order_id.as_ref() {
+                            log_info!(
+                                self.logger,
+                                "EVENT: PaymentClaimed payment hash {} is for order {order_id}",
+                                payment_hash.0.to_hex()
+                            );
+                        }
+                        match persister.persist_payment_info(
                             &payment_hash,
                             &saved_payment_info,
                             true,
@@ -260,6 +761,7 @@ impl<S: MutinyStorage> EventHandler<S> {
                         let payment_preimage = payment_preimage.map(|p| p.0);
                         let payment_secret = payment_secret.map(|p| p.0);
                         let last_update = crate::utils::now().as_secs();
+                        self.record_received_payment(&payment_hash, amount_msat, None);
 
                         let payment_info = PaymentInfo {
                             preimage: payment_preimage,
@@ -269,6 +771,11 @@ impl<S: MutinyStorage> EventHandler<S> {
                             fee_paid_msat: None,
                             payee_pubkey: receiver_node_id,
                             bolt11: None,
+                            // nothing was saved when it was claimable, so there
+                            // was no invoice or custom TLV with an order id
+                            order_id: None,
+                            custom_tlvs: vec![],
+                            failure: None,
                             last_update,
                         };
                         match self.persister.persist_payment_info(
@@ -296,6 +803,16 @@ impl<S: MutinyStorage> EventHandler<S> {
                     "EVENT: PaymentSent: {}",
                     payment_hash.0.to_hex()
                 );
+                self.router.payment_finished(&payment_hash);
+                self.update_payment_htlcs(&payment_hash, false, HtlcState::Fulfilled);
+                self.notify_payment(
+                    &payment_hash,
+                    false,
+                    PaymentState::Settled {
+                        preimage: Some(payment_preimage.0.to_hex()),
+                        fee_msat: fee_paid_msat,
+                    },
+                );
 
                 match self
                     .persister
@@ -305,6 +822,11 @@ impl<S: MutinyStorage> EventHandler<S> {
                         saved_payment_info.status = HTLCStatus::Succeeded;
                         saved_payment_info.preimage = Some(payment_preimage.0);
                         saved_payment_info.fee_paid_msat = fee_paid_msat;
+                        self.record_sent_payment(
+                            &payment_hash,
+                            saved_payment_info.amt_msat.0.unwrap_or(0),
+                            fee_paid_msat,
+                        );
                         saved_payment_info.last_update = crate::utils::now().as_secs();
                         match self.persister.persist_payment_info(
                             &payment_hash,
@@ -330,6 +852,7 @@ impl<S: MutinyStorage> EventHandler<S> {
             Event::OpenChannelRequest {
                 temporary_channel_id,
                 counterparty_node_id,
+                funding_satoshis,
                 ..
             } => {
                 log_debug!(
@@ -337,6 +860,48 @@ impl<S: MutinyStorage> EventHandler<S> {
                     "EVENT: OpenChannelRequest incoming: {counterparty_node_id}"
                 );
 
+                let lnurl_channel = self
+                    .persister
+                    .storage
+                    .open_lnurl_channel(&counterparty_node_id, crate::utils::now().as_secs())
+                    .unwrap_or_else(|e| {
+                        log_error!(self.logger, "Failed to check lnurl channels: {e}");
+                        None
+                    });
+                if let Some(request) = lnurl_channel.as_ref() {
+                    log_debug!(
+                        self.logger,
+                        "EVENT: OpenChannelRequest from LNURL-channel service {}",
+                        request.service
+                    );
+                }
+
+                if lnurl_channel.is_none()
+                    && self.lsp_client_pubkey.as_ref() != Some(&counterparty_node_id)
+                {
+                    let policy = self
+                        .persister
+                        .storage
+                        .get_inbound_policy()
+                        .unwrap_or_default();
+                    if !policy.should_accept_channel(&counterparty_node_id, funding_satoshis) {
+                        log_debug!(
+                            self.logger,
+                            "EVENT: OpenChannelRequest rejected by inbound policy"
+                        );
+                        if let Err(e) = self.channel_manager.force_close_without_broadcasting_txn(
+                            &temporary_channel_id,
+                            &counterparty_node_id,
+                        ) {
+                            log_error!(
+                                self.logger,
+                                "EVENT: OpenChannelRequest could not reject channel: {e:?}"
+                            );
+                        }
+                        return;
+                    }
+                }
+
                 let mut internal_channel_id_bytes = [0u8; 16];
                 if getrandom::getrandom(&mut internal_channel_id_bytes).is_err() {
                     log_debug!(
@@ -371,24 +936,95 @@ impl<S: MutinyStorage> EventHandler<S> {
                     log_result(result);
                 }
             }
-            Event::PaymentPathSuccessful { .. } => {
-                log_debug!(self.logger, "EVENT: PaymentPathSuccessful, ignored");
+            Event::PaymentPathSuccessful {
+                payment_hash, path, ..
+            } => {
+                log_debug!(self.logger, "EVENT: PaymentPathSuccessful");
+                if let Some(payment_hash) = payment_hash {
+                    let htlc = PaymentHtlc::new(
+                        false,
+                        path.hops.first().map(|h| h.short_channel_id.to_string()),
+                        Some(path.final_value_msat()),
+                        HtlcState::Fulfilled,
+                        crate::utils::now().as_secs(),
+                    );
+                    self.add_payment_htlc(&payment_hash, htlc);
+                    let state = PaymentState::PartSucceeded {
+                        amount_msat: path.final_value_msat(),
+                    };
+                    self.notify_payment(&payment_hash, false, state);
+                }
             }
-            Event::PaymentPathFailed { .. } => {
-                log_debug!(self.logger, "EVENT: PaymentPathFailed, ignored");
+            Event::PaymentPathFailed {
+                payment_hash,
+                path,
+                short_channel_id,
+                payment_failed_permanently,
+                failure,
+                ..
+            } => {
+                log_debug!(
+                    self.logger,
+                    "EVENT: PaymentPathFailed: {}, failing channel: {short_channel_id:?}",
+                    payment_hash.0.to_hex()
+                );
+                self.router.path_failed(&payment_hash, &path);
+                let now = crate::utils::now().as_secs();
+                let htlc = PaymentHtlc::new(
+                    false,
+                    path.hops.first().map(|h| h.short_channel_id.to_string()),
+                    Some(path.final_value_msat()),
+                    HtlcState::Failed,
+                    now,
+                );
+                self.add_payment_htlc(&payment_hash, htlc);
+                let failed_path = FailedPath::new(
+                    &failure,
+                    short_channel_id,
+                    payment_failed_permanently,
+                    path.final_value_msat(),
+                    now,
+                );
+                self.record_path_failure(&payment_hash, failed_path);
+                let state = PaymentState::PartFailed {
+                    amount_msat: path.final_value_msat(),
+                    short_channel_id,
+                };
+                self.notify_payment(&payment_hash, false, state);
             }
-            Event::ProbeSuccessful { .. } => {
-                log_debug!(self.logger, "EVENT: ProbeSuccessful, ignored");
+            Event::ProbeSuccessful { payment_id, .. } => {
+                log_debug!(
+                    self.logger,
+                    "EVENT: ProbeSuccessful: {}",
+                    payment_id.0.to_hex()
+                );
+                self.set_probe_result(payment_id, true);
             }
-            Event::ProbeFailed { .. } => {
-                log_debug!(self.logger, "EVENT: ProbeFailed, ignored");
+            Event::ProbeFailed {
+                payment_id,
+                short_channel_id,
+                ..
+            } => {
+                log_debug!(
+                    self.logger,
+                    "EVENT: ProbeFailed: {}, failing channel: {short_channel_id:?}",
+                    payment_id.0.to_hex()
+                );
+                self.set_probe_result(payment_id, false);
             }
-            Event::PaymentFailed { payment_hash, .. } => {
+            Event::PaymentFailed {
+                payment_hash,
+                reason,
+                ..
+            } => {
                 log_error!(
                     self.logger,
                     "EVENT: PaymentFailed: {}",
                     payment_hash.0.to_hex()
                 );
+                self.router.payment_finished(&payment_hash);
+                self.update_payment_htlcs(&payment_hash, false, HtlcState::Failed);
+                self.notify_payment(&payment_hash, false, PaymentState::failed(reason));
 
                 match self
                     .persister
@@ -396,6 +1032,10 @@ impl<S: MutinyStorage> EventHandler<S> {
                 {
                     Some(mut saved_payment_info) => {
                         saved_payment_info.status = HTLCStatus::Failed;
+                        saved_payment_info
+                            .failure
+                            .get_or_insert_with(PaymentFailure::default)
+                            .reason = Some(failure_reason_message(reason).to_string());
                         saved_payment_info.last_update = crate::utils::now().as_secs();
                         match self.persister.persist_payment_info(
                             &payment_hash,
@@ -421,8 +1061,28 @@ impl<S: MutinyStorage> EventHandler<S> {
             Event::PaymentForwarded { .. } => {
                 log_info!(self.logger, "EVENT: PaymentForwarded somehow...");
             }
-            Event::HTLCHandlingFailed { .. } => {
-                log_debug!(self.logger, "EVENT: HTLCHandlingFailed, ignored");
+            Event::HTLCHandlingFailed {
+                prev_channel_id,
+                failed_next_destination,
+            } => {
+                log_debug!(
+                    self.logger,
+                    "EVENT: HTLCHandlingFailed: {failed_next_destination:?}"
+                );
+                if let HTLCDestination::FailedPayment { payment_hash } = failed_next_destination {
+                    // the HTLCs we held for the payment are gone
+                    self.inbound_htlcs_resolved(&payment_hash);
+                    self.update_payment_htlcs(&payment_hash, true, HtlcState::Failed);
+                    let htlc = PaymentHtlc::new(
+                        true,
+                        Some(prev_channel_id.to_hex()),
+                        None,
+                        HtlcState::Failed,
+                        crate::utils::now().as_secs(),
+                    );
+                    self.add_payment_htlc(&payment_hash, htlc);
+                    self.expire_hold_invoice(&payment_hash);
+                }
             }
             Event::PendingHTLCsForwardable { time_forwardable } => {
                 log_debug!(
@@ -436,10 +1096,21 @@ impl<S: MutinyStorage> EventHandler<S> {
                 forwarding_channel_manager.process_pending_htlc_forwards();
             }
             Event::SpendableOutputs { outputs } => {
-                if let Err(e) = self.handle_spendable_outputs(&outputs).await {
-                    log_error!(self.logger, "Failed to handle spendable outputs: {e}");
-                    // if we have an error we should persist the outputs so we can try again later
-                    if let Err(e) = self.persister.persist_failed_spendable_outputs(outputs) {
+                // queue them up so outputs from channels closed around the
+                // same time can be swept in a single transaction
+                match self.persister.queue_pending_sweep(outputs.clone()) {
+                    Ok(_) => return,
+                    Err(e) => log_warn!(
+                        self.logger,
+                        "Failed to queue spendable outputs, sweeping now: {e}"
+                    ),
+                }
+
+                let failed = self.sweep_spendable_outputs(&outputs).await;
+
+                // persist the outputs that weren't swept so we can try again later
+                if !failed.is_empty() {
+                    if let Err(e) = self.persister.persist_failed_spendable_outputs(failed) {
                         log_error!(
                             self.logger,
                             "Failed to persist failed spendable outputs: {e}"
@@ -476,7 +1147,16 @@ impl<S: MutinyStorage> EventHandler<S> {
                     }
                 });
 
-                let closure = ChannelClosure::new(user_channel_id, channel_id, node_id, reason);
+                let mut closure = ChannelClosure::new(user_channel_id, channel_id, node_id, reason);
+                if let Some(postmortem) = closure.postmortem.as_mut() {
+                    self.fill_postmortem(channel_id, postmortem);
+                    log_warn!(
+                        self.logger,
+                        "Channel {} force closed: {}",
+                        channel_id.to_hex(),
+                        postmortem.summary()
+                    );
+                }
                 if let Err(e) = self
                     .persister
                     .persist_channel_closure(user_channel_id, closure)
@@ -553,11 +1233,12 @@ impl<S: MutinyStorage> EventHandler<S> {
 
     // Separate function to handle spendable outputs
     // This is so we can return a result and handle errors
-    // without having to use a lot of nested if statements
+    // without having to use a lot of nested if statements.
+    // Returns the outputs that could not be swept, an error means none were.
     pub(crate) async fn handle_spendable_outputs(
         &self,
         outputs: &[SpendableOutputDescriptor],
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Vec<SpendableOutputDescriptor>> {
         // Filter out static outputs, we don't want to spend them
         // because they have gone to our BDK wallet.
         // This would only be a waste in fees.
@@ -572,7 +1253,7 @@ impl<S: MutinyStorage> EventHandler<S> {
 
         // If there are no spendable outputs, we don't need to do anything
         if output_descriptors.is_empty() {
-            return Ok(());
+            return Ok(vec![]);
         }
 
         log_debug!(
@@ -581,22 +1262,134 @@ impl<S: MutinyStorage> EventHandler<S> {
             output_descriptors.len()
         );
 
+        // group the outputs by where they should be swept to
+        let channel_destinations = self.persister.storage.get_channel_sweep_destinations()?;
+        let mut global_script: Option<Option<Script>> = None;
+        let mut groups: Vec<(Option<Script>, Vec<&SpendableOutputDescriptor>)> = vec![];
+        for descriptor in output_descriptors {
+            let channel_script = self.funding_txo_for_output(descriptor).and_then(|f| {
+                channel_destinations
+                    .iter()
+                    .find(|(o, _)| *o == f)
+                    .map(|(_, a)| a.script_pubkey())
+            });
+            let script = match channel_script {
+                Some(script) => Some(script),
+                // only look this up once so xpubs don't skip addresses
+                None => match global_script.as_ref() {
+                    Some(script) => script.clone(),
+                    None => {
+                        let script = self.persister.storage.peek_sweep_script()?;
+                        global_script = Some(script.clone());
+                        script
+                    }
+                },
+            };
+
+            match groups.iter_mut().find(|(s, _)| *s == script) {
+                Some((_, group)) => group.push(descriptor),
+                None => groups.push((script, vec![descriptor])),
+            }
+        }
+
         let tx_feerate = self
             .fee_estimator
             .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
-        let spending_tx = self
-            .keys_manager
-            .spend_spendable_outputs(
-                &output_descriptors,
+        let mut failed = vec![];
+        for (destination, descriptors) in groups {
+            let spending_tx = match self.keys_manager.spend_spendable_outputs(
+                &descriptors,
                 Vec::new(),
+                destination.clone(),
                 tx_feerate,
                 &Secp256k1::new(),
-            )
-            .map_err(|_| anyhow!("Failed to spend spendable outputs"))?;
+            ) {
+                Ok(tx) => tx,
+                Err(_) => {
+                    log_error!(
+                        self.logger,
+                        "Failed to spend {} spendable outputs",
+                        descriptors.len()
+                    );
+                    failed.extend(descriptors.into_iter().cloned());
+                    continue;
+                }
+            };
 
-        self.wallet.broadcast_transaction(spending_tx).await?;
+            if let Err(e) = self.wallet.broadcast_transaction(spending_tx).await {
+                log_error!(self.logger, "Failed to broadcast sweep transaction: {e}");
+                failed.extend(descriptors.into_iter().cloned());
+                continue;
+            }
 
-        Ok(())
+            // only move an xpub destination to its next address once it has been used,
+            // so retries of these outputs go to the same address
+            if let (Some(script), Some(Some(global))) = (&destination, &global_script) {
+                if script == global {
+                    if let Err(e) = self.persister.storage.sweep_script_used(script) {
+                        log_error!(self.logger, "Failed to advance sweep destination: {e}");
+                    }
+                }
+            }
+        }
+
+        Ok(failed)
+    }
+
+    /// Sweeps the outputs together, then retries the ones that failed one at a time
+    /// in case a single bad output was failing the rest of its group.
+    /// Returns the outputs that still could not be swept.
+    pub(crate) async fn sweep_spendable_outputs(
+        &self,
+        outputs: &[SpendableOutputDescriptor],
+    ) -> Vec<SpendableOutputDescriptor> {
+        let failed = match self.handle_spendable_outputs(outputs).await {
+            Ok(failed) => failed,
+            Err(e) => {
+                log_error!(self.logger, "Failed to handle spendable outputs: {e}");
+                outputs.to_vec()
+            }
+        };
+
+        // if there was only one we don't need to retry
+        if failed.len() <= 1 {
+            return failed;
+        }
+
+        let mut still_failed = vec![];
+        for o in failed {
+            match self.handle_spendable_outputs(&[o.clone()]).await {
+                Ok(f) if f.is_empty() => {}
+                Ok(_) => still_failed.push(o),
+                Err(e) => {
+                    log_error!(self.logger, "Failed to sweep spendable output: {e}");
+                    still_failed.push(o);
+                }
+            }
+        }
+
+        still_failed
+    }
+
+    /// Finds the funding outpoint of the channel a spendable output came from
+    /// by looking for its transaction in the channel monitors.
+    fn funding_txo_for_output(&self, descriptor: &SpendableOutputDescriptor) -> Option<OutPoint> {
+        let txid = match descriptor {
+            SpendableOutputDescriptor::StaticOutput { outpoint, .. } => outpoint.txid,
+            SpendableOutputDescriptor::DelayedPaymentOutput(d) => d.outpoint.txid,
+            SpendableOutputDescriptor::StaticPaymentOutput(d) => d.outpoint.txid,
+        };
+
+        self.chain_monitor
+            .list_monitors()
+            .into_iter()
+            .find(|funding_txo| {
+                self.chain_monitor
+                    .get_monitor(*funding_txo)
+                    .map(|m| m.get_relevant_txids().iter().any(|(t, _)| *t == txid))
+                    .unwrap_or(false)
+            })
+            .map(|funding_txo| funding_txo.into_bitcoin_outpoint())
     }
 }
 
@@ -626,6 +1419,9 @@ mod test {
             bolt11: None,
             payee_pubkey: Some(pubkey),
             secret: None,
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
             last_update: utils::now().as_secs(),
         };
 
@@ -0,0 +1,157 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, OutPoint};
+use serde::{Deserialize, Serialize};
+
+pub const EXTERNAL_FUNDINGS_KEY: &str = "external_fundings";
+
+/// How long we wait for the external wallet to pay before giving up, one week
+pub(crate) const EXTERNAL_FUNDING_EXPIRY_SECS: u64 = 60 * 60 * 24 * 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternalFundingStatus {
+    /// Waiting for the external wallet to pay the funding address
+    Waiting,
+    /// Less than the requested amount has confirmed, we keep waiting for the rest
+    Underpaid { received_sats: u64 },
+    /// The channel was opened with the funds
+    Opened { channel: Option<OutPoint> },
+    /// Nothing more will happen, any funds that were sent stay in the on-chain wallet
+    Failed { reason: String },
+}
+
+/// A channel open paid for from another wallet.
+///
+/// The other wallet pays an address of our on-chain wallet and once the funds
+/// confirm we sweep exactly those outputs into the channel. The funds are ours
+/// the whole time, so if the open fails they stay in the on-chain balance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalFunding {
+    /// The address the external wallet should pay
+    pub address: Address,
+    /// What the external wallet needs to send, the channel size plus the fee to open it
+    pub amount_sats: u64,
+    pub from_node: PublicKey,
+    /// The peer to open the channel with, the LSP if not set
+    pub to_pubkey: Option<PublicKey>,
+    pub created_at: u64,
+    pub status: ExternalFundingStatus,
+}
+
+impl ExternalFunding {
+    pub(crate) fn is_pending(&self) -> bool {
+        matches!(
+            self.status,
+            ExternalFundingStatus::Waiting | ExternalFundingStatus::Underpaid { .. }
+        )
+    }
+
+    /// Updates the status with the confirmed amount paid to the address.
+    /// Returns true if there is enough to open the channel.
+    pub(crate) fn update(&mut self, received_sats: u64, now: u64) -> bool {
+        if !self.is_pending() {
+            return false;
+        }
+
+        if received_sats >= self.amount_sats {
+            return true;
+        }
+
+        if now >= self.created_at + EXTERNAL_FUNDING_EXPIRY_SECS {
+            let reason = if received_sats == 0 {
+                "Expired before the address was paid".to_string()
+            } else {
+                format!(
+                    "Only {received_sats} of {} sats were paid",
+                    self.amount_sats
+                )
+            };
+            self.status = ExternalFundingStatus::Failed { reason };
+        } else if received_sats > 0 {
+            self.status = ExternalFundingStatus::Underpaid { received_sats };
+        }
+
+        false
+    }
+}
+
+pub trait ExternalFundingStorage {
+    fn get_external_fundings(&self) -> Result<Vec<ExternalFunding>, MutinyError>;
+    fn persist_external_fundings(&self, fundings: Vec<ExternalFunding>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> ExternalFundingStorage for S {
+    fn get_external_fundings(&self) -> Result<Vec<ExternalFunding>, MutinyError> {
+        let fundings: Option<Vec<ExternalFunding>> = self.get_data(EXTERNAL_FUNDINGS_KEY)?;
+        Ok(fundings.unwrap_or_default())
+    }
+
+    fn persist_external_fundings(&self, fundings: Vec<ExternalFunding>) -> Result<(), MutinyError> {
+        self.set_data(EXTERNAL_FUNDINGS_KEY, fundings, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_funding() -> ExternalFunding {
+        ExternalFunding {
+            address: Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap(),
+            amount_sats: 100_000,
+            from_node: PublicKey::from_str(
+                "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+            )
+            .unwrap(),
+            to_pubkey: None,
+            created_at: 0,
+            status: ExternalFundingStatus::Waiting,
+        }
+    }
+
+    #[test]
+    fn test_external_funding_update() {
+        log!("test external funding update");
+
+        let mut funding = dummy_funding();
+        assert!(!funding.update(0, 100));
+        assert_eq!(funding.status, ExternalFundingStatus::Waiting);
+
+        assert!(!funding.update(50_000, 100));
+        assert_eq!(
+            funding.status,
+            ExternalFundingStatus::Underpaid {
+                received_sats: 50_000
+            }
+        );
+
+        // paying the rest lets us open
+        assert!(funding.update(100_000, 200));
+
+        // underpaid until it expires
+        let mut funding = dummy_funding();
+        assert!(!funding.update(50_000, EXTERNAL_FUNDING_EXPIRY_SECS));
+        assert!(!funding.is_pending());
+        assert!(!funding.update(100_000, EXTERNAL_FUNDING_EXPIRY_SECS + 1));
+    }
+
+    #[test]
+    fn test_external_funding_storage() {
+        log!("test external funding storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_external_fundings().unwrap().is_empty());
+
+        let fundings = vec![dummy_funding()];
+        storage.persist_external_fundings(fundings.clone()).unwrap();
+        assert_eq!(storage.get_external_fundings().unwrap(), fundings);
+    }
+}
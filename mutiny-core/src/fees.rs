@@ -1,3 +1,4 @@
+use crate::http_cache::cached_get;
 use crate::logging::MutinyLogger;
 use crate::multiesplora::MultiEsploraClient;
 use crate::storage::MutinyStorage;
@@ -18,7 +19,6 @@ pub(crate) const TX_OVERHEAD: usize = 10;
 pub(crate) const TAPROOT_INPUT_NON_WITNESS_SIZE: usize = 41;
 pub(crate) const TAPROOT_INPUT_WITNESS_SIZE: usize = 67;
 pub(crate) const P2WSH_OUTPUT_SIZE: usize = 43;
-#[allow(dead_code)]
 pub(crate) const TAPROOT_OUTPUT_SIZE: usize = 43;
 
 #[derive(Clone)]
@@ -87,15 +87,9 @@ struct MempoolFees {
 
 impl<S: MutinyStorage> MutinyFeeEstimator<S> {
     async fn get_mempool_recommended_fees(&self) -> anyhow::Result<HashMap<String, f64>> {
-        let fees = self
-            .esplora
-            .client()
-            .get(&format!("{}/v1/fees/recommended", self.esplora.url()))
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<MempoolFees>()
-            .await?;
+        let url = format!("{}/v1/fees/recommended", self.esplora.url());
+        let body = cached_get(self.esplora.http_client(), &self.storage, &url).await?;
+        let fees: MempoolFees = serde_json::from_str(&body)?;
 
         // convert to hashmap of num blocks -> fee rate
         let mut fee_estimates = HashMap::new();
@@ -108,6 +102,12 @@ impl<S: MutinyStorage> MutinyFeeEstimator<S> {
         Ok(fee_estimates)
     }
 
+    async fn get_esplora_fee_estimates(&self) -> anyhow::Result<HashMap<String, f64>> {
+        let url = format!("{}/fee-estimates", self.esplora.url());
+        let body = cached_get(self.esplora.http_client(), &self.storage, &url).await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
     pub async fn update_fee_estimates_if_necessary(&self) -> Result<(), MutinyError> {
         let last_sync = self.get_last_sync_time().await;
         if last_sync.is_none() || utils::now().as_secs() > last_sync.unwrap() + 60 * 10 {
@@ -131,9 +131,9 @@ impl<S: MutinyStorage> MutinyFeeEstimator<S> {
                     self.logger,
                     "Failed to retrieve fees from mempool, falling back to esplora: {e}"
                 );
-                self.esplora.get_fee_estimates().await.map_err(|e| {
+                self.get_esplora_fee_estimates().await.map_err(|e| {
                     log_trace!(self.logger, "Failed to get esplora fee: {e}");
-                    e
+                    MutinyError::Other(e)
                 })?
             }
         };
@@ -0,0 +1,243 @@
+//! Entry points for fuzzing the code that handles untrusted input,
+//! only built with the `fuzzing` feature.
+//!
+//! Everything here runs off-browser on in-memory storage, with fixed keys and
+//! times so a crashing input reproduces. The fuzz targets are in `mutiny-core/fuzz`.
+
+use crate::gossip::GossipRateLimiter;
+use crate::ldkstorage::MutinyNodePersister;
+use crate::lnurlpay::parse_lnurl_or_address;
+use crate::logging::MutinyLogger;
+use crate::node::NetworkGraph;
+use crate::offline_receive::OfflineReceiveHandler;
+use crate::payments::parse_payment_request;
+use crate::peermanager::{GossipMessageHandler, GossipQueries, MutinyMessageHandler};
+use crate::scb::message_handler::SCBMessageHandler;
+use crate::storage::MemoryStorage;
+use crate::uri::parse_uri;
+use crate::utils;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::Network;
+use lightning::io::Cursor;
+use lightning::ln::msgs::{self, RoutingMessageHandler};
+use lightning::ln::peer_handler::{
+    CustomMessageHandler, ErroringMessageHandler, IgnoringMessageHandler, MessageHandler,
+    PeerManager, SocketDescriptor,
+};
+use lightning::ln::wire::CustomMessageReader;
+use lightning::sign::KeysManager;
+use lightning::util::ser::Readable;
+use lightning_invoice::Bolt11Invoice;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The time the harness pretends it is, so runs are reproducible
+const FUZZ_TIME_SECS: u64 = 1_700_000_000;
+
+/// A connection that accepts everything written to it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FuzzSocketDescriptor(u64);
+
+impl SocketDescriptor for FuzzSocketDescriptor {
+    fn send_data(&mut self, data: &[u8], _resume_read: bool) -> usize {
+        data.len()
+    }
+
+    fn disconnect_socket(&mut self) {}
+}
+
+type FuzzPeerManager = PeerManager<
+    FuzzSocketDescriptor,
+    Arc<ErroringMessageHandler>,
+    Arc<GossipMessageHandler<MemoryStorage>>,
+    Arc<IgnoringMessageHandler>,
+    Arc<MutinyLogger>,
+    Arc<MutinyMessageHandler<MemoryStorage>>,
+    Arc<KeysManager>,
+>;
+
+/// A node's message handling stack, without channels or a network
+pub struct FuzzHarness {
+    peer_manager: FuzzPeerManager,
+    gossip_handler: Arc<GossipMessageHandler<MemoryStorage>>,
+    custom_handler: Arc<MutinyMessageHandler<MemoryStorage>>,
+    /// Treated as our LSP, so its messages get past the LSP-only checks
+    lsp_pubkey: PublicKey,
+    next_descriptor: u64,
+}
+
+impl Default for FuzzHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FuzzHarness {
+    pub fn new() -> Self {
+        let storage = MemoryStorage::default();
+        let logger = Arc::new(MutinyLogger::default());
+        let network = Network::Regtest;
+
+        let lsp_pubkey = SecretKey::from_slice(&[1; 32])
+            .expect("valid key")
+            .public_key(&Secp256k1::new());
+
+        let persister = Arc::new(MutinyNodePersister::new(
+            "fuzz".to_string(),
+            storage.clone(),
+            logger.clone(),
+        ));
+        let offline_receive = Arc::new(OfflineReceiveHandler::new(
+            persister,
+            Some(lsp_pubkey),
+            logger.clone(),
+        ));
+        let gossip_handler = Arc::new(GossipMessageHandler {
+            storage,
+            network_graph: Arc::new(NetworkGraph::new(network, logger.clone())),
+            lsp_pubkey: Some(lsp_pubkey),
+            rate_limiter: Arc::new(utils::Mutex::new(GossipRateLimiter::default())),
+            gossip_queries: Arc::new(utils::Mutex::new(GossipQueries::default())),
+            utxo_lookup: None,
+            network,
+            offline_receive: offline_receive.clone(),
+            logger: logger.clone(),
+        });
+        let custom_handler = Arc::new(MutinyMessageHandler {
+            scb: Arc::new(SCBMessageHandler::new()),
+            offline_receive,
+        });
+
+        let keys_manager = Arc::new(KeysManager::new(&[2; 32], FUZZ_TIME_SECS, 0));
+        let peer_manager = PeerManager::new(
+            MessageHandler {
+                chan_handler: Arc::new(ErroringMessageHandler::new()),
+                route_handler: gossip_handler.clone(),
+                onion_message_handler: Arc::new(IgnoringMessageHandler {}),
+                custom_message_handler: custom_handler.clone(),
+            },
+            FUZZ_TIME_SECS as u32,
+            &[3; 32],
+            logger,
+            keys_manager,
+        );
+
+        Self {
+            peer_manager,
+            gossip_handler,
+            custom_handler,
+            lsp_pubkey,
+            next_descriptor: 0,
+        }
+    }
+
+    /// Feeds raw bytes to the peer manager as a new inbound connection,
+    /// starting at the noise handshake.
+    pub fn read_event(&mut self, data: &[u8]) {
+        self.next_descriptor += 1;
+        let mut descriptor = FuzzSocketDescriptor(self.next_descriptor);
+        if self
+            .peer_manager
+            .new_inbound_connection(descriptor.clone(), None)
+            .is_err()
+        {
+            return;
+        }
+
+        if self.peer_manager.read_event(&mut descriptor, data).is_ok() {
+            self.peer_manager.process_events();
+        }
+        self.peer_manager.socket_disconnected(&descriptor);
+    }
+
+    /// Decodes a gossip message and hands it to our gossip handler.
+    /// The first byte picks the message type, the rest is the message.
+    pub fn gossip_message(&self, data: &[u8]) {
+        let Some((kind, data)) = data.split_first() else {
+            return;
+        };
+        let mut reader = Cursor::new(data);
+        let handler = &self.gossip_handler;
+        let peer = &self.lsp_pubkey;
+
+        let _ = match kind % 5 {
+            0 => msgs::NodeAnnouncement::read(&mut reader)
+                .map(|msg| handler.handle_node_announcement(&msg).map(|_| ())),
+            1 => msgs::ChannelAnnouncement::read(&mut reader)
+                .map(|msg| handler.handle_channel_announcement(&msg).map(|_| ())),
+            2 => msgs::ChannelUpdate::read(&mut reader)
+                .map(|msg| handler.handle_channel_update(&msg).map(|_| ())),
+            3 => msgs::ReplyChannelRange::read(&mut reader)
+                .map(|msg| handler.handle_reply_channel_range(peer, msg)),
+            _ => msgs::ReplyShortChannelIdsEnd::read(&mut reader)
+                .map(|msg| handler.handle_reply_short_channel_ids_end(peer, msg)),
+        };
+    }
+
+    /// Decodes a custom message and hands it to our custom message handler, as
+    /// if our LSP sent it. The first two bytes are the big endian message type.
+    pub fn custom_message(&self, data: &[u8]) {
+        if data.len() < 2 {
+            return;
+        }
+        let msg_type = u16::from_be_bytes([data[0], data[1]]);
+        let mut reader = Cursor::new(&data[2..]);
+
+        if let Ok(Some(msg)) = self.custom_handler.read(msg_type, &mut reader) {
+            let _ = self
+                .custom_handler
+                .handle_custom_message(msg, &self.lsp_pubkey);
+            self.custom_handler.get_and_clear_pending_msg();
+        }
+    }
+}
+
+/// Runs untrusted text through every parser that takes a payment request,
+/// invoice, LNURL or URI.
+pub fn parse_payment_input(data: &[u8]) {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let _ = Bolt11Invoice::from_str(input);
+    let _ = parse_lnurl_or_address(input);
+    for network in [Network::Bitcoin, Network::Testnet, Network::Regtest] {
+        let _ = parse_payment_request(input, network);
+        let _ = parse_uri(input, network);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offline_receive::HELD_HTLCS_TYPE;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_fuzz_harness() {
+        log!("test fuzz harness");
+
+        // garbage shouldn't panic anywhere
+        let mut harness = FuzzHarness::new();
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0],
+            vec![0xff; 50],
+            (0..=255).collect(),
+            [HELD_HTLCS_TYPE.to_be_bytes().to_vec(), vec![0xff, 0xff]].concat(),
+            [HELD_HTLCS_TYPE.to_be_bytes().to_vec(), vec![0, 1, 7]].concat(),
+        ];
+        for input in inputs.iter() {
+            harness.read_event(input);
+            harness.gossip_message(input);
+            harness.custom_message(input);
+            parse_payment_input(input);
+        }
+
+        parse_payment_input(b"lightning:lnurl1dp68gurn8ghj7");
+        parse_payment_input("bitcoin:bcrt1q?amount=\u{1F4A9}".as_bytes());
+    }
+}
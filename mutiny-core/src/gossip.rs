@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::hex::ToHex;
 use bitcoin::Network;
 use lightning::routing::gossip::NodeId;
 use lightning::util::logger::Logger;
@@ -11,10 +11,11 @@ use lightning::{
     ln::msgs::NodeAnnouncement, routing::scoring::ProbabilisticScoringDecayParameters,
 };
 use lightning::{log_debug, log_error, log_info, log_warn};
-use reqwest::Client;
 use reqwest::{Method, Url};
 use serde::{Deserialize, Serialize};
 
+use crate::encoding::Bytes;
+use crate::http_client::{HttpRequest, MutinyHttpClient};
 use crate::logging::MutinyLogger;
 use crate::node::{NetworkGraph, ProbScorer, RapidGossipSync};
 use crate::storage::MutinyStorage;
@@ -26,6 +27,10 @@ pub const GOSSIP_SYNC_TIME_KEY: &str = "last_sync_timestamp";
 pub const NETWORK_GRAPH_KEY: &str = "network_graph";
 pub const PROB_SCORER_KEY: &str = "prob_scorer";
 
+/// Node aliases are at most 32 bytes of UTF-8, padded with zeros
+pub(crate) const NODE_ALIAS_LEN: usize = 32;
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
 struct Gossip {
     pub last_sync_timestamp: u32,
     pub network_graph: Arc<NetworkGraph>,
@@ -47,8 +52,7 @@ async fn get_scorer(
     network_graph: Arc<NetworkGraph>,
     logger: Arc<MutinyLogger>,
 ) -> Result<Option<ProbScorer>, MutinyError> {
-    if let Some(prob_scorer_str) = storage.get_data::<String>(PROB_SCORER_KEY)? {
-        let prob_scorer_bytes: Vec<u8> = Vec::from_hex(&prob_scorer_str)?;
+    if let Some(Bytes(prob_scorer_bytes)) = storage.get_data(PROB_SCORER_KEY)? {
         let mut readable_bytes = lightning::io::Cursor::new(prob_scorer_bytes);
         let params = ProbabilisticScoringDecayParameters::default();
         let args = (params, Arc::clone(&network_graph), Arc::clone(&logger));
@@ -69,9 +73,8 @@ async fn get_gossip_data(
     };
 
     // Get the `network_graph`
-    let network_graph: Arc<NetworkGraph> = match storage.get_data::<String>(NETWORK_GRAPH_KEY)? {
-        Some(network_graph_str) => {
-            let network_graph_bytes: Vec<u8> = Vec::from_hex(&network_graph_str)?;
+    let network_graph: Arc<NetworkGraph> = match storage.get_data(NETWORK_GRAPH_KEY)? {
+        Some(Bytes(network_graph_bytes)) => {
             let mut readable_bytes = lightning::io::Cursor::new(network_graph_bytes);
             Arc::new(NetworkGraph::read(&mut readable_bytes, logger.clone())?)
         }
@@ -131,7 +134,7 @@ fn write_gossip_data(
     storage.set_data(GOSSIP_SYNC_TIME_KEY, last_sync_timestamp, None)?;
 
     // Save the network graph
-    storage.set_data(NETWORK_GRAPH_KEY, network_graph.encode().to_hex(), None)?;
+    storage.set_data_compressed(NETWORK_GRAPH_KEY, Bytes(network_graph.encode()), None)?;
 
     Ok(())
 }
@@ -141,6 +144,7 @@ pub async fn get_gossip_sync(
     user_rgs_url: Option<String>,
     remote_scorer_url: Option<String>,
     auth_client: Option<Arc<MutinyAuthClient>>,
+    http_client: &dyn MutinyHttpClient,
     network: Network,
     logger: Arc<MutinyLogger>,
 ) -> Result<(RapidGossipSync, ProbScorer), MutinyError> {
@@ -210,6 +214,7 @@ pub async fn get_gossip_sync(
 
         let now = utils::now().as_secs();
         let fetch_result = fetch_updated_gossip(
+            http_client,
             rgs_url,
             now,
             gossip_data.last_sync_timestamp,
@@ -231,6 +236,7 @@ pub async fn get_gossip_sync(
 }
 
 async fn fetch_updated_gossip(
+    http_client: &dyn MutinyHttpClient,
     rgs_url: String,
     now: u64,
     last_sync_timestamp: u32,
@@ -238,20 +244,14 @@ async fn fetch_updated_gossip(
     storage: &impl MutinyStorage,
     logger: &MutinyLogger,
 ) -> Result<(), MutinyError> {
-    let http_client = Client::builder()
-        .build()
-        .map_err(|_| MutinyError::RapidGossipSyncError)?;
     let rgs_response = http_client
-        .get(rgs_url)
-        .send()
+        .send(HttpRequest::get(rgs_url))
         .await
         .map_err(|_| MutinyError::RapidGossipSyncError)?;
-
-    let rgs_data = rgs_response
-        .bytes()
-        .await
-        .map_err(|_| MutinyError::RapidGossipSyncError)?
-        .to_vec();
+    if !rgs_response.is_success() {
+        return Err(MutinyError::RapidGossipSyncError);
+    }
+    let rgs_data = rgs_response.body;
 
     let new_last_sync_timestamp_result =
         gossip_sync.update_network_graph_no_std(&rgs_data, Some(now))?;
@@ -356,7 +356,7 @@ impl From<NodeAnnouncement> for LnPeerMetadata {
     fn from(value: NodeAnnouncement) -> Self {
         Self {
             connection_string: None, // todo get from addresses
-            alias: Some(value.contents.alias.to_string()),
+            alias: Some(decode_node_alias(&value.contents.alias.0)),
             color: Some(value.contents.rgb.to_hex()),
             label: None,
             timestamp: Some(value.contents.timestamp),
@@ -365,6 +365,63 @@ impl From<NodeAnnouncement> for LnPeerMetadata {
     }
 }
 
+/// Encodes an alias for a node announcement. Aliases longer than 32 bytes are
+/// truncated without splitting a character or a character from its accents.
+pub(crate) fn encode_node_alias(alias: &str) -> [u8; NODE_ALIAS_LEN] {
+    let mut end = alias.len();
+    if end > NODE_ALIAS_LEN {
+        // find the last place that starts a new character that fits
+        let mut prev = None;
+        for (i, c) in alias.char_indices() {
+            if i > NODE_ALIAS_LEN {
+                break;
+            }
+            if !is_joined_to_previous(c, prev) {
+                end = i;
+            }
+            prev = Some(c);
+        }
+    }
+
+    let mut bytes = [0; NODE_ALIAS_LEN];
+    bytes[..end].copy_from_slice(&alias.as_bytes()[..end]);
+    bytes
+}
+
+/// Decodes the alias of a node announcement. Other implementations may cut a
+/// character in half when truncating, so an incomplete character at the end is dropped.
+pub(crate) fn decode_node_alias(alias: &[u8; NODE_ALIAS_LEN]) -> String {
+    let end = alias.iter().position(|b| *b == 0).unwrap_or(NODE_ALIAS_LEN);
+    let bytes = match std::str::from_utf8(&alias[..end]) {
+        Err(e) if e.error_len().is_none() => &alias[..e.valid_up_to()],
+        _ => &alias[..end],
+    };
+
+    String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+/// Whether the character is shown together with the one before it. This covers
+/// combining accents, variation selectors, emoji modifiers and tags, and anything
+/// after a zero width joiner.
+fn is_joined_to_previous(c: char, prev: Option<char>) -> bool {
+    prev == Some(ZERO_WIDTH_JOINER)
+        || matches!(
+            c as u32,
+            0x0300..=0x036F
+                | 0x1AB0..=0x1AFF
+                | 0x1DC0..=0x1DFF
+                | 0x20D0..=0x20FF
+                | 0xFE00..=0xFE0F
+                | 0xFE20..=0xFE2F
+                | 0x1F3FB..=0x1F3FF
+                | 0xE0020..=0xE007F
+                | 0x200D
+        )
+}
+
 pub(crate) fn read_peer_info(
     storage: &impl MutinyStorage,
     node_id: &NodeId,
@@ -509,6 +566,145 @@ pub(crate) fn get_rgs_url(
     }
 }
 
+/// How many blocks before the RGS snapshot to also ask peers about,
+/// as channels can take a while to make it into a snapshot
+const GOSSIP_QUERY_BUFFER_BLOCKS: u32 = 144;
+/// The most blocks we ask peers about at once
+const MAX_GOSSIP_QUERY_BLOCKS: u32 = 2016;
+
+/// Estimates how many recent blocks to ask peers about to find
+/// the channels that are newer than our last RGS snapshot.
+pub(crate) fn blocks_since_gossip_sync(now: u64, last_sync_timestamp: u32) -> u32 {
+    let secs = now.saturating_sub(last_sync_timestamp as u64);
+    let blocks = (secs / 600).min(MAX_GOSSIP_QUERY_BLOCKS as u64) as u32;
+    (blocks + GOSSIP_QUERY_BUFFER_BLOCKS).min(MAX_GOSSIP_QUERY_BLOCKS)
+}
+/// How long each rate limiting window for gossip messages lasts
+const GOSSIP_RATE_LIMIT_WINDOW_SECS: u64 = 60;
+/// The most channel_updates we process for one side of a channel per window
+const MAX_CHANNEL_UPDATES_PER_WINDOW: u32 = 2;
+/// The most new channel_announcements we process per window
+const MAX_CHANNEL_ANNOUNCEMENTS_PER_WINDOW: u32 = 500;
+
+/// Counters for the gossip messages our peers sent us directly.
+/// Most of our gossip comes from RGS and is not counted here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipStats {
+    pub channel_updates: u64,
+    pub channel_updates_duplicate: u64,
+    pub channel_updates_rate_limited: u64,
+    pub channel_announcements: u64,
+    pub channel_announcements_duplicate: u64,
+    pub channel_announcements_rate_limited: u64,
+}
+
+impl GossipStats {
+    pub(crate) fn add(&mut self, other: &GossipStats) {
+        self.channel_updates += other.channel_updates;
+        self.channel_updates_duplicate += other.channel_updates_duplicate;
+        self.channel_updates_rate_limited += other.channel_updates_rate_limited;
+        self.channel_announcements += other.channel_announcements;
+        self.channel_announcements_duplicate += other.channel_announcements_duplicate;
+        self.channel_announcements_rate_limited += other.channel_announcements_rate_limited;
+    }
+}
+
+/// The size of our network graph along with the gossip counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphStats {
+    pub channels: usize,
+    pub nodes: usize,
+    pub gossip: GossipStats,
+}
+
+/// What to do with a gossip message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GossipVerdict {
+    Process,
+    Duplicate,
+    RateLimited,
+}
+
+/// Drops repeated and excessive gossip so a peer flooding us
+/// can't burn all of our CPU on signature checks and graph updates.
+///
+/// The gossip handler isn't told which peer a message came from, so
+/// channel_updates are limited per channel direction and
+/// channel_announcements are limited overall.
+#[derive(Debug, Default)]
+pub(crate) struct GossipRateLimiter {
+    window_start: u64,
+    /// (short channel id, direction) -> (newest timestamp seen, updates this window)
+    channel_updates: HashMap<(u64, u8), (u32, u32)>,
+    channel_announcements: u32,
+    stats: GossipStats,
+}
+
+impl GossipRateLimiter {
+    fn roll_window(&mut self, now: u64) {
+        if now >= self.window_start + GOSSIP_RATE_LIMIT_WINDOW_SECS {
+            self.window_start = now;
+            self.channel_updates.clear();
+            self.channel_announcements = 0;
+        }
+    }
+
+    pub(crate) fn check_channel_update(
+        &mut self,
+        short_channel_id: u64,
+        direction: u8,
+        timestamp: u32,
+        now: u64,
+    ) -> GossipVerdict {
+        self.roll_window(now);
+
+        let entry = self
+            .channel_updates
+            .entry((short_channel_id, direction))
+            .or_insert((0, 0));
+        let verdict = if entry.1 > 0 && timestamp <= entry.0 {
+            GossipVerdict::Duplicate
+        } else if entry.1 >= MAX_CHANNEL_UPDATES_PER_WINDOW {
+            GossipVerdict::RateLimited
+        } else {
+            *entry = (timestamp, entry.1 + 1);
+            GossipVerdict::Process
+        };
+
+        match verdict {
+            GossipVerdict::Process => self.stats.channel_updates += 1,
+            GossipVerdict::Duplicate => self.stats.channel_updates_duplicate += 1,
+            GossipVerdict::RateLimited => self.stats.channel_updates_rate_limited += 1,
+        }
+        verdict
+    }
+
+    /// `known` is whether the channel is already in our network graph
+    pub(crate) fn check_channel_announcement(&mut self, known: bool, now: u64) -> GossipVerdict {
+        self.roll_window(now);
+
+        let verdict = if known {
+            GossipVerdict::Duplicate
+        } else if self.channel_announcements >= MAX_CHANNEL_ANNOUNCEMENTS_PER_WINDOW {
+            GossipVerdict::RateLimited
+        } else {
+            self.channel_announcements += 1;
+            GossipVerdict::Process
+        };
+
+        match verdict {
+            GossipVerdict::Process => self.stats.channel_announcements += 1,
+            GossipVerdict::Duplicate => self.stats.channel_announcements_duplicate += 1,
+            GossipVerdict::RateLimited => self.stats.channel_announcements_rate_limited += 1,
+        }
+        verdict
+    }
+
+    pub(crate) fn stats(&self) -> GossipStats {
+        self.stats
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::storage::MemoryStorage;
@@ -567,6 +763,39 @@ mod test {
         assert_eq!(max_timestamp.merge(&min_timestamp), max_timestamp);
     }
 
+    #[test]
+    fn test_node_alias_round_trip() {
+        let alias = "Nodo de Peña 🌶️";
+        assert_eq!(decode_node_alias(&encode_node_alias(alias)), alias);
+
+        // 31 bytes then a 2 byte character, which doesn't fit
+        let alias = format!("{}ñ", "a".repeat(31));
+        assert_eq!(
+            decode_node_alias(&encode_node_alias(&alias)),
+            "a".repeat(31)
+        );
+
+        // an accent is not split from its letter
+        let alias = format!("{}n\u{303}", "a".repeat(30));
+        assert_eq!(
+            decode_node_alias(&encode_node_alias(&alias)),
+            "a".repeat(30)
+        );
+
+        // an emoji sequence is not split at its joiner
+        let alias = format!("{}👩\u{200d}👩", "a".repeat(26));
+        assert_eq!(
+            decode_node_alias(&encode_node_alias(&alias)),
+            "a".repeat(26)
+        );
+
+        // an alias cut in the middle of a character by someone else
+        let mut bytes = [0; NODE_ALIAS_LEN];
+        let cut = format!("{}ñ", "a".repeat(31));
+        bytes.copy_from_slice(&cut.as_bytes()[..NODE_ALIAS_LEN]);
+        assert_eq!(decode_node_alias(&bytes), "a".repeat(31));
+    }
+
     #[test]
     // hack to disable this test
     #[cfg(feature = "ignored_tests")]
@@ -575,10 +804,18 @@ mod test {
         let storage = MemoryStorage::default();
 
         let logger = Arc::new(MutinyLogger::default());
-        let _gossip_sync =
-            get_gossip_sync(&storage, None, None, None, Network::Regtest, logger.clone())
-                .await
-                .unwrap();
+        let http_client = crate::http_client::ReqwestHttpClient::default();
+        let _gossip_sync = get_gossip_sync(
+            &storage,
+            None,
+            None,
+            None,
+            &http_client,
+            Network::Regtest,
+            logger.clone(),
+        )
+        .await
+        .unwrap();
 
         let data = get_gossip_data(&storage, logger).await.unwrap();
 
@@ -629,4 +866,80 @@ mod test {
         assert!(read.is_some());
         assert_eq!(read.unwrap(), expected);
     }
+
+    #[test]
+    fn test_channel_update_rate_limit() {
+        let mut limiter = GossipRateLimiter::default();
+
+        assert_eq!(
+            limiter.check_channel_update(1, 0, 100, 0),
+            GossipVerdict::Process
+        );
+        // same or older update is a duplicate
+        assert_eq!(
+            limiter.check_channel_update(1, 0, 100, 1),
+            GossipVerdict::Duplicate
+        );
+        // the other direction is tracked separately
+        assert_eq!(
+            limiter.check_channel_update(1, 1, 100, 1),
+            GossipVerdict::Process
+        );
+        assert_eq!(
+            limiter.check_channel_update(1, 0, 101, 2),
+            GossipVerdict::Process
+        );
+        assert_eq!(
+            limiter.check_channel_update(1, 0, 102, 3),
+            GossipVerdict::RateLimited
+        );
+
+        // a new window allows more updates
+        assert_eq!(
+            limiter.check_channel_update(1, 0, 102, GOSSIP_RATE_LIMIT_WINDOW_SECS),
+            GossipVerdict::Process
+        );
+
+        let stats = limiter.stats();
+        assert_eq!(stats.channel_updates, 4);
+        assert_eq!(stats.channel_updates_duplicate, 1);
+        assert_eq!(stats.channel_updates_rate_limited, 1);
+    }
+
+    #[test]
+    fn test_channel_announcement_rate_limit() {
+        let mut limiter = GossipRateLimiter::default();
+
+        assert_eq!(
+            limiter.check_channel_announcement(true, 0),
+            GossipVerdict::Duplicate
+        );
+        for _ in 0..MAX_CHANNEL_ANNOUNCEMENTS_PER_WINDOW {
+            assert_eq!(
+                limiter.check_channel_announcement(false, 0),
+                GossipVerdict::Process
+            );
+        }
+        assert_eq!(
+            limiter.check_channel_announcement(false, 0),
+            GossipVerdict::RateLimited
+        );
+
+        let stats = limiter.stats();
+        assert_eq!(
+            stats.channel_announcements,
+            MAX_CHANNEL_ANNOUNCEMENTS_PER_WINDOW as u64
+        );
+        assert_eq!(stats.channel_announcements_duplicate, 1);
+        assert_eq!(stats.channel_announcements_rate_limited, 1);
+    }
+
+    #[test]
+    fn test_blocks_since_gossip_sync() {
+        // a fresh snapshot still asks about the last day
+        assert_eq!(blocks_since_gossip_sync(1_000, 1_000), 144);
+        assert_eq!(blocks_since_gossip_sync(6_000, 0), 154);
+        // never synced
+        assert_eq!(blocks_since_gossip_sync(1_700_000_000, 0), 2016);
+    }
 }
@@ -0,0 +1,403 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub const EXTERNAL_PAYMENT_PREFIX: &str = "external_payment/";
+
+/// The wallets we can import payment history from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImportSource {
+    /// The CSV accounting report from LND (faraday's `audit` export)
+    Lnd,
+    /// The JSON transaction export from BlueWallet
+    BlueWallet,
+    /// The CSV payment export from Phoenix
+    Phoenix,
+}
+
+impl fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportSource::Lnd => write!(f, "lnd"),
+            ImportSource::BlueWallet => write!(f, "bluewallet"),
+            ImportSource::Phoenix => write!(f, "phoenix"),
+        }
+    }
+}
+
+impl FromStr for ImportSource {
+    type Err = MutinyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lnd" => Ok(ImportSource::Lnd),
+            "bluewallet" => Ok(ImportSource::BlueWallet),
+            "phoenix" => Ok(ImportSource::Phoenix),
+            _ => Err(MutinyError::InvalidArgumentsError),
+        }
+    }
+}
+
+/// A payment made by another wallet, imported so the user keeps
+/// their full history after moving to Mutiny.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalPayment {
+    /// The identifier from the other wallet, a txid or payment hash when there is one
+    pub id: String,
+    pub source: ImportSource,
+    pub amount_sats: u64,
+    pub fees_sats: Option<u64>,
+    pub inbound: bool,
+    /// Whether this was an on-chain transaction rather than a lightning payment
+    pub on_chain: bool,
+    pub description: Option<String>,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+impl ExternalPayment {
+    fn key(&self) -> String {
+        format!("{EXTERNAL_PAYMENT_PREFIX}{}/{}", self.source, self.id)
+    }
+}
+
+/// Parses a payment history export from the given wallet
+pub fn parse_history(
+    source: ImportSource,
+    data: &str,
+) -> Result<Vec<ExternalPayment>, MutinyError> {
+    match source {
+        ImportSource::Lnd => parse_lnd_csv(data),
+        ImportSource::BlueWallet => parse_bluewallet_json(data),
+        ImportSource::Phoenix => parse_phoenix_csv(data),
+    }
+}
+
+fn parse_lnd_csv(data: &str) -> Result<Vec<ExternalPayment>, MutinyError> {
+    let mut payments = vec![];
+    for (i, row) in parse_csv(data)?.into_iter().enumerate() {
+        let amount_msat: i64 = column(&row, &["amount(msat)", "amount_msat", "amount"])
+            .and_then(|a| a.parse().ok())
+            .ok_or(MutinyError::InvalidArgumentsError)?;
+        // fees are reported as their own rows, skip rows that don't move funds
+        if amount_msat == 0 {
+            continue;
+        }
+        let on_chain = column(&row, &["onchain", "on_chain"]).is_some_and(|o| o == "true");
+        let id = column(&row, &["txid", "reference"])
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("row-{i}"));
+
+        payments.push(ExternalPayment {
+            id,
+            source: ImportSource::Lnd,
+            amount_sats: amount_msat.unsigned_abs() / 1_000,
+            fees_sats: None,
+            inbound: amount_msat > 0,
+            on_chain,
+            description: column(&row, &["note"])
+                .filter(|n| !n.is_empty())
+                .or_else(|| column(&row, &["type"]))
+                .map(str::to_string),
+            timestamp: column(&row, &["timestamp"])
+                .and_then(parse_timestamp)
+                .ok_or(MutinyError::InvalidArgumentsError)?,
+        });
+    }
+
+    Ok(payments)
+}
+
+fn parse_phoenix_csv(data: &str) -> Result<Vec<ExternalPayment>, MutinyError> {
+    let mut payments = vec![];
+    for (i, row) in parse_csv(data)?.into_iter().enumerate() {
+        let amount_msat: i64 = column(&row, &["amount millisatoshi", "amount_msat"])
+            .and_then(|a| a.parse().ok())
+            .ok_or(MutinyError::InvalidArgumentsError)?;
+        let fees_msat: Option<u64> = column(&row, &["fees millisatoshi", "fees_msat"])
+            .and_then(|f| f.parse::<i64>().ok())
+            .map(|f| f.unsigned_abs());
+        let context = column(&row, &["context", "type"]).unwrap_or_default();
+
+        payments.push(ExternalPayment {
+            id: column(&row, &["id", "payment hash", "payment_hash"])
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("row-{i}")),
+            source: ImportSource::Phoenix,
+            amount_sats: amount_msat.unsigned_abs() / 1_000,
+            fees_sats: fees_msat.map(|f| f / 1_000),
+            inbound: amount_msat > 0,
+            on_chain: context.to_lowercase().contains("onchain")
+                || context.to_lowercase().contains("swap"),
+            description: column(&row, &["description"])
+                .filter(|d| !d.is_empty())
+                .map(str::to_string),
+            timestamp: column(&row, &["date", "timestamp"])
+                .and_then(parse_timestamp)
+                .ok_or(MutinyError::InvalidArgumentsError)?,
+        });
+    }
+
+    Ok(payments)
+}
+
+fn parse_bluewallet_json(data: &str) -> Result<Vec<ExternalPayment>, MutinyError> {
+    let txs: Vec<Value> =
+        serde_json::from_str(data).map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+    let mut payments = vec![];
+    for (i, tx) in txs.iter().enumerate() {
+        let kind = tx["type"].as_str().unwrap_or_default();
+        // unpaid invoices were never payments
+        if kind == "user_invoice" && !tx["ispaid"].as_bool().unwrap_or(false) {
+            continue;
+        }
+
+        let on_chain = kind == "bitcoind_tx" || tx.get("txid").is_some();
+        let value = tx["value"]
+            .as_f64()
+            .or_else(|| tx["amt"].as_f64())
+            // on-chain transactions are in BTC
+            .or_else(|| tx["amount"].as_f64().map(|btc| btc * 100_000_000.0))
+            .ok_or(MutinyError::InvalidArgumentsError)?;
+        // LndHub reports payments we made as positive values
+        let inbound = match kind {
+            "paid_invoice" => false,
+            "user_invoice" => true,
+            _ => tx["category"]
+                .as_str()
+                .map_or(value > 0.0, |category| category == "receive"),
+        };
+
+        let id = ["payment_hash", "txid", "hash"]
+            .iter()
+            .find_map(|k| tx[*k].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("tx-{i}"));
+
+        payments.push(ExternalPayment {
+            id,
+            source: ImportSource::BlueWallet,
+            amount_sats: value.abs().round() as u64,
+            fees_sats: tx["fee"].as_f64().map(|f| f.abs() as u64),
+            inbound,
+            on_chain,
+            description: tx["memo"]
+                .as_str()
+                .filter(|m| !m.is_empty())
+                .map(str::to_string),
+            timestamp: tx["timestamp"]
+                .as_u64()
+                .or_else(|| tx["time"].as_u64())
+                .or_else(|| tx["received"].as_str().and_then(parse_timestamp))
+                .ok_or(MutinyError::InvalidArgumentsError)?,
+        });
+    }
+
+    Ok(payments)
+}
+
+/// Gets the first of the given columns that exists in the row
+fn column<'a>(row: &'a HashMap<String, String>, names: &[&str]) -> Option<&'a str> {
+    names
+        .iter()
+        .find_map(|name| row.get(*name))
+        .map(|v| v.as_str())
+}
+
+/// Parses unix seconds, unix milliseconds or an RFC 3339 / `YYYY-MM-DD HH:MM:SS` UTC date
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<u64>() {
+        // anything this big is in milliseconds
+        return Some(if n > 100_000_000_000 { n / 1_000 } else { n });
+    }
+    // dates without a timezone are in UTC
+    let rfc3339 = match s.contains('T') {
+        true => s.to_string(),
+        false => format!("{}Z", s.replacen(' ', "T", 1)),
+    };
+
+    DateTime::parse_from_rfc3339(&rfc3339)
+        .ok()
+        .and_then(|date| u64::try_from(date.timestamp()).ok())
+}
+
+/// Parses a CSV file with a header row into rows keyed by the lowercased column names
+fn parse_csv(data: &str) -> Result<Vec<HashMap<String, String>>, MutinyError> {
+    let mut lines = data.lines().filter(|l| !l.trim().is_empty());
+    let headers: Vec<String> = match lines.next() {
+        Some(header) => parse_csv_line(header)
+            .into_iter()
+            .map(|h| h.trim().to_lowercase())
+            .collect(),
+        None => return Ok(vec![]),
+    };
+
+    lines
+        .map(|line| {
+            let fields = parse_csv_line(line);
+            if fields.len() != headers.len() {
+                return Err(MutinyError::InvalidArgumentsError);
+            }
+            Ok(headers.iter().cloned().zip(fields).collect())
+        })
+        .collect()
+}
+
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+pub trait ExternalPaymentStorage {
+    /// Saves the imported payments, skipping any that were imported before.
+    /// Returns how many were new.
+    fn import_external_payments(
+        &self,
+        payments: Vec<ExternalPayment>,
+    ) -> Result<usize, MutinyError>;
+    fn list_external_payments(&self) -> Result<Vec<ExternalPayment>, MutinyError>;
+}
+
+impl<S: MutinyStorage> ExternalPaymentStorage for S {
+    fn import_external_payments(
+        &self,
+        payments: Vec<ExternalPayment>,
+    ) -> Result<usize, MutinyError> {
+        let mut imported = 0;
+        for payment in payments {
+            let key = payment.key();
+            if self.get_data::<ExternalPayment>(&key)?.is_none() {
+                self.set_data(key, payment, None)?;
+                imported += 1;
+            }
+        }
+
+        Ok(imported)
+    }
+
+    fn list_external_payments(&self) -> Result<Vec<ExternalPayment>, MutinyError> {
+        let payments: HashMap<String, ExternalPayment> =
+            self.scan(EXTERNAL_PAYMENT_PREFIX, None)?;
+        Ok(payments.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const LND_CSV: &str = "Timestamp,OnChain,Type,Amount(Msat),Amount(USD),TxID,Reference,Note
+1690000000,false,RECEIPT,21000000,5.10,,abcd,\"coffee, thanks\"
+1690000100,true,PAYMENT,-50000000,12.00,f00d,f00d:0,
+1690000200,false,FEE,0,0,,abcd,";
+
+    const PHOENIX_CSV: &str = "Date,Amount Millisatoshi,Fees Millisatoshi,Context,Description
+2023-07-22T04:26:40Z,-10000000,4000,Outgoing LN payment,pizza
+2023-07-22 05:00:00,2500000,0,Incoming LN payment,";
+
+    const BLUEWALLET_JSON: &str = r#"[
+        {"type":"paid_invoice","value":1000,"fee":2,"timestamp":1690000000,"payment_hash":"aa"},
+        {"type":"user_invoice","amt":500,"ispaid":true,"timestamp":1690000100,"payment_hash":"bb"},
+        {"type":"user_invoice","amt":700,"ispaid":false,"timestamp":1690000200,"payment_hash":"cc"}
+    ]"#;
+
+    #[test]
+    fn test_parse_lnd_csv() {
+        log!("test parse lnd csv");
+
+        let payments = parse_history(ImportSource::Lnd, LND_CSV).unwrap();
+        assert_eq!(payments.len(), 2);
+
+        assert_eq!(payments[0].id, "abcd");
+        assert_eq!(payments[0].amount_sats, 21_000);
+        assert!(payments[0].inbound);
+        assert!(!payments[0].on_chain);
+        assert_eq!(payments[0].description.as_deref(), Some("coffee, thanks"));
+
+        assert_eq!(payments[1].id, "f00d");
+        assert_eq!(payments[1].amount_sats, 50_000);
+        assert!(!payments[1].inbound);
+        assert!(payments[1].on_chain);
+    }
+
+    #[test]
+    fn test_parse_phoenix_csv() {
+        log!("test parse phoenix csv");
+
+        let payments = parse_history(ImportSource::Phoenix, PHOENIX_CSV).unwrap();
+        assert_eq!(payments.len(), 2);
+
+        assert_eq!(payments[0].amount_sats, 10_000);
+        assert_eq!(payments[0].fees_sats, Some(4));
+        assert!(!payments[0].inbound);
+        assert_eq!(payments[0].timestamp, 1690000000);
+        assert_eq!(payments[0].description.as_deref(), Some("pizza"));
+
+        assert_eq!(payments[1].amount_sats, 2_500);
+        assert!(payments[1].inbound);
+        assert_eq!(payments[1].description, None);
+    }
+
+    #[test]
+    fn test_parse_bluewallet_json() {
+        log!("test parse bluewallet json");
+
+        let payments = parse_history(ImportSource::BlueWallet, BLUEWALLET_JSON).unwrap();
+        // the unpaid invoice is skipped
+        assert_eq!(payments.len(), 2);
+
+        assert_eq!(payments[0].id, "aa");
+        assert_eq!(payments[0].amount_sats, 1_000);
+        assert_eq!(payments[0].fees_sats, Some(2));
+        assert!(!payments[0].inbound);
+
+        assert_eq!(payments[1].id, "bb");
+        assert_eq!(payments[1].amount_sats, 500);
+        assert!(payments[1].inbound);
+    }
+
+    #[test]
+    fn test_import_external_payments() {
+        log!("test import external payments");
+
+        let storage = MemoryStorage::default();
+        let payments = parse_history(ImportSource::Lnd, LND_CSV).unwrap();
+        assert_eq!(
+            storage.import_external_payments(payments.clone()).unwrap(),
+            2
+        );
+        // importing the same file again doesn't duplicate anything
+        assert_eq!(storage.import_external_payments(payments).unwrap(), 0);
+        assert_eq!(storage.list_external_payments().unwrap().len(), 2);
+    }
+}
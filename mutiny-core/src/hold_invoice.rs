@@ -0,0 +1,240 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::PaymentPreimage;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const HOLD_INVOICE_PREFIX: &str = "hold_invoice/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldInvoiceStatus {
+    /// Waiting for the payer's HTLCs
+    Open,
+    /// The HTLCs arrived and are held until the invoice is settled or cancelled
+    Accepted {
+        amount_msat: u64,
+    },
+    /// Hex encoded preimage the HTLCs were claimed with
+    Settled {
+        preimage: String,
+    },
+    Cancelled,
+    /// The held HTLCs were failed back before the invoice was settled, LDK does
+    /// this when they get close to expiring. The payer can pay it again.
+    Expired,
+}
+
+/// What to do with HTLCs that pay a hold invoice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HoldAction {
+    Hold,
+    Claim(PaymentPreimage),
+    Fail,
+}
+
+/// An invoice we don't know the preimage for, its HTLCs are held until
+/// the preimage is given to settle it or it is cancelled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HoldInvoice {
+    pub payment_hash: sha256::Hash,
+    pub bolt11: Bolt11Invoice,
+    /// The node the invoice was created on, which holds the HTLCs
+    pub node_pubkey: PublicKey,
+    pub created_at: u64,
+    pub status: HoldInvoiceStatus,
+}
+
+impl HoldInvoice {
+    fn key(&self) -> String {
+        hold_invoice_key(&self.payment_hash)
+    }
+
+    /// Called when the HTLCs paying the invoice are claimable. LDK gives us them
+    /// again after a restart if we hadn't claimed or failed them yet.
+    pub(crate) fn on_claimable(&mut self, amount_msat: u64) -> HoldAction {
+        match &self.status {
+            HoldInvoiceStatus::Open
+            | HoldInvoiceStatus::Accepted { .. }
+            | HoldInvoiceStatus::Expired => {
+                self.status = HoldInvoiceStatus::Accepted { amount_msat };
+                HoldAction::Hold
+            }
+            HoldInvoiceStatus::Settled { preimage } => match <[u8; 32]>::from_hex(preimage) {
+                Ok(preimage) => HoldAction::Claim(PaymentPreimage(preimage)),
+                Err(_) => HoldAction::Fail,
+            },
+            HoldInvoiceStatus::Cancelled => HoldAction::Fail,
+        }
+    }
+
+    /// Called when the HTLCs paying the invoice were failed back. Held HTLCs that
+    /// weren't failed by us expired, returns true if the status changed.
+    pub(crate) fn on_failed(&mut self) -> bool {
+        if !matches!(self.status, HoldInvoiceStatus::Accepted { .. }) {
+            return false;
+        }
+        self.status = HoldInvoiceStatus::Expired;
+
+        true
+    }
+
+    /// Marks the invoice as settled, only accepted invoices can be settled
+    pub(crate) fn settle(&mut self, preimage: &[u8; 32]) -> Result<(), MutinyError> {
+        if !matches!(self.status, HoldInvoiceStatus::Accepted { .. }) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        self.status = HoldInvoiceStatus::Settled {
+            preimage: preimage.to_hex(),
+        };
+
+        Ok(())
+    }
+
+    /// Marks the invoice as cancelled, settled invoices can't be cancelled
+    pub(crate) fn cancel(&mut self) -> Result<(), MutinyError> {
+        if matches!(self.status, HoldInvoiceStatus::Settled { .. }) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        self.status = HoldInvoiceStatus::Cancelled;
+
+        Ok(())
+    }
+}
+
+fn hold_invoice_key(payment_hash: &sha256::Hash) -> String {
+    format!("{HOLD_INVOICE_PREFIX}{payment_hash}")
+}
+
+pub trait HoldInvoiceStorage {
+    fn get_hold_invoice(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<HoldInvoice>, MutinyError>;
+    fn persist_hold_invoice(&self, invoice: HoldInvoice) -> Result<(), MutinyError>;
+    fn list_hold_invoices(&self) -> Result<Vec<HoldInvoice>, MutinyError>;
+}
+
+impl<S: MutinyStorage> HoldInvoiceStorage for S {
+    fn get_hold_invoice(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<HoldInvoice>, MutinyError> {
+        self.get_data(hold_invoice_key(payment_hash))
+    }
+
+    fn persist_hold_invoice(&self, invoice: HoldInvoice) -> Result<(), MutinyError> {
+        self.set_data(invoice.key(), invoice, None)
+    }
+
+    fn list_hold_invoices(&self) -> Result<Vec<HoldInvoice>, MutinyError> {
+        let map: HashMap<String, HoldInvoice> = self.scan(HOLD_INVOICE_PREFIX, None)?;
+        let mut invoices: Vec<HoldInvoice> = map.into_values().collect();
+        // newest first
+        invoices.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(invoices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const INVOICE: &str = "lnbc923720n1pj9nrefpp5pczykgk37af5388n8dzynljpkzs7sje4melqgazlwv9y3apay8jqhp5rd8saxz3juve3eejq7z5fjttxmpaq88d7l92xv34n4h3mq6kwq2qcqzzsxqzfvsp5z0jwpehkuz9f2kv96h62p8x30nku76aj8yddpcust7g8ad0tr52q9qyyssqfy622q25helv8cj8hyxqltws4rdwz0xx2hw0uh575mn7a76cp3q4jcptmtjkjs4a34dqqxn8uy70d0qlxqleezv4zp84uk30pp5q3nqq4c9gkz";
+
+    fn dummy_hold_invoice() -> HoldInvoice {
+        let bolt11 = Bolt11Invoice::from_str(INVOICE).unwrap();
+        HoldInvoice {
+            payment_hash: *bolt11.payment_hash(),
+            node_pubkey: bolt11.recover_payee_pub_key(),
+            bolt11,
+            created_at: 0,
+            status: HoldInvoiceStatus::Open,
+        }
+    }
+
+    #[test]
+    fn test_hold_invoice_lifecycle() {
+        log!("test hold invoice lifecycle");
+
+        let preimage = [1; 32];
+        let mut invoice = dummy_hold_invoice();
+        // can't settle before the HTLCs arrive
+        assert!(invoice.settle(&preimage).is_err());
+
+        assert_eq!(invoice.on_claimable(1_000), HoldAction::Hold);
+        assert_eq!(
+            invoice.status,
+            HoldInvoiceStatus::Accepted { amount_msat: 1_000 }
+        );
+
+        invoice.settle(&preimage).unwrap();
+        assert!(invoice.cancel().is_err());
+        // claimable again after a restart, claim it with the preimage
+        assert_eq!(
+            invoice.on_claimable(1_000),
+            HoldAction::Claim(PaymentPreimage(preimage))
+        );
+
+        let mut invoice = dummy_hold_invoice();
+        invoice.on_claimable(1_000);
+        invoice.cancel().unwrap();
+        assert!(!invoice.on_failed());
+        assert_eq!(invoice.status, HoldInvoiceStatus::Cancelled);
+        assert_eq!(invoice.on_claimable(1_000), HoldAction::Fail);
+        assert!(invoice.settle(&preimage).is_err());
+    }
+
+    #[test]
+    fn test_hold_invoice_expired() {
+        log!("test hold invoice expired");
+
+        let preimage = [1; 32];
+        let mut invoice = dummy_hold_invoice();
+        // nothing is held yet
+        assert!(!invoice.on_failed());
+        assert_eq!(invoice.status, HoldInvoiceStatus::Open);
+
+        invoice.on_claimable(1_000);
+        assert!(invoice.on_failed());
+        assert_eq!(invoice.status, HoldInvoiceStatus::Expired);
+        // the HTLCs are gone, there is nothing to settle
+        assert!(invoice.settle(&preimage).is_err());
+
+        // the payer pays again
+        assert_eq!(invoice.on_claimable(1_000), HoldAction::Hold);
+        invoice.settle(&preimage).unwrap();
+        assert!(!invoice.on_failed());
+    }
+
+    #[test]
+    fn test_hold_invoice_storage() {
+        log!("test hold invoice storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_hold_invoices().unwrap().is_empty());
+
+        let invoice = dummy_hold_invoice();
+        assert!(storage
+            .get_hold_invoice(&invoice.payment_hash)
+            .unwrap()
+            .is_none());
+        storage.persist_hold_invoice(invoice.clone()).unwrap();
+        assert_eq!(
+            storage.get_hold_invoice(&invoice.payment_hash).unwrap(),
+            Some(invoice.clone())
+        );
+        assert_eq!(storage.list_hold_invoices().unwrap(), vec![invoice]);
+    }
+}
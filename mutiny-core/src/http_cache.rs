@@ -0,0 +1,176 @@
+use crate::error::MutinyError;
+use crate::http_client::{HttpRequest, MutinyHttpClient};
+use crate::storage::MutinyStorage;
+use crate::utils;
+use bitcoin::hashes::{sha256, Hash};
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+pub const HTTP_CACHE_PREFIX: &str = "http_cache/";
+
+/// A saved response to a GET request, along with the validators
+/// the server gave us to check if it has changed since.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+    /// Epoch time in seconds of when the server last confirmed the body
+    pub fetched_at: u64,
+}
+
+impl CachedResponse {
+    fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+fn http_cache_key(url: &str) -> String {
+    // urls can be long and have query strings, key by their hash instead
+    let hash = sha256::Hash::hash(url.as_bytes());
+    format!("{HTTP_CACHE_PREFIX}{hash}")
+}
+
+pub trait HttpCacheStorage {
+    fn get_cached_response(&self, url: &str) -> Result<Option<CachedResponse>, MutinyError>;
+    fn persist_cached_response(&self, response: CachedResponse) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> HttpCacheStorage for S {
+    fn get_cached_response(&self, url: &str) -> Result<Option<CachedResponse>, MutinyError> {
+        let cached: Option<CachedResponse> = self.get_data(http_cache_key(url))?;
+        // guard against a hash collision handing back another url's body
+        Ok(cached.filter(|c| c.url == url))
+    }
+
+    fn persist_cached_response(&self, response: CachedResponse) -> Result<(), MutinyError> {
+        self.set_data(http_cache_key(&response.url), response, None)
+    }
+}
+
+/// Makes a GET request, sending the ETag and Last-Modified of the saved response
+/// so the server can answer with a 304 instead of the full body if nothing changed.
+/// Returns the body, from the cache if it is still current.
+pub(crate) async fn cached_get(
+    client: &dyn MutinyHttpClient,
+    storage: &impl MutinyStorage,
+    url: &str,
+) -> anyhow::Result<String> {
+    let cached = storage.get_cached_response(url).unwrap_or_default();
+
+    let mut request = HttpRequest::get(url);
+    if let Some(cached) = cached.as_ref() {
+        if let Some(etag) = cached.etag.as_ref() {
+            request = request.with_header(IF_NONE_MATCH.as_str(), etag);
+        }
+        if let Some(last_modified) = cached.last_modified.as_ref() {
+            request = request.with_header(IF_MODIFIED_SINCE.as_str(), last_modified);
+        }
+    }
+
+    let response = client.send(request).await?;
+    if response.status == StatusCode::NOT_MODIFIED.as_u16() {
+        if let Some(mut cached) = cached {
+            cached.fetched_at = utils::now().as_secs();
+            let body = cached.body.clone();
+            storage.persist_cached_response(cached)?;
+            return Ok(body);
+        }
+    }
+
+    if !response.is_success() {
+        anyhow::bail!("GET {url} failed with status {}", response.status);
+    }
+    let header = |name: HeaderName| response.header(name.as_str()).map(|v| v.to_string());
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let body = response.text()?;
+
+    let fresh = CachedResponse {
+        url: url.to_string(),
+        etag,
+        last_modified,
+        body: body.clone(),
+        fetched_at: utils::now().as_secs(),
+    };
+    // without validators we could never use the saved body
+    if fresh.has_validators() {
+        storage.persist_cached_response(fresh)?;
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::{HttpResponse, MockMutinyHttpClient};
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_http_cache_storage() {
+        log!("test http cache storage");
+
+        let storage = MemoryStorage::default();
+        let url = "https://mempool.space/api/v1/fees/recommended";
+        assert_eq!(storage.get_cached_response(url).unwrap(), None);
+
+        let response = CachedResponse {
+            url: url.to_string(),
+            etag: Some("W/\"abc\"".to_string()),
+            last_modified: None,
+            body: "{\"fastestFee\":10}".to_string(),
+            fetched_at: 100,
+        };
+        assert!(response.has_validators());
+        storage.persist_cached_response(response.clone()).unwrap();
+
+        assert_eq!(storage.get_cached_response(url).unwrap(), Some(response));
+        assert_eq!(
+            storage
+                .get_cached_response("https://mempool.space/api/fee-estimates")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn test_cached_get_not_modified() {
+        log!("test cached get not modified");
+
+        let storage = MemoryStorage::default();
+        let url = "https://mempool.space/api/v1/fees/recommended";
+        storage
+            .persist_cached_response(CachedResponse {
+                url: url.to_string(),
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+                body: "{\"fastestFee\":10}".to_string(),
+                fetched_at: 100,
+            })
+            .unwrap();
+
+        let mut client = MockMutinyHttpClient::new();
+        client
+            .expect_send()
+            .withf(|req| req.headers == vec![("if-none-match".to_string(), "\"abc\"".to_string())])
+            .times(1)
+            .returning(|_| {
+                Ok(HttpResponse {
+                    status: 304,
+                    ..Default::default()
+                })
+            });
+
+        let body = cached_get(&client, &storage, url).await.unwrap();
+        assert_eq!(body, "{\"fastestFee\":10}");
+        let cached = storage.get_cached_response(url).unwrap().unwrap();
+        assert!(cached.fetched_at > 100);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::MutinyError;
+use async_trait::async_trait;
+use reqwest::{Client, Method};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(test)]
+use mockall::automock;
+
+/// An outbound HTTP request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl HttpRequest {
+    pub fn get(url: impl Into<String>) -> Self {
+        Self {
+            method: Method::GET,
+            url: url.into(),
+            headers: vec![],
+            body: None,
+        }
+    }
+
+    /// A POST request with the given value as its json body
+    pub fn post_json<T: Serialize>(url: impl Into<String>, body: &T) -> Result<Self, MutinyError> {
+        let body = serde_json::to_vec(body)?;
+        Ok(Self {
+            method: Method::POST,
+            url: url.into(),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: Some(body),
+        })
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// The response to an [HttpRequest]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns the value of a header, header names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn text(&self) -> Result<String, MutinyError> {
+        String::from_utf8(self.body.clone()).map_err(|e| MutinyError::Other(e.into()))
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, MutinyError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+/// Makes the outbound HTTP requests for rapid gossip sync, the LSP, fee estimates,
+/// the bitcoin price, NIP-05 lookups and telemetry. Embedders can provide their own
+/// to add headers, go through Tor or a proxy, or retry failed requests.
+///
+/// Esplora chain sync and LNURL requests are made by their own libraries' clients.
+#[cfg_attr(test, automock)]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+pub trait MutinyHttpClient: Send + Sync {
+    /// Sends the request and returns the response, whatever its status is.
+    /// Only failing to get a response at all is an error.
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MutinyError>;
+}
+
+/// The default [MutinyHttpClient], backed by reqwest
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestHttpClient {
+    client: Client,
+}
+
+impl ReqwestHttpClient {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl MutinyHttpClient for ReqwestHttpClient {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MutinyError> {
+        let mut builder = self.client.request(request.method, &request.url);
+        for (name, value) in request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| MutinyError::Other(e.into()))?;
+
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| MutinyError::Other(e.into()))?
+            .to_vec();
+
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_http_response() {
+        log!("test http response");
+
+        let response = HttpResponse {
+            status: 200,
+            headers: vec![("ETag".to_string(), "\"abc\"".to_string())],
+            body: b"{\"fee\":1}".to_vec(),
+        };
+        assert!(response.is_success());
+        assert_eq!(response.header("etag"), Some("\"abc\""));
+        assert_eq!(response.header("last-modified"), None);
+
+        let json: serde_json::Value = response.json().unwrap();
+        assert_eq!(json["fee"], 1);
+
+        let request = HttpRequest::post_json("https://example.com", &json).unwrap();
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.body, Some(b"{\"fee\":1}".to_vec()));
+    }
+}
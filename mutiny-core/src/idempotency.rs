@@ -0,0 +1,240 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use bitcoin::hashes::sha256;
+use bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+
+pub const IDEMPOTENCY_KEY_PREFIX: &str = "idempotency/";
+
+/// How long a call can hold a key without a result before we assume it was
+/// interrupted, such as by a page reload, and let the key be used again.
+/// Results are saved before a payment or transaction is sent so a stale
+/// pending key never hides something that went out.
+pub(crate) const PENDING_TIMEOUT_SECS: u64 = 5 * 60;
+
+/// The API call an idempotency key was used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdempotentOperation {
+    PayInvoice,
+    Keysend,
+    SendToAddress,
+}
+
+/// What the first call with an idempotency key did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IdempotentResult {
+    /// The call has started but we don't know what it will produce yet
+    Pending,
+    /// A lightning payment with the given payment hash
+    Payment(sha256::Hash),
+    /// An on-chain transaction
+    Transaction(Txid),
+    /// An on-chain transaction that was signed but may not have been broadcast,
+    /// it needs to be looked up before the key can be used again
+    Broadcasting(Txid),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub operation: IdempotentOperation,
+    pub result: IdempotentResult,
+    pub created_at: u64,
+}
+
+pub trait IdempotencyStorage {
+    fn get_idempotency_record(&self, key: &str) -> Result<Option<IdempotencyRecord>, MutinyError>;
+    fn set_idempotency_result(
+        &self,
+        key: &str,
+        operation: IdempotentOperation,
+        result: IdempotentResult,
+    ) -> Result<(), MutinyError>;
+    fn delete_idempotency_record(&self, key: &str) -> Result<(), MutinyError>;
+
+    /// Claims an idempotency key for a new call.
+    ///
+    /// Returns `None` if the key is unused, after storing `initial` for it.
+    /// Otherwise returns what the first call with the key did, the caller
+    /// should return that instead of doing the operation again.
+    ///
+    /// An interrupted [`IdempotentResult::Broadcasting`] call is returned as is,
+    /// the caller has to check whether the transaction went out before
+    /// claiming the key again with [`IdempotencyStorage::set_idempotency_result`].
+    fn claim_idempotency_key(
+        &self,
+        key: &str,
+        operation: IdempotentOperation,
+        initial: IdempotentResult,
+    ) -> Result<Option<IdempotentResult>, MutinyError> {
+        match self.get_idempotency_record(key)? {
+            None => {
+                self.set_idempotency_result(key, operation, initial)?;
+                Ok(None)
+            }
+            // the same key can't be reused for a different kind of call
+            Some(record) if record.operation != operation => {
+                Err(MutinyError::InvalidArgumentsError)
+            }
+            Some(record) if record.result == IdempotentResult::Pending => {
+                if utils::now().as_secs() < record.created_at + PENDING_TIMEOUT_SECS {
+                    return Err(MutinyError::IdempotencyKeyInUse);
+                }
+                self.set_idempotency_result(key, operation, initial)?;
+                Ok(None)
+            }
+            Some(record) if matches!(record.result, IdempotentResult::Broadcasting(_)) => {
+                if utils::now().as_secs() < record.created_at + PENDING_TIMEOUT_SECS {
+                    return Err(MutinyError::IdempotencyKeyInUse);
+                }
+                Ok(Some(record.result))
+            }
+            Some(record) => Ok(Some(record.result)),
+        }
+    }
+}
+
+fn idempotency_key(key: &str) -> String {
+    format!("{IDEMPOTENCY_KEY_PREFIX}{key}")
+}
+
+impl<S: MutinyStorage> IdempotencyStorage for S {
+    fn get_idempotency_record(&self, key: &str) -> Result<Option<IdempotencyRecord>, MutinyError> {
+        self.get_data(idempotency_key(key))
+    }
+
+    fn set_idempotency_result(
+        &self,
+        key: &str,
+        operation: IdempotentOperation,
+        result: IdempotentResult,
+    ) -> Result<(), MutinyError> {
+        let record = IdempotencyRecord {
+            operation,
+            result,
+            created_at: utils::now().as_secs(),
+        };
+        self.set_data(idempotency_key(key), record, None)
+    }
+
+    fn delete_idempotency_record(&self, key: &str) -> Result<(), MutinyError> {
+        self.delete(&[idempotency_key(key)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_claim_idempotency_key() {
+        log!("test claim idempotency key");
+
+        let storage = MemoryStorage::default();
+        let key = "key";
+
+        // first call gets to do the operation
+        let claimed = storage
+            .claim_idempotency_key(key, IdempotentOperation::Keysend, IdempotentResult::Pending)
+            .unwrap();
+        assert_eq!(claimed, None);
+
+        // a retry while the first call is running is rejected
+        let err = storage
+            .claim_idempotency_key(key, IdempotentOperation::Keysend, IdempotentResult::Pending)
+            .unwrap_err();
+        assert!(matches!(err, MutinyError::IdempotencyKeyInUse));
+
+        // once finished, retries get the original result
+        let result = IdempotentResult::Payment(sha256::Hash::all_zeros());
+        storage
+            .set_idempotency_result(key, IdempotentOperation::Keysend, result)
+            .unwrap();
+        let claimed = storage
+            .claim_idempotency_key(key, IdempotentOperation::Keysend, IdempotentResult::Pending)
+            .unwrap();
+        assert_eq!(claimed, Some(result));
+
+        // can't reuse the key for something else
+        let err = storage
+            .claim_idempotency_key(
+                key,
+                IdempotentOperation::SendToAddress,
+                IdempotentResult::Pending,
+            )
+            .unwrap_err();
+        assert!(matches!(err, MutinyError::InvalidArgumentsError));
+
+        // a failed call frees up the key
+        storage.delete_idempotency_record(key).unwrap();
+        assert!(storage.get_idempotency_record(key).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reclaim_interrupted_idempotency_key() {
+        log!("test reclaim interrupted idempotency key");
+
+        let storage = MemoryStorage::default();
+        let key = "key";
+
+        // a call that never finished, e.g. the page was reloaded
+        let record = IdempotencyRecord {
+            operation: IdempotentOperation::Keysend,
+            result: IdempotentResult::Pending,
+            created_at: utils::now().as_secs() - PENDING_TIMEOUT_SECS - 1,
+        };
+        storage
+            .set_data(idempotency_key(key), record, None)
+            .unwrap();
+
+        let claimed = storage
+            .claim_idempotency_key(key, IdempotentOperation::Keysend, IdempotentResult::Pending)
+            .unwrap();
+        assert_eq!(claimed, None);
+
+        // the new call holds the key now
+        let err = storage
+            .claim_idempotency_key(key, IdempotentOperation::Keysend, IdempotentResult::Pending)
+            .unwrap_err();
+        assert!(matches!(err, MutinyError::IdempotencyKeyInUse));
+    }
+
+    #[test]
+    fn test_interrupted_broadcast_is_returned() {
+        log!("test interrupted broadcast is returned");
+
+        let storage = MemoryStorage::default();
+        let key = "key";
+        let operation = IdempotentOperation::SendToAddress;
+        let result = IdempotentResult::Broadcasting(Txid::all_zeros());
+
+        // a retry while the broadcast is running is rejected
+        storage
+            .set_idempotency_result(key, operation, result)
+            .unwrap();
+        let err = storage
+            .claim_idempotency_key(key, operation, IdempotentResult::Pending)
+            .unwrap_err();
+        assert!(matches!(err, MutinyError::IdempotencyKeyInUse));
+
+        // once it times out the caller gets the txid to look up
+        let record = IdempotencyRecord {
+            operation,
+            result,
+            created_at: utils::now().as_secs() - PENDING_TIMEOUT_SECS - 1,
+        };
+        storage
+            .set_data(idempotency_key(key), record, None)
+            .unwrap();
+        let claimed = storage
+            .claim_idempotency_key(key, operation, IdempotentResult::Pending)
+            .unwrap();
+        assert_eq!(claimed, Some(result));
+    }
+}
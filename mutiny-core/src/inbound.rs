@@ -0,0 +1,538 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use lightning::util::config::ChannelHandshakeConfig;
+use serde::{Deserialize, Serialize};
+
+pub const INBOUND_POLICY_KEY: &str = "inbound_policy";
+pub const INBOUND_CAPACITY_ALERTS_KEY: &str = "inbound_capacity_alerts";
+pub const INBOUND_CAPACITY_WARNINGS_KEY: &str = "inbound_capacity_warnings";
+pub const INBOUND_HTLC_LIMITS_KEY: &str = "inbound_htlc_limits";
+pub const INBOUND_HTLC_COUNTERS_KEY: &str = "inbound_htlc_counters";
+
+/// Only keep the most recent warnings
+const MAX_WARNINGS: usize = 10;
+
+/// LDK's lowest dust limit, HTLCs below it don't get an output on the
+/// commitment transaction so they can't be claimed on-chain
+const DUST_LIMIT_MSAT: u64 = 354_000;
+
+/// The most HTLCs the protocol allows in one direction of a channel
+const MAX_ACCEPTED_HTLCS: u16 = 483;
+
+/// Controls which channels other nodes can open to us.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundPolicy {
+    /// Accept channels other nodes open to us
+    pub accept_channels: bool,
+    /// The smallest channel we will accept, in sats
+    pub min_channel_size_sats: Option<u64>,
+    /// Only accept channels from these peers, anyone if not set
+    pub allowed_peers: Option<Vec<PublicKey>>,
+}
+
+impl Default for InboundPolicy {
+    fn default() -> Self {
+        Self {
+            accept_channels: true,
+            min_channel_size_sats: None,
+            allowed_peers: None,
+        }
+    }
+}
+
+impl InboundPolicy {
+    /// Returns true if a channel of the given size from the given peer should be accepted.
+    /// Channels from our LSP are always accepted and do not go through this check.
+    pub(crate) fn should_accept_channel(
+        &self,
+        counterparty_node_id: &PublicKey,
+        funding_satoshis: u64,
+    ) -> bool {
+        if !self.accept_channels {
+            return false;
+        }
+        if self
+            .min_channel_size_sats
+            .is_some_and(|min| funding_satoshis < min)
+        {
+            return false;
+        }
+
+        self.allowed_peers
+            .as_ref()
+            .map_or(true, |peers| peers.contains(counterparty_node_id))
+    }
+}
+
+/// Limits on the payments we receive, so spam and HTLC jamming can't tie up
+/// the few channels a small node has.
+///
+/// All limits are checked when a payment to us arrives, and payments over them
+/// are failed back. `max_accepted_htlcs` is also put in the handshake of new
+/// channels, so those peers can't send us more HTLCs than that in the first place.
+/// Existing channels do not get that protection, channels we open get it right
+/// away and channels opened to us only after the node restarts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundHtlcLimits {
+    /// Reject payments smaller than this, in msats
+    pub min_payment_msat: Option<u64>,
+    /// Reject payments from a peer that already has this many payments
+    /// to us that we accepted and haven't claimed or failed
+    #[serde(alias = "max_pending_htlcs_per_peer")]
+    pub max_accepted_htlcs: Option<u16>,
+    /// Reject payments below the dust limit (354 sats), they cost nothing to send.
+    /// This is checked against the whole payment so multi-part payments
+    /// can still have smaller parts, but any payment under 354 sats,
+    /// such as a small zap, can't be received while this is set.
+    pub reject_dust_htlcs: bool,
+}
+
+impl InboundHtlcLimits {
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        if self
+            .max_accepted_htlcs
+            .is_some_and(|max| max == 0 || max > MAX_ACCEPTED_HTLCS)
+        {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the HTLC limit on a new channel, our peer can't send HTLCs over it
+    pub(crate) fn apply(&self, config: &mut ChannelHandshakeConfig) {
+        if let Some(max) = self.max_accepted_htlcs {
+            config.our_max_accepted_htlcs = max.min(MAX_ACCEPTED_HTLCS);
+        }
+    }
+
+    /// Returns why a payment should be rejected, if it should be.
+    /// `amount_msat` is the total of all the payment's HTLCs and `pending_htlcs`
+    /// is how many unresolved payments the sending peer has to us, including this one.
+    pub(crate) fn check(&self, amount_msat: u64, pending_htlcs: usize) -> Option<InboundRejection> {
+        if self.reject_dust_htlcs && amount_msat < DUST_LIMIT_MSAT {
+            return Some(InboundRejection::Dust);
+        }
+        if self.min_payment_msat.is_some_and(|min| amount_msat < min) {
+            return Some(InboundRejection::BelowMinimum);
+        }
+        if self
+            .max_accepted_htlcs
+            .is_some_and(|max| pending_htlcs > max as usize)
+        {
+            return Some(InboundRejection::TooManyPending);
+        }
+
+        None
+    }
+}
+
+/// Why a payment to us was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InboundRejection {
+    BelowMinimum,
+    Dust,
+    TooManyPending,
+}
+
+/// How many payments to us were accepted, and rejected by the [InboundHtlcLimits]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InboundHtlcCounters {
+    pub accepted: u64,
+    pub rejected_below_minimum: u64,
+    pub rejected_dust: u64,
+    pub rejected_too_many_pending: u64,
+}
+
+impl InboundHtlcCounters {
+    pub(crate) fn record(&mut self, rejection: Option<InboundRejection>) {
+        let counter = match rejection {
+            None => &mut self.accepted,
+            Some(InboundRejection::BelowMinimum) => &mut self.rejected_below_minimum,
+            Some(InboundRejection::Dust) => &mut self.rejected_dust,
+            Some(InboundRejection::TooManyPending) => &mut self.rejected_too_many_pending,
+        };
+        *counter += 1;
+    }
+}
+
+/// A way to get more inbound capacity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InboundSuggestion {
+    /// Have the LSP open a channel of this size by receiving through it
+    LspChannel { amount_sats: u64, fee_sats: u64 },
+    /// Move lightning funds on-chain, which frees up the same amount of inbound capacity
+    SwapOut { amount_sats: u64, fee_sats: u64 },
+}
+
+/// How much we can receive over lightning and what to do if it isn't enough
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundCapacityReport {
+    pub inbound_sats: u64,
+    pub outbound_sats: u64,
+    /// The inbound capacity we want to keep, if set
+    pub threshold_sats: Option<u64>,
+    /// How much inbound capacity is missing to reach the threshold
+    pub shortfall_sats: u64,
+    pub suggestions: Vec<InboundSuggestion>,
+}
+
+impl InboundCapacityReport {
+    pub(crate) fn new(inbound_sats: u64, outbound_sats: u64, threshold_sats: Option<u64>) -> Self {
+        let shortfall_sats = threshold_sats.map_or(0, |t| t.saturating_sub(inbound_sats));
+        Self {
+            inbound_sats,
+            outbound_sats,
+            threshold_sats,
+            shortfall_sats,
+            suggestions: vec![],
+        }
+    }
+
+    /// How much we could swap out to cover the shortfall, limited by our outbound capacity
+    pub(crate) fn swap_out_amount(&self) -> u64 {
+        self.shortfall_sats.min(self.outbound_sats)
+    }
+}
+
+/// Emitted when inbound capacity drops below the threshold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundCapacityWarning {
+    pub inbound_sats: u64,
+    pub threshold_sats: u64,
+    pub suggestions: Vec<InboundSuggestion>,
+    pub created_at: u64,
+}
+
+/// When to warn about low inbound capacity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundCapacityAlerts {
+    /// Warn when inbound capacity drops below this, never if not set
+    pub threshold_sats: Option<u64>,
+    /// Whether we were below the threshold at the last check
+    #[serde(default)]
+    below_threshold: bool,
+}
+
+impl InboundCapacityAlerts {
+    pub(crate) fn new(threshold_sats: Option<u64>) -> Self {
+        Self {
+            threshold_sats,
+            below_threshold: false,
+        }
+    }
+
+    /// Returns a warning if inbound capacity has dropped below the threshold.
+    /// We only warn again once it has gone back above the threshold.
+    pub(crate) fn next_warning(
+        &mut self,
+        report: &InboundCapacityReport,
+        now: u64,
+    ) -> Option<InboundCapacityWarning> {
+        let threshold_sats = self.threshold_sats?;
+        let below_threshold = report.inbound_sats < threshold_sats;
+        let was_below = self.below_threshold;
+        self.below_threshold = below_threshold;
+
+        if !below_threshold || was_below {
+            return None;
+        }
+
+        Some(InboundCapacityWarning {
+            inbound_sats: report.inbound_sats,
+            threshold_sats,
+            suggestions: report.suggestions.clone(),
+            created_at: now,
+        })
+    }
+}
+
+pub trait InboundStorage {
+    fn get_inbound_policy(&self) -> Result<InboundPolicy, MutinyError>;
+    fn set_inbound_policy(&self, policy: InboundPolicy) -> Result<(), MutinyError>;
+    fn get_inbound_capacity_alerts(&self) -> Result<InboundCapacityAlerts, MutinyError>;
+    fn set_inbound_capacity_alerts(&self, alerts: InboundCapacityAlerts)
+        -> Result<(), MutinyError>;
+    fn get_inbound_capacity_warnings(&self) -> Result<Vec<InboundCapacityWarning>, MutinyError>;
+    fn push_inbound_capacity_warning(
+        &self,
+        warning: InboundCapacityWarning,
+    ) -> Result<(), MutinyError>;
+    fn clear_inbound_capacity_warnings(&self) -> Result<(), MutinyError>;
+    fn get_inbound_htlc_limits(&self) -> Result<InboundHtlcLimits, MutinyError>;
+    fn set_inbound_htlc_limits(&self, limits: InboundHtlcLimits) -> Result<(), MutinyError>;
+    fn get_inbound_htlc_counters(&self) -> Result<InboundHtlcCounters, MutinyError>;
+    /// Counts a payment to us as accepted, or rejected for the given reason
+    fn record_inbound_htlc(&self, rejection: Option<InboundRejection>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> InboundStorage for S {
+    fn get_inbound_policy(&self) -> Result<InboundPolicy, MutinyError> {
+        let policy: Option<InboundPolicy> = self.get_data(INBOUND_POLICY_KEY)?;
+        Ok(policy.unwrap_or_default())
+    }
+
+    fn set_inbound_policy(&self, policy: InboundPolicy) -> Result<(), MutinyError> {
+        self.set_data(INBOUND_POLICY_KEY, policy, None)
+    }
+
+    fn get_inbound_capacity_alerts(&self) -> Result<InboundCapacityAlerts, MutinyError> {
+        let alerts: Option<InboundCapacityAlerts> = self.get_data(INBOUND_CAPACITY_ALERTS_KEY)?;
+        Ok(alerts.unwrap_or_default())
+    }
+
+    fn set_inbound_capacity_alerts(
+        &self,
+        alerts: InboundCapacityAlerts,
+    ) -> Result<(), MutinyError> {
+        self.set_data(INBOUND_CAPACITY_ALERTS_KEY, alerts, None)
+    }
+
+    fn get_inbound_capacity_warnings(&self) -> Result<Vec<InboundCapacityWarning>, MutinyError> {
+        let warnings: Option<Vec<InboundCapacityWarning>> =
+            self.get_data(INBOUND_CAPACITY_WARNINGS_KEY)?;
+        Ok(warnings.unwrap_or_default())
+    }
+
+    fn push_inbound_capacity_warning(
+        &self,
+        warning: InboundCapacityWarning,
+    ) -> Result<(), MutinyError> {
+        let mut warnings = self.get_inbound_capacity_warnings()?;
+        warnings.push(warning);
+        if warnings.len() > MAX_WARNINGS {
+            let start_index = warnings.len() - MAX_WARNINGS;
+            warnings.drain(..start_index);
+        }
+        self.set_data(INBOUND_CAPACITY_WARNINGS_KEY, warnings, None)
+    }
+
+    fn clear_inbound_capacity_warnings(&self) -> Result<(), MutinyError> {
+        self.delete(&[INBOUND_CAPACITY_WARNINGS_KEY])
+    }
+
+    fn get_inbound_htlc_limits(&self) -> Result<InboundHtlcLimits, MutinyError> {
+        let limits: Option<InboundHtlcLimits> = self.get_data(INBOUND_HTLC_LIMITS_KEY)?;
+        Ok(limits.unwrap_or_default())
+    }
+
+    fn set_inbound_htlc_limits(&self, limits: InboundHtlcLimits) -> Result<(), MutinyError> {
+        limits.validate()?;
+        self.set_data(INBOUND_HTLC_LIMITS_KEY, limits, None)
+    }
+
+    fn get_inbound_htlc_counters(&self) -> Result<InboundHtlcCounters, MutinyError> {
+        let counters: Option<InboundHtlcCounters> = self.get_data(INBOUND_HTLC_COUNTERS_KEY)?;
+        Ok(counters.unwrap_or_default())
+    }
+
+    fn record_inbound_htlc(&self, rejection: Option<InboundRejection>) -> Result<(), MutinyError> {
+        let mut counters = self.get_inbound_htlc_counters()?;
+        counters.record(rejection);
+        self.set_data(INBOUND_HTLC_COUNTERS_KEY, counters, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const PEER_PUBKEY: &str = "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b";
+    const OTHER_PUBKEY: &str = "03864ef025fde8fb587d989186ce6a4a186895ee44a926bfc370e2c366597a3f8f";
+
+    #[test]
+    fn test_should_accept_channel() {
+        log!("test should accept channel");
+
+        let peer = PublicKey::from_str(PEER_PUBKEY).unwrap();
+        let other = PublicKey::from_str(OTHER_PUBKEY).unwrap();
+
+        let policy = InboundPolicy::default();
+        assert!(policy.should_accept_channel(&peer, 10_000));
+
+        let policy = InboundPolicy {
+            min_channel_size_sats: Some(100_000),
+            allowed_peers: Some(vec![peer]),
+            ..Default::default()
+        };
+        assert!(policy.should_accept_channel(&peer, 100_000));
+        assert!(!policy.should_accept_channel(&peer, 10_000));
+        assert!(!policy.should_accept_channel(&other, 100_000));
+
+        let policy = InboundPolicy {
+            accept_channels: false,
+            ..Default::default()
+        };
+        assert!(!policy.should_accept_channel(&peer, 100_000));
+    }
+
+    #[test]
+    fn test_inbound_policy_storage() {
+        log!("test inbound policy storage");
+
+        let storage = MemoryStorage::default();
+        assert_eq!(
+            storage.get_inbound_policy().unwrap(),
+            InboundPolicy::default()
+        );
+
+        let policy = InboundPolicy {
+            accept_channels: false,
+            ..Default::default()
+        };
+        storage.set_inbound_policy(policy.clone()).unwrap();
+        assert_eq!(storage.get_inbound_policy().unwrap(), policy);
+    }
+
+    #[test]
+    fn test_inbound_capacity_report() {
+        log!("test inbound capacity report");
+
+        let report = InboundCapacityReport::new(10_000, 50_000, None);
+        assert_eq!(report.shortfall_sats, 0);
+
+        let report = InboundCapacityReport::new(10_000, 50_000, Some(100_000));
+        assert_eq!(report.shortfall_sats, 90_000);
+        // can only swap out what we have
+        assert_eq!(report.swap_out_amount(), 50_000);
+
+        let report = InboundCapacityReport::new(200_000, 50_000, Some(100_000));
+        assert_eq!(report.shortfall_sats, 0);
+        assert_eq!(report.swap_out_amount(), 0);
+    }
+
+    #[test]
+    fn test_inbound_capacity_warnings() {
+        log!("test inbound capacity warnings");
+
+        let mut alerts = InboundCapacityAlerts::default();
+        let low = InboundCapacityReport::new(10_000, 50_000, Some(100_000));
+        assert!(alerts.next_warning(&low, 0).is_none());
+
+        let mut alerts = InboundCapacityAlerts::new(Some(100_000));
+        let warning = alerts.next_warning(&low, 1).unwrap();
+        assert_eq!(warning.inbound_sats, 10_000);
+        assert_eq!(warning.threshold_sats, 100_000);
+
+        // still low, don't warn again
+        assert!(alerts.next_warning(&low, 2).is_none());
+
+        // warn again after recovering
+        let high = InboundCapacityReport::new(150_000, 50_000, Some(100_000));
+        assert!(alerts.next_warning(&high, 3).is_none());
+        assert!(alerts.next_warning(&low, 4).is_some());
+
+        let storage = MemoryStorage::default();
+        for _ in 0..MAX_WARNINGS + 1 {
+            storage
+                .push_inbound_capacity_warning(warning.clone())
+                .unwrap();
+        }
+        assert_eq!(
+            storage.get_inbound_capacity_warnings().unwrap().len(),
+            MAX_WARNINGS
+        );
+        storage.clear_inbound_capacity_warnings().unwrap();
+        assert!(storage.get_inbound_capacity_warnings().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inbound_htlc_limits() {
+        log!("test inbound htlc limits");
+
+        let limits = InboundHtlcLimits::default();
+        assert_eq!(limits.check(1_000, 100), None);
+        let default_config = ChannelHandshakeConfig::default();
+        let mut config = default_config;
+        limits.apply(&mut config);
+        assert_eq!(
+            config.our_htlc_minimum_msat,
+            default_config.our_htlc_minimum_msat
+        );
+        assert_eq!(
+            config.our_max_accepted_htlcs,
+            default_config.our_max_accepted_htlcs
+        );
+
+        let limits = InboundHtlcLimits {
+            min_payment_msat: Some(1_000_000),
+            max_accepted_htlcs: Some(5),
+            reject_dust_htlcs: true,
+        };
+        assert_eq!(limits.check(1_000, 1), Some(InboundRejection::Dust));
+        assert_eq!(
+            limits.check(500_000, 1),
+            Some(InboundRejection::BelowMinimum)
+        );
+        assert_eq!(
+            limits.check(1_000_000, 6),
+            Some(InboundRejection::TooManyPending)
+        );
+        assert_eq!(limits.check(1_000_000, 5), None);
+
+        // the HTLC limit goes in the channel config, the dust limit is only per payment
+        limits.apply(&mut config);
+        assert_eq!(
+            config.our_htlc_minimum_msat,
+            default_config.our_htlc_minimum_msat
+        );
+        assert_eq!(config.our_max_accepted_htlcs, 5);
+
+        // limits saved before the rename still load
+        let old: InboundHtlcLimits = serde_json::from_str(
+            r#"{"min_payment_msat":null,"max_pending_htlcs_per_peer":3,"reject_dust_htlcs":false}"#,
+        )
+        .unwrap();
+        assert_eq!(old.max_accepted_htlcs, Some(3));
+
+        let storage = MemoryStorage::default();
+        assert_eq!(
+            storage.get_inbound_htlc_limits().unwrap(),
+            InboundHtlcLimits::default()
+        );
+        storage.set_inbound_htlc_limits(limits.clone()).unwrap();
+        assert_eq!(storage.get_inbound_htlc_limits().unwrap(), limits);
+        let bad = InboundHtlcLimits {
+            max_accepted_htlcs: Some(0),
+            ..Default::default()
+        };
+        assert!(storage.set_inbound_htlc_limits(bad).is_err());
+        let bad = InboundHtlcLimits {
+            max_accepted_htlcs: Some(MAX_ACCEPTED_HTLCS + 1),
+            ..Default::default()
+        };
+        assert!(storage.set_inbound_htlc_limits(bad).is_err());
+
+        storage.record_inbound_htlc(None).unwrap();
+        storage.record_inbound_htlc(None).unwrap();
+        storage
+            .record_inbound_htlc(Some(InboundRejection::BelowMinimum))
+            .unwrap();
+        storage
+            .record_inbound_htlc(Some(InboundRejection::Dust))
+            .unwrap();
+        storage
+            .record_inbound_htlc(Some(InboundRejection::TooManyPending))
+            .unwrap();
+        let counters = storage.get_inbound_htlc_counters().unwrap();
+        assert_eq!(counters.accepted, 2);
+        assert_eq!(counters.rejected_below_minimum, 1);
+        assert_eq!(counters.rejected_dust, 1);
+        assert_eq!(counters.rejected_too_many_pending, 1);
+
+        // counters saved before the dust and pending counters still load
+        let old: InboundHtlcCounters =
+            serde_json::from_str(r#"{"accepted":1,"rejected_below_minimum":2}"#).unwrap();
+        assert_eq!(old.rejected_dust, 0);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::error::MutinyError;
+use crate::nostr::recovery::{create_dm, read_dm};
+use crate::nostr::NostrManager;
+use crate::storage::MutinyStorage;
+use bitcoin::{Address, OutPoint, Transaction, Txid};
+use nostr::prelude::XOnlyPublicKey;
+use nostr::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+pub const INHERITANCE_PLAN_KEY: &str = "inheritance_plan";
+pub const INHERITANCE_REMINDERS_KEY: &str = "inheritance_reminders";
+pub const HELD_INHERITANCE_SWEEPS_KEY: &str = "held_inheritance_sweeps";
+
+/// How long before the sweep unlocks we remind the user to check in, in seconds.
+/// Must be in descending order.
+const REMINDER_THRESHOLDS_SECS: [u64; 3] = [60 * 60 * 24 * 7, 60 * 60 * 24, 60 * 60];
+
+/// Only keep the most recent reminders
+const MAX_REMINDERS: usize = 10;
+
+/// How often we check for sweeps other users have sent us as their heir, in seconds
+pub(crate) const HELD_SWEEP_SYNC_INTERVAL_SECS: u64 = 60 * 60;
+
+/// A dead-man switch that sweeps the on-chain wallet to an heir.
+///
+/// The sweep transaction is signed ahead of time with a timelock so it can't be
+/// mined until `unlock_time`. Checking in invalidates the old transaction by
+/// spending one of its inputs back to the wallet and signs a new one with a
+/// later unlock time.
+///
+/// Whenever the wallet's utxos change the sweep is signed again with the same
+/// unlock time, so it keeps covering the whole wallet. Each new sweep is sent to
+/// the heir's npub, whose wallet broadcasts it once it unlocks. The owner's
+/// wallet broadcasts it as well if it is still running by then. The replaced
+/// sweeps stay valid too, so checking in invalidates every one of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InheritancePlan {
+    pub heir_address: Address,
+    /// Where the signed sweep transaction is sent as an encrypted DM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heir_npub: Option<XOnlyPublicKey>,
+    pub check_in_interval_secs: u64,
+    pub last_check_in: u64,
+    /// Unix timestamp after which the sweep transaction can be mined
+    pub unlock_time: u64,
+    /// The signed sweep transaction to give to the heir,
+    /// this is `None` when the wallet has no funds to sweep.
+    pub sweep_tx: Option<Transaction>,
+    /// Whether the current sweep transaction has been sent to the heir's npub
+    #[serde(default)]
+    pub delivered: bool,
+    /// The inputs of the sweeps signed since the last check in that were replaced
+    /// by a newer one. The heir may still have them, so they are invalidated too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_sweeps: Vec<Vec<OutPoint>>,
+    /// The last reminder threshold we emitted a reminder for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_reminder_threshold: Option<u64>,
+}
+
+impl InheritancePlan {
+    pub(crate) fn new(
+        heir_address: Address,
+        heir_npub: Option<XOnlyPublicKey>,
+        check_in_interval_secs: u64,
+        now: u64,
+        sweep_tx: Option<Transaction>,
+    ) -> Self {
+        Self {
+            heir_address,
+            heir_npub,
+            check_in_interval_secs,
+            last_check_in: now,
+            unlock_time: now + check_in_interval_secs,
+            sweep_tx,
+            delivered: false,
+            previous_sweeps: vec![],
+            last_reminder_threshold: None,
+        }
+    }
+
+    /// Whether the sweep transaction no longer spends exactly the given utxos,
+    /// because the wallet received or spent funds since it was signed.
+    pub(crate) fn sweep_outdated(&self, utxos: &HashSet<OutPoint>) -> bool {
+        let inputs: HashSet<OutPoint> = self
+            .sweep_tx
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|i| i.previous_output))
+            .collect();
+        inputs != *utxos
+    }
+
+    /// Replaces the sweep transaction, the heir needs to be sent the new one.
+    pub(crate) fn set_sweep_tx(&mut self, sweep_tx: Option<Transaction>) {
+        if let Some(old) = std::mem::replace(&mut self.sweep_tx, sweep_tx) {
+            self.previous_sweeps
+                .push(old.input.iter().map(|i| i.previous_output).collect());
+        }
+        self.delivered = false;
+    }
+
+    /// The utxos to spend to invalidate every sweep signed since the last check in.
+    /// A sweep is already invalid once any of its inputs is spent, so this picks
+    /// one input from each sweep whose inputs are all still in the wallet.
+    pub(crate) fn outpoints_to_invalidate(&self, utxos: &HashSet<OutPoint>) -> Vec<OutPoint> {
+        let current = self.sweep_tx.iter().map(|tx| {
+            tx.input
+                .iter()
+                .map(|i| i.previous_output)
+                .collect::<Vec<_>>()
+        });
+        let mut spend: Vec<OutPoint> = vec![];
+        for inputs in self.previous_sweeps.iter().cloned().chain(current) {
+            let valid = !inputs.is_empty() && inputs.iter().all(|o| utxos.contains(o));
+            if valid && !inputs.iter().any(|o| spend.contains(o)) {
+                spend.push(inputs[0]);
+            }
+        }
+        spend
+    }
+
+    /// Whether there is a sweep transaction the heir hasn't been sent yet
+    pub fn needs_delivery(&self) -> bool {
+        self.heir_npub.is_some() && self.sweep_tx.is_some() && !self.delivered
+    }
+
+    /// Returns a reminder if we have passed a reminder threshold
+    /// we haven't emitted a reminder for yet.
+    pub(crate) fn next_reminder(&mut self, now: u64) -> Option<InheritanceReminder> {
+        let seconds_remaining = self.unlock_time.saturating_sub(now);
+        let threshold = REMINDER_THRESHOLDS_SECS
+            .iter()
+            .copied()
+            .filter(|t| seconds_remaining <= *t)
+            .min()?;
+
+        if self
+            .last_reminder_threshold
+            .is_some_and(|last| last <= threshold)
+        {
+            return None;
+        }
+
+        self.last_reminder_threshold = Some(threshold);
+        Some(InheritanceReminder {
+            unlock_time: self.unlock_time,
+            seconds_remaining,
+            created_at: now,
+        })
+    }
+}
+
+/// Emitted when the inheritance sweep is about to unlock and the user should check in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InheritanceReminder {
+    pub unlock_time: u64,
+    pub seconds_remaining: u64,
+    pub created_at: u64,
+}
+
+/// Sent to the heir as an encrypted nostr DM every time the sweep transaction changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InheritanceMessage {
+    InheritanceSweep {
+        owner: XOnlyPublicKey,
+        unlock_time: u64,
+        sweep_tx: Transaction,
+    },
+}
+
+/// A sweep transaction someone sent us as their heir,
+/// we broadcast it once it unlocks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeldSweep {
+    pub owner: XOnlyPublicKey,
+    pub unlock_time: u64,
+    pub sweep_tx: Transaction,
+    /// When the owner sent the sweep, newer sweeps from the same owner replace older ones
+    pub sent_at: u64,
+    /// When we broadcast the sweep, if we have
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_at: Option<u64>,
+}
+
+pub trait InheritanceStorage {
+    fn get_inheritance_plan(&self) -> Result<Option<InheritancePlan>, MutinyError>;
+    fn persist_inheritance_plan(&self, plan: InheritancePlan) -> Result<(), MutinyError>;
+    fn delete_inheritance_plan(&self) -> Result<(), MutinyError>;
+    fn get_inheritance_reminders(&self) -> Result<Vec<InheritanceReminder>, MutinyError>;
+    fn push_inheritance_reminder(&self, reminder: InheritanceReminder) -> Result<(), MutinyError>;
+    fn clear_inheritance_reminders(&self) -> Result<(), MutinyError>;
+    fn get_held_sweeps(&self) -> Result<Vec<HeldSweep>, MutinyError>;
+    /// Keeps the sweep unless we already hold a newer one from the same owner.
+    /// Returns true if the sweep was kept.
+    fn hold_sweep(&self, sweep: HeldSweep) -> Result<bool, MutinyError>;
+    fn mark_held_sweep_broadcast(&self, txid: Txid, now: u64) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> InheritanceStorage for S {
+    fn get_inheritance_plan(&self) -> Result<Option<InheritancePlan>, MutinyError> {
+        self.get_data(INHERITANCE_PLAN_KEY)
+    }
+
+    fn persist_inheritance_plan(&self, plan: InheritancePlan) -> Result<(), MutinyError> {
+        self.set_data(INHERITANCE_PLAN_KEY, plan, None)
+    }
+
+    fn delete_inheritance_plan(&self) -> Result<(), MutinyError> {
+        self.delete(&[INHERITANCE_PLAN_KEY])
+    }
+
+    fn get_inheritance_reminders(&self) -> Result<Vec<InheritanceReminder>, MutinyError> {
+        let reminders: Option<Vec<InheritanceReminder>> =
+            self.get_data(INHERITANCE_REMINDERS_KEY)?;
+        Ok(reminders.unwrap_or_default())
+    }
+
+    fn push_inheritance_reminder(&self, reminder: InheritanceReminder) -> Result<(), MutinyError> {
+        let mut reminders = self.get_inheritance_reminders()?;
+        reminders.push(reminder);
+        if reminders.len() > MAX_REMINDERS {
+            let start_index = reminders.len() - MAX_REMINDERS;
+            reminders.drain(..start_index);
+        }
+        self.set_data(INHERITANCE_REMINDERS_KEY, reminders, None)
+    }
+
+    fn clear_inheritance_reminders(&self) -> Result<(), MutinyError> {
+        self.delete(&[INHERITANCE_REMINDERS_KEY])
+    }
+
+    fn get_held_sweeps(&self) -> Result<Vec<HeldSweep>, MutinyError> {
+        let sweeps: Option<Vec<HeldSweep>> = self.get_data(HELD_INHERITANCE_SWEEPS_KEY)?;
+        Ok(sweeps.unwrap_or_default())
+    }
+
+    fn hold_sweep(&self, sweep: HeldSweep) -> Result<bool, MutinyError> {
+        let mut sweeps = self.get_held_sweeps()?;
+        if sweeps
+            .iter()
+            .any(|s| s.owner == sweep.owner && s.sent_at >= sweep.sent_at)
+        {
+            return Ok(false);
+        }
+
+        sweeps.retain(|s| s.owner != sweep.owner);
+        sweeps.push(sweep);
+        self.set_data(HELD_INHERITANCE_SWEEPS_KEY, sweeps, None)?;
+        Ok(true)
+    }
+
+    fn mark_held_sweep_broadcast(&self, txid: Txid, now: u64) -> Result<(), MutinyError> {
+        let mut sweeps = self.get_held_sweeps()?;
+        for sweep in sweeps.iter_mut().filter(|s| s.sweep_tx.txid() == txid) {
+            sweep.broadcast_at = Some(now);
+        }
+        self.set_data(HELD_INHERITANCE_SWEEPS_KEY, sweeps, None)
+    }
+}
+
+impl<S: MutinyStorage> NostrManager<S> {
+    /// Creates the DM sending the plan's sweep transaction to the heir,
+    /// `None` if there is no heir npub or nothing to sweep.
+    pub fn create_sweep_event(&self, plan: &InheritancePlan) -> anyhow::Result<Option<Event>> {
+        let (Some(heir), Some(sweep_tx)) = (plan.heir_npub, plan.sweep_tx.clone()) else {
+            return Ok(None);
+        };
+
+        let msg = InheritanceMessage::InheritanceSweep {
+            owner: self.primary_key.public_key(),
+            unlock_time: plan.unlock_time,
+            sweep_tx,
+        };
+        Ok(Some(create_dm(&self.primary_key, heir, &msg)?))
+    }
+
+    /// Holds on to a sweep transaction sent to us as an heir.
+    /// Returns true if the event was an inheritance message.
+    pub fn handle_inheritance_message(&self, event: &Event) -> Result<bool, MutinyError> {
+        let msg: InheritanceMessage = match read_dm(&self.primary_key, event) {
+            Some(msg) => msg,
+            None => return Ok(false),
+        };
+
+        match msg {
+            InheritanceMessage::InheritanceSweep {
+                owner,
+                unlock_time,
+                sweep_tx,
+            } => {
+                // only the owner can send us their sweep
+                if owner != event.pubkey {
+                    return Ok(false);
+                }
+
+                self.storage.hold_sweep(HeldSweep {
+                    owner,
+                    unlock_time,
+                    sweep_tx,
+                    sent_at: event.created_at.as_u64(),
+                    broadcast_at: None,
+                })?;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_seed;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use bitcoin::{Network, PackedLockTime, TxIn};
+    use nostr::Keys;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const DAY: u64 = 60 * 60 * 24;
+
+    fn create_nostr_manager() -> NostrManager<MemoryStorage> {
+        let mnemonic = generate_seed(12).unwrap();
+        let xprivkey =
+            ExtendedPrivKey::new_master(Network::Regtest, &mnemonic.to_seed("")).unwrap();
+        NostrManager::from_mnemonic(xprivkey, MemoryStorage::default()).unwrap()
+    }
+
+    fn dummy_plan(now: u64) -> InheritancePlan {
+        let address = Address::from_str("tb1qhgemzcaj5ehn7yvqq7wh0w2pkxg3ymq5yc0k8t").unwrap();
+        InheritancePlan::new(address, None, 30 * DAY, now, None)
+    }
+
+    #[test]
+    fn test_inheritance_reminders() {
+        log!("test inheritance reminders");
+
+        let start = 1_000_000;
+        let mut plan = dummy_plan(start);
+        assert_eq!(plan.unlock_time, start + 30 * DAY);
+
+        // nothing until we are a week out
+        assert!(plan.next_reminder(start).is_none());
+        assert!(plan.next_reminder(start + 22 * DAY).is_none());
+
+        let reminder = plan.next_reminder(start + 23 * DAY).unwrap();
+        assert_eq!(reminder.seconds_remaining, 7 * DAY);
+        // only emitted once per threshold
+        assert!(plan.next_reminder(start + 24 * DAY).is_none());
+
+        assert!(plan.next_reminder(start + 29 * DAY).is_some());
+        assert!(plan.next_reminder(start + 30 * DAY).is_some());
+        assert!(plan.next_reminder(start + 31 * DAY).is_none());
+
+        // checking in resets the reminders
+        let mut plan = dummy_plan(start + 31 * DAY);
+        assert!(plan.next_reminder(start + 31 * DAY).is_none());
+    }
+
+    fn dummy_tx(outpoints: &[OutPoint]) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: outpoints
+                .iter()
+                .map(|o| TxIn {
+                    previous_output: *o,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_inheritance_sweep_outdated() {
+        log!("test inheritance sweep outdated");
+
+        let a = OutPoint::new(Txid::all_zeros(), 0);
+        let b = OutPoint::new(Txid::all_zeros(), 1);
+
+        // an empty wallet needs a sweep once it receives funds
+        let mut plan = dummy_plan(100);
+        assert!(!plan.sweep_outdated(&HashSet::new()));
+        assert!(plan.sweep_outdated(&HashSet::from([a])));
+
+        plan.set_sweep_tx(Some(dummy_tx(&[a])));
+        assert!(!plan.sweep_outdated(&HashSet::from([a])));
+        // received more funds
+        assert!(plan.sweep_outdated(&HashSet::from([a, b])));
+        // spent the swept utxo
+        assert!(plan.sweep_outdated(&HashSet::from([b])));
+
+        // nothing to deliver without an heir npub
+        assert!(!plan.needs_delivery());
+        plan.heir_npub = Some(Keys::generate().public_key());
+        assert!(plan.needs_delivery());
+        plan.delivered = true;
+        assert!(!plan.needs_delivery());
+        plan.set_sweep_tx(Some(dummy_tx(&[a, b])));
+        assert!(plan.needs_delivery());
+    }
+
+    #[test]
+    fn test_inheritance_outpoints_to_invalidate() {
+        log!("test inheritance outpoints to invalidate");
+
+        let a = OutPoint::new(Txid::all_zeros(), 0);
+        let b = OutPoint::new(Txid::all_zeros(), 1);
+        let c = OutPoint::new(Txid::all_zeros(), 2);
+
+        let mut plan = dummy_plan(100);
+        assert!(plan.outpoints_to_invalidate(&HashSet::from([a])).is_empty());
+
+        // received b, the first sweep only spending a is still valid
+        plan.set_sweep_tx(Some(dummy_tx(&[a])));
+        plan.set_sweep_tx(Some(dummy_tx(&[b, a])));
+        assert_eq!(plan.previous_sweeps, vec![vec![a]]);
+        assert_eq!(
+            plan.outpoints_to_invalidate(&HashSet::from([a, b])),
+            vec![a]
+        );
+
+        // spent a, so only the sweep of c needs invalidating
+        plan.set_sweep_tx(Some(dummy_tx(&[c])));
+        assert_eq!(plan.outpoints_to_invalidate(&HashSet::from([c])), vec![c]);
+
+        // the sweeps of a and c are both still valid
+        plan.set_sweep_tx(Some(dummy_tx(&[a])));
+        assert_eq!(
+            plan.outpoints_to_invalidate(&HashSet::from([a, c])),
+            vec![a, c]
+        );
+    }
+
+    #[test]
+    fn test_inheritance_sweep_delivery() {
+        log!("test inheritance sweep delivery");
+
+        let owner = create_nostr_manager();
+        let heir = create_nostr_manager();
+        let other = create_nostr_manager();
+
+        let mut plan = dummy_plan(100);
+        plan.set_sweep_tx(Some(dummy_tx(&[OutPoint::new(Txid::all_zeros(), 0)])));
+        assert!(owner.create_sweep_event(&plan).unwrap().is_none());
+
+        plan.heir_npub = Some(heir.primary_key.public_key());
+        let event = owner.create_sweep_event(&plan).unwrap().unwrap();
+
+        // only the heir can read it
+        assert!(!other.handle_inheritance_message(&event).unwrap());
+        assert!(heir.handle_inheritance_message(&event).unwrap());
+
+        let held = heir.storage.get_held_sweeps().unwrap();
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].owner, owner.primary_key.public_key());
+        assert_eq!(held[0].unlock_time, plan.unlock_time);
+        assert_eq!(Some(&held[0].sweep_tx), plan.sweep_tx.as_ref());
+    }
+
+    #[test]
+    fn test_held_sweeps() {
+        log!("test held sweeps");
+
+        let storage = MemoryStorage::default();
+        let owner = Keys::generate().public_key();
+        let sweep = |sent_at: u64, vout: u32| HeldSweep {
+            owner,
+            unlock_time: 1_000,
+            sweep_tx: dummy_tx(&[OutPoint::new(Txid::all_zeros(), vout)]),
+            sent_at,
+            broadcast_at: None,
+        };
+
+        assert!(storage.hold_sweep(sweep(10, 0)).unwrap());
+        // newer sweeps from the same owner replace older ones
+        assert!(storage.hold_sweep(sweep(20, 1)).unwrap());
+        assert!(!storage.hold_sweep(sweep(10, 0)).unwrap());
+        let held = storage.get_held_sweeps().unwrap();
+        assert_eq!(held, vec![sweep(20, 1)]);
+
+        storage
+            .mark_held_sweep_broadcast(held[0].sweep_tx.txid(), 30)
+            .unwrap();
+        assert_eq!(storage.get_held_sweeps().unwrap()[0].broadcast_at, Some(30));
+    }
+
+    #[test]
+    fn test_inheritance_storage() {
+        log!("test inheritance storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_inheritance_plan().unwrap().is_none());
+
+        let plan = dummy_plan(100);
+        storage.persist_inheritance_plan(plan.clone()).unwrap();
+        assert_eq!(storage.get_inheritance_plan().unwrap(), Some(plan));
+
+        storage.delete_inheritance_plan().unwrap();
+        assert!(storage.get_inheritance_plan().unwrap().is_none());
+
+        for i in 0..(MAX_REMINDERS as u64 + 1) {
+            storage
+                .push_inheritance_reminder(InheritanceReminder {
+                    unlock_time: 0,
+                    seconds_remaining: 0,
+                    created_at: i,
+                })
+                .unwrap();
+        }
+        let reminders = storage.get_inheritance_reminders().unwrap();
+        assert_eq!(reminders.len(), MAX_REMINDERS);
+        assert_eq!(reminders[0].created_at, 1);
+
+        storage.clear_inheritance_reminders().unwrap();
+        assert!(storage.get_inheritance_reminders().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+
+pub const JUSTICE_PROOFS_KEY: &str = "justice_proofs";
+
+/// A record of a counterparty broadcasting a revoked commitment transaction
+/// and us claiming their funds with justice transactions.
+///
+/// Everything in here can be checked against a block explorer: the transactions
+/// spend the channel's funding output and pay the recovered funds to our wallet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JusticeProof {
+    /// Our node that the channel belonged to
+    pub node_id: PublicKey,
+    pub counterparty: Option<PublicKey>,
+    pub funding_txo: OutPoint,
+    /// The revoked commitment transaction and our justice transactions
+    pub txids: Vec<Txid>,
+    /// Amount recovered from the revoked commitment transaction
+    pub amount_recovered_sats: u64,
+    /// Epoch time in seconds of when the breach was detected
+    pub detected_at: u64,
+    /// Epoch time in seconds of when all the justice transactions were confirmed
+    pub claimed_at: Option<u64>,
+}
+
+impl JusticeProof {
+    pub(crate) fn new(
+        node_id: PublicKey,
+        counterparty: Option<PublicKey>,
+        funding_txo: OutPoint,
+        now: u64,
+    ) -> Self {
+        Self {
+            node_id,
+            counterparty,
+            funding_txo,
+            txids: vec![],
+            amount_recovered_sats: 0,
+            detected_at: now,
+            claimed_at: None,
+        }
+    }
+
+    /// Updates the proof with what the channel monitor currently sees.
+    ///
+    /// `revoked_sats` is the amount still claimable from revoked outputs,
+    /// once it drops to zero all of the justice transactions have confirmed.
+    /// Returns true if the proof changed.
+    pub(crate) fn observe(&mut self, revoked_sats: u64, txids: Vec<Txid>, now: u64) -> bool {
+        if self.claimed_at.is_some() {
+            return false;
+        }
+
+        let old = self.clone();
+        self.amount_recovered_sats = self.amount_recovered_sats.max(revoked_sats);
+        for txid in txids {
+            if txid != self.funding_txo.txid && !self.txids.contains(&txid) {
+                self.txids.push(txid);
+            }
+        }
+        if revoked_sats == 0 {
+            self.claimed_at = Some(now);
+        }
+
+        old != *self
+    }
+
+    pub fn is_claimed(&self) -> bool {
+        self.claimed_at.is_some()
+    }
+
+    /// A plain text version of the proof that can be shared
+    pub fn to_shareable_string(&self) -> String {
+        let status = if self.is_claimed() {
+            "claimed"
+        } else {
+            "pending"
+        };
+        let counterparty = self
+            .counterparty
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let txids: Vec<String> = self.txids.iter().map(|t| t.to_string()).collect();
+
+        format!(
+            "Justice claim ({status})\nChannel: {}\nCounterparty: {counterparty}\nRecovered: {} sats\nTransactions: {}",
+            self.funding_txo,
+            self.amount_recovered_sats,
+            txids.join(", "),
+        )
+    }
+}
+
+pub trait JusticeStorage {
+    fn get_justice_proofs(&self) -> Result<Vec<JusticeProof>, MutinyError>;
+    fn persist_justice_proofs(&self, proofs: Vec<JusticeProof>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> JusticeStorage for S {
+    fn get_justice_proofs(&self) -> Result<Vec<JusticeProof>, MutinyError> {
+        let proofs: Option<Vec<JusticeProof>> = self.get_data(JUSTICE_PROOFS_KEY)?;
+        Ok(proofs.unwrap_or_default())
+    }
+
+    fn persist_justice_proofs(&self, proofs: Vec<JusticeProof>) -> Result<(), MutinyError> {
+        self.set_data(JUSTICE_PROOFS_KEY, proofs, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_proof() -> JusticeProof {
+        let node_id = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let funding_txo = OutPoint::new(Txid::all_zeros(), 0);
+        JusticeProof::new(node_id, None, funding_txo, 100)
+    }
+
+    #[test]
+    fn test_justice_proof_observe() {
+        log!("test justice proof observe");
+
+        let mut proof = dummy_proof();
+        let commitment = Txid::from_slice(&[1; 32]).unwrap();
+        let justice = Txid::from_slice(&[2; 32]).unwrap();
+
+        // funding txid is not included
+        assert!(proof.observe(10_000, vec![Txid::all_zeros(), commitment], 200));
+        assert_eq!(proof.txids, vec![commitment]);
+        assert!(!proof.is_claimed());
+
+        // no changes
+        assert!(!proof.observe(10_000, vec![commitment], 300));
+
+        // amount recovered keeps the max seen
+        assert!(proof.observe(0, vec![commitment, justice], 400));
+        assert_eq!(proof.amount_recovered_sats, 10_000);
+        assert_eq!(proof.txids, vec![commitment, justice]);
+        assert_eq!(proof.claimed_at, Some(400));
+
+        // claimed proofs are final
+        assert!(!proof.observe(5_000, vec![], 500));
+
+        let shareable = proof.to_shareable_string();
+        assert!(shareable.contains("claimed"));
+        assert!(shareable.contains("10000 sats"));
+        assert!(shareable.contains(&justice.to_string()));
+    }
+
+    #[test]
+    fn test_justice_storage() {
+        log!("test justice storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_justice_proofs().unwrap().is_empty());
+
+        let proofs = vec![dummy_proof()];
+        storage.persist_justice_proofs(proofs.clone()).unwrap();
+        assert_eq!(storage.get_justice_proofs().unwrap(), proofs);
+    }
+}
@@ -9,7 +9,7 @@ use bitcoin::bech32::u5;
 use bitcoin::secp256k1::ecdh::SharedSecret;
 use bitcoin::secp256k1::ecdsa::RecoverableSignature;
 use bitcoin::secp256k1::ecdsa::Signature;
-use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, Signing};
+use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey, Signing};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
 use bitcoin::{Script, Transaction, TxOut};
 use lightning::ln::msgs::{DecodeError, UnsignedGossipMessage};
@@ -51,14 +51,34 @@ impl<S: MutinyStorage> PhantomKeysManager<S> {
         }
     }
 
+    /// The secret key of the node, for signing messages as the node
+    pub(crate) fn get_node_secret_key(&self) -> SecretKey {
+        self.inner.get_node_secret_key()
+    }
+
     /// See [`KeysManager::spend_spendable_outputs`] for documentation on this method.
+    ///
+    /// The outputs are swept to `destination` if given,
+    /// otherwise to a new address in the on-chain wallet.
     pub fn spend_spendable_outputs<C: Signing>(
         &self,
         descriptors: &[&SpendableOutputDescriptor],
         outputs: Vec<TxOut>,
+        destination: Option<Script>,
         feerate_sat_per_1000_weight: u32,
         secp_ctx: &Secp256k1<C>,
     ) -> Result<Transaction, ()> {
+        if let Some(destination) = destination {
+            return self.inner.spend_spendable_outputs(
+                descriptors,
+                outputs,
+                destination,
+                feerate_sat_per_1000_weight,
+                None, // tx locktime of 0
+                secp_ctx,
+            );
+        }
+
         let address = {
             let mut wallet = self.wallet.wallet.try_write().map_err(|_| ())?;
             wallet.get_internal_address(AddressIndex::New).address
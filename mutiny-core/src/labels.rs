@@ -1,5 +1,6 @@
 use crate::error::MutinyError;
 use crate::nodemanager::NodeManager;
+use crate::nostr::nip05::Nip05Identity;
 use crate::storage::MutinyStorage;
 use bitcoin::{Address, XOnlyPublicKey};
 use lightning_invoice::Bolt11Invoice;
@@ -11,10 +12,10 @@ use std::collections::HashMap;
 use std::str::FromStr;
 use uuid::Uuid;
 
-const ADDRESS_LABELS_MAP_KEY: &str = "address_labels";
-const INVOICE_LABELS_MAP_KEY: &str = "invoice_labels";
-const LABEL_PREFIX: &str = "label/";
-const CONTACT_PREFIX: &str = "contact/";
+pub(crate) const ADDRESS_LABELS_MAP_KEY: &str = "address_labels";
+pub(crate) const INVOICE_LABELS_MAP_KEY: &str = "invoice_labels";
+pub(crate) const LABEL_PREFIX: &str = "label/";
+pub(crate) const CONTACT_PREFIX: &str = "contact/";
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd, Hash, Default)]
 pub struct LabelItem {
@@ -39,6 +40,9 @@ pub struct Contact {
     pub image_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub archived: Option<bool>,
+    /// The contact's NIP-05 identifier and whether it still points to their npub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nip05: Option<Nip05Identity>,
     pub last_used: u64,
 }
 
@@ -59,6 +63,13 @@ impl Contact {
 
         self.image_url = metadata.picture.or(self.image_url);
 
+        // a new identifier has to be verified again
+        if let Some(nip05) = metadata.nip05.map(|n| Nip05Identity::new(&n)) {
+            if self.nip05.as_ref().map(|n| &n.address) != Some(&nip05.address) {
+                self.nip05 = Some(nip05);
+            }
+        }
+
         self
     }
 
@@ -424,7 +435,15 @@ impl<S: MutinyStorage> LabelStorage for NodeManager<S> {
         self.storage.archive_contact(id)
     }
 
-    fn edit_contact(&self, id: impl AsRef<str>, contact: Contact) -> Result<(), MutinyError> {
+    fn edit_contact(&self, id: impl AsRef<str>, mut contact: Contact) -> Result<(), MutinyError> {
+        // the NIP-05 verification is managed by the wallet, keep it unless the npub changed
+        if contact.nip05.is_none() {
+            if let Some(existing) = self.storage.get_contact(&id)? {
+                if existing.npub == contact.npub {
+                    contact.nip05 = existing.nip05;
+                }
+            }
+        }
         self.storage.edit_contact(id, contact)
     }
 
@@ -519,6 +538,7 @@ mod tests {
                 lnurl: None,
                 archived: Some(false),
                 image_url: None,
+                nip05: None,
                 last_used: 0,
             },
         );
@@ -531,6 +551,7 @@ mod tests {
                 lnurl: None,
                 archived: Some(false),
                 image_url: None,
+                nip05: None,
                 last_used: 0,
             },
         );
@@ -543,6 +564,7 @@ mod tests {
                 lnurl: None,
                 archived: Some(false),
                 image_url: None,
+                nip05: None,
                 last_used: 0,
             },
         );
@@ -697,6 +719,7 @@ mod tests {
             lnurl: None,
             archived: Some(false),
             image_url: None,
+            nip05: None,
             last_used: 0,
         };
         let id = storage.create_new_contact(contact.clone()).unwrap();
@@ -719,6 +742,7 @@ mod tests {
             lnurl: None,
             archived: Some(false),
             image_url: None,
+            nip05: None,
             last_used: 0,
         };
         let id = storage.create_new_contact(contact).unwrap();
@@ -745,6 +769,7 @@ mod tests {
             lnurl: None,
             archived: Some(false),
             image_url: None,
+            nip05: None,
             last_used: 0,
         };
         let id = storage.create_new_contact(contact).unwrap();
@@ -1,23 +1,31 @@
+use crate::activity_index::{ActivityIndexStorage, ActivityRef};
 use crate::chain::MutinyChain;
+use crate::encoding::{checksum, Bytes};
 use crate::error::{MutinyError, MutinyStorageError};
-use crate::event::PaymentInfo;
+use crate::event::{HTLCStatus, PaymentInfo};
 use crate::fees::MutinyFeeEstimator;
 use crate::gossip::{NETWORK_GRAPH_KEY, PROB_SCORER_KEY};
 use crate::keymanager::PhantomKeysManager;
 use crate::logging::MutinyLogger;
 use crate::multiesplora::MultiEsploraClient;
-use crate::node::{default_user_config, ChainMonitor, ProbScorer};
+use crate::node::{ChainMonitor, ProbScorer};
 use crate::node::{NetworkGraph, Router};
-use crate::nodemanager::ChannelClosure;
+use crate::nodemanager::{ChannelClosure, HtlcState, PaymentHtlc};
+use crate::retention::PruneSummary;
 use crate::storage::{MutinyStorage, VersionedValue};
 use crate::utils;
+use crate::vss::{KeyVersion, VssKeyValueItem};
 use anyhow::anyhow;
 use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::Network;
 use bitcoin::{BlockHash, Transaction};
 use futures::{try_join, TryFutureExt};
+use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
 use lightning::chain::chainmonitor::{MonitorUpdateId, Persist};
-use lightning::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use lightning::chain::channelmonitor::{
+    ChannelMonitor, ChannelMonitorUpdate, CLOSED_CHANNEL_UPDATE_ID,
+};
 use lightning::chain::transaction::OutPoint;
 use lightning::chain::BestBlock;
 use lightning::io::Cursor;
@@ -25,11 +33,15 @@ use lightning::ln::channelmanager::{
     self, ChainParameters, ChannelManager as LdkChannelManager, ChannelManagerReadArgs,
 };
 use lightning::ln::PaymentHash;
-use lightning::sign::{InMemorySigner, SpendableOutputDescriptor, WriteableEcdsaChannelSigner};
+use lightning::sign::{
+    EntropySource, InMemorySigner, SignerProvider, SpendableOutputDescriptor,
+    WriteableEcdsaChannelSigner,
+};
+use lightning::util::config::UserConfig;
 use lightning::util::logger::Logger;
 use lightning::util::persist::Persister;
 use lightning::util::ser::{Readable, ReadableArgs, Writeable};
-use lightning::{chain, log_debug, log_error, log_trace};
+use lightning::{chain, log_debug, log_error, log_trace, log_warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::io;
@@ -38,11 +50,23 @@ use std::sync::Arc;
 
 pub const CHANNEL_MANAGER_KEY: &str = "manager";
 pub const MONITORS_PREFIX_KEY: &str = "monitors/";
-const PAYMENT_INBOUND_PREFIX_KEY: &str = "payment_inbound/";
-const PAYMENT_OUTBOUND_PREFIX_KEY: &str = "payment_outbound/";
+pub const MONITOR_UPDATES_PREFIX_KEY: &str = "monitor_updates/";
+pub(crate) const PAYMENT_INBOUND_PREFIX_KEY: &str = "payment_inbound/";
+pub(crate) const PAYMENT_OUTBOUND_PREFIX_KEY: &str = "payment_outbound/";
 const CHANNEL_OPENING_PARAMS_PREFIX: &str = "chan_open_params/";
 const CHANNEL_CLOSURE_PREFIX: &str = "channel_closure/";
+pub(crate) const PAYMENT_HTLCS_PREFIX: &str = "payment_htlcs/";
+/// Which node saved a phantom invoice, shared between all nodes
+const PHANTOM_INVOICE_PREFIX: &str = "phantom_invoice/";
 const FAILED_SPENDABLE_OUTPUT_DESCRIPTOR_KEY: &str = "failed_spendable_outputs";
+const PENDING_SWEEP_KEY: &str = "pending_sweep";
+
+/// How many monitor updates are saved on their own before the full monitor is
+/// written again and the updates are deleted. Keeps writes small for busy channels
+/// without making the chain of updates to replay on startup too long.
+const MAX_PENDING_MONITOR_UPDATES: u64 = 100;
+/// The longest to wait between attempts to save a monitor update durably
+const MAX_MONITOR_UPDATE_RETRY_MS: i32 = 30_000;
 
 pub(crate) type PhantomChannelManager<S: MutinyStorage> = LdkChannelManager<
     Arc<ChainMonitor<S>>,
@@ -51,15 +75,47 @@ pub(crate) type PhantomChannelManager<S: MutinyStorage> = LdkChannelManager<
     Arc<PhantomKeysManager<S>>,
     Arc<PhantomKeysManager<S>>,
     Arc<MutinyFeeEstimator<S>>,
-    Arc<Router>,
+    Arc<Router<S>>,
     Arc<MutinyLogger>,
 >;
 
+/// An encoded channel monitor or monitor update saved along with a checksum, so data that was
+/// corrupted can be told apart from data that is missing or can't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChecksummedBytes {
+    Checked {
+        checksum: String,
+        data: Vec<u8>,
+    },
+    /// Saved before checksums were added
+    Unchecked(Vec<u8>),
+}
+
+impl ChecksummedBytes {
+    pub(crate) fn new(data: Vec<u8>) -> Self {
+        Self::Checked {
+            checksum: checksum(&data).to_hex(),
+            data,
+        }
+    }
+
+    /// The encoded data, without checking it
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::Checked { data, .. } => data,
+            Self::Unchecked(data) => data,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct MutinyNodePersister<S: MutinyStorage> {
     node_id: String,
     pub(crate) storage: S,
     manager_version: Arc<AtomicU32>,
+    /// Told when a monitor update saved in the background is durable
+    chain_monitor: Arc<utils::Mutex<Option<Arc<ChainMonitor<S>>>>>,
     logger: Arc<MutinyLogger>,
 }
 
@@ -75,10 +131,19 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             node_id,
             storage,
             manager_version: Arc::new(AtomicU32::new(0)),
+            chain_monitor: Arc::new(utils::Mutex::new(None)),
             logger,
         }
     }
 
+    /// Sets the chain monitor to tell when monitor updates are durable.
+    /// Until it is set, monitor updates are reported as completed once written.
+    pub(crate) fn set_chain_monitor(&self, chain_monitor: Arc<ChainMonitor<S>>) {
+        if let Ok(mut lock) = self.chain_monitor.lock() {
+            *lock = Some(chain_monitor);
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn manager_version(&self) -> u32 {
         self.manager_version.load(Ordering::Relaxed)
@@ -88,15 +153,15 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         format!("{}_{}", key, self.node_id)
     }
 
-    fn persist_local_storage<W: Writeable>(
+    fn persist_local_storage<T: Serialize>(
         &self,
         key: &str,
-        object: &W,
+        value: T,
         version: Option<u32>,
     ) -> Result<(), lightning::io::Error> {
         let key_with_node = self.get_key(key);
         self.storage
-            .set_data(key_with_node, object.encode(), version)
+            .set_data(key_with_node, value, version)
             .map_err(|e| {
                 match e {
                     MutinyError::PersistenceFailed { source } => {
@@ -123,38 +188,305 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         }
     }
 
-    pub fn read_channel_monitors(
+    /// Checks the data saved under the key matches its checksum. Data saved
+    /// before checksums were added can't be checked and is returned as is.
+    fn verify_checksum(
         &self,
-        keys_manager: Arc<PhantomKeysManager<S>>,
-    ) -> Result<Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>, io::Error> {
+        key: &str,
+        data: Vec<u8>,
+        expected: Option<&str>,
+    ) -> Result<Vec<u8>, MutinyError> {
+        let Some(expected) = expected else {
+            return Ok(data);
+        };
+        let actual = checksum(&data).to_hex();
+        if actual != expected {
+            log_error!(
+                self.logger,
+                "Checksum mismatch for {key}: expected {expected}, got {actual} for {} bytes",
+                data.len()
+            );
+            return Err(MutinyError::CorruptedChannelState {
+                key: key.to_string(),
+            });
+        }
+
+        Ok(data)
+    }
+
+    /// Reads the channel monitors for this node and replays any monitor updates
+    /// that were saved after each monitor was last written in full.
+    pub fn read_channel_monitors<K, B, F>(
+        &self,
+        keys_manager: Arc<K>,
+        broadcaster: &B,
+        fee_estimator: &F,
+    ) -> Result<Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>, MutinyError>
+    where
+        K: EntropySource + SignerProvider<Signer = InMemorySigner>,
+        B: BroadcasterInterface,
+        F: FeeEstimator,
+    {
+        let channel_monitor_list = self.read_monitor_bytes()?;
+
+        let mut res = Vec::with_capacity(channel_monitor_list.len());
+        for (key, data) in channel_monitor_list {
+            let mut buffer = Cursor::new(data);
+            let monitor = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+                &mut buffer,
+                (keys_manager.as_ref(), keys_manager.as_ref()),
+            )
+            .map_err(|e| {
+                MutinyError::read_err(MutinyStorageError::Other(anyhow!(
+                    "Failed to deserialize ChannelMonitor {key}: {e}"
+                )))
+            })?;
+            res.push(monitor);
+        }
+
+        for (_, monitor) in res.iter() {
+            let funding_txo = monitor.get_funding_txo().0;
+            let updates = monitor_updates_to_apply(
+                monitor.get_latest_update_id(),
+                self.read_monitor_updates(&funding_txo)?,
+                |u| u.update_id,
+            )
+            .map_err(|missing| {
+                log_error!(
+                    self.logger,
+                    "ChannelMonitorUpdate {missing} for {funding_txo} is missing"
+                );
+                MutinyError::CorruptedChannelState {
+                    key: self.get_key(&monitor_update_key(&funding_txo, missing)),
+                }
+            })?;
+            for update in updates {
+                monitor
+                    .update_monitor(&update, &broadcaster, fee_estimator, &self.logger)
+                    .map_err(|_| {
+                        MutinyError::read_err(MutinyStorageError::Other(anyhow!(
+                            "Failed to apply ChannelMonitorUpdate {}",
+                            update.update_id
+                        )))
+                    })?;
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// The encoded channel monitors for this node by key, checked against their checksums
+    fn read_monitor_bytes(&self) -> Result<HashMap<String, Vec<u8>>, MutinyError> {
         // Get all the channel monitor buffers that exist for this node
         let suffix = self.node_id.as_str();
-        let channel_monitor_list: HashMap<String, Vec<u8>> = self
-            .storage
-            .scan(MONITORS_PREFIX_KEY, Some(suffix))
-            .map_err(|_| io::ErrorKind::Other)?;
-
-        let res =
-            channel_monitor_list
-                .into_iter()
-                .try_fold(Vec::new(), |mut accum, (_, data)| {
-                    let mut buffer = Cursor::new(data);
-                    match <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
-                        &mut buffer,
-                        (keys_manager.as_ref(), keys_manager.as_ref()),
-                    ) {
-                        Ok((blockhash, channel_monitor)) => {
-                            accum.push((blockhash, channel_monitor));
-                            Ok(accum)
-                        }
-                        Err(e) => Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            format!("Failed to deserialize ChannelMonitor: {e}"),
-                        )),
+        let channel_monitor_list: HashMap<String, ChecksummedBytes> =
+            self.storage.scan(MONITORS_PREFIX_KEY, Some(suffix))?;
+
+        channel_monitor_list
+            .into_iter()
+            .map(|(key, value)| {
+                let data = match value {
+                    ChecksummedBytes::Checked { checksum, data } => {
+                        self.verify_checksum(&key, data, Some(&checksum))?
                     }
-                })?;
+                    ChecksummedBytes::Unchecked(data) => data,
+                };
+                Ok((key, data))
+            })
+            .collect()
+    }
 
-        Ok(res)
+    /// The monitor updates saved for a channel since its monitor was last written in full,
+    /// sorted by update id
+    fn read_monitor_updates(
+        &self,
+        funding_txo: &OutPoint,
+    ) -> Result<Vec<ChannelMonitorUpdate>, MutinyError> {
+        let prefix = format!("{}_", monitor_updates_prefix(funding_txo));
+        let update_list: HashMap<String, ChecksummedBytes> =
+            self.storage.scan(&prefix, Some(self.node_id.as_str()))?;
+
+        let mut updates = update_list
+            .into_iter()
+            .map(|(key, value)| {
+                let data = match value {
+                    ChecksummedBytes::Checked { checksum, data } => {
+                        self.verify_checksum(&key, data, Some(&checksum))?
+                    }
+                    ChecksummedBytes::Unchecked(data) => data,
+                };
+                ChannelMonitorUpdate::read(&mut Cursor::new(data)).map_err(|e| {
+                    log_error!(
+                        self.logger,
+                        "Failed to deserialize ChannelMonitorUpdate {key}: {e}"
+                    );
+                    MutinyError::CorruptedChannelState { key }
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        updates.sort_by_key(|u| u.update_id);
+
+        Ok(updates)
+    }
+
+    /// Writes the channel monitor in full and deletes the monitor updates it contains,
+    /// so they don't have to be replayed on the next startup.
+    pub(crate) fn compact_monitor_updates<ChannelSigner: WriteableEcdsaChannelSigner>(
+        &self,
+        monitor: &ChannelMonitor<ChannelSigner>,
+    ) -> Result<(), MutinyError> {
+        let funding_txo = monitor.get_funding_txo().0;
+        if self.monitor_update_keys(&funding_txo)?.is_empty() {
+            return Ok(());
+        }
+
+        self.persist_monitor(&funding_txo, monitor).map_err(|_| {
+            MutinyError::PersistenceFailed {
+                source: MutinyStorageError::Other(anyhow!("failed to persist channel monitor")),
+            }
+        })?;
+        self.delete_monitor_updates(&funding_txo)
+    }
+
+    fn monitor_update_keys(&self, funding_txo: &OutPoint) -> Result<Vec<String>, MutinyError> {
+        let prefix = format!("{}_", monitor_updates_prefix(funding_txo));
+        self.storage.scan_keys(&prefix, Some(self.node_id.as_str()))
+    }
+
+    /// Deletes the saved monitor updates of a channel, only call after its monitor
+    /// has been written in full
+    fn delete_monitor_updates(&self, funding_txo: &OutPoint) -> Result<(), MutinyError> {
+        let keys = self.monitor_update_keys(funding_txo)?;
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        self.storage.delete(&keys)?;
+        log_debug!(
+            self.logger,
+            "Compacted {} monitor updates for channel {}",
+            keys.len(),
+            funding_txo.txid.to_hex()
+        );
+
+        Ok(())
+    }
+
+    fn persist_monitor<ChannelSigner: WriteableEcdsaChannelSigner>(
+        &self,
+        funding_txo: &OutPoint,
+        monitor: &ChannelMonitor<ChannelSigner>,
+    ) -> Result<(), lightning::io::Error> {
+        self.persist_monitor_bytes(
+            funding_txo,
+            monitor.get_latest_update_id(),
+            monitor.encode(),
+        )
+    }
+
+    fn persist_monitor_bytes(
+        &self,
+        funding_txo: &OutPoint,
+        update_id: u64,
+        bytes: Vec<u8>,
+    ) -> Result<(), lightning::io::Error> {
+        let key = monitor_key(funding_txo);
+        let version = monitor_version(update_id);
+        self.persist_local_storage(&key, ChecksummedBytes::new(bytes), Some(version))
+    }
+
+    fn persist_monitor_update_bytes(
+        &self,
+        funding_txo: &OutPoint,
+        update_id: u64,
+        bytes: Vec<u8>,
+    ) -> Result<(), lightning::io::Error> {
+        let key = monitor_update_key(funding_txo, update_id);
+        let version = monitor_version(update_id);
+        self.persist_local_storage(&key, ChecksummedBytes::new(bytes), Some(version))
+    }
+
+    /// Saves a monitor update on its own. Monitor updates are only in indexed db,
+    /// so LDK is told the update completed once it is saved there and to VSS.
+    /// Until then the channel waits, if saving fails it is retried in the background.
+    fn persist_monitor_update(
+        &self,
+        funding_txo: OutPoint,
+        update: &ChannelMonitorUpdate,
+        monitor_update_id: MonitorUpdateId,
+    ) -> chain::ChannelMonitorUpdateStatus {
+        let chain_monitor = self.chain_monitor.lock().ok().and_then(|c| c.clone());
+        let Some(chain_monitor) = chain_monitor else {
+            return match self.persist_monitor_update_bytes(
+                &funding_txo,
+                update.update_id,
+                update.encode(),
+            ) {
+                Ok(()) => chain::ChannelMonitorUpdateStatus::Completed,
+                Err(_) => chain::ChannelMonitorUpdateStatus::PermanentFailure,
+            };
+        };
+
+        let bytes = ChecksummedBytes::new(update.encode());
+        let key = monitor_update_key(&funding_txo, update.update_id);
+        let vss_item = match self.storage.vss_client() {
+            Some(vss) => match serde_json::to_value(&bytes) {
+                Ok(value) => Some((
+                    vss,
+                    VssKeyValueItem {
+                        key: self.get_key(&key),
+                        value,
+                        version: monitor_version(update.update_id),
+                    },
+                )),
+                Err(_) => return chain::ChannelMonitorUpdateStatus::PermanentFailure,
+            },
+            None => None,
+        };
+
+        // saved without a version so it isn't uploaded in the background,
+        // it is uploaded below before telling LDK
+        if self.persist_local_storage(&key, bytes, None).is_err() {
+            return chain::ChannelMonitorUpdateStatus::PermanentFailure;
+        }
+
+        let storage = self.storage.clone();
+        let logger = self.logger.clone();
+        let update_id = update.update_id;
+        utils::spawn(async move {
+            let mut attempts = 0;
+            loop {
+                let res = async {
+                    storage.flush().await?;
+                    if let Some((vss, item)) = vss_item.as_ref() {
+                        vss.put_objects(vec![item.clone()]).await?;
+                    }
+                    Ok::<(), MutinyError>(())
+                }
+                .await;
+                match res {
+                    Ok(()) => break,
+                    Err(e) => {
+                        attempts += 1;
+                        log_error!(
+                            logger,
+                            "Failed to save monitor update {update_id}, attempt {attempts}: {e}"
+                        );
+                        let wait = 1_000i32.saturating_mul(attempts);
+                        utils::sleep(wait.min(MAX_MONITOR_UPDATE_RETRY_MS)).await;
+                    }
+                }
+            }
+
+            if let Err(e) = chain_monitor.channel_monitor_updated(funding_txo, monitor_update_id) {
+                log_error!(
+                    logger,
+                    "Failed to complete monitor update {update_id}: {e:?}"
+                );
+            }
+        });
+
+        chain::ChannelMonitorUpdateStatus::InProgress
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -166,66 +498,84 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
         mutiny_logger: Arc<MutinyLogger>,
         keys_manager: Arc<PhantomKeysManager<S>>,
-        router: Arc<Router>,
+        router: Arc<Router<S>>,
+        user_config: UserConfig,
         channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
         esplora: &MultiEsploraClient,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         log_debug!(mutiny_logger, "Reading channel manager from storage");
+        let Some((bytes, version)) = self.read_manager_bytes()? else {
+            // no key manager stored, start a new one
+            return Self::create_new_channel_manager(
+                network,
+                chain_monitor,
+                mutiny_chain,
+                fee_estimator,
+                mutiny_logger,
+                keys_manager,
+                router,
+                user_config,
+                channel_monitors,
+                esplora,
+            )
+            .await;
+        };
+
+        let res = Self::parse_channel_manager(
+            bytes,
+            chain_monitor,
+            mutiny_chain,
+            fee_estimator,
+            mutiny_logger,
+            keys_manager,
+            router,
+            user_config,
+            channel_monitors,
+        )?;
+        if let Some(version) = version {
+            self.manager_version.swap(version, Ordering::Relaxed);
+        }
+
+        Ok(res)
+    }
+
+    /// The encoded channel manager checked against its checksum, and its version.
+    /// Managers saved in the old encoding have no version.
+    fn read_manager_bytes(&self) -> Result<Option<(Vec<u8>, Option<u32>)>, MutinyError> {
         let key = self.get_key(CHANNEL_MANAGER_KEY);
         match self.storage.get_data::<VersionedValue>(&key) {
             Ok(Some(versioned_value)) => {
                 // new encoding is in hex
-                let hex: String = serde_json::from_value(versioned_value.value.clone())?;
+                let hex: String = serde_json::from_value(versioned_value.value)?;
                 let bytes = FromHex::from_hex(&hex)?;
-                let res = Self::parse_channel_manager(
-                    bytes,
-                    chain_monitor,
-                    mutiny_chain,
-                    fee_estimator,
-                    mutiny_logger,
-                    keys_manager,
-                    router,
-                    channel_monitors,
-                )?;
-
-                self.manager_version
-                    .swap(versioned_value.version, Ordering::Relaxed);
-
-                Ok(res)
-            }
-            Ok(None) => {
-                // no key manager stored, start a new one
-
-                Self::create_new_channel_manager(
-                    network,
-                    chain_monitor,
-                    mutiny_chain,
-                    fee_estimator,
-                    mutiny_logger,
-                    keys_manager,
-                    router,
-                    channel_monitors,
-                    esplora,
-                )
-                .await
+                let bytes =
+                    self.verify_checksum(&key, bytes, versioned_value.checksum.as_deref())?;
+                Ok(Some((bytes, Some(versioned_value.version))))
             }
+            Ok(None) => Ok(None),
             Err(_) => {
                 // old encoding with no version number and as an array of numbers
                 let bytes = self.read_value(CHANNEL_MANAGER_KEY)?;
-                Self::parse_channel_manager(
-                    bytes,
-                    chain_monitor,
-                    mutiny_chain,
-                    fee_estimator,
-                    mutiny_logger,
-                    keys_manager,
-                    router,
-                    channel_monitors,
-                )
+                Ok(Some((bytes, None)))
             }
         }
     }
 
+    /// Saves the encoded channel manager under the next version
+    fn persist_manager_bytes(&self, bytes: &[u8]) -> Result<(), MutinyError> {
+        let old = self.manager_version.fetch_add(1, Ordering::Relaxed);
+        let version = old + 1;
+        let key = self.get_key(CHANNEL_MANAGER_KEY);
+
+        let value = VersionedValue {
+            version,
+            value: serde_json::to_value(bytes.to_hex()).unwrap(),
+            checksum: Some(checksum(bytes).to_hex()),
+        };
+
+        self.storage.set_data(key, value, Some(version))
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn parse_channel_manager(
         bytes: Vec<u8>,
@@ -234,7 +584,8 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
         mutiny_logger: Arc<MutinyLogger>,
         keys_manager: Arc<PhantomKeysManager<S>>,
-        router: Arc<Router>,
+        router: Arc<Router<S>>,
+        user_config: UserConfig,
         mut channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         let mut channel_monitor_mut_references = Vec::new();
@@ -250,17 +601,15 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             mutiny_chain,
             router,
             mutiny_logger,
-            default_user_config(),
+            user_config,
             channel_monitor_mut_references,
         );
         let mut readable_kv_value = Cursor::new(bytes);
-        let Ok((_, channel_manager)) =
+        let (_, channel_manager) =
             <(BlockHash, PhantomChannelManager<S>)>::read(&mut readable_kv_value, read_args)
-        else {
-            return Err(MutinyError::ReadError {
-                source: MutinyStorageError::Other(anyhow!("could not read manager")),
-            });
-        };
+                .map_err(|e| MutinyError::ReadError {
+                    source: MutinyStorageError::Other(anyhow!("could not read manager: {e}")),
+                })?;
         Ok(ReadChannelManager {
             channel_manager,
             is_restarting: true,
@@ -276,7 +625,8 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
         mutiny_logger: Arc<MutinyLogger>,
         keys_manager: Arc<PhantomKeysManager<S>>,
-        router: Arc<Router>,
+        router: Arc<Router<S>>,
+        user_config: UserConfig,
         channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
         esplora: &MultiEsploraClient,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
@@ -308,7 +658,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             keys_manager.clone(),
             keys_manager.clone(),
             keys_manager,
-            default_user_config(),
+            user_config,
             chain_params,
             utils::now().as_secs() as u32,
         );
@@ -328,8 +678,19 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
     ) -> io::Result<()> {
         let key = self.get_key(payment_key(inbound, payment_hash).as_str());
         self.storage
-            .set_data(key, payment_info, None)
-            .map_err(io::Error::other)
+            .set_data_binary(key, payment_info, None)
+            .map_err(io::Error::other)?;
+
+        // only paid invoices show up in the activity
+        if payment_info.status == HTLCStatus::Succeeded {
+            self.storage
+                .index_activity(
+                    payment_activity(payment_hash, inbound),
+                    payment_info.last_update,
+                )
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
     }
 
     pub(crate) fn read_payment_info(
@@ -368,6 +729,28 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             .collect())
     }
 
+    /// Remembers this node saved a phantom invoice, so whichever of our nodes
+    /// receives the payment can update it
+    pub(crate) fn persist_phantom_invoice_owner(
+        &self,
+        payment_hash: &PaymentHash,
+    ) -> Result<(), MutinyError> {
+        let key = format!("{PHANTOM_INVOICE_PREFIX}{}", payment_hash.0.to_hex());
+        self.storage.set_data(key, &self.node_id, None)
+    }
+
+    /// The persister of the other node that saved the phantom invoice for a payment
+    pub(crate) fn phantom_invoice_owner(
+        &self,
+        payment_hash: &PaymentHash,
+    ) -> Result<Option<Self>, MutinyError> {
+        let key = format!("{PHANTOM_INVOICE_PREFIX}{}", payment_hash.0.to_hex());
+        let node_id: Option<String> = self.storage.get_data(key)?;
+        Ok(node_id
+            .filter(|id| id != &self.node_id)
+            .map(|id| Self::new(id, self.storage.clone(), self.logger.clone())))
+    }
+
     pub(crate) fn persist_channel_closure(
         &self,
         user_channel_id: u128,
@@ -377,7 +760,27 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             "{CHANNEL_CLOSURE_PREFIX}{}",
             user_channel_id.to_be_bytes().to_hex()
         ));
+        let timestamp = closure.timestamp;
         self.storage.set_data(key, closure, None)?;
+        self.storage
+            .index_activity(ActivityRef::ChannelClosed(user_channel_id), timestamp)
+    }
+
+    /// Adds this node's paid invoices and channel closures to the activity
+    /// index, for wallets that had activity before the index existed.
+    pub(crate) fn index_existing_activity(&self) -> Result<(), MutinyError> {
+        for inbound in [true, false] {
+            for (hash, info) in self.list_payment_info(inbound)? {
+                if info.status == HTLCStatus::Succeeded {
+                    self.storage
+                        .index_activity(payment_activity(&hash, inbound), info.last_update)?;
+                }
+            }
+        }
+        for (id, closure) in self.list_channel_closures()? {
+            self.storage
+                .index_activity(ActivityRef::ChannelClosed(id), closure.timestamp)?;
+        }
         Ok(())
     }
 
@@ -413,6 +816,142 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             .collect())
     }
 
+    pub(crate) fn get_payment_htlcs(
+        &self,
+        payment_hash: &PaymentHash,
+    ) -> Result<Vec<PaymentHtlc>, MutinyError> {
+        let key = self.get_key(&format!(
+            "{PAYMENT_HTLCS_PREFIX}{}",
+            payment_hash.0.to_hex()
+        ));
+        let htlcs: Option<Vec<PaymentHtlc>> = self.storage.get_data(key)?;
+        Ok(htlcs.unwrap_or_default())
+    }
+
+    fn persist_payment_htlcs(
+        &self,
+        payment_hash: &PaymentHash,
+        htlcs: Vec<PaymentHtlc>,
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(&format!(
+            "{PAYMENT_HTLCS_PREFIX}{}",
+            payment_hash.0.to_hex()
+        ));
+        self.storage.set_data_binary(key, htlcs, None)
+    }
+
+    /// Adds a new HTLC to the payment's timeline
+    pub(crate) fn add_payment_htlc(
+        &self,
+        payment_hash: &PaymentHash,
+        htlc: PaymentHtlc,
+    ) -> Result<(), MutinyError> {
+        let mut htlcs = self.get_payment_htlcs(payment_hash)?;
+        htlcs.push(htlc);
+        self.persist_payment_htlcs(payment_hash, htlcs)
+    }
+
+    /// Moves all the unresolved HTLCs of the payment in the given direction to the new state
+    pub(crate) fn update_payment_htlcs(
+        &self,
+        payment_hash: &PaymentHash,
+        inbound: bool,
+        state: HtlcState,
+    ) -> Result<(), MutinyError> {
+        let mut htlcs = self.get_payment_htlcs(payment_hash)?;
+        if htlcs.is_empty() {
+            return Ok(());
+        }
+
+        let now = utils::now().as_secs();
+        htlcs
+            .iter_mut()
+            .filter(|h| h.inbound == inbound)
+            .for_each(|h| h.update(state, now));
+        self.persist_payment_htlcs(payment_hash, htlcs)
+    }
+
+    /// The inbound HTLCs we received over a channel that haven't been claimed
+    /// or failed back yet
+    pub(crate) fn list_unresolved_inbound_htlcs(
+        &self,
+        channel_id: &str,
+    ) -> Result<Vec<(PaymentHash, PaymentHtlc)>, MutinyError> {
+        let suffix = format!("_{}", self.node_id);
+        let map: HashMap<String, Vec<PaymentHtlc>> =
+            self.storage.scan(PAYMENT_HTLCS_PREFIX, Some(&suffix))?;
+
+        let mut unresolved = vec![];
+        for (key, htlcs) in map {
+            let payment_hash_str = key
+                .trim_start_matches(PAYMENT_HTLCS_PREFIX)
+                .trim_end_matches(&suffix);
+            let hash: [u8; 32] =
+                FromHex::from_hex(payment_hash_str).expect("key should be a sha256 hash");
+            unresolved.extend(
+                htlcs
+                    .into_iter()
+                    .filter(|h| {
+                        h.inbound && !h.state.is_final() && h.channel.as_deref() == Some(channel_id)
+                    })
+                    .map(|h| (PaymentHash(hash), h)),
+            );
+        }
+
+        Ok(unresolved)
+    }
+
+    /// Drops the full details of payments and channel closures last updated
+    /// before the cutoff, keeping a summary of each.
+    ///
+    /// Payments lose their HTLC timeline and received payments their invoice,
+    /// channel closures lose their force close postmortem. Pending payments are
+    /// never pruned and sent payments keep their invoice for payment proofs.
+    pub(crate) fn prune_details(&self, cutoff: u64) -> Result<PruneSummary, MutinyError> {
+        let mut summary = PruneSummary::default();
+
+        for inbound in [true, false] {
+            for (payment_hash, mut info) in self.list_payment_info(inbound)? {
+                let is_final = matches!(info.status, HTLCStatus::Succeeded | HTLCStatus::Failed);
+                if !is_final || info.last_update >= cutoff {
+                    continue;
+                }
+
+                let htlcs_key = self.get_key(&format!(
+                    "{PAYMENT_HTLCS_PREFIX}{}",
+                    payment_hash.0.to_hex()
+                ));
+                let had_htlcs = !self.get_payment_htlcs(&payment_hash)?.is_empty();
+                if had_htlcs {
+                    self.storage.delete(&[htlcs_key])?;
+                }
+
+                // sent payments keep their invoice for payment proofs
+                if inbound && info.prune_details() {
+                    self.persist_payment_info(&payment_hash, &info, inbound)
+                        .map_err(|_| MutinyError::PersistenceFailed {
+                            source: MutinyStorageError::Other(anyhow!(
+                                "failed to persist pruned payment info"
+                            )),
+                        })?;
+                    summary.payments += 1;
+                } else if had_htlcs {
+                    summary.payments += 1;
+                }
+            }
+        }
+
+        for (user_channel_id, mut closure) in self.list_channel_closures()? {
+            if closure.timestamp < cutoff && closure.postmortem.is_some() {
+                closure.postmortem = None;
+                self.persist_channel_closure(user_channel_id, closure)?;
+                summary.channel_closures += 1;
+            }
+        }
+
+        Ok(summary)
+    }
+
     /// Persists the failed spendable outputs to storage.
     /// Previously failed spendable outputs are not overwritten.
     ///
@@ -492,6 +1031,72 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         Ok(())
     }
 
+    /// Adds spendable outputs to the batch waiting to be swept together.
+    pub(crate) fn queue_pending_sweep(
+        &self,
+        outputs: Vec<SpendableOutputDescriptor>,
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(PENDING_SWEEP_KEY);
+        let mut pending: PendingSweep =
+            self.storage
+                .get_data(&key)?
+                .unwrap_or_else(|| PendingSweep {
+                    first_queued_at: utils::now().as_secs(),
+                    descriptors: vec![],
+                });
+
+        pending
+            .descriptors
+            .extend(outputs.into_iter().map(|desc| desc.encode().to_hex()));
+
+        self.storage.set_data(key, pending, None)
+    }
+
+    /// Retrieves the spendable outputs waiting to be swept, along with
+    /// when the first of them was queued.
+    pub(crate) fn get_pending_sweep(
+        &self,
+    ) -> Result<Option<(u64, Vec<SpendableOutputDescriptor>)>, MutinyError> {
+        let key = self.get_key(PENDING_SWEEP_KEY);
+        let pending: Option<PendingSweep> = self.storage.get_data(&key)?;
+        let Some(pending) = pending else {
+            return Ok(None);
+        };
+
+        let mut descriptors = vec![];
+        for desc in pending.descriptors {
+            let bytes = Vec::from_hex(&desc).map_err(|_| MutinyError::ReadError {
+                source: MutinyStorageError::Other(anyhow!("failed to decode descriptor {desc}")),
+            })?;
+            let descriptor = SpendableOutputDescriptor::read(&mut Cursor::new(bytes))?;
+            descriptors.push(descriptor);
+        }
+
+        Ok(Some((pending.first_queued_at, descriptors)))
+    }
+
+    /// Removes outputs that were swept from the ones waiting to be swept.
+    /// Outputs queued since the sweep started are kept for the next one.
+    pub(crate) fn remove_pending_sweep(
+        &self,
+        swept: &[SpendableOutputDescriptor],
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(PENDING_SWEEP_KEY);
+        let pending: Option<PendingSweep> = self.storage.get_data(&key)?;
+        let Some(mut pending) = pending else {
+            return Ok(());
+        };
+
+        let swept: Vec<String> = swept.iter().map(|desc| desc.encode().to_hex()).collect();
+        pending.descriptors.retain(|desc| !swept.contains(desc));
+
+        if pending.descriptors.is_empty() {
+            self.storage.delete(&[key])
+        } else {
+            self.storage.set_data(key, pending, None)
+        }
+    }
+
     pub(crate) fn persist_channel_open_params(
         &self,
         id: u128,
@@ -515,10 +1120,110 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
     }
 }
 
+fn monitor_key(funding_txo: &OutPoint) -> String {
+    format!(
+        "{MONITORS_PREFIX_KEY}{}_{}",
+        funding_txo.txid.to_hex(),
+        funding_txo.index
+    )
+}
+
+fn monitor_updates_prefix(funding_txo: &OutPoint) -> String {
+    format!(
+        "{MONITOR_UPDATES_PREFIX_KEY}{}_{}",
+        funding_txo.txid.to_hex(),
+        funding_txo.index
+    )
+}
+
+fn monitor_update_key(funding_txo: &OutPoint, update_id: u64) -> String {
+    format!("{}_{update_id}", monitor_updates_prefix(funding_txo))
+}
+
+/// The monitor updates in VSS that the channel monitor saved there already contains.
+/// They are deleted locally when the monitor is written in full, this finds them
+/// so they can be deleted from VSS too, once the full monitor has been uploaded.
+pub fn stale_vss_monitor_updates(keys: &[KeyVersion]) -> Vec<KeyVersion> {
+    let monitor_versions: HashMap<&str, u32> = keys
+        .iter()
+        .filter(|kv| kv.key.starts_with(MONITORS_PREFIX_KEY))
+        .map(|kv| (kv.key.as_str(), kv.version))
+        .collect();
+
+    keys.iter()
+        .filter(|kv| {
+            // monitor_updates/{txid}_{index}_{update_id}_{node_id} belongs to
+            // monitors/{txid}_{index}_{node_id}
+            let Some(rest) = kv.key.strip_prefix(MONITOR_UPDATES_PREFIX_KEY) else {
+                return false;
+            };
+            let parts: Vec<&str> = rest.splitn(4, '_').collect();
+            let [txid, index, _, node_id] = parts[..] else {
+                return false;
+            };
+            let monitor = format!("{MONITORS_PREFIX_KEY}{txid}_{index}_{node_id}");
+            monitor_versions
+                .get(monitor.as_str())
+                .is_some_and(|version| kv.version <= *version)
+        })
+        .cloned()
+        .collect()
+}
+
+/// The storage version for a monitor or monitor update, safely converting the u64 update id
+fn monitor_version(update_id: u64) -> u32 {
+    if update_id >= u32::MAX as u64 {
+        u32::MAX
+    } else {
+        update_id as u32
+    }
+}
+
+/// The saved updates a monitor at `latest_update_id` still needs, in order. Updates
+/// already in the monitor can be left over from a failed cleanup and are skipped.
+/// LDK panics on an update that skips ahead, so a gap fails with the first missing id.
+fn monitor_updates_to_apply<U>(
+    latest_update_id: u64,
+    updates: Vec<U>,
+    update_id: impl Fn(&U) -> u64,
+) -> Result<Vec<U>, u64> {
+    let mut next_id = latest_update_id.saturating_add(1);
+    let mut res = Vec::with_capacity(updates.len());
+    for update in updates {
+        let id = update_id(&update);
+        if id <= latest_update_id {
+            continue;
+        }
+        // the final update of a closed channel can follow any update
+        if id != next_id && id != CLOSED_CHANNEL_UPDATE_ID {
+            return Err(next_id);
+        }
+        next_id = id.saturating_add(1);
+        res.push(update);
+    }
+
+    Ok(res)
+}
+
+/// Whether a monitor update should be saved on its own instead of writing the full monitor.
+/// Every [MAX_PENDING_MONITOR_UPDATES] updates the full monitor is written so the chain
+/// of updates stays short, and the final update when a channel closes is never saved alone.
+fn persist_update_only(update_id: u64) -> bool {
+    update_id != CLOSED_CHANNEL_UPDATE_ID && update_id % MAX_PENDING_MONITOR_UPDATES != 0
+}
+
 fn channel_open_params_key(id: u128) -> String {
     format!("{CHANNEL_OPENING_PARAMS_PREFIX}{id}")
 }
 
+/// Spendable outputs that are waiting to be swept in a single transaction
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PendingSweep {
+    first_queued_at: u64,
+    /// The encoded [SpendableOutputDescriptor]s
+    descriptors: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) struct ChannelOpenParams {
     pub(crate) sats_per_vbyte: f32,
@@ -574,6 +1279,13 @@ fn payment_key(inbound: bool, payment_hash: &PaymentHash) -> String {
     }
 }
 
+fn payment_activity(payment_hash: &PaymentHash, inbound: bool) -> ActivityRef {
+    ActivityRef::Lightning {
+        payment_hash: sha256::Hash::from_inner(payment_hash.0),
+        inbound,
+    }
+}
+
 impl<S: MutinyStorage>
     Persister<
         '_,
@@ -583,7 +1295,7 @@ impl<S: MutinyStorage>
         Arc<PhantomKeysManager<S>>,
         Arc<PhantomKeysManager<S>>,
         Arc<MutinyFeeEstimator<S>>,
-        Arc<Router>,
+        Arc<Router<S>>,
         Arc<MutinyLogger>,
         utils::Mutex<ProbScorer>,
     > for MutinyNodePersister<S>
@@ -592,23 +1304,13 @@ impl<S: MutinyStorage>
         &self,
         channel_manager: &PhantomChannelManager<S>,
     ) -> Result<(), lightning::io::Error> {
-        let old = self.manager_version.fetch_add(1, Ordering::Relaxed);
-        let version = old + 1;
-        let key = self.get_key(CHANNEL_MANAGER_KEY);
-
-        let value = VersionedValue {
-            version,
-            value: serde_json::to_value(channel_manager.encode().to_hex()).unwrap(),
-        };
-
-        self.storage
-            .set_data(key, value, Some(version))
+        self.persist_manager_bytes(&channel_manager.encode())
             .map_err(|_| lightning::io::ErrorKind::Other.into())
     }
 
     fn persist_graph(&self, network_graph: &NetworkGraph) -> Result<(), lightning::io::Error> {
         self.storage
-            .set_data(NETWORK_GRAPH_KEY, network_graph.encode().to_hex(), None)
+            .set_data_compressed(NETWORK_GRAPH_KEY, Bytes(network_graph.encode()), None)
             .map_err(|_| lightning::io::ErrorKind::Other.into())
     }
 
@@ -616,9 +1318,8 @@ impl<S: MutinyStorage>
         &self,
         scorer: &utils::Mutex<ProbScorer>,
     ) -> Result<(), lightning::io::Error> {
-        let scorer_str = scorer.encode().to_hex();
         self.storage
-            .set_data(PROB_SCORER_KEY, scorer_str, None)
+            .set_data_compressed(PROB_SCORER_KEY, Bytes(scorer.encode()), None)
             .map_err(|_| lightning::io::ErrorKind::Other.into())
     }
 }
@@ -632,20 +1333,7 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, S: MutinyStorage> Persist<Chann
         monitor: &ChannelMonitor<ChannelSigner>,
         _update_id: MonitorUpdateId,
     ) -> chain::ChannelMonitorUpdateStatus {
-        let key = format!(
-            "{MONITORS_PREFIX_KEY}{}_{}",
-            funding_txo.txid.to_hex(),
-            funding_txo.index
-        );
-        // safely convert u64 to u32
-        let update_id = monitor.get_latest_update_id();
-        let version = if update_id >= u32::MAX as u64 {
-            u32::MAX
-        } else {
-            update_id as u32
-        };
-
-        match self.persist_local_storage(&key, monitor, Some(version)) {
+        match self.persist_monitor(&funding_txo, monitor) {
             Ok(()) => chain::ChannelMonitorUpdateStatus::Completed,
             Err(_) => chain::ChannelMonitorUpdateStatus::PermanentFailure,
         }
@@ -654,27 +1342,25 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, S: MutinyStorage> Persist<Chann
     fn update_persisted_channel(
         &self,
         funding_txo: OutPoint,
-        _update: Option<&ChannelMonitorUpdate>,
+        update: Option<&ChannelMonitorUpdate>,
         monitor: &ChannelMonitor<ChannelSigner>,
-        _update_id: MonitorUpdateId,
+        update_id: MonitorUpdateId,
     ) -> chain::ChannelMonitorUpdateStatus {
-        let key = format!(
-            "{MONITORS_PREFIX_KEY}{}_{}",
-            funding_txo.txid.to_hex(),
-            funding_txo.index
-        );
-        // safely convert u64 to u32
-        let update_id = monitor.get_latest_update_id();
-        let version = if update_id >= u32::MAX as u64 {
-            u32::MAX
-        } else {
-            update_id as u32
-        };
+        // most updates are only saved on their own, they are replayed onto the monitor on startup
+        if let Some(update) = update.filter(|u| persist_update_only(u.update_id)) {
+            return self.persist_monitor_update(funding_txo, update, update_id);
+        }
 
-        match self.persist_local_storage(&key, monitor, Some(version)) {
-            Ok(()) => chain::ChannelMonitorUpdateStatus::Completed,
-            Err(_) => chain::ChannelMonitorUpdateStatus::PermanentFailure,
+        if self.persist_monitor(&funding_txo, monitor).is_err() {
+            return chain::ChannelMonitorUpdateStatus::PermanentFailure;
+        }
+
+        // the full monitor has every update now, leftover updates are skipped
+        // on startup so failing to delete them isn't a problem
+        if let Err(e) = self.delete_monitor_updates(&funding_txo) {
+            log_warn!(self.logger, "Failed to delete old monitor updates: {e}");
         }
+        chain::ChannelMonitorUpdateStatus::Completed
     }
 }
 
@@ -682,6 +1368,8 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, S: MutinyStorage> Persist<Chann
 mod test {
     use crate::event::{HTLCStatus, MillisatAmount};
     use crate::keymanager::create_keys_manager;
+    use crate::node::default_user_config;
+    use crate::nodemanager::{CltvConfig, ForceClosePostmortem};
     use crate::onchain::OnChainWallet;
     use crate::storage::MemoryStorage;
     use crate::{esplora::EsploraSyncClient, node::scoring_params};
@@ -691,9 +1379,12 @@ mod test {
     use bitcoin::util::bip32::ExtendedPrivKey;
     use bitcoin::Txid;
     use esplora_client::Builder;
+    use lightning::events::ClosureReason;
     use lightning::routing::router::DefaultRouter;
     use lightning::routing::scoring::ProbabilisticScoringDecayParameters;
     use lightning::sign::EntropySource;
+    use proptest::prelude::*;
+    use proptest::test_runner::{Config, TestCaseError, TestRunner};
     use std::str::FromStr;
     use std::sync::atomic::AtomicBool;
     use uuid::Uuid;
@@ -701,6 +1392,7 @@ mod test {
 
     use super::*;
 
+    use crate::router::FeeCappedRouter;
     use crate::test_utils::*;
 
     wasm_bindgen_test_configure!(run_in_browser);
@@ -732,6 +1424,9 @@ mod test {
             bolt11: None,
             payee_pubkey: Some(pubkey),
             secret: None,
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
             last_update: utils::now().as_secs(),
         };
         let result = persister.persist_payment_info(&payment_hash, &payment_info, true);
@@ -760,6 +1455,163 @@ mod test {
         assert_eq!(list[0].1.preimage, Some(preimage));
     }
 
+    #[test]
+    fn test_checksummed_bytes() {
+        let test_name = "test_checksummed_bytes";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        let key = persister.get_key(&monitor_key(&OutPoint {
+            txid: Txid::all_zeros(),
+            index: 0,
+        }));
+
+        let data = vec![1, 2, 3, 4];
+        let value = ChecksummedBytes::new(data.clone());
+        persister.storage.set_data(&key, &value, None).unwrap();
+        let read: ChecksummedBytes = persister.storage.get_data(&key).unwrap().unwrap();
+        assert_eq!(read, value);
+        assert_eq!(read.data(), data.as_slice());
+
+        // monitors saved before checksums were added are still read
+        persister.storage.set_data(&key, &data, None).unwrap();
+        let read: ChecksummedBytes = persister.storage.get_data(&key).unwrap().unwrap();
+        assert_eq!(read, ChecksummedBytes::Unchecked(data.clone()));
+
+        let ChecksummedBytes::Checked { checksum, .. } = value else {
+            panic!("new values should have a checksum");
+        };
+        assert_eq!(
+            persister
+                .verify_checksum(&key, data.clone(), Some(&checksum))
+                .unwrap(),
+            data
+        );
+        assert!(matches!(
+            persister.verify_checksum(&key, vec![1, 2, 3, 5], Some(&checksum)),
+            Err(MutinyError::CorruptedChannelState { .. })
+        ));
+        assert!(persister.verify_checksum(&key, data, None).is_ok());
+    }
+
+    #[test]
+    fn test_corrupted_monitor_updates() {
+        let test_name = "test_corrupted_monitor_updates";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        let funding_txo = OutPoint {
+            txid: Txid::all_zeros(),
+            index: 0,
+        };
+        assert!(persister
+            .read_monitor_updates(&funding_txo)
+            .unwrap()
+            .is_empty());
+
+        // an update that doesn't decode is corrupted channel state, not a read error
+        persister
+            .persist_monitor_update_bytes(&funding_txo, 1, vec![1, 2, 3])
+            .unwrap();
+        let key = persister.get_key(&monitor_update_key(&funding_txo, 1));
+        let saved: ChecksummedBytes = persister.storage.get_data(&key).unwrap().unwrap();
+        assert!(matches!(saved, ChecksummedBytes::Checked { .. }));
+        match persister.read_monitor_updates(&funding_txo) {
+            Err(MutinyError::CorruptedChannelState { key: k }) => assert_eq!(k, key),
+            _ => panic!("expected corrupted channel state"),
+        }
+
+        // as is one that doesn't match its checksum
+        let ChecksummedBytes::Checked { checksum, .. } = saved else {
+            unreachable!()
+        };
+        let tampered = ChecksummedBytes::Checked {
+            checksum,
+            data: vec![1, 2, 4],
+        };
+        persister.storage.set_data(&key, tampered, None).unwrap();
+        match persister.read_monitor_updates(&funding_txo) {
+            Err(MutinyError::CorruptedChannelState { key: k }) => assert_eq!(k, key),
+            _ => panic!("expected corrupted channel state"),
+        }
+    }
+
+    #[test]
+    fn test_stale_vss_monitor_updates() {
+        let test_name = "test_stale_vss_monitor_updates";
+        log!("{}", test_name);
+
+        let txid = Txid::all_zeros();
+        let funding_txo = OutPoint { txid, index: 1 };
+        let node_id = Uuid::new_v4().to_string();
+        let key = |key: String| format!("{key}_{node_id}");
+        let kv = |key: String, version| KeyVersion { key, version };
+
+        let keys = vec![
+            kv(key(monitor_key(&funding_txo)), 100),
+            kv(key(monitor_update_key(&funding_txo, 99)), 99),
+            kv(key(monitor_update_key(&funding_txo, 100)), 100),
+            kv(key(monitor_update_key(&funding_txo, 101)), 101),
+            // the monitor of this channel isn't in VSS, keep its updates
+            kv(key(monitor_update_key(&OutPoint { txid, index: 2 }, 1)), 1),
+        ];
+        assert_eq!(
+            stale_vss_monitor_updates(&keys),
+            vec![keys[1].clone(), keys[2].clone()]
+        );
+    }
+
+    #[test]
+    fn test_monitor_updates() {
+        let test_name = "test_monitor_updates";
+        log!("{}", test_name);
+
+        assert!(persist_update_only(1));
+        assert!(persist_update_only(MAX_PENDING_MONITOR_UPDATES + 1));
+        assert!(!persist_update_only(MAX_PENDING_MONITOR_UPDATES));
+        assert!(!persist_update_only(CLOSED_CHANNEL_UPDATE_ID));
+
+        let apply = |latest, ids: Vec<u64>| monitor_updates_to_apply(latest, ids, |id| *id);
+        assert_eq!(apply(3, vec![2, 3, 4, 5]), Ok(vec![4, 5]));
+        assert_eq!(apply(3, vec![]), Ok(vec![]));
+        assert_eq!(
+            apply(3, vec![4, 5, CLOSED_CHANNEL_UPDATE_ID]),
+            Ok(vec![4, 5, CLOSED_CHANNEL_UPDATE_ID])
+        );
+        // a missing update fails instead of reaching LDK
+        assert_eq!(apply(3, vec![4, 6, 7]), Err(5));
+        assert_eq!(apply(3, vec![5, 6]), Err(4));
+
+        let persister = get_test_persister();
+        let txid = Txid::all_zeros();
+        let funding_txo = OutPoint { txid, index: 1 };
+        let other_txo = OutPoint { txid, index: 10 };
+        for update_id in 1..=3 {
+            let key = persister.get_key(&monitor_update_key(&funding_txo, update_id));
+            persister.storage.set_data(key, vec![0u8], None).unwrap();
+        }
+        let other_key = persister.get_key(&monitor_update_key(&other_txo, 1));
+        persister
+            .storage
+            .set_data(other_key.clone(), vec![0u8], None)
+            .unwrap();
+
+        // updates of other channels are left alone
+        assert_eq!(
+            persister.monitor_update_keys(&funding_txo).unwrap().len(),
+            3
+        );
+        persister.delete_monitor_updates(&funding_txo).unwrap();
+        assert!(persister
+            .monitor_update_keys(&funding_txo)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            persister.monitor_update_keys(&other_txo).unwrap(),
+            vec![other_key]
+        );
+    }
+
     #[test]
     fn test_persist_channel_closure() {
         let test_name = "test_persist_channel_closure";
@@ -774,6 +1626,7 @@ mod test {
             node_id: None,
             reason: "This is a test.".to_string(),
             timestamp: utils::now().as_secs(),
+            postmortem: None,
         };
         let result = persister.persist_channel_closure(user_channel_id, closure.clone());
         assert!(result.is_ok());
@@ -785,6 +1638,195 @@ mod test {
         assert_eq!(result, Some(closure));
     }
 
+    #[test]
+    fn test_prune_details() {
+        let test_name = "test_prune_details";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        let now = utils::now().as_secs();
+        let cutoff = now - 100;
+
+        let payment_info = PaymentInfo {
+            preimage: Some([1; 32]),
+            status: HTLCStatus::Succeeded,
+            amt_msat: MillisatAmount(Some(420)),
+            fee_paid_msat: None,
+            bolt11: None,
+            payee_pubkey: None,
+            secret: Some([2; 32]),
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
+            last_update: cutoff - 1,
+        };
+        let old_hash = PaymentHash([0; 32]);
+        persister
+            .persist_payment_info(&old_hash, &payment_info, true)
+            .unwrap();
+        let htlc = PaymentHtlc::new(true, None, Some(420), HtlcState::Fulfilled, cutoff - 1);
+        persister.add_payment_htlc(&old_hash, htlc).unwrap();
+
+        // recent and pending payments are kept
+        let recent_hash = PaymentHash([1; 32]);
+        let recent = PaymentInfo {
+            last_update: now,
+            ..payment_info.clone()
+        };
+        persister
+            .persist_payment_info(&recent_hash, &recent, true)
+            .unwrap();
+        let pending_hash = PaymentHash([2; 32]);
+        let pending = PaymentInfo {
+            status: HTLCStatus::Pending,
+            ..payment_info.clone()
+        };
+        persister
+            .persist_payment_info(&pending_hash, &pending, false)
+            .unwrap();
+        // sent payments keep their invoice details
+        let sent_hash = PaymentHash([3; 32]);
+        persister
+            .persist_payment_info(&sent_hash, &payment_info, false)
+            .unwrap();
+
+        let closure = ChannelClosure {
+            user_channel_id: Some(1u128.to_be_bytes()),
+            channel_id: Some([1; 32]),
+            node_id: None,
+            reason: "This is a test.".to_string(),
+            timestamp: cutoff - 1,
+            postmortem: ForceClosePostmortem::from_closure_reason(
+                &ClosureReason::HolderForceClosed,
+            ),
+        };
+        persister.persist_channel_closure(1, closure).unwrap();
+
+        let summary = persister.prune_details(cutoff).unwrap();
+        assert_eq!(summary.payments, 1);
+        assert_eq!(summary.channel_closures, 1);
+
+        let logger = MutinyLogger::default();
+        let pruned = persister
+            .read_payment_info(&old_hash, true, &logger)
+            .unwrap();
+        assert_eq!(pruned.secret, None);
+        // the summary is kept
+        assert_eq!(pruned.preimage, payment_info.preimage);
+        assert_eq!(pruned.amt_msat, payment_info.amt_msat);
+        assert!(persister.get_payment_htlcs(&old_hash).unwrap().is_empty());
+
+        let kept = persister
+            .read_payment_info(&recent_hash, true, &logger)
+            .unwrap();
+        assert_eq!(kept, recent);
+        let kept = persister
+            .read_payment_info(&pending_hash, false, &logger)
+            .unwrap();
+        assert_eq!(kept, pending);
+        let kept = persister
+            .read_payment_info(&sent_hash, false, &logger)
+            .unwrap();
+        assert_eq!(kept, payment_info);
+
+        let closure = persister.get_channel_closure(1).unwrap().unwrap();
+        assert!(closure.postmortem.is_none());
+
+        // nothing left to prune
+        let summary = persister.prune_details(cutoff).unwrap();
+        assert_eq!(summary, PruneSummary::default());
+    }
+
+    #[test]
+    fn test_persist_payment_htlcs() {
+        let test_name = "test_persist_payment_htlcs";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        let payment_hash = PaymentHash([0; 32]);
+        assert!(persister
+            .get_payment_htlcs(&payment_hash)
+            .unwrap()
+            .is_empty());
+
+        let now = utils::now().as_secs();
+        let added = PaymentHtlc::new(false, None, Some(10_000), HtlcState::Added, now);
+        persister
+            .add_payment_htlc(&payment_hash, added.clone())
+            .unwrap();
+        let path = PaymentHtlc::new(
+            false,
+            Some("123".to_string()),
+            Some(5_000),
+            HtlcState::Failed,
+            now,
+        );
+        persister.add_payment_htlc(&payment_hash, path).unwrap();
+
+        persister
+            .update_payment_htlcs(&payment_hash, false, HtlcState::Fulfilled)
+            .unwrap();
+
+        let htlcs = persister.get_payment_htlcs(&payment_hash).unwrap();
+        assert_eq!(htlcs.len(), 2);
+        assert_eq!(htlcs[0].state, HtlcState::Fulfilled);
+        assert_eq!(htlcs[0].updates.len(), 2);
+        assert_eq!(htlcs[0].updates[0].state, HtlcState::Added);
+        // already failed htlcs don't change
+        assert_eq!(htlcs[1].state, HtlcState::Failed);
+        assert_eq!(htlcs[1].updates.len(), 1);
+    }
+
+    #[test]
+    fn test_list_unresolved_inbound_htlcs() {
+        let test_name = "test_list_unresolved_inbound_htlcs";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        let now = utils::now().as_secs();
+        let channel = Some("aa".repeat(32));
+
+        let held = PaymentHash([1; 32]);
+        let htlc = PaymentHtlc::new(
+            true,
+            channel.clone(),
+            Some(5_000),
+            HtlcState::Committed,
+            now,
+        );
+        persister.add_payment_htlc(&held, htlc).unwrap();
+
+        let claimed = PaymentHash([2; 32]);
+        let htlc = PaymentHtlc::new(
+            true,
+            channel.clone(),
+            Some(6_000),
+            HtlcState::Committed,
+            now,
+        );
+        persister.add_payment_htlc(&claimed, htlc).unwrap();
+        persister
+            .update_payment_htlcs(&claimed, true, HtlcState::Fulfilled)
+            .unwrap();
+
+        let other_channel = PaymentHash([3; 32]);
+        let htlc = PaymentHtlc::new(
+            true,
+            Some("bb".repeat(32)),
+            Some(7_000),
+            HtlcState::Committed,
+            now,
+        );
+        persister.add_payment_htlc(&other_channel, htlc).unwrap();
+
+        let unresolved = persister
+            .list_unresolved_inbound_htlcs(&"aa".repeat(32))
+            .unwrap();
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0].0, held);
+        assert_eq!(unresolved[0].1.amount_msat, Some(5_000));
+    }
+
     #[test]
     fn test_persist_spendable_output_descriptor() {
         let test_name = "test_persist_spendable_output_descriptor";
@@ -825,6 +1867,301 @@ mod test {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_pending_sweep() {
+        let test_name = "test_pending_sweep";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        assert!(persister.get_pending_sweep().unwrap().is_none());
+
+        let outputs: Vec<SpendableOutputDescriptor> = (0..2)
+            .map(|index| SpendableOutputDescriptor::StaticOutput {
+                outpoint: OutPoint {
+                    txid: Txid::all_zeros(),
+                    index,
+                },
+                output: Default::default(),
+            })
+            .collect();
+
+        persister
+            .queue_pending_sweep(vec![outputs[0].clone()])
+            .unwrap();
+        let (first_queued_at, _) = persister.get_pending_sweep().unwrap().unwrap();
+        persister
+            .queue_pending_sweep(vec![outputs[1].clone()])
+            .unwrap();
+
+        // later outputs are added to the same batch
+        let (queued_at, descriptors) = persister.get_pending_sweep().unwrap().unwrap();
+        assert_eq!(queued_at, first_queued_at);
+        assert_eq!(descriptors, outputs);
+
+        // an output queued while the batch was being swept stays queued
+        let new_output = SpendableOutputDescriptor::StaticOutput {
+            outpoint: OutPoint {
+                txid: Txid::all_zeros(),
+                index: 2,
+            },
+            output: Default::default(),
+        };
+        persister
+            .queue_pending_sweep(vec![new_output.clone()])
+            .unwrap();
+        persister.remove_pending_sweep(&outputs).unwrap();
+        let (_, descriptors) = persister.get_pending_sweep().unwrap().unwrap();
+        assert_eq!(descriptors, vec![new_output.clone()]);
+
+        persister.remove_pending_sweep(&[new_output]).unwrap();
+        assert!(persister.get_pending_sweep().unwrap().is_none());
+    }
+
+    /// A step of the storage state machine test
+    #[derive(Debug, Clone)]
+    enum StorageOp {
+        PersistManager(Vec<u8>),
+        /// A burst of updates to one of a few channels, each saving the update
+        /// on its own or the full monitor
+        PersistMonitor {
+            channel: u16,
+            updates: u64,
+            data: Vec<u8>,
+        },
+        /// Starts again with a new persister on the same storage, reading everything back
+        Restart,
+        /// The last manager write only partly made it to storage
+        TornManagerWrite {
+            cut: usize,
+        },
+        /// The last full write of a monitor only partly made it to storage
+        TornMonitorWrite {
+            channel: u16,
+            cut: usize,
+        },
+    }
+
+    fn storage_op() -> impl Strategy<Value = StorageOp> {
+        let data = proptest::collection::vec(any::<u8>(), 1..64);
+        prop_oneof![
+            data.clone().prop_map(StorageOp::PersistManager),
+            (0..3u16, 1..40u64, data).prop_map(|(channel, updates, data)| {
+                StorageOp::PersistMonitor {
+                    channel,
+                    updates,
+                    data,
+                }
+            }),
+            Just(StorageOp::Restart),
+            any::<usize>().prop_map(|cut| StorageOp::TornManagerWrite { cut }),
+            (0..3u16, any::<usize>())
+                .prop_map(|(channel, cut)| StorageOp::TornMonitorWrite { channel, cut }),
+        ]
+    }
+
+    /// What a channel's monitor should look like in storage
+    struct ModelMonitor {
+        update_id: u64,
+        data: Vec<u8>,
+        /// Updates saved since the monitor was last written in full
+        pending_updates: usize,
+        torn: bool,
+    }
+
+    /// What the storage should contain after the operations so far
+    #[derive(Default)]
+    struct StorageModel {
+        manager: Option<Vec<u8>>,
+        manager_version: u32,
+        manager_torn: bool,
+        monitors: HashMap<u16, ModelMonitor>,
+    }
+
+    fn funding_txo(channel: u16) -> OutPoint {
+        OutPoint {
+            txid: Txid::all_zeros(),
+            index: channel,
+        }
+    }
+
+    /// Reads everything back like a node starting up does. Returns false if
+    /// the node wouldn't start, which must only happen when a write was torn.
+    fn restart(
+        persister: &MutinyNodePersister<MemoryStorage>,
+        model: &StorageModel,
+    ) -> Result<bool, TestCaseError> {
+        let manager = persister.read_manager_bytes();
+        if model.manager_torn {
+            prop_assert!(manager.is_err(), "torn manager was read: {manager:?}");
+            return Ok(false);
+        }
+        match (manager?, model.manager.as_ref()) {
+            (Some((bytes, version)), Some(expected)) => {
+                prop_assert_eq!(&bytes, expected);
+                prop_assert_eq!(version, Some(model.manager_version));
+                persister
+                    .manager_version
+                    .swap(model.manager_version, Ordering::Relaxed);
+            }
+            (None, None) => {}
+            (read, expected) => {
+                return Err(TestCaseError::fail(format!(
+                    "read manager {read:?}, expected {expected:?}"
+                )))
+            }
+        }
+
+        let monitors = persister.read_monitor_bytes();
+        if model.monitors.values().any(|m| m.torn) {
+            prop_assert!(
+                matches!(monitors, Err(MutinyError::CorruptedChannelState { .. })),
+                "torn monitor was read: {monitors:?}"
+            );
+            return Ok(false);
+        }
+        let monitors = monitors?;
+        prop_assert_eq!(monitors.len(), model.monitors.len());
+        for (channel, expected) in model.monitors.iter() {
+            let txo = funding_txo(*channel);
+            let key = persister.get_key(&monitor_key(&txo));
+            prop_assert_eq!(monitors.get(&key), Some(&expected.data));
+            prop_assert_eq!(
+                persister.monitor_update_keys(&txo)?.len(),
+                expected.pending_updates
+            );
+        }
+
+        Ok(true)
+    }
+
+    /// Runs the operations against a persister and a model of what it should
+    /// have saved, checking they agree whenever the node restarts.
+    fn check_storage_ops(ops: Vec<StorageOp>) -> Result<(), TestCaseError> {
+        let storage = MemoryStorage::default();
+        let node_id = Uuid::new_v4().to_string();
+        let logger = Arc::new(MutinyLogger::default());
+        let mut persister =
+            MutinyNodePersister::new(node_id.clone(), storage.clone(), logger.clone());
+        let mut model = StorageModel::default();
+
+        for op in ops {
+            match op {
+                StorageOp::PersistManager(data) => {
+                    persister.persist_manager_bytes(&data)?;
+                    model.manager_version += 1;
+                    model.manager = Some(data);
+                    model.manager_torn = false;
+
+                    // versions only go up, or remote storage would ignore the write
+                    let key = persister.get_key(CHANNEL_MANAGER_KEY);
+                    let saved: Option<VersionedValue> = storage.get_data(key)?;
+                    prop_assert_eq!(saved.map(|v| v.version), Some(model.manager_version));
+                }
+                StorageOp::PersistMonitor {
+                    channel,
+                    updates,
+                    data,
+                } => {
+                    let txo = funding_txo(channel);
+                    if !model.monitors.contains_key(&channel) {
+                        // a new channel, its monitor is written in full
+                        persister.persist_monitor_bytes(&txo, 0, data.clone())?;
+                        model.monitors.insert(
+                            channel,
+                            ModelMonitor {
+                                update_id: 0,
+                                data,
+                                pending_updates: 0,
+                                torn: false,
+                            },
+                        );
+                        continue;
+                    }
+                    let monitor = model.monitors.get_mut(&channel).expect("just checked");
+
+                    for _ in 0..updates {
+                        monitor.update_id += 1;
+                        if persist_update_only(monitor.update_id) {
+                            persister.persist_monitor_update_bytes(
+                                &txo,
+                                monitor.update_id,
+                                data.clone(),
+                            )?;
+                            monitor.pending_updates += 1;
+                        } else {
+                            persister.persist_monitor_bytes(
+                                &txo,
+                                monitor.update_id,
+                                data.clone(),
+                            )?;
+                            persister.delete_monitor_updates(&txo)?;
+                            monitor.data = data.clone();
+                            monitor.pending_updates = 0;
+                            monitor.torn = false;
+                        }
+                    }
+                }
+                StorageOp::Restart => {
+                    persister =
+                        MutinyNodePersister::new(node_id.clone(), storage.clone(), logger.clone());
+                    if !restart(&persister, &model)? {
+                        // a node with corrupted state doesn't start, so nothing else is written
+                        return Ok(());
+                    }
+                }
+                StorageOp::TornManagerWrite { cut } => {
+                    if model.manager_torn {
+                        continue;
+                    }
+                    let key = persister.get_key(CHANNEL_MANAGER_KEY);
+                    let Some(mut saved) = storage.get_data::<VersionedValue>(&key)? else {
+                        continue;
+                    };
+                    let hex: String = serde_json::from_value(saved.value)?;
+                    saved.value = serde_json::to_value(&hex[..cut % hex.len()])?;
+                    storage.set_data(key, saved, Some(model.manager_version))?;
+                    model.manager_torn = true;
+                }
+                StorageOp::TornMonitorWrite { channel, cut } => {
+                    let Some(monitor) = model.monitors.get_mut(&channel).filter(|m| !m.torn) else {
+                        continue;
+                    };
+                    let key = persister.get_key(&monitor_key(&funding_txo(channel)));
+                    let saved: Option<ChecksummedBytes> = storage.get_data(&key)?;
+                    let Some(ChecksummedBytes::Checked { checksum, mut data }) = saved else {
+                        return Err(TestCaseError::fail("monitor saved without a checksum"));
+                    };
+                    data.truncate(cut % data.len());
+                    storage.set_data(key, ChecksummedBytes::Checked { checksum, data }, None)?;
+                    monitor.torn = true;
+                }
+            }
+        }
+
+        // whatever happened, starting again reads back what was saved last
+        let persister = MutinyNodePersister::new(node_id, storage, logger);
+        restart(&persister, &model)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_storage_state_machine() {
+        let test_name = "test_storage_state_machine";
+        log!("{}", test_name);
+
+        let config = Config {
+            cases: 64,
+            failure_persistence: None,
+            ..Config::default()
+        };
+        let mut runner = TestRunner::new(config);
+        let ops = proptest::collection::vec(storage_op(), 1..64);
+        if let Err(e) = runner.run(&ops, check_storage_ops) {
+            panic!("{test_name} failed: {e}");
+        }
+    }
+
     const MANAGER_BYTES: [u8; 256] = [
         1, 1, 246, 30, 238, 59, 99, 163, 128, 164, 119, 160, 99, 175, 50, 178, 187, 201, 124, 159,
         249, 240, 31, 44, 66, 37, 233, 115, 152, 129, 8, 0, 0, 0, 0, 3, 123, 222, 76, 244, 143, 88,
@@ -913,12 +2250,16 @@ mod test {
             persister.clone(),
         ));
 
-        let router: Arc<Router> = Arc::new(DefaultRouter::new(
-            network_graph,
+        let router: Arc<Router<MemoryStorage>> = Arc::new(FeeCappedRouter::new(
+            DefaultRouter::new(
+                network_graph,
+                logger.clone(),
+                km.clone().get_secure_random_bytes(),
+                Arc::new(utils::Mutex::new(scorer)),
+                scoring_params(),
+            ),
+            persister.storage.clone(),
             logger.clone(),
-            km.clone().get_secure_random_bytes(),
-            Arc::new(utils::Mutex::new(scorer)),
-            scoring_params(),
         ));
 
         // make sure it correctly reads
@@ -931,6 +2272,7 @@ mod test {
                 logger.clone(),
                 km.clone(),
                 router.clone(),
+                default_user_config(&CltvConfig::default()),
                 vec![],
                 &esplora,
             )
@@ -954,6 +2296,7 @@ mod test {
                 logger.clone(),
                 km,
                 router,
+                default_user_config(&CltvConfig::default()),
                 vec![],
                 &esplora,
             )
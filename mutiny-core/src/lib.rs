@@ -11,18 +11,45 @@
 // background file is mostly an LDK copy paste
 mod background;
 
+pub mod activity_index;
+pub mod alerts;
+pub mod allowlist;
 pub mod auth;
+pub mod balance_changes;
 mod chain;
+pub mod channel_policy;
+pub mod conflict;
+pub mod crash;
+pub mod devices;
+mod encoding;
 pub mod encrypt;
+pub mod enrichment;
 pub mod error;
 pub mod esplora;
 mod event;
+pub mod external_funding;
 mod fees;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 mod gossip;
+pub mod history_import;
+pub mod hold_invoice;
+pub mod http_cache;
+pub mod http_client;
+pub mod idempotency;
+pub mod inbound;
+pub mod inheritance;
+pub mod justice;
 mod keymanager;
 pub mod labels;
 mod ldkstorage;
+pub mod lnaddress_pairing;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lnrpc_json;
 pub mod lnurlauth;
+pub mod lnurlchannel;
+pub mod lnurlpay;
+pub mod lnurlwithdraw;
 pub mod logging;
 mod lspclient;
 mod multiesplora;
@@ -30,13 +57,39 @@ mod networking;
 mod node;
 pub mod nodemanager;
 pub mod nostr;
+pub mod offline_receive;
 mod onchain;
+pub mod payment_metadata;
+pub mod payment_proof;
+pub mod payment_retry;
+pub mod payment_status;
+pub mod payments;
 mod peermanager;
+pub mod receipts;
+pub mod recurring;
 pub mod redshift;
+pub mod restore_points;
+pub mod retention;
+pub mod rollout;
+mod router;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rpc;
 pub mod scb;
+pub mod scheduled_close;
+pub mod service_auth;
+pub mod slip39;
+pub mod splits;
+pub mod startup;
+pub mod status;
 pub mod storage;
 mod subscription;
+pub mod swap_out;
+pub mod sweep;
+pub mod telemetry;
+pub mod uri;
+mod utxo;
 pub mod vss;
+pub mod watchtower;
 
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils;
@@ -44,11 +97,24 @@ mod utils;
 
 pub use crate::gossip::{GOSSIP_SYNC_TIME_KEY, NETWORK_GRAPH_KEY, PROB_SCORER_KEY};
 pub use crate::keymanager::generate_seed;
-pub use crate::ldkstorage::{CHANNEL_MANAGER_KEY, MONITORS_PREFIX_KEY};
+pub use crate::ldkstorage::{
+    stale_vss_monitor_updates, ChecksummedBytes, CHANNEL_MANAGER_KEY, MONITORS_PREFIX_KEY,
+    MONITOR_UPDATES_PREFIX_KEY,
+};
 
 use crate::auth::MutinyAuthClient;
+use crate::devices::{DeviceRegistryStorage, DEVICE_CAPABILITIES};
+use crate::http_client::MutinyHttpClient;
+#[cfg(not(test))]
+use crate::inheritance::HELD_SWEEP_SYNC_INTERVAL_SECS;
 use crate::labels::{Contact, LabelStorage};
-use crate::nostr::nwc::SpendingConditions;
+use crate::multiesplora::DEFAULT_SYNC_PARALLEL_REQUESTS;
+use crate::nodemanager::CltvConfig;
+use crate::nostr::nwc::{NwcScope, SpendingConditions};
+use crate::nostr::recovery::{fetch_returned_shards, recovery_dm_filter};
+use crate::nostr::relays::{backoff_secs, connect_relays, RelayUse, MAX_BACKOFF_SECS};
+use crate::rollout::CanaryEndpoint;
+use crate::startup::StartupProgress;
 use crate::storage::{MutinyStorage, DEVICE_ID_KEY, NEED_FULL_SYNC_KEY};
 use crate::{error::MutinyError, nostr::ReservedProfile};
 use crate::{nodemanager::NodeManager, nostr::ProfileType};
@@ -80,8 +146,16 @@ pub struct MutinyWalletConfig {
     auth_client: Option<Arc<MutinyAuthClient>>,
     subscription_url: Option<String>,
     scorer_url: Option<String>,
+    telemetry_url: Option<String>,
     do_not_connect_peers: bool,
+    validate_gossip: bool,
+    cltv_config: CltvConfig,
     skip_device_lock: bool,
+    force_takeover: bool,
+    startup_progress: Option<Arc<StartupProgress>>,
+    sync_parallel_requests: usize,
+    http_client: Option<Arc<dyn MutinyHttpClient>>,
+    canary_endpoints: Vec<CanaryEndpoint>,
 }
 
 impl MutinyWalletConfig {
@@ -109,8 +183,16 @@ impl MutinyWalletConfig {
             lsp_url,
             auth_client,
             subscription_url,
+            telemetry_url: None,
             do_not_connect_peers: false,
+            validate_gossip: false,
+            cltv_config: CltvConfig::default(),
             skip_device_lock,
+            force_takeover: false,
+            startup_progress: None,
+            sync_parallel_requests: DEFAULT_SYNC_PARALLEL_REQUESTS,
+            http_client: None,
+            canary_endpoints: vec![],
         }
     }
 
@@ -118,6 +200,68 @@ impl MutinyWalletConfig {
         self.do_not_connect_peers = true;
         self
     }
+
+    /// Validates channel announcements received directly from peers by
+    /// looking up their funding outputs with esplora, instead of trusting them.
+    pub fn with_gossip_validation(mut self) -> Self {
+        self.validate_gossip = true;
+        self
+    }
+
+    /// Sets the CLTV deltas used for forwarding and in the invoices we create,
+    /// for integrators who want more time to react to misbehaving peers.
+    pub fn with_cltv_config(mut self, cltv_config: CltvConfig) -> Self {
+        self.cltv_config = CltvConfig::new(
+            cltv_config.cltv_expiry_delta,
+            cltv_config.min_final_cltv_expiry_delta,
+        );
+        self
+    }
+
+    /// Starts even if the remote backup has newer channel state than this device,
+    /// by replacing the local channel state with the remote one.
+    pub fn with_forced_takeover(mut self) -> Self {
+        self.force_takeover = true;
+        self
+    }
+
+    /// Reports the progress of loading the network graph and nodes while the
+    /// wallet is starting up.
+    pub fn with_startup_progress(mut self, progress: Arc<StartupProgress>) -> Self {
+        self.startup_progress = Some(progress);
+        self
+    }
+
+    /// Sets how many esplora requests are made at once while syncing.
+    /// Lower values are gentler on flaky connections, higher ones sync faster.
+    pub fn with_sync_parallel_requests(mut self, parallel_requests: usize) -> Self {
+        self.sync_parallel_requests = parallel_requests.max(1);
+        self
+    }
+
+    /// Sends the wallet's HTTP requests through the given client instead of the
+    /// default reqwest one, for custom headers, proxies, Tor or retries.
+    pub fn with_http_client(mut self, http_client: Arc<dyn MutinyHttpClient>) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Sends a percentage of wallets to another endpoint for a service, so a
+    /// migration can be tried on some wallets first. Each wallet keeps its
+    /// rollout bucket, so it stays on the same endpoint until the percentage changes.
+    pub fn with_canary_endpoint(mut self, canary: CanaryEndpoint) -> Self {
+        self.canary_endpoints
+            .retain(|c| c.service != canary.service);
+        self.canary_endpoints.push(canary);
+        self
+    }
+
+    /// Sets the server anonymized telemetry summaries are uploaded to.
+    /// Nothing is uploaded unless the user has opted in.
+    pub fn with_telemetry_url(mut self, telemetry_url: String) -> Self {
+        self.telemetry_url = Some(telemetry_url);
+        self
+    }
 }
 
 #[derive(Clone)]
@@ -147,6 +291,10 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         };
 
         NodeManager::start_sync(node_manager.clone());
+        NodeManager::start_lightning_address_pairing(node_manager.clone());
+        if let Err(e) = node_manager.start_watchtower().await {
+            log_error!(node_manager.logger, "Failed to start watchtower: {e}");
+        }
 
         // create nostr manager
         let nostr = Arc::new(NostrManager::from_mnemonic(
@@ -161,8 +309,25 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             nostr,
         };
 
+        // record this device, then stop the NWC connections of every revoked device.
+        // The registry is shared, so this catches revokes made on other devices.
+        mw.storage.record_device_seen(&DEVICE_CAPABILITIES)?;
+        let registry = mw.storage.get_device_registry()?;
+        for device in registry.devices.iter().filter(|d| d.revoked) {
+            let disabled = mw.nostr.disable_device_profiles(&device.id)?;
+            if disabled > 0 {
+                log_warn!(
+                    mw.node_manager.logger,
+                    "Device {} was revoked, disabled {disabled} NWC profiles",
+                    device.id
+                );
+            }
+        }
+
         // start the nostr wallet connect background process
         mw.start_nostr_wallet_connect(first_node).await;
+        #[cfg(not(test))]
+        mw.start_inheritance_sync();
 
         #[cfg(not(test))]
         {
@@ -183,7 +348,13 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         self.node_manager =
             Arc::new(NodeManager::new(self.config.clone(), self.storage.clone()).await?);
         NodeManager::start_sync(self.node_manager.clone());
+        NodeManager::start_lightning_address_pairing(self.node_manager.clone());
         NodeManager::start_redshifts(self.node_manager.clone());
+        if let Err(e) = self.node_manager.start_watchtower().await {
+            log_error!(self.node_manager.logger, "Failed to start watchtower: {e}");
+        }
+        #[cfg(not(test))]
+        self.start_inheritance_sync();
         Ok(())
     }
 
@@ -192,6 +363,8 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         let nostr = self.nostr.clone();
         let nm = self.node_manager.clone();
         utils::spawn(async move {
+            // how many times in a row we've lost our connection to the relays
+            let mut failures = 0;
             loop {
                 if nm.stop.load(Ordering::Relaxed) {
                     break;
@@ -210,17 +383,15 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 }
 
                 let client = Client::new(&nostr.primary_key);
-
-                #[cfg(target_arch = "wasm32")]
-                let add_relay_res = client.add_relays(nostr.get_relays()).await;
-
-                #[cfg(not(target_arch = "wasm32"))]
-                let add_relay_res = client
-                    .add_relays(nostr.get_relays().into_iter().map(|s| (s, None)).collect())
-                    .await;
-
-                add_relay_res.expect("Failed to add relays");
-                client.connect().await;
+                if let Err(e) =
+                    connect_relays(&nostr.storage, &client, RelayUse::Read, relays).await
+                {
+                    log_warn!(nm.logger, "Failed to connect to NWC relays: {e}");
+                    failures += 1;
+                    utils::sleep((backoff_secs(failures) * 1_000) as i32).await;
+                    continue;
+                }
+                let connected_at = utils::now().as_secs();
 
                 let mut last_filters = nostr.get_nwc_filters();
                 client.subscribe(last_filters.clone()).await;
@@ -289,6 +460,19 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 if let Err(e) = client.disconnect().await {
                     log_warn!(nm.logger, "Error disconnecting from relays: {e}");
                 }
+
+                if nm.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                // back off if we keep getting disconnected, a connection that lasted
+                // longer than the longest backoff was healthy so we start over
+                if utils::now().as_secs().saturating_sub(connected_at) > MAX_BACKOFF_SECS {
+                    failures = 0;
+                } else {
+                    failures += 1;
+                }
+                utils::sleep((backoff_secs(failures) * 1_000) as i32).await;
             }
         });
     }
@@ -334,6 +518,9 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                     inv,
                     None,
                     vec!["Mutiny+ Subscription".to_string()],
+                    None,
+                    None,
+                    None,
                 )
                 .await?;
 
@@ -362,6 +549,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
                 .create_new_nwc_profile(
                     ProfileType::Reserved(ReservedProfile::MutinySubscription),
                     SpendingConditions::RequireApproval,
+                    vec![NwcScope::PayInvoice],
                 )
                 .await?;
             // only should have to submit the NWC if never created locally before
@@ -378,14 +566,7 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     ) -> Result<(), MutinyError> {
         let keys = Keys::from_public_key(npub);
         let client = Client::new(&keys);
-
-        #[cfg(target_arch = "wasm32")]
-        client.add_relay("wss://relay.damus.io").await?;
-
-        #[cfg(not(target_arch = "wasm32"))]
-        client.add_relay("wss://relay.damus.io", None).await?;
-
-        client.connect().await;
+        connect_relays(&self.storage, &client, RelayUse::Read, vec![]).await?;
 
         let mut metadata = nostr::get_contact_list_metadata(&client, timeout).await?;
 
@@ -416,9 +597,195 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         }
 
         client.disconnect().await?;
+
+        // flag any contacts whose NIP-05 identifier no longer points to them
+        match self.node_manager.verify_contacts_nip05().await {
+            Ok(broken) if !broken.is_empty() => log_warn!(
+                self.node_manager.logger,
+                "{} contacts failed NIP-05 verification",
+                broken.len()
+            ),
+            Ok(_) => {}
+            Err(e) => log_warn!(self.node_manager.logger, "Failed to verify contacts: {e}"),
+        }
+
+        Ok(())
+    }
+
+    /// Splits the seed into shards and sends them to the given guardians over
+    /// encrypted nostr DMs, any `threshold` of them can help restore the wallet.
+    ///
+    /// Calling this again with a different set of guardians rotates the shards.
+    pub async fn setup_social_recovery(
+        &self,
+        threshold: u8,
+        guardians: Vec<XOnlyPublicKey>,
+    ) -> Result<(), MutinyError> {
+        let mnemonic = self
+            .storage
+            .get_mnemonic()?
+            .ok_or(MutinyError::InvalidMnemonic)?;
+        let (events, config) = self
+            .nostr
+            .create_recovery_shard_events(&mnemonic, threshold, guardians)?;
+
+        let client = Client::new(&self.nostr.primary_key);
+        connect_relays(&self.storage, &client, RelayUse::Write, vec![]).await?;
+
+        for event in events {
+            if let Err(e) = client.send_event(event).await {
+                let _ = client.disconnect().await;
+                return Err(e.into());
+            }
+        }
+
+        client.disconnect().await?;
+
+        // only save the new version once every guardian was sent their shard,
+        // so a failed send can be retried with the same version
+        self.nostr.set_social_recovery_config(config)
+    }
+
+    /// Fetches the recovery shards other users have sent us to hold as their guardian.
+    pub async fn sync_recovery_shards(&self, timeout: Option<Duration>) -> Result<(), MutinyError> {
+        let client = Client::new(&self.nostr.primary_key);
+        connect_relays(&self.storage, &client, RelayUse::Read, vec![]).await?;
+
+        let filter = recovery_dm_filter(self.nostr.primary_key.public_key());
+        let mut events = client.get_events_of(vec![filter], timeout).await?;
+        client.disconnect().await?;
+
+        // handle oldest first so revokes are applied in order
+        events.sort_by_key(|e| e.created_at);
+        for event in events {
+            if let Err(e) = self.nostr.handle_recovery_message(&event) {
+                log_warn!(
+                    self.node_manager.logger,
+                    "Failed to handle recovery message: {e}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the shard we hold for `owner` to the recovery key they gave us.
+    pub async fn return_recovery_shard(
+        &self,
+        owner: XOnlyPublicKey,
+        recovery_pubkey: XOnlyPublicKey,
+    ) -> Result<(), MutinyError> {
+        let event = self
+            .nostr
+            .create_shard_return_event(owner, recovery_pubkey)?;
+
+        let client = Client::new(&self.nostr.primary_key);
+        connect_relays(&self.storage, &client, RelayUse::Write, vec![]).await?;
+        client.send_event(event).await?;
+        client.disconnect().await?;
+        Ok(())
+    }
+
+    /// Revokes another device. The NWC connections it created are disabled and their
+    /// budgets reset right away here, and by every device that has them once it starts.
+    pub fn revoke_device(&self, device_id: &str) -> Result<(), MutinyError> {
+        if self.storage.get_device_id()? == device_id {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        self.storage.revoke_device(device_id)?;
+        self.nostr.disable_device_profiles(device_id)?;
+        Ok(())
+    }
+
+    /// Sends the inheritance sweep to the heir's npub if they haven't been sent
+    /// the current one yet. Returns true if it was sent.
+    pub async fn deliver_inheritance_sweep(&self) -> Result<bool, MutinyError> {
+        let Some(plan) = self
+            .node_manager
+            .get_inheritance_plan()?
+            .filter(|p| p.needs_delivery())
+        else {
+            return Ok(false);
+        };
+        let (Some(event), Some(txid)) = (
+            self.nostr.create_sweep_event(&plan)?,
+            plan.sweep_tx.as_ref().map(|tx| tx.txid()),
+        ) else {
+            return Ok(false);
+        };
+
+        let client = Client::new(&self.nostr.primary_key);
+        connect_relays(&self.storage, &client, RelayUse::Write, vec![]).await?;
+        client.send_event(event).await?;
+        client.disconnect().await?;
+
+        self.node_manager.mark_inheritance_sweep_delivered(txid)?;
+        Ok(true)
+    }
+
+    /// Fetches the sweep transactions other users have sent us as their heir.
+    pub async fn sync_inheritance_sweeps(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<(), MutinyError> {
+        let client = Client::new(&self.nostr.primary_key);
+        connect_relays(&self.storage, &client, RelayUse::Read, vec![]).await?;
+
+        let filter = recovery_dm_filter(self.nostr.primary_key.public_key());
+        let events = client.get_events_of(vec![filter], timeout).await?;
+        client.disconnect().await?;
+
+        for event in events {
+            if let Err(e) = self.nostr.handle_inheritance_message(&event) {
+                log_warn!(
+                    self.node_manager.logger,
+                    "Failed to handle inheritance message: {e}"
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// Starts a background process that sends the inheritance sweep to the heir
+    /// whenever it changes and fetches the sweeps other users have sent us as their heir.
+    #[cfg(not(test))]
+    pub(crate) fn start_inheritance_sync(&self) {
+        let mw = self.clone();
+        utils::spawn(async move {
+            let mut last_fetch = 0;
+            loop {
+                if mw.node_manager.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                if let Err(e) = mw.deliver_inheritance_sweep().await {
+                    log_warn!(
+                        mw.node_manager.logger,
+                        "Failed to send inheritance sweep to the heir: {e}"
+                    );
+                }
+
+                let now = utils::now().as_secs();
+                if now >= last_fetch + HELD_SWEEP_SYNC_INTERVAL_SECS {
+                    match mw
+                        .sync_inheritance_sweeps(Some(Duration::from_secs(10)))
+                        .await
+                    {
+                        Ok(_) => last_fetch = now,
+                        Err(e) => log_warn!(
+                            mw.node_manager.logger,
+                            "Failed to fetch inheritance sweeps: {e}"
+                        ),
+                    }
+                }
+
+                utils::sleep(60_000).await;
+            }
+        });
+    }
+
     /// Stops all of the nodes and background processes.
     /// Returns after node has been stopped.
     pub async fn stop(&self) -> Result<(), MutinyError> {
@@ -480,16 +847,44 @@ impl<S: MutinyStorage> MutinyWallet<S> {
     ///
     /// Backup the state beforehand. Does not restore lightning data.
     /// Should refresh or restart afterwards. Wallet should be stopped.
-    pub async fn restore_mnemonic(mut storage: S, m: Mnemonic) -> Result<(), MutinyError> {
+    ///
+    /// If the wallet's birthday height is given and it is at the current tip,
+    /// the restore skips scanning for the wallet's history.
+    pub async fn restore_mnemonic(
+        mut storage: S,
+        m: Mnemonic,
+        birthday: Option<u32>,
+    ) -> Result<(), MutinyError> {
         let device_id = storage.get_device_id()?;
         storage.stop();
         S::clear().await?;
         storage.start().await?;
         storage.insert_mnemonic(m)?;
+        if let Some(height) = birthday {
+            storage.set_wallet_birthday(height)?;
+        }
         storage.set_data(NEED_FULL_SYNC_KEY, true, None)?;
         storage.set_data(DEVICE_ID_KEY, device_id, None)?;
         Ok(())
     }
+
+    /// Restores the mnemonic from the shards guardians have returned to the
+    /// recovery key, after deleting the previous state.
+    ///
+    /// The shards are fetched from the given relays, the ones the guardians use,
+    /// or the default relays if none are given.
+    ///
+    /// Backup the state beforehand. Does not restore lightning data.
+    /// Should refresh or restart afterwards. Wallet should be stopped.
+    pub async fn restore_from_social_recovery(
+        storage: S,
+        recovery_keys: &Keys,
+        relays: Vec<String>,
+        timeout: Option<Duration>,
+    ) -> Result<(), MutinyError> {
+        let mnemonic = fetch_returned_shards(recovery_keys, relays, timeout).await?;
+        Self::restore_mnemonic(storage, mnemonic, None).await
+    }
 }
 
 #[cfg(test)]
@@ -674,7 +1069,7 @@ mod tests {
         let pass = uuid::Uuid::new_v4().to_string();
         let cipher = encryption_key_from_pass(&pass).unwrap();
         let storage3 = MemoryStorage::new(Some(pass), Some(cipher), None);
-        MutinyWallet::restore_mnemonic(storage3.clone(), mnemonic.clone())
+        MutinyWallet::restore_mnemonic(storage3.clone(), mnemonic.clone(), None)
             .await
             .expect("mutiny wallet should restore");
 
@@ -0,0 +1,280 @@
+//! Receiving to a lightning address through a server the wallet pairs with.
+//!
+//! A browser node can't host `/.well-known/lnurlp/<name>` itself, so a
+//! lightning address server does that and fetches invoices from the wallet
+//! over a websocket the wallet keeps open to `<server>/v1/pair`. Every message
+//! is a JSON object with a `type` field:
+//!
+//! 1. The server sends a `challenge` with a random hex `k1`.
+//! 2. The wallet sends a `claim` with the `username` it wants, its LNURL-auth
+//!    linking `key` for the server's domain and a DER hex `sig` of `k1`. The
+//!    key is derived per domain (LUD-05), so the server recognizes the wallet
+//!    when it reconnects without learning anything else about it.
+//! 3. The server answers with `claimed` and the full `address`, or an `error`
+//!    with a `reason` if the username is taken.
+//! 4. When someone pays the address, the server sends an `invoice_request`
+//!    with an `id`, the `amount_msat` and the hex `description_hash` of the
+//!    LNURL metadata. The wallet replies with an `invoice` with the same `id`
+//!    and the bolt11 invoice as `pr`, or an `error` with the `id`.
+//!
+//! The address can only receive while the wallet is open in at least one place.
+
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::sha256;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+pub const LIGHTNING_ADDRESS_PAIRING_KEY: &str = "lightning_address_pairing";
+
+const PAIRING_PATH: &str = "/v1/pair";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingStatus {
+    /// Waiting for the server to accept the username
+    Pending,
+    Claimed {
+        address: String,
+    },
+    /// The server refused the pairing, it won't be retried
+    Failed {
+        reason: String,
+    },
+}
+
+/// A lightning address server the wallet provides invoices to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LightningAddressPairing {
+    pub server_url: String,
+    pub username: String,
+    pub status: PairingStatus,
+}
+
+impl LightningAddressPairing {
+    /// Returns true if this is a pairing with the same server and username
+    pub(crate) fn same_as(&self, other: &Self) -> bool {
+        self.server_url == other.server_url && self.username == other.username
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ServerMessage {
+    Challenge {
+        k1: String,
+    },
+    Claimed {
+        address: String,
+    },
+    InvoiceRequest {
+        id: String,
+        amount_msat: u64,
+        description_hash: Option<String>,
+    },
+    Error {
+        #[serde(default)]
+        id: Option<String>,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClientMessage {
+    Claim {
+        username: String,
+        key: String,
+        sig: String,
+    },
+    Invoice {
+        id: String,
+        pr: String,
+    },
+    Error {
+        id: Option<String>,
+        reason: String,
+    },
+}
+
+/// Checks a username only uses the characters allowed by LUD-16
+pub(crate) fn validate_username(username: &str) -> Result<(), MutinyError> {
+    let valid = !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c));
+    if !valid {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+
+    Ok(())
+}
+
+/// The websocket url for pairing with the server
+pub(crate) fn pairing_ws_url(server_url: &str) -> Result<Url, MutinyError> {
+    let mut url = Url::parse(server_url).map_err(|_| MutinyError::InvalidArgumentsError)?;
+    let ws_scheme = match url.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        _ => return Err(MutinyError::InvalidArgumentsError),
+    };
+    url.set_scheme(ws_scheme)
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+    url.set_path(PAIRING_PATH);
+
+    Ok(url)
+}
+
+/// Parses the amount and description hash of an invoice request.
+/// Our invoices are in whole sats, so the amount has to be too.
+pub(crate) fn parse_invoice_request(
+    amount_msat: u64,
+    description_hash: Option<&str>,
+) -> Result<(u64, Option<sha256::Hash>), MutinyError> {
+    if amount_msat == 0 || amount_msat % 1_000 != 0 {
+        return Err(MutinyError::BadAmountError);
+    }
+    let description_hash = description_hash
+        .map(sha256::Hash::from_hex)
+        .transpose()
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+    Ok((amount_msat / 1_000, description_hash))
+}
+
+pub trait LightningAddressPairingStorage {
+    fn get_lightning_address_pairing(&self)
+        -> Result<Option<LightningAddressPairing>, MutinyError>;
+    fn set_lightning_address_pairing(
+        &self,
+        pairing: Option<LightningAddressPairing>,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> LightningAddressPairingStorage for S {
+    fn get_lightning_address_pairing(
+        &self,
+    ) -> Result<Option<LightningAddressPairing>, MutinyError> {
+        self.get_data(LIGHTNING_ADDRESS_PAIRING_KEY)
+    }
+
+    fn set_lightning_address_pairing(
+        &self,
+        pairing: Option<LightningAddressPairing>,
+    ) -> Result<(), MutinyError> {
+        match pairing {
+            Some(pairing) => self.set_data(LIGHTNING_ADDRESS_PAIRING_KEY, pairing, None),
+            None => self.delete(&[LIGHTNING_ADDRESS_PAIRING_KEY]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_validate_username() {
+        log!("test validate username");
+
+        assert!(validate_username("satoshi").is_ok());
+        assert!(validate_username("bob-1.test_").is_ok());
+        assert!(validate_username("").is_err());
+        assert!(validate_username("Bob").is_err());
+        assert!(validate_username("bob@example.com").is_err());
+    }
+
+    #[test]
+    fn test_pairing_ws_url() {
+        log!("test pairing ws url");
+
+        let url = pairing_ws_url("https://example.com").unwrap();
+        assert_eq!(url.as_str(), "wss://example.com/v1/pair");
+        let url = pairing_ws_url("http://localhost:8080/").unwrap();
+        assert_eq!(url.as_str(), "ws://localhost:8080/v1/pair");
+        assert!(pairing_ws_url("ftp://example.com").is_err());
+        assert!(pairing_ws_url("not a url").is_err());
+    }
+
+    #[test]
+    fn test_parse_invoice_request() {
+        log!("test parse invoice request");
+
+        let hash = sha256::Hash::hash(b"metadata");
+        let (amount, parsed) = parse_invoice_request(21_000, Some(&hash.to_string())).unwrap();
+        assert_eq!(amount, 21);
+        assert_eq!(parsed, Some(hash));
+
+        assert!(parse_invoice_request(0, None).is_err());
+        assert!(parse_invoice_request(21_500, None).is_err());
+        assert!(parse_invoice_request(21_000, Some("not a hash")).is_err());
+    }
+
+    #[test]
+    fn test_pairing_messages() {
+        log!("test pairing messages");
+
+        let msg: ServerMessage = serde_json::from_str(
+            r#"{"type":"invoice_request","id":"1","amount_msat":1000,"description_hash":null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            msg,
+            ServerMessage::InvoiceRequest {
+                id: "1".to_string(),
+                amount_msat: 1_000,
+                description_hash: None,
+            }
+        );
+
+        let msg: ServerMessage =
+            serde_json::from_str(r#"{"type":"error","reason":"username taken"}"#).unwrap();
+        assert_eq!(
+            msg,
+            ServerMessage::Error {
+                id: None,
+                reason: "username taken".to_string(),
+            }
+        );
+
+        let msg = ClientMessage::Invoice {
+            id: "1".to_string(),
+            pr: "lnbc".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"type":"invoice","id":"1","pr":"lnbc"}"#
+        );
+    }
+
+    #[test]
+    fn test_pairing_storage() {
+        log!("test pairing storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_lightning_address_pairing().unwrap().is_none());
+
+        let pairing = LightningAddressPairing {
+            server_url: "https://example.com".to_string(),
+            username: "satoshi".to_string(),
+            status: PairingStatus::Pending,
+        };
+        storage
+            .set_lightning_address_pairing(Some(pairing.clone()))
+            .unwrap();
+        assert_eq!(
+            storage.get_lightning_address_pairing().unwrap(),
+            Some(pairing)
+        );
+
+        storage.set_lightning_address_pairing(None).unwrap();
+        assert!(storage.get_lightning_address_pairing().unwrap().is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! A few extra JSON-RPC methods for the [crate::rpc] control interface,
+//! shaped after methods of the `lnrpc.Lightning` service.
+//!
+//! Methods are called as `lnrpc.Lightning/<Method>` over the same websocket
+//! JSON-RPC as everything else, and their params and results follow the JSON
+//! encoding of the lnrpc messages: `int64` fields are strings and `bytes`
+//! fields are base64. This is not gRPC or REST, so LND clients can't connect
+//! to it, but code written against those messages can reuse its types.
+
+use crate::error::MutinyError;
+use crate::nodemanager::{MutinyChannel, NodeManager};
+use crate::rpc::{param, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::Hash;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+
+pub const LNRPC_METHOD_PREFIX: &str = "lnrpc.Lightning/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddInvoiceResponse {
+    pub r_hash: String,
+    pub payment_request: String,
+    pub add_index: String,
+    pub payment_addr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendResponse {
+    pub payment_error: String,
+    pub payment_preimage: String,
+    pub payment_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub active: bool,
+    pub remote_pubkey: String,
+    pub channel_point: String,
+    pub capacity: String,
+    pub local_balance: String,
+    pub remote_balance: String,
+    pub local_chan_reserve_sat: String,
+}
+
+impl From<MutinyChannel> for Channel {
+    fn from(c: MutinyChannel) -> Self {
+        let active = c.confirmations >= c.confirmations_required.unwrap_or(0);
+        Channel {
+            active,
+            remote_pubkey: c.peer.to_string(),
+            channel_point: c.outpoint.map(|o| o.to_string()).unwrap_or_default(),
+            capacity: c.size.to_string(),
+            local_balance: (c.balance + c.reserve).to_string(),
+            remote_balance: c.size.saturating_sub(c.balance + c.reserve).to_string(),
+            local_chan_reserve_sat: c.reserve.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListChannelsResponse {
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chain {
+    pub chain: String,
+    pub network: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetInfoResponse {
+    pub version: String,
+    pub identity_pubkey: String,
+    pub num_active_channels: u32,
+    pub num_pending_channels: u32,
+    pub num_peers: u32,
+    pub chains: Vec<Chain>,
+}
+
+/// lnrpc encodes `int64` fields as strings, but accept numbers as well.
+fn int64_param(params: &Value, name: &str) -> Result<Option<u64>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => s
+            .parse()
+            .map(Some)
+            .map_err(|_| RpcError::new(INVALID_PARAMS, format!("invalid param {name}"))),
+        Some(_) => param(params, name),
+    }
+}
+
+/// Converts a hex string into the base64 `bytes` encoding of lnrpc
+fn hex_to_base64(hex: &str) -> Result<String, MutinyError> {
+    let bytes = Vec::<u8>::from_hex(hex).map_err(|_| MutinyError::InvalidArgumentsError)?;
+    Ok(base64::encode(bytes))
+}
+
+/// Returns `None` if the method is not one of the `lnrpc.Lightning` methods.
+pub(crate) async fn handle_lnrpc_request<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+    method: &str,
+    params: &Value,
+) -> Option<Result<Value, RpcError>> {
+    let method = method.strip_prefix(LNRPC_METHOD_PREFIX)?;
+    let result = match method {
+        "AddInvoice" => add_invoice(nm, params).await.and_then(to_value),
+        "SendPaymentSync" => send_payment_sync(nm, params).await.and_then(to_value),
+        "ListChannels" => list_channels(nm).await.and_then(to_value),
+        "GetInfo" => get_info(nm).await.and_then(to_value),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unsupported lnrpc method: {method}"),
+        )),
+    };
+    Some(result)
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    Ok(serde_json::to_value(value).map_err(MutinyError::from)?)
+}
+
+async fn add_invoice<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+    params: &Value,
+) -> Result<AddInvoiceResponse, RpcError> {
+    let amount = match int64_param(params, "value_msat")? {
+        Some(msats) => Some(msats / 1_000),
+        None => int64_param(params, "value")?,
+    };
+    // zero means any amount in lnrpc
+    let amount = amount.filter(|a| *a > 0);
+    let memo: Option<String> = param(params, "memo")?;
+    let labels = memo.filter(|m| !m.is_empty()).into_iter().collect();
+
+    let invoice = nm.create_invoice(amount, labels, None, None).await?;
+    let bolt11 = invoice.bolt11.ok_or(MutinyError::InvoiceCreationFailed)?;
+
+    Ok(AddInvoiceResponse {
+        r_hash: base64::encode(invoice.payment_hash.into_inner()),
+        payment_request: bolt11.to_string(),
+        add_index: invoice.last_updated.to_string(),
+        payment_addr: base64::encode(bolt11.payment_secret().0),
+    })
+}
+
+async fn send_payment_sync<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+    params: &Value,
+) -> Result<SendResponse, RpcError> {
+    let payment_request: String = param(params, "payment_request")?;
+    let invoice =
+        Bolt11Invoice::from_str(&payment_request).map_err(|_| MutinyError::InvoiceInvalid)?;
+    let amt_sats = int64_param(params, "amt")?.filter(|a| *a > 0);
+    let from_node = crate::rpc::from_node_param(nm, params).await?;
+
+    let payment_hash = base64::encode(invoice.payment_hash().into_inner());
+    // lnrpc reports payment failures in the response rather than as an error
+    let response = match nm
+        .pay_invoice(&from_node, &invoice, amt_sats, vec![], None, None, None)
+        .await
+    {
+        Ok(payment) => SendResponse {
+            payment_error: String::new(),
+            payment_preimage: match payment.preimage {
+                Some(preimage) => hex_to_base64(&preimage)?,
+                None => String::new(),
+            },
+            payment_hash,
+        },
+        Err(e) => SendResponse {
+            payment_error: e.to_string(),
+            payment_preimage: String::new(),
+            payment_hash,
+        },
+    };
+
+    Ok(response)
+}
+
+async fn list_channels<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+) -> Result<ListChannelsResponse, RpcError> {
+    let channels = nm
+        .list_channels()
+        .await?
+        .into_iter()
+        .map(Channel::from)
+        .collect();
+    Ok(ListChannelsResponse { channels })
+}
+
+async fn get_info<S: MutinyStorage>(nm: &NodeManager<S>) -> Result<GetInfoResponse, RpcError> {
+    let identity_pubkey = nm
+        .list_nodes()
+        .await?
+        .first()
+        .map(|pk| pk.to_string())
+        .unwrap_or_default();
+
+    let channels: Vec<Channel> = list_channels(nm).await?.channels;
+    let num_active_channels = channels.iter().filter(|c| c.active).count() as u32;
+    let num_pending_channels = channels.len() as u32 - num_active_channels;
+    let num_peers = nm
+        .list_peers()
+        .await?
+        .iter()
+        .filter(|p| p.is_connected)
+        .count() as u32;
+
+    let network = match nm.get_network() {
+        bitcoin::Network::Bitcoin => "mainnet",
+        bitcoin::Network::Testnet => "testnet",
+        bitcoin::Network::Signet => "signet",
+        bitcoin::Network::Regtest => "regtest",
+    };
+
+    Ok(GetInfoResponse {
+        version: format!("mutiny-{}", env!("CARGO_PKG_VERSION")),
+        identity_pubkey,
+        num_active_channels,
+        num_pending_channels,
+        num_peers,
+        chains: vec![Chain {
+            chain: "bitcoin".to_string(),
+            network: network.to_string(),
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::PublicKey;
+    use serde_json::json;
+
+    #[test]
+    fn test_int64_param() {
+        let params = json!({"value": "1000", "amt": 5, "bad": "abc"});
+        assert_eq!(int64_param(&params, "value").unwrap(), Some(1000));
+        assert_eq!(int64_param(&params, "amt").unwrap(), Some(5));
+        assert_eq!(int64_param(&params, "missing").unwrap(), None);
+        assert_eq!(
+            int64_param(&params, "bad").unwrap_err().code,
+            INVALID_PARAMS
+        );
+    }
+
+    #[test]
+    fn test_hex_to_base64() {
+        assert_eq!(hex_to_base64("deadbeef").unwrap(), "3q2+7w==");
+        assert!(hex_to_base64("xyz").is_err());
+    }
+
+    #[test]
+    fn test_channel_conversion() {
+        let peer = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let channel = MutinyChannel {
+            user_chan_id: "1".to_string(),
+            balance: 40_000,
+            size: 100_000,
+            reserve: 1_000,
+            outpoint: None,
+            peer,
+            confirmations_required: Some(3),
+            confirmations: 1,
+        };
+
+        let channel = Channel::from(channel);
+        assert!(!channel.active);
+        assert_eq!(channel.remote_pubkey, peer.to_string());
+        assert_eq!(channel.capacity, "100000");
+        assert_eq!(channel.local_balance, "41000");
+        assert_eq!(channel.remote_balance, "59000");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::service_auth::ServiceKeys;
 use crate::{error::MutinyError, logging::MutinyLogger};
 use anyhow::anyhow;
 use bdk_chain::collections::HashMap;
@@ -34,6 +35,11 @@ impl AuthManager {
         })
     }
 
+    /// The keys for signing requests to backend services, see [ServiceKeys]
+    pub fn service_keys(&self) -> ServiceKeys {
+        ServiceKeys::new(self.xprivkey)
+    }
+
     pub(crate) fn get_secret_key(&self, url: Url) -> Result<SecretKey, MutinyError> {
         let path = lnurl::get_derivation_path(self.hashing_key.secret_bytes(), &url)?;
         let key = self
@@ -64,6 +70,10 @@ pub(crate) async fn make_lnurl_auth_connection(
     lnurl: LnUrl,
     logger: Arc<MutinyLogger>,
 ) -> Result<(), MutinyError> {
+    if !lnurl.is_lnurl_auth() {
+        return Err(MutinyError::IncorrectLnUrlFunction);
+    }
+
     let url = Url::parse(&lnurl.url)?;
     let query_pairs: HashMap<String, String> = url
         .query_pairs()
@@ -116,4 +126,32 @@ mod test {
             .verify_ecdsa(&Message::from_slice(&k1).unwrap(), &sig, &pk)
             .unwrap();
     }
+
+    #[test]
+    async fn test_keys_per_domain() {
+        let test_name = "test_keys_per_domain";
+        log!("{}", test_name);
+
+        let auth = create_manager();
+
+        // the linking key only depends on the domain
+        let key = auth
+            .get_secret_key(Url::parse("https://mutinywallet.com/login?k1=00").unwrap())
+            .unwrap();
+        let same_domain = auth
+            .get_secret_key(Url::parse("https://mutinywallet.com/other?k1=11").unwrap())
+            .unwrap();
+        assert_eq!(key, same_domain);
+
+        let other_domain = auth
+            .get_secret_key(Url::parse("https://example.com/login?k1=00").unwrap())
+            .unwrap();
+        assert_ne!(key, other_domain);
+
+        // a different seed gets different keys for the same domain
+        let other_seed = create_manager()
+            .get_secret_key(Url::parse("https://mutinywallet.com/login?k1=00").unwrap())
+            .unwrap();
+        assert_ne!(key, other_seed);
+    }
 }
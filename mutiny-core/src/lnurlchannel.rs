@@ -0,0 +1,201 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use lnurl::lnurl::LnUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use url::Url;
+
+pub const LNURL_CHANNEL_PREFIX: &str = "lnurl_channel/";
+
+/// How long after a request we wait for the service to open the channel
+pub const LNURL_CHANNEL_TIMEOUT_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LnUrlChannelStatus {
+    /// The service accepted our request and should open the channel soon
+    Pending,
+    /// The service opened the channel and we accepted it
+    Opened { opened_at: u64 },
+    /// The service did not open the channel in time
+    Expired,
+    /// The service refused to open the channel
+    Failed { reason: String },
+}
+
+/// A channel requested from a LNURL-channel service, tracked until the service opens it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LnUrlChannelRequest {
+    pub lnurl: LnUrl,
+    /// The domain of the service, for showing where a channel came from
+    pub service: String,
+    /// The node the service opens the channel from
+    pub node_pubkey: PublicKey,
+    /// The connection string of the service's node
+    pub uri: String,
+    /// The k1 the service gave us for this request
+    pub k1: String,
+    pub private: bool,
+    pub created_at: u64,
+    pub status: LnUrlChannelStatus,
+}
+
+impl LnUrlChannelRequest {
+    fn key(&self) -> String {
+        format!("{LNURL_CHANNEL_PREFIX}{}", self.k1)
+    }
+
+    /// Whether a channel opened by `node_pubkey` should be accepted for this request
+    pub(crate) fn accepts_channel_from(&self, node_pubkey: &PublicKey, now: u64) -> bool {
+        self.status == LnUrlChannelStatus::Pending
+            && &self.node_pubkey == node_pubkey
+            && now < self.created_at + LNURL_CHANNEL_TIMEOUT_SECS
+    }
+
+    /// Expires a pending request the service didn't open a channel for in time.
+    /// Returns true if the status changed.
+    pub(crate) fn update(&mut self, now: u64) -> bool {
+        if self.status != LnUrlChannelStatus::Pending
+            || now < self.created_at + LNURL_CHANNEL_TIMEOUT_SECS
+        {
+            return false;
+        }
+
+        self.status = LnUrlChannelStatus::Expired;
+        true
+    }
+}
+
+/// The domain of the LNURL-channel service, falling back to the whole url
+pub(crate) fn lnurl_service(lnurl: &LnUrl) -> String {
+    Url::parse(&lnurl.url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_else(|| lnurl.url.clone())
+}
+
+/// The pubkey of the node in a LNURL-channel uri, `pubkey@host:port`
+pub(crate) fn parse_channel_uri(uri: &str) -> Result<PublicKey, MutinyError> {
+    let pubkey = uri.split('@').next().unwrap_or_default();
+    pubkey
+        .parse()
+        .map_err(|_| MutinyError::InvalidArgumentsError)
+}
+
+pub trait LnUrlChannelStorage {
+    fn persist_lnurl_channel(&self, request: LnUrlChannelRequest) -> Result<(), MutinyError>;
+    fn list_lnurl_channels(&self) -> Result<Vec<LnUrlChannelRequest>, MutinyError>;
+    /// Marks the pending request from `node_pubkey` as opened, returning it if there was one
+    fn open_lnurl_channel(
+        &self,
+        node_pubkey: &PublicKey,
+        now: u64,
+    ) -> Result<Option<LnUrlChannelRequest>, MutinyError>;
+}
+
+impl<S: MutinyStorage> LnUrlChannelStorage for S {
+    fn persist_lnurl_channel(&self, request: LnUrlChannelRequest) -> Result<(), MutinyError> {
+        self.set_data(request.key(), request, None)
+    }
+
+    fn list_lnurl_channels(&self) -> Result<Vec<LnUrlChannelRequest>, MutinyError> {
+        let map: HashMap<String, LnUrlChannelRequest> = self.scan(LNURL_CHANNEL_PREFIX, None)?;
+        let mut requests: Vec<LnUrlChannelRequest> = map.into_values().collect();
+        // newest first
+        requests.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(requests)
+    }
+
+    fn open_lnurl_channel(
+        &self,
+        node_pubkey: &PublicKey,
+        now: u64,
+    ) -> Result<Option<LnUrlChannelRequest>, MutinyError> {
+        let request = self
+            .list_lnurl_channels()?
+            .into_iter()
+            .find(|r| r.accepts_channel_from(node_pubkey, now));
+
+        match request {
+            Some(mut request) => {
+                request.status = LnUrlChannelStatus::Opened { opened_at: now };
+                self.persist_lnurl_channel(request.clone())?;
+                Ok(Some(request))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const NODE_PUBKEY: &str = "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b";
+
+    fn dummy_request() -> LnUrlChannelRequest {
+        let lnurl = LnUrl::from_str("LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS").unwrap();
+        let uri = format!("{NODE_PUBKEY}@127.0.0.1:9735");
+        LnUrlChannelRequest {
+            service: lnurl_service(&lnurl),
+            lnurl,
+            node_pubkey: parse_channel_uri(&uri).unwrap(),
+            uri,
+            k1: "k1".to_string(),
+            private: true,
+            created_at: 0,
+            status: LnUrlChannelStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn test_lnurl_channel_request() {
+        log!("test lnurl channel request");
+
+        let request = dummy_request();
+        assert_eq!(request.service, "service.com");
+        assert!(parse_channel_uri("not a pubkey@127.0.0.1:9735").is_err());
+
+        let node_pubkey = PublicKey::from_str(NODE_PUBKEY).unwrap();
+        assert!(request.accepts_channel_from(&node_pubkey, 100));
+        assert!(!request.accepts_channel_from(&node_pubkey, LNURL_CHANNEL_TIMEOUT_SECS));
+
+        let mut request = dummy_request();
+        assert!(!request.update(100));
+        assert!(request.update(LNURL_CHANNEL_TIMEOUT_SECS));
+        assert_eq!(request.status, LnUrlChannelStatus::Expired);
+        assert!(!request.accepts_channel_from(&node_pubkey, 100));
+    }
+
+    #[test]
+    fn test_lnurl_channel_storage() {
+        log!("test lnurl channel storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_lnurl_channels().unwrap().is_empty());
+
+        let request = dummy_request();
+        storage.persist_lnurl_channel(request.clone()).unwrap();
+
+        let node_pubkey = PublicKey::from_str(NODE_PUBKEY).unwrap();
+        let opened = storage
+            .open_lnurl_channel(&node_pubkey, 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(opened.status, LnUrlChannelStatus::Opened { opened_at: 100 });
+        // only accepted once
+        assert!(storage
+            .open_lnurl_channel(&node_pubkey, 100)
+            .unwrap()
+            .is_none());
+
+        assert_eq!(storage.list_lnurl_channels().unwrap(), vec![opened]);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::MutinyError;
+use bitcoin::hashes::{sha256, Hash};
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+use lnurl::lightning_address::LightningAddress;
+use lnurl::lnurl::LnUrl;
+use reqwest::Url;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// The successful response of a LNURL-pay callback
+#[derive(Debug, Deserialize)]
+pub(crate) struct LnUrlPayCallbackResponse {
+    pub pr: Bolt11Invoice,
+}
+
+/// Parses either a LNURL or a lightning address into the LNURL to call
+pub fn parse_lnurl_or_address(input: &str) -> Result<LnUrl, MutinyError> {
+    let input = input.trim();
+    let input = input.strip_prefix("lightning:").unwrap_or(input);
+
+    if input.contains('@') {
+        let address =
+            LightningAddress::from_str(input).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        return Ok(address.lnurl());
+    }
+
+    LnUrl::from_str(input).map_err(|_| MutinyError::InvalidArgumentsError)
+}
+
+/// Checks the amount is within what the LNURL-pay endpoint accepts
+pub(crate) fn check_pay_amount(
+    min_sendable: u64,
+    max_sendable: u64,
+    amount_msats: u64,
+) -> Result<(), MutinyError> {
+    if amount_msats < min_sendable || amount_msats > max_sendable {
+        return Err(MutinyError::BadAmountError);
+    }
+
+    Ok(())
+}
+
+/// Checks the invoice from a LNURL-pay callback is the one we asked for.
+///
+/// The amount has to match and the description hash has to commit to
+/// `description`, which is the LNURL metadata or, for zaps, the zap request.
+/// Otherwise the server could have us pay more, or pay for something else.
+pub(crate) fn check_pay_invoice(
+    invoice: &Bolt11Invoice,
+    amount_msats: u64,
+    description: &str,
+) -> Result<(), MutinyError> {
+    if invoice.amount_milli_satoshis() != Some(amount_msats) {
+        return Err(MutinyError::LnUrlFailure);
+    }
+
+    let matches = match invoice.description() {
+        Bolt11InvoiceDescription::Hash(hash) => {
+            hash.0 == sha256::Hash::hash(description.as_bytes())
+        }
+        // some servers put the metadata in the invoice instead of hashing it
+        Bolt11InvoiceDescription::Direct(direct) => direct.clone().into_inner() == description,
+    };
+    if !matches {
+        return Err(MutinyError::LnUrlFailure);
+    }
+
+    Ok(())
+}
+
+/// Builds the url of a LNURL-pay callback for an amount, with the zap request
+/// and the comment (LUD-12) if there are any.
+pub(crate) fn lnurl_pay_callback_url(
+    callback: &str,
+    amount_msats: u64,
+    zap_request: Option<&str>,
+    comment: Option<&str>,
+) -> Result<String, MutinyError> {
+    let mut url = Url::parse(callback).map_err(|_| MutinyError::LnUrlFailure)?;
+    {
+        let mut query = url.query_pairs_mut();
+        query.append_pair("amount", &amount_msats.to_string());
+        if let Some(zap_request) = zap_request {
+            query.append_pair("nostr", zap_request);
+        }
+        if let Some(comment) = comment {
+            query.append_pair("comment", comment);
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_parse_lnurl_or_address() {
+        log!("test parse lnurl or address");
+
+        let lnurl = parse_lnurl_or_address("bob@example.com").unwrap();
+        assert_eq!(lnurl.url, "https://example.com/.well-known/lnurlp/bob");
+
+        let lnurl = parse_lnurl_or_address("lightning:bob@example.com").unwrap();
+        assert_eq!(lnurl.url, "https://example.com/.well-known/lnurlp/bob");
+
+        let encoded = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        let lnurl = parse_lnurl_or_address(encoded).unwrap();
+        assert_eq!(lnurl, LnUrl::from_str(encoded).unwrap());
+
+        assert!(parse_lnurl_or_address("not an lnurl").is_err());
+    }
+
+    #[test]
+    fn test_check_pay_amount() {
+        log!("test check pay amount");
+
+        assert!(check_pay_amount(1_000, 100_000, 1_000).is_ok());
+        assert!(check_pay_amount(1_000, 100_000, 100_000).is_ok());
+        assert!(check_pay_amount(1_000, 100_000, 999).is_err());
+        assert!(check_pay_amount(1_000, 100_000, 100_001).is_err());
+    }
+
+    #[test]
+    fn test_lnurl_pay_callback_url() {
+        log!("test lnurl pay callback url");
+
+        let url = lnurl_pay_callback_url(
+            "https://example.com/lnurlp/ben/callback?id=1",
+            21_000,
+            None,
+            Some("thanks for the coffee"),
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://example.com/lnurlp/ben/callback?id=1&amount=21000&comment=thanks+for+the+coffee"
+        );
+
+        assert!(lnurl_pay_callback_url("not a url", 21_000, None, None).is_err());
+    }
+}
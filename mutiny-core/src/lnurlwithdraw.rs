@@ -0,0 +1,161 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::sha256;
+use lnurl::lnurl::LnUrl;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const LNURL_WITHDRAWAL_PREFIX: &str = "lnurl_withdrawal/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LnUrlWithdrawalStatus {
+    /// The service accepted our invoice and should pay it soon
+    Pending,
+    Paid,
+    /// The invoice expired before the service paid it
+    Expired,
+    /// The service refused to pay the invoice
+    Failed {
+        reason: String,
+    },
+}
+
+/// A withdrawal from a LNURL-withdraw service, tracked until the service pays our invoice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LnUrlWithdrawal {
+    pub lnurl: LnUrl,
+    /// The payment hash of the invoice we gave the service
+    pub payment_hash: sha256::Hash,
+    pub amount_sats: u64,
+    /// Unix timestamp in seconds of when the invoice expires
+    pub expires_at: u64,
+    pub created_at: u64,
+    pub status: LnUrlWithdrawalStatus,
+}
+
+impl LnUrlWithdrawal {
+    fn key(&self) -> String {
+        format!("{LNURL_WITHDRAWAL_PREFIX}{}", self.payment_hash)
+    }
+
+    /// Updates a pending withdrawal with whether our invoice has been paid.
+    /// Returns true if the status changed.
+    pub(crate) fn update(&mut self, paid: bool, now: u64) -> bool {
+        if self.status != LnUrlWithdrawalStatus::Pending {
+            return false;
+        }
+
+        if paid {
+            self.status = LnUrlWithdrawalStatus::Paid;
+        } else if now >= self.expires_at {
+            self.status = LnUrlWithdrawalStatus::Expired;
+        } else {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Checks the amount is within what the LNURL-withdraw service allows
+pub(crate) fn check_withdraw_amount(
+    min_withdrawable: Option<u64>,
+    max_withdrawable: u64,
+    amount_msats: u64,
+) -> Result<(), MutinyError> {
+    if amount_msats < min_withdrawable.unwrap_or(0) || amount_msats > max_withdrawable {
+        return Err(MutinyError::BadAmountError);
+    }
+
+    Ok(())
+}
+
+pub trait LnUrlWithdrawalStorage {
+    fn persist_lnurl_withdrawal(&self, withdrawal: LnUrlWithdrawal) -> Result<(), MutinyError>;
+    fn list_lnurl_withdrawals(&self) -> Result<Vec<LnUrlWithdrawal>, MutinyError>;
+}
+
+impl<S: MutinyStorage> LnUrlWithdrawalStorage for S {
+    fn persist_lnurl_withdrawal(&self, withdrawal: LnUrlWithdrawal) -> Result<(), MutinyError> {
+        self.set_data(withdrawal.key(), withdrawal, None)
+    }
+
+    fn list_lnurl_withdrawals(&self) -> Result<Vec<LnUrlWithdrawal>, MutinyError> {
+        let map: HashMap<String, LnUrlWithdrawal> = self.scan(LNURL_WITHDRAWAL_PREFIX, None)?;
+        let mut withdrawals: Vec<LnUrlWithdrawal> = map.into_values().collect();
+        // newest first
+        withdrawals.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(withdrawals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_withdrawal() -> LnUrlWithdrawal {
+        let lnurl = LnUrl::from_str("LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS").unwrap();
+        LnUrlWithdrawal {
+            lnurl,
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            amount_sats: 1_000,
+            expires_at: 3_600,
+            created_at: 0,
+            status: LnUrlWithdrawalStatus::Pending,
+        }
+    }
+
+    #[test]
+    fn test_lnurl_withdrawal_update() {
+        log!("test lnurl withdrawal update");
+
+        let mut withdrawal = dummy_withdrawal();
+        assert!(!withdrawal.update(false, 100));
+        assert_eq!(withdrawal.status, LnUrlWithdrawalStatus::Pending);
+
+        assert!(withdrawal.update(true, 100));
+        assert_eq!(withdrawal.status, LnUrlWithdrawalStatus::Paid);
+        // finished withdrawals don't change
+        assert!(!withdrawal.update(false, 10_000));
+
+        let mut withdrawal = dummy_withdrawal();
+        assert!(withdrawal.update(false, 3_600));
+        assert_eq!(withdrawal.status, LnUrlWithdrawalStatus::Expired);
+    }
+
+    #[test]
+    fn test_check_withdraw_amount() {
+        log!("test check withdraw amount");
+
+        assert!(check_withdraw_amount(None, 100_000, 1).is_ok());
+        assert!(check_withdraw_amount(Some(1_000), 100_000, 999).is_err());
+        assert!(check_withdraw_amount(Some(1_000), 100_000, 100_001).is_err());
+    }
+
+    #[test]
+    fn test_lnurl_withdrawal_storage() {
+        log!("test lnurl withdrawal storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_lnurl_withdrawals().unwrap().is_empty());
+
+        let mut withdrawal = dummy_withdrawal();
+        storage
+            .persist_lnurl_withdrawal(withdrawal.clone())
+            .unwrap();
+        withdrawal.update(true, 100);
+        storage
+            .persist_lnurl_withdrawal(withdrawal.clone())
+            .unwrap();
+
+        assert_eq!(storage.list_lnurl_withdrawals().unwrap(), vec![withdrawal]);
+    }
+}
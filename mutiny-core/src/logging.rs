@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
@@ -13,11 +14,16 @@ use log::*;
 pub(crate) const LOGGING_KEY: &str = "logs";
 
 const MAX_LOG_ITEMS: usize = 10_000;
+/// How many of the most recent log lines are kept in memory for crash reports
+const MAX_RECENT_LOG_ITEMS: usize = 100;
 
 #[derive(Clone)]
 pub struct MutinyLogger {
     should_write_to_storage: bool,
     memory_logs: Arc<Mutex<Vec<String>>>,
+    /// Ring buffer of the most recent logs, this is always kept
+    /// regardless of whether we are writing to storage
+    recent_logs: Arc<Mutex<VecDeque<String>>>,
 }
 
 impl MutinyLogger {
@@ -25,6 +31,7 @@ impl MutinyLogger {
         let l = MutinyLogger {
             should_write_to_storage: true,
             memory_logs: Arc::new(Mutex::new(vec![])),
+            recent_logs: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_RECENT_LOG_ITEMS))),
         };
 
         let log_copy = l.clone();
@@ -77,6 +84,15 @@ impl MutinyLogger {
         }
         get_logging_data(storage)
     }
+
+    /// Returns the most recent log lines that are kept in memory.
+    /// This does not block, if the logs are locked it will return nothing.
+    pub(crate) fn get_recent_logs(&self) -> Vec<String> {
+        match self.recent_logs.try_lock() {
+            Ok(recent_logs) => recent_logs.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
 }
 
 impl Default for MutinyLogger {
@@ -84,6 +100,7 @@ impl Default for MutinyLogger {
         Self {
             should_write_to_storage: Default::default(),
             memory_logs: Arc::new(Mutex::new(vec![])),
+            recent_logs: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_RECENT_LOG_ITEMS))),
         }
     }
 }
@@ -103,6 +120,13 @@ impl Logger for MutinyLogger {
             raw_log
         );
 
+        if let Ok(mut recent_logs) = self.recent_logs.lock() {
+            if recent_logs.len() >= MAX_RECENT_LOG_ITEMS {
+                recent_logs.pop_front();
+            }
+            recent_logs.push_back(log.clone());
+        }
+
         if self.should_write_to_storage && record.level >= Level::Trace {
             if let Ok(mut memory_logs) = self.memory_logs.lock() {
                 memory_logs.push(log.clone());
@@ -232,4 +256,23 @@ mod tests {
 
         stop.swap(true, Ordering::Relaxed);
     }
+
+    #[test]
+    fn recent_logs_are_bounded() {
+        let test_name = "recent_logs_are_bounded";
+        log!("{}", test_name);
+
+        let logger = MutinyLogger::default();
+        for i in 0..(super::MAX_RECENT_LOG_ITEMS + 10) {
+            log_debug!(logger, "log {i}");
+        }
+
+        let recent = logger.get_recent_logs();
+        assert_eq!(recent.len(), super::MAX_RECENT_LOG_ITEMS);
+        assert!(recent.first().unwrap().contains("log 10"));
+        assert!(recent
+            .last()
+            .unwrap()
+            .contains(&format!("log {}", super::MAX_RECENT_LOG_ITEMS + 9)));
+    }
 }
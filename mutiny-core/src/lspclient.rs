@@ -1,15 +1,26 @@
 use bitcoin::secp256k1::PublicKey;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 use crate::error::MutinyError;
+use crate::http_client::{HttpRequest, MutinyHttpClient};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub(crate) struct LspClient {
     pub pubkey: PublicKey,
     pub connection_string: String,
     pub url: String,
-    pub http_client: Client,
+    pub http_client: Arc<dyn MutinyHttpClient>,
+}
+
+impl std::fmt::Debug for LspClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LspClient")
+            .field("pubkey", &self.pubkey)
+            .field("connection_string", &self.connection_string)
+            .field("url", &self.url)
+            .finish()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,15 +85,15 @@ const PROPOSAL_PATH: &str = "/api/v1/proposal";
 const FEE_PATH: &str = "/api/v1/fee";
 
 impl LspClient {
-    pub async fn new(url: &str) -> Result<Self, MutinyError> {
-        let http_client = Client::new();
+    pub async fn new(
+        url: &str,
+        http_client: Arc<dyn MutinyHttpClient>,
+    ) -> Result<Self, MutinyError> {
         let get_info_response: GetInfoResponse = http_client
-            .get(format!("{}{}", url, GET_INFO_PATH))
-            .send()
+            .send(HttpRequest::get(format!("{}{}", url, GET_INFO_PATH)))
             .await
             .map_err(|_| MutinyError::LspGenericError)?
             .json()
-            .await
             .map_err(|_| MutinyError::LspGenericError)?;
 
         let connection_string = get_info_response
@@ -127,28 +138,21 @@ impl LspClient {
             port: None,
         };
 
-        let response: reqwest::Response = self
+        let request = HttpRequest::post_json(format!("{}{}", &self.url, PROPOSAL_PATH), &payload)?;
+        let response = self
             .http_client
-            .post(format!("{}{}", &self.url, PROPOSAL_PATH))
-            .json(&payload)
-            .send()
+            .send(request)
             .await
             .map_err(|_| MutinyError::LspGenericError)?;
 
-        let status = response.status().as_u16();
-        if (200..300).contains(&status) {
-            let proposal_response: ProposalResponse = response
-                .json()
-                .await
-                .map_err(|_| MutinyError::LspGenericError)?;
+        if response.is_success() {
+            let proposal_response: ProposalResponse =
+                response.json().map_err(|_| MutinyError::LspGenericError)?;
 
             return Ok(proposal_response.jit_bolt11);
-        } else if response.status().as_u16() >= 400 {
+        } else if response.status >= 400 {
             // If it's not OK, copy the response body to a string and try to parse as ErrorResponse
-            let response_body = response
-                .text()
-                .await
-                .map_err(|_| MutinyError::LspGenericError)?;
+            let response_body = response.text().map_err(|_| MutinyError::LspGenericError)?;
 
             if let Ok(error_body) = serde_json::from_str::<ErrorResponse>(&response_body) {
                 if error_body.error == "Internal Server Error" {
@@ -170,15 +174,13 @@ impl LspClient {
         &self,
         fee_request: FeeRequest,
     ) -> Result<u64, MutinyError> {
+        let request = HttpRequest::post_json(format!("{}{}", &self.url, FEE_PATH), &fee_request)?;
         let fee_response: FeeResponse = self
             .http_client
-            .post(format!("{}{}", &self.url, FEE_PATH))
-            .json(&fee_request)
-            .send()
+            .send(request)
             .await
             .map_err(|_| MutinyError::LspGenericError)?
             .json()
-            .await
             .map_err(|_| MutinyError::LspGenericError)?;
 
         Ok(fee_response.fee_amount_msat)
@@ -1,3 +1,5 @@
+use crate::error::MutinyError;
+use crate::http_client::{MutinyHttpClient, ReqwestHttpClient};
 use async_trait::async_trait;
 use bdk_chain::{
     bitcoin::{BlockHash, OutPoint, Script, Txid},
@@ -15,9 +17,28 @@ use reqwest::Client;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+/// How many esplora requests are made at once while syncing, unless configured otherwise
+pub const DEFAULT_SYNC_PARALLEL_REQUESTS: usize = 5;
+
+/// How long idle connections to esplora are kept open to be reused by the next sync
+#[cfg(not(target_arch = "wasm32"))]
+const ESPLORA_KEEP_ALIVE: core::time::Duration = core::time::Duration::from_secs(90);
+
+#[derive(Clone)]
 pub struct MultiEsploraClient {
     clients: Vec<Arc<AsyncClient>>,
+    parallel_requests: usize,
+    /// Used for the fee requests, chain sync goes through the esplora clients
+    http_client: Arc<dyn MutinyHttpClient>,
+}
+
+impl std::fmt::Debug for MultiEsploraClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MultiEsploraClient")
+            .field("clients", &self.clients)
+            .field("parallel_requests", &self.parallel_requests)
+            .finish()
+    }
 }
 
 impl MultiEsploraClient {
@@ -26,7 +47,53 @@ impl MultiEsploraClient {
             panic!("No esplora clients provided");
         }
 
-        Self { clients }
+        let http_client = Arc::new(ReqwestHttpClient::new(clients[0].client().clone()));
+        Self {
+            clients,
+            parallel_requests: DEFAULT_SYNC_PARALLEL_REQUESTS,
+            http_client,
+        }
+    }
+
+    /// Creates a client for a space separated list of esplora urls.
+    /// The urls share one http client, so connections are kept alive
+    /// and reused between requests instead of reconnecting for each one.
+    pub fn from_urls(esplora_server_url: &str) -> Result<Self, MutinyError> {
+        let builder = Client::builder();
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = builder
+            .pool_idle_timeout(ESPLORA_KEEP_ALIVE)
+            .tcp_keepalive(ESPLORA_KEEP_ALIVE);
+        let http_client = builder.build().map_err(|e| MutinyError::Other(e.into()))?;
+
+        let clients = esplora_server_url
+            .split(' ')
+            .map(|url| {
+                Arc::new(AsyncClient::from_client(
+                    url.to_string(),
+                    http_client.clone(),
+                ))
+            })
+            .collect();
+
+        Ok(Self::new(clients))
+    }
+
+    /// Sets how many requests are made at once while syncing, at least one.
+    pub fn with_parallel_requests(mut self, parallel_requests: usize) -> Self {
+        self.parallel_requests = parallel_requests.max(1);
+        self
+    }
+
+    /// How many requests are made at once while syncing
+    pub fn parallel_requests(&self) -> usize {
+        self.parallel_requests
+    }
+
+    /// Sets the client used for the fee requests
+    pub fn with_http_client(mut self, http_client: Arc<dyn MutinyHttpClient>) -> Self {
+        self.http_client = http_client;
+        self
     }
 
     /// Broadcast a [`Transaction`] to Esplora
@@ -35,9 +102,8 @@ impl MultiEsploraClient {
         client.clone()
     }
 
-    pub(crate) fn client(&self) -> Client {
-        let client = self.get_random_client();
-        client.client().to_owned()
+    pub(crate) fn http_client(&self) -> &dyn MutinyHttpClient {
+        self.http_client.as_ref()
     }
 
     pub(crate) fn url(&self) -> String {
@@ -81,6 +147,16 @@ impl MultiEsploraClient {
         client.scripthash_txs(script, last_seen).await
     }
 
+    /// Get the [`Txid`] of the transaction at the given index of a block
+    pub async fn get_txid_at_block_index(
+        &self,
+        block_hash: &BlockHash,
+        index: usize,
+    ) -> Result<Option<Txid>, Error> {
+        let client = self.get_random_client();
+        client.get_txid_at_block_index(block_hash, index).await
+    }
+
     /// Get a [`Transaction`] option given its [`Txid`]
     pub async fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
         let client = self.get_random_client();
@@ -243,50 +319,86 @@ impl EsploraAsyncExt for MultiEsploraClient {
             }
         }
 
-        for txid in txids.into_iter() {
-            if update.graph.get_tx(txid).is_none() {
-                match self.get_tx(&txid).await? {
-                    Some(tx) => {
-                        let _ = update.graph.insert_tx(tx);
-                    }
-                    None => continue,
-                }
+        // unlike bdk, look up the txids and outpoints in batches of parallel requests too
+        let mut txids = txids.into_iter();
+        loop {
+            let futures = (0..parallel_requests)
+                .filter_map(|_| {
+                    let txid = txids.next()?;
+                    let known = update.graph.get_tx(txid).is_some();
+                    let client = self.clone();
+                    Some(async move {
+                        let tx = if known {
+                            None
+                        } else {
+                            match client.get_tx(&txid).await? {
+                                Some(tx) => Some(tx),
+                                None => return Result::<_, Error>::Ok(None),
+                            }
+                        };
+                        let tx_status = client.get_tx_status(&txid).await?;
+                        Ok(Some((txid, tx, tx_status)))
+                    })
+                })
+                .collect::<FuturesOrdered<_>>();
+
+            if futures.is_empty() {
+                break;
             }
-            match self.get_tx_status(&txid).await? {
-                tx_status if tx_status.confirmed => {
+
+            for (txid, tx, tx_status) in
+                futures.try_collect::<Vec<_>>().await?.into_iter().flatten()
+            {
+                if let Some(tx) = tx {
+                    let _ = update.graph.insert_tx(tx);
+                }
+                if tx_status.confirmed {
                     if let Some(anchor) = map_confirmation_time_anchor(&tx_status, tip_at_start) {
                         let _ = update.graph.insert_anchor(txid, anchor);
                     }
                 }
-                _ => continue,
             }
         }
 
-        for op in outpoints.into_iter() {
-            let mut op_txs = Vec::with_capacity(2);
-            if let (
-                Some(tx),
-                tx_status @ TxStatus {
-                    confirmed: true, ..
-                },
-            ) = (
-                self.get_tx(&op.txid).await?,
-                self.get_tx_status(&op.txid).await?,
-            ) {
-                op_txs.push((tx, tx_status));
-                if let Some(OutputStatus {
-                    txid: Some(txid),
-                    status: Some(spend_status),
-                    ..
-                }) = self.get_output_status(&op.txid, op.vout as _).await?
-                {
-                    if let Some(spend_tx) = self.get_tx(&txid).await? {
-                        op_txs.push((spend_tx, spend_status));
-                    }
-                }
+        let mut outpoints = outpoints.into_iter();
+        loop {
+            let futures = (0..parallel_requests)
+                .filter_map(|_| {
+                    let op = outpoints.next()?;
+                    let client = self.clone();
+                    Some(async move {
+                        let mut op_txs = Vec::with_capacity(2);
+                        if let (
+                            Some(tx),
+                            tx_status @ TxStatus {
+                                confirmed: true, ..
+                            },
+                        ) = (
+                            client.get_tx(&op.txid).await?,
+                            client.get_tx_status(&op.txid).await?,
+                        ) {
+                            op_txs.push((tx, tx_status));
+                            if let Some(OutputStatus {
+                                txid: Some(txid),
+                                status: Some(spend_status),
+                                ..
+                            }) = client.get_output_status(&op.txid, op.vout as _).await?
+                            {
+                                if let Some(spend_tx) = client.get_tx(&txid).await? {
+                                    op_txs.push((spend_tx, spend_status));
+                                }
+                            }
+                        }
+                        Result::<_, Error>::Ok(op_txs)
+                    })
+                })
+                .collect::<FuturesOrdered<_>>();
+
+            if futures.is_empty() {
+                break;
             }
 
-            for (tx, status) in op_txs {
+            for (tx, status) in futures.try_collect::<Vec<_>>().await?.into_iter().flatten() {
                 let txid = tx.txid();
                 let anchor = map_confirmation_time_anchor(&status, tip_at_start);
 
@@ -1,7 +1,16 @@
+use crate::allowlist::PeerAllowlistStorage;
+use crate::hold_invoice::{HoldInvoice, HoldInvoiceStatus, HoldInvoiceStorage};
+use crate::justice::JusticeProof;
 use crate::keymanager::PhantomKeysManager;
 use crate::labels::LabelStorage;
 use crate::ldkstorage::ChannelOpenParams;
-use crate::nodemanager::ChannelClosure;
+use crate::nodemanager::{ChannelClosure, PaymentFailure};
+use crate::payment_metadata::PaymentMetadataStorage;
+use crate::payment_retry::{PaymentRetry, PaymentRetryStorage, RetryPolicy};
+use crate::payment_status::{
+    failure_reason_message, send_failure_reason, PaymentState, PaymentSubscriptions,
+};
+use crate::router::FeeCappedRouter;
 use crate::scb::StaticChannelBackup;
 use crate::{
     background::process_events_async,
@@ -9,15 +18,26 @@ use crate::{
     error::{MutinyError, MutinyStorageError},
     event::{EventHandler, HTLCStatus, MillisatAmount, PaymentInfo},
     fees::MutinyFeeEstimator,
-    gossip::{get_all_peers, read_peer_info, save_peer_connection_info},
+    gossip::{
+        blocks_since_gossip_sync, get_all_peers, read_peer_info, save_peer_connection_info,
+        GossipRateLimiter, GossipStats, GOSSIP_SYNC_TIME_KEY,
+    },
     keymanager::{create_keys_manager, pubkey_from_keys_manager},
     ldkstorage::{MutinyNodePersister, PhantomChannelManager},
     logging::MutinyLogger,
     lspclient::LspClient,
-    nodemanager::{MutinyInvoice, NodeIndex},
+    nodemanager::{
+        ChannelDebugInfo, CltvConfig, CommitmentHtlcDebugInfo, CustomTlv, HtlcState, MutinyInvoice,
+        NodeIndex, PaymentEstimate, PaymentHtlc, PendingCloseOutput, PendingCloseOutputKind,
+        PendingHtlcDebugInfo, ProbeResult, ReconnectionStatus,
+    },
+    offline_receive::OfflineReceiveHandler,
     onchain::OnChainWallet,
-    peermanager::{GossipMessageHandler, PeerManager, PeerManagerImpl},
+    peermanager::{
+        GossipMessageHandler, GossipQueries, MutinyMessageHandler, PeerManager, PeerManagerImpl,
+    },
     utils::{self, sleep},
+    utxo::EsploraUtxoLookup,
 };
 
 use crate::scb::message_handler::SCBMessageHandler;
@@ -25,11 +45,12 @@ use crate::{fees::P2WSH_OUTPUT_SIZE, peermanager::connect_peer_if_necessary};
 use crate::{lspclient::FeeRequest, storage::MutinyStorage};
 use anyhow::{anyhow, Context};
 use bdk::FeeRate;
+use bitcoin::bech32::ToBase32;
 use bitcoin::hashes::{hex::ToHex, sha256::Hash as Sha256};
 use bitcoin::secp256k1::rand;
 use bitcoin::{hashes::Hash, secp256k1::PublicKey, BlockHash, Network, OutPoint};
 use core::time::Duration;
-use lightning::chain::channelmonitor::ChannelMonitor;
+use lightning::chain::channelmonitor::{Balance, ChannelMonitor};
 use lightning::util::ser::{ReadableArgs, Writeable};
 use lightning::{
     chain::chaininterface::{ConfirmationTarget, FeeEstimator},
@@ -41,12 +62,12 @@ use lightning::{
 use crate::multiesplora::MultiEsploraClient;
 use bitcoin::util::bip32::ExtendedPrivKey;
 use lightning::ln::PaymentSecret;
-use lightning::sign::{EntropySource, InMemorySigner};
+use lightning::sign::{EntropySource, InMemorySigner, NodeSigner, Recipient};
 use lightning::util::config::MaxDustHTLCExposure;
 use lightning::{
     chain::{chainmonitor, Filter, Watch},
     ln::{
-        channelmanager::{PaymentId, PhantomRouteHints, Retry},
+        channelmanager::{ChannelDetails, PaymentId, PhantomRouteHints, Retry},
         peer_handler::{IgnoringMessageHandler, MessageHandler as LdkMessageHandler},
         PaymentHash, PaymentPreimage,
     },
@@ -54,7 +75,7 @@ use lightning::{
     routing::{
         gossip,
         gossip::NodeId,
-        router::{DefaultRouter, PaymentParameters, RouteParameters},
+        router::{DefaultRouter, Path, PaymentParameters, Route, RouteParameters, Router as _},
         scoring::ProbabilisticScorer,
     },
     util::{
@@ -65,21 +86,29 @@ use lightning::{
 use lightning_invoice::payment::PaymentError;
 use lightning_invoice::{
     payment::{pay_invoice, pay_zero_value_invoice},
-    utils::{create_invoice_from_channelmanager_and_duration_since_epoch, create_phantom_invoice},
-    Bolt11Invoice,
+    utils::{create_phantom_invoice, create_phantom_invoice_with_description_hash},
+    Bolt11Invoice, Bolt11InvoiceDescription, InvoiceBuilder, RouteHint, RouteHintHop, RoutingFees,
 };
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::{
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc, RwLock,
     },
 };
 
 const DEFAULT_PAYMENT_TIMEOUT: u64 = 30;
+/// Most route hints put in an invoice, each one makes the invoice bigger
+const MAX_ROUTE_HINTS: usize = 3;
+/// How long invoices are valid for when no expiry is given
+pub(crate) const DEFAULT_INVOICE_EXPIRY_SECS: u32 = 60 * 60;
 const INITIAL_RECONNECTION_DELAY: u64 = 5;
 const MAX_RECONNECTION_DELAY: u64 = 60;
+/// How long to wait after a channel's outputs become spendable before
+/// sweeping them, so other closed channels can be swept in the same transaction
+const SWEEP_BATCH_WINDOW_SECS: u64 = 60 * 60;
 
 pub(crate) type RapidGossipSync =
     lightning_rapid_gossip_sync::RapidGossipSync<Arc<NetworkGraph>, Arc<MutinyLogger>>;
@@ -90,7 +119,7 @@ pub(crate) type MessageHandler<S: MutinyStorage> = LdkMessageHandler<
     Arc<PhantomChannelManager<S>>,
     Arc<GossipMessageHandler<S>>,
     Arc<IgnoringMessageHandler>,
-    Arc<SCBMessageHandler>,
+    Arc<MutinyMessageHandler<S>>,
 >;
 
 pub(crate) type ChainMonitor<S: MutinyStorage> = chainmonitor::ChainMonitor<
@@ -102,12 +131,15 @@ pub(crate) type ChainMonitor<S: MutinyStorage> = chainmonitor::ChainMonitor<
     Arc<MutinyNodePersister<S>>,
 >;
 
-pub(crate) type Router = DefaultRouter<
-    Arc<NetworkGraph>,
-    Arc<MutinyLogger>,
-    Arc<utils::Mutex<ProbScorer>>,
-    ProbabilisticScoringFeeParameters,
-    ProbScorer,
+pub(crate) type Router<S: MutinyStorage> = FeeCappedRouter<
+    DefaultRouter<
+        Arc<NetworkGraph>,
+        Arc<MutinyLogger>,
+        Arc<utils::Mutex<ProbScorer>>,
+        ProbabilisticScoringFeeParameters,
+        ProbScorer,
+    >,
+    S,
 >;
 
 pub(crate) type ProbScorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<MutinyLogger>>;
@@ -157,6 +189,13 @@ pub(crate) struct Node<S: MutinyStorage> {
     logger: Arc<MutinyLogger>,
     pub(crate) lsp_client: Option<LspClient>,
     stop: Arc<AtomicBool>,
+    event_handler: EventHandler<S>,
+    gossip_handler: Arc<GossipMessageHandler<S>>,
+    router: Arc<Router<S>>,
+    scorer: Arc<utils::Mutex<ProbScorer>>,
+    /// The result of the last reconnection attempt for each peer
+    reconnection_status: Arc<RwLock<HashMap<NodeId, ReconnectionStatus>>>,
+    cltv_config: CltvConfig,
     #[cfg(target_arch = "wasm32")]
     websocket_proxy_addr: String,
 }
@@ -177,7 +216,11 @@ impl<S: MutinyStorage> Node<S> {
         esplora: &MultiEsploraClient,
         lsp_clients: &[LspClient],
         logger: Arc<MutinyLogger>,
+        generation: Arc<AtomicU64>,
         do_not_connect_peers: bool,
+        validate_gossip: bool,
+        cltv_config: CltvConfig,
+        payment_subscriptions: Arc<PaymentSubscriptions>,
         empty_state: bool,
         #[cfg(target_arch = "wasm32")] websocket_proxy_addr: String,
     ) -> Result<Self, MutinyError> {
@@ -209,30 +252,47 @@ impl<S: MutinyStorage> Node<S> {
             fee_estimator.clone(),
             persister.clone(),
         ));
+        persister.set_chain_monitor(chain_monitor.clone());
 
         // read channelmonitor state from disk
         let channel_monitors = if empty_state {
             vec![]
         } else {
-            persister
-                .read_channel_monitors(keys_manager.clone())
-                .map_err(|e| MutinyError::ReadError {
-                    source: MutinyStorageError::Other(anyhow!(
-                        "failed to read channel monitors: {e}"
-                    )),
-                })?
+            persister.read_channel_monitors(
+                keys_manager.clone(),
+                chain.as_ref(),
+                fee_estimator.as_ref(),
+            )?
         };
 
+        // save the replayed monitor updates into the monitors so the next startup is faster
+        for (_, monitor) in channel_monitors.iter() {
+            if let Err(e) = persister.compact_monitor_updates(monitor) {
+                log_warn!(logger, "failed to compact channel monitor updates: {e}");
+            }
+        }
+
         let network_graph = gossip_sync.network_graph().clone();
 
-        let router: Arc<Router> = Arc::new(DefaultRouter::new(
-            network_graph,
+        let router: Arc<Router<S>> = Arc::new(FeeCappedRouter::new(
+            DefaultRouter::new(
+                network_graph,
+                logger.clone(),
+                keys_manager.clone().get_secure_random_bytes(),
+                scorer.clone(),
+                scoring_params(),
+            ),
+            persister.storage.clone(),
             logger.clone(),
-            keys_manager.clone().get_secure_random_bytes(),
-            scorer.clone(),
-            scoring_params(),
         ));
 
+        // channels opened to us get the inbound HTLC limits from the default config
+        let mut user_config = default_user_config(&cltv_config);
+        persister
+            .storage
+            .get_inbound_htlc_limits()?
+            .apply(&mut user_config.channel_handshake_config);
+
         // init channel manager
         let mut read_channel_manager = if empty_state {
             MutinyNodePersister::create_new_channel_manager(
@@ -243,6 +303,7 @@ impl<S: MutinyStorage> Node<S> {
                 logger.clone(),
                 keys_manager.clone(),
                 router.clone(),
+                user_config.clone(),
                 channel_monitors,
                 esplora,
             )
@@ -257,6 +318,7 @@ impl<S: MutinyStorage> Node<S> {
                     logger.clone(),
                     keys_manager.clone(),
                     router.clone(),
+                    user_config,
                     channel_monitors,
                     esplora,
                 )
@@ -269,7 +331,7 @@ impl<S: MutinyStorage> Node<S> {
         // Check all existing channels against default configs.
         // If we have default config changes, those should apply
         // to all existing and new channels.
-        let default_config = default_user_config().channel_config;
+        let default_config = default_user_config(&cltv_config).channel_config;
         for channel in channel_manager.list_channels() {
             // unwrap is safe after LDK.0.0.109
             if channel.config.unwrap() != default_config {
@@ -296,21 +358,6 @@ impl<S: MutinyStorage> Node<S> {
             }
         }
 
-        let route_handler = Arc::new(GossipMessageHandler {
-            storage: persister.storage.clone(),
-            network_graph: gossip_sync.network_graph().clone(),
-            logger: logger.clone(),
-        });
-
-        // init peer manager
-        let scb_message_handler = Arc::new(SCBMessageHandler::new());
-        let ln_msg_handler = MessageHandler {
-            chan_handler: channel_manager.clone(),
-            route_handler,
-            onion_message_handler: Arc::new(IgnoringMessageHandler {}),
-            custom_message_handler: scb_message_handler.clone(),
-        };
-
         log_info!(logger, "creating lsp client");
         let lsp_client: Option<LspClient> = match node_index.lsp {
             None => {
@@ -330,15 +377,62 @@ impl<S: MutinyStorage> Node<S> {
 
         let lsp_client_pubkey = lsp_client.clone().map(|lsp| lsp.pubkey);
 
+        let utxo_lookup = validate_gossip.then(|| {
+            Arc::new(EsploraUtxoLookup::new(
+                esplora.clone(),
+                gossip_sync.network_graph().clone(),
+                logger.clone(),
+            ))
+        });
+        let offline_receive = Arc::new(OfflineReceiveHandler::new(
+            persister.clone(),
+            lsp_client_pubkey,
+            logger.clone(),
+        ));
+        let gossip_handler = Arc::new(GossipMessageHandler {
+            storage: persister.storage.clone(),
+            network_graph: gossip_sync.network_graph().clone(),
+            lsp_pubkey: lsp_client_pubkey,
+            rate_limiter: Arc::new(utils::Mutex::new(GossipRateLimiter::default())),
+            gossip_queries: Arc::new(utils::Mutex::new(GossipQueries::default())),
+            utxo_lookup,
+            network,
+            offline_receive: offline_receive.clone(),
+            logger: logger.clone(),
+        });
+
+        // init peer manager
+        let scb_message_handler = Arc::new(SCBMessageHandler::new());
+        let ln_msg_handler = MessageHandler {
+            chan_handler: channel_manager.clone(),
+            route_handler: gossip_handler.clone(),
+            // Onion messages are ignored for now. Responding to BOLT12 invoice_requests
+            // for our offers needs an OffersMessageHandler and ChannelManager support for
+            // building invoices, neither of which exist in the LDK version we use.
+            // The same applies to BOLT12 refunds, we can't create a refund or
+            // receive the invoice for one until the offers flow is supported.
+            // Creating and paying offers is blocked on the same thing, the ChannelManager
+            // in this LDK version can't build offers or pay one through an invoice_request.
+            onion_message_handler: Arc::new(IgnoringMessageHandler {}),
+            custom_message_handler: Arc::new(MutinyMessageHandler {
+                scb: scb_message_handler.clone(),
+                offline_receive,
+            }),
+        };
+
         // init event handler
         let event_handler = EventHandler::new(
             channel_manager.clone(),
+            chain_monitor.clone(),
             fee_estimator.clone(),
             wallet.clone(),
             keys_manager.clone(),
             persister.clone(),
+            router.clone(),
             lsp_client_pubkey,
             logger.clone(),
+            generation,
+            payment_subscriptions,
         );
 
         let peer_man = Arc::new(create_peer_manager(
@@ -402,31 +496,15 @@ impl<S: MutinyStorage> Node<S> {
                     retry_spendable_outputs.len()
                 );
 
-                match event_handler
-                    .handle_spendable_outputs(&retry_spendable_outputs)
-                    .await
-                {
-                    Ok(_) => {
-                        log_info!(logger, "Successfully retried spendable outputs");
-                        persister.clear_failed_spendable_outputs()?;
-                    }
-                    Err(_) => {
-                        // retry them individually then only save failed ones
-                        // if there was only one we don't need to retry
-                        if retry_spendable_outputs.len() > 1 {
-                            let mut failed = vec![];
-                            for o in retry_spendable_outputs {
-                                if event_handler
-                                    .handle_spendable_outputs(&[o.clone()])
-                                    .await
-                                    .is_err()
-                                {
-                                    failed.push(o);
-                                }
-                            }
-                            persister.set_failed_spendable_outputs(failed)?;
-                        };
-                    }
+                let failed = event_handler
+                    .sweep_spendable_outputs(&retry_spendable_outputs)
+                    .await;
+                if failed.is_empty() {
+                    log_info!(logger, "Successfully retried spendable outputs");
+                    persister.clear_failed_spendable_outputs()?;
+                } else {
+                    // only keep the ones that still failed, the rest were broadcast
+                    persister.set_failed_spendable_outputs(failed)?;
                 }
             }
         }
@@ -442,6 +520,7 @@ impl<S: MutinyStorage> Node<S> {
         let background_gossip_sync = gossip_sync.clone();
         let background_logger = logger.clone();
         let background_stop = stop.clone();
+        let background_scorer = scorer.clone();
         stopped_components.try_write()?.push(false);
         let background_stopped_components = stopped_components.clone();
         utils::spawn(async move {
@@ -456,7 +535,7 @@ impl<S: MutinyStorage> Node<S> {
                     gs,
                     background_processor_peer_manager.clone(),
                     background_processor_logger.clone(),
-                    Some(scorer.clone()),
+                    Some(background_scorer.clone()),
                     |d| {
                         let background_event_stop = background_stop.clone();
                         Box::pin(async move {
@@ -488,6 +567,7 @@ impl<S: MutinyStorage> Node<S> {
             }
         });
 
+        let reconnection_status = Arc::new(RwLock::new(HashMap::new()));
         if !do_not_connect_peers {
             #[cfg(target_arch = "wasm32")]
             let reconnection_proxy_addr = websocket_proxy_addr.clone();
@@ -501,6 +581,7 @@ impl<S: MutinyStorage> Node<S> {
             let reconnection_lsp_client = lsp_client.clone();
             let reconnection_stop = stop.clone();
             let reconnection_stopped_comp = stopped_components.clone();
+            let reconnection_status = reconnection_status.clone();
             reconnection_stopped_comp.try_write()?.push(false);
             utils::spawn(async move {
                 start_reconnection_handling(
@@ -515,6 +596,7 @@ impl<S: MutinyStorage> Node<S> {
                     &reconnection_lsp_client,
                     reconnection_stop,
                     reconnection_stopped_comp,
+                    reconnection_status,
                     network == Network::Regtest,
                 )
                 .await;
@@ -538,6 +620,12 @@ impl<S: MutinyStorage> Node<S> {
             logger,
             lsp_client,
             stop,
+            event_handler,
+            gossip_handler,
+            router,
+            scorer,
+            reconnection_status,
+            cltv_config,
             #[cfg(target_arch = "wasm32")]
             websocket_proxy_addr,
         })
@@ -569,6 +657,71 @@ impl<S: MutinyStorage> Node<S> {
         Ok(())
     }
 
+    /// Counters for the gossip messages our peers have sent us
+    pub(crate) fn gossip_stats(&self) -> GossipStats {
+        self.gossip_handler.gossip_stats()
+    }
+
+    /// Asks a connected peer for the channels we're missing to pay the invoice,
+    /// so we can route through channels that are newer than our RGS snapshot.
+    ///
+    /// Channels in the invoice's route hints are asked for directly. If we don't
+    /// know the payee or any of its hint nodes at all, we ask for the channels
+    /// opened since the snapshot instead.
+    pub(crate) fn request_missing_channels(&self, invoice: &Bolt11Invoice) {
+        let payee = invoice
+            .payee_pub_key()
+            .cloned()
+            .unwrap_or_else(|| invoice.recover_payee_pub_key());
+        let hops: Vec<RouteHintHop> = invoice
+            .route_hints()
+            .into_iter()
+            .flat_map(|hint| hint.0)
+            .collect();
+
+        let (missing_scids, known_endpoint) = {
+            let graph = self.gossip_handler.network_graph.read_only();
+            let missing_scids: Vec<u64> = hops
+                .iter()
+                .map(|hop| hop.short_channel_id)
+                .filter(|scid| !graph.channels().contains_key(scid))
+                .collect();
+            let known_endpoint = std::iter::once(&payee)
+                .chain(hops.iter().map(|hop| &hop.src_node_id))
+                .any(|node| graph.nodes().contains_key(&NodeId::from_pubkey(node)));
+            (missing_scids, known_endpoint)
+        };
+
+        let peers = self.peer_manager.get_peer_node_ids();
+        if !missing_scids.is_empty()
+            && !self
+                .gossip_handler
+                .query_short_channel_ids(&peers, missing_scids)
+        {
+            log_debug!(self.logger, "no connected peers to query for gossip");
+        }
+        if known_endpoint {
+            return;
+        }
+
+        let last_sync: u32 = self
+            .persister
+            .storage
+            .get_data(GOSSIP_SYNC_TIME_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let blocks = blocks_since_gossip_sync(utils::now().as_secs(), last_sync);
+        let best_height = self.channel_manager.current_best_block().height();
+        let first_block = best_height.saturating_sub(blocks);
+        if !self
+            .gossip_handler
+            .query_channel_range(&peers, first_block, blocks + 1)
+        {
+            log_debug!(self.logger, "not querying peers for recent channels");
+        }
+    }
+
     pub fn node_index(&self) -> NodeIndex {
         NodeIndex {
             child_index: self.child_index,
@@ -582,6 +735,15 @@ impl<S: MutinyStorage> Node<S> {
         peer_connection_info: PubkeyConnectionInfo,
         label: Option<String>,
     ) -> Result<(), MutinyError> {
+        let lsp_pubkey = self.lsp_client.as_ref().map(|l| l.pubkey);
+        if !self
+            .persister
+            .storage
+            .is_peer_allowed(&peer_connection_info.pubkey, lsp_pubkey.as_ref())
+        {
+            return Err(MutinyError::PeerNotAllowed);
+        }
+
         let connect_res = connect_peer_if_necessary(
             #[cfg(target_arch = "wasm32")]
             &self.websocket_proxy_addr,
@@ -650,10 +812,38 @@ impl<S: MutinyStorage> Node<S> {
         &self,
         amount_sat: Option<u64>,
         labels: Vec<String>,
+        order_id: Option<String>,
         route_hints: Option<Vec<PhantomRouteHints>>,
+        description_hash: Option<Sha256>,
+        expiry_secs: Option<u32>,
     ) -> Result<Bolt11Invoice, MutinyError> {
+        // a zero-amount invoice is created with `None`
+        if amount_sat == Some(0) {
+            return Err(MutinyError::BadAmountError);
+        }
+        if expiry_secs == Some(0) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        // The LSP needs the amount to size a new channel, so zero-amount invoices
+        // skip it. That only works if we can already receive over a channel with it.
+        let lsp = match (self.lsp_client.clone(), amount_sat) {
+            (Some(lsp), None) => {
+                let has_inbound = self
+                    .channel_manager
+                    .list_channels_with_counterparty(&lsp.pubkey)
+                    .iter()
+                    .any(|c| c.is_usable && c.inbound_capacity_msat > 0);
+                if !has_inbound {
+                    return Err(MutinyError::BadAmountError);
+                }
+                None
+            }
+            (lsp, _) => lsp,
+        };
+
         // the amount to create for the invoice whether or not there is an lsp
-        let (amount_sat, lsp_fee_msat) = if let Some(lsp) = self.lsp_client.clone() {
+        let (amount_sat, lsp_fee_msat) = if let Some(lsp) = lsp.clone() {
             // LSP requires an amount:
             let amount_sat = amount_sat.ok_or(MutinyError::BadAmountError)?;
 
@@ -702,10 +892,18 @@ impl<S: MutinyStorage> Node<S> {
         };
 
         let invoice = self
-            .create_internal_invoice(amount_sat, lsp_fee_msat, labels, route_hints)
+            .create_internal_invoice(
+                amount_sat,
+                lsp_fee_msat,
+                labels,
+                order_id,
+                route_hints,
+                description_hash,
+                expiry_secs.unwrap_or(DEFAULT_INVOICE_EXPIRY_SECS),
+            )
             .await?;
 
-        if let Some(lsp) = self.lsp_client.clone() {
+        if let Some(lsp) = lsp {
             self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
                 .await?;
             let lsp_invoice = match lsp.get_lsp_invoice(invoice.to_string()).await {
@@ -716,8 +914,8 @@ impl<S: MutinyStorage> Node<S> {
                 }
             };
 
-            if invoice.network() != self.network {
-                return Err(MutinyError::IncorrectNetwork(invoice.network()));
+            if lsp_invoice.network() != self.network {
+                return Err(MutinyError::IncorrectNetwork(lsp_invoice.network()));
             }
 
             if lsp_invoice.payment_hash() != invoice.payment_hash()
@@ -726,71 +924,240 @@ impl<S: MutinyStorage> Node<S> {
                 return Err(MutinyError::InvoiceCreationFailed);
             }
 
+            // the LSP's invoice wraps ours, it can only add the fee it quoted
+            if !wrapped_amount_within_fee(
+                invoice.amount_milli_satoshis(),
+                lsp_invoice.amount_milli_satoshis(),
+                lsp_fee_msat.unwrap_or(0),
+            ) {
+                log_error!(
+                    self.logger,
+                    "LSP invoice amount {:?} is more than our invoice plus the quoted fee",
+                    lsp_invoice.amount_milli_satoshis()
+                );
+                return Err(MutinyError::InvoiceCreationFailed);
+            }
+
+            // the point of the wrapped invoice is that senders don't learn our node id
+            let reveals_node_id = lsp_invoice
+                .route_hints()
+                .iter()
+                .flat_map(|hint| hint.0.iter())
+                .any(|hop| hop.src_node_id == self.pubkey);
+            if reveals_node_id {
+                log_error!(self.logger, "LSP invoice route hints include our node id");
+                return Err(MutinyError::InvoiceCreationFailed);
+            }
+
+            // the LSP has to keep the description hash the payer will check
+            if let Some(hash) = description_hash {
+                match lsp_invoice.description() {
+                    Bolt11InvoiceDescription::Hash(h) if h.0 == hash => {}
+                    _ => return Err(MutinyError::InvoiceCreationFailed),
+                }
+            }
+
             Ok(lsp_invoice)
         } else {
             Ok(invoice)
         }
     }
 
+    /// Creates a phantom invoice that can be paid to any of the nodes the route
+    /// hints are for. The LSP isn't used, so the nodes need inbound capacity.
+    pub async fn create_phantom_invoice(
+        &self,
+        amount_sat: Option<u64>,
+        labels: Vec<String>,
+        order_id: Option<String>,
+        route_hints: Vec<PhantomRouteHints>,
+        expiry_secs: Option<u32>,
+    ) -> Result<Bolt11Invoice, MutinyError> {
+        if amount_sat == Some(0) {
+            return Err(MutinyError::BadAmountError);
+        }
+        if expiry_secs == Some(0) || route_hints.is_empty() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        self.create_internal_invoice(
+            amount_sat,
+            None,
+            labels,
+            order_id,
+            Some(route_hints),
+            None,
+            expiry_secs.unwrap_or(DEFAULT_INVOICE_EXPIRY_SECS),
+        )
+        .await
+    }
+
     async fn create_internal_invoice(
         &self,
         amount_sat: Option<u64>,
         fee_amount_msat: Option<u64>,
         labels: Vec<String>,
+        order_id: Option<String>,
         route_hints: Option<Vec<PhantomRouteHints>>,
+        description_hash: Option<Sha256>,
+        expiry_secs: u32,
     ) -> Result<Bolt11Invoice, MutinyError> {
         let amount_msat = amount_sat.map(|s| s * 1_000);
         // Set description to empty string to make smallest possible invoice/QR code
         let description = "".to_string();
 
-        // wait for first sync to complete
-        for _ in 0..60 {
-            // check if we've been stopped
-            if self.stop.load(Ordering::Relaxed) {
-                return Err(MutinyError::NotRunning);
-            }
-
-            if let Ok(true) = self.persister.storage.has_done_first_sync() {
-                break;
-            }
-
-            sleep(1_000).await;
-        }
-
-        let invoice_res = match route_hints {
-            None => {
-                let now = crate::utils::now();
-                create_invoice_from_channelmanager_and_duration_since_epoch(
-                    &self.channel_manager.clone(),
-                    self.keys_manager.clone(),
-                    self.logger.clone(),
-                    self.network.into(),
+        self.wait_for_first_sync().await?;
+
+        let phantom = route_hints.is_some();
+        let invoice_res = match (route_hints, description_hash) {
+            (None, description_hash) => {
+                let (payment_hash, payment_secret) = self
+                    .channel_manager
+                    .create_inbound_payment(
+                        amount_msat,
+                        expiry_secs,
+                        Some(self.cltv_config.min_final_cltv_expiry_delta),
+                    )
+                    .map_err(|_| MutinyError::InvoiceCreationFailed)?;
+                self.build_invoice(
                     amount_msat,
-                    description,
-                    now,
-                    3600,
-                    Some(40),
+                    payment_hash,
+                    payment_secret,
+                    description_hash,
+                    expiry_secs,
                 )
             }
-            Some(r) => create_phantom_invoice(
+            (Some(r), Some(hash)) => create_phantom_invoice_with_description_hash(
+                amount_msat,
+                None,
+                expiry_secs,
+                lightning_invoice::Sha256(hash),
+                r,
+                self.keys_manager.clone(),
+                self.keys_manager.clone(),
+                self.logger.clone(),
+                self.network.into(),
+                Some(self.cltv_config.min_final_cltv_expiry_delta),
+                crate::utils::now(),
+            )
+            .map_err(|e| {
+                log_error!(self.logger, "ERROR: could not generate invoice: {e}");
+                MutinyError::InvoiceCreationFailed
+            }),
+            (Some(r), None) => create_phantom_invoice(
                 amount_msat,
                 None,
                 description,
-                3600,
+                expiry_secs,
                 r,
                 self.keys_manager.clone(),
                 self.keys_manager.clone(),
                 self.logger.clone(),
                 self.network.into(),
-                Some(40),
+                Some(self.cltv_config.min_final_cltv_expiry_delta),
                 crate::utils::now(),
-            ),
+            )
+            .map_err(|e| {
+                log_error!(self.logger, "ERROR: could not generate invoice: {e}");
+                MutinyError::InvoiceCreationFailed
+            }),
         };
-        let invoice = invoice_res.map_err(|e| {
-            log_error!(self.logger, "ERROR: could not generate invoice: {e}");
+        let invoice = invoice_res?;
+
+        self.persist_new_invoice(&invoice, amount_msat, fee_amount_msat, labels, order_id)?;
+        if phantom {
+            let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+            self.persister
+                .persist_phantom_invoice_owner(&payment_hash)?;
+        }
+
+        Ok(invoice)
+    }
+
+    /// Builds and signs an invoice for a payment the channel manager expects,
+    /// with route hints for our unannounced channels so senders can find us.
+    fn build_invoice(
+        &self,
+        amount_msat: Option<u64>,
+        payment_hash: PaymentHash,
+        payment_secret: PaymentSecret,
+        description_hash: Option<Sha256>,
+        expiry_secs: u32,
+    ) -> Result<Bolt11Invoice, MutinyError> {
+        let builder = InvoiceBuilder::new(self.network.into());
+        // Empty description to make the smallest possible invoice/QR code
+        let builder = match description_hash {
+            Some(hash) => builder.description_hash(hash),
+            None => builder.description("".to_string()),
+        };
+        let mut builder = builder
+            .duration_since_epoch(utils::now())
+            .payment_hash(Sha256::from_inner(payment_hash.0))
+            .payment_secret(payment_secret)
+            .basic_mpp()
+            .min_final_cltv_expiry_delta(self.cltv_config.min_final_cltv_expiry_delta as u64)
+            .expiry_time(Duration::from_secs(expiry_secs as u64));
+        if let Some(amount_msat) = amount_msat {
+            builder = builder.amount_milli_satoshis(amount_msat);
+        }
+
+        let candidates = self
+            .channel_manager
+            .list_channels()
+            .iter()
+            .filter_map(|c| Some((c.inbound_capacity_msat, private_route_hint(c)?)))
+            .collect();
+        for hint in select_route_hints(candidates, amount_msat) {
+            builder = builder.private_route(hint);
+        }
+
+        let raw_invoice = builder.build_raw().map_err(|e| {
+            log_error!(self.logger, "ERROR: could not build invoice: {e}");
             MutinyError::InvoiceCreationFailed
         })?;
+        let hrp = raw_invoice.hrp.to_string();
+        let data = raw_invoice.data.to_base32();
+        let signed = raw_invoice
+            .sign(|_| {
+                self.keys_manager
+                    .sign_invoice(hrp.as_bytes(), &data, Recipient::Node)
+            })
+            .map_err(|_| {
+                log_error!(self.logger, "ERROR: could not sign invoice");
+                MutinyError::InvoiceCreationFailed
+            })?;
+
+        Bolt11Invoice::from_signed(signed).map_err(|e| {
+            log_error!(self.logger, "ERROR: could not generate invoice: {e}");
+            MutinyError::InvoiceCreationFailed
+        })
+    }
+
+    async fn wait_for_first_sync(&self) -> Result<(), MutinyError> {
+        for _ in 0..60 {
+            // check if we've been stopped
+            if self.stop.load(Ordering::Relaxed) {
+                return Err(MutinyError::NotRunning);
+            }
+
+            if let Ok(true) = self.persister.storage.has_done_first_sync() {
+                break;
+            }
+
+            sleep(1_000).await;
+        }
+
+        Ok(())
+    }
 
+    fn persist_new_invoice(
+        &self,
+        invoice: &Bolt11Invoice,
+        amount_msat: Option<u64>,
+        fee_amount_msat: Option<u64>,
+        labels: Vec<String>,
+        order_id: Option<String>,
+    ) -> Result<(), MutinyError> {
         let last_update = crate::utils::now().as_secs();
         let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
         let payment_info = PaymentInfo {
@@ -801,6 +1168,9 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: fee_amount_msat,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            order_id,
+            custom_tlvs: vec![],
+            failure: None,
             last_update,
         };
         self.persister
@@ -816,7 +1186,134 @@ impl<S: MutinyStorage> Node<S> {
 
         log_info!(self.logger, "SUCCESS: generated invoice: {invoice}");
 
-        Ok(invoice)
+        Ok(())
+    }
+
+    /// Creates a hold invoice for a payment hash we don't know the preimage for.
+    /// The HTLCs paying it are held until it is settled with the preimage or cancelled,
+    /// so they have to arrive over an existing channel, we don't use the LSP.
+    pub async fn create_hold_invoice(
+        &self,
+        amount_sat: Option<u64>,
+        payment_hash: Sha256,
+        labels: Vec<String>,
+    ) -> Result<HoldInvoice, MutinyError> {
+        if amount_sat == Some(0) {
+            return Err(MutinyError::BadAmountError);
+        }
+        if self
+            .persister
+            .storage
+            .get_hold_invoice(&payment_hash)?
+            .is_some()
+        {
+            return Err(MutinyError::NonUniquePaymentHash);
+        }
+
+        self.wait_for_first_sync().await?;
+
+        let amount_msat = amount_sat.map(|s| s * 1_000);
+        let now = crate::utils::now();
+        let hash = PaymentHash(payment_hash.into_inner());
+        let payment_secret = self
+            .channel_manager
+            .create_inbound_payment_for_hash(
+                hash,
+                amount_msat,
+                DEFAULT_INVOICE_EXPIRY_SECS,
+                Some(self.cltv_config.min_final_cltv_expiry_delta),
+            )
+            .map_err(|_| {
+                log_error!(self.logger, "ERROR: could not generate hold invoice");
+                MutinyError::InvoiceCreationFailed
+            })?;
+        let invoice = self.build_invoice(
+            amount_msat,
+            hash,
+            payment_secret,
+            None,
+            DEFAULT_INVOICE_EXPIRY_SECS,
+        )?;
+
+        self.persist_new_invoice(&invoice, amount_msat, None, labels, None)?;
+
+        let hold_invoice = HoldInvoice {
+            payment_hash,
+            bolt11: invoice,
+            node_pubkey: self.pubkey,
+            created_at: now.as_secs(),
+            status: HoldInvoiceStatus::Open,
+        };
+        self.persister
+            .storage
+            .persist_hold_invoice(hold_invoice.clone())?;
+
+        Ok(hold_invoice)
+    }
+
+    /// Claims the held HTLCs of a hold invoice with its preimage.
+    /// Fails if the HTLCs aren't held anymore, like when they expired.
+    pub fn settle_hold_invoice(&self, preimage: [u8; 32]) -> Result<HoldInvoice, MutinyError> {
+        let payment_hash = Sha256::hash(&preimage);
+        let mut hold_invoice = self.get_own_hold_invoice(&payment_hash)?;
+        if !self.has_held_htlcs(&PaymentHash(payment_hash.into_inner())) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        hold_invoice.settle(&preimage)?;
+
+        // persist first so we claim again if we restart before the claim completes
+        self.persister
+            .storage
+            .persist_hold_invoice(hold_invoice.clone())?;
+        self.channel_manager.claim_funds(PaymentPreimage(preimage));
+
+        Ok(hold_invoice)
+    }
+
+    /// Fails the held HTLCs of a hold invoice back to the payer,
+    /// any HTLCs that arrive for it later are failed too
+    pub fn cancel_hold_invoice(&self, payment_hash: Sha256) -> Result<HoldInvoice, MutinyError> {
+        let mut hold_invoice = self.get_own_hold_invoice(&payment_hash)?;
+        hold_invoice.cancel()?;
+
+        self.persister
+            .storage
+            .persist_hold_invoice(hold_invoice.clone())?;
+        let payment_hash = PaymentHash(payment_hash.into_inner());
+        self.channel_manager.fail_htlc_backwards(&payment_hash);
+
+        if let Some(mut info) = self
+            .persister
+            .read_payment_info(&payment_hash, true, &self.logger)
+        {
+            info.status = HTLCStatus::Failed;
+            info.last_update = crate::utils::now().as_secs();
+            self.persister
+                .persist_payment_info(&payment_hash, &info, true)?;
+        }
+
+        Ok(hold_invoice)
+    }
+
+    /// Whether we still hold inbound HTLCs for the payment, LDK fails them back
+    /// on its own when they get close to expiring
+    fn has_held_htlcs(&self, payment_hash: &PaymentHash) -> bool {
+        match self.persister.get_payment_htlcs(payment_hash) {
+            Ok(htlcs) => htlcs
+                .iter()
+                .any(|h| h.inbound && h.state == HtlcState::Committed),
+            Err(e) => {
+                log_error!(self.logger, "Failed to read payment htlcs: {e}");
+                false
+            }
+        }
+    }
+
+    fn get_own_hold_invoice(&self, payment_hash: &Sha256) -> Result<HoldInvoice, MutinyError> {
+        match self.persister.storage.get_hold_invoice(payment_hash)? {
+            Some(invoice) if invoice.node_pubkey == self.pubkey => Ok(invoice),
+            _ => Err(MutinyError::NotFound),
+        }
     }
 
     pub fn get_invoice(&self, invoice: &Bolt11Invoice) -> Result<MutinyInvoice, MutinyError> {
@@ -832,12 +1329,27 @@ impl<S: MutinyStorage> Node<S> {
             .and_then(|inv| labels_map.get(inv).cloned())
             .unwrap_or_default();
 
-        MutinyInvoice::from(
+        let mut invoice = MutinyInvoice::from(
             payment_info,
             PaymentHash(payment_hash.into_inner()),
             inbound,
             labels,
-        )
+        )?;
+        invoice.metadata = self.persister.storage.get_payment_metadata(payment_hash)?;
+        if !inbound {
+            invoice.parts = self.payment_parts(&PaymentHash(payment_hash.into_inner()))?;
+        }
+        Ok(invoice)
+    }
+
+    /// The paths of an outbound payment that succeeded. LDK tells us about each
+    /// path shortly after the payment succeeds, so this can lag behind a bit.
+    fn payment_parts(&self, payment_hash: &PaymentHash) -> Result<Vec<PaymentHtlc>, MutinyError> {
+        let htlcs = self.persister.get_payment_htlcs(payment_hash)?;
+        Ok(htlcs
+            .into_iter()
+            .filter(|h| !h.inbound && h.channel.is_some() && h.state == HtlcState::Fulfilled)
+            .collect())
     }
 
     pub fn list_invoices(&self) -> Result<Vec<MutinyInvoice>, MutinyError> {
@@ -853,6 +1365,7 @@ impl<S: MutinyStorage> Node<S> {
     ) -> Result<Vec<MutinyInvoice>, MutinyError> {
         let now = utils::now();
         let labels_map = self.persister.storage.get_invoice_labels()?;
+        let mut metadata_map = self.persister.storage.get_all_payment_metadata()?;
 
         Ok(self
             .persister
@@ -863,7 +1376,15 @@ impl<S: MutinyStorage> Node<S> {
                     None => vec![],
                     Some(i) => labels_map.get(&i).cloned().unwrap_or_default(),
                 };
-                let mutiny_invoice = MutinyInvoice::from(i.clone(), h, inbound, labels).ok();
+                let mutiny_invoice =
+                    MutinyInvoice::from(i.clone(), h, inbound, labels)
+                        .ok()
+                        .map(|mut invoice| {
+                            if let Some(metadata) = metadata_map.remove(&invoice.payment_hash) {
+                                invoice.metadata = metadata;
+                            }
+                            invoice
+                        });
 
                 // filter out expired invoices
                 mutiny_invoice.filter(|invoice| {
@@ -874,6 +1395,35 @@ impl<S: MutinyStorage> Node<S> {
             .collect())
     }
 
+    /// Marks the unpaid invoices that have expired as failed, so they aren't
+    /// shown as pending anymore. Hold invoices are left alone, they can still
+    /// be settled once their payment has arrived.
+    ///
+    /// Returns the number of invoices that were marked.
+    pub fn expire_invoices(&self) -> Result<usize, MutinyError> {
+        let now = utils::now();
+        let mut expired = 0;
+        for (payment_hash, mut info) in self.persister.list_payment_info(true)? {
+            if info.status != HTLCStatus::Pending
+                || !info.bolt11.as_ref().is_some_and(|b| b.would_expire(now))
+            {
+                continue;
+            }
+            let hash = Sha256::from_inner(payment_hash.0);
+            if self.persister.storage.get_hold_invoice(&hash)?.is_some() {
+                continue;
+            }
+
+            info.status = HTLCStatus::Failed;
+            info.last_update = now.as_secs();
+            self.persister
+                .persist_payment_info(&payment_hash, &info, true)?;
+            expired += 1;
+        }
+
+        Ok(expired)
+    }
+
     /// Gets all the closed channels for this node
     pub fn get_channel_closure(
         &self,
@@ -923,17 +1473,19 @@ impl<S: MutinyStorage> Node<S> {
     }
 
     fn retry_strategy() -> Retry {
-        Retry::Attempts(15)
+        RetryPolicy::default().retry()
     }
 
     /// init_invoice_payment sends off the payment but does not wait for results
-    /// use pay_invoice_with_timeout to wait for results
+    /// use await_invoice_payment to wait for results
     pub async fn init_invoice_payment(
         &self,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
         labels: Vec<String>,
+        retry_policy: RetryPolicy,
     ) -> Result<PaymentHash, MutinyError> {
+        retry_policy.validate()?;
         let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
 
         if self
@@ -944,12 +1496,17 @@ impl<S: MutinyStorage> Node<S> {
             return Err(MutinyError::NonUniquePaymentHash);
         }
 
-        if self
+        match self
             .persister
             .read_payment_info(&payment_hash, true, &self.logger)
-            .is_some_and(|p| p.status != HTLCStatus::Failed)
+            .map(|p| p.status)
         {
-            return Err(MutinyError::NonUniquePaymentHash);
+            // we made this invoice, routing to ourselves can only time out
+            Some(HTLCStatus::Pending) | Some(HTLCStatus::InFlight) => {
+                return Err(MutinyError::SelfPayment)
+            }
+            Some(HTLCStatus::Succeeded) => return Err(MutinyError::NonUniquePaymentHash),
+            Some(HTLCStatus::Failed) | None => {}
         }
 
         if self.channel_manager.list_channels().is_empty() {
@@ -970,32 +1527,20 @@ impl<S: MutinyStorage> Node<S> {
             sleep(1_000).await;
         }
 
-        let (pay_result, amt_msat) = if invoice.amount_milli_satoshis().is_none() {
-            if amt_sats.is_none() {
-                return Err(MutinyError::InvoiceInvalid);
-            }
-            let amt_msats = amt_sats.unwrap() * 1_000;
-            (
-                pay_zero_value_invoice(
-                    invoice,
-                    amt_msats,
-                    Self::retry_strategy(),
-                    self.channel_manager.as_ref(),
-                ),
-                amt_msats,
-            )
+        let amt_msat = payment_amount_msat(invoice.amount_milli_satoshis(), amt_sats)?;
+
+        // persisted before paying so the router can apply the fee cap to every attempt
+        let retry = retry_policy.retry();
+        self.persister.storage.persist_payment_retry(PaymentRetry {
+            payment_hash: *invoice.payment_hash(),
+            policy: retry_policy,
+            started_at: utils::now().as_secs(),
+        })?;
+
+        let pay_result = if invoice.amount_milli_satoshis().is_none() {
+            pay_zero_value_invoice(invoice, amt_msat, retry, self.channel_manager.as_ref())
         } else {
-            if amt_sats.is_some() {
-                return Err(MutinyError::InvoiceInvalid);
-            }
-            (
-                pay_invoice(
-                    invoice,
-                    Self::retry_strategy(),
-                    self.channel_manager.as_ref(),
-                ),
-                invoice.amount_milli_satoshis().unwrap(),
-            )
+            pay_invoice(invoice, retry, self.channel_manager.as_ref())
         };
 
         if let Err(e) = self
@@ -1015,6 +1560,9 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: None,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
             last_update,
         };
 
@@ -1022,9 +1570,28 @@ impl<S: MutinyStorage> Node<S> {
             .persist_payment_info(&payment_hash, &payment_info, false)?;
 
         match pay_result {
-            Ok(_) => Ok(payment_hash),
+            Ok(_) => {
+                let htlc =
+                    PaymentHtlc::new(false, None, Some(amt_msat), HtlcState::Added, last_update);
+                if let Err(e) = self.persister.add_payment_htlc(&payment_hash, htlc) {
+                    log_error!(self.logger, "could not persist payment htlc: {e}");
+                }
+                self.event_handler
+                    .notify_payment(&payment_hash, false, PaymentState::InFlight);
+                Ok(payment_hash)
+            }
             Err(e) => {
                 log_error!(self.logger, "failed to make payment: {:?}", e);
+                let failure = match &e {
+                    PaymentError::Sending(failure) => Some(failure),
+                    PaymentError::Invoice(_) => None,
+                };
+                self.event_handler.notify_payment(
+                    &payment_hash,
+                    false,
+                    PaymentState::not_sent(failure),
+                );
+                let reason = failure_reason_message(send_failure_reason(failure));
                 // call list channels to see what our channels are
                 let current_channels = self.channel_manager.list_channels();
                 log_debug!(
@@ -1034,8 +1601,16 @@ impl<S: MutinyStorage> Node<S> {
                 );
 
                 payment_info.status = HTLCStatus::Failed;
+                payment_info.failure = Some(PaymentFailure {
+                    reason: Some(reason.to_string()),
+                    paths: vec![],
+                });
                 self.persister
                     .persist_payment_info(&payment_hash, &payment_info, false)?;
+                self.persister
+                    .storage
+                    .delete_payment_retry(invoice.payment_hash())?;
+                let over_fee_cap = self.router.take_over_fee_cap(&payment_hash);
 
                 // If the payment failed because of a route not found, check if the amount was
                 // valid and return the correct error
@@ -1055,6 +1630,15 @@ impl<S: MutinyStorage> Node<S> {
                     if ln_balance - reserved_amt < amt_msat {
                         return Err(MutinyError::ReserveAmountError);
                     }
+
+                    // we may be missing channels newer than our gossip snapshot,
+                    // ask our peers for them so a retry can find a route
+                    self.request_missing_channels(invoice);
+
+                    // there were routes, but they all cost more than allowed
+                    if over_fee_cap {
+                        return Err(MutinyError::FeeCapExceeded);
+                    }
                 }
 
                 Err(MutinyError::RoutingFailed)
@@ -1082,11 +1666,26 @@ impl<S: MutinyStorage> Node<S> {
             if let Some(info) = payment_info {
                 match info.status {
                     HTLCStatus::Succeeded => {
-                        let mutiny_invoice =
+                        self.router.take_over_fee_cap(&payment_hash);
+                        let mut mutiny_invoice =
                             MutinyInvoice::from(info, payment_hash, false, labels)?;
+                        mutiny_invoice.parts = self.payment_parts(&payment_hash)?;
                         return Ok(mutiny_invoice);
                     }
-                    HTLCStatus::Failed => return Err(MutinyError::RoutingFailed),
+                    HTLCStatus::Failed => {
+                        // the details are saved with the payment, see MutinyInvoice::failure
+                        if let Some(failure) = info.failure.as_ref() {
+                            log_warn!(
+                                self.logger,
+                                "payment {} failed: {failure:?}",
+                                payment_hash.0.to_hex()
+                            );
+                        }
+                        if self.router.take_over_fee_cap(&payment_hash) {
+                            return Err(MutinyError::FeeCapExceeded);
+                        }
+                        return Err(MutinyError::RoutingFailed);
+                    }
                     _ => {}
                 }
             }
@@ -1095,29 +1694,89 @@ impl<S: MutinyStorage> Node<S> {
         }
     }
 
+    /// Pays an invoice and waits for the result. If the retry policy has a timeout
+    /// we stop retrying when it is reached, otherwise the payment can still complete.
     pub async fn pay_invoice_with_timeout(
         &self,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
         timeout_secs: Option<u64>,
         labels: Vec<String>,
+        retry_policy: RetryPolicy,
     ) -> Result<MutinyInvoice, MutinyError> {
+        let timeout_secs = timeout_secs.or(retry_policy.timeout_secs);
         // initiate payment
         let payment_hash = self
-            .init_invoice_payment(invoice, amt_sats, labels.clone())
+            .init_invoice_payment(invoice, amt_sats, labels.clone(), retry_policy)
             .await?;
+
+        self.await_invoice_payment(payment_hash, timeout_secs, labels)
+            .await
+    }
+
+    /// Waits for the result of a payment started with init_invoice_payment
+    pub async fn await_invoice_payment(
+        &self,
+        payment_hash: PaymentHash,
+        timeout_secs: Option<u64>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
         let timeout: u64 = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
 
-        self.await_payment(payment_hash, timeout, labels).await
+        let res = self.await_payment(payment_hash, timeout, labels).await;
+        if matches!(res, Err(MutinyError::PaymentTimeout)) {
+            // stop retrying if the policy's timeout has passed too
+            self.check_payment_retries()?;
+        }
+
+        res
+    }
+
+    /// Stops retrying payments that have been retried for longer than their
+    /// retry policy allows, and forgets the policies of finished payments.
+    pub(crate) fn check_payment_retries(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+        for retry in self.persister.storage.list_payment_retries()? {
+            let payment_hash = PaymentHash(retry.payment_hash.into_inner());
+            // each node keeps its own payments
+            let Some(info) = self
+                .persister
+                .read_payment_info(&payment_hash, false, &self.logger)
+            else {
+                continue;
+            };
+
+            if info.status == HTLCStatus::InFlight {
+                if !retry.is_expired(now) {
+                    continue;
+                }
+                // HTLCs already sent can still succeed, LDK fails the
+                // payment once they resolve if they don't
+                log_info!(
+                    self.logger,
+                    "Retry timeout reached, abandoning payment {}",
+                    payment_hash.0.to_hex()
+                );
+                self.channel_manager
+                    .abandon_payment(PaymentId(payment_hash.0));
+            }
+
+            self.persister
+                .storage
+                .delete_payment_retry(&retry.payment_hash)?;
+        }
+
+        Ok(())
     }
 
     /// init_keysend_payment sends off the payment but does not wait for results
-    /// use keysend_with_timeout to wait for results
+    /// use await_keysend to wait for results
     pub fn init_keysend_payment(
         &self,
         to_node: PublicKey,
         amt_sats: u64,
         labels: Vec<String>,
+        custom_tlvs: Vec<CustomTlv>,
     ) -> Result<MutinyInvoice, MutinyError> {
         let mut entropy = [0u8; 32];
         getrandom::getrandom(&mut entropy).map_err(|_| MutinyError::SeedGenerationFailed)?;
@@ -1140,7 +1799,7 @@ impl<S: MutinyStorage> Node<S> {
             payment_params,
         };
 
-        let recipient_onion = RecipientOnionFields::secret_only(payment_secret);
+        let recipient_onion = keysend_onion_fields(payment_secret, &custom_tlvs)?;
 
         let pay_result = self.channel_manager.send_spontaneous_payment_with_retry(
             Some(preimage),
@@ -1161,6 +1820,9 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: None,
             bolt11: None,
             payee_pubkey: Some(to_node),
+            order_id: CustomTlv::order_id(&custom_tlvs),
+            custom_tlvs,
+            failure: None,
             last_update,
         };
 
@@ -1169,35 +1831,242 @@ impl<S: MutinyStorage> Node<S> {
 
         match pay_result {
             Ok(_) => {
+                let htlc =
+                    PaymentHtlc::new(false, None, Some(amt_msats), HtlcState::Added, last_update);
+                if let Err(e) = self.persister.add_payment_htlc(&payment_hash, htlc) {
+                    log_error!(self.logger, "could not persist payment htlc: {e}");
+                }
+                self.event_handler
+                    .notify_payment(&payment_hash, false, PaymentState::InFlight);
                 let mutiny_invoice =
                     MutinyInvoice::from(payment_info, payment_hash, false, labels)?;
                 Ok(mutiny_invoice)
             }
-            Err(_) => {
+            Err(e) => {
+                self.event_handler.notify_payment(
+                    &payment_hash,
+                    false,
+                    PaymentState::not_sent(Some(&e)),
+                );
+                let reason = failure_reason_message(send_failure_reason(Some(&e)));
                 payment_info.status = HTLCStatus::Failed;
+                payment_info.failure = Some(PaymentFailure {
+                    reason: Some(reason.to_string()),
+                    paths: vec![],
+                });
                 self.persister
                     .persist_payment_info(&payment_hash, &payment_info, false)?;
+                if self.router.take_over_fee_cap(&payment_hash) {
+                    return Err(MutinyError::FeeCapExceeded);
+                }
                 Err(MutinyError::RoutingFailed)
             }
         }
     }
 
-    pub async fn keysend_with_timeout(
+    /// Waits for the result of a payment started with init_keysend_payment
+    pub async fn await_keysend(
         &self,
-        to_node: PublicKey,
-        amt_sats: u64,
+        pay: &MutinyInvoice,
         labels: Vec<String>,
         timeout_secs: Option<u64>,
     ) -> Result<MutinyInvoice, MutinyError> {
-        // initiate payment
-        let pay = self.init_keysend_payment(to_node, amt_sats, labels.clone())?;
-
         let timeout: u64 = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
         let payment_hash = PaymentHash(pay.payment_hash.into_inner());
 
         self.await_payment(payment_hash, timeout, labels).await
     }
 
+    /// Sends probes along a route to `destination` for `amt_sats` without paying anything.
+    /// Use await_probes to wait for their results.
+    pub(crate) fn send_probes(
+        &self,
+        destination: PublicKey,
+        amt_sats: u64,
+    ) -> Result<(Route, Vec<PaymentId>), MutinyError> {
+        if amt_sats == 0 {
+            return Err(MutinyError::BadAmountError);
+        }
+
+        let first_hops = self.channel_manager.list_usable_channels();
+        if first_hops.is_empty() {
+            // No usable channels so routing will always fail
+            return Err(MutinyError::RoutingFailed);
+        }
+
+        let route_params = RouteParameters {
+            final_value_msat: amt_sats * 1_000,
+            payment_params: PaymentParameters::from_node_id(destination, 40),
+        };
+        let route = self
+            .router
+            .find_route(
+                &self.pubkey,
+                &route_params,
+                Some(&first_hops.iter().collect::<Vec<_>>()),
+                self.channel_manager.compute_inflight_htlcs(),
+            )
+            .map_err(|e| {
+                log_debug!(self.logger, "could not find a route to probe: {}", e.err);
+                MutinyError::RoutingFailed
+            })?;
+
+        let mut pending = Vec::with_capacity(route.paths.len());
+        for path in route.paths.iter() {
+            match self.channel_manager.send_probe(path.clone()) {
+                Ok((_, payment_id)) => {
+                    self.event_handler.track_probe(payment_id);
+                    pending.push(payment_id);
+                }
+                Err(e) => {
+                    log_error!(self.logger, "failed to send probe: {e:?}");
+                    pending
+                        .iter()
+                        .for_each(|id| self.event_handler.forget_probe(id));
+                    return Err(MutinyError::RoutingFailed);
+                }
+            }
+        }
+
+        Ok((route, pending))
+    }
+
+    /// Waits for the results of probes sent with send_probes.
+    /// The probe results are recorded in the scorer, so a payment made afterwards
+    /// avoids the channels that couldn't carry the amount.
+    pub(crate) async fn await_probes(
+        &self,
+        destination: PublicKey,
+        amt_sats: u64,
+        route: Route,
+        mut pending: Vec<PaymentId>,
+        timeout_secs: Option<u64>,
+    ) -> Result<ProbeResult, MutinyError> {
+        let timeout = timeout_secs.unwrap_or(DEFAULT_PAYMENT_TIMEOUT);
+        let start = utils::now().as_secs();
+        let mut success = true;
+        loop {
+            pending.retain(|id| match self.event_handler.take_probe_result(id) {
+                Some(result) => {
+                    success &= result;
+                    false
+                }
+                None => true,
+            });
+            if pending.is_empty() {
+                break;
+            }
+
+            if utils::now().as_secs().saturating_sub(start) > timeout {
+                pending
+                    .iter()
+                    .for_each(|id| self.event_handler.forget_probe(id));
+                return Err(MutinyError::PaymentTimeout);
+            }
+            sleep(250).await;
+        }
+
+        // the scorer has been updated with the probe results by now
+        let probability = {
+            let scorer = self.scorer.lock().map_err(|_| MutinyError::RoutingFailed)?;
+            route
+                .paths
+                .iter()
+                .map(|path| path_success_probability(&scorer, path))
+                .product::<f64>()
+        };
+
+        Ok(ProbeResult {
+            destination,
+            amount_sats: amt_sats,
+            success,
+            fee_sats: route.get_total_fees() / 1_000,
+            probability,
+        })
+    }
+
+    /// Finds a route for paying an invoice with the limits of the retry policy, without
+    /// sending anything, to show what a payment would cost and how long our funds could
+    /// be locked up for if it got stuck.
+    pub fn estimate_payment(
+        &self,
+        invoice: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        retry_policy: &RetryPolicy,
+    ) -> Result<PaymentEstimate, MutinyError> {
+        retry_policy.validate()?;
+        let amt_msat = payment_amount_msat(invoice.amount_milli_satoshis(), amt_sats)?;
+
+        let first_hops = self.channel_manager.list_usable_channels();
+        if first_hops.is_empty() {
+            // No usable channels so routing will always fail
+            return Err(MutinyError::RoutingFailed);
+        }
+
+        let payee = invoice
+            .payee_pub_key()
+            .cloned()
+            .unwrap_or_else(|| invoice.recover_payee_pub_key());
+        let expiry = invoice.duration_since_epoch() + invoice.expiry_time();
+        let mut payment_params =
+            PaymentParameters::from_node_id(payee, invoice.min_final_cltv_expiry_delta() as u32)
+                .with_expiry_time(expiry.as_secs())
+                .with_route_hints(invoice.route_hints())
+                .map_err(|_| MutinyError::InvoiceInvalid)?;
+        if let Some(features) = invoice.features() {
+            payment_params = payment_params
+                .with_bolt11_features(features.clone())
+                .map_err(|_| MutinyError::InvoiceInvalid)?;
+        }
+        let max_total_cltv_expiry_delta = retry_policy
+            .max_total_cltv_expiry_delta
+            .unwrap_or(payment_params.max_total_cltv_expiry_delta)
+            .min(payment_params.max_total_cltv_expiry_delta);
+
+        let route_params = RouteParameters {
+            final_value_msat: amt_msat,
+            payment_params,
+        };
+        let route = self
+            .router
+            .find_route_with_policy(
+                &self.pubkey,
+                &route_params,
+                &first_hops.iter().collect::<Vec<_>>(),
+                self.channel_manager.compute_inflight_htlcs(),
+                retry_policy,
+            )
+            .map_err(|e| {
+                log_debug!(self.logger, "could not find a route to estimate: {}", e.err);
+                MutinyError::RoutingFailed
+            })?;
+
+        let probability = {
+            let scorer = self.scorer.lock().map_err(|_| MutinyError::RoutingFailed)?;
+            route
+                .paths
+                .iter()
+                .map(|path| path_success_probability(&scorer, path))
+                .product::<f64>()
+        };
+        // each hop adds its delta to the expiry of the HTLC we send
+        let cltv_expiry_delta = route
+            .paths
+            .iter()
+            .map(|path| path.hops.iter().map(|h| h.cltv_expiry_delta).sum::<u32>())
+            .max()
+            .unwrap_or_default();
+
+        Ok(PaymentEstimate {
+            amount_sats: amt_msat / 1_000,
+            fee_sats: route.get_total_fees() / 1_000,
+            probability,
+            parts: route.paths.len(),
+            cltv_expiry_delta,
+            max_total_cltv_expiry_delta,
+        })
+    }
+
     async fn await_chan_funding_tx(
         &self,
         user_channel_id: u128,
@@ -1266,7 +2135,11 @@ impl<S: MutinyStorage> Node<S> {
         fee_rate: Option<f32>,
         user_channel_id: Option<u128>,
     ) -> Result<u128, MutinyError> {
-        let mut config = default_user_config();
+        let mut config = default_user_config(&self.cltv_config);
+        self.persister
+            .storage
+            .get_inbound_htlc_limits()?
+            .apply(&mut config.channel_handshake_config);
 
         // if we are opening channel to LSP, turn off SCID alias until CLN is updated
         // LSP protects all invoice information anyways, so no UTXO leakage
@@ -1373,9 +2246,15 @@ impl<S: MutinyStorage> Node<S> {
         );
 
         // channel size is the total value of the utxos minus the fee
-        let channel_value_satoshis = utxo_value - expected_fee;
+        let channel_value_satoshis = utxo_value
+            .checked_sub(expected_fee)
+            .ok_or(MutinyError::InsufficientBalance)?;
 
-        let mut config = default_user_config();
+        let mut config = default_user_config(&self.cltv_config);
+        self.persister
+            .storage
+            .get_inbound_htlc_limits()?
+            .apply(&mut config.channel_handshake_config);
         // if we are opening channel to LSP, turn off SCID alias until CLN is updated
         // LSP protects all invoice information anyways, so no UTXO leakage
         if let Some(lsp) = self.lsp_client.clone() {
@@ -1437,6 +2316,314 @@ impl<S: MutinyStorage> Node<S> {
         self.await_chan_funding_tx(init, &pubkey, timeout).await
     }
 
+    /// Returns the internal state of the given channel, if we have it.
+    /// The channel can be given by its channel id or user channel id, in hex.
+    pub fn debug_channel(&self, channel_id: &str) -> Option<ChannelDebugInfo> {
+        let channel = self.channel_manager.list_channels().into_iter().find(|c| {
+            c.channel_id.to_hex() == channel_id || c.user_channel_id.to_hex() == channel_id
+        })?;
+
+        let monitor = channel
+            .funding_txo
+            .and_then(|funding_txo| self.chain_monitor.get_monitor(funding_txo).ok());
+        let monitor_update_id = monitor.as_ref().map(|m| m.get_latest_update_id());
+        let commitment_htlcs = monitor
+            .map(|m| {
+                m.get_claimable_balances()
+                    .into_iter()
+                    .filter_map(|b| match b {
+                        Balance::MaybeTimeoutClaimableHTLC {
+                            claimable_amount_satoshis,
+                            claimable_height,
+                            ..
+                        } => Some(CommitmentHtlcDebugInfo {
+                            amount_sats: claimable_amount_satoshis,
+                            inbound: false,
+                            height: claimable_height,
+                        }),
+                        Balance::MaybePreimageClaimableHTLC {
+                            claimable_amount_satoshis,
+                            expiry_height,
+                            ..
+                        } => Some(CommitmentHtlcDebugInfo {
+                            amount_sats: claimable_amount_satoshis,
+                            inbound: true,
+                            height: expiry_height,
+                        }),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // outbound HTLCs from the paths we routed over the channel, inbound
+        // ones from the payments we received over it and haven't resolved
+        let mut pending_htlcs: Vec<PendingHtlcDebugInfo> = channel
+            .short_channel_id
+            .map(|scid| self.router.in_flight_over_channel(scid))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(payment_hash, amount_msat)| PendingHtlcDebugInfo {
+                payment_hash: payment_hash.0.to_hex(),
+                amount_msat,
+                inbound: false,
+            })
+            .collect();
+        match self
+            .persister
+            .list_unresolved_inbound_htlcs(&channel.channel_id.to_hex())
+        {
+            Ok(htlcs) => pending_htlcs.extend(htlcs.into_iter().map(|(payment_hash, htlc)| {
+                PendingHtlcDebugInfo {
+                    payment_hash: payment_hash.0.to_hex(),
+                    amount_msat: htlc.amount_msat.unwrap_or_default(),
+                    inbound: true,
+                }
+            })),
+            Err(e) => log_warn!(self.logger, "Failed to read inbound HTLCs: {e}"),
+        }
+
+        let outbound_in_flight_msat = channel
+            .short_channel_id
+            .and_then(|scid| {
+                self.channel_manager
+                    .compute_inflight_htlcs()
+                    .used_liquidity_msat(
+                        &NodeId::from_pubkey(&self.pubkey),
+                        &NodeId::from_pubkey(&channel.counterparty.node_id),
+                        scid,
+                    )
+            })
+            .unwrap_or_default();
+
+        let peer = channel.counterparty.node_id;
+        let peer_connected = self.peer_manager.get_peer_node_ids().contains(&peer);
+        let last_reconnection = self
+            .reconnection_status
+            .try_read()
+            .ok()
+            .and_then(|s| s.get(&NodeId::from_pubkey(&peer)).cloned());
+
+        Some(ChannelDebugInfo {
+            channel_id: channel.channel_id.to_hex(),
+            user_chan_id: channel.user_channel_id.to_hex(),
+            node_id: self.pubkey,
+            peer,
+            peer_connected,
+            outpoint: channel.funding_txo.map(|f| f.into_bitcoin_outpoint()),
+            short_channel_id: channel.short_channel_id,
+            size: channel.channel_value_satoshis,
+            balance_msat: channel.balance_msat,
+            outbound_capacity_msat: channel.outbound_capacity_msat,
+            next_outbound_htlc_limit_msat: channel.next_outbound_htlc_limit_msat,
+            inbound_capacity_msat: channel.inbound_capacity_msat,
+            reserve: channel.unspendable_punishment_reserve,
+            feerate_sat_per_1000_weight: channel.feerate_sat_per_1000_weight,
+            confirmations_required: channel.confirmations_required,
+            confirmations: channel.confirmations,
+            force_close_spend_delay: channel.force_close_spend_delay,
+            is_outbound: channel.is_outbound,
+            is_channel_ready: channel.is_channel_ready,
+            is_usable: channel.is_usable,
+            is_public: channel.is_public,
+            monitor_update_id,
+            outbound_in_flight_msat,
+            pending_htlcs,
+            commitment_htlcs,
+            last_reconnection,
+        })
+    }
+
+    /// Returns the outputs from force closed channels that we are still waiting on
+    pub(crate) fn pending_close_outputs(&self, chain_tip: u32) -> Vec<PendingCloseOutput> {
+        let open_channels: Vec<_> = self
+            .channel_manager
+            .list_channels()
+            .into_iter()
+            .filter_map(|c| c.funding_txo)
+            .collect();
+
+        let mut outputs = vec![];
+        for funding_txo in self.chain_monitor.list_monitors() {
+            // open channels have HTLC balances too, skip them
+            if open_channels.contains(&funding_txo) {
+                continue;
+            }
+            let Ok(monitor) = self.chain_monitor.get_monitor(funding_txo) else {
+                continue;
+            };
+
+            let outpoint = funding_txo.into_bitcoin_outpoint();
+            for balance in monitor.get_claimable_balances() {
+                let amount_sats = balance.claimable_amount_satoshis();
+                let (kind, height) = match balance {
+                    // the commitment transaction was broadcast but hasn't
+                    // confirmed, so there is no height to wait for yet
+                    Balance::ClaimableOnChannelClose { .. } => {
+                        (PendingCloseOutputKind::AwaitingCommitmentConfirmation, None)
+                    }
+                    Balance::ClaimableAwaitingConfirmations {
+                        confirmation_height,
+                        ..
+                    } => (
+                        PendingCloseOutputKind::AwaitingConfirmations,
+                        Some(confirmation_height),
+                    ),
+                    Balance::ContentiousClaimable { timeout_height, .. } => {
+                        (PendingCloseOutputKind::Contentious, Some(timeout_height))
+                    }
+                    Balance::MaybeTimeoutClaimableHTLC {
+                        claimable_height, ..
+                    } => (PendingCloseOutputKind::HtlcTimeout, Some(claimable_height)),
+                    Balance::MaybePreimageClaimableHTLC { expiry_height, .. } => {
+                        (PendingCloseOutputKind::HtlcPreimage, Some(expiry_height))
+                    }
+                    Balance::CounterpartyRevokedOutputClaimable { .. } => {
+                        (PendingCloseOutputKind::RevokedOutput, None)
+                    }
+                };
+                outputs.push(PendingCloseOutput::new(
+                    outpoint,
+                    kind,
+                    amount_sats,
+                    height,
+                    chain_tip,
+                ));
+            }
+        }
+
+        outputs
+    }
+
+    /// Sweeps the outputs from closed channels that are waiting to be swept,
+    /// all in a single transaction.
+    ///
+    /// Nothing is swept until [SWEEP_BATCH_WINDOW_SECS] after the first output
+    /// was queued. Outputs that can't be spent together are swept one at a time.
+    pub(crate) async fn sweep_pending_outputs(&self) -> Result<(), MutinyError> {
+        let Some((first_queued_at, outputs)) = self.persister.get_pending_sweep()? else {
+            return Ok(());
+        };
+        if utils::now().as_secs() < first_queued_at + SWEEP_BATCH_WINDOW_SECS {
+            return Ok(());
+        }
+
+        log_info!(
+            self.logger,
+            "Sweeping {} outputs from closed channels",
+            outputs.len()
+        );
+        // these get retried on the next startup
+        let failed = self.event_handler.sweep_spendable_outputs(&outputs).await;
+        if !failed.is_empty() {
+            self.persister.persist_failed_spendable_outputs(failed)?;
+        }
+
+        // only remove what we swept, more outputs may have been queued while sweeping
+        self.persister.remove_pending_sweep(&outputs)
+    }
+
+    /// Updates the postmortems of our force closed channels that haven't
+    /// resolved on-chain yet
+    pub(crate) fn update_postmortems(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+        for (user_channel_id, mut closure) in self.persister.list_channel_closures()? {
+            let (Some(channel_id), Some(postmortem)) =
+                (closure.channel_id, closure.postmortem.as_mut())
+            else {
+                continue;
+            };
+            if postmortem.resolved_at.is_some() {
+                continue;
+            }
+            let Some(funding_txo) = self
+                .chain_monitor
+                .list_monitors()
+                .into_iter()
+                .find(|o| o.to_channel_id() == channel_id)
+            else {
+                continue;
+            };
+            let Ok(monitor) = self.chain_monitor.get_monitor(funding_txo) else {
+                continue;
+            };
+
+            let txids = monitor
+                .get_relevant_txids()
+                .into_iter()
+                .map(|(txid, _)| txid)
+                .collect();
+            if postmortem.observe(&monitor.get_claimable_balances(), txids, now) {
+                if postmortem.resolved_at.is_some() {
+                    log_info!(
+                        self.logger,
+                        "Force close of channel {} resolved: {}",
+                        channel_id.to_hex(),
+                        postmortem.summary()
+                    );
+                }
+                self.persister
+                    .persist_channel_closure(user_channel_id, closure)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks for channels where the counterparty broadcast a revoked commitment
+    /// transaction and updates the justice proofs for them.
+    /// Returns true if any of the proofs changed.
+    pub(crate) fn update_justice_proofs(&self, proofs: &mut Vec<JusticeProof>) -> bool {
+        let now = utils::now().as_secs();
+        let mut changed = false;
+        for funding_txo in self.chain_monitor.list_monitors() {
+            let Ok(monitor) = self.chain_monitor.get_monitor(funding_txo) else {
+                continue;
+            };
+
+            let revoked_sats: u64 = monitor
+                .get_claimable_balances()
+                .iter()
+                .filter(|b| matches!(b, Balance::CounterpartyRevokedOutputClaimable { .. }))
+                .map(|b| b.claimable_amount_satoshis())
+                .sum();
+
+            let outpoint = funding_txo.into_bitcoin_outpoint();
+            let index = match proofs.iter().position(|p| p.funding_txo == outpoint) {
+                Some(index) => index,
+                None if revoked_sats > 0 => {
+                    log_error!(
+                        self.logger,
+                        "Counterparty broadcast a revoked state on channel {outpoint}, claiming {revoked_sats} sats"
+                    );
+                    let counterparty = monitor.get_counterparty_node_id();
+                    proofs.push(JusticeProof::new(self.pubkey, counterparty, outpoint, now));
+                    proofs.len() - 1
+                }
+                None => continue,
+            };
+
+            let txids = monitor
+                .get_relevant_txids()
+                .into_iter()
+                .map(|(txid, _)| txid)
+                .collect();
+            let proof = &mut proofs[index];
+            if proof.observe(revoked_sats, txids, now) {
+                if proof.is_claimed() {
+                    log_info!(
+                        self.logger,
+                        "Justice transactions confirmed for channel {outpoint}, recovered {} sats",
+                        proof.amount_recovered_sats
+                    );
+                }
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
     pub fn create_static_channel_backup(&self) -> Result<StaticChannelBackup, MutinyError> {
         let mut monitors = HashMap::new();
         for outpoint in self.chain_monitor.list_monitors() {
@@ -1498,6 +2685,57 @@ impl<S: MutinyStorage> Node<S> {
     }
 }
 
+/// Builds the onion fields for a keysend with the given custom TLV records.
+/// LDK requires the records sorted by key and rejects keys outside the custom range.
+fn keysend_onion_fields(
+    payment_secret: PaymentSecret,
+    custom_tlvs: &[CustomTlv],
+) -> Result<RecipientOnionFields, MutinyError> {
+    let mut records: Vec<(u64, Vec<u8>)> = custom_tlvs
+        .iter()
+        .map(|tlv| (tlv.key, tlv.value.clone()))
+        .collect();
+    records.sort_by_key(|(key, _)| *key);
+
+    RecipientOnionFields::secret_only(payment_secret)
+        .with_custom_tlvs(records)
+        .map_err(|_| MutinyError::InvalidArgumentsError)
+}
+
+/// Estimates the chance a payment along the path succeeds from the liquidity
+/// the scorer has learned for each channel. Our own channel is always usable and
+/// channels the scorer knows nothing about are assumed to have enough liquidity.
+fn path_success_probability(scorer: &ProbScorer, path: &Path) -> f64 {
+    let mut amount_msat = 0;
+    let mut probability = 1.0;
+    // walk back from the destination, each hop forwards the fees of the hops after it
+    for (i, hop) in path.hops.iter().enumerate().rev() {
+        amount_msat += hop.fee_msat;
+        if i == 0 {
+            break;
+        }
+
+        let target = NodeId::from_pubkey(&hop.pubkey);
+        let range = scorer.estimated_channel_liquidity_range(hop.short_channel_id, &target);
+        if let Some((min, max)) = range {
+            probability *= channel_success_probability(amount_msat, min, max);
+        }
+    }
+
+    probability
+}
+
+/// Chance a channel can forward `amount_msat` when its liquidity is somewhere in `min..max`
+fn channel_success_probability(amount_msat: u64, min: u64, max: u64) -> f64 {
+    if amount_msat <= min {
+        1.0
+    } else if amount_msat >= max {
+        0.0
+    } else {
+        (max - amount_msat) as f64 / (max - min) as f64
+    }
+}
+
 pub(crate) fn scoring_params() -> ProbabilisticScoringFeeParameters {
     ProbabilisticScoringFeeParameters {
         base_penalty_amount_multiplier_msat: 8192 * 5, // default * 5
@@ -1518,6 +2756,7 @@ async fn start_reconnection_handling<S: MutinyStorage>(
     lsp_client: &Option<LspClient>,
     stop: Arc<AtomicBool>,
     stopped_components: Arc<RwLock<Vec<bool>>>,
+    reconnection_status: Arc<RwLock<HashMap<NodeId, ReconnectionStatus>>>,
     skip_fee_estimates: bool,
 ) {
     // wait for fee estimates sync to finish, it can cause issues if we try to connect before
@@ -1591,6 +2830,7 @@ async fn start_reconnection_handling<S: MutinyStorage>(
     let connect_fee_estimator = fee_estimator.clone();
     let connect_logger = logger.clone();
     let connect_storage = storage.clone();
+    let lsp_pubkey = lsp_client.as_ref().map(|l| l.pubkey);
     utils::spawn(async move {
         // hashMap to store backoff times for each pubkey
         let mut backoff_times = HashMap::new();
@@ -1617,6 +2857,7 @@ async fn start_reconnection_handling<S: MutinyStorage>(
 
             let peer_connections = get_all_peers(&connect_storage).unwrap_or_default();
             let current_connections = connect_peer_man.get_peer_node_ids();
+            let allowlist = connect_storage.get_peer_allowlist().unwrap_or_default();
 
             let not_connected: Vec<(NodeId, String)> = peer_connections
                 .into_iter()
@@ -1630,6 +2871,10 @@ async fn start_reconnection_handling<S: MutinyStorage>(
                         .iter()
                         .any(|c| &NodeId::from_pubkey(c) == n)
                 })
+                .filter(|(n, _)| {
+                    n.as_pubkey()
+                        .is_ok_and(|pk| allowlist.is_allowed(&pk, lsp_pubkey.as_ref()))
+                })
                 .collect();
 
             for (pubkey, conn_str) in not_connected.into_iter() {
@@ -1667,17 +2912,29 @@ async fn start_reconnection_handling<S: MutinyStorage>(
                     stop.clone(),
                 )
                 .await;
-                match connect_res {
+                let error = match connect_res {
                     Ok(_) => {
                         log_trace!(connect_logger, "auto connected peer: {pubkey}");
                         // reset backoff time to initial value if connection is successful
                         backoff_entry.0 = INITIAL_RECONNECTION_DELAY;
+                        None
                     }
                     Err(e) => {
                         log_warn!(connect_logger, "could not auto connect peer: {e}");
                         // double the backoff time if connection fails, but do not exceed max
                         backoff_entry.0 = (backoff_entry.0 * 2).min(MAX_RECONNECTION_DELAY);
+                        Some(e.to_string())
                     }
+                };
+
+                if let Ok(mut statuses) = reconnection_status.try_write() {
+                    let status = ReconnectionStatus {
+                        timestamp: now.as_secs(),
+                        success: error.is_none(),
+                        error,
+                        backoff_secs: backoff_entry.0,
+                    };
+                    statuses.insert(pubkey, status);
                 }
             }
         }
@@ -1711,6 +2968,71 @@ pub(crate) fn create_peer_manager<S: MutinyStorage>(
     )
 }
 
+/// Whether an LSP's wrapped invoice only asks the payer for our invoice's amount
+/// plus the fee the LSP quoted, so it can't skim more than it told us.
+/// A route hint for an unannounced channel, senders can't find a path to us
+/// over it without one. Uses the SCID alias when the channel has one.
+fn private_route_hint(channel: &ChannelDetails) -> Option<RouteHint> {
+    if channel.is_public || !channel.is_channel_ready {
+        return None;
+    }
+    let short_channel_id = channel.get_inbound_payment_scid()?;
+    let forwarding_info = channel.counterparty.forwarding_info.as_ref()?;
+    Some(RouteHint(vec![RouteHintHop {
+        src_node_id: channel.counterparty.node_id,
+        short_channel_id,
+        fees: RoutingFees {
+            base_msat: forwarding_info.fee_base_msat,
+            proportional_millionths: forwarding_info.fee_proportional_millionths,
+        },
+        cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+        htlc_minimum_msat: channel.inbound_htlc_minimum_msat,
+        htlc_maximum_msat: channel.inbound_htlc_maximum_msat,
+    }]))
+}
+
+/// Picks the route hints to put in an invoice from each channel's inbound
+/// capacity and hint. Channels that can receive the whole amount come first,
+/// then the ones with the most inbound capacity.
+fn select_route_hints(
+    mut candidates: Vec<(u64, RouteHint)>,
+    amount_msat: Option<u64>,
+) -> Vec<RouteHint> {
+    let amount_msat = amount_msat.unwrap_or(0);
+    candidates.sort_by_key(|(inbound, _)| Reverse((*inbound >= amount_msat, *inbound)));
+    candidates
+        .into_iter()
+        .take(MAX_ROUTE_HINTS)
+        .map(|(_, hint)| hint)
+        .collect()
+}
+
+fn wrapped_amount_within_fee(
+    amount_msat: Option<u64>,
+    wrapped_amount_msat: Option<u64>,
+    quoted_fee_msat: u64,
+) -> bool {
+    match (amount_msat, wrapped_amount_msat) {
+        (Some(amount), Some(wrapped)) => wrapped <= amount.saturating_add(quoted_fee_msat),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// The amount to pay an invoice. Zero-amount invoices need an amount from the
+/// caller, invoices with an amount can't have it overridden.
+fn payment_amount_msat(
+    invoice_amount_msat: Option<u64>,
+    amt_sats: Option<u64>,
+) -> Result<u64, MutinyError> {
+    match (invoice_amount_msat, amt_sats) {
+        (None, Some(0)) => Err(MutinyError::BadAmountError),
+        (None, Some(amt_sats)) => Ok(amt_sats * 1_000),
+        (Some(amount_msat), None) => Ok(amount_msat),
+        (None, None) | (Some(_), Some(_)) => Err(MutinyError::InvoiceInvalid),
+    }
+}
+
 pub(crate) fn parse_peer_info(
     peer_pubkey_and_ip_addr: &str,
 ) -> Result<(PublicKey, String), MutinyError> {
@@ -1739,7 +3061,7 @@ pub(crate) fn split_peer_connection_string(
     Ok((pubkey, peer_addr_str.to_string()))
 }
 
-pub(crate) fn default_user_config() -> UserConfig {
+pub(crate) fn default_user_config(cltv_config: &CltvConfig) -> UserConfig {
     UserConfig {
         channel_handshake_limits: ChannelHandshakeLimits {
             // lnd's max to_self_delay is 2016, so we want to be compatible.
@@ -1760,6 +3082,7 @@ pub(crate) fn default_user_config() -> UserConfig {
             // Any lightning payment above this, but below current
             // HTLC fees will have issues paying until anchor outputs
             max_dust_htlc_exposure: MaxDustHTLCExposure::FixedLimitMsat(20_000_000),
+            cltv_expiry_delta: cltv_config.cltv_expiry_delta,
             ..Default::default()
         },
         ..Default::default()
@@ -1772,7 +3095,13 @@ mod tests {
     use bitcoin::secp256k1::PublicKey;
     use std::str::FromStr;
 
-    use crate::node::parse_peer_info;
+    use crate::node::{
+        channel_success_probability, keysend_onion_fields, parse_peer_info, payment_amount_msat,
+        select_route_hints, wrapped_amount_within_fee, MAX_ROUTE_HINTS,
+    };
+    use crate::nodemanager::CustomTlv;
+    use lightning::ln::PaymentSecret;
+    use lightning_invoice::{RouteHint, RouteHintHop, RoutingFees};
 
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
 
@@ -1810,4 +3139,124 @@ mod tests {
         assert_eq!(pub_key, peer_pubkey);
         assert_eq!(format!("{addr}:{port}"), peer_addr);
     }
+
+    #[test]
+    fn test_wrapped_amount_within_fee() {
+        log!("test wrapped amount within fee");
+
+        // the fee was taken out of our invoice
+        assert!(wrapped_amount_within_fee(
+            Some(99_000_000),
+            Some(100_000_000),
+            1_000_000
+        ));
+        // the fee rounded down to sats when it was taken out
+        assert!(wrapped_amount_within_fee(
+            Some(99_000_000),
+            Some(100_000_000),
+            1_000_500
+        ));
+        // the LSP asks the payer for more than it quoted
+        assert!(!wrapped_amount_within_fee(
+            Some(99_000_000),
+            Some(100_000_001),
+            1_000_000
+        ));
+        assert!(!wrapped_amount_within_fee(Some(1_000), None, 0));
+        assert!(wrapped_amount_within_fee(None, None, 0));
+    }
+
+    #[test]
+    fn test_payment_amount_msat() {
+        log!("test payment amount msat");
+
+        // zero-amount invoices are paid with the given amount
+        assert_eq!(payment_amount_msat(None, Some(21)).unwrap(), 21_000);
+        assert!(payment_amount_msat(None, Some(0)).is_err());
+        assert!(payment_amount_msat(None, None).is_err());
+
+        // invoices with an amount can't be overridden
+        assert_eq!(payment_amount_msat(Some(21_000), None).unwrap(), 21_000);
+        assert!(payment_amount_msat(Some(21_000), Some(42)).is_err());
+    }
+
+    #[test]
+    fn test_channel_success_probability() {
+        log!("test channel success probability");
+
+        assert_eq!(channel_success_probability(1_000, 1_000, 5_000), 1.0);
+        assert_eq!(channel_success_probability(3_000, 1_000, 5_000), 0.5);
+        assert_eq!(channel_success_probability(5_000, 1_000, 5_000), 0.0);
+        // a failed probe can leave the range empty
+        assert_eq!(channel_success_probability(5_000, 4_000, 4_000), 0.0);
+    }
+
+    #[test]
+    fn test_select_route_hints() {
+        log!("test select route hints");
+
+        let pubkey = PublicKey::from_str(
+            "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b",
+        )
+        .unwrap();
+        let hint = |short_channel_id| {
+            RouteHint(vec![RouteHintHop {
+                src_node_id: pubkey,
+                short_channel_id,
+                fees: RoutingFees {
+                    base_msat: 1_000,
+                    proportional_millionths: 100,
+                },
+                cltv_expiry_delta: 72,
+                htlc_minimum_msat: None,
+                htlc_maximum_msat: None,
+            }])
+        };
+        let candidates = vec![
+            (10_000, hint(1)),
+            (50_000, hint(2)),
+            (20_000, hint(3)),
+            (30_000, hint(4)),
+        ];
+
+        // most inbound capacity first, limited to the max
+        let hints = select_route_hints(candidates.clone(), None);
+        assert_eq!(hints.len(), MAX_ROUTE_HINTS);
+        assert_eq!(hints, vec![hint(2), hint(4), hint(3)]);
+
+        // channels that can't receive the amount go last
+        let hints = select_route_hints(candidates, Some(40_000));
+        assert_eq!(hints[0], hint(2));
+
+        assert!(select_route_hints(vec![], Some(1_000)).is_empty());
+    }
+
+    #[test]
+    fn test_keysend_onion_fields() {
+        log!("test keysend onion fields");
+
+        let secret = PaymentSecret([0; 32]);
+        let boost = CustomTlv {
+            key: 7629169,
+            value: b"{\"action\":\"boost\"}".to_vec(),
+        };
+        let sender = CustomTlv {
+            key: 696969,
+            value: b"mutiny".to_vec(),
+        };
+
+        // records are sorted for LDK
+        let fields = keysend_onion_fields(secret, &[boost.clone(), sender.clone()]).unwrap();
+        assert_eq!(
+            fields.custom_tlvs(),
+            &vec![(sender.key, sender.value), (boost.key, boost.value)]
+        );
+
+        // keys below the custom range are rejected
+        let invalid = CustomTlv {
+            key: 1,
+            value: vec![],
+        };
+        assert!(keysend_onion_fields(secret, &[invalid]).is_err());
+    }
 }
@@ -1,28 +1,113 @@
 use anyhow::anyhow;
 use lightning::sign::{NodeSigner, Recipient};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::{collections::HashMap, ops::Deref, sync::Arc};
+use std::panic::PanicInfo;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::{
+    collections::HashMap, collections::HashSet, future::Future, ops::Deref, sync::Arc, sync::RwLock,
+};
 
+use crate::activity_index::{ActivityIndexStorage, ActivityRef};
+use crate::alerts::{
+    update_offline_since, AlertCondition, AlertEvent, AlertMetrics, AlertRule, AlertStorage,
+    FAILURE_RATE_WINDOW_SECS,
+};
+use crate::allowlist::{PeerAllowlist, PeerAllowlistStorage};
+use crate::balance_changes::{onchain_balance_changes, BalanceChange, BalanceChangeStorage};
+use crate::channel_policy::{
+    already_handled, evaluate_rules, update_channel_activity, validate_rules, ChannelAction,
+    ChannelPolicyStorage, ChannelRule, RuleExecution, RuleMatch,
+};
+use crate::conflict::{
+    find_state_conflicts, get_state_conflicts, save_state_conflicts, take_over_remote_state,
+    StateConflict,
+};
+use crate::crash::{CrashReport, CrashReportStorage, StateSummary};
+use crate::devices::{DeviceInfo, DeviceRegistryStorage};
+use crate::encoding::migrate_to_binary;
+use crate::enrichment::{
+    ActivityEnricher, EnrichedActivityItem, EnrichmentResolver, LnUrlMetadataResolver,
+    NodeAliasResolver, NostrProfileResolver,
+};
+use crate::external_funding::{ExternalFunding, ExternalFundingStatus, ExternalFundingStorage};
 use crate::gossip::*;
+use crate::history_import::{parse_history, ExternalPayment, ExternalPaymentStorage, ImportSource};
+use crate::hold_invoice::{HoldInvoice, HoldInvoiceStorage};
+use crate::http_cache::cached_get;
+use crate::http_client::{HttpRequest, MutinyHttpClient, ReqwestHttpClient};
+use crate::idempotency::{IdempotencyStorage, IdempotentOperation, IdempotentResult};
+use crate::inbound::{
+    InboundCapacityAlerts, InboundCapacityReport, InboundCapacityWarning, InboundHtlcCounters,
+    InboundHtlcLimits, InboundPolicy, InboundStorage, InboundSuggestion,
+};
+use crate::inheritance::{HeldSweep, InheritancePlan, InheritanceReminder, InheritanceStorage};
+use crate::justice::{JusticeProof, JusticeStorage};
+use crate::lnaddress_pairing::{
+    pairing_ws_url, parse_invoice_request, validate_username, ClientMessage,
+    LightningAddressPairing, LightningAddressPairingStorage, PairingStatus, ServerMessage,
+};
 use crate::lnurlauth::AuthManager;
+use crate::lnurlchannel::{
+    lnurl_service, parse_channel_uri, LnUrlChannelRequest, LnUrlChannelStatus, LnUrlChannelStorage,
+};
+use crate::lnurlpay::{
+    check_pay_amount, check_pay_invoice, lnurl_pay_callback_url, parse_lnurl_or_address,
+    LnUrlPayCallbackResponse,
+};
+use crate::lnurlwithdraw::{
+    check_withdraw_amount, LnUrlWithdrawal, LnUrlWithdrawalStatus, LnUrlWithdrawalStorage,
+};
 use crate::logging::LOGGING_KEY;
 use crate::multiesplora::MultiEsploraClient;
+use crate::networking::websocket::{SimpleWebSocket, WebSocketImpl};
+use crate::nostr::nip05::{
+    resolve_nip05, Nip05Identity, Nip05Status, Nip05Storage, Nip05Verification,
+};
+use crate::nostr::relays::{backoff_secs, RelayConfig, RelayPoolStorage};
+use crate::offline_receive::{PendingReceive, PendingReceiveStorage};
+use crate::payment_metadata::{PaymentMetadata, PaymentMetadataStorage};
+use crate::payment_proof::{self, PaymentProof, VerifiedPaymentProof};
+use crate::payment_retry::{FeeCap, MppConfig, PaymentRetryStorage, RetryPolicy};
+use crate::payment_status::{PaymentState, PaymentStatusUpdate, PaymentSubscriptions};
+use crate::payments::{
+    can_fall_back_on_chain, parse_payment_request, PaymentRequest, UnifiedPayment,
+};
+use crate::receipts::{Receipt, ReceiptStorage, RECEIPT_FIAT_WINDOW_SECS};
+use crate::recurring::{
+    CatchUpPolicy, RecurringPayment, RecurringPaymentStorage, RecurringPaymentTarget,
+    MIN_RECURRING_INTERVAL_SECS,
+};
 use crate::redshift::{RedshiftManager, RedshiftStatus, RedshiftStorage};
+use crate::restore_points::{RestorePointInfo, RestorePointStorage, RESTORE_POINT_INTERVAL_SECS};
+use crate::retention::{PruneSummary, RetentionPolicy, RetentionStorage, PRUNE_INTERVAL_SECS};
+use crate::rollout::{select_endpoint, EndpointSelection, RolloutStorage};
 use crate::scb::{
     EncryptedSCB, StaticChannelBackup, StaticChannelBackupStorage,
     SCB_ENCRYPTION_KEY_DERIVATION_PATH,
 };
+use crate::scheduled_close::{ScheduledClose, ScheduledCloseStorage};
+use crate::splits::{SplitDestination, SplitPaymentResult, SplitStorage, SplitTable};
+use crate::status::{Feature, Service, ServiceEndpoint, StatusMonitor, StatusReport};
 use crate::storage::{MutinyStorage, DEVICE_ID_KEY, KEYCHAIN_STORE_KEY, NEED_FULL_SYNC_KEY};
+use crate::swap_out::{pick_channel, SwapOut, SwapOutPolicy, SwapOutStatus, SwapOutStorage};
+use crate::sweep::{SweepDestination, SweepDestinationStorage};
+use crate::telemetry::{
+    upload_telemetry_summary, TelemetryStorage, TelemetrySummary, TELEMETRY_UPLOAD_INTERVAL_SECS,
+};
+use crate::uri::{parse_uri, ParsedUri, UriIntent};
 use crate::utils::sleep;
+use crate::watchtower::{
+    get_breach_alerts, is_watchtower_enabled, WatchData, WatchedMonitor, Watchtower,
+    WatchtowerStatus, WATCHTOWER_ENABLED_KEY,
+};
 use crate::MutinyWalletConfig;
 use crate::{
     chain::MutinyChain,
     error::MutinyError,
     esplora::EsploraSyncClient,
-    fees::MutinyFeeEstimator,
+    fees::{MutinyFeeEstimator, P2WSH_OUTPUT_SIZE, TAPROOT_OUTPUT_SIZE},
     gossip,
     logging::MutinyLogger,
-    lspclient::LspClient,
+    lspclient::{FeeRequest, LspClient},
     node::{Node, ProbScorer, PubkeyConnectionInfo, RapidGossipSync},
     onchain::get_esplora_url,
     onchain::OnChainWallet,
@@ -32,27 +117,37 @@ use crate::{
     event::{HTLCStatus, PaymentInfo},
     lnurlauth::make_lnurl_auth_connection,
 };
-use crate::{labels::LabelStorage, subscription::MutinySubscriptionClient};
+use crate::{
+    labels::{Contact, LabelStorage},
+    subscription::MutinySubscriptionClient,
+};
 use bdk::chain::{BlockId, ConfirmationTime};
 use bdk::{wallet::AddressIndex, LocalUtxo};
 use bitcoin::blockdata::script;
-use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::secp256k1::{rand, PublicKey, Secp256k1, SecretKey};
 use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
 use bitcoin::{Address, Network, OutPoint, Transaction, Txid};
 use core::time::Duration;
-use esplora_client::Builder;
-use futures::{future::join_all, lock::Mutex};
+use futures::{
+    channel::mpsc::UnboundedReceiver,
+    future::join_all,
+    lock::{Mutex, MutexGuard},
+};
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::channelmonitor::Balance;
 use lightning::chain::Confirm;
-use lightning::events::ClosureReason;
+use lightning::events::{ClosureReason, PathFailure};
 use lightning::io::Read;
-use lightning::ln::channelmanager::{ChannelDetails, PhantomRouteHints};
+use lightning::ln::channelmanager::{
+    ChannelDetails, PhantomRouteHints, MIN_CLTV_EXPIRY_DELTA, MIN_FINAL_CLTV_EXPIRY_DELTA,
+};
 use lightning::ln::msgs::DecodeError;
 use lightning::ln::script::ShutdownScript;
 use lightning::ln::PaymentHash;
-use lightning::routing::gossip::NodeId;
+use lightning::routing::gossip::{NetworkUpdate, NodeId};
+use lightning::util::config::ChannelConfig;
 use lightning::util::logger::*;
 use lightning::util::ser::{Readable, Writeable, Writer};
 use lightning::{log_debug, log_error, log_info, log_warn};
@@ -61,13 +156,14 @@ use lnurl::lnurl::LnUrl;
 use lnurl::{AsyncClient as LnUrlClient, LnUrlResponse, Response};
 use nostr::key::XOnlyPublicKey;
 use nostr::{EventBuilder, Keys, Kind, Tag, TagKind};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::str::FromStr;
 use uuid::Uuid;
 
 const BITCOIN_PRICE_CACHE_SEC: u64 = 300;
+const BITCOIN_PRICE_URL: &str =
+    "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd";
 pub const DEVICE_LOCK_INTERVAL_SECS: u64 = 60;
 
 // This is the NodeStorage object saved to the DB
@@ -177,9 +273,147 @@ pub struct MutinyInvoice {
     pub fees_paid: Option<u64>,
     pub inbound: bool,
     pub labels: Vec<String>,
+    pub order_id: Option<String>,
+    /// Custom TLV records sent with the payment, such as podcast boost metadata
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_tlvs: Vec<CustomTlv>,
+    /// The label, notes and tags the user gave the payment
+    #[serde(default, skip_serializing_if = "PaymentMetadata::is_empty")]
+    pub metadata: PaymentMetadata,
+    /// Why the payment, or some of its paths, failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<PaymentFailure>,
+    /// The paths an outbound payment was split over, added as each one succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<PaymentHtlc>,
     pub last_updated: u64,
 }
 
+/// Everything in a lightning invoice, decoded so frontends don't need their own parser.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DecodedInvoice {
+    pub bolt11: Bolt11Invoice,
+    pub payment_hash: sha256::Hash,
+    pub amount_msat: Option<u64>,
+    pub amount_sats: Option<u64>,
+    pub description: Option<String>,
+    pub description_hash: Option<sha256::Hash>,
+    /// The payee, recovered from the signature if the invoice doesn't include it
+    pub payee_pubkey: PublicKey,
+    /// Epoch time in seconds of when the invoice was created
+    pub timestamp: u64,
+    pub expiry_secs: u64,
+    /// Epoch time in seconds of when the invoice expires
+    pub expires_at: u64,
+    pub min_final_cltv_expiry_delta: u64,
+    /// Private route hints, each one is a list of hops ending at the payee
+    pub route_hints: Vec<Vec<InvoiceRouteHop>>,
+    pub features: InvoiceFeatures,
+}
+
+/// A hop of a route hint in an invoice
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct InvoiceRouteHop {
+    pub src_node_id: PublicKey,
+    pub short_channel_id: u64,
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub cltv_expiry_delta: u16,
+    pub htlc_minimum_msat: Option<u64>,
+    pub htlc_maximum_msat: Option<u64>,
+}
+
+/// The features an invoice sets, whether optional or required
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct InvoiceFeatures {
+    pub var_onion_optin: bool,
+    pub payment_secret: bool,
+    pub basic_mpp: bool,
+    pub payment_metadata: bool,
+    /// The invoice requires features we don't know about, so it can't be paid
+    pub requires_unknown: bool,
+}
+
+impl From<Bolt11Invoice> for DecodedInvoice {
+    fn from(value: Bolt11Invoice) -> Self {
+        let (description, description_hash) = match value.description() {
+            Bolt11InvoiceDescription::Direct(d) if d.is_empty() => (None, None),
+            Bolt11InvoiceDescription::Direct(d) => (Some(d.to_string()), None),
+            Bolt11InvoiceDescription::Hash(h) => (None, Some(h.0)),
+        };
+
+        let route_hints = value
+            .route_hints()
+            .into_iter()
+            .map(|hint| {
+                hint.0
+                    .into_iter()
+                    .map(|hop| InvoiceRouteHop {
+                        src_node_id: hop.src_node_id,
+                        short_channel_id: hop.short_channel_id,
+                        fee_base_msat: hop.fees.base_msat,
+                        fee_proportional_millionths: hop.fees.proportional_millionths,
+                        cltv_expiry_delta: hop.cltv_expiry_delta,
+                        htlc_minimum_msat: hop.htlc_minimum_msat,
+                        htlc_maximum_msat: hop.htlc_maximum_msat,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let features = value
+            .features()
+            .map(|f| InvoiceFeatures {
+                var_onion_optin: f.supports_variable_length_onion(),
+                payment_secret: f.supports_payment_secret(),
+                basic_mpp: f.supports_basic_mpp(),
+                payment_metadata: f.supports_payment_metadata(),
+                requires_unknown: f.requires_unknown_bits(),
+            })
+            .unwrap_or_default();
+
+        let timestamp = value.duration_since_epoch().as_secs();
+        let expiry_secs = value.expiry_time().as_secs();
+
+        DecodedInvoice {
+            payment_hash: value.payment_hash().to_owned(),
+            amount_msat: value.amount_milli_satoshis(),
+            amount_sats: value.amount_milli_satoshis().map(|m| m / 1000),
+            description,
+            description_hash,
+            payee_pubkey: value.recover_payee_pub_key(),
+            timestamp,
+            expiry_secs,
+            expires_at: timestamp + expiry_secs,
+            min_final_cltv_expiry_delta: value.min_final_cltv_expiry_delta(),
+            route_hints,
+            features,
+            bolt11: value,
+        }
+    }
+}
+
+/// A custom TLV record in the onion of a payment. Keys must be in the
+/// custom range (65536 and above) and the value is the raw record bytes.
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Hash)]
+pub struct CustomTlv {
+    pub key: u64,
+    pub value: Vec<u8>,
+}
+
+/// Custom TLV record a keysend can carry the order it pays for in, as UTF-8
+pub const ORDER_ID_TLV_KEY: u64 = 5_482_373_485;
+
+impl CustomTlv {
+    /// The order id carried in the records, if there is a valid one
+    pub(crate) fn order_id(custom_tlvs: &[CustomTlv]) -> Option<String> {
+        custom_tlvs
+            .iter()
+            .find(|tlv| tlv.key == ORDER_ID_TLV_KEY)
+            .and_then(|tlv| String::from_utf8(tlv.value.clone()).ok())
+    }
+}
+
 impl From<Bolt11Invoice> for MutinyInvoice {
     fn from(value: Bolt11Invoice) -> Self {
         let description = match value.description() {
@@ -212,6 +446,11 @@ impl From<Bolt11Invoice> for MutinyInvoice {
             fees_paid: None,
             inbound: true,
             labels: vec![],
+            order_id: None,
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
             last_updated: timestamp,
         }
     }
@@ -245,6 +484,9 @@ impl MutinyInvoice {
                     payee_pubkey: i.payee_pubkey,
                     preimage: i.preimage.map(|p| p.to_hex()),
                     fees_paid: i.fee_paid_msat.map(|f| f / 1_000),
+                    order_id: i.order_id,
+                    custom_tlvs: i.custom_tlvs,
+                    failure: i.failure,
                     ..invoice.into()
                 })
             }
@@ -266,6 +508,11 @@ impl MutinyInvoice {
                     fees_paid,
                     inbound,
                     labels,
+                    order_id: i.order_id,
+                    custom_tlvs: i.custom_tlvs,
+                    metadata: PaymentMetadata::default(),
+                    failure: i.failure,
+                    parts: vec![],
                     last_updated: i.last_update,
                 };
                 Ok(invoice)
@@ -327,6 +574,178 @@ impl From<&ChannelDetails> for MutinyChannel {
     }
 }
 
+/// A snapshot of a channel's internal state, used by support to diagnose stuck channels.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelDebugInfo {
+    pub channel_id: String,
+    pub user_chan_id: String,
+    pub node_id: PublicKey,
+    pub peer: PublicKey,
+    pub peer_connected: bool,
+    pub outpoint: Option<OutPoint>,
+    pub short_channel_id: Option<u64>,
+    pub size: u64,
+    pub balance_msat: u64,
+    pub outbound_capacity_msat: u64,
+    pub next_outbound_htlc_limit_msat: u64,
+    pub inbound_capacity_msat: u64,
+    pub reserve: Option<u64>,
+    pub feerate_sat_per_1000_weight: Option<u32>,
+    pub confirmations_required: Option<u32>,
+    pub confirmations: Option<u32>,
+    pub force_close_spend_delay: Option<u16>,
+    pub is_outbound: bool,
+    pub is_channel_ready: bool,
+    pub is_usable: bool,
+    pub is_public: bool,
+    /// The latest channel monitor update id.
+    /// This increases with every new commitment transaction so it can be
+    /// compared between exports to see if the channel state is advancing.
+    /// LDK doesn't expose the commitment numbers themselves.
+    pub monitor_update_id: Option<u64>,
+    /// The total of our outbound HTLCs in flight over the channel, as the
+    /// channel manager tracks them for routing
+    pub outbound_in_flight_msat: u64,
+    /// The HTLCs of our payments that are in flight over the channel, whether
+    /// or not they are on a commitment transaction yet
+    pub pending_htlcs: Vec<PendingHtlcDebugInfo>,
+    /// The HTLCs on our latest commitment transaction, as the channel monitor
+    /// would claim them if the channel was force closed
+    pub commitment_htlcs: Vec<CommitmentHtlcDebugInfo>,
+    pub last_reconnection: Option<ReconnectionStatus>,
+}
+
+/// An HTLC of one of our payments that has not been resolved
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingHtlcDebugInfo {
+    pub payment_hash: String,
+    pub amount_msat: u64,
+    pub inbound: bool,
+}
+
+/// An HTLC on the latest commitment transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CommitmentHtlcDebugInfo {
+    pub amount_sats: u64,
+    pub inbound: bool,
+    /// For outbound HTLCs the height we can claim it back on-chain,
+    /// for inbound HTLCs the height it expires at.
+    pub height: u32,
+}
+
+/// The result of probing a route to a node, an estimate of how a payment
+/// of the same amount would go without sending one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProbeResult {
+    pub destination: PublicKey,
+    pub amount_sats: u64,
+    /// Whether the probe reached the destination on every path of the route
+    pub success: bool,
+    /// The routing fee of the probed route
+    pub fee_sats: u64,
+    /// Estimated chance that a payment along the route succeeds, from 0 to 1
+    pub probability: f64,
+}
+
+/// What paying an invoice would cost, from a route found without sending anything.
+///
+/// A lower limit on how long funds can be locked up rules out longer routes, which
+/// usually lowers the chance the payment succeeds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PaymentEstimate {
+    pub amount_sats: u64,
+    /// The routing fee of the route found
+    pub fee_sats: u64,
+    /// Estimated chance that a payment along the route succeeds, from 0 to 1
+    pub probability: f64,
+    /// How many paths the payment would be split over
+    pub parts: usize,
+    /// The most blocks our funds would be locked up for if the payment got stuck
+    pub cltv_expiry_delta: u32,
+    /// The most blocks the retry policy allows funds to be locked up for
+    pub max_total_cltv_expiry_delta: u32,
+}
+
+/// The result of paying one of the invoices given to [NodeManager::pay_invoices]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BatchPaymentResult {
+    pub invoice: String,
+    /// The payment, if it was made
+    pub payment: Option<MutinyInvoice>,
+    /// Why the invoice could not be paid
+    pub error: Option<String>,
+}
+
+impl BatchPaymentResult {
+    fn new(invoice: String, res: Result<MutinyInvoice, MutinyError>) -> Self {
+        match res {
+            Ok(payment) => Self {
+                invoice,
+                payment: Some(payment),
+                error: None,
+            },
+            Err(e) => Self {
+                invoice,
+                payment: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// The CLTV deltas our nodes use, in blocks. Longer deltas give more time to
+/// react to a misbehaving peer, at the cost of funds being locked up longer.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CltvConfig {
+    /// The delta we require between an incoming and outgoing HTLC when forwarding
+    pub cltv_expiry_delta: u16,
+    /// The delta the last hop must leave us, put in the invoices we create
+    pub min_final_cltv_expiry_delta: u16,
+}
+
+impl Default for CltvConfig {
+    fn default() -> Self {
+        Self {
+            cltv_expiry_delta: ChannelConfig::default().cltv_expiry_delta,
+            min_final_cltv_expiry_delta: 40,
+        }
+    }
+}
+
+impl CltvConfig {
+    /// Raises deltas below LDK's minimums up to them, as LDK won't use less
+    pub fn new(cltv_expiry_delta: u16, min_final_cltv_expiry_delta: u16) -> Self {
+        Self {
+            cltv_expiry_delta: cltv_expiry_delta.max(MIN_CLTV_EXPIRY_DELTA),
+            min_final_cltv_expiry_delta: min_final_cltv_expiry_delta
+                .max(MIN_FINAL_CLTV_EXPIRY_DELTA),
+        }
+    }
+}
+
+/// The result of the last attempt to automatically reconnect to a peer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectionStatus {
+    pub timestamp: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    /// How long we will wait before trying again, in seconds
+    pub backoff_secs: u64,
+}
+
+/// How long the most recent successful sync took
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SyncMetrics {
+    /// Epoch time in seconds of when the sync finished
+    pub timestamp: u64,
+    /// Time spent syncing the lightning channels, in milliseconds
+    pub ldk_sync_ms: u64,
+    /// Time spent syncing the on-chain wallet, in milliseconds
+    pub onchain_sync_ms: u64,
+    /// How many esplora requests were made at once
+    pub parallel_requests: usize,
+}
+
 /// A wallet transaction
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct TransactionDetails {
@@ -390,6 +809,285 @@ impl From<bdk::TransactionDetails> for TransactionDetails {
     }
 }
 
+/// Average time between blocks, used to estimate when outputs become claimable
+const AVERAGE_BLOCK_TIME_SECS: u64 = 600;
+
+/// What kind of output we are waiting on from a force closed channel
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PendingCloseOutputKind {
+    /// Our balance, waiting for the commitment transaction to confirm
+    AwaitingCommitmentConfirmation,
+    /// Our balance, waiting for the closing or claiming transaction to confirm
+    AwaitingConfirmations,
+    /// An HTLC we have the preimage for that the counterparty can claim back after a timeout
+    Contentious,
+    /// An outbound HTLC we can claim back once it times out
+    HtlcTimeout,
+    /// An inbound HTLC we can claim if we get the preimage before it expires
+    HtlcPreimage,
+    /// An output from a revoked commitment transaction we can claim
+    RevokedOutput,
+}
+
+/// An output from a force closed channel that is not spendable yet
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingCloseOutput {
+    pub funding_txo: OutPoint,
+    pub kind: PendingCloseOutputKind,
+    pub amount_sats: u64,
+    /// The block height at which the output can be claimed, or expires for
+    /// [PendingCloseOutputKind::HtlcPreimage] and [PendingCloseOutputKind::Contentious].
+    /// `None` if it can be claimed as soon as possible, or if the commitment
+    /// transaction hasn't confirmed yet so the height isn't known.
+    pub height: Option<u32>,
+    pub blocks_remaining: Option<u32>,
+    /// Rough estimate assuming ten minute blocks
+    pub estimated_secs_remaining: Option<u64>,
+}
+
+impl PendingCloseOutput {
+    pub(crate) fn new(
+        funding_txo: OutPoint,
+        kind: PendingCloseOutputKind,
+        amount_sats: u64,
+        height: Option<u32>,
+        chain_tip: u32,
+    ) -> Self {
+        let blocks_remaining = height.map(|h| h.saturating_sub(chain_tip));
+        Self {
+            funding_txo,
+            kind,
+            amount_sats,
+            height,
+            blocks_remaining,
+            estimated_secs_remaining: blocks_remaining.map(|b| b as u64 * AVERAGE_BLOCK_TIME_SECS),
+        }
+    }
+}
+
+/// The outputs from force closed channels we are waiting on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingCloseStatus {
+    pub chain_tip: u32,
+    pub outputs: Vec<PendingCloseOutput>,
+}
+
+/// How other nodes can reach one of our nodes to open a channel
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub pubkey: PublicKey,
+    /// The `host:port` of the websocket proxy other nodes connect through,
+    /// `None` if we aren't using one.
+    pub address: Option<String>,
+    /// `pubkey@host:port`, or just the pubkey if there is no address
+    pub connection_string: String,
+    /// What to encode in a QR code for other wallets to scan
+    pub qr_payload: String,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(pubkey: PublicKey, proxy_url: Option<&str>) -> Self {
+        let address = proxy_url.and_then(|url| {
+            let url = url::Url::parse(url).ok()?;
+            let host = url.host_str()?;
+            let port = url.port_or_known_default()?;
+            Some(format!("{host}:{port}"))
+        });
+        let connection_string = match address.as_ref() {
+            Some(address) => format!("{pubkey}@{address}"),
+            None => pubkey.to_string(),
+        };
+
+        Self {
+            pubkey,
+            address,
+            qr_payload: connection_string.clone(),
+            connection_string,
+        }
+    }
+}
+
+/// Where an HTLC is in its lifecycle
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HtlcState {
+    /// We started sending the payment
+    Added,
+    /// The HTLC is locked in on the channel's commitment transaction
+    Committed,
+    Fulfilled,
+    Failed,
+}
+
+impl HtlcState {
+    pub fn is_final(&self) -> bool {
+        matches!(self, HtlcState::Fulfilled | HtlcState::Failed)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HtlcUpdate {
+    pub state: HtlcState,
+    pub timestamp: u64,
+}
+
+/// A single HTLC of a payment.
+///
+/// LDK doesn't tell us about outbound HTLCs until they resolve, so an outbound
+/// payment starts with a single `Added` entry for the whole payment and gets an
+/// entry per path as each one succeeds or fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PaymentHtlc {
+    pub inbound: bool,
+    /// The channel the HTLC went through, the short channel id for outbound
+    /// HTLCs and the channel id for inbound HTLCs.
+    pub channel: Option<String>,
+    pub amount_msat: Option<u64>,
+    pub state: HtlcState,
+    pub updates: Vec<HtlcUpdate>,
+}
+
+impl PaymentHtlc {
+    pub(crate) fn new(
+        inbound: bool,
+        channel: Option<String>,
+        amount_msat: Option<u64>,
+        state: HtlcState,
+        now: u64,
+    ) -> Self {
+        Self {
+            inbound,
+            channel,
+            amount_msat,
+            state,
+            updates: vec![HtlcUpdate {
+                state,
+                timestamp: now,
+            }],
+        }
+    }
+
+    pub(crate) fn update(&mut self, state: HtlcState, now: u64) {
+        if self.state.is_final() || self.state == state {
+            return;
+        }
+        self.state = state;
+        self.updates.push(HtlcUpdate {
+            state,
+            timestamp: now,
+        });
+    }
+}
+
+/// The most failed paths kept for a payment, LDK can retry many times
+const MAX_FAILED_PATHS: usize = 25;
+
+/// Why an outbound payment failed, kept with the payment to debug routing problems.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PaymentFailure {
+    /// Why we gave up on the payment, not set while it is still being retried
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The paths that failed, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<FailedPath>,
+}
+
+impl PaymentFailure {
+    pub(crate) fn add_path(&mut self, path: FailedPath) {
+        self.paths.push(path);
+        if self.paths.len() > MAX_FAILED_PATHS {
+            self.paths.remove(0);
+        }
+    }
+}
+
+/// A path of an outbound payment that failed.
+///
+/// LDK doesn't give us the raw BOLT 4 error code outside of its own tests, what
+/// it does give us is the node or channel the error blamed and any channel
+/// update that came with it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FailedPath {
+    pub amount_msat: u64,
+    /// The node that failed the payment, if the error blamed a node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failing_node: Option<PublicKey>,
+    /// The channel that failed the payment, if the error blamed a channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failing_channel: Option<u64>,
+    /// The failing node or channel shouldn't be tried again
+    pub permanent: bool,
+    /// The recipient rejected the payment, so no other path will work either
+    pub rejected_by_recipient: bool,
+    /// The channel's new fees, if it failed because we were using outdated ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggested_fee: Option<SuggestedFeeUpdate>,
+    /// Set if we couldn't send the HTLC at all, such as when the channel was offline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_error: Option<String>,
+    pub timestamp: u64,
+}
+
+impl FailedPath {
+    pub(crate) fn new(
+        failure: &PathFailure,
+        short_channel_id: Option<u64>,
+        payment_failed_permanently: bool,
+        amount_msat: u64,
+        now: u64,
+    ) -> Self {
+        let mut path = Self {
+            amount_msat,
+            failing_node: None,
+            failing_channel: short_channel_id,
+            permanent: false,
+            rejected_by_recipient: payment_failed_permanently,
+            suggested_fee: None,
+            send_error: None,
+            timestamp: now,
+        };
+
+        match failure {
+            PathFailure::InitialSend { err } => path.send_error = Some(format!("{err:?}")),
+            PathFailure::OnPath { network_update } => match network_update {
+                Some(NetworkUpdate::ChannelUpdateMessage { msg }) => {
+                    path.failing_channel = Some(msg.contents.short_channel_id);
+                    path.suggested_fee = Some(SuggestedFeeUpdate {
+                        fee_base_msat: msg.contents.fee_base_msat,
+                        fee_proportional_millionths: msg.contents.fee_proportional_millionths,
+                        cltv_expiry_delta: msg.contents.cltv_expiry_delta,
+                    });
+                }
+                Some(NetworkUpdate::ChannelFailure {
+                    short_channel_id,
+                    is_permanent,
+                }) => {
+                    path.failing_channel = Some(*short_channel_id);
+                    path.permanent |= is_permanent;
+                }
+                Some(NetworkUpdate::NodeFailure {
+                    node_id,
+                    is_permanent,
+                }) => {
+                    path.failing_node = Some(*node_id);
+                    path.permanent |= is_permanent;
+                }
+                None => {}
+            },
+        }
+
+        path
+    }
+}
+
+/// The fees a channel asked for in the channel update sent with a failure
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SuggestedFeeUpdate {
+    pub fee_base_msat: u32,
+    pub fee_proportional_millionths: u32,
+    pub cltv_expiry_delta: u16,
+}
+
 /// Information about a channel that was closed.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ChannelClosure {
@@ -398,6 +1096,9 @@ pub struct ChannelClosure {
     pub node_id: Option<PublicKey>,
     pub reason: String,
     pub timestamp: u64,
+    /// Only set for force closes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postmortem: Option<ForceClosePostmortem>,
 }
 
 impl ChannelClosure {
@@ -411,12 +1112,187 @@ impl ChannelClosure {
             user_channel_id: Some(user_channel_id.to_be_bytes()),
             channel_id: Some(channel_id),
             node_id,
+            postmortem: ForceClosePostmortem::from_closure_reason(&reason),
             reason: reason.to_string(),
             timestamp: utils::now().as_secs(),
         }
     }
 }
 
+/// The errors LDK closes a channel with when the counterparty's fee rate is too far from ours
+const FEERATE_ERROR_PREFIXES: [&str; 2] = [
+    "Peer's feerate much too low",
+    "Peer's feerate much too high",
+];
+
+/// Why a channel was force closed, as best as we can tell
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ForceCloseReason {
+    /// An HTLC was about to expire so the channel had to go on-chain to claim it
+    HtlcTimeout,
+    /// The counterparty broadcast a revoked commitment transaction
+    StaleState,
+    /// We could not agree with the counterparty on the commitment fee rate
+    FeeDisagreement,
+    /// We force closed the channel
+    HolderForceClosed,
+    /// The counterparty force closed the channel
+    CounterpartyForceClosed,
+    /// We hit an error processing a message from the counterparty
+    ProcessingError,
+    /// A commitment transaction confirmed without either side telling us why
+    CommitmentConfirmed,
+}
+
+/// Details about a force close to help the user understand what happened
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ForceClosePostmortem {
+    pub reason: ForceCloseReason,
+    /// Whether we broadcast the commitment transaction, `None` if unknown
+    pub initiated_by_us: Option<bool>,
+    /// The error message given by us or the counterparty, if any
+    pub message: Option<String>,
+    pub funding_txo: Option<OutPoint>,
+    /// The on-chain transactions involved in the close
+    #[serde(default)]
+    pub txids: Vec<Txid>,
+    /// Our balance when the channel closed, `None` if the channel monitor
+    /// wasn't found
+    #[serde(default)]
+    pub amount_sats: Option<u64>,
+    /// What we are still waiting to claim on-chain
+    #[serde(default)]
+    pub pending_sats: u64,
+    /// Epoch time in seconds of when everything was claimed on-chain
+    #[serde(default)]
+    pub resolved_at: Option<u64>,
+}
+
+impl ForceClosePostmortem {
+    /// Returns `None` for closures that are not force closes
+    pub(crate) fn from_closure_reason(reason: &ClosureReason) -> Option<Self> {
+        let (reason, initiated_by_us, message) = match reason {
+            ClosureReason::HolderForceClosed => {
+                (ForceCloseReason::HolderForceClosed, Some(true), None)
+            }
+            // the counterparty's message is untrusted, only show it
+            ClosureReason::CounterpartyForceClosed { peer_msg } => (
+                ForceCloseReason::CounterpartyForceClosed,
+                Some(false),
+                Some(peer_msg.to_string()),
+            ),
+            ClosureReason::ProcessingError { err } => {
+                let reason = if FEERATE_ERROR_PREFIXES.iter().any(|p| err.starts_with(p)) {
+                    ForceCloseReason::FeeDisagreement
+                } else {
+                    ForceCloseReason::ProcessingError
+                };
+                (reason, Some(true), Some(err.clone()))
+            }
+            ClosureReason::CommitmentTxConfirmed => {
+                (ForceCloseReason::CommitmentConfirmed, None, None)
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            reason,
+            initiated_by_us,
+            message,
+            funding_txo: None,
+            txids: vec![],
+            amount_sats: None,
+            pending_sats: 0,
+            resolved_at: None,
+        })
+    }
+
+    /// Updates the postmortem with what the channel monitor has seen on-chain.
+    /// Returns true if anything changed.
+    pub(crate) fn observe(&mut self, balances: &[Balance], txids: Vec<Txid>, now: u64) -> bool {
+        if self.resolved_at.is_some() {
+            return false;
+        }
+        let before = self.clone();
+
+        if balances
+            .iter()
+            .any(|b| matches!(b, Balance::CounterpartyRevokedOutputClaimable { .. }))
+        {
+            self.reason = ForceCloseReason::StaleState;
+        } else if self.reason == ForceCloseReason::CommitmentConfirmed
+            && balances
+                .iter()
+                .any(|b| matches!(b, Balance::MaybeTimeoutClaimableHTLC { .. }))
+        {
+            // a commitment confirming while we have outbound HTLCs
+            // to time out is usually us going on-chain to claim them
+            self.reason = ForceCloseReason::HtlcTimeout;
+        }
+
+        // the monitor forgets transactions once they are buried deep enough
+        for txid in txids {
+            if !self.txids.contains(&txid) {
+                self.txids.push(txid);
+            }
+        }
+
+        let pending_sats = balances.iter().map(|b| b.claimable_amount_satoshis()).sum();
+        if self.amount_sats.is_none() {
+            self.amount_sats = Some(pending_sats);
+        }
+        self.pending_sats = pending_sats;
+        if balances.is_empty() {
+            self.resolved_at = Some(now);
+        }
+
+        *self != before
+    }
+
+    /// A human-readable explanation of the force close
+    pub fn summary(&self) -> String {
+        let mut summary = match self.reason {
+            ForceCloseReason::HtlcTimeout => {
+                "A pending payment was about to expire, so the channel was closed on-chain to claim it.".to_string()
+            }
+            ForceCloseReason::StaleState => {
+                "Your channel partner broadcast an old channel state, the funds were claimed with a penalty transaction.".to_string()
+            }
+            ForceCloseReason::FeeDisagreement => {
+                "Your node and your channel partner could not agree on a fee rate for the channel.".to_string()
+            }
+            ForceCloseReason::HolderForceClosed => "You force closed the channel.".to_string(),
+            ForceCloseReason::CounterpartyForceClosed => {
+                "Your channel partner force closed the channel.".to_string()
+            }
+            ForceCloseReason::ProcessingError => {
+                "Your node closed the channel after an error with your channel partner.".to_string()
+            }
+            ForceCloseReason::CommitmentConfirmed => {
+                "A channel state was broadcast and confirmed on-chain.".to_string()
+            }
+        };
+
+        if let Some(message) = self.message.as_ref().filter(|m| !m.is_empty()) {
+            summary.push_str(&format!(" Message: \"{message}\"."));
+        }
+        if self.resolved_at.is_some() {
+            summary.push_str(" All funds were claimed on-chain.");
+        } else if self.pending_sats > 0 {
+            summary.push_str(&format!(
+                " {} sats are still waiting to be claimed on-chain.",
+                self.pending_sats
+            ));
+        }
+        if !self.txids.is_empty() {
+            let txids: Vec<String> = self.txids.iter().map(|t| t.to_string()).collect();
+            summary.push_str(&format!(" Transactions: {}.", txids.join(", ")));
+        }
+
+        summary
+    }
+}
+
 impl PartialOrd for ChannelClosure {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
@@ -434,6 +1310,8 @@ pub enum ActivityItem {
     OnChain(TransactionDetails),
     Lightning(Box<MutinyInvoice>),
     ChannelClosed(ChannelClosure),
+    /// A payment imported from another wallet's history
+    External(ExternalPayment),
 }
 
 impl ActivityItem {
@@ -445,6 +1323,7 @@ impl ActivityItem {
             },
             ActivityItem::Lightning(i) => Some(i.last_updated),
             ActivityItem::ChannelClosed(c) => Some(c.timestamp),
+            ActivityItem::External(p) => Some(p.timestamp),
         }
     }
 
@@ -453,6 +1332,7 @@ impl ActivityItem {
             ActivityItem::OnChain(t) => t.labels.clone(),
             ActivityItem::Lightning(i) => i.labels.clone(),
             ActivityItem::ChannelClosed(_) => vec![],
+            ActivityItem::External(_) => vec![],
         }
     }
 
@@ -463,6 +1343,7 @@ impl ActivityItem {
             }
             ActivityItem::Lightning(_) => false,
             ActivityItem::ChannelClosed(_) => false,
+            ActivityItem::External(_) => false,
         }
     }
 }
@@ -486,6 +1367,7 @@ impl Ord for ActivityItem {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MutinyBalance {
     pub confirmed: u64,
     pub unconfirmed: u64,
@@ -510,6 +1392,19 @@ pub struct Plan {
     pub amount_sat: u64,
 }
 
+/// Holds the command queue for a mutating operation and bumps the
+/// wallet's generation once the operation is done.
+struct CommandGuard<'a> {
+    _guard: MutexGuard<'a, ()>,
+    generation: &'a AtomicU64,
+}
+
+impl Drop for CommandGuard<'_> {
+    fn drop(&mut self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// The [NodeManager] is the main entry point for interacting with the Mutiny Wallet.
 /// It is responsible for managing the on-chain wallet and the lightning nodes.
 ///
@@ -534,11 +1429,31 @@ pub struct NodeManager<S: MutinyStorage> {
     pub(crate) nodes: Arc<Mutex<HashMap<PublicKey, Arc<Node<S>>>>>,
     auth: AuthManager,
     lnurl_client: Arc<LnUrlClient>,
+    http_client: Arc<dyn MutinyHttpClient>,
     pub(crate) lsp_clients: Vec<LspClient>,
     pub(crate) subscription_client: Option<Arc<MutinySubscriptionClient>>,
     pub(crate) logger: Arc<MutinyLogger>,
     bitcoin_price_cache: Arc<Mutex<Option<(f32, Duration)>>>,
+    telemetry_url: Option<String>,
     do_not_connect_peers: bool,
+    validate_gossip: bool,
+    cltv_config: CltvConfig,
+    /// Subscribers to payment updates, shared with every node's event handler
+    payment_subscriptions: Arc<PaymentSubscriptions>,
+    /// Held for the duration of any operation that moves funds or changes
+    /// state, so overlapping calls run one after another. Reads don't take it.
+    command_queue: Mutex<()>,
+    /// Bumped every time the wallet's state changes, see [NodeManager::generation]
+    generation: Arc<AtomicU64>,
+    enricher: Arc<ActivityEnricher<S>>,
+    last_sync_metrics: Arc<RwLock<Option<SyncMetrics>>>,
+    /// Wallet state as of the last sync, so crash reports don't have to
+    /// call into the nodes from inside a panic hook
+    state_summary: Arc<RwLock<Option<StateSummary>>>,
+    /// The watch-only companion, while it is enabled
+    watchtower: Mutex<Option<Arc<Watchtower<S>>>>,
+    status_monitor: Arc<StatusMonitor>,
+    endpoint_selections: Vec<EndpointSelection>,
 }
 
 impl<S: MutinyStorage> NodeManager<S> {
@@ -548,27 +1463,108 @@ impl<S: MutinyStorage> NodeManager<S> {
         storage.get_mnemonic().is_ok_and(|x| x.is_some())
     }
 
+    /// Returns the channel state the remote backup has newer than this device,
+    /// as found by the last startup. When this isn't empty startup fails with
+    /// [MutinyError::StaleChannelState] until told to take over the remote state.
+    pub fn get_state_conflicts(storage: S) -> Result<Vec<StateConflict>, MutinyError> {
+        get_state_conflicts(&storage)
+    }
+
     /// Creates a new [NodeManager] with the given parameters.
     /// The mnemonic seed is read from storage, unless one is provided.
     /// If no mnemonic is provided, a new one is generated and stored.
     pub async fn new(c: MutinyWalletConfig, storage: S) -> Result<NodeManager<S>, MutinyError> {
         let stop = Arc::new(AtomicBool::new(false));
 
+        let logger = Arc::new(MutinyLogger::with_writer(stop.clone(), storage.clone()));
+
+        // pick between the primary and canary endpoints for this wallet
+        let bucket = storage.get_rollout_bucket()?;
+        let canaries = &c.canary_endpoints;
+        let mut endpoint_selections = vec![
+            select_endpoint(
+                Service::Esplora,
+                c.user_esplora_url.clone(),
+                canaries,
+                bucket,
+            ),
+            select_endpoint(Service::Rgs, c.user_rgs_url.clone(), canaries, bucket),
+            select_endpoint(Service::Lsp, c.lsp_url.clone(), canaries, bucket),
+        ];
         #[cfg(target_arch = "wasm32")]
-        let websocket_proxy_addr = c
-            .websocket_proxy_addr
-            .unwrap_or_else(|| String::from("wss://p.mutinywallet.com"));
+        endpoint_selections.push(select_endpoint(
+            Service::Proxy,
+            c.websocket_proxy_addr.clone(),
+            canaries,
+            bucket,
+        ));
+        // remote storage is set up before the wallet, so only report what was picked
+        if let Some(vss) = storage.vss_client() {
+            endpoint_selections.push(EndpointSelection {
+                service: Service::Storage,
+                url: Some(vss.url().to_string()),
+                canary: canaries
+                    .iter()
+                    .any(|e| e.service == Service::Storage && e.url == vss.url()),
+            });
+        }
+        for selection in endpoint_selections.iter().filter(|s| s.canary) {
+            log_info!(
+                logger,
+                "Using canary endpoint for {:?}: {:?}",
+                selection.service,
+                selection.url
+            );
+        }
+        let selected_url = |service: Service| {
+            endpoint_selections
+                .iter()
+                .find(|s| s.service == service)
+                .and_then(|s| s.url.clone())
+        };
+        let user_esplora_url = selected_url(Service::Esplora);
+        let user_rgs_url = selected_url(Service::Rgs);
+        let lsp_url = selected_url(Service::Lsp);
 
-        let logger = Arc::new(MutinyLogger::with_writer(stop.clone(), storage.clone()));
+        #[cfg(target_arch = "wasm32")]
+        let websocket_proxy_addr = selected_url(Service::Proxy)
+            .unwrap_or_else(|| String::from("wss://p.mutinywallet.com"));
 
         // Need to prevent other devices from running at the same time
         if !c.skip_device_lock {
             storage.set_device_lock()?;
         }
 
-        let storage_clone = storage.clone();
-        let logger_clone = logger.clone();
-        let stop_clone = stop.clone();
+        // Going online with older channel state than another device
+        // could broadcast a revoked commitment, refuse unless told to take over
+        let conflicts = find_state_conflicts(&storage).await?;
+        // saved so the app can show what is newer on the other device
+        save_state_conflicts(&storage, &conflicts)?;
+        if !conflicts.is_empty() {
+            for conflict in conflicts.iter() {
+                log_error!(
+                    logger,
+                    "Remote backup has newer state for {}: local {:?}, remote {}",
+                    conflict.key,
+                    conflict.local_version,
+                    conflict.remote_version
+                );
+            }
+
+            if !c.force_takeover {
+                return Err(MutinyError::StaleChannelState);
+            }
+
+            log_warn!(
+                logger,
+                "Taking over the channel state from the remote backup"
+            );
+            take_over_remote_state(&storage, &conflicts).await?;
+        }
+
+        let storage_clone = storage.clone();
+        let logger_clone = logger.clone();
+        let stop_clone = stop.clone();
         utils::spawn(async move {
             loop {
                 if stop_clone.load(Ordering::Relaxed) {
@@ -581,18 +1577,15 @@ impl<S: MutinyStorage> NodeManager<S> {
             }
         });
 
-        let esplora_server_url = get_esplora_url(c.network, c.user_esplora_url);
-        let esplora_clients = {
-            // esplora_server_url is a space separated list of urls
-            let urls = esplora_server_url.split(' ').collect::<Vec<_>>();
-            let mut clients = Vec::with_capacity(urls.len());
-            for url in urls {
-                let client = Builder::new(url).build_async()?;
-                clients.push(Arc::new(client));
-            }
-            clients
+        let http_client: Arc<dyn MutinyHttpClient> = match c.http_client.clone() {
+            Some(http_client) => http_client,
+            None => Arc::new(ReqwestHttpClient::default()),
         };
-        let esplora = MultiEsploraClient::new(esplora_clients);
+
+        let esplora_server_url = get_esplora_url(c.network, user_esplora_url);
+        let esplora = MultiEsploraClient::from_urls(&esplora_server_url)?
+            .with_parallel_requests(c.sync_parallel_requests)
+            .with_http_client(http_client.clone());
         let tx_sync = Arc::new(EsploraSyncClient::from_client(
             esplora.clone(),
             logger.clone(),
@@ -617,27 +1610,34 @@ impl<S: MutinyStorage> NodeManager<S> {
 
         let chain = Arc::new(MutinyChain::new(tx_sync, wallet.clone(), logger.clone()));
 
+        let rgs_url = get_rgs_url(c.network, user_rgs_url.clone(), Some(0));
         let (gossip_sync, scorer) = get_gossip_sync(
             &storage,
-            c.user_rgs_url,
+            user_rgs_url,
             c.scorer_url,
             c.auth_client.clone(),
+            http_client.as_ref(),
             c.network,
             logger.clone(),
         )
         .await?;
+        if let Some(progress) = c.startup_progress.as_ref() {
+            progress.network_graph_loaded();
+        }
 
         let scorer = Arc::new(utils::Mutex::new(scorer));
 
         let gossip_sync = Arc::new(gossip_sync);
 
         // load lsp clients, if any
-        let lsp_clients: Vec<LspClient> = match c.lsp_url.clone() {
+        let lsp_clients: Vec<LspClient> = match lsp_url {
             // check if string is some and not an empty string
             Some(lsp_urls) if !lsp_urls.is_empty() => {
                 let urls: Vec<&str> = lsp_urls.split(',').collect();
 
-                let futs = urls.into_iter().map(|url| LspClient::new(url.trim()));
+                let futs = urls
+                    .into_iter()
+                    .map(|url| LspClient::new(url.trim(), http_client.clone()));
 
                 let results = futures::future::join_all(futs).await;
 
@@ -655,6 +1655,28 @@ impl<S: MutinyStorage> NodeManager<S> {
             _ => Vec::new(),
         };
 
+        // the endpoints to watch so we know which features will work
+        let mut endpoints = vec![];
+        #[cfg(target_arch = "wasm32")]
+        endpoints.push(ServiceEndpoint::head(Service::Proxy, &websocket_proxy_addr));
+        for url in esplora_server_url.split(' ') {
+            endpoints.push(ServiceEndpoint::get(
+                Service::Esplora,
+                url,
+                "/blocks/tip/height",
+            ));
+        }
+        if let Some(rgs_url) = rgs_url {
+            endpoints.push(ServiceEndpoint::head(Service::Rgs, &rgs_url));
+        }
+        for lsp in lsp_clients.iter() {
+            endpoints.push(ServiceEndpoint::get(Service::Lsp, &lsp.url, "/api/v1/info"));
+        }
+        if let Some(vss) = storage.vss_client() {
+            endpoints.push(ServiceEndpoint::head(Service::Storage, vss.url()));
+        }
+        let status_monitor = Arc::new(StatusMonitor::new(endpoints));
+
         let node_storage = storage.get_nodes()?;
 
         // Remove the archived nodes, we don't need to start them up.
@@ -662,8 +1684,14 @@ impl<S: MutinyStorage> NodeManager<S> {
             .clone()
             .nodes
             .into_iter()
-            .filter(|(_, n)| !n.is_archived());
+            .filter(|(_, n)| !n.is_archived())
+            .collect::<Vec<_>>();
+        if let Some(progress) = c.startup_progress.as_ref() {
+            progress.set_nodes_total(unarchived_nodes.len());
+        }
 
+        let generation = Arc::new(AtomicU64::new(0));
+        let payment_subscriptions = Arc::new(PaymentSubscriptions::default());
         let mut nodes_map = HashMap::new();
 
         for node_item in unarchived_nodes {
@@ -681,7 +1709,11 @@ impl<S: MutinyStorage> NodeManager<S> {
                 &esplora,
                 &lsp_clients,
                 logger.clone(),
+                generation.clone(),
                 c.do_not_connect_peers,
+                c.validate_gossip,
+                c.cltv_config,
+                payment_subscriptions.clone(),
                 false,
                 #[cfg(target_arch = "wasm32")]
                 websocket_proxy_addr.clone(),
@@ -692,6 +1724,9 @@ impl<S: MutinyStorage> NodeManager<S> {
                 .keys_manager
                 .get_node_id(Recipient::Node)
                 .expect("Failed to get node id");
+            if let Some(progress) = c.startup_progress.as_ref() {
+                progress.node_loaded(node.chain_monitor.list_monitors().len());
+            }
 
             nodes_map.insert(id, Arc::new(node));
         }
@@ -713,6 +1748,22 @@ impl<S: MutinyStorage> NodeManager<S> {
 
         log_info!(logger, "inserted updated nodes");
 
+        if !storage.is_activity_index_built()? {
+            log_info!(logger, "indexing existing activity");
+            for node in nodes_map.values() {
+                node.persister.index_existing_activity()?;
+            }
+            storage.set_activity_index_built()?;
+        }
+
+        let migrated = migrate_to_binary(&storage)?;
+        if migrated > 0 {
+            log_info!(
+                logger,
+                "Migrated {migrated} values to the binary storage format"
+            );
+        }
+
         let nodes = Arc::new(Mutex::new(nodes_map));
 
         let lnurl_client = Arc::new(
@@ -738,6 +1789,17 @@ impl<S: MutinyStorage> NodeManager<S> {
             (None, auth_manager)
         };
 
+        let enricher = Arc::new(ActivityEnricher::new(storage.clone(), logger.clone()));
+        enricher.register(Arc::new(NostrProfileResolver {
+            storage: storage.clone(),
+        }));
+        enricher.register(Arc::new(LnUrlMetadataResolver {
+            client: lnurl_client.clone(),
+        }));
+        enricher.register(Arc::new(NodeAliasResolver {
+            network_graph: gossip_sync.network_graph().clone(),
+        }));
+
         let nm = NodeManager {
             stop,
             xprivkey: c.xprivkey,
@@ -755,16 +1817,67 @@ impl<S: MutinyStorage> NodeManager<S> {
             esplora,
             auth,
             lnurl_client,
+            http_client,
             lsp_clients,
             subscription_client,
             logger,
             bitcoin_price_cache: Arc::new(Mutex::new(None)),
+            telemetry_url: c.telemetry_url,
             do_not_connect_peers: c.do_not_connect_peers,
+            validate_gossip: c.validate_gossip,
+            cltv_config: c.cltv_config,
+            payment_subscriptions,
+            command_queue: Mutex::new(()),
+            generation,
+            enricher,
+            last_sync_metrics: Arc::new(RwLock::new(None)),
+            state_summary: Arc::new(RwLock::new(None)),
+            watchtower: Mutex::new(None),
+            status_monitor,
+            endpoint_selections,
         };
 
         Ok(nm)
     }
 
+    /// Waits for any in progress mutating operation to finish.
+    ///
+    /// The returned guard must be held until the operation is done. Operations
+    /// that call other queued operations must not take it themselves, the lock
+    /// is not reentrant.
+    async fn queue_command(&self) -> CommandGuard<'_> {
+        CommandGuard {
+            _guard: self.command_queue.lock().await,
+            generation: &self.generation,
+        }
+    }
+
+    /// A counter that goes up every time the wallet's state changes, such as
+    /// after a payment, a channel open or close, a lightning event or a sync.
+    ///
+    /// Embedders can store the generation along with any data they cache and
+    /// refresh the cache once the generation no longer matches.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Runs the given reads without any mutating operations happening in between.
+    ///
+    /// Returns the result along with the generation it was read at, background
+    /// lightning events can still change the state so compare it with
+    /// [NodeManager::generation] to know if the data is stale.
+    ///
+    /// The reads must not call any mutating operations, that would deadlock.
+    pub async fn with_snapshot<T, F, Fut>(&self, f: F) -> (T, u64)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let _queue = self.command_queue.lock().await;
+        let generation = self.generation();
+        (f().await, generation)
+    }
+
     /// Returns the node with the given pubkey
     pub(crate) async fn get_node(&self, pk: &PublicKey) -> Result<Arc<Node<S>>, MutinyError> {
         let nodes = self.nodes.lock().await;
@@ -776,6 +1889,9 @@ impl<S: MutinyStorage> NodeManager<S> {
     /// Returns after node has been stopped.
     pub async fn stop(&self) -> Result<(), MutinyError> {
         self.stop.swap(true, Ordering::Relaxed);
+        if let Some(watchtower) = self.watchtower.lock().await.take() {
+            watchtower.stop();
+        }
         let mut nodes = self.nodes.lock().await;
         let node_futures = nodes.iter().map(|(_, n)| async {
             match n.stop().await {
@@ -891,6 +2007,8 @@ impl<S: MutinyStorage> NodeManager<S> {
                     return;
                 }
 
+                nm.check_service_status().await;
+
                 // we don't need to re-sync fees every time
                 // just do it every 10 minutes
                 if let Err(e) = nm.fee_estimator.update_fee_estimates_if_necessary().await {
@@ -899,12 +2017,111 @@ impl<S: MutinyStorage> NodeManager<S> {
                     log_info!(nm.logger, "Updated fee estimates!");
                 }
 
-                if let Err(e) = nm.sync().await {
+                let start = utils::now();
+                if !nm.is_feature_available(Feature::ChainSync) {
+                    log_warn!(nm.logger, "Skipping sync, esplora is down");
+                } else if let Err(e) = nm.sync().await {
                     log_error!(nm.logger, "Failed to sync: {e}");
-                } else if !synced {
-                    // if this is the first sync, set the done_first_sync flag
-                    let _ = nm.storage.set_done_first_sync();
-                    synced = true;
+                } else {
+                    let _ = nm
+                        .storage
+                        .record_sync_telemetry(utils::now().saturating_sub(start));
+
+                    if !synced {
+                        // if this is the first sync, set the done_first_sync flag
+                        let _ = nm.storage.set_done_first_sync();
+                        synced = true;
+                    }
+
+                    if let Err(e) = nm.record_onchain_balance_changes().await {
+                        log_warn!(nm.logger, "Failed to record on-chain balance changes: {e}");
+                    }
+
+                    if let Err(e) = nm.record_wallet_birthday_if_necessary().await {
+                        log_warn!(nm.logger, "Failed to record wallet birthday: {e}");
+                    }
+
+                    if let Err(e) = nm.refresh_inheritance_sweep().await {
+                        log_warn!(nm.logger, "Failed to refresh inheritance sweep: {e}");
+                    }
+
+                    if let Err(e) = nm.broadcast_unlocked_inheritance_sweeps().await {
+                        log_warn!(nm.logger, "Failed to broadcast inheritance sweeps: {e}");
+                    }
+
+                    nm.generation.fetch_add(1, Ordering::Relaxed);
+                }
+
+                if let Err(e) = nm.upload_telemetry_if_necessary().await {
+                    log_warn!(nm.logger, "Failed to upload telemetry: {e}");
+                }
+
+                if let Err(e) = nm.check_inheritance_reminders() {
+                    log_warn!(nm.logger, "Failed to check inheritance reminders: {e}");
+                }
+
+                if let Err(e) = nm.check_justice_proofs().await {
+                    log_warn!(nm.logger, "Failed to check for justice transactions: {e}");
+                }
+
+                if let Err(e) = nm.check_force_close_postmortems().await {
+                    log_warn!(nm.logger, "Failed to update force close postmortems: {e}");
+                }
+
+                if let Err(e) = nm.check_channel_rules().await {
+                    log_warn!(nm.logger, "Failed to run channel rules: {e}");
+                }
+
+                if let Err(e) = nm.check_scheduled_closes().await {
+                    log_warn!(nm.logger, "Failed to check scheduled channel closes: {e}");
+                }
+
+                if let Err(e) = nm.check_recurring_payments().await {
+                    log_warn!(nm.logger, "Failed to make recurring payments: {e}");
+                }
+
+                if let Err(e) = nm.expire_invoices().await {
+                    log_warn!(nm.logger, "Failed to expire invoices: {e}");
+                }
+
+                if let Err(e) = nm.check_external_fundings().await {
+                    log_warn!(nm.logger, "Failed to check external channel fundings: {e}");
+                }
+
+                if let Err(e) = nm.check_inbound_capacity().await {
+                    log_warn!(nm.logger, "Failed to check inbound capacity: {e}");
+                }
+
+                if let Err(e) = nm.check_swap_out().await {
+                    log_warn!(nm.logger, "Failed to check swap out policy: {e}");
+                }
+
+                if let Err(e) = nm.check_alerts().await {
+                    log_warn!(nm.logger, "Failed to check alerts: {e}");
+                }
+
+                if let Err(e) = nm.check_receipts().await {
+                    log_warn!(nm.logger, "Failed to generate receipts: {e}");
+                }
+
+                if let Err(e) = nm.check_payment_retries().await {
+                    log_warn!(nm.logger, "Failed to check payment retries: {e}");
+                }
+
+                if let Err(e) = nm.check_pending_receives() {
+                    log_warn!(nm.logger, "Failed to check pending receives: {e}");
+                }
+
+                if let Err(e) = nm.sweep_pending_outputs().await {
+                    log_warn!(nm.logger, "Failed to sweep closed channel outputs: {e}");
+                }
+
+                if let Err(e) = nm.prune_if_necessary().await {
+                    log_warn!(nm.logger, "Failed to prune old data: {e}");
+                }
+
+                if let Err(e) = nm.create_restore_point_if_necessary() {
+                    log_warn!(nm.logger, "Failed to create restore point: {e}");
                 }
 
                 // sleep for 1 minute, checking graceful shutdown check each 1s.
@@ -988,7 +2205,9 @@ impl<S: MutinyStorage> NodeManager<S> {
         amount: Option<u64>,
         labels: Vec<String>,
     ) -> Result<MutinyBip21RawMaterials, MutinyError> {
-        let invoice = self.create_invoice(amount, labels.clone()).await?;
+        let invoice = self
+            .create_invoice(amount, labels.clone(), None, None)
+            .await?;
 
         let Ok(address) = self.get_new_address(labels.clone()) else {
             return Err(MutinyError::WalletOperationFailed);
@@ -1010,18 +2229,113 @@ impl<S: MutinyStorage> NodeManager<S> {
     /// The amount is in satoshis and the fee rate is in sat/vbyte.
     ///
     /// If a fee rate is not provided, one will be used from the fee estimator.
+    ///
+    /// If an idempotency key is given and was already used, the original
+    /// transaction id is returned instead of sending again.
     pub async fn send_to_address(
         &self,
         send_to: Address,
         amount: u64,
         labels: Vec<String>,
         fee_rate: Option<f32>,
+        idempotency_key: Option<String>,
     ) -> Result<Txid, MutinyError> {
         if !send_to.is_valid_for_network(self.network) {
             return Err(MutinyError::IncorrectNetwork(send_to.network));
         }
 
-        self.wallet.send(send_to, amount, labels, fee_rate).await
+        let _queue = self.queue_command().await;
+
+        let operation = IdempotentOperation::SendToAddress;
+        let Some(key) = idempotency_key.as_deref() else {
+            return self.wallet.send(send_to, amount, labels, fee_rate).await;
+        };
+
+        match self
+            .storage
+            .claim_idempotency_key(key, operation, IdempotentResult::Pending)?
+        {
+            Some(IdempotentResult::Transaction(txid)) => return Ok(txid),
+            // the first call was interrupted while broadcasting,
+            // only send again if the transaction never made it out
+            Some(IdempotentResult::Broadcasting(txid)) => {
+                if self.is_transaction_known(txid).await? {
+                    self.storage.set_idempotency_result(
+                        key,
+                        operation,
+                        IdempotentResult::Transaction(txid),
+                    )?;
+                    return Ok(txid);
+                }
+                self.storage
+                    .set_idempotency_result(key, operation, IdempotentResult::Pending)?;
+            }
+            _ => {}
+        }
+
+        let tx = match self
+            .wallet
+            .create_send_transaction(send_to, amount, labels, fee_rate)
+        {
+            Ok(tx) => tx,
+            Err(e) => {
+                self.release_idempotency_key(key);
+                return Err(e);
+            }
+        };
+        let txid = tx.txid();
+
+        // save the txid before broadcasting so a retry after an interruption
+        // can look for it instead of sending a second transaction
+        self.storage.set_idempotency_result(
+            key,
+            operation,
+            IdempotentResult::Broadcasting(txid),
+        )?;
+
+        match self.wallet.broadcast_transaction(tx).await {
+            Ok(()) => {
+                log_debug!(self.logger, "Transaction broadcast! TXID: {txid}");
+                self.storage.set_idempotency_result(
+                    key,
+                    operation,
+                    IdempotentResult::Transaction(txid),
+                )?;
+                Ok(txid)
+            }
+            Err(e) => {
+                self.release_idempotency_key(key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks if a transaction is in our wallet or was seen by esplora
+    async fn is_transaction_known(&self, txid: Txid) -> Result<bool, MutinyError> {
+        if self.wallet.get_transaction(txid, false)?.is_some() {
+            return Ok(true);
+        }
+        Ok(self.esplora.get_tx(&txid).await?.is_some())
+    }
+
+    /// Frees up an idempotency key after a failed call so it can be retried
+    fn release_idempotency_key(&self, key: &str) {
+        if let Err(e) = self.storage.delete_idempotency_record(key) {
+            log_warn!(self.logger, "Failed to release idempotency key: {e}");
+        }
+    }
+
+    /// Looks up the current state of a payment made with an idempotency key.
+    /// Returns `None` if the payment was never saved, so it is safe to retry.
+    async fn get_idempotent_payment(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<MutinyInvoice>, MutinyError> {
+        match self.get_invoice_by_hash(payment_hash).await {
+            Ok(invoice) => Ok(Some(invoice)),
+            Err(MutinyError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
     }
 
     /// Sweeps all the funds from the wallet to the given address.
@@ -1038,6 +2352,7 @@ impl<S: MutinyStorage> NodeManager<S> {
             return Err(MutinyError::IncorrectNetwork(send_to.network));
         }
 
+        let _queue = self.queue_command().await;
         self.wallet.sweep(send_to, labels, fee_rate).await
     }
 
@@ -1173,6 +2488,29 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(details_opt.map(|(d, _)| d))
     }
 
+    /// Returns the activity with display names and icons for who each entry was with.
+    /// Only cached lookups are used, missing ones are looked up in the background
+    /// and show up the next time this is called.
+    pub async fn get_enriched_activity(&self) -> Result<Vec<EnrichedActivityItem>, MutinyError> {
+        let activity = self.get_activity().await?;
+        let contacts = self.storage.get_contacts()?;
+        let now = utils::now().as_secs();
+        let mut stale = HashSet::new();
+        let enriched = activity
+            .into_iter()
+            .map(|item| self.enricher.enrich(item, &contacts, now, &mut stale))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.enricher.refresh_in_background(stale);
+
+        Ok(enriched)
+    }
+
+    /// Adds a resolver for enriching activity, replacing any with the same name.
+    /// The built in resolvers look up nostr profiles, LNURL metadata and node aliases.
+    pub fn register_enrichment_resolver(&self, resolver: Arc<dyn EnrichmentResolver>) {
+        self.enricher.register(resolver);
+    }
+
     /// Returns all the on-chain and lightning activity from the wallet.
     pub async fn get_activity(&self) -> Result<Vec<ActivityItem>, MutinyError> {
         // todo add contacts to the activity
@@ -1201,13 +2539,135 @@ impl<S: MutinyStorage> NodeManager<S> {
         for chan in closures {
             activity.push(ActivityItem::ChannelClosed(chan));
         }
+        let external = self
+            .storage
+            .list_external_payments()
+            .map_err(|e| {
+                log_warn!(self.logger, "Failed to get imported payments: {e}");
+                e
+            })
+            .unwrap_or_default();
+        for payment in external {
+            activity.push(ActivityItem::External(payment));
+        }
+
+        // Newest first
+        activity.sort_by(|a, b| b.cmp(a));
+
+        Ok(activity)
+    }
+
+    /// Returns a page of activity, newest first, with up to `limit` items
+    /// last updated before `before`. Pass the `last_updated` of the final item
+    /// of a page to get the next one. Pending on-chain transactions are only on
+    /// the first page.
+    ///
+    /// Lightning payments and channel closures are read from the activity
+    /// index, so only the ones on the page are loaded. With a `label`, only
+    /// activity for that label or contact is returned.
+    pub async fn list_activity(
+        &self,
+        before: Option<u64>,
+        limit: usize,
+        label: Option<String>,
+    ) -> Result<Vec<ActivityItem>, MutinyError> {
+        let is_before = |time: u64| before.map_or(true, |b| time < b);
+        let mut activity = Vec::with_capacity(limit);
+
+        match label.as_ref() {
+            Some(label) => {
+                let invoices = self
+                    .storage
+                    .get_label(label)?
+                    .map(|l| l.invoices)
+                    .unwrap_or_default();
+                for invoice in invoices {
+                    match self.get_invoice_by_hash(invoice.payment_hash()).await {
+                        Ok(inv) if inv.paid && is_before(inv.last_updated) => {
+                            activity.push(ActivityItem::Lightning(Box::new(inv)));
+                        }
+                        Ok(_) | Err(MutinyError::NotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            None => {
+                for entry in self.storage.activity_page(before, limit)? {
+                    let item = match entry.activity {
+                        ActivityRef::Lightning { payment_hash, .. } => self
+                            .get_invoice_by_hash(&payment_hash)
+                            .await
+                            .map(|inv| ActivityItem::Lightning(Box::new(inv))),
+                        ActivityRef::ChannelClosed(id) => self
+                            .get_channel_closure(id)
+                            .await
+                            .map(ActivityItem::ChannelClosed),
+                    };
+                    match item {
+                        Ok(item) => activity.push(item),
+                        // the payment may have been pruned since it was indexed
+                        Err(MutinyError::NotFound) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                let external = self
+                    .storage
+                    .list_external_payments()
+                    .map_err(|e| {
+                        log_warn!(self.logger, "Failed to get imported payments: {e}");
+                        e
+                    })
+                    .unwrap_or_default();
+                activity.extend(
+                    external
+                        .into_iter()
+                        .filter(|p| is_before(p.timestamp))
+                        .map(ActivityItem::External),
+                );
+            }
+        }
+
+        let onchain = self
+            .list_onchain()
+            .map_err(|e| {
+                log_warn!(self.logger, "Failed to get bdk history: {e}");
+                e
+            })
+            .unwrap_or_default();
+        activity.extend(
+            onchain
+                .into_iter()
+                .filter(|tx| label.as_ref().map_or(true, |l| tx.labels.contains(l)))
+                .map(ActivityItem::OnChain)
+                .filter(|item| match item.last_updated() {
+                    Some(time) => is_before(time),
+                    None => before.is_none(),
+                }),
+        );
 
         // Newest first
         activity.sort_by(|a, b| b.cmp(a));
+        activity.truncate(limit);
 
         Ok(activity)
     }
 
+    /// Imports the payment history exported from another wallet so it shows up
+    /// in our activity. Payments that were already imported are skipped.
+    ///
+    /// Returns the number of newly imported payments.
+    pub fn import_payment_history(
+        &self,
+        source: ImportSource,
+        data: &str,
+    ) -> Result<usize, MutinyError> {
+        let payments = parse_history(source, data)?;
+        let imported = self.storage.import_external_payments(payments)?;
+        log_info!(self.logger, "Imported {imported} payments from {source}");
+        Ok(imported)
+    }
+
     /// Adds labels to the TransactionDetails based on the address labels.
     /// This will panic if the TransactionDetails does not have a transaction.
     /// Make sure you flag `include_raw` when calling `list_transactions` to
@@ -1349,19 +2809,156 @@ impl<S: MutinyStorage> NodeManager<S> {
         // to addresses that are in our bdk wallet. This way
         // they are found on this iteration of syncing instead
         // of the next one.
+        let start = utils::now();
         if let Err(e) = self.sync_ldk().await {
             log_error!(self.logger, "Failed to sync ldk: {e}");
             return Err(e);
         }
+        let ldk_done = utils::now();
 
         // sync bdk wallet
-        match self.wallet.sync().await {
-            Ok(()) => Ok(log_info!(self.logger, "We are synced!")),
-            Err(e) => {
-                log_error!(self.logger, "Failed to sync on-chain wallet: {e}");
-                Err(e)
+        if let Err(e) = self.wallet.sync().await {
+            log_error!(self.logger, "Failed to sync on-chain wallet: {e}");
+            return Err(e);
+        }
+        let onchain_done = utils::now();
+
+        let metrics = SyncMetrics {
+            timestamp: onchain_done.as_secs(),
+            ldk_sync_ms: (ldk_done - start).as_millis() as u64,
+            onchain_sync_ms: (onchain_done - ldk_done).as_millis() as u64,
+            parallel_requests: self.esplora.parallel_requests(),
+        };
+        log_info!(
+            self.logger,
+            "We are synced! ldk took {}ms, on-chain took {}ms",
+            metrics.ldk_sync_ms,
+            metrics.onchain_sync_ms
+        );
+        if let Ok(mut last) = self.last_sync_metrics.write() {
+            *last = Some(metrics);
+        }
+
+        if let Some(summary) = self.get_state_summary() {
+            if let Ok(mut state) = self.state_summary.write() {
+                *state = Some(summary);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns how long the most recent successful sync took,
+    /// if there has been one since the wallet started.
+    /// Checks the configured backend services if they haven't been checked recently
+    async fn check_service_status(&self) {
+        let now = utils::now().as_secs();
+        self.status_monitor
+            .check_if_necessary(self.http_client.as_ref(), now)
+            .await;
+
+        let report = self.status_monitor.report();
+        if !report.suppressed_features.is_empty() {
+            log_warn!(
+                self.logger,
+                "Services are down, turning off {:?}",
+                report.suppressed_features
+            );
+        }
+    }
+
+    /// Returns the status of the backend services the wallet uses,
+    /// and the features that are turned off because their service is down.
+    pub fn get_service_status(&self) -> StatusReport {
+        self.status_monitor.report()
+    }
+
+    /// Returns the endpoint picked for each service, and whether it is a canary
+    pub fn get_endpoint_selections(&self) -> Vec<EndpointSelection> {
+        self.endpoint_selections.clone()
+    }
+
+    /// Returns false if the feature is turned off because its service is down
+    pub fn is_feature_available(&self, feature: Feature) -> bool {
+        !self.status_monitor.is_suppressed(feature)
+    }
+
+    pub fn get_sync_metrics(&self) -> Option<SyncMetrics> {
+        self.last_sync_metrics
+            .read()
+            .ok()
+            .and_then(|metrics| metrics.clone())
+    }
+
+    /// Returns every recorded change to the wallet's balances, oldest first,
+    /// each tagged with the reason it happened.
+    pub fn get_balance_changes(&self) -> Result<Vec<BalanceChange>, MutinyError> {
+        self.storage.get_balance_changes()
+    }
+
+    /// Remembers the height the wallet was created at after its first sync,
+    /// so restoring the seed later can skip the full scan if it has no history yet.
+    async fn record_wallet_birthday_if_necessary(&self) -> Result<(), MutinyError> {
+        if self.storage.get_wallet_birthday()?.is_some() {
+            return Ok(());
+        }
+
+        let height = self.wallet.birthday_height().await?;
+        log_info!(self.logger, "Recording wallet birthday at block {height}");
+        self.storage.set_wallet_birthday(height)
+    }
+
+    /// Gets the height the wallet was created at, if it is known.
+    /// A restore only uses it to skip the scan when the wallet has no history,
+    /// esplora can't limit the scan of an older wallet to its birthday.
+    pub fn get_wallet_birthday(&self) -> Result<Option<u32>, MutinyError> {
+        self.storage.get_wallet_birthday()
+    }
+
+    /// Records the balance changes for any new on-chain wallet transactions.
+    async fn record_onchain_balance_changes(&self) -> Result<(), MutinyError> {
+        let txs = self.wallet.list_transactions(true)?;
+        let Some(mut processed) = self.storage.get_balance_change_txids()? else {
+            // first time running, don't record the existing history
+            let txids = txs.into_iter().map(|t| t.txid).collect();
+            return self.storage.set_balance_change_txids(txids);
+        };
+
+        let new_txs: Vec<TransactionDetails> = txs
+            .into_iter()
+            .filter(|t| !processed.contains(&t.txid))
+            .collect();
+        if new_txs.is_empty() {
+            return Ok(());
+        }
+
+        let mut funding_outpoints = HashSet::new();
+        let mut channel_txids = HashSet::new();
+        {
+            let nodes = self.nodes.lock().await;
+            for node in nodes.values() {
+                for funding_txo in node.chain_monitor.list_monitors() {
+                    funding_outpoints.insert(funding_txo.into_bitcoin_outpoint());
+                    channel_txids.insert(funding_txo.txid);
+                    if let Ok(monitor) = node.chain_monitor.get_monitor(funding_txo) {
+                        channel_txids.extend(monitor.get_relevant_txids().into_iter().map(|t| t.0));
+                    }
+                }
             }
         }
+
+        let mut changes = vec![];
+        for tx in new_txs {
+            changes.extend(onchain_balance_changes(
+                &tx,
+                &funding_outpoints,
+                &channel_txids,
+            ));
+            processed.insert(tx.txid);
+        }
+
+        self.storage.record_balance_changes(changes)?;
+        self.storage.set_balance_change_txids(processed)
     }
 
     /// Gets a fee estimate for an average priority transaction.
@@ -1380,6 +2977,7 @@ impl<S: MutinyStorage> NodeManager<S> {
 
     /// Creates a new lightning node and adds it to the manager.
     pub async fn new_node(&self) -> Result<NodeIdentity, MutinyError> {
+        let _queue = self.queue_command().await;
         create_new_node_from_node_manager(self).await
     }
 
@@ -1424,22 +3022,328 @@ impl<S: MutinyStorage> NodeManager<S> {
         }
     }
 
-    /// Lists the pubkeys of the lightning node in the manager.
-    pub async fn list_nodes(&self) -> Result<Vec<PublicKey>, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        let peers = nodes.iter().map(|(_, n)| n.pubkey).collect();
-        Ok(peers)
+    /// Returns the connection string other nodes can use to reach the given node.
+    pub async fn get_connection_info(
+        &self,
+        from_node: &PublicKey,
+    ) -> Result<ConnectionInfo, MutinyError> {
+        let node = self.get_node(from_node).await?;
+
+        #[cfg(target_arch = "wasm32")]
+        let proxy_url = Some(self.websocket_proxy_addr.as_str());
+        #[cfg(not(target_arch = "wasm32"))]
+        let proxy_url = None;
+
+        Ok(ConnectionInfo::new(node.pubkey, proxy_url))
     }
 
-    /// Attempts to connect to a peer from the selected node.
-    pub async fn connect_to_peer(
+    /// Sets which channels other nodes can open to us.
+    pub fn set_inbound_policy(&self, policy: InboundPolicy) -> Result<(), MutinyError> {
+        if policy.min_channel_size_sats == Some(0) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        self.storage.set_inbound_policy(policy)
+    }
+
+    /// Gets which channels other nodes can open to us.
+    pub fn get_inbound_policy(&self) -> Result<InboundPolicy, MutinyError> {
+        self.storage.get_inbound_policy()
+    }
+
+    /// Sets the limits on payments to us. Payments over any limit are failed back
+    /// to the sender as soon as they arrive. With `reject_dust_htlcs` set, payments
+    /// under 354 sats can't be received at all.
+    ///
+    /// The HTLC limit is also put in the handshake of channels we open from now on,
+    /// and of channels opened to us after a restart. Existing channels only get
+    /// the checks done when a payment arrives.
+    pub fn set_inbound_htlc_limits(&self, limits: InboundHtlcLimits) -> Result<(), MutinyError> {
+        self.storage.set_inbound_htlc_limits(limits)
+    }
+
+    /// Gets the limits on payments to us.
+    pub fn get_inbound_htlc_limits(&self) -> Result<InboundHtlcLimits, MutinyError> {
+        self.storage.get_inbound_htlc_limits()
+    }
+
+    /// Gets how many payments to us were accepted, and how many were rejected by each limit.
+    pub fn get_inbound_htlc_counters(&self) -> Result<InboundHtlcCounters, MutinyError> {
+        self.storage.get_inbound_htlc_counters()
+    }
+
+    /// Sets the inbound capacity to keep. When what we can receive over lightning
+    /// drops below it, a warning is emitted. Setting `None` disables the warnings.
+    pub fn set_inbound_capacity_threshold(
         &self,
-        self_node_pubkey: &PublicKey,
-        connection_string: &str,
-        label: Option<String>,
+        threshold_sats: Option<u64>,
     ) -> Result<(), MutinyError> {
-        if let Some(node) = self.nodes.lock().await.get(self_node_pubkey) {
-            let connect_info = PubkeyConnectionInfo::new(connection_string)?;
+        let alerts = InboundCapacityAlerts::new(threshold_sats);
+        self.storage.set_inbound_capacity_alerts(alerts)
+    }
+
+    /// Reports how much we can receive over lightning and, when that is below the
+    /// inbound capacity threshold, the ways to get more priced with live fee quotes.
+    pub async fn inbound_capacity_report(&self) -> Result<InboundCapacityReport, MutinyError> {
+        let threshold_sats = self.storage.get_inbound_capacity_alerts()?.threshold_sats;
+
+        let (channels, lsp) = {
+            let nodes = self.nodes.lock().await;
+            let channels: Vec<_> = nodes
+                .values()
+                .flat_map(|n| n.channel_manager.list_usable_channels())
+                .collect();
+            let lsp = nodes
+                .values()
+                .find_map(|n| n.lsp_client.clone().map(|l| (n.pubkey, l)));
+            (channels, lsp)
+        };
+        let inbound_sats = channels
+            .iter()
+            .map(|c| c.inbound_capacity_msat)
+            .sum::<u64>()
+            / 1_000;
+        let outbound_sats = channels
+            .iter()
+            .map(|c| c.outbound_capacity_msat)
+            .sum::<u64>()
+            / 1_000;
+
+        let mut report = InboundCapacityReport::new(inbound_sats, outbound_sats, threshold_sats);
+        if report.shortfall_sats == 0 {
+            return Ok(report);
+        }
+
+        if let Some((pubkey, lsp)) = lsp {
+            let fee_request = FeeRequest {
+                pubkey: pubkey.to_hex(),
+                amount_msat: report.shortfall_sats * 1_000,
+            };
+            match lsp.get_lsp_fee_msat(fee_request).await {
+                Ok(fee_msat) => report.suggestions.push(InboundSuggestion::LspChannel {
+                    amount_sats: report.shortfall_sats,
+                    fee_sats: fee_msat / 1_000,
+                }),
+                Err(e) => log_warn!(self.logger, "Could not get LSP fee quote: {e}"),
+            }
+        }
+
+        let swap_out_sats = report.swap_out_amount();
+        if swap_out_sats > 0 {
+            // the swap pays us on-chain, so we pay for that transaction
+            let fee_sats =
+                self.fee_estimator
+                    .calculate_expected_fee(1, TAPROOT_OUTPUT_SIZE, None, None);
+            report.suggestions.push(InboundSuggestion::SwapOut {
+                amount_sats: swap_out_sats,
+                fee_sats,
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Returns the low inbound capacity warnings emitted since they were last cleared.
+    pub fn get_inbound_capacity_warnings(
+        &self,
+    ) -> Result<Vec<InboundCapacityWarning>, MutinyError> {
+        self.storage.get_inbound_capacity_warnings()
+    }
+
+    pub fn clear_inbound_capacity_warnings(&self) -> Result<(), MutinyError> {
+        self.storage.clear_inbound_capacity_warnings()
+    }
+
+    /// Emits a warning if inbound capacity has dropped below the threshold.
+    async fn check_inbound_capacity(&self) -> Result<(), MutinyError> {
+        let mut alerts = self.storage.get_inbound_capacity_alerts()?;
+        if alerts.threshold_sats.is_none() {
+            return Ok(());
+        }
+
+        let report = self.inbound_capacity_report().await?;
+        let previous = alerts.clone();
+        if let Some(warning) = alerts.next_warning(&report, utils::now().as_secs()) {
+            log_warn!(
+                self.logger,
+                "Inbound capacity is down to {} sats, below the {} sat threshold",
+                warning.inbound_sats,
+                warning.threshold_sats
+            );
+            self.storage.push_inbound_capacity_warning(warning)?;
+        }
+        if alerts != previous {
+            self.storage.set_inbound_capacity_alerts(alerts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the peer allowlist. When enabled, our nodes only connect to and accept
+    /// connections from their LSP and the listed peers.
+    /// Any connected peers that are no longer allowed are disconnected.
+    pub async fn set_peer_allowlist(&self, allowlist: PeerAllowlist) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
+        self.storage.set_peer_allowlist(allowlist.clone())?;
+
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            let lsp_pubkey = node.lsp_client.as_ref().map(|l| l.pubkey);
+            for peer in node.peer_manager.get_peer_node_ids() {
+                if !allowlist.is_allowed(&peer, lsp_pubkey.as_ref()) {
+                    log_info!(self.logger, "disconnecting peer not in allowlist: {peer}");
+                    node.disconnect_peer(peer);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists every device that has run this wallet.
+    pub fn list_devices(&self) -> Result<Vec<DeviceInfo>, MutinyError> {
+        Ok(self.storage.get_device_registry()?.devices)
+    }
+
+    /// Checks that a NIP-05 identifier points to the given npub.
+    /// Results are cached for a day so contacts aren't looked up on every check.
+    pub async fn verify_nip05(
+        &self,
+        address: &str,
+        npub: &bitcoin::XOnlyPublicKey,
+    ) -> Result<bool, MutinyError> {
+        let now = utils::now().as_secs();
+        if let Some(cached) = self.storage.get_nip05_verification(address, npub)? {
+            if !cached.is_expired(now) {
+                return Ok(cached.verified);
+            }
+        }
+
+        let verified = resolve_nip05(self.http_client.as_ref(), address).await? == Some(*npub);
+        let verification = Nip05Verification {
+            verified,
+            checked_at: now,
+        };
+        self.storage
+            .set_nip05_verification(address, npub, verification)?;
+
+        Ok(verified)
+    }
+
+    /// Creates a contact from a NIP-05 identifier, like `bob@example.com`,
+    /// using the npub the identifier points to. Returns the new contact's id.
+    pub async fn create_contact_from_nip05(
+        &self,
+        address: String,
+        name: Option<String>,
+    ) -> Result<String, MutinyError> {
+        let npub = resolve_nip05(self.http_client.as_ref(), &address)
+            .await?
+            .ok_or(MutinyError::Nip05Failure)?;
+
+        let verification = Nip05Verification {
+            verified: true,
+            checked_at: utils::now().as_secs(),
+        };
+        self.storage
+            .set_nip05_verification(&address, &npub, verification)?;
+
+        let mut nip05 = Nip05Identity::new(&address);
+        nip05.status = Nip05Status::Verified;
+        let contact = Contact {
+            name: name.unwrap_or_else(|| nip05.address.clone()),
+            npub: Some(npub),
+            archived: Some(false),
+            nip05: Some(nip05),
+            last_used: utils::now().as_secs(),
+            ..Default::default()
+        };
+
+        self.storage.create_new_contact(contact)
+    }
+
+    /// Checks a contact's NIP-05 identifier still points to their npub and saves the result.
+    /// Returns `None` if the contact doesn't have an identifier to check.
+    pub async fn verify_contact_nip05(&self, id: &str) -> Result<Option<Nip05Status>, MutinyError> {
+        let mut contact = self.storage.get_contact(id)?.ok_or(MutinyError::NotFound)?;
+        let (Some(npub), Some(mut nip05)) = (contact.npub, contact.nip05.clone()) else {
+            return Ok(None);
+        };
+
+        let verified = self.verify_nip05(&nip05.address, &npub).await?;
+        let status = Nip05Status::next(Some(nip05.status), verified);
+        if status != nip05.status {
+            if status == Nip05Status::Broken {
+                log_warn!(
+                    self.logger,
+                    "NIP-05 identifier {} no longer points to contact {id}",
+                    nip05.address
+                );
+            }
+            nip05.status = status;
+            contact.nip05 = Some(nip05);
+            self.storage.edit_contact(id, contact)?;
+        }
+
+        Ok(Some(status))
+    }
+
+    /// Checks the NIP-05 identifiers of all contacts, returning the ids of
+    /// the contacts whose verification is broken.
+    pub async fn verify_contacts_nip05(&self) -> Result<Vec<String>, MutinyError> {
+        let mut broken = vec![];
+        for (id, contact) in self.storage.get_contacts()? {
+            if contact.nip05.is_none() {
+                continue;
+            }
+
+            match self.verify_contact_nip05(&id).await {
+                Ok(Some(Nip05Status::Broken)) => broken.push(id),
+                Ok(_) => {}
+                // don't flag contacts because we couldn't reach their domain
+                Err(e) => log_debug!(self.logger, "Could not verify contact {id}: {e}"),
+            }
+        }
+
+        Ok(broken)
+    }
+
+    /// Lists the nostr relays used for NWC, DMs and contact syncing,
+    /// along with how reliably we have been able to connect to them.
+    pub fn list_relays(&self) -> Result<Vec<RelayConfig>, MutinyError> {
+        self.storage.get_relay_pool()
+    }
+
+    /// Adds a relay to the relay pool, or changes whether it is read from and written to.
+    pub fn add_relay(&self, url: &str, read: bool, write: bool) -> Result<(), MutinyError> {
+        self.storage.add_relay(url, read, write)
+    }
+
+    /// Removes a relay from the relay pool.
+    pub fn remove_relay(&self, url: &str) -> Result<(), MutinyError> {
+        self.storage.remove_relay(url)
+    }
+
+    /// Gets the peer allowlist.
+    pub fn get_peer_allowlist(&self) -> Result<PeerAllowlist, MutinyError> {
+        self.storage.get_peer_allowlist()
+    }
+
+    /// Lists the pubkeys of the lightning node in the manager.
+    pub async fn list_nodes(&self) -> Result<Vec<PublicKey>, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let peers = nodes.iter().map(|(_, n)| n.pubkey).collect();
+        Ok(peers)
+    }
+
+    /// Attempts to connect to a peer from the selected node.
+    pub async fn connect_to_peer(
+        &self,
+        self_node_pubkey: &PublicKey,
+        connection_string: &str,
+        label: Option<String>,
+    ) -> Result<(), MutinyError> {
+        if let Some(node) = self.nodes.lock().await.get(self_node_pubkey) {
+            let connect_info = PubkeyConnectionInfo::new(connection_string)?;
             let label_opt = label.filter(|s| !s.is_empty()); // filter out empty strings
             let res = node.connect_peer(connect_info, label_opt).await;
             match res {
@@ -1508,18 +3412,92 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(())
     }
 
+    /// Sets the label, payer note, comment and tags of a lightning payment,
+    /// replacing what it had. Empty metadata clears them.
+    pub async fn label_payment(
+        &self,
+        payment_hash: &sha256::Hash,
+        metadata: PaymentMetadata,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let _queue = self.queue_command().await;
+        // only label payments we know about
+        self.get_invoice_by_hash(payment_hash).await?;
+        self.storage.set_payment_metadata(payment_hash, metadata)?;
+        self.get_invoice_by_hash(payment_hash).await
+    }
+
     // all values in sats
 
     /// Creates a lightning invoice. The amount should be in satoshis.
-    /// If no amount is provided, the invoice will be created with no amount.
+    /// If no amount is provided, the invoice will be created with no amount
+    /// and the payer picks how much to send. With an LSP this needs an existing
+    /// channel with it that we can receive over.
     /// If no description is provided, the invoice will be created with no description.
     ///
+    /// An order id can be given to link the invoice to an external reference,
+    /// it is saved with the payment and returned when the invoice is looked up.
+    ///
+    /// The invoice expires after `expiry_secs`, an hour if not given. Unpaid
+    /// invoices are marked as failed once they expire.
+    ///
     /// If the manager has more than one node it will create a phantom invoice.
     /// If there is only one node it will create an invoice just for that node.
     pub async fn create_invoice(
         &self,
         amount: Option<u64>,
         labels: Vec<String>,
+        order_id: Option<String>,
+        expiry_secs: Option<u32>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        self.create_invoice_with_description_hash(amount, labels, order_id, None, expiry_secs)
+            .await
+    }
+
+    /// Creates a phantom invoice that any of our nodes with a channel can receive,
+    /// so the payment still arrives when one of them is out of inbound capacity.
+    /// The amount should be in satoshis, the LSP isn't used.
+    /// An order id can be given to link the invoice to an external reference.
+    ///
+    /// The invoice expires after `expiry_secs`, an hour if not given.
+    pub async fn create_phantom_invoice(
+        &self,
+        amount: Option<u64>,
+        labels: Vec<String>,
+        order_id: Option<String>,
+        expiry_secs: Option<u32>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let route_hints: Vec<PhantomRouteHints> = nodes
+            .values()
+            .map(|n| n.get_phantom_route_hint())
+            .filter(|hints| !hints.channels.is_empty())
+            .collect();
+        if route_hints.is_empty() {
+            return Err(MutinyError::InvoiceCreationFailed);
+        }
+
+        let Some(first_node) = nodes.values().next() else {
+            return Err(MutinyError::InvoiceCreationFailed);
+        };
+        let invoice = first_node
+            .create_phantom_invoice(amount, labels, order_id.clone(), route_hints, expiry_secs)
+            .await?;
+
+        Ok(MutinyInvoice {
+            order_id,
+            ..invoice.into()
+        })
+    }
+
+    /// Creates a lightning invoice that commits to a description hash instead of a description,
+    /// this is what LNURL-pay servers need to hand out invoices for a lightning address.
+    pub(crate) async fn create_invoice_with_description_hash(
+        &self,
+        amount: Option<u64>,
+        labels: Vec<String>,
+        order_id: Option<String>,
+        description_hash: Option<sha256::Hash>,
+        expiry_secs: Option<u32>,
     ) -> Result<MutinyInvoice, MutinyError> {
         let nodes = self.nodes.lock().await;
         let use_phantom = nodes.len() > 1 && self.lsp_clients.is_empty();
@@ -1544,70 +3522,618 @@ impl<S: MutinyStorage> NodeManager<S> {
             return Err(MutinyError::WalletOperationFailed);
         };
         let invoice = first_node
-            .create_invoice(amount, labels, route_hints)
+            .create_invoice(
+                amount,
+                labels,
+                order_id.clone(),
+                route_hints,
+                description_hash,
+                expiry_secs,
+            )
             .await?;
 
-        Ok(invoice.into())
+        Ok(MutinyInvoice {
+            order_id,
+            ..invoice.into()
+        })
+    }
+
+    /// Creates a hold invoice for a payment hash we don't know the preimage for.
+    /// The payment is held until it is settled with the preimage or cancelled.
+    pub async fn create_hold_invoice(
+        &self,
+        amount: Option<u64>,
+        payment_hash: sha256::Hash,
+        labels: Vec<String>,
+    ) -> Result<HoldInvoice, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let node = nodes
+            .values()
+            .next()
+            .ok_or(MutinyError::WalletOperationFailed)?;
+        node.create_hold_invoice(amount, payment_hash, labels).await
+    }
+
+    /// Settles a hold invoice whose payment has arrived, claiming the payment
+    pub async fn settle_hold_invoice(
+        &self,
+        preimage: [u8; 32],
+    ) -> Result<HoldInvoice, MutinyError> {
+        let _queue = self.queue_command().await;
+        let payment_hash = sha256::Hash::hash(&preimage);
+        let node = self.get_hold_invoice_node(&payment_hash).await?;
+        node.settle_hold_invoice(preimage)
+    }
+
+    /// Cancels a hold invoice, failing its payment back to the payer
+    pub async fn cancel_hold_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<HoldInvoice, MutinyError> {
+        let _queue = self.queue_command().await;
+        let node = self.get_hold_invoice_node(&payment_hash).await?;
+        node.cancel_hold_invoice(payment_hash)
+    }
+
+    /// Lists all the hold invoices, newest first
+    pub fn list_hold_invoices(&self) -> Result<Vec<HoldInvoice>, MutinyError> {
+        self.storage.list_hold_invoices()
+    }
+
+    async fn get_hold_invoice_node(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Arc<Node<S>>, MutinyError> {
+        let hold_invoice = self
+            .storage
+            .get_hold_invoice(payment_hash)?
+            .ok_or(MutinyError::NotFound)?;
+        self.get_node(&hold_invoice.node_pubkey).await
     }
 
     /// Pays a lightning invoice from the selected node.
     /// An amount should only be provided if the invoice does not have an amount.
     /// The amount should be in satoshis.
+    ///
+    /// If an idempotency key is given and was already used, the current state
+    /// of the original payment is returned instead of paying again.
+    ///
+    /// The retry policy limits how many times and for how long the payment is retried,
+    /// and how much it can pay in fees. LDK's defaults are used if none is given.
+    ///
+    /// Metadata, such as a label or tags, is saved with the payment before it is sent.
+    /// It can be changed afterwards with [NodeManager::label_payment].
+    #[allow(clippy::too_many_arguments)]
     pub async fn pay_invoice(
         &self,
         from_node: &PublicKey,
         invoice: &Bolt11Invoice,
         amt_sats: Option<u64>,
         labels: Vec<String>,
+        idempotency_key: Option<String>,
+        retry_policy: Option<RetryPolicy>,
+        metadata: Option<PaymentMetadata>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let queue = self.queue_command().await;
+        let node = self.get_node(from_node).await?;
+        self.pay_invoice_from(
+            queue,
+            &node,
+            invoice,
+            amt_sats,
+            labels,
+            idempotency_key.as_deref(),
+            retry_policy,
+            metadata,
+        )
+        .await
+    }
+
+    /// Pays a lightning invoice from the given node, see [NodeManager::pay_invoice].
+    ///
+    /// The command queue is held until the payment is sent, it is released
+    /// while waiting for the result so a slow payment doesn't hold up others.
+    #[allow(clippy::too_many_arguments)]
+    async fn pay_invoice_from(
+        &self,
+        queue: CommandGuard<'_>,
+        node: &Node<S>,
+        invoice: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        labels: Vec<String>,
+        idempotency_key: Option<&str>,
+        retry_policy: Option<RetryPolicy>,
+        metadata: Option<PaymentMetadata>,
     ) -> Result<MutinyInvoice, MutinyError> {
         if invoice.network() != self.network {
             return Err(MutinyError::IncorrectNetwork(invoice.network()));
         }
 
-        let node = self.get_node(from_node).await?;
-        node.pay_invoice_with_timeout(invoice, amt_sats, None, labels)
+        if let Some(key) = idempotency_key {
+            let initial = IdempotentResult::Payment(*invoice.payment_hash());
+            if let Some(IdempotentResult::Payment(hash)) =
+                self.storage
+                    .claim_idempotency_key(key, IdempotentOperation::PayInvoice, initial)?
+            {
+                if let Some(payment) = self.get_idempotent_payment(&hash).await? {
+                    return Ok(payment);
+                }
+            }
+        }
+
+        let start = utils::now();
+        let res = match self
+            .send_invoice_payment(
+                node,
+                invoice,
+                amt_sats,
+                labels.clone(),
+                retry_policy,
+                metadata,
+            )
+            .await
+        {
+            Ok((payment_hash, timeout_secs)) => {
+                drop(queue);
+                node.await_invoice_payment(payment_hash, timeout_secs, labels)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let _ = self
+            .storage
+            .record_payment_telemetry(res.is_ok(), utils::now().saturating_sub(start));
+
+        // a timed out payment can still complete, so keep the key for it
+        if let (Some(key), Err(e)) = (idempotency_key, res.as_ref()) {
+            if !matches!(e, MutinyError::PaymentTimeout) {
+                self.release_idempotency_key(key);
+            }
+        }
+        res
+    }
+
+    /// Saves the payment's metadata and sends it off, returning its payment
+    /// hash and how long to wait for it.
+    async fn send_invoice_payment(
+        &self,
+        node: &Node<S>,
+        invoice: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        labels: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        metadata: Option<PaymentMetadata>,
+    ) -> Result<(PaymentHash, Option<u64>), MutinyError> {
+        if let Some(owner) = self.get_invoice_owner(invoice).await {
+            if owner == node.pubkey {
+                return Err(MutinyError::SelfPayment);
+            }
+            log_info!(
+                self.logger,
+                "Paying invoice from our node {} as a transfer from {}",
+                owner.to_hex(),
+                node.pubkey.to_hex()
+            );
+        }
+
+        let retry_policy = retry_policy.unwrap_or_default();
+        let timeout_secs = retry_policy.timeout_secs;
+        let payment_hash = node
+            .init_invoice_payment(invoice, amt_sats, labels, retry_policy)
+            .await?;
+
+        // only saved once the payment was sent, so a payment that was never
+        // made or one we already made doesn't get these details
+        if let Some(metadata) = metadata {
+            if let Err(e) = self
+                .storage
+                .set_payment_metadata(invoice.payment_hash(), metadata)
+            {
+                log_error!(self.logger, "Failed to save payment metadata: {e}");
+            }
+        }
+
+        Ok((payment_hash, timeout_secs))
+    }
+
+    /// Returns which of our nodes created the invoice, if it is still unpaid.
+    async fn get_invoice_owner(&self, invoice: &Bolt11Invoice) -> Option<PublicKey> {
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let nodes = self.nodes.lock().await;
+        nodes
+            .iter()
+            .find(|(_, n)| {
+                n.persister
+                    .read_payment_info(&payment_hash, true, &self.logger)
+                    .is_some_and(|p| matches!(p.status, HTLCStatus::Pending | HTLCStatus::InFlight))
+            })
+            .map(|(pk, _)| *pk)
+    }
+
+    /// Moves funds from one of our nodes to another over lightning, by having
+    /// the receiving node create an invoice and the sending node pay it.
+    /// The amount should be in satoshis.
+    pub async fn transfer_between_nodes(
+        &self,
+        from_node: &PublicKey,
+        to_node: &PublicKey,
+        amt_sats: u64,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        if from_node == to_node {
+            return Err(MutinyError::SelfPayment);
+        }
+
+        let queue = self.queue_command().await;
+        let from = self.get_node(from_node).await?;
+        let to = self.get_node(to_node).await?;
+
+        let invoice = to
+            .create_invoice(Some(amt_sats), labels.clone(), None, None, None, None)
+            .await?;
+        log_info!(
+            self.logger,
+            "Transferring {amt_sats} sats from {} to {}",
+            from_node.to_hex(),
+            to_node.to_hex()
+        );
+
+        let retry_policy = RetryPolicy::default();
+        let timeout_secs = retry_policy.timeout_secs;
+        let payment_hash = from
+            .init_invoice_payment(&invoice, None, labels.clone(), retry_policy)
+            .await?;
+        drop(queue);
+        from.await_invoice_payment(payment_hash, timeout_secs, labels)
             .await
     }
 
     /// Sends a spontaneous payment to a node from the selected node.
     /// The amount should be in satoshis.
+    ///
+    /// If an idempotency key is given and was already used, the current state
+    /// of the original payment is returned instead of paying again.
+    ///
+    /// Custom TLV records, such as podcast boost metadata, are sent to the recipient
+    /// in the payment onion.
     pub async fn keysend(
         &self,
         from_node: &PublicKey,
         to_node: PublicKey,
         amt_sats: u64,
         labels: Vec<String>,
+        idempotency_key: Option<String>,
+        custom_tlvs: Vec<CustomTlv>,
     ) -> Result<MutinyInvoice, MutinyError> {
+        let queue = self.queue_command().await;
         let node = self.get_node(from_node).await?;
-        log_debug!(self.logger, "Keysending to {to_node}");
-        node.keysend_with_timeout(to_node, amt_sats, labels, None)
-            .await
+        self.keysend_from(
+            queue,
+            &node,
+            to_node,
+            amt_sats,
+            labels,
+            idempotency_key.as_deref(),
+            custom_tlvs,
+        )
+        .await
     }
 
-    /// Decodes a lightning invoice into useful information.
-    /// Will return an error if the invoice is for a different network.
-    pub async fn decode_invoice(
+    /// Sends a spontaneous payment from the given node, see [NodeManager::keysend].
+    ///
+    /// The command queue is held until the payment is sent, it is released
+    /// while waiting for the result so a slow payment doesn't hold up others.
+    #[allow(clippy::too_many_arguments)]
+    async fn keysend_from(
         &self,
-        invoice: Bolt11Invoice,
+        queue: CommandGuard<'_>,
+        node: &Node<S>,
+        to_node: PublicKey,
+        amt_sats: u64,
+        labels: Vec<String>,
+        idempotency_key: Option<&str>,
+        custom_tlvs: Vec<CustomTlv>,
     ) -> Result<MutinyInvoice, MutinyError> {
-        if invoice.network() != self.network {
-            return Err(MutinyError::IncorrectNetwork(invoice.network()));
+        if to_node == node.pubkey {
+            return Err(MutinyError::SelfPayment);
         }
 
-        Ok(invoice.into())
-    }
-
-    /// Calls upon a LNURL to get the parameters for it.
-    /// This contains what kind of LNURL it is (pay, withdrawal, auth, etc).
-    // todo revamp LnUrlParams to be well designed
-    pub async fn decode_lnurl(&self, lnurl: LnUrl) -> Result<LnUrlParams, MutinyError> {
-        // handle LNURL-AUTH
-        if lnurl.is_lnurl_auth() {
-            return Ok(LnUrlParams {
-                max: 0,
-                min: 0,
-                tag: "login".to_string(),
-            });
+        let operation = IdempotentOperation::Keysend;
+        if let Some(key) = idempotency_key {
+            if let Some(IdempotentResult::Payment(hash)) =
+                self.storage
+                    .claim_idempotency_key(key, operation, IdempotentResult::Pending)?
+            {
+                if let Some(payment) = self.get_idempotent_payment(&hash).await? {
+                    return Ok(payment);
+                }
+            }
+        }
+
+        log_debug!(self.logger, "Keysending to {to_node}");
+        let start = utils::now();
+        let res = match node.init_keysend_payment(to_node, amt_sats, labels.clone(), custom_tlvs) {
+            Ok(pay) => {
+                // save the payment hash as soon as the payment is sent, a reload while
+                // it is in flight then finds this payment instead of sending another
+                if let Some(key) = idempotency_key {
+                    let result = IdempotentResult::Payment(pay.payment_hash);
+                    if let Err(e) = self.storage.set_idempotency_result(key, operation, result) {
+                        log_warn!(self.logger, "Failed to save idempotency result: {e}");
+                    }
+                }
+                drop(queue);
+                node.await_keysend(&pay, labels, None).await
+            }
+            Err(e) => Err(e),
+        };
+        let _ = self
+            .storage
+            .record_payment_telemetry(res.is_ok(), utils::now().saturating_sub(start));
+
+        // a timed out payment can still complete, so keep the key for it
+        if let (Some(key), Err(e)) = (idempotency_key, res.as_ref()) {
+            if !matches!(e, MutinyError::PaymentTimeout) {
+                self.release_idempotency_key(key);
+            }
+        }
+        res
+    }
+
+    /// Pays a batch of lightning invoices concurrently, spread over the nodes
+    /// with enough outbound liquidity for each of them.
+    ///
+    /// Invoices must have an amount. Returns a result for every invoice in
+    /// the order they were given, one invoice failing doesn't stop the others.
+    ///
+    /// Each invoice is paid the same way as [NodeManager::pay_invoice], with
+    /// the given retry policy.
+    pub async fn pay_invoices(
+        &self,
+        invoices: Vec<String>,
+        labels: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+    ) -> Result<Vec<BatchPaymentResult>, MutinyError> {
+        let nodes: Vec<Arc<Node<S>>> = self.nodes.lock().await.values().cloned().collect();
+        let capacities: Vec<u64> = nodes
+            .iter()
+            .map(|n| {
+                n.channel_manager
+                    .list_usable_channels()
+                    .iter()
+                    .map(|c| c.outbound_capacity_msat)
+                    .sum()
+            })
+            .collect();
+
+        let mut payment_hashes = HashSet::new();
+        let parsed: Vec<Result<(Bolt11Invoice, u64), MutinyError>> = invoices
+            .iter()
+            .map(|i| {
+                let invoice =
+                    Bolt11Invoice::from_str(i).map_err(|_| MutinyError::InvoiceInvalid)?;
+                if invoice.network() != self.network {
+                    return Err(MutinyError::IncorrectNetwork(invoice.network()));
+                }
+                // the same invoice can only be paid once
+                if !payment_hashes.insert(*invoice.payment_hash()) {
+                    return Err(MutinyError::NonUniquePaymentHash);
+                }
+                let amount_msat = invoice
+                    .amount_milli_satoshis()
+                    .filter(|a| *a > 0)
+                    .ok_or(MutinyError::BadAmountError)?;
+                Ok((invoice, amount_msat))
+            })
+            .collect();
+
+        let amounts: Vec<Option<u64>> = parsed
+            .iter()
+            .map(|p| p.as_ref().ok().map(|(_, amount)| *amount))
+            .collect();
+        let assigned = assign_batch_payments(&amounts, &capacities);
+        log_debug!(self.logger, "Paying a batch of {} invoices", invoices.len());
+
+        let futs = parsed.into_iter().zip(assigned).map(|(parsed, node)| {
+            let nodes = &nodes;
+            let labels = labels.clone();
+            let retry_policy = retry_policy.clone();
+            async move {
+                let (invoice, _) = parsed?;
+                let node = node
+                    .map(|i| &nodes[i])
+                    .ok_or(MutinyError::InsufficientBalance)?;
+                // each payment waits its turn to be sent, then they finish concurrently
+                let queue = self.queue_command().await;
+                self.pay_invoice_from(
+                    queue,
+                    node,
+                    &invoice,
+                    None,
+                    labels,
+                    None,
+                    retry_policy,
+                    None,
+                )
+                .await
+            }
+        });
+        let results = join_all(futs).await;
+
+        Ok(invoices
+            .into_iter()
+            .zip(results)
+            .map(|(invoice, res)| BatchPaymentResult::new(invoice, res))
+            .collect())
+    }
+
+    /// Saves a split table, replacing any with the same id
+    pub fn save_split(&self, split: SplitTable) -> Result<(), MutinyError> {
+        split.validate()?;
+        self.storage.save_split(split)
+    }
+
+    pub fn list_splits(&self) -> Result<Vec<SplitTable>, MutinyError> {
+        self.storage.list_splits()
+    }
+
+    pub fn delete_split(&self, id: &str) -> Result<(), MutinyError> {
+        self.storage.delete_split(id)
+    }
+
+    /// Pays a total amount, in satoshis, split between the recipients of a
+    /// split table by their share. Node recipients are sent a keysend and
+    /// lightning addresses are paid through LNURL-pay.
+    ///
+    /// Returns a result for every recipient, one recipient failing doesn't
+    /// stop the others from being paid. Each payment is sent through the
+    /// command queue.
+    pub async fn pay_split(
+        &self,
+        from_node: &PublicKey,
+        split_id: &str,
+        total_amount_sats: u64,
+        labels: Vec<String>,
+    ) -> Result<Vec<SplitPaymentResult>, MutinyError> {
+        let split = self
+            .storage
+            .get_split(split_id)?
+            .ok_or(MutinyError::NotFound)?;
+        if total_amount_sats == 0 {
+            return Err(MutinyError::BadAmountError);
+        }
+
+        log_debug!(
+            self.logger,
+            "Paying {total_amount_sats} sats split between {} recipients",
+            split.recipients.len()
+        );
+        let node = self.get_node(from_node).await?;
+        let amounts = split.amounts(total_amount_sats);
+        let mut results = Vec::with_capacity(amounts.len());
+        for (recipient, amount_sats) in split.recipients.into_iter().zip(amounts) {
+            let res = if amount_sats == 0 {
+                Err(MutinyError::BadAmountError)
+            } else {
+                match &recipient.destination {
+                    SplitDestination::Keysend(pubkey) => {
+                        let queue = self.queue_command().await;
+                        self.keysend_from(
+                            queue,
+                            &node,
+                            *pubkey,
+                            amount_sats,
+                            labels.clone(),
+                            None,
+                            recipient.custom_tlvs.clone(),
+                        )
+                        .await
+                    }
+                    SplitDestination::LnAddress(address) => {
+                        match self.get_split_lnurl_invoice(address, amount_sats).await {
+                            Ok(invoice) => {
+                                let queue = self.queue_command().await;
+                                self.pay_invoice_from(
+                                    queue,
+                                    &node,
+                                    &invoice,
+                                    None,
+                                    labels.clone(),
+                                    None,
+                                    None,
+                                    None,
+                                )
+                                .await
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                }
+            };
+            if let Err(e) = res.as_ref() {
+                log_warn!(self.logger, "Failed to pay split recipient: {e}");
+            }
+            results.push(SplitPaymentResult::new(recipient, amount_sats, res));
+        }
+
+        Ok(results)
+    }
+
+    /// Gets the invoice to pay a lightning address in a split table
+    async fn get_split_lnurl_invoice(
+        &self,
+        address: &str,
+        amount_sats: u64,
+    ) -> Result<Bolt11Invoice, MutinyError> {
+        let lnurl = parse_lnurl_or_address(address)?;
+        self.get_lnurl_invoice(&lnurl, amount_sats, None, None)
+            .await
+    }
+
+    /// Probes a route to a node from the selected node without paying anything,
+    /// to see whether a payment of `amt_sats` is likely to succeed and what it costs.
+    pub async fn probe_route(
+        &self,
+        from_node: &PublicKey,
+        destination: PublicKey,
+        amt_sats: u64,
+    ) -> Result<ProbeResult, MutinyError> {
+        let queue = self.queue_command().await;
+        let node = self.get_node(from_node).await?;
+        log_debug!(
+            self.logger,
+            "Probing route to {destination} for {amt_sats} sats"
+        );
+        let (route, pending) = node.send_probes(destination, amt_sats)?;
+        // the probes are sent, other commands don't need to wait for their results
+        drop(queue);
+        node.await_probes(destination, amt_sats, route, pending, None)
+            .await
+    }
+
+    /// Estimates paying an invoice from the selected node with the given retry policy,
+    /// the fee, the chance it succeeds and the longest our funds could be locked up.
+    /// Nothing is sent. The defaults are used if there is no retry policy.
+    pub async fn estimate_payment(
+        &self,
+        from_node: &PublicKey,
+        invoice: &Bolt11Invoice,
+        amt_sats: Option<u64>,
+        retry_policy: Option<RetryPolicy>,
+    ) -> Result<PaymentEstimate, MutinyError> {
+        if invoice.network() != self.network {
+            return Err(MutinyError::IncorrectNetwork(invoice.network()));
+        }
+
+        let node = self.get_node(from_node).await?;
+        node.estimate_payment(invoice, amt_sats, &retry_policy.unwrap_or_default())
+    }
+
+    /// Decodes a lightning invoice into its amount, description, payee,
+    /// expiry, route hints and features.
+    /// Will return an error if the invoice is for a different network.
+    pub async fn decode_invoice(
+        &self,
+        invoice: Bolt11Invoice,
+    ) -> Result<DecodedInvoice, MutinyError> {
+        if invoice.network() != self.network {
+            return Err(MutinyError::IncorrectNetwork(invoice.network()));
+        }
+
+        Ok(invoice.into())
+    }
+
+    /// Calls upon a LNURL to get the parameters for it.
+    /// This contains what kind of LNURL it is (pay, withdrawal, auth, etc).
+    // todo revamp LnUrlParams to be well designed
+    pub async fn decode_lnurl(&self, lnurl: LnUrl) -> Result<LnUrlParams, MutinyError> {
+        // handle LNURL-AUTH
+        if lnurl.is_lnurl_auth() {
+            return Ok(LnUrlParams {
+                max: 0,
+                min: 0,
+                tag: "login".to_string(),
+            });
         }
 
         let response = self.lnurl_client.make_request(&lnurl.url).await?;
@@ -1635,6 +4161,9 @@ impl<S: MutinyStorage> NodeManager<S> {
 
     /// Calls upon a LNURL and pays it.
     /// This will fail if the LNURL is not a LNURL pay.
+    ///
+    /// A comment in the metadata is sent to the LNURL-pay endpoint along with
+    /// the payment, and the metadata is saved with the payment.
     pub async fn lnurl_pay(
         &self,
         from_node: &PublicKey,
@@ -1642,12 +4171,30 @@ impl<S: MutinyStorage> NodeManager<S> {
         amount_sats: u64,
         zap_npub: Option<XOnlyPublicKey>,
         labels: Vec<String>,
+        metadata: Option<PaymentMetadata>,
     ) -> Result<MutinyInvoice, MutinyError> {
+        let invoice = self
+            .get_lnurl_invoice(lnurl, amount_sats, zap_npub, metadata.as_ref())
+            .await?;
+        self.pay_invoice(from_node, &invoice, None, labels, None, None, metadata)
+            .await
+    }
+
+    /// Gets an invoice to pay from a LNURL-pay endpoint and checks it is for
+    /// the amount and metadata we asked for.
+    async fn get_lnurl_invoice(
+        &self,
+        lnurl: &LnUrl,
+        amount_sats: u64,
+        zap_npub: Option<XOnlyPublicKey>,
+        metadata: Option<&PaymentMetadata>,
+    ) -> Result<Bolt11Invoice, MutinyError> {
         let response = self.lnurl_client.make_request(&lnurl.url).await?;
 
         match response {
             LnUrlResponse::LnUrlPayResponse(pay) => {
                 let msats = amount_sats * 1000;
+                check_pay_amount(pay.min_sendable, pay.max_sendable, msats)?;
 
                 // if user's npub is given, do an anon zap
                 let zap_request = match zap_npub {
@@ -1667,19 +4214,57 @@ impl<S: MutinyStorage> NodeManager<S> {
                     None => None,
                 };
 
-                let invoice = self
-                    .lnurl_client
-                    .get_invoice(&pay, msats, zap_request)
-                    .await?;
+                let comment = metadata.and_then(|m| m.comment.as_deref());
+                let invoice = match comment {
+                    // the lnurl client can't send comments, so call the callback ourselves
+                    Some(comment) => {
+                        let url = lnurl_pay_callback_url(
+                            &pay.callback,
+                            msats,
+                            zap_request.as_deref(),
+                            Some(comment),
+                        )?;
+                        let response = self
+                            .http_client
+                            .send(HttpRequest::get(url))
+                            .await
+                            .map_err(|_| MutinyError::LnUrlFailure)?;
+                        let callback: LnUrlPayCallbackResponse =
+                            response.json().map_err(|_| MutinyError::LnUrlFailure)?;
+                        callback.pr
+                    }
+                    None => self
+                        .lnurl_client
+                        .get_invoice(&pay, msats, zap_request.clone())
+                        .await?
+                        .invoice(),
+                };
 
-                self.pay_invoice(from_node, &invoice.invoice(), None, labels)
-                    .await
+                // a zap's invoice commits to the zap request instead of the metadata
+                let description = zap_request.as_deref().unwrap_or(&pay.metadata);
+                check_pay_invoice(&invoice, msats, description)?;
+
+                Ok(invoice)
             }
             LnUrlResponse::LnUrlWithdrawResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
             LnUrlResponse::LnUrlChannelResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
         }
     }
 
+    /// Pays a LNURL-pay endpoint or a lightning address, like `bob@example.com`.
+    /// The amount should be in satoshis.
+    pub async fn pay_lnurl(
+        &self,
+        from_node: &PublicKey,
+        lnurl_or_address: &str,
+        amount_sats: u64,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let lnurl = parse_lnurl_or_address(lnurl_or_address)?;
+        self.lnurl_pay(from_node, &lnurl, amount_sats, None, labels, None)
+            .await
+    }
+
     /// Calls upon a LNURL and withdraws from it.
     /// This will fail if the LNURL is not a LNURL withdrawal.
     pub async fn lnurl_withdraw(
@@ -1693,22 +4278,157 @@ impl<S: MutinyStorage> NodeManager<S> {
             LnUrlResponse::LnUrlPayResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
             LnUrlResponse::LnUrlChannelResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
             LnUrlResponse::LnUrlWithdrawResponse(withdraw) => {
+                check_withdraw_amount(
+                    withdraw.min_withdrawable,
+                    withdraw.max_withdrawable,
+                    amount_sats * 1_000,
+                )?;
+
                 // fixme: do we need to use this description?
                 let _description = withdraw.default_description.clone();
                 let mutiny_invoice = self
-                    .create_invoice(Some(amount_sats), vec!["LNURL Withdrawal".to_string()])
+                    .create_invoice(
+                        Some(amount_sats),
+                        vec!["LNURL Withdrawal".to_string()],
+                        None,
+                        None,
+                    )
                     .await?;
                 let invoice_str = mutiny_invoice.bolt11.expect("Invoice should have bolt11");
+
+                let mut withdrawal = LnUrlWithdrawal {
+                    lnurl: lnurl.clone(),
+                    payment_hash: mutiny_invoice.payment_hash,
+                    amount_sats,
+                    expires_at: mutiny_invoice.expire,
+                    created_at: utils::now().as_secs(),
+                    status: LnUrlWithdrawalStatus::Pending,
+                };
+
                 let res = self
                     .lnurl_client
                     .do_withdrawal(&withdraw, &invoice_str.to_string())
+                    .await;
+                let accepted = match res {
+                    Ok(Response::Ok { .. }) => true,
+                    Ok(Response::Error { reason }) => {
+                        withdrawal.status = LnUrlWithdrawalStatus::Failed { reason };
+                        false
+                    }
+                    Err(e) => {
+                        let e: MutinyError = e.into();
+                        withdrawal.status = LnUrlWithdrawalStatus::Failed {
+                            reason: e.to_string(),
+                        };
+                        self.storage.persist_lnurl_withdrawal(withdrawal)?;
+                        return Err(e);
+                    }
+                };
+                self.storage.persist_lnurl_withdrawal(withdrawal)?;
+
+                Ok(accepted)
+            }
+        }
+    }
+
+    /// Lists the LNURL withdrawals we have made, newest first.
+    /// Pending withdrawals are updated with whether the service has paid us yet.
+    pub async fn list_lnurl_withdrawals(&self) -> Result<Vec<LnUrlWithdrawal>, MutinyError> {
+        let mut withdrawals = self.storage.list_lnurl_withdrawals()?;
+        let now = utils::now().as_secs();
+
+        for withdrawal in withdrawals.iter_mut() {
+            if withdrawal.status != LnUrlWithdrawalStatus::Pending {
+                continue;
+            }
+
+            let paid = self
+                .get_invoice_by_hash(&withdrawal.payment_hash)
+                .await
+                .map(|i| i.paid)
+                .unwrap_or(false);
+            if withdrawal.update(paid, now) {
+                self.storage.persist_lnurl_withdrawal(withdrawal.clone())?;
+            }
+        }
+
+        Ok(withdrawals)
+    }
+
+    /// Calls upon a LNURL-channel and asks the service to open a channel to the
+    /// selected node. We connect to the service's node first, and the channel it
+    /// opens is accepted even if the inbound policy would reject it.
+    /// This will fail if the LNURL is not a LNURL-channel.
+    pub async fn lnurl_channel(
+        &self,
+        self_node_pubkey: &PublicKey,
+        lnurl: &LnUrl,
+        private: bool,
+    ) -> Result<LnUrlChannelRequest, MutinyError> {
+        let response = self.lnurl_client.make_request(&lnurl.url).await?;
+
+        match response {
+            LnUrlResponse::LnUrlPayResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
+            LnUrlResponse::LnUrlWithdrawResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
+            LnUrlResponse::LnUrlChannelResponse(chan) => {
+                let node_pubkey = parse_channel_uri(&chan.uri)?;
+                let service = lnurl_service(lnurl);
+                self.connect_to_peer(self_node_pubkey, &chan.uri, Some(service.clone()))
                     .await?;
+
+                let mut request = LnUrlChannelRequest {
+                    lnurl: lnurl.clone(),
+                    service,
+                    node_pubkey,
+                    uri: chan.uri.clone(),
+                    k1: chan.k1.clone(),
+                    private,
+                    created_at: utils::now().as_secs(),
+                    status: LnUrlChannelStatus::Pending,
+                };
+                // save before calling the service so we're ready to accept its channel
+                self.storage.persist_lnurl_channel(request.clone())?;
+
+                let res = self
+                    .lnurl_client
+                    .open_channel(&chan, *self_node_pubkey, private)
+                    .await;
                 match res {
-                    Response::Ok { .. } => Ok(true),
-                    Response::Error { .. } => Ok(false),
+                    Ok(Response::Ok { .. }) => {}
+                    Ok(Response::Error { reason }) => {
+                        request.status = LnUrlChannelStatus::Failed { reason };
+                    }
+                    Err(e) => {
+                        let e: MutinyError = e.into();
+                        request.status = LnUrlChannelStatus::Failed {
+                            reason: e.to_string(),
+                        };
+                        self.storage.persist_lnurl_channel(request)?;
+                        return Err(e);
+                    }
+                };
+                if request.status != LnUrlChannelStatus::Pending {
+                    self.storage.persist_lnurl_channel(request.clone())?;
                 }
+
+                Ok(request)
+            }
+        }
+    }
+
+    /// Lists the channels we have requested from LNURL-channel services, newest first.
+    /// Pending requests the service hasn't opened a channel for in time are expired.
+    pub fn list_lnurl_channels(&self) -> Result<Vec<LnUrlChannelRequest>, MutinyError> {
+        let mut requests = self.storage.list_lnurl_channels()?;
+        let now = utils::now().as_secs();
+
+        for request in requests.iter_mut() {
+            if request.update(now) {
+                self.storage.persist_lnurl_channel(request.clone())?;
             }
         }
+
+        Ok(requests)
     }
 
     /// Authenticate with a LNURL-auth
@@ -1722,85 +4442,465 @@ impl<S: MutinyStorage> NodeManager<S> {
         .await
     }
 
-    /// Gets an invoice from the node manager.
-    /// This includes sent and received invoices.
-    pub async fn get_invoice(&self, invoice: &Bolt11Invoice) -> Result<MutinyInvoice, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        let inv_opt: Option<MutinyInvoice> =
-            nodes.iter().find_map(|(_, n)| n.get_invoice(invoice).ok());
-        match inv_opt {
-            Some(i) => Ok(i),
-            None => Err(MutinyError::NotFound),
-        }
+    /// The public key the backend service at this url authenticates the wallet by,
+    /// see [crate::service_auth]. It is the same for every url on the same host.
+    pub fn get_service_pubkey(&self, url: &str) -> Result<PublicKey, MutinyError> {
+        self.auth.service_keys().public_key(url)
     }
 
-    /// Gets an invoice from the node manager.
-    /// This includes sent and received invoices.
-    pub async fn get_invoice_by_hash(
+    /// Pairs with a lightning address server so we can receive at `username@<server domain>`.
+    /// The pairing happens in the background, see [crate::lnaddress_pairing] for how
+    /// the server and the wallet talk to each other. Pairing again replaces the old pairing.
+    pub fn pair_lightning_address(
         &self,
-        hash: &sha256::Hash,
-    ) -> Result<MutinyInvoice, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        for (_, node) in nodes.iter() {
-            if let Ok(inv) = node.get_invoice_by_hash(hash) {
-                return Ok(inv);
-            }
-        }
-
-        Err(MutinyError::NotFound)
-    }
+        server_url: String,
+        username: String,
+    ) -> Result<LightningAddressPairing, MutinyError> {
+        pairing_ws_url(&server_url)?;
+        validate_username(&username)?;
+
+        let pairing = LightningAddressPairing {
+            server_url,
+            username,
+            status: PairingStatus::Pending,
+        };
+        self.storage
+            .set_lightning_address_pairing(Some(pairing.clone()))?;
 
-    /// Gets an invoice from the node manager.
-    /// This includes sent and received invoices.
-    pub async fn list_invoices(&self) -> Result<Vec<MutinyInvoice>, MutinyError> {
-        let mut invoices: Vec<MutinyInvoice> = vec![];
-        let nodes = self.nodes.lock().await;
-        for (_, node) in nodes.iter() {
-            if let Ok(mut invs) = node.list_invoices() {
-                invoices.append(&mut invs)
-            }
-        }
-        Ok(invoices)
+        Ok(pairing)
     }
 
-    pub async fn get_channel_closure(
+    pub fn get_lightning_address_pairing(
         &self,
-        user_channel_id: u128,
-    ) -> Result<ChannelClosure, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        for (_, node) in nodes.iter() {
-            if let Ok(Some(closure)) = node.get_channel_closure(user_channel_id) {
-                return Ok(closure);
-            }
-        }
-
-        Err(MutinyError::NotFound)
+    ) -> Result<Option<LightningAddressPairing>, MutinyError> {
+        self.storage.get_lightning_address_pairing()
     }
 
-    pub async fn list_channel_closures(&self) -> Result<Vec<ChannelClosure>, MutinyError> {
-        let mut channels: Vec<ChannelClosure> = vec![];
-        let nodes = self.nodes.lock().await;
-        for (_, node) in nodes.iter() {
-            if let Ok(mut invs) = node.get_channel_closures() {
-                channels.append(&mut invs)
-            }
-        }
-        Ok(channels)
+    /// Stops providing invoices to the lightning address server.
+    pub fn unpair_lightning_address(&self) -> Result<(), MutinyError> {
+        self.storage.set_lightning_address_pairing(None)
     }
 
-    /// Opens a channel from our selected node to the given pubkey.
-    /// The amount is in satoshis.
-    ///
-    /// The node must be online and have a connection to the peer.
-    /// The wallet much have enough funds to open the channel.
-    pub async fn open_channel(
-        &self,
-        from_node: &PublicKey,
-        to_pubkey: Option<PublicKey>,
-        amount: u64,
-        fee_rate: Option<f32>,
+    /// Creates a background process that keeps the connection to the
+    /// lightning address server open while we are paired with one.
+    pub fn start_lightning_address_pairing(nm: Arc<NodeManager<S>>) {
+        utils::spawn(async move {
+            let mut failures = 0;
+            loop {
+                if nm.stop.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                let pairing = match nm.storage.get_lightning_address_pairing() {
+                    Ok(pairing) => pairing,
+                    Err(e) => {
+                        log_error!(nm.logger, "Failed to get lightning address pairing: {e}");
+                        None
+                    }
+                };
+                let wait_secs = match pairing {
+                    // failed pairings wait for the user to pair again
+                    Some(p) if matches!(p.status, PairingStatus::Failed { .. }) => 10,
+                    Some(pairing) => match nm.run_lightning_address_pairing(pairing).await {
+                        Ok(_) => {
+                            failures = 0;
+                            1
+                        }
+                        Err(e) => {
+                            log_warn!(nm.logger, "Lightning address pairing disconnected: {e}");
+                            failures += 1;
+                            backoff_secs(failures)
+                        }
+                    },
+                    None => 10,
+                };
+
+                for _ in 0..wait_secs {
+                    if nm.stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    sleep(1_000).await;
+                }
+            }
+        });
+    }
+
+    /// Connects to the lightning address server and answers its messages until
+    /// the connection drops, we stop, or the pairing is changed.
+    async fn run_lightning_address_pairing(
+        &self,
+        pairing: LightningAddressPairing,
+    ) -> Result<(), MutinyError> {
+        let url = pairing_ws_url(&pairing.server_url)?;
+        let mut ws = WebSocketImpl::new(url.to_string()).await.map_err(|e| {
+            log_error!(
+                self.logger,
+                "Error connecting to lightning address server: {e}"
+            );
+            MutinyError::ConnectionFailed
+        })?;
+
+        loop {
+            let text = ws.recv().await.map_err(|e| {
+                log_error!(
+                    self.logger,
+                    "Error receiving from lightning address server: {e}"
+                );
+                MutinyError::ConnectionFailed
+            })?;
+
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let mut current = match self.storage.get_lightning_address_pairing()? {
+                Some(current) if current.same_as(&pairing) => current,
+                _ => return Ok(()),
+            };
+
+            let msg: ServerMessage = match serde_json::from_str(&text) {
+                Ok(msg) => msg,
+                Err(_) => {
+                    log_warn!(self.logger, "Unknown message from lightning address server");
+                    continue;
+                }
+            };
+
+            let reply = match msg {
+                ServerMessage::Challenge { k1 } => {
+                    let k1: [u8; 32] =
+                        FromHex::from_hex(&k1).map_err(|_| MutinyError::LnUrlFailure)?;
+                    let (sig, key) = self.auth.sign(url.clone(), &k1)?;
+                    ClientMessage::Claim {
+                        username: pairing.username.clone(),
+                        key: key.to_string(),
+                        sig: sig.to_string(),
+                    }
+                }
+                ServerMessage::Claimed { address } => {
+                    log_info!(self.logger, "Paired lightning address {address}");
+                    current.status = PairingStatus::Claimed { address };
+                    self.storage.set_lightning_address_pairing(Some(current))?;
+                    continue;
+                }
+                ServerMessage::InvoiceRequest {
+                    id,
+                    amount_msat,
+                    description_hash,
+                } => match self
+                    .provide_lightning_address_invoice(amount_msat, description_hash.as_deref())
+                    .await
+                {
+                    Ok(pr) => ClientMessage::Invoice { id, pr },
+                    Err(e) => ClientMessage::Error {
+                        id: Some(id),
+                        reason: e.to_string(),
+                    },
+                },
+                ServerMessage::Error {
+                    id: Some(id),
+                    reason,
+                } => {
+                    log_warn!(
+                        self.logger,
+                        "Lightning address server error for {id}: {reason}"
+                    );
+                    continue;
+                }
+                ServerMessage::Error { id: None, reason } => {
+                    log_error!(self.logger, "Lightning address pairing failed: {reason}");
+                    current.status = PairingStatus::Failed { reason };
+                    self.storage.set_lightning_address_pairing(Some(current))?;
+                    return Ok(());
+                }
+            };
+
+            let reply = serde_json::to_string(&reply)?;
+            ws.send(reply).await.map_err(|e| {
+                log_error!(
+                    self.logger,
+                    "Error sending to lightning address server: {e}"
+                );
+                MutinyError::ConnectionFailed
+            })?;
+        }
+    }
+
+    async fn provide_lightning_address_invoice(
+        &self,
+        amount_msat: u64,
+        description_hash: Option<&str>,
+    ) -> Result<String, MutinyError> {
+        let (amount_sats, description_hash) = parse_invoice_request(amount_msat, description_hash)?;
+        let invoice = self
+            .create_invoice_with_description_hash(
+                Some(amount_sats),
+                vec!["Lightning Address".to_string()],
+                None,
+                description_hash,
+                None,
+            )
+            .await?;
+
+        invoice
+            .bolt11
+            .map(|i| i.to_string())
+            .ok_or(MutinyError::InvoiceCreationFailed)
+    }
+
+    /// Parses a BOLT 11 invoice, BOLT 12 offer, LNURL, lightning address,
+    /// on-chain address or BIP 21 URI into what it asks to be paid.
+    pub fn parse_payment_request(&self, request: &str) -> Result<PaymentRequest, MutinyError> {
+        parse_payment_request(request, self.network)
+    }
+
+    /// Pays any payment request from the selected node. A BIP 21 URI with both
+    /// an invoice and an address is paid over lightning first, and on-chain if
+    /// the lightning payment fails without sending anything.
+    /// The amount is only used if the request doesn't have one.
+    pub async fn pay_unified(
+        &self,
+        from_node: &PublicKey,
+        request: &str,
+        amount_sats: Option<u64>,
+        labels: Vec<String>,
+    ) -> Result<UnifiedPayment, MutinyError> {
+        match parse_payment_request(request, self.network)? {
+            PaymentRequest::Bolt11 { invoice } => {
+                let amt_sats = amount_sats.filter(|_| invoice.amount_milli_satoshis().is_none());
+                let invoice = self
+                    .pay_invoice(from_node, &invoice, amt_sats, labels, None, None, None)
+                    .await?;
+                Ok(UnifiedPayment::Lightning { invoice })
+            }
+            PaymentRequest::Bolt12 { .. } => Err(MutinyError::Other(anyhow!(
+                "Paying BOLT 12 offers is not supported yet"
+            ))),
+            PaymentRequest::LnUrl { lnurl } | PaymentRequest::LightningAddress { lnurl, .. } => {
+                let amount_sats = amount_sats.ok_or(MutinyError::BadAmountError)?;
+                let invoice = self
+                    .lnurl_pay(from_node, &lnurl, amount_sats, None, labels, None)
+                    .await?;
+                Ok(UnifiedPayment::Lightning { invoice })
+            }
+            PaymentRequest::OnChain {
+                address,
+                amount_sats: requested,
+                ..
+            } => {
+                let amount = requested
+                    .or(amount_sats)
+                    .ok_or(MutinyError::BadAmountError)?;
+                let txid = self
+                    .send_to_address(address, amount, labels, None, None)
+                    .await?;
+                Ok(UnifiedPayment::OnChain {
+                    txid,
+                    lightning_error: None,
+                })
+            }
+            PaymentRequest::Unified {
+                address,
+                invoice,
+                amount_sats: requested,
+                ..
+            } => {
+                let amount = requested.or(amount_sats);
+                let amt_sats = amount.filter(|_| invoice.amount_milli_satoshis().is_none());
+                let lightning_error = match self
+                    .pay_invoice(
+                        from_node,
+                        &invoice,
+                        amt_sats,
+                        labels.clone(),
+                        None,
+                        None,
+                        None,
+                    )
+                    .await
+                {
+                    Ok(invoice) => return Ok(UnifiedPayment::Lightning { invoice }),
+                    Err(e) if can_fall_back_on_chain(&e) => e,
+                    Err(e) => return Err(e),
+                };
+                log_warn!(
+                    self.logger,
+                    "Lightning payment failed, paying on-chain instead: {lightning_error}"
+                );
+
+                let amount = amount.ok_or(MutinyError::BadAmountError)?;
+                let txid = self
+                    .send_to_address(address, amount, labels, None, None)
+                    .await?;
+                Ok(UnifiedPayment::OnChain {
+                    txid,
+                    lightning_error: Some(lightning_error.to_string()),
+                })
+            }
+        }
+    }
+
+    /// Parses and validates a URI (BIP 21, invoice, LNURL, lightning address,
+    /// nostr wallet connect or a Mutiny deep link) and returns what the
+    /// frontend should do with it.
+    /// LNURLs are called upon to find out if they are for paying or withdrawing.
+    pub async fn handle_uri(&self, uri: &str) -> Result<UriIntent, MutinyError> {
+        let (lnurl, lightning_address) = match parse_uri(uri, self.network)? {
+            ParsedUri::Intent(intent) => return Ok(intent),
+            ParsedUri::LnUrl {
+                lnurl,
+                lightning_address,
+            } => (lnurl, lightning_address),
+        };
+
+        let response = self.lnurl_client.make_request(&lnurl.url).await?;
+
+        match response {
+            LnUrlResponse::LnUrlPayResponse(pay) => Ok(UriIntent::LnUrlPay {
+                lnurl,
+                lightning_address,
+                min_sendable_msats: pay.min_sendable,
+                max_sendable_msats: pay.max_sendable,
+            }),
+            LnUrlResponse::LnUrlWithdrawResponse(withdraw) => Ok(UriIntent::Withdraw {
+                lnurl,
+                min_withdrawable_msats: withdraw.min_withdrawable.unwrap_or(0),
+                max_withdrawable_msats: withdraw.max_withdrawable,
+            }),
+            LnUrlResponse::LnUrlChannelResponse(chan) => Ok(UriIntent::Channel {
+                service: lnurl_service(&lnurl),
+                node_pubkey: parse_channel_uri(&chan.uri)?,
+                lnurl,
+            }),
+        }
+    }
+
+    /// Gets an invoice from the node manager.
+    /// This includes sent and received invoices.
+    pub async fn get_invoice(&self, invoice: &Bolt11Invoice) -> Result<MutinyInvoice, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let inv_opt: Option<MutinyInvoice> =
+            nodes.iter().find_map(|(_, n)| n.get_invoice(invoice).ok());
+        match inv_opt {
+            Some(i) => Ok(i),
+            None => Err(MutinyError::NotFound),
+        }
+    }
+
+    /// Gets an invoice from the node manager.
+    /// This includes sent and received invoices.
+    pub async fn get_invoice_by_hash(
+        &self,
+        hash: &sha256::Hash,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for (_, node) in nodes.iter() {
+            if let Ok(inv) = node.get_invoice_by_hash(hash) {
+                return Ok(inv);
+            }
+        }
+
+        Err(MutinyError::NotFound)
+    }
+
+    /// Gets the lifecycle of each HTLC of a payment, across all of the nodes.
+    /// Useful for understanding payments that have been pending for a long time.
+    pub async fn get_payment_htlcs(
+        &self,
+        hash: &sha256::Hash,
+    ) -> Result<Vec<PaymentHtlc>, MutinyError> {
+        let payment_hash = PaymentHash(hash.into_inner());
+        let nodes = self.nodes.lock().await;
+        let mut htlcs = vec![];
+        for (_, node) in nodes.iter() {
+            htlcs.extend(node.persister.get_payment_htlcs(&payment_hash)?);
+        }
+
+        Ok(htlcs)
+    }
+
+    /// Subscribes to the state changes of a payment, sent or received, on any of the nodes.
+    /// The first update is the payment's current state, if we know of it, and the
+    /// stream ends once the payment is settled or failed.
+    pub async fn subscribe_payment(
+        &self,
+        hash: &sha256::Hash,
+    ) -> Result<UnboundedReceiver<PaymentStatusUpdate>, MutinyError> {
+        let payment_hash = PaymentHash(hash.into_inner());
+        // subscribe before reading the current state so no update is missed in between
+        let receiver = self.payment_subscriptions.subscribe(payment_hash.0);
+
+        let nodes = self.nodes.lock().await;
+        let current = nodes.values().find_map(|node| {
+            [false, true].into_iter().find_map(|inbound| {
+                node.persister
+                    .read_payment_info(&payment_hash, inbound, &self.logger)
+                    .map(|info| (inbound, info))
+            })
+        });
+        if let Some((inbound, info)) = current {
+            self.payment_subscriptions.notify(
+                &payment_hash.0,
+                inbound,
+                PaymentState::from_payment_info(&info),
+                info.order_id,
+            );
+        }
+
+        Ok(receiver)
+    }
+
+    /// Gets an invoice from the node manager.
+    /// This includes sent and received invoices.
+    pub async fn list_invoices(&self) -> Result<Vec<MutinyInvoice>, MutinyError> {
+        let mut invoices: Vec<MutinyInvoice> = vec![];
+        let nodes = self.nodes.lock().await;
+        for (_, node) in nodes.iter() {
+            if let Ok(mut invs) = node.list_invoices() {
+                invoices.append(&mut invs)
+            }
+        }
+        Ok(invoices)
+    }
+
+    pub async fn get_channel_closure(
+        &self,
+        user_channel_id: u128,
+    ) -> Result<ChannelClosure, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for (_, node) in nodes.iter() {
+            if let Ok(Some(closure)) = node.get_channel_closure(user_channel_id) {
+                return Ok(closure);
+            }
+        }
+
+        Err(MutinyError::NotFound)
+    }
+
+    pub async fn list_channel_closures(&self) -> Result<Vec<ChannelClosure>, MutinyError> {
+        let mut channels: Vec<ChannelClosure> = vec![];
+        let nodes = self.nodes.lock().await;
+        for (_, node) in nodes.iter() {
+            if let Ok(mut invs) = node.get_channel_closures() {
+                channels.append(&mut invs)
+            }
+        }
+        Ok(channels)
+    }
+
+    /// Opens a channel from our selected node to the given pubkey.
+    /// The amount is in satoshis.
+    ///
+    /// The node must be online and have a connection to the peer.
+    /// The wallet much have enough funds to open the channel.
+    pub async fn open_channel(
+        &self,
+        from_node: &PublicKey,
+        to_pubkey: Option<PublicKey>,
+        amount: u64,
+        fee_rate: Option<f32>,
         user_channel_id: Option<u128>,
     ) -> Result<MutinyChannel, MutinyError> {
+        let _queue = self.queue_command().await;
         let node = self.get_node(from_node).await?;
 
         let to_pubkey = match to_pubkey {
@@ -1839,6 +4939,19 @@ impl<S: MutinyStorage> NodeManager<S> {
         from_node: &PublicKey,
         utxos: &[OutPoint],
         to_pubkey: Option<PublicKey>,
+    ) -> Result<MutinyChannel, MutinyError> {
+        let _queue = self.queue_command().await;
+        self.open_sweep_channel(user_chan_id, from_node, utxos, to_pubkey)
+            .await
+    }
+
+    /// Opens a channel spending the given utxos in full, the command queue must be held.
+    async fn open_sweep_channel(
+        &self,
+        user_chan_id: Option<u128>,
+        from_node: &PublicKey,
+        utxos: &[OutPoint],
+        to_pubkey: Option<PublicKey>,
     ) -> Result<MutinyChannel, MutinyError> {
         let node = self.get_node(from_node).await?;
 
@@ -1877,50 +4990,213 @@ impl<S: MutinyStorage> NodeManager<S> {
         from_node: &PublicKey,
         to_pubkey: Option<PublicKey>,
     ) -> Result<MutinyChannel, MutinyError> {
+        let _queue = self.queue_command().await;
         let utxos = self
             .list_utxos()?
             .iter()
             .map(|u| u.outpoint)
             .collect::<Vec<_>>();
 
-        self.sweep_utxos_to_channel(user_chan_id, from_node, &utxos, to_pubkey)
+        self.open_sweep_channel(user_chan_id, from_node, &utxos, to_pubkey)
             .await
     }
 
-    /// Closes a channel with the given outpoint.
-    ///
-    /// If force is true, the channel will be force closed.
+    /// The largest channel we can open with our entire on-chain balance,
+    /// which is the balance minus the on-chain fee to open it.
     ///
-    /// If abandon is true, the channel will be abandoned.
-    /// This will force close without broadcasting the latest transaction.
-    /// This should only be used if the channel will never actually be opened.
+    /// Errors if that is less than the smallest channel worth opening.
+    pub fn max_channel_amount(&self) -> Result<u64, MutinyError> {
+        let utxos = self.list_utxos()?;
+        let total: u64 = utxos.iter().map(|u| u.txout.value).sum();
+        // the same estimate used when the channel is opened
+        let fee =
+            self.fee_estimator
+                .calculate_expected_fee(utxos.len(), P2WSH_OUTPUT_SIZE, None, None);
+
+        let amount = total.saturating_sub(fee);
+        if amount < utils::min_lightning_amount(self.network) {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        Ok(amount)
+    }
+
+    /// Opens a single channel with our entire on-chain balance, there is no change output.
+    /// The channel size is [NodeManager::max_channel_amount].
+    pub async fn open_channel_max(
+        &self,
+        from_node: &PublicKey,
+        to_pubkey: Option<PublicKey>,
+    ) -> Result<MutinyChannel, MutinyError> {
+        let _queue = self.queue_command().await;
+        let amount = self.max_channel_amount()?;
+        log_info!(
+            self.logger,
+            "Opening a channel with our whole balance, {amount} sats"
+        );
+
+        let utxos = self
+            .list_utxos()?
+            .iter()
+            .map(|u| u.outpoint)
+            .collect::<Vec<_>>();
+        self.open_sweep_channel(None, from_node, &utxos, to_pubkey)
+            .await
+    }
+
+    /// Starts a channel open that is paid for from another wallet.
     ///
-    /// If both force and abandon are true, an error will be returned.
-    pub async fn close_channel(
+    /// Returns the address and amount the other wallet has to pay, the amount
+    /// is the channel size in sats plus the on-chain fee to open it. Once the
+    /// payment confirms the channel is opened with exactly those funds.
+    pub async fn open_channel_external(
         &self,
-        outpoint: &OutPoint,
-        address: Option<Address>,
-        force: bool,
-        abandon: bool,
-    ) -> Result<(), MutinyError> {
-        if force && abandon {
-            return Err(MutinyError::ChannelClosingFailed);
+        from_node: &PublicKey,
+        to_pubkey: Option<PublicKey>,
+        amount: u64,
+    ) -> Result<ExternalFunding, MutinyError> {
+        let _queue = self.queue_command().await;
+        let node = self.get_node(from_node).await?;
+        if to_pubkey.is_none() && node.lsp_client.is_none() {
+            return Err(MutinyError::PubkeyInvalid);
         }
 
-        let nodes = self.nodes.lock().await;
-        let channel_opt: Option<(Arc<Node<S>>, ChannelDetails)> =
-            nodes.iter().find_map(|(_, n)| {
-                n.channel_manager
-                    .list_channels()
-                    .iter()
-                    .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
-                    .map(|c| (n.clone(), c.clone()))
-            });
+        // the funds arrive in a single output that gets spent to the channel
+        let fee = self
+            .fee_estimator
+            .calculate_expected_fee(1, P2WSH_OUTPUT_SIZE, None, None);
+        let address = self.get_new_address(vec!["External Channel Funding".to_string()])?;
 
-        match channel_opt {
-            Some((node, channel)) => {
-                if force {
-                    node.channel_manager
+        let funding = ExternalFunding {
+            address,
+            amount_sats: amount + fee,
+            from_node: *from_node,
+            to_pubkey,
+            created_at: utils::now().as_secs(),
+            status: ExternalFundingStatus::Waiting,
+        };
+
+        let mut fundings = self.storage.get_external_fundings()?;
+        fundings.push(funding.clone());
+        self.storage.persist_external_fundings(fundings)?;
+
+        Ok(funding)
+    }
+
+    /// Lists the channel opens paid for from other wallets.
+    pub fn list_external_fundings(&self) -> Result<Vec<ExternalFunding>, MutinyError> {
+        self.storage.get_external_fundings()
+    }
+
+    /// Opens the channels for external fundings whose payments have confirmed.
+    async fn check_external_fundings(&self) -> Result<(), MutinyError> {
+        let fundings = self.storage.get_external_fundings()?;
+        if !fundings.iter().any(|f| f.is_pending()) {
+            return Ok(());
+        }
+
+        let utxos = self.list_utxos()?;
+        let now = utils::now().as_secs();
+
+        let mut updated = vec![];
+        for mut funding in fundings.into_iter().filter(|f| f.is_pending()) {
+            let script = funding.address.script_pubkey();
+            // only use confirmed funds so the sender can't double spend the channel away
+            let paid: Vec<&LocalUtxo> = utxos
+                .iter()
+                .filter(|u| u.txout.script_pubkey == script)
+                .filter(|u| matches!(u.confirmation_time, ConfirmationTime::Confirmed { .. }))
+                .collect();
+            let received_sats = paid.iter().map(|u| u.txout.value).sum();
+
+            let before = funding.status.clone();
+            if funding.update(received_sats, now) {
+                let outpoints: Vec<OutPoint> = paid.iter().map(|u| u.outpoint).collect();
+                match self
+                    .sweep_utxos_to_channel(None, &funding.from_node, &outpoints, funding.to_pubkey)
+                    .await
+                {
+                    Ok(channel) => {
+                        funding.status = ExternalFundingStatus::Opened {
+                            channel: channel.outpoint,
+                        };
+                    }
+                    // keep it pending to try again next time
+                    Err(e) => {
+                        log_warn!(self.logger, "Failed to open externally funded channel: {e}")
+                    }
+                }
+            }
+
+            if funding.status != before {
+                updated.push(funding);
+            }
+        }
+
+        if updated.is_empty() {
+            return Ok(());
+        }
+
+        // re-read in case a funding was added while we were opening
+        let mut fundings = self.storage.get_external_fundings()?;
+        for funding in fundings.iter_mut() {
+            if let Some(update) = updated.iter().find(|u| u.address == funding.address) {
+                *funding = update.clone();
+            }
+        }
+        self.storage.persist_external_fundings(fundings)
+    }
+
+    /// Closes a channel with the given outpoint.
+    ///
+    /// If force is true, the channel will be force closed.
+    ///
+    /// If abandon is true, the channel will be abandoned.
+    /// This will force close without broadcasting the latest transaction.
+    /// This should only be used if the channel will never actually be opened.
+    ///
+    /// If both force and abandon are true, an error will be returned.
+    ///
+    /// Our funds are sent to the given address, or to the
+    /// configured sweep destination if one is not given.
+    pub async fn close_channel(
+        &self,
+        outpoint: &OutPoint,
+        address: Option<Address>,
+        force: bool,
+        abandon: bool,
+    ) -> Result<(), MutinyError> {
+        if force && abandon {
+            return Err(MutinyError::ChannelClosingFailed);
+        }
+        if let Some(address) = address.as_ref() {
+            if !address.is_valid_for_network(self.network) {
+                return Err(MutinyError::IncorrectNetwork(address.network));
+            }
+        }
+
+        let _queue = self.queue_command().await;
+        let nodes = self.nodes.lock().await;
+        let channel_opt: Option<(Arc<Node<S>>, ChannelDetails)> =
+            nodes.iter().find_map(|(_, n)| {
+                n.channel_manager
+                    .list_channels()
+                    .iter()
+                    .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
+                    .map(|c| (n.clone(), c.clone()))
+            });
+
+        match channel_opt {
+            Some((node, channel)) => {
+                if force {
+                    // our outputs get swept once they are spendable,
+                    // remember where this channel's should go
+                    if address.is_some() {
+                        self.storage
+                            .set_channel_sweep_destination(*outpoint, address)?;
+                    }
+
+                    node.channel_manager
                         .force_close_broadcasting_latest_txn(
                             &channel.channel_id,
                             &channel.counterparty.node_id,
@@ -1950,11 +5226,15 @@ impl<S: MutinyStorage> NodeManager<S> {
                             MutinyError::ChannelClosingFailed
                         })?;
                 } else {
-                    // convert address to ShutdownScript
-                    let shutdown_script = if let Some(addr) = address {
-                        Some(ShutdownScript::try_from(addr.script_pubkey())?)
-                    } else {
-                        None
+                    // convert address to ShutdownScript,
+                    // falling back to the configured sweep destination
+                    let script = match address {
+                        Some(addr) => Some(addr.script_pubkey()),
+                        None => self.storage.next_sweep_script()?,
+                    };
+                    let shutdown_script = match script {
+                        Some(script) => Some(ShutdownScript::try_from(script)?),
+                        None => None,
                     };
 
                     node.channel_manager
@@ -1987,295 +5267,1670 @@ impl<S: MutinyStorage> NodeManager<S> {
         }
     }
 
-    /// Lists all the channels for all the nodes in the node manager.
-    pub async fn list_channels(&self) -> Result<Vec<MutinyChannel>, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        let channels: Vec<ChannelDetails> = nodes
-            .iter()
-            .flat_map(|(_, n)| n.channel_manager.list_channels())
-            .collect();
-
-        let mutiny_channels: Vec<MutinyChannel> =
-            channels.iter().map(MutinyChannel::from).collect();
+    /// Sets where the funds from closed channels are sent by default.
+    ///
+    /// This can be overridden for a single channel by passing an address
+    /// to [NodeManager::close_channel].
+    pub async fn set_sweep_destination(
+        &self,
+        destination: SweepDestination,
+    ) -> Result<(), MutinyError> {
+        destination.validate(self.network)?;
+        let _queue = self.queue_command().await;
+        self.storage.set_sweep_destination(destination)
+    }
 
-        Ok(mutiny_channels)
+    /// Gets where the funds from closed channels are sent by default.
+    pub fn get_sweep_destination(&self) -> Result<SweepDestination, MutinyError> {
+        self.storage.get_sweep_destination()
     }
 
-    fn get_scb_key(&self) -> SecretKey {
-        let path = DerivationPath::from_str(SCB_ENCRYPTION_KEY_DERIVATION_PATH).unwrap();
-        let context = Secp256k1::new();
+    /// Schedules a cooperative close of the channel with the given outpoint.
+    ///
+    /// The channel is closed once the normal priority fee rate drops to
+    /// `at_fee_below` (in sat/vbyte) within the window between `not_before`
+    /// and `deadline` (unix timestamps). At the deadline it is closed regardless
+    /// of fees. Scheduling a channel again replaces its previous schedule.
+    pub async fn schedule_close(
+        &self,
+        outpoint: OutPoint,
+        at_fee_below: Option<f32>,
+        not_before: Option<u64>,
+        deadline: Option<u64>,
+        address: Option<Address>,
+    ) -> Result<ScheduledClose, MutinyError> {
+        if at_fee_below.is_none() && not_before.is_none() && deadline.is_none() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        if let (Some(start), Some(end)) = (not_before, deadline) {
+            if start > end {
+                return Err(MutinyError::InvalidArgumentsError);
+            }
+        }
+        if let Some(address) = address.as_ref() {
+            if !address.is_valid_for_network(self.network) {
+                return Err(MutinyError::IncorrectNetwork(address.network));
+            }
+        }
 
-        self.xprivkey
-            .derive_priv(&context, &path)
-            .unwrap()
-            .private_key
+        let channels = self.list_channels().await?;
+        if !channels.iter().any(|c| c.outpoint == Some(outpoint)) {
+            return Err(MutinyError::NotFound);
+        }
+
+        let close = ScheduledClose {
+            outpoint,
+            max_fee_rate: at_fee_below,
+            not_before,
+            deadline,
+            address,
+            created_at: utils::now().as_secs(),
+        };
+
+        let mut closes = self.storage.get_scheduled_closes()?;
+        closes.retain(|c| c.outpoint != outpoint);
+        closes.push(close.clone());
+        self.storage.persist_scheduled_closes(closes)?;
+
+        Ok(close)
     }
 
-    /// Creates a static channel backup for all the nodes in the node manager.
-    /// The backup is encrypted with the SCB key.
-    pub async fn create_static_channel_backup(&self) -> Result<EncryptedSCB, MutinyError> {
-        let nodes = self.nodes.lock().await;
-        let mut backups: HashMap<PublicKey, (NodeIndex, StaticChannelBackup)> = HashMap::new();
-        for (_, node) in nodes.iter() {
-            let scb = node.create_static_channel_backup()?;
-            backups.insert(node.pubkey, (node.node_index(), scb));
+    /// Cancels a scheduled close for the channel with the given outpoint.
+    pub fn cancel_scheduled_close(&self, outpoint: &OutPoint) -> Result<(), MutinyError> {
+        let mut closes = self.storage.get_scheduled_closes()?;
+        let len = closes.len();
+        closes.retain(|c| c.outpoint != *outpoint);
+        if closes.len() == len {
+            return Err(MutinyError::NotFound);
         }
 
-        let peers = get_all_peers(&self.storage).unwrap_or_default();
+        self.storage.persist_scheduled_closes(closes)
+    }
 
-        let peer_connections = peers
-            .into_iter()
-            .filter_map(|(n, p)| p.connection_string.map(|str| (n.as_pubkey().unwrap(), str)))
-            .collect::<HashMap<_, _>>();
+    /// Lists the channel closes that are waiting to happen.
+    pub fn list_scheduled_closes(&self) -> Result<Vec<ScheduledClose>, MutinyError> {
+        self.storage.get_scheduled_closes()
+    }
 
-        let scb = StaticChannelBackupStorage {
-            backups,
-            peer_connections,
-        };
+    /// Replaces the channel rules, which are run on every channel in the background.
+    /// An empty list turns the rules off.
+    pub fn set_channel_rules(&self, rules: Vec<ChannelRule>) -> Result<(), MutinyError> {
+        validate_rules(&rules, self.network)?;
+        self.storage.set_channel_rules(rules)
+    }
 
-        // encrypt
-        let encryption_key = self.get_scb_key();
-        let scb = scb.encrypt(&encryption_key);
-        log_debug!(
-            self.logger,
-            "Created SCB with a size of {} bytes",
-            scb.encode().len()
-        );
-        Ok(scb)
+    pub fn list_channel_rules(&self) -> Result<Vec<ChannelRule>, MutinyError> {
+        self.storage.get_channel_rules()
     }
 
-    /// Takes an encrypted static channel backup and recovers the channels from it.
-    /// If the backup is encrypted with a different key than the current key, it will fail.
-    pub async fn recover_from_static_channel_backup(
+    /// Shows which channels the rules would act on right now without doing anything.
+    /// Previews the saved rules if none are given.
+    pub async fn preview_channel_rules(
         &self,
-        scb: EncryptedSCB,
-    ) -> Result<(), MutinyError> {
-        // decrypt
-        let encryption_key = self.get_scb_key();
-        let scb = scb.decrypt(&encryption_key)?;
+        rules: Option<Vec<ChannelRule>>,
+    ) -> Result<Vec<RuleMatch>, MutinyError> {
+        let rules = match rules {
+            Some(rules) => {
+                validate_rules(&rules, self.network)?;
+                rules
+            }
+            None => self.storage.get_channel_rules()?,
+        };
+        let channels = self.list_channels().await?;
+        let activity = self.storage.get_channel_activity()?;
+
+        Ok(evaluate_rules(
+            &rules,
+            &channels,
+            &activity,
+            utils::now().as_secs(),
+        ))
+    }
 
-        // stop all nodes, todo stop in parallel
-        for node in self.nodes.lock().await.values() {
-            node.stop().await?;
+    /// The actions the channel rules have taken, newest first.
+    pub fn get_channel_rule_log(&self) -> Result<Vec<RuleExecution>, MutinyError> {
+        self.storage.get_channel_rule_log()
+    }
+
+    /// Creates a payment that is made every `interval_secs` while the node is
+    /// running, starting at `start` (a unix timestamp) or right away.
+    ///
+    /// The catch-up policy decides what happens to intervals that were missed
+    /// while the node wasn't running.
+    pub fn create_recurring_payment(
+        &self,
+        target: RecurringPaymentTarget,
+        amount_sats: u64,
+        interval_secs: u64,
+        start: Option<u64>,
+        catch_up: CatchUpPolicy,
+        labels: Vec<String>,
+    ) -> Result<RecurringPayment, MutinyError> {
+        if amount_sats == 0 {
+            return Err(MutinyError::BadAmountError);
+        }
+        if interval_secs < MIN_RECURRING_INTERVAL_SECS {
+            return Err(MutinyError::InvalidArgumentsError);
         }
 
-        for (pubkey, (node_index, backup)) in scb.backups {
-            // find the uuid if we have it, otherwise create a new one and save it
-            let uuid = {
-                let mut node_mutex = self.node_storage.lock().await;
-                let current = node_mutex
-                    .nodes
-                    .iter()
-                    .find(|(_, n)| *n == &node_index)
-                    .map(|(uuid, _)| uuid.clone());
+        let now = utils::now().as_secs();
+        let payment = RecurringPayment {
+            id: Uuid::new_v4().to_string(),
+            target,
+            amount_sats,
+            interval_secs,
+            next_payment: start.unwrap_or(now),
+            catch_up,
+            labels,
+            created_at: now,
+            last_paid: None,
+            last_error: None,
+        };
 
-                match current {
-                    Some(uuid) => uuid,
-                    None => {
-                        let mut existing_nodes = self.storage.get_nodes()?;
-                        let new_uuid = Uuid::new_v4().to_string();
-                        existing_nodes
-                            .nodes
-                            .insert(new_uuid.clone(), node_index.clone());
+        let mut payments = self.storage.get_recurring_payments()?;
+        payments.push(payment.clone());
+        self.storage.persist_recurring_payments(payments)?;
 
-                        self.storage.insert_nodes(existing_nodes.clone())?;
-                        node_mutex.nodes = existing_nodes.nodes.clone();
+        Ok(payment)
+    }
 
-                        new_uuid
-                    }
-                }
-            };
+    /// Cancels a recurring payment, no more payments are made for it.
+    pub fn cancel_recurring_payment(&self, id: &str) -> Result<(), MutinyError> {
+        let mut payments = self.storage.get_recurring_payments()?;
+        let len = payments.len();
+        payments.retain(|p| p.id != id);
+        if payments.len() == len {
+            return Err(MutinyError::NotFound);
+        }
 
-            // create a fresh instance of each node
-            let new_node = Node::new(
-                uuid,
-                &node_index,
-                self.xprivkey,
-                self.storage.clone(),
-                self.gossip_sync.clone(),
-                self.scorer.clone(),
-                self.chain.clone(),
-                self.fee_estimator.clone(),
-                self.wallet.clone(),
-                self.network,
-                &self.esplora,
-                &self.lsp_clients,
-                self.logger.clone(),
-                true,
-                true,
-                #[cfg(target_arch = "wasm32")]
-                self.websocket_proxy_addr.clone(),
-            )
-            .await;
+        self.storage.persist_recurring_payments(payments)
+    }
 
-            match new_node {
-                Ok(node) => {
-                    debug_assert!(node.node_index().child_index == node_index.child_index);
-                    log_info!(
-                        self.logger,
-                        "Recovering node {pubkey} from static channel backup"
-                    );
-                    node.recover_from_static_channel_backup(backup, &scb.peer_connections)
-                        .await?;
-                }
-                Err(_) => {
-                    log_error!(
-                        self.logger,
-                        "Could not find node with pubkey {pubkey} to recover from backup",
-                    );
-                }
+    /// Lists the recurring payments and when each is next due.
+    pub fn list_recurring_payments(&self) -> Result<Vec<RecurringPayment>, MutinyError> {
+        self.storage.get_recurring_payments()
+    }
+
+    /// Sets the policy that moves lightning funds above a ceiling to cold storage,
+    /// `None` turns it off. See [SwapOutPolicy] for how the funds are moved.
+    pub fn set_swap_out_policy(&self, policy: Option<SwapOutPolicy>) -> Result<(), MutinyError> {
+        if let Some(policy) = policy.as_ref() {
+            if !policy.address.is_valid_for_network(self.network) {
+                return Err(MutinyError::IncorrectNetwork(policy.address.network));
             }
         }
 
-        Ok(())
+        self.storage.set_swap_out_policy(policy)
     }
 
-    /// Lists all the peers for all the nodes in the node manager.
-    pub async fn list_peers(&self) -> Result<Vec<MutinyPeer>, MutinyError> {
-        let peer_data = gossip::get_all_peers(&self.storage)?;
+    pub fn get_swap_out_policy(&self) -> Result<Option<SwapOutPolicy>, MutinyError> {
+        self.storage.get_swap_out_policy()
+    }
 
-        // get peers saved in storage
-        let mut storage_peers: Vec<MutinyPeer> = peer_data
-            .iter()
-            .map(|(node_id, metadata)| MutinyPeer {
-                // node id should be safe here
-                pubkey: PublicKey::from_slice(node_id.as_slice()).expect("Invalid pubkey"),
-                connection_string: metadata.connection_string.clone(),
-                alias: metadata.alias.clone(),
-                color: metadata.color.clone(),
-                label: metadata.label.clone(),
-                is_connected: false,
-            })
-            .collect();
+    /// Lists every time the swap out policy moved funds or asked to.
+    pub fn list_swap_outs(&self) -> Result<Vec<SwapOut>, MutinyError> {
+        self.storage.get_swap_outs()
+    }
 
-        let nodes = self.nodes.lock().await;
+    /// Confirms a swap out that is waiting for confirmation, closing its channel.
+    pub async fn confirm_swap_out(&self, channel: &OutPoint) -> Result<SwapOut, MutinyError> {
+        let mut swap_outs = self.storage.get_swap_outs()?;
+        let swap_out = swap_outs
+            .iter_mut()
+            .find(|s| s.channel == *channel && s.status == SwapOutStatus::AwaitingConfirmation)
+            .ok_or(MutinyError::NotFound)?;
 
-        // get peers we are connected to
-        let connected_peers: Vec<PublicKey> = nodes
-            .iter()
-            .flat_map(|(_, n)| n.peer_manager.get_peer_node_ids())
-            .collect();
+        self.execute_swap_out(swap_out).await;
+        let swap_out = swap_out.clone();
+        self.storage.persist_swap_outs(swap_outs)?;
 
-        // correctly set is_connected
-        for peer in &mut storage_peers {
-            if connected_peers.contains(&peer.pubkey) {
-                peer.is_connected = true;
-            }
+        Ok(swap_out)
+    }
+
+    /// Declines a swap out that is waiting for confirmation.
+    /// The policy won't try to close that channel again.
+    pub fn decline_swap_out(&self, channel: &OutPoint) -> Result<(), MutinyError> {
+        let mut swap_outs = self.storage.get_swap_outs()?;
+        let swap_out = swap_outs
+            .iter_mut()
+            .find(|s| s.channel == *channel && s.status == SwapOutStatus::AwaitingConfirmation)
+            .ok_or(MutinyError::NotFound)?;
+        swap_out.status = SwapOutStatus::Declined;
+
+        self.storage.persist_swap_outs(swap_outs)
+    }
+
+    async fn execute_swap_out(&self, swap_out: &mut SwapOut) {
+        log_info!(
+            self.logger,
+            "Swapping out {} sats by closing channel {}",
+            swap_out.amount_sats,
+            swap_out.channel
+        );
+        let address = Some(swap_out.address.clone());
+        swap_out.status = match self
+            .close_channel(&swap_out.channel, address, false, false)
+            .await
+        {
+            Ok(_) => SwapOutStatus::Completed,
+            Err(e) => {
+                log_warn!(self.logger, "Failed to swap out: {e}");
+                SwapOutStatus::Failed {
+                    reason: e.to_string(),
+                }
+            }
+        };
+    }
+
+    /// Moves lightning funds above the swap out policy's ceiling to cold storage,
+    /// or asks the user to if the policy requires confirmation.
+    async fn check_swap_out(&self) -> Result<(), MutinyError> {
+        let Some(policy) = self.storage.get_swap_out_policy()? else {
+            return Ok(());
+        };
+
+        let mut swap_outs = self.storage.get_swap_outs()?;
+        // don't ask again until the user has answered
+        if swap_outs
+            .iter()
+            .any(|s| s.status == SwapOutStatus::AwaitingConfirmation)
+        {
+            return Ok(());
         }
 
-        // add any connected peers that weren't in our storage,
-        // likely new or inbound connections
-        let mut missing: Vec<MutinyPeer> = Vec::new();
-        for peer in connected_peers {
-            if !storage_peers.iter().any(|p| p.pubkey == peer) {
-                let new = MutinyPeer {
-                    pubkey: peer,
-                    connection_string: None,
-                    alias: None,
-                    color: None,
-                    label: None,
-                    is_connected: true,
+        // channels we already swapped out may still be closing
+        let closing: HashSet<OutPoint> = swap_outs
+            .iter()
+            .filter(|s| s.status == SwapOutStatus::Completed)
+            .map(|s| s.channel)
+            .collect();
+        let channels: Vec<MutinyChannel> = self
+            .list_channels()
+            .await?
+            .into_iter()
+            .filter(|c| c.outpoint.map_or(true, |o| !closing.contains(&o)))
+            .collect();
+
+        let balance: u64 = channels.iter().map(|c| c.balance).sum();
+        let excess_sats = balance.saturating_sub(policy.ceiling_sats);
+        if excess_sats == 0 {
+            return Ok(());
+        }
+
+        // skip channels that are still opening, scheduled to close,
+        // or that the policy has already tried to close
+        let scheduled = self.storage.get_scheduled_closes()?;
+        let candidates: Vec<(OutPoint, u64)> = channels
+            .iter()
+            .filter(|c| {
+                c.confirmations_required
+                    .map_or(true, |r| c.confirmations >= r)
+            })
+            .filter_map(|c| c.outpoint.map(|o| (o, c.balance)))
+            .filter(|(o, _)| !scheduled.iter().any(|s| s.outpoint == *o))
+            .filter(|(o, _)| !swap_outs.iter().any(|s| s.channel == *o))
+            .collect();
+        let Some((channel, amount_sats)) = pick_channel(&candidates, excess_sats) else {
+            return Ok(());
+        };
+
+        let mut swap_out = SwapOut {
+            channel,
+            amount_sats,
+            excess_sats,
+            address: policy.address,
+            created_at: utils::now().as_secs(),
+            status: SwapOutStatus::AwaitingConfirmation,
+        };
+        if policy.needs_confirmation(amount_sats, excess_sats) {
+            log_info!(
+                self.logger,
+                "Lightning balance is {excess_sats} sats above the ceiling, waiting for confirmation to swap out {amount_sats} sats"
+            );
+        } else {
+            self.execute_swap_out(&mut swap_out).await;
+        }
+
+        swap_outs.push(swap_out);
+        self.storage.persist_swap_outs(swap_outs)
+    }
+
+    /// Sweeps the batched outputs from closed channels for each node.
+    async fn sweep_pending_outputs(&self) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            node.sweep_pending_outputs().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the unpaid invoices that have expired on every node as failed.
+    async fn expire_invoices(&self) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            let expired = node.expire_invoices()?;
+            if expired > 0 {
+                log_debug!(self.logger, "Marked {expired} expired invoices as failed");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Keeps track of when each channel was last used and runs the enabled
+    /// channel rules. The rules act on a channel once, so a close that was
+    /// cancelled isn't scheduled again, failures are retried a day later.
+    async fn check_channel_rules(&self) -> Result<(), MutinyError> {
+        let channels = self.list_channels().await?;
+        let now = utils::now().as_secs();
+        let mut activity = self.storage.get_channel_activity()?;
+        if update_channel_activity(&mut activity, &channels, now) {
+            self.storage.set_channel_activity(activity.clone())?;
+        }
+
+        let rules = self.storage.get_channel_rules()?;
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let log = self.storage.get_channel_rule_log()?;
+        let matches = evaluate_rules(&rules, &channels, &activity, now)
+            .into_iter()
+            .filter(|m| m.enabled)
+            .filter(|m| !already_handled(&log, &m.outpoint, now));
+        for m in matches {
+            let res = match m.action.clone() {
+                // closes right away if there is no fee limit
+                ChannelAction::Close {
+                    max_fee_rate,
+                    address,
+                } => self
+                    .schedule_close(m.outpoint, max_fee_rate, Some(now), None, address)
+                    .await
+                    .map(|_| ()),
+            };
+
+            match res.as_ref() {
+                Ok(()) => log_info!(
+                    self.logger,
+                    "Channel rule \"{}\" scheduled a close of {}",
+                    m.rule,
+                    m.outpoint
+                ),
+                Err(e) => log_warn!(
+                    self.logger,
+                    "Channel rule \"{}\" failed on {}: {e}",
+                    m.rule,
+                    m.outpoint
+                ),
+            }
+            self.storage.log_rule_execution(RuleExecution {
+                rule: m.rule,
+                outpoint: m.outpoint,
+                action: m.action,
+                executed_at: now,
+                error: res.err().map(|e| e.to_string()),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes any scheduled channels that are ready to be closed.
+    async fn check_scheduled_closes(&self) -> Result<(), MutinyError> {
+        let closes = self.storage.get_scheduled_closes()?;
+        if closes.is_empty() {
+            return Ok(());
+        }
+
+        // fee estimates are in sat/kw, 250 kw per vbyte
+        let fee_rate = self.estimate_fee_normal() as f32 / 250.0;
+        let now = utils::now().as_secs();
+        let channels = self.list_channels().await?;
+
+        let mut done = HashSet::new();
+        for close in closes {
+            // the channel was closed some other way
+            if !channels.iter().any(|c| c.outpoint == Some(close.outpoint)) {
+                done.insert(close.outpoint);
+                continue;
+            }
+
+            if !close.is_ready(fee_rate, now) {
+                continue;
+            }
+
+            log_info!(
+                self.logger,
+                "Closing scheduled channel {} at {fee_rate} sat/vbyte",
+                close.outpoint
+            );
+            match self
+                .close_channel(&close.outpoint, close.address.clone(), false, false)
+                .await
+            {
+                Ok(_) => {
+                    done.insert(close.outpoint);
+                }
+                // keep it around to try again next time
+                Err(e) => log_warn!(self.logger, "Failed to close scheduled channel: {e}"),
+            }
+        }
+
+        if done.is_empty() {
+            return Ok(());
+        }
+
+        // re-read in case the schedule changed while we were closing
+        let mut closes = self.storage.get_scheduled_closes()?;
+        closes.retain(|c| !done.contains(&c.outpoint));
+        self.storage.persist_scheduled_closes(closes)
+    }
+
+    /// Makes the recurring payments that are due. Each payment is saved as soon
+    /// as it is made so a restart doesn't pay the same interval twice.
+    async fn check_recurring_payments(&self) -> Result<(), MutinyError> {
+        let payments = self.storage.get_recurring_payments()?;
+        let now = utils::now().as_secs();
+        if !payments.iter().any(|p| !p.due_payments(now).is_empty()) {
+            return Ok(());
+        }
+
+        let from_node = self
+            .list_nodes()
+            .await?
+            .first()
+            .copied()
+            .ok_or(MutinyError::NotFound)?;
+
+        for payment in payments {
+            for (amount_sats, intervals) in payment.due_payments(now) {
+                log_info!(
+                    self.logger,
+                    "Making recurring payment {} of {amount_sats} sats",
+                    payment.id
+                );
+                let res = match &payment.target {
+                    RecurringPaymentTarget::Keysend(pubkey) => self
+                        .keysend(
+                            &from_node,
+                            *pubkey,
+                            amount_sats,
+                            payment.labels.clone(),
+                            None,
+                            vec![],
+                        )
+                        .await
+                        .map(|_| ()),
+                    RecurringPaymentTarget::LnAddress(address) => self
+                        .pay_lnurl(&from_node, address, amount_sats, payment.labels.clone())
+                        .await
+                        .map(|_| ()),
                 };
-                missing.push(new);
+
+                // re-read in case it was changed or canceled while we were paying
+                let mut current = self.storage.get_recurring_payments()?;
+                let Some(stored) = current.iter_mut().find(|p| p.id == payment.id) else {
+                    break;
+                };
+                let failed = res.is_err();
+                match res {
+                    Ok(_) => stored.paid(intervals, now),
+                    // try again on the next check
+                    Err(e) => {
+                        log_warn!(self.logger, "Failed to make recurring payment: {e}");
+                        stored.last_error = Some(e.to_string());
+                    }
+                }
+                self.storage.persist_recurring_payments(current)?;
+                if failed {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the outputs from force closed channels that are not claimable yet,
+    /// along with the block heights at which they will be.
+    pub async fn get_pending_close_status(&self) -> Result<PendingCloseStatus, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let chain_tip = nodes
+            .values()
+            .map(|n| n.channel_manager.current_best_block().height())
+            .max()
+            .unwrap_or_default();
+        let outputs = nodes
+            .values()
+            .flat_map(|n| n.pending_close_outputs(chain_tip))
+            .collect();
+
+        Ok(PendingCloseStatus { chain_tip, outputs })
+    }
+
+    /// Lists all the channels for all the nodes in the node manager.
+    pub async fn list_channels(&self) -> Result<Vec<MutinyChannel>, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let channels: Vec<ChannelDetails> = nodes
+            .iter()
+            .flat_map(|(_, n)| n.channel_manager.list_channels())
+            .collect();
+
+        let mutiny_channels: Vec<MutinyChannel> =
+            channels.iter().map(MutinyChannel::from).collect();
+
+        Ok(mutiny_channels)
+    }
+
+    /// Returns the internal state of a channel for debugging.
+    /// The channel can be given by its channel id or user channel id, in hex.
+    pub async fn debug_channel(&self, channel_id: &str) -> Result<ChannelDebugInfo, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        nodes
+            .values()
+            .find_map(|n| n.debug_channel(channel_id))
+            .ok_or(MutinyError::NotFound)
+    }
+
+    /// Returns the proofs for all the times a counterparty broadcast a revoked
+    /// commitment transaction and we claimed their funds.
+    pub fn get_justice_proofs(&self) -> Result<Vec<JusticeProof>, MutinyError> {
+        self.storage.get_justice_proofs()
+    }
+
+    /// Turns the watchtower on or off. While it is on, it watches the channel monitors
+    /// in storage, like ones synced from the main device, and broadcasts justice
+    /// transactions if a counterparty broadcasts a revoked commitment transaction.
+    /// The setting is saved, so the watchtower starts again with the wallet.
+    pub async fn set_watchtower_enabled(&self, enabled: bool) -> Result<(), MutinyError> {
+        self.storage
+            .set_data(WATCHTOWER_ENABLED_KEY, enabled, None)?;
+        if enabled {
+            self.start_watchtower().await
+        } else {
+            if let Some(watchtower) = self.watchtower.lock().await.take() {
+                watchtower.stop();
+            }
+            Ok(())
+        }
+    }
+
+    /// Starts the watchtower if it was enabled and isn't running yet
+    pub async fn start_watchtower(&self) -> Result<(), MutinyError> {
+        if !is_watchtower_enabled(&self.storage)? {
+            return Ok(());
+        }
+
+        let mut watchtower = self.watchtower.lock().await;
+        if watchtower.is_some() {
+            return Ok(());
+        }
+
+        let new = Arc::new(Watchtower::with_esplora(
+            self.storage.clone(),
+            self.esplora.clone(),
+            self.logger.clone(),
+        )?);
+        Watchtower::start(new.clone());
+        *watchtower = Some(new);
+        Ok(())
+    }
+
+    /// Gets whether the watchtower is on, how many channels it watches
+    /// and the breaches it has detected.
+    pub async fn get_watchtower_status(&self) -> Result<WatchtowerStatus, MutinyError> {
+        let channel_count = self
+            .watchtower
+            .lock()
+            .await
+            .as_ref()
+            .map(|w| w.channel_count())
+            .unwrap_or(0);
+        Ok(WatchtowerStatus {
+            enabled: is_watchtower_enabled(&self.storage)?,
+            channel_count,
+            alerts: get_breach_alerts(&self.storage)?,
+        })
+    }
+
+    /// Exports the channel monitors of every node so a watchtower without the seed
+    /// or this wallet's storage can watch the channels, see [Watchtower::from_watch_data].
+    /// It should be exported again whenever channels are opened or make payments.
+    pub async fn export_watch_data(&self) -> Result<WatchData, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let mut monitors = vec![];
+        for node in nodes.values() {
+            for funding_txo in node.chain_monitor.list_monitors() {
+                let monitor = node
+                    .chain_monitor
+                    .get_monitor(funding_txo)
+                    .map_err(|_| MutinyError::NotFound)?;
+                monitors.push(WatchedMonitor::new(node._uuid.clone(), &*monitor));
+            }
+        }
+
+        Ok(WatchData { monitors })
+    }
+
+    async fn check_justice_proofs(&self) -> Result<(), MutinyError> {
+        let mut proofs = self.storage.get_justice_proofs()?;
+        let nodes = self.nodes.lock().await;
+        let mut changed = false;
+        for node in nodes.values() {
+            changed |= node.update_justice_proofs(&mut proofs);
+        }
+
+        if changed {
+            self.storage.persist_justice_proofs(proofs)?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the postmortems of force closed channels with how the close
+    /// resolved on-chain
+    async fn check_force_close_postmortems(&self) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            node.update_postmortems()?;
+        }
+
+        Ok(())
+    }
+
+    fn get_scb_key(&self) -> SecretKey {
+        let path = DerivationPath::from_str(SCB_ENCRYPTION_KEY_DERIVATION_PATH).unwrap();
+        let context = Secp256k1::new();
+
+        self.xprivkey
+            .derive_priv(&context, &path)
+            .unwrap()
+            .private_key
+    }
+
+    /// Creates a static channel backup for all the nodes in the node manager.
+    /// The backup is encrypted with the SCB key.
+    pub async fn create_static_channel_backup(&self) -> Result<EncryptedSCB, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let mut backups: HashMap<PublicKey, (NodeIndex, StaticChannelBackup)> = HashMap::new();
+        for (_, node) in nodes.iter() {
+            let scb = node.create_static_channel_backup()?;
+            backups.insert(node.pubkey, (node.node_index(), scb));
+        }
+
+        let peers = get_all_peers(&self.storage).unwrap_or_default();
+
+        let peer_connections = peers
+            .into_iter()
+            .filter_map(|(n, p)| p.connection_string.map(|str| (n.as_pubkey().unwrap(), str)))
+            .collect::<HashMap<_, _>>();
+
+        let scb = StaticChannelBackupStorage {
+            backups,
+            peer_connections,
+        };
+
+        // encrypt
+        let encryption_key = self.get_scb_key();
+        let scb = scb.encrypt(&encryption_key);
+        log_debug!(
+            self.logger,
+            "Created SCB with a size of {} bytes",
+            scb.encode().len()
+        );
+        Ok(scb)
+    }
+
+    /// Takes an encrypted static channel backup and recovers the channels from it.
+    /// If the backup is encrypted with a different key than the current key, it will fail.
+    pub async fn recover_from_static_channel_backup(
+        &self,
+        scb: EncryptedSCB,
+    ) -> Result<(), MutinyError> {
+        // decrypt
+        let encryption_key = self.get_scb_key();
+        let scb = scb.decrypt(&encryption_key)?;
+
+        // stop all nodes, todo stop in parallel
+        for node in self.nodes.lock().await.values() {
+            node.stop().await?;
+        }
+
+        for (pubkey, (node_index, backup)) in scb.backups {
+            // find the uuid if we have it, otherwise create a new one and save it
+            let uuid = {
+                let mut node_mutex = self.node_storage.lock().await;
+                let current = node_mutex
+                    .nodes
+                    .iter()
+                    .find(|(_, n)| *n == &node_index)
+                    .map(|(uuid, _)| uuid.clone());
+
+                match current {
+                    Some(uuid) => uuid,
+                    None => {
+                        let mut existing_nodes = self.storage.get_nodes()?;
+                        let new_uuid = Uuid::new_v4().to_string();
+                        existing_nodes
+                            .nodes
+                            .insert(new_uuid.clone(), node_index.clone());
+
+                        self.storage.insert_nodes(existing_nodes.clone())?;
+                        node_mutex.nodes = existing_nodes.nodes.clone();
+
+                        new_uuid
+                    }
+                }
+            };
+
+            // create a fresh instance of each node
+            let new_node = Node::new(
+                uuid,
+                &node_index,
+                self.xprivkey,
+                self.storage.clone(),
+                self.gossip_sync.clone(),
+                self.scorer.clone(),
+                self.chain.clone(),
+                self.fee_estimator.clone(),
+                self.wallet.clone(),
+                self.network,
+                &self.esplora,
+                &self.lsp_clients,
+                self.logger.clone(),
+                self.generation.clone(),
+                true,
+                self.validate_gossip,
+                self.cltv_config,
+                self.payment_subscriptions.clone(),
+                true,
+                #[cfg(target_arch = "wasm32")]
+                self.websocket_proxy_addr.clone(),
+            )
+            .await;
+
+            match new_node {
+                Ok(node) => {
+                    debug_assert!(node.node_index().child_index == node_index.child_index);
+                    log_info!(
+                        self.logger,
+                        "Recovering node {pubkey} from static channel backup"
+                    );
+                    node.recover_from_static_channel_backup(backup, &scb.peer_connections)
+                        .await?;
+                }
+                Err(_) => {
+                    log_error!(
+                        self.logger,
+                        "Could not find node with pubkey {pubkey} to recover from backup",
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists all the peers for all the nodes in the node manager.
+    pub async fn list_peers(&self) -> Result<Vec<MutinyPeer>, MutinyError> {
+        let peer_data = gossip::get_all_peers(&self.storage)?;
+
+        // get peers saved in storage
+        let mut storage_peers: Vec<MutinyPeer> = peer_data
+            .iter()
+            .map(|(node_id, metadata)| MutinyPeer {
+                // node id should be safe here
+                pubkey: PublicKey::from_slice(node_id.as_slice()).expect("Invalid pubkey"),
+                connection_string: metadata.connection_string.clone(),
+                alias: metadata.alias.clone(),
+                color: metadata.color.clone(),
+                label: metadata.label.clone(),
+                is_connected: false,
+            })
+            .collect();
+
+        let nodes = self.nodes.lock().await;
+
+        // get peers we are connected to
+        let connected_peers: Vec<PublicKey> = nodes
+            .iter()
+            .flat_map(|(_, n)| n.peer_manager.get_peer_node_ids())
+            .collect();
+
+        // correctly set is_connected
+        for peer in &mut storage_peers {
+            if connected_peers.contains(&peer.pubkey) {
+                peer.is_connected = true;
+            }
+        }
+
+        // add any connected peers that weren't in our storage,
+        // likely new or inbound connections
+        let mut missing: Vec<MutinyPeer> = Vec::new();
+        for peer in connected_peers {
+            if !storage_peers.iter().any(|p| p.pubkey == peer) {
+                let new = MutinyPeer {
+                    pubkey: peer,
+                    connection_string: None,
+                    alias: None,
+                    color: None,
+                    label: None,
+                    is_connected: true,
+                };
+                missing.push(new);
+            }
+        }
+
+        storage_peers.append(&mut missing);
+        storage_peers.sort();
+
+        Ok(storage_peers)
+    }
+
+    /// Checks whether or not the user is subscribed to Mutiny+.
+    ///
+    /// Returns None if there's no subscription at all.
+    /// Returns Some(u64) for their unix expiration timestamp, which may be in the
+    /// past or in the future, depending on whether or not it is currently active.
+    pub(crate) async fn check_subscribed(&self) -> Result<Option<u64>, MutinyError> {
+        if let Some(subscription_client) = self.subscription_client.clone() {
+            Ok(subscription_client.check_subscribed().await?)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Gets the subscription plans for Mutiny+ subscriptions
+    pub async fn get_subscription_plans(&self) -> Result<Vec<Plan>, MutinyError> {
+        if let Some(subscription_client) = self.subscription_client.clone() {
+            Ok(subscription_client.get_plans().await?)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// Subscribes to a Mutiny+ plan with a specific plan id.
+    ///
+    /// Returns a lightning invoice so that the plan can be paid for to start it.
+    pub async fn subscribe_to_plan(&self, id: u8) -> Result<MutinyInvoice, MutinyError> {
+        if let Some(subscription_client) = self.subscription_client.clone() {
+            Ok(Bolt11Invoice::from_str(&subscription_client.subscribe_to_plan(id).await?)?.into())
+        } else {
+            Err(MutinyError::SubscriptionClientNotConfigured)
+        }
+    }
+
+    /// Gets the current bitcoin price in USD.
+    pub async fn get_bitcoin_price(&self) -> Result<f32, MutinyError> {
+        let now = crate::utils::now();
+
+        let mut bitcoin_price_cache = self.bitcoin_price_cache.lock().await;
+
+        let (price, timestamp) = match bitcoin_price_cache.as_ref() {
+            Some((price, timestamp))
+                if *timestamp + Duration::from_secs(BITCOIN_PRICE_CACHE_SEC) > now =>
+            {
+                // Cache is not expired
+                (*price, *timestamp)
+            }
+            _ => {
+                // Cache is either expired or empty, fetch new price
+                match self.fetch_bitcoin_price().await {
+                    Ok(new_price) => (new_price, now),
+                    Err(e) => {
+                        // If fetching price fails, return the cached price (if any)
+                        if let Some((price, timestamp)) = bitcoin_price_cache.as_ref() {
+                            log_warn!(self.logger, "price api failed, returning cached price");
+                            (*price, *timestamp)
+                        } else {
+                            // If there is no cached price, return the error
+                            log_error!(self.logger, "no cached price and price api failed");
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        };
+
+        *bitcoin_price_cache = Some((price, timestamp));
+        Ok(price)
+    }
+
+    async fn fetch_bitcoin_price(&self) -> Result<f32, MutinyError> {
+        log_debug!(self.logger, "fetching new bitcoin price");
+
+        let body = cached_get(self.http_client.as_ref(), &self.storage, BITCOIN_PRICE_URL)
+            .await
+            .map_err(|_| MutinyError::BitcoinPriceError)?;
+
+        let response: CoingeckoResponse =
+            serde_json::from_str(&body).map_err(|_| MutinyError::BitcoinPriceError)?;
+
+        Ok(response.bitcoin.usd)
+    }
+
+    /// Sets the most to pay in routing fees on any payment.
+    /// Payments with a lower cap in their retry policy keep to their own cap.
+    pub async fn set_fee_cap(&self, fee_cap: FeeCap) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
+        self.storage.set_fee_cap(fee_cap)
+    }
+
+    /// Returns the most to pay in routing fees on any payment.
+    pub fn get_fee_cap(&self) -> Result<FeeCap, MutinyError> {
+        self.storage.get_fee_cap()
+    }
+
+    /// Sets how payments are split over multiple paths.
+    /// A payment's retry policy can turn splitting off for that payment.
+    pub async fn set_mpp_config(&self, config: MppConfig) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
+        self.storage.set_mpp_config(config)
+    }
+
+    /// Returns how payments are split over multiple paths.
+    pub fn get_mpp_config(&self) -> Result<MppConfig, MutinyError> {
+        self.storage.get_mpp_config()
+    }
+
+    /// Sets whether the user has opted in to uploading anonymized telemetry.
+    /// Metrics are always aggregated locally, they are only uploaded if opted in.
+    pub fn set_telemetry_opt_in(&self, opt_in: bool) -> Result<(), MutinyError> {
+        self.storage.set_telemetry_opt_in(opt_in)
+    }
+
+    /// Returns whether the user has opted in to uploading anonymized telemetry.
+    pub fn get_telemetry_opt_in(&self) -> Result<bool, MutinyError> {
+        self.storage.get_telemetry_opt_in()
+    }
+
+    /// Returns the anonymized summary of the locally aggregated metrics.
+    /// This is exactly what would be uploaded if the user opts in.
+    pub fn get_telemetry_summary(&self) -> Result<TelemetrySummary, MutinyError> {
+        Ok(self.storage.get_telemetry_stats()?.summary(self.network))
+    }
+
+    /// Uploads a telemetry summary if the user has opted in, a telemetry server
+    /// is configured, and we haven't uploaded one recently.
+    async fn upload_telemetry_if_necessary(&self) -> Result<(), MutinyError> {
+        let Some(url) = self.telemetry_url.as_ref() else {
+            return Ok(());
+        };
+
+        if !self.storage.get_telemetry_opt_in()? {
+            return Ok(());
+        }
+
+        let stats = self.storage.get_telemetry_stats()?;
+        let now = utils::now().as_secs();
+        if stats.last_upload + TELEMETRY_UPLOAD_INTERVAL_SECS > now {
+            return Ok(());
+        }
+
+        // clear the stats before uploading so samples recorded during the
+        // upload aren't dropped, and put them back if it fails
+        let stats = self.storage.take_telemetry_stats(now)?;
+        let summary = stats.summary(self.network);
+        if let Err(e) = upload_telemetry_summary(self.http_client.as_ref(), url, &summary).await {
+            self.storage.restore_telemetry_stats(stats)?;
+            return Err(e);
+        }
+        log_debug!(self.logger, "Uploaded telemetry summary");
+
+        Ok(())
+    }
+
+    /// Returns a summary of the wallet state for crash reports.
+    /// This does not block, if the nodes are locked it will return None.
+    pub fn get_state_summary(&self) -> Option<StateSummary> {
+        let nodes = self.nodes.try_lock()?;
+        let mut channel_count = 0;
+        let mut usable_channel_count = 0;
+        let mut peer_count = 0;
+        for node in nodes.values() {
+            channel_count += node.channel_manager.list_channels().len();
+            usable_channel_count += node.channel_manager.list_usable_channels().len();
+            peer_count += node.peer_manager.get_peer_node_ids().len();
+        }
+
+        Some(StateSummary {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            network: self.network,
+            node_count: nodes.len(),
+            channel_count,
+            usable_channel_count,
+            peer_count,
+        })
+    }
+
+    /// Saves a crash report for the given panic to storage.
+    /// This is meant to be called from a panic hook, so it only uses the panic info,
+    /// the logs already in memory and the state summary from the last sync.
+    /// It never calls into the nodes and never blocks on a lock.
+    pub fn save_crash_report(&self, info: &PanicInfo) -> Result<(), MutinyError> {
+        let state = self
+            .state_summary
+            .try_read()
+            .ok()
+            .and_then(|state| state.clone());
+        let report = CrashReport::new(
+            info,
+            utils::now().as_secs(),
+            self.logger.get_recent_logs(),
+            state,
+        );
+        self.storage.persist_crash_report(report)
+    }
+
+    /// Returns the saved crash reports, so they can be shared after the app recovers.
+    pub fn get_crash_reports(&self) -> Result<Vec<CrashReport>, MutinyError> {
+        self.storage.get_crash_reports()
+    }
+
+    /// Deletes all of the saved crash reports.
+    pub fn clear_crash_reports(&self) -> Result<(), MutinyError> {
+        self.storage.clear_crash_reports()
+    }
+
+    /// Sets up a dead-man switch that sweeps the on-chain wallet to the heir's
+    /// address unless [NodeManager::inheritance_check_in] is called within the interval.
+    ///
+    /// The sweep transaction can't be mined until the plan's unlock time. If the heir's
+    /// npub is given, every new sweep transaction is sent to them so their wallet
+    /// can broadcast it, otherwise the sweep in the plan should be given to them.
+    pub async fn setup_inheritance(
+        &self,
+        heir_address: Address,
+        heir_npub: Option<XOnlyPublicKey>,
+        check_in_interval_secs: u64,
+        fee_rate: Option<f32>,
+    ) -> Result<InheritancePlan, MutinyError> {
+        if !heir_address.is_valid_for_network(self.network) {
+            return Err(MutinyError::IncorrectNetwork(heir_address.network));
+        }
+        if check_in_interval_secs == 0 {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        self.invalidate_inheritance_tx(fee_rate).await?;
+        let plan = self.create_inheritance_plan(
+            heir_address,
+            heir_npub,
+            check_in_interval_secs,
+            fee_rate,
+        )?;
+        self.storage.persist_inheritance_plan(plan.clone())?;
+        self.storage.clear_inheritance_reminders()?;
+        Ok(plan)
+    }
+
+    /// Checks in to push back the inheritance sweep. The previous sweep
+    /// transaction is invalidated and a new one is signed with a later unlock time.
+    ///
+    /// This spends an input of each sweep signed since the last check in back
+    /// to the wallet, so checking in costs an on-chain fee.
+    pub async fn inheritance_check_in(
+        &self,
+        fee_rate: Option<f32>,
+    ) -> Result<InheritancePlan, MutinyError> {
+        let old = self
+            .storage
+            .get_inheritance_plan()?
+            .ok_or(MutinyError::NotFound)?;
+
+        self.invalidate_inheritance_tx(fee_rate).await?;
+        let plan = self.create_inheritance_plan(
+            old.heir_address,
+            old.heir_npub,
+            old.check_in_interval_secs,
+            fee_rate,
+        )?;
+        self.storage.persist_inheritance_plan(plan.clone())?;
+        self.storage.clear_inheritance_reminders()?;
+        log_info!(
+            self.logger,
+            "Inheritance check in, new unlock time: {}",
+            plan.unlock_time
+        );
+        Ok(plan)
+    }
+
+    /// Cancels the inheritance plan, invalidating the signed sweep transaction.
+    pub async fn cancel_inheritance(&self, fee_rate: Option<f32>) -> Result<(), MutinyError> {
+        self.invalidate_inheritance_tx(fee_rate).await?;
+        self.storage.delete_inheritance_plan()?;
+        self.storage.clear_inheritance_reminders()
+    }
+
+    pub fn get_inheritance_plan(&self) -> Result<Option<InheritancePlan>, MutinyError> {
+        self.storage.get_inheritance_plan()
+    }
+
+    /// Returns the reminders to check in that have been emitted
+    /// since they were last cleared.
+    pub fn get_inheritance_reminders(&self) -> Result<Vec<InheritanceReminder>, MutinyError> {
+        self.storage.get_inheritance_reminders()
+    }
+
+    pub fn clear_inheritance_reminders(&self) -> Result<(), MutinyError> {
+        self.storage.clear_inheritance_reminders()
+    }
+
+    /// Returns the sweep transactions other users have sent us as their heir.
+    pub fn get_held_sweeps(&self) -> Result<Vec<HeldSweep>, MutinyError> {
+        self.storage.get_held_sweeps()
+    }
+
+    /// Adds an alert that fires an event when its condition is met.
+    /// Alerts are checked by the background sync.
+    pub fn add_alert_rule(&self, condition: AlertCondition) -> Result<AlertRule, MutinyError> {
+        condition.validate()?;
+
+        let rule = AlertRule::new(condition, utils::now().as_secs());
+        let mut rules = self.storage.get_alert_rules()?;
+        rules.push(rule.clone());
+        self.storage.persist_alert_rules(rules)?;
+
+        Ok(rule)
+    }
+
+    pub fn remove_alert_rule(&self, id: &str) -> Result<(), MutinyError> {
+        let mut rules = self.storage.get_alert_rules()?;
+        let len = rules.len();
+        rules.retain(|r| r.id != id);
+        if rules.len() == len {
+            return Err(MutinyError::NotFound);
+        }
+
+        self.storage.persist_alert_rules(rules)
+    }
+
+    pub fn list_alert_rules(&self) -> Result<Vec<AlertRule>, MutinyError> {
+        self.storage.get_alert_rules()
+    }
+
+    /// Returns the alerts that have fired since they were last cleared.
+    pub fn get_alert_events(&self) -> Result<Vec<AlertEvent>, MutinyError> {
+        self.storage.get_alert_events()
+    }
+
+    pub fn clear_alert_events(&self) -> Result<(), MutinyError> {
+        self.storage.clear_alert_events()
+    }
+
+    /// Gathers what the alert rules are checked against.
+    async fn get_alert_metrics(&self, now: u64) -> Result<AlertMetrics, MutinyError> {
+        let balance = self.get_balance().await?;
+        let balance_sats = balance.confirmed + balance.unconfirmed + balance.lightning;
+
+        let mut payments_succeeded = 0;
+        let mut payments_failed = 0;
+        let mut offline = vec![];
+        {
+            let nodes = self.nodes.lock().await;
+            for node in nodes.values() {
+                let recent = node
+                    .persister
+                    .list_payment_info(false)?
+                    .into_iter()
+                    .filter(|(_, i)| i.last_update + FAILURE_RATE_WINDOW_SECS >= now);
+                for (_, info) in recent {
+                    match info.status {
+                        HTLCStatus::Succeeded => payments_succeeded += 1,
+                        HTLCStatus::Failed => payments_failed += 1,
+                        _ => {}
+                    }
+                }
+
+                // ready channels that aren't usable have an offline peer
+                offline.extend(
+                    node.channel_manager
+                        .list_channels()
+                        .iter()
+                        .filter(|c| c.is_channel_ready && !c.is_usable)
+                        .map(|c| c.user_channel_id.to_hex()),
+                );
+            }
+        }
+
+        let mut offline_since = self.storage.get_channels_offline_since()?;
+        let longest_offline_secs = update_offline_since(&mut offline_since, &offline, now);
+        self.storage.set_channels_offline_since(offline_since)?;
+
+        Ok(AlertMetrics {
+            balance_sats,
+            payments_succeeded,
+            payments_failed,
+            longest_offline_secs,
+        })
+    }
+
+    /// Checks the alert rules and records an event for each one that fired.
+    async fn check_alerts(&self) -> Result<(), MutinyError> {
+        let mut rules = self.storage.get_alert_rules()?;
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        let now = utils::now().as_secs();
+        let metrics = self.get_alert_metrics(now).await?;
+        let events: Vec<AlertEvent> = rules
+            .iter_mut()
+            .filter_map(|r| r.evaluate(&metrics, now))
+            .collect();
+        for event in events.iter() {
+            log_warn!(self.logger, "Alert {}: {}", event.rule_id, event.message);
+        }
+
+        // always persist, rules that stopped being met can fire again
+        self.storage.persist_alert_rules(rules)?;
+        if !events.is_empty() {
+            self.storage.push_alert_events(events)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the receipt for a settled payment, generating it if it doesn't exist yet.
+    /// Payments that settled a while ago won't have a fiat value.
+    pub async fn get_receipt(&self, hash: &sha256::Hash) -> Result<Receipt, MutinyError> {
+        if let Some(receipt) = self.storage.get_receipt(hash)? {
+            return Ok(receipt);
+        }
+
+        let invoice = self.get_invoice_by_hash(hash).await?;
+        let now = utils::now().as_secs();
+        let btc_price = self.get_bitcoin_price().await.ok();
+        let receipt =
+            Receipt::from_invoice(&invoice, btc_price, now).ok_or(MutinyError::NotFound)?;
+        self.storage.persist_receipt(receipt.clone())?;
+
+        Ok(receipt)
+    }
+
+    /// Lists the receipts that have been generated, newest first
+    pub fn list_receipts(&self) -> Result<Vec<Receipt>, MutinyError> {
+        self.storage.list_receipts()
+    }
+
+    /// Creates a proof of payment for a settled outgoing payment. With a message,
+    /// the proof is also signed by the node that paid, tying the payment to it.
+    pub async fn create_payment_proof(
+        &self,
+        hash: &sha256::Hash,
+        message: Option<String>,
+    ) -> Result<PaymentProof, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let (node, invoice) = nodes
+            .values()
+            .find_map(|n| n.get_invoice_by_hash(hash).ok().map(|i| (n, i)))
+            .ok_or(MutinyError::NotFound)?;
+
+        if invoice.inbound || !invoice.paid {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        // keysends have no invoice to prove anything with
+        let bolt11 = invoice.bolt11.ok_or(MutinyError::InvalidArgumentsError)?;
+        let preimage: [u8; 32] = invoice
+            .preimage
+            .as_deref()
+            .and_then(|p| FromHex::from_hex(p).ok())
+            .ok_or(MutinyError::NotFound)?;
+
+        let secret_key = message
+            .as_ref()
+            .map(|_| node.keys_manager.get_node_secret_key());
+        let payer = secret_key
+            .as_ref()
+            .zip(message)
+            .map(|(sk, message)| (sk, node.pubkey, message));
+        PaymentProof::new(bolt11, preimage, payer)
+    }
+
+    /// Verifies a payment proof, whichever wallet made it
+    pub fn verify_payment_proof(
+        &self,
+        proof: &PaymentProof,
+    ) -> Result<VerifiedPaymentProof, MutinyError> {
+        if proof.invoice.network() != self.network {
+            return Err(MutinyError::IncorrectNetwork(proof.invoice.network()));
+        }
+
+        payment_proof::verify_payment_proof(proof)
+    }
+
+    /// Stops retrying payments that are past their retry policy's timeout
+    /// Lists the payments our LSP held for us while we were offline
+    pub fn list_pending_receives(&self) -> Result<Vec<PendingReceive>, MutinyError> {
+        self.storage.list_pending_receives()
+    }
+
+    /// Marks the payments the LSP stopped holding for us as expired
+    fn check_pending_receives(&self) -> Result<(), MutinyError> {
+        let expired = self
+            .storage
+            .expire_pending_receives(utils::now().as_secs())?;
+        if expired > 0 {
+            log_info!(
+                self.logger,
+                "{expired} held payments expired before we took them"
+            );
+        }
+        Ok(())
+    }
+
+    async fn check_payment_retries(&self) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            node.check_payment_retries()?;
+        }
+
+        Ok(())
+    }
+
+    /// Generates receipts for payments that just settled,
+    /// so their fiat value is priced close to when they settled.
+    async fn check_receipts(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+        let mut settled = vec![];
+        for invoice in self.list_invoices().await? {
+            let recent = now.saturating_sub(invoice.last_updated) <= RECEIPT_FIAT_WINDOW_SECS;
+            if !invoice.paid || !recent {
+                continue;
+            }
+            if self.storage.get_receipt(&invoice.payment_hash)?.is_none() {
+                settled.push(invoice);
+            }
+        }
+
+        if settled.is_empty() {
+            return Ok(());
+        }
+
+        let btc_price = match self.get_bitcoin_price().await {
+            Ok(price) => Some(price),
+            Err(e) => {
+                log_warn!(self.logger, "Failed to get bitcoin price for receipts: {e}");
+                None
+            }
+        };
+        for invoice in settled {
+            if let Some(receipt) = Receipt::from_invoice(&invoice, btc_price, now) {
+                self.storage.persist_receipt(receipt)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_inheritance_plan(
+        &self,
+        heir_address: Address,
+        heir_npub: Option<XOnlyPublicKey>,
+        check_in_interval_secs: u64,
+        fee_rate: Option<f32>,
+    ) -> Result<InheritancePlan, MutinyError> {
+        let now = utils::now().as_secs();
+        let sweep_tx =
+            self.sign_inheritance_sweep(&heir_address, now + check_in_interval_secs, fee_rate)?;
+
+        Ok(InheritancePlan::new(
+            heir_address,
+            heir_npub,
+            check_in_interval_secs,
+            now,
+            sweep_tx,
+        ))
+    }
+
+    /// Signs a transaction sweeping the whole wallet to the heir that can't be mined
+    /// before the unlock time, `None` if the wallet is empty.
+    fn sign_inheritance_sweep(
+        &self,
+        heir_address: &Address,
+        unlock_time: u64,
+        fee_rate: Option<f32>,
+    ) -> Result<Option<Transaction>, MutinyError> {
+        let unlock_time: u32 = unlock_time
+            .try_into()
+            .map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+        // nothing to sweep if the wallet is empty
+        if self.wallet.list_utxos()?.is_empty() {
+            return Ok(None);
+        }
+
+        let psbt = self.wallet.create_timelocked_sweep_psbt(
+            heir_address.script_pubkey(),
+            unlock_time,
+            fee_rate,
+        )?;
+        Ok(Some(psbt.extract_tx()))
+    }
+
+    /// Signs the inheritance sweep again if the wallet has received or spent funds
+    /// since it was signed, so it keeps sweeping the whole wallet. The unlock time
+    /// stays the same, the old sweep is remembered so checking in invalidates it too.
+    async fn refresh_inheritance_sweep(&self) -> Result<(), MutinyError> {
+        let Some(mut plan) = self.storage.get_inheritance_plan()? else {
+            return Ok(());
+        };
+
+        let utxos: HashSet<OutPoint> = self
+            .wallet
+            .list_utxos()?
+            .into_iter()
+            .map(|u| u.outpoint)
+            .collect();
+        if !plan.sweep_outdated(&utxos) {
+            return Ok(());
+        }
+
+        let sweep_tx = self.sign_inheritance_sweep(&plan.heir_address, plan.unlock_time, None)?;
+        log_info!(
+            self.logger,
+            "Wallet utxos changed, signed a new inheritance sweep: {:?}",
+            sweep_tx.as_ref().map(|tx| tx.txid())
+        );
+        plan.set_sweep_tx(sweep_tx);
+        self.storage.persist_inheritance_plan(plan)
+    }
+
+    /// Broadcasts our own inheritance sweep and the sweeps we hold as an heir
+    /// once they have unlocked.
+    async fn broadcast_unlocked_inheritance_sweeps(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+
+        if let Some(plan) = self.storage.get_inheritance_plan()? {
+            // once it is broadcast the wallet has no utxos left and the sweep is
+            // refreshed to `None`, so this only broadcasts it once
+            if let Some(tx) = plan.sweep_tx.filter(|_| now >= plan.unlock_time) {
+                let txid = tx.txid();
+                match self.wallet.broadcast_transaction(tx).await {
+                    Ok(_) => log_warn!(
+                        self.logger,
+                        "Inheritance sweep has unlocked, broadcast {txid} to the heir"
+                    ),
+                    Err(e) => log_warn!(
+                        self.logger,
+                        "Could not broadcast inheritance sweep {txid} yet: {e}"
+                    ),
+                }
             }
         }
 
-        storage_peers.append(&mut missing);
-        storage_peers.sort();
+        for sweep in self.storage.get_held_sweeps()? {
+            if sweep.broadcast_at.is_some() || now < sweep.unlock_time {
+                continue;
+            }
 
-        Ok(storage_peers)
+            let txid = sweep.sweep_tx.txid();
+            // the timelock is checked against the median time of the last blocks,
+            // so this can be rejected for a while after the unlock time
+            match self.esplora.broadcast(&sweep.sweep_tx).await {
+                Ok(_) => {
+                    log_info!(
+                        self.logger,
+                        "Broadcast inheritance sweep {txid} from {}",
+                        sweep.owner
+                    );
+                    self.storage.mark_held_sweep_broadcast(txid, now)?;
+                }
+                Err(e) => log_warn!(
+                    self.logger,
+                    "Could not broadcast inheritance sweep {txid} yet: {e}"
+                ),
+            }
+        }
+
+        Ok(())
     }
 
-    /// Checks whether or not the user is subscribed to Mutiny+.
-    ///
-    /// Returns None if there's no subscription at all.
-    /// Returns Some(u64) for their unix expiration timestamp, which may be in the
-    /// past or in the future, depending on whether or not it is currently active.
-    pub(crate) async fn check_subscribed(&self) -> Result<Option<u64>, MutinyError> {
-        if let Some(subscription_client) = self.subscription_client.clone() {
-            Ok(subscription_client.check_subscribed().await?)
-        } else {
-            Ok(None)
+    /// Records that the heir has been sent the sweep transaction with the given txid.
+    /// If the sweep was signed again in the meantime, the new one still needs to be sent.
+    pub fn mark_inheritance_sweep_delivered(&self, txid: Txid) -> Result<(), MutinyError> {
+        let Some(mut plan) = self.storage.get_inheritance_plan()? else {
+            return Ok(());
+        };
+
+        if plan.sweep_tx.as_ref().map(|tx| tx.txid()) == Some(txid) {
+            plan.delivered = true;
+            self.storage.persist_inheritance_plan(plan)?;
         }
+
+        Ok(())
     }
 
-    /// Gets the subscription plans for Mutiny+ subscriptions
-    pub async fn get_subscription_plans(&self) -> Result<Vec<Plan>, MutinyError> {
-        if let Some(subscription_client) = self.subscription_client.clone() {
-            Ok(subscription_client.get_plans().await?)
-        } else {
-            Ok(vec![])
+    /// Spends an input of every sweep signed since the last check in back to
+    /// the wallet, so none of the sweeps the heir may have can ever be mined.
+    async fn invalidate_inheritance_tx(&self, fee_rate: Option<f32>) -> Result<(), MutinyError> {
+        let Some(plan) = self.storage.get_inheritance_plan()? else {
+            return Ok(());
+        };
+
+        let utxos: HashSet<OutPoint> = self
+            .wallet
+            .list_utxos()?
+            .into_iter()
+            .map(|u| u.outpoint)
+            .collect();
+        // sweeps with a spent input are already invalid
+        let outpoints = plan.outpoints_to_invalidate(&utxos);
+        if outpoints.is_empty() {
+            return Ok(());
         }
+
+        let txid = self.wallet.spend_to_self(&outpoints, fee_rate).await?;
+        log_info!(
+            self.logger,
+            "Invalidated inheritance sweeps with transaction {txid}"
+        );
+        Ok(())
     }
 
-    /// Subscribes to a Mutiny+ plan with a specific plan id.
-    ///
-    /// Returns a lightning invoice so that the plan can be paid for to start it.
-    pub async fn subscribe_to_plan(&self, id: u8) -> Result<MutinyInvoice, MutinyError> {
-        if let Some(subscription_client) = self.subscription_client.clone() {
-            Ok(Bolt11Invoice::from_str(&subscription_client.subscribe_to_plan(id).await?)?.into())
-        } else {
-            Err(MutinyError::SubscriptionClientNotConfigured)
+    /// Emits a reminder if the inheritance sweep is about to unlock.
+    fn check_inheritance_reminders(&self) -> Result<(), MutinyError> {
+        let Some(mut plan) = self.storage.get_inheritance_plan()? else {
+            return Ok(());
+        };
+
+        if let Some(reminder) = plan.next_reminder(utils::now().as_secs()) {
+            log_warn!(
+                self.logger,
+                "Inheritance sweep unlocks in {} seconds, check in to push it back",
+                reminder.seconds_remaining
+            );
+            self.storage.push_inheritance_reminder(reminder)?;
+            self.storage.persist_inheritance_plan(plan)?;
         }
+
+        Ok(())
     }
 
-    /// Gets the current bitcoin price in USD.
-    pub async fn get_bitcoin_price(&self) -> Result<f32, MutinyError> {
-        let now = crate::utils::now();
+    /// Sets how long to keep the full details of old payments and channels.
+    pub async fn set_retention_policy(&self, policy: RetentionPolicy) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
+        self.storage.set_retention_policy(policy)
+    }
 
-        let mut bitcoin_price_cache = self.bitcoin_price_cache.lock().await;
+    /// Gets how long the full details of old payments and channels are kept.
+    pub fn get_retention_policy(&self) -> Result<RetentionPolicy, MutinyError> {
+        self.storage.get_retention_policy()
+    }
 
-        let (price, timestamp) = match bitcoin_price_cache.as_ref() {
-            Some((price, timestamp))
-                if *timestamp + Duration::from_secs(BITCOIN_PRICE_CACHE_SEC) > now =>
-            {
-                // Cache is not expired
-                (*price, *timestamp)
-            }
-            _ => {
-                // Cache is either expired or empty, fetch new price
-                match self.fetch_bitcoin_price().await {
-                    Ok(new_price) => (new_price, now),
-                    Err(e) => {
-                        // If fetching price fails, return the cached price (if any)
-                        if let Some((price, timestamp)) = bitcoin_price_cache.as_ref() {
-                            log_warn!(self.logger, "price api failed, returning cached price");
-                            (*price, *timestamp)
-                        } else {
-                            // If there is no cached price, return the error
-                            log_error!(self.logger, "no cached price and price api failed");
-                            return Err(e);
-                        }
-                    }
-                }
+    /// Drops the full details of payments and channels that are older
+    /// than the retention policy allows, keeping a summary of each.
+    pub async fn prune_old_data(&self) -> Result<PruneSummary, MutinyError> {
+        let now = utils::now().as_secs();
+        let mut summary = PruneSummary::default();
+        if let Some(cutoff) = self.storage.get_retention_policy()?.cutoff(now) {
+            let nodes = self.nodes.lock().await;
+            for node in nodes.values() {
+                let pruned = node.persister.prune_details(cutoff)?;
+                summary.payments += pruned.payments;
+                summary.channel_closures += pruned.channel_closures;
             }
-        };
+        }
 
-        *bitcoin_price_cache = Some((price, timestamp));
-        Ok(price)
+        self.storage.set_last_prune_time(now)?;
+        Ok(summary)
     }
 
-    async fn fetch_bitcoin_price(&self) -> Result<f32, MutinyError> {
-        log_debug!(self.logger, "fetching new bitcoin price");
+    /// Runs the pruner if it hasn't been run in the last day.
+    async fn prune_if_necessary(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+        let last_prune = self.storage.get_last_prune_time()?.unwrap_or(0);
+        if now < last_prune + PRUNE_INTERVAL_SECS {
+            return Ok(());
+        }
 
-        let client = Client::builder()
-            .build()
-            .map_err(|_| MutinyError::BitcoinPriceError)?;
+        let summary = self.prune_old_data().await?;
+        log_info!(
+            self.logger,
+            "Pruned {} payments and {} channel closures",
+            summary.payments,
+            summary.channel_closures
+        );
+        Ok(())
+    }
 
-        let resp = client
-            .get("https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd")
-            .send()
-            .await
-            .map_err(|_| MutinyError::BitcoinPriceError)?;
+    /// Saves the current settings, labels and contacts as a restore point.
+    /// Restore points are also created once a day in the background.
+    pub fn create_restore_point(&self) -> Result<RestorePointInfo, MutinyError> {
+        self.storage.create_restore_point(utils::now().as_secs())
+    }
 
-        let response: CoingeckoResponse = resp
-            .error_for_status()
-            .map_err(|_| MutinyError::BitcoinPriceError)?
-            .json()
-            .await
-            .map_err(|_| MutinyError::BitcoinPriceError)?;
+    /// Lists the saved restore points, newest first.
+    pub fn list_restore_points(&self) -> Result<Vec<RestorePointInfo>, MutinyError> {
+        self.storage.list_restore_points()
+    }
 
-        Ok(response.bitcoin.usd)
+    /// Rolls the settings, labels and contacts back to the given restore point.
+    /// Channels and payments are not affected.
+    ///
+    /// The current state is saved as a new restore point first,
+    /// so the restore can be undone.
+    pub async fn restore(&self, restore_point_id: &str) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
+        self.storage
+            .restore(restore_point_id, utils::now().as_secs())
+    }
+
+    fn create_restore_point_if_necessary(&self) -> Result<(), MutinyError> {
+        let now = utils::now().as_secs();
+        let last = self
+            .storage
+            .list_restore_points()?
+            .first()
+            .map(|p| p.created_at)
+            .unwrap_or(0);
+        if now >= last + RESTORE_POINT_INTERVAL_SECS {
+            self.storage.create_restore_point(now)?;
+        }
+
+        Ok(())
     }
 
     /// Retrieves the logs from storage.
@@ -2286,8 +6941,25 @@ impl<S: MutinyStorage> NodeManager<S> {
         logger.get_logs(&storage)
     }
 
+    /// Gets the size of the network graph and counters for the gossip
+    /// our peers have sent us, including how much was dropped as duplicate or rate limited.
+    pub async fn graph_stats(&self) -> GraphStats {
+        let mut gossip = GossipStats::default();
+        for node in self.nodes.lock().await.values() {
+            gossip.add(&node.gossip_stats());
+        }
+
+        let graph = self.gossip_sync.network_graph().read_only();
+        GraphStats {
+            channels: graph.channels().len(),
+            nodes: graph.nodes().len(),
+            gossip,
+        }
+    }
+
     /// Resets the scorer and network graph. This can be useful if you get stuck in a bad state.
     pub async fn reset_router(&self) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
         // if we're not connected to the db, start it up
         let needs_db_connection = !self.storage.clone().connected().unwrap_or(true);
         if needs_db_connection {
@@ -2310,6 +6982,7 @@ impl<S: MutinyStorage> NodeManager<S> {
     ///
     /// This can be useful if you get stuck in a bad state.
     pub async fn reset_onchain_tracker(&self) -> Result<(), MutinyError> {
+        let _queue = self.queue_command().await;
         // if we're not connected to the db, start it up
         let needs_db_connection = !self.storage.clone().connected().unwrap_or(true);
         if needs_db_connection {
@@ -2328,6 +7001,14 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(())
     }
 
+    /// Rescans the on-chain wallet's history starting at the given block height or
+    /// unix timestamp, with a larger gap limit than a regular sync.
+    ///
+    /// This is for restoring an old seed whose history is missing after the restore.
+    pub async fn rescan_from(&self, height_or_timestamp: u64) -> Result<(), MutinyError> {
+        self.wallet.rescan_from(height_or_timestamp).await
+    }
+
     /// Exports the current state of the node manager to a json object.
     pub async fn export_json(storage: S) -> Result<Value, MutinyError> {
         let needs_db_connection = !storage.clone().connected().unwrap_or(true);
@@ -2335,17 +7016,24 @@ impl<S: MutinyStorage> NodeManager<S> {
             storage.clone().start().await?;
         }
 
-        // get all the data from storage, scanning with prefix "" will get all keys
-        let map = storage.scan("", None)?;
-        let serde_map = serde_json::map::Map::from_iter(map.into_iter().filter(|(k, _)| {
+        // get all the keys from storage, scanning with prefix "" will get all keys
+        let keys = storage.scan_keys("", None)?;
+        let mut serde_map = serde_json::map::Map::with_capacity(keys.len());
+        for key in keys {
             // filter out logs and network graph
             // these are really big and not needed for export
             // filter out device id so a new one is generated
-            !matches!(
-                k.as_str(),
+            if matches!(
+                key.as_str(),
                 LOGGING_KEY | NETWORK_GRAPH_KEY | PROB_SCORER_KEY | DEVICE_ID_KEY
-            )
-        }));
+            ) {
+                continue;
+            }
+            // values in the binary format are exported as json
+            if let Some(value) = storage.get_data::<Value>(&key)? {
+                serde_map.insert(key, value);
+            }
+        }
 
         // shut back down after reading if it was already closed
         if needs_db_connection {
@@ -2435,7 +7123,11 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
         &node_manager.esplora,
         &node_manager.lsp_clients,
         node_manager.logger.clone(),
+        node_manager.generation.clone(),
         node_manager.do_not_connect_peers,
+        node_manager.validate_gossip,
+        node_manager.cltv_config,
+        node_manager.payment_subscriptions.clone(),
         false,
         #[cfg(target_arch = "wasm32")]
         node_manager.websocket_proxy_addr.clone(),
@@ -2461,24 +7153,62 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
     })
 }
 
+/// Picks the node to pay each amount from so that no node pays more than its
+/// outbound capacity. Bigger payments are placed first, each on the node with
+/// the most capacity left. Returns `None` for amounts no node can cover.
+fn assign_batch_payments(amounts: &[Option<u64>], capacities: &[u64]) -> Vec<Option<usize>> {
+    let mut remaining = capacities.to_vec();
+    let mut assigned = vec![None; amounts.len()];
+
+    let mut order: Vec<usize> = (0..amounts.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(amounts[*i]));
+    for i in order {
+        let Some(amount) = amounts[i] else {
+            continue;
+        };
+        let best = remaining
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, capacity)| **capacity)
+            .filter(|(_, capacity)| **capacity >= amount)
+            .map(|(node, _)| node);
+        if let Some(node) = best {
+            remaining[node] -= amount;
+            assigned[i] = Some(node);
+        }
+    }
+
+    assigned
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         encrypt::encryption_key_from_pass,
         nodemanager::{
-            ActivityItem, ChannelClosure, MutinyInvoice, NodeManager, TransactionDetails,
+            assign_batch_payments, ActivityItem, ChannelClosure, CltvConfig, ConnectionInfo,
+            CustomTlv, FailedPath, ForceClosePostmortem, ForceCloseReason, MutinyInvoice,
+            NodeManager, PaymentFailure, PendingCloseOutput, PendingCloseOutputKind,
+            TransactionDetails, MAX_FAILED_PATHS, ORDER_ID_TLV_KEY,
         },
     };
-    use crate::{keymanager::generate_seed, MutinyWalletConfig};
+    use crate::{keymanager::generate_seed, node::default_user_config, MutinyWalletConfig};
+    use crate::{ldkstorage::MutinyNodePersister, logging::MutinyLogger};
     use bdk::chain::ConfirmationTime;
     use bitcoin::hashes::hex::{FromHex, ToHex};
     use bitcoin::hashes::{sha256, Hash};
     use bitcoin::secp256k1::PublicKey;
     use bitcoin::util::bip32::ExtendedPrivKey;
-    use bitcoin::{Network, PackedLockTime, Transaction, TxOut, Txid};
+    use bitcoin::{Network, OutPoint, PackedLockTime, Transaction, TxOut, Txid};
+    use lightning::chain::channelmonitor::Balance;
+    use lightning::events::{ClosureReason, PathFailure};
+    use lightning::ln::channelmanager::{MIN_CLTV_EXPIRY_DELTA, MIN_FINAL_CLTV_EXPIRY_DELTA};
     use lightning::ln::PaymentHash;
+    use lightning::routing::gossip::NetworkUpdate;
+    use lightning::util::string::UntrustedString;
     use lightning_invoice::Bolt11Invoice;
     use std::str::FromStr;
+    use std::sync::Arc;
 
     use crate::test_utils::*;
 
@@ -2522,6 +7252,39 @@ mod tests {
         assert!(NodeManager::has_node_manager(storage));
     }
 
+    #[test]
+    async fn test_sync_parallel_requests() {
+        let test_name = "test_sync_parallel_requests";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher), None);
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        )
+        .with_sync_parallel_requests(12);
+        let nm = NodeManager::new(c, storage)
+            .await
+            .expect("node manager should initialize");
+
+        assert_eq!(nm.esplora.parallel_requests(), 12);
+        assert_eq!(nm.wallet.blockchain.parallel_requests(), 12);
+        assert!(nm.get_sync_metrics().is_none());
+    }
+
     #[test]
     async fn created_new_nodes() {
         let test_name = "created_new_nodes";
@@ -2644,6 +7407,36 @@ mod tests {
         assert_eq!(tx.labels, labels);
     }
 
+    #[test]
+    fn test_decoded_invoice() {
+        log!("test decoded invoice");
+
+        let invoice = Bolt11Invoice::from_str(BOLT_11).unwrap();
+        let decoded = DecodedInvoice::from(invoice.clone());
+
+        assert_eq!(decoded.payment_hash, *invoice.payment_hash());
+        assert_eq!(decoded.amount_msat, Some(100_000_000));
+        assert_eq!(decoded.amount_sats, Some(100_000));
+        assert_eq!(decoded.description, None);
+        assert_eq!(decoded.description_hash, None);
+        assert_eq!(decoded.payee_pubkey, invoice.recover_payee_pub_key());
+        assert_eq!(decoded.timestamp, 1681781649);
+        assert_eq!(decoded.expiry_secs, 86400);
+        assert_eq!(decoded.expires_at, 1681781649 + 86400);
+        assert!(decoded.route_hints.is_empty());
+        assert_eq!(
+            decoded.features,
+            InvoiceFeatures {
+                var_onion_optin: true,
+                payment_secret: true,
+                basic_mpp: true,
+                payment_metadata: false,
+                requires_unknown: false,
+            }
+        );
+        assert_eq!(decoded.bolt11, invoice);
+    }
+
     #[test]
     fn test_bolt11_payment_info_into_mutiny_invoice() {
         let preimage: [u8; 32] =
@@ -2670,6 +7463,9 @@ mod tests {
             fee_paid_msat: None,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            order_id: Some("order-123".to_string()),
+            custom_tlvs: vec![],
+            failure: None,
             last_update: 1681781585,
         };
 
@@ -2685,6 +7481,11 @@ mod tests {
             fees_paid: None,
             inbound: true,
             labels: labels.clone(),
+            order_id: Some("order-123".to_string()),
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
             last_updated: 1681781585,
         };
 
@@ -2715,6 +7516,11 @@ mod tests {
         )
         .unwrap();
 
+        let custom_tlvs = vec![CustomTlv {
+            key: 7629169,
+            value: b"boost".to_vec(),
+        }];
+
         let payment_info = PaymentInfo {
             preimage: Some(preimage),
             secret: None,
@@ -2723,6 +7529,9 @@ mod tests {
             fee_paid_msat: Some(1_000),
             bolt11: None,
             payee_pubkey: Some(pubkey),
+            order_id: None,
+            custom_tlvs: custom_tlvs.clone(),
+            failure: None,
             last_update: 1681781585,
         };
 
@@ -2738,6 +7547,11 @@ mod tests {
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
+            order_id: None,
+            custom_tlvs,
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
             last_updated: 1681781585,
         };
 
@@ -2752,6 +7566,298 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_order_id_round_trip() {
+        let invoice = Bolt11Invoice::from_str(BOLT_11).unwrap();
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let order_id = Some("order-123".to_string());
+
+        // an invoice created for an order is read back with it
+        let persister = MutinyNodePersister::new(
+            "node".to_string(),
+            MemoryStorage::default(),
+            Arc::new(MutinyLogger::default()),
+        );
+        let payment_info = PaymentInfo {
+            preimage: None,
+            secret: Some(invoice.payment_secret().0),
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(100_000_000)),
+            fee_paid_msat: None,
+            bolt11: Some(invoice.clone()),
+            payee_pubkey: None,
+            order_id: order_id.clone(),
+            custom_tlvs: vec![],
+            failure: None,
+            last_update: 1681781585,
+        };
+        persister
+            .persist_payment_info(&payment_hash, &payment_info, true)
+            .unwrap();
+        let read = persister
+            .read_payment_info(&payment_hash, true, &MutinyLogger::default())
+            .unwrap();
+        assert_eq!(read.order_id, order_id);
+        let mutiny_invoice = MutinyInvoice::from(read, payment_hash, true, vec![]).unwrap();
+        assert_eq!(mutiny_invoice.order_id, order_id);
+
+        // a keysend carries its order id in a custom record
+        let custom_tlvs = vec![
+            CustomTlv {
+                key: 7629169,
+                value: b"boost".to_vec(),
+            },
+            CustomTlv {
+                key: ORDER_ID_TLV_KEY,
+                value: b"order-123".to_vec(),
+            },
+        ];
+        assert_eq!(CustomTlv::order_id(&custom_tlvs), order_id);
+        assert_eq!(CustomTlv::order_id(&custom_tlvs[..1]), None);
+
+        let invalid = vec![CustomTlv {
+            key: ORDER_ID_TLV_KEY,
+            value: vec![0xff, 0xfe],
+        }];
+        assert_eq!(CustomTlv::order_id(&invalid), None);
+    }
+
+    #[test]
+    fn test_assign_batch_payments() {
+        log!("test assign batch payments");
+
+        // each node pays what fits in its outbound capacity
+        let assigned =
+            assign_batch_payments(&[Some(3_000), Some(5_000), Some(4_000)], &[6_000, 8_000]);
+        assert_eq!(assigned, vec![Some(1), Some(1), Some(0)]);
+
+        // invalid invoices and amounts too big for any node are skipped
+        let assigned = assign_batch_payments(&[None, Some(8_000), Some(1_000)], &[6_000, 7_000]);
+        assert_eq!(assigned, vec![None, None, Some(1)]);
+
+        // no nodes with liquidity left
+        let assigned = assign_batch_payments(&[Some(5_000), Some(5_000)], &[6_000]);
+        assert_eq!(assigned, vec![Some(0), None]);
+    }
+
+    #[test]
+    fn test_cltv_config() {
+        log!("test cltv config");
+
+        let config = CltvConfig::new(144, 80);
+        assert_eq!(config.cltv_expiry_delta, 144);
+        assert_eq!(config.min_final_cltv_expiry_delta, 80);
+
+        // LDK won't go below its minimums
+        let config = CltvConfig::new(1, 1);
+        assert_eq!(config.cltv_expiry_delta, MIN_CLTV_EXPIRY_DELTA);
+        assert_eq!(
+            config.min_final_cltv_expiry_delta,
+            MIN_FINAL_CLTV_EXPIRY_DELTA
+        );
+
+        let user_config = default_user_config(&CltvConfig::new(144, 80));
+        assert_eq!(user_config.channel_config.cltv_expiry_delta, 144);
+    }
+
+    #[test]
+    fn test_failed_path() {
+        log!("test failed path");
+
+        let node_id = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let failure = PathFailure::OnPath {
+            network_update: Some(NetworkUpdate::NodeFailure {
+                node_id,
+                is_permanent: true,
+            }),
+        };
+        let path = FailedPath::new(&failure, Some(42), false, 1_000, 1681781585);
+        assert_eq!(path.failing_node, Some(node_id));
+        assert_eq!(path.failing_channel, Some(42));
+        assert!(path.permanent);
+        assert!(!path.rejected_by_recipient);
+        assert!(path.suggested_fee.is_none());
+
+        // the channel in the update replaces the one LDK guessed
+        let failure = PathFailure::OnPath {
+            network_update: Some(NetworkUpdate::ChannelFailure {
+                short_channel_id: 7,
+                is_permanent: false,
+            }),
+        };
+        let path = FailedPath::new(&failure, Some(42), true, 1_000, 1681781585);
+        assert_eq!(path.failing_channel, Some(7));
+        assert!(!path.permanent);
+        assert!(path.rejected_by_recipient);
+
+        // only the most recent paths are kept
+        let mut payment_failure = PaymentFailure::default();
+        for i in 0..MAX_FAILED_PATHS as u64 + 5 {
+            let failure = PathFailure::OnPath {
+                network_update: None,
+            };
+            payment_failure.add_path(FailedPath::new(&failure, Some(i), false, 1_000, i));
+        }
+        assert_eq!(payment_failure.paths.len(), MAX_FAILED_PATHS);
+        assert_eq!(payment_failure.paths[0].failing_channel, Some(5));
+    }
+
+    #[test]
+    fn test_force_close_postmortem() {
+        log!("test force close postmortem");
+
+        assert!(
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::CooperativeClosure).is_none()
+        );
+
+        let postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::HolderForceClosed).unwrap();
+        assert_eq!(postmortem.reason, ForceCloseReason::HolderForceClosed);
+        assert_eq!(postmortem.initiated_by_us, Some(true));
+
+        let postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::ProcessingError {
+                err: "Peer's feerate much too low".to_string(),
+            })
+            .unwrap();
+        assert_eq!(postmortem.reason, ForceCloseReason::FeeDisagreement);
+        assert!(postmortem.summary().contains("Peer's feerate much too low"));
+
+        let mut postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::CommitmentTxConfirmed)
+                .unwrap();
+        assert_eq!(postmortem.reason, ForceCloseReason::CommitmentConfirmed);
+        assert_eq!(postmortem.initiated_by_us, None);
+
+        postmortem.txids = vec![Txid::all_zeros()];
+        assert!(postmortem
+            .summary()
+            .contains(&Txid::all_zeros().to_string()));
+
+        // the counterparty's message is shown but doesn't decide the reason
+        let postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::CounterpartyForceClosed {
+                peer_msg: UntrustedString("fee too low".to_string()),
+            })
+            .unwrap();
+        assert_eq!(postmortem.reason, ForceCloseReason::CounterpartyForceClosed);
+        assert_eq!(postmortem.message, Some("fee too low".to_string()));
+
+        let postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::ProcessingError {
+                err: "Remote HTLC add would overdraw remaining funds, no fee left".to_string(),
+            })
+            .unwrap();
+        assert_eq!(postmortem.reason, ForceCloseReason::ProcessingError);
+    }
+
+    #[test]
+    fn test_force_close_postmortem_resolution() {
+        log!("test force close postmortem resolution");
+
+        let mut postmortem =
+            ForceClosePostmortem::from_closure_reason(&ClosureReason::CommitmentTxConfirmed)
+                .unwrap();
+        let balances = vec![Balance::ClaimableAwaitingConfirmations {
+            claimable_amount_satoshis: 10_000,
+            confirmation_height: 800_144,
+        }];
+        let commitment = Txid::from_slice(&[1; 32]).unwrap();
+        assert!(postmortem.observe(&balances, vec![commitment], 100));
+        assert_eq!(postmortem.amount_sats, Some(10_000));
+        assert_eq!(postmortem.pending_sats, 10_000);
+        assert_eq!(postmortem.resolved_at, None);
+        assert!(postmortem
+            .summary()
+            .contains("10000 sats are still waiting"));
+
+        // nothing new
+        assert!(!postmortem.observe(&balances, vec![commitment], 200));
+
+        // the counterparty's commitment was revoked
+        let balances = vec![Balance::CounterpartyRevokedOutputClaimable {
+            claimable_amount_satoshis: 20_000,
+        }];
+        let justice = Txid::from_slice(&[2; 32]).unwrap();
+        assert!(postmortem.observe(&balances, vec![justice], 300));
+        assert_eq!(postmortem.reason, ForceCloseReason::StaleState);
+        assert_eq!(postmortem.amount_sats, Some(10_000));
+        assert_eq!(postmortem.pending_sats, 20_000);
+        assert_eq!(postmortem.txids, vec![commitment, justice]);
+
+        // everything was claimed
+        assert!(postmortem.observe(&[], vec![], 400));
+        assert_eq!(postmortem.pending_sats, 0);
+        assert_eq!(postmortem.resolved_at, Some(400));
+        assert_eq!(postmortem.txids, vec![commitment, justice]);
+        assert!(postmortem.summary().contains("All funds were claimed"));
+        assert!(!postmortem.observe(&balances, vec![], 500));
+    }
+
+    #[test]
+    fn test_pending_close_output() {
+        log!("test pending close output");
+
+        let funding_txo = OutPoint::new(Txid::all_zeros(), 0);
+        let output = PendingCloseOutput::new(
+            funding_txo,
+            PendingCloseOutputKind::AwaitingConfirmations,
+            10_000,
+            Some(800_144),
+            800_000,
+        );
+        assert_eq!(output.blocks_remaining, Some(144));
+        assert_eq!(output.estimated_secs_remaining, Some(144 * 600));
+
+        // already claimable
+        let output = PendingCloseOutput::new(
+            funding_txo,
+            PendingCloseOutputKind::HtlcTimeout,
+            10_000,
+            Some(799_000),
+            800_000,
+        );
+        assert_eq!(output.blocks_remaining, Some(0));
+
+        let output = PendingCloseOutput::new(
+            funding_txo,
+            PendingCloseOutputKind::RevokedOutput,
+            10_000,
+            None,
+            800_000,
+        );
+        assert_eq!(output.blocks_remaining, None);
+        assert_eq!(output.estimated_secs_remaining, None);
+    }
+
+    #[test]
+    fn test_connection_info() {
+        log!("test connection info");
+
+        let pubkey = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+
+        let info = ConnectionInfo::new(pubkey, Some("wss://p.mutinywallet.com"));
+        assert_eq!(info.address, Some("p.mutinywallet.com:443".to_string()));
+        assert_eq!(
+            info.connection_string,
+            format!("{pubkey}@p.mutinywallet.com:443")
+        );
+        assert_eq!(info.qr_payload, info.connection_string);
+
+        let info = ConnectionInfo::new(pubkey, Some("ws://127.0.0.1:3001"));
+        assert_eq!(info.address, Some("127.0.0.1:3001".to_string()));
+
+        let info = ConnectionInfo::new(pubkey, None);
+        assert_eq!(info.address, None);
+        assert_eq!(info.connection_string, pubkey.to_string());
+    }
+
     #[test]
     fn test_sort_activity_item() {
         let preimage: [u8; 32] =
@@ -2774,6 +7880,7 @@ mod tests {
             node_id: None,
             reason: "".to_string(),
             timestamp: 1686258926,
+            postmortem: None,
         };
 
         let tx1: TransactionDetails = TransactionDetails {
@@ -2811,6 +7918,11 @@ mod tests {
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
+            order_id: None,
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
             last_updated: 1681781585,
         };
 
@@ -2826,6 +7938,11 @@ mod tests {
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
+            order_id: None,
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
             last_updated: 1781781585,
         };
 
@@ -0,0 +1,54 @@
+//! Receiving to a lightning address from a server the user hosts themselves.
+//!
+//! A lightning address server has to hand out a fresh invoice every time
+//! someone pays the address, but the wallet is only online some of the time.
+//! Instead of trusting Mutiny's infrastructure to do this, the server pulls
+//! invoices from the wallet over a dedicated NWC connection:
+//!
+//! 1. The user creates the connection with
+//!    [NostrManager::get_lightning_address_connection] and gives the NWC URI
+//!    to their server. The connection can only use `make_invoice` and
+//!    `lookup_invoice`, it can never spend.
+//! 2. The server answers `/.well-known/lnurlp/<name>` with a LNURL-pay
+//!    response (LUD-06 and LUD-16) as usual.
+//! 3. When the payer calls the callback with an `amount` in millisats, the
+//!    server sends a NIP-47 `make_invoice` request to the wallet with the same
+//!    `amount` and a `description_hash` of the hex sha256 of the LNURL metadata.
+//!    The wallet creates an invoice committing to that hash and returns it in
+//!    the `invoice` field of the response, which the server returns as `pr`.
+//! 4. If the wallet doesn't answer before the payer's request times out, the
+//!    server returns a LNURL error. NIP-47 errors, like `RESTRICTED` or
+//!    `INTERNAL`, should be passed on as the error's reason.
+//! 5. Optionally, the server can report whether an invoice has been paid
+//!    (LUD-21) with a `lookup_invoice` request for its payment hash.
+//!
+//! The wallet answers requests while it is running, so the address can only
+//! receive while the wallet is open in at least one place.
+
+use crate::error::MutinyError;
+use crate::nostr::nwc::{NwcScope, SpendingConditions};
+use crate::nostr::{NostrManager, ProfileType, ReservedProfile};
+use crate::storage::MutinyStorage;
+
+/// What a lightning address server can do with its connection
+pub(crate) const LIGHTNING_ADDRESS_SCOPES: [NwcScope; 2] =
+    [NwcScope::MakeInvoice, NwcScope::LookupInvoice];
+
+impl<S: MutinyStorage> NostrManager<S> {
+    /// Gets the NWC URI a self-hosted lightning address server uses to fetch
+    /// invoices, creating the connection the first time.
+    pub async fn get_lightning_address_connection(&self) -> Result<String, MutinyError> {
+        let index = ReservedProfile::LightningAddress.info().1;
+        let exists = self.profiles().iter().any(|p| p.index == index);
+        if !exists {
+            self.create_new_nwc_profile(
+                ProfileType::Reserved(ReservedProfile::LightningAddress),
+                SpendingConditions::default(),
+                LIGHTNING_ADDRESS_SCOPES.to_vec(),
+            )
+            .await?;
+        }
+
+        self.get_nwc_uri(index)
+    }
+}
@@ -1,10 +1,12 @@
 use crate::error::MutinyError;
 use crate::nodemanager::NodeManager;
 use crate::nostr::nwc::{
-    NostrWalletConnect, NwcProfile, PendingNwcInvoice, Profile, SingleUseSpendingConditions,
-    SpendingConditions, PENDING_NWC_EVENTS_KEY,
+    NostrWalletConnect, NwcActivity, NwcActivityResult, NwcActivityStorage, NwcProfile, NwcScope,
+    PendingNwcInvoice, Profile, SingleUseSpendingConditions, SpendingConditions,
+    PENDING_NWC_EVENTS_KEY,
 };
 use crate::storage::MutinyStorage;
+use crate::utils;
 use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, Signing};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
@@ -20,7 +22,11 @@ use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+pub mod lnaddress;
+pub mod nip05;
 pub mod nwc;
+pub mod recovery;
+pub mod relays;
 
 const NWC_ACCOUNT_INDEX: u32 = 1;
 const USER_NWC_PROFILE_START_INDEX: u32 = 1000;
@@ -31,12 +37,14 @@ const NWC_STORAGE_KEY: &str = "nwc_profiles";
 /// Must not exceed `USER_NWC_PROFILE_START_INDEX`
 pub enum ReservedProfile {
     MutinySubscription,
+    LightningAddress,
 }
 
 impl ReservedProfile {
     pub fn info(&self) -> (&'static str, u32) {
         let (n, i) = match self {
             ReservedProfile::MutinySubscription => ("Mutiny+ Subscription", 0),
+            ReservedProfile::LightningAddress => ("Lightning Address Server", 1),
         };
         if i >= USER_NWC_PROFILE_START_INDEX {
             panic!("Must not exceed 1000 reserved indexes")
@@ -127,7 +135,12 @@ impl<S: MutinyStorage> NostrManager<S> {
             .find(|nwc| nwc.profile.index == index)
             .ok_or(MutinyError::NotFound)?;
 
-        nwc.profile = profile.profile();
+        // the device that created the profile can't be changed
+        let device_id = nwc.profile.device_id.clone();
+        nwc.profile = Profile {
+            device_id,
+            ..profile.profile()
+        };
 
         let nwc_profile = nwc.nwc_profile();
 
@@ -143,11 +156,47 @@ impl<S: MutinyStorage> NostrManager<S> {
         Ok(nwc_profile)
     }
 
+    /// Disables and archives every NWC profile created by the given device, and
+    /// resets their budgets so they need approval to spend even if enabled again.
+    /// Returns the number of profiles that were disabled.
+    pub(crate) fn disable_device_profiles(&self, device_id: &str) -> Result<usize, MutinyError> {
+        let mut profiles = self.nwc.write().unwrap();
+
+        let mut disabled = 0;
+        for nwc in profiles.iter_mut() {
+            if nwc.profile.device_id.as_deref() != Some(device_id) {
+                continue;
+            }
+            if !nwc.profile.enabled
+                && nwc.profile.spending_conditions == SpendingConditions::RequireApproval
+            {
+                continue;
+            }
+
+            nwc.profile.enabled = false;
+            nwc.profile.archived = true;
+            nwc.profile.spending_conditions = SpendingConditions::RequireApproval;
+            disabled += 1;
+        }
+
+        // save to storage
+        if disabled > 0 {
+            let profiles = profiles
+                .iter()
+                .map(|x| x.profile.clone())
+                .collect::<Vec<_>>();
+            self.storage.set_data(NWC_STORAGE_KEY, profiles, None)?;
+        }
+
+        Ok(disabled)
+    }
+
     /// Creates a new NWC profile and saves to storage
     pub(crate) fn create_new_profile(
         &self,
         profile_type: ProfileType,
         spending_conditions: SpendingConditions,
+        scopes: Vec<NwcScope>,
     ) -> Result<NwcProfile, MutinyError> {
         let mut profiles = self.nwc.write().unwrap();
 
@@ -174,6 +223,8 @@ impl<S: MutinyStorage> NostrManager<S> {
             enabled: true,
             archived: false,
             spending_conditions,
+            device_id: Some(self.storage.get_device_id()?),
+            scopes,
         };
         let nwc = NostrWalletConnect::new(&Secp256k1::new(), self.xprivkey, profile)?;
 
@@ -198,8 +249,9 @@ impl<S: MutinyStorage> NostrManager<S> {
         &self,
         profile_type: ProfileType,
         spending_conditions: SpendingConditions,
+        scopes: Vec<NwcScope>,
     ) -> Result<NwcProfile, MutinyError> {
-        let profile = self.create_new_profile(profile_type, spending_conditions)?;
+        let profile = self.create_new_profile(profile_type, spending_conditions, scopes)?;
 
         let info_event = self.nwc.read().unwrap().iter().find_map(|nwc| {
             if nwc.profile.index == profile.index {
@@ -242,10 +294,16 @@ impl<S: MutinyStorage> NostrManager<S> {
             amount_sats,
             spent: false,
         });
-        self.create_new_nwc_profile(profile, spending_conditions)
+        self.create_new_nwc_profile(profile, spending_conditions, vec![NwcScope::PayInvoice])
             .await
     }
 
+    /// Gets every request an app made over the connection with the given index,
+    /// oldest first, so users can audit what the app did with it
+    pub fn get_nwc_activity(&self, index: u32) -> Result<Vec<NwcActivity>, MutinyError> {
+        self.storage.get_nwc_activity(index)
+    }
+
     /// Lists all pending NWC invoices
     pub fn get_pending_nwc_invoices(&self) -> Result<Vec<PendingNwcInvoice>, MutinyError> {
         Ok(self
@@ -329,9 +387,24 @@ impl<S: MutinyStorage> NostrManager<S> {
     ) -> Result<EventId, MutinyError> {
         let (nwc, inv) = self.find_nwc_data(hash)?;
 
-        let resp = nwc
+        let method = NwcScope::PayInvoice.method();
+        let amount_msats = inv.invoice.amount_milli_satoshis();
+        let resp = match nwc
             .pay_nwc_invoice(node_manager, from_node, &inv.invoice)
-            .await?;
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                let result = NwcActivityResult::Failed {
+                    code: "INTERNAL".to_string(),
+                    message: e.to_string(),
+                };
+                nwc.record_activity(node_manager, inv.event_id, method, amount_msats, result);
+                return Err(e);
+            }
+        };
+        let result = NwcActivityResult::Success;
+        nwc.record_activity(node_manager, inv.event_id, method, amount_msats, result);
 
         let event_id = self.broadcast_nwc_response(resp, nwc, inv).await?;
 
@@ -378,6 +451,26 @@ impl<S: MutinyStorage> NostrManager<S> {
             .get_data(PENDING_NWC_EVENTS_KEY)?
             .unwrap_or_default();
 
+        // record the denial in the connection's activity
+        if let Some(inv) = invoices.iter().find(|x| x.invoice.payment_hash() == &hash) {
+            let activity = NwcActivity {
+                event_id: inv.event_id.to_hex(),
+                app: self
+                    .nwc
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .find(|nwc| nwc.profile.index == inv.index)
+                    .map(|nwc| nwc.profile.name.clone())
+                    .unwrap_or_default(),
+                method: NwcScope::PayInvoice.method().to_string(),
+                amount_msats: inv.invoice.amount_milli_satoshis(),
+                result: NwcActivityResult::Denied,
+                timestamp: utils::now().as_secs(),
+            };
+            self.storage.record_nwc_activity(inv.index, activity)?;
+        }
+
         // remove expired invoices
         invoices.retain(|x| !x.is_expired());
 
@@ -478,7 +571,7 @@ impl<S: MutinyStorage> NostrManager<S> {
         client.connect().await;
 
         let invoice = node_manager
-            .create_invoice(Some(amount_sats), vec!["Gift".to_string()])
+            .create_invoice(Some(amount_sats), vec!["Gift".to_string()], None, None)
             .await?;
 
         let req = Request {
@@ -647,6 +740,7 @@ mod test {
             .create_new_profile(
                 ProfileType::Normal { name: name.clone() },
                 SpendingConditions::default(),
+                NwcScope::default_scopes(),
             )
             .unwrap();
 
@@ -679,6 +773,7 @@ mod test {
             .create_new_profile(
                 ProfileType::Reserved(ReservedProfile::MutinySubscription),
                 SpendingConditions::default(),
+                NwcScope::default_scopes(),
             )
             .unwrap();
 
@@ -707,6 +802,7 @@ mod test {
             .create_new_profile(
                 ProfileType::Normal { name: name.clone() },
                 SpendingConditions::default(),
+                NwcScope::default_scopes(),
             )
             .unwrap();
 
@@ -724,6 +820,7 @@ mod test {
             .create_new_profile(
                 ProfileType::Normal { name: name.clone() },
                 SpendingConditions::default(),
+                NwcScope::default_scopes(),
             )
             .unwrap();
 
@@ -753,6 +850,89 @@ mod test {
         assert!(!profiles[0].enabled);
     }
 
+    #[test]
+    fn test_disable_device_profiles() {
+        let nostr_manager = create_nostr_manager();
+        let device_id = nostr_manager.storage.get_device_id().unwrap();
+
+        let budget = SpendingConditions::SingleUse(SingleUseSpendingConditions {
+            spent: false,
+            amount_sats: 10_000,
+        });
+        let profile = nostr_manager
+            .create_new_profile(
+                ProfileType::Normal {
+                    name: "test".to_string(),
+                },
+                budget,
+                NwcScope::default_scopes(),
+            )
+            .unwrap();
+        assert_eq!(profile.device_id, Some(device_id.clone()));
+
+        assert_eq!(nostr_manager.disable_device_profiles("other").unwrap(), 0);
+        assert_eq!(nostr_manager.profiles().len(), 1);
+
+        assert_eq!(
+            nostr_manager.disable_device_profiles(&device_id).unwrap(),
+            1
+        );
+        assert!(nostr_manager.profiles().is_empty());
+        assert!(nostr_manager.get_nwc_filters().is_empty());
+
+        // the budget is gone from storage too
+        let profiles: Vec<Profile> = nostr_manager
+            .storage
+            .get_data(NWC_STORAGE_KEY)
+            .unwrap()
+            .unwrap_or_default();
+        assert_eq!(
+            profiles[0].spending_conditions,
+            SpendingConditions::RequireApproval
+        );
+
+        // already disabled
+        assert_eq!(
+            nostr_manager.disable_device_profiles(&device_id).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_nwc_scopes() {
+        let nostr_manager = create_nostr_manager();
+
+        let profile = nostr_manager
+            .create_new_profile(
+                ProfileType::Normal {
+                    name: "read only".to_string(),
+                },
+                SpendingConditions::default(),
+                vec![NwcScope::GetBalance, NwcScope::ListTransactions],
+            )
+            .unwrap();
+        assert!(!profile.scopes.contains(&NwcScope::PayInvoice));
+
+        let info = nostr_manager.nwc.read().unwrap()[0]
+            .create_nwc_info_event()
+            .unwrap();
+        assert_eq!(info.content, "get_balance list_transactions");
+
+        // profiles saved before scopes existed could only pay invoices
+        let json = serde_json::json!({
+            "name": "old",
+            "index": 1001,
+            "relay": "wss://nostr.mutinywallet.com",
+            "enabled": true,
+        });
+        let old: Profile = serde_json::from_value(json).unwrap();
+        assert_eq!(old.scopes, vec![NwcScope::PayInvoice]);
+
+        for scope in NwcScope::default_scopes() {
+            assert_eq!(NwcScope::from_method(scope.method()), Some(scope));
+        }
+    }
+
     #[test]
     fn test_deny_invoice() {
         let nostr_manager = create_nostr_manager();
@@ -760,7 +940,11 @@ mod test {
         let name = "test".to_string();
 
         let profile = nostr_manager
-            .create_new_profile(ProfileType::Normal { name }, SpendingConditions::default())
+            .create_new_profile(
+                ProfileType::Normal { name },
+                SpendingConditions::default(),
+                NwcScope::default_scopes(),
+            )
             .unwrap();
 
         let inv = PendingNwcInvoice {
@@ -784,5 +968,11 @@ mod test {
 
         let pending = nostr_manager.get_pending_nwc_invoices().unwrap();
         assert_eq!(pending.len(), 0);
+
+        // the denial is in the connection's activity
+        let activity = nostr_manager.get_nwc_activity(profile.index).unwrap();
+        assert_eq!(activity.len(), 1);
+        assert_eq!(activity[0].result, NwcActivityResult::Denied);
+        assert_eq!(activity[0].method, "pay_invoice");
     }
 }
@@ -0,0 +1,232 @@
+use crate::error::MutinyError;
+use crate::http_client::{HttpRequest, MutinyHttpClient};
+use crate::storage::MutinyStorage;
+use bitcoin::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub(crate) const NIP05_CACHE_PREFIX: &str = "nip05/";
+
+/// How long a verification result is trusted before we check the identifier again
+pub(crate) const NIP05_CACHE_SECS: u64 = 60 * 60 * 24;
+
+/// Whether a contact's NIP-05 identifier points to their npub
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Nip05Status {
+    /// We haven't been able to verify the identifier yet
+    Unverified,
+    Verified,
+    /// The identifier was verified before but no longer points to the contact's npub,
+    /// someone may be impersonating them
+    Broken,
+}
+
+impl Nip05Status {
+    /// The status after a new check. A contact that was verified and no longer is has broken.
+    pub(crate) fn next(previous: Option<Self>, verified: bool) -> Self {
+        match (previous, verified) {
+            (_, true) => Nip05Status::Verified,
+            (Some(Nip05Status::Verified), false) | (Some(Nip05Status::Broken), false) => {
+                Nip05Status::Broken
+            }
+            _ => Nip05Status::Unverified,
+        }
+    }
+}
+
+/// A contact's NIP-05 identifier, like `bob@example.com`, and whether it is verified
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd, Hash)]
+pub struct Nip05Identity {
+    pub address: String,
+    pub status: Nip05Status,
+}
+
+impl Nip05Identity {
+    pub(crate) fn new(address: &str) -> Self {
+        Self {
+            address: address.trim().to_lowercase(),
+            status: Nip05Status::Unverified,
+        }
+    }
+}
+
+/// A cached result of checking an identifier against an npub
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Nip05Verification {
+    pub verified: bool,
+    /// Unix timestamp in seconds of when we checked
+    pub checked_at: u64,
+}
+
+impl Nip05Verification {
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        now >= self.checked_at + NIP05_CACHE_SECS
+    }
+}
+
+/// Splits an identifier into its name and domain, a bare domain is the `_` name
+fn split_address(address: &str) -> Result<(String, String), MutinyError> {
+    let address = address.trim().to_lowercase();
+    let (name, domain) = match address.split_once('@') {
+        Some((name, domain)) => (name.to_string(), domain.to_string()),
+        None => ("_".to_string(), address),
+    };
+
+    if name.is_empty() || domain.is_empty() || domain.contains('/') {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+
+    Ok((name, domain))
+}
+
+/// Finds the pubkey for `name` in a `.well-known/nostr.json` response
+fn pubkey_from_response(json: &serde_json::Value, name: &str) -> Option<XOnlyPublicKey> {
+    let pubkey = json.get("names")?.get(name)?.as_str()?;
+    XOnlyPublicKey::from_str(pubkey).ok()
+}
+
+/// Looks up the npub an identifier points to.
+/// Returns `None` if the domain doesn't know the name.
+pub(crate) async fn resolve_nip05(
+    client: &dyn MutinyHttpClient,
+    address: &str,
+) -> Result<Option<XOnlyPublicKey>, MutinyError> {
+    let (name, domain) = split_address(address)?;
+    let url = format!("https://{domain}/.well-known/nostr.json?name={name}");
+
+    let response = client
+        .send(HttpRequest::get(url))
+        .await
+        .map_err(|_| MutinyError::Nip05Failure)?;
+    if !response.is_success() {
+        return Err(MutinyError::Nip05Failure);
+    }
+    let json: serde_json::Value = response.json().map_err(|_| MutinyError::Nip05Failure)?;
+
+    Ok(pubkey_from_response(&json, &name))
+}
+
+fn get_nip05_key(address: &str, npub: &XOnlyPublicKey) -> String {
+    format!(
+        "{NIP05_CACHE_PREFIX}{}/{npub}",
+        address.trim().to_lowercase()
+    )
+}
+
+pub trait Nip05Storage {
+    fn get_nip05_verification(
+        &self,
+        address: &str,
+        npub: &XOnlyPublicKey,
+    ) -> Result<Option<Nip05Verification>, MutinyError>;
+
+    fn set_nip05_verification(
+        &self,
+        address: &str,
+        npub: &XOnlyPublicKey,
+        verification: Nip05Verification,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> Nip05Storage for S {
+    fn get_nip05_verification(
+        &self,
+        address: &str,
+        npub: &XOnlyPublicKey,
+    ) -> Result<Option<Nip05Verification>, MutinyError> {
+        self.get_data(get_nip05_key(address, npub))
+    }
+
+    fn set_nip05_verification(
+        &self,
+        address: &str,
+        npub: &XOnlyPublicKey,
+        verification: Nip05Verification,
+    ) -> Result<(), MutinyError> {
+        self.set_data(get_nip05_key(address, npub), verification, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use serde_json::json;
+
+    const PUBKEY: &str = "b2d670de53b27691c0c3400225b65c35a26d06093bcc41f48ffc71e0907f9d4a";
+
+    #[test]
+    fn test_split_address() {
+        assert_eq!(
+            split_address(" Bob@Example.com").unwrap(),
+            ("bob".to_string(), "example.com".to_string())
+        );
+        assert_eq!(
+            split_address("example.com").unwrap(),
+            ("_".to_string(), "example.com".to_string())
+        );
+        assert!(split_address("@example.com").is_err());
+        assert!(split_address("bob@").is_err());
+        assert!(split_address("bob@example.com/evil").is_err());
+    }
+
+    #[test]
+    fn test_pubkey_from_response() {
+        let npub = XOnlyPublicKey::from_str(PUBKEY).unwrap();
+        let response = json!({ "names": { "bob": PUBKEY } });
+        assert_eq!(pubkey_from_response(&response, "bob"), Some(npub));
+        assert_eq!(pubkey_from_response(&response, "alice"), None);
+
+        let response = json!({ "names": { "bob": "not a pubkey" } });
+        assert_eq!(pubkey_from_response(&response, "bob"), None);
+        assert_eq!(pubkey_from_response(&json!({}), "bob"), None);
+    }
+
+    #[test]
+    fn test_nip05_status() {
+        assert_eq!(Nip05Status::next(None, true), Nip05Status::Verified);
+        assert_eq!(Nip05Status::next(None, false), Nip05Status::Unverified);
+        assert_eq!(
+            Nip05Status::next(Some(Nip05Status::Unverified), false),
+            Nip05Status::Unverified
+        );
+        assert_eq!(
+            Nip05Status::next(Some(Nip05Status::Verified), false),
+            Nip05Status::Broken
+        );
+        assert_eq!(
+            Nip05Status::next(Some(Nip05Status::Broken), false),
+            Nip05Status::Broken
+        );
+        assert_eq!(
+            Nip05Status::next(Some(Nip05Status::Broken), true),
+            Nip05Status::Verified
+        );
+    }
+
+    #[test]
+    fn test_nip05_cache() {
+        let storage = MemoryStorage::default();
+        let npub = XOnlyPublicKey::from_str(PUBKEY).unwrap();
+        assert!(storage
+            .get_nip05_verification("bob@example.com", &npub)
+            .unwrap()
+            .is_none());
+
+        let verification = Nip05Verification {
+            verified: true,
+            checked_at: 1_000,
+        };
+        storage
+            .set_nip05_verification("Bob@example.com", &npub, verification)
+            .unwrap();
+        let cached = storage
+            .get_nip05_verification("bob@example.com", &npub)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached, verification);
+        assert!(!cached.is_expired(1_000 + NIP05_CACHE_SECS - 1));
+        assert!(cached.is_expired(1_000 + NIP05_CACHE_SECS));
+    }
+}
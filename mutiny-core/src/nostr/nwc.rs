@@ -1,9 +1,11 @@
 use crate::error::MutinyError;
-use crate::nodemanager::NodeManager;
+use crate::nodemanager::{MutinyInvoice, NodeManager};
 use crate::nostr::NostrManager;
 use crate::storage::MutinyStorage;
 use crate::utils;
 use anyhow::anyhow;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, Signing};
 use bitcoin::util::bip32::ExtendedPrivKey;
 use futures_util::lock::Mutex;
@@ -15,6 +17,7 @@ use nostr::nips::nip47::*;
 use nostr::prelude::{decrypt, encrypt};
 use nostr::{Event, EventBuilder, EventId, Filter, Keys, Kind, Tag};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::cmp::Ordering;
 use std::str::FromStr;
 
@@ -39,6 +42,112 @@ impl Default for SpendingConditions {
     }
 }
 
+/// What a NWC connection is allowed to do, one scope for each NIP-47 method.
+/// Only [NwcScope::PayInvoice] can spend, connections without it are read-only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NwcScope {
+    PayInvoice,
+    MakeInvoice,
+    LookupInvoice,
+    ListTransactions,
+    GetBalance,
+}
+
+impl NwcScope {
+    /// The NIP-47 method this scope allows
+    pub fn method(&self) -> &'static str {
+        match self {
+            NwcScope::PayInvoice => "pay_invoice",
+            NwcScope::MakeInvoice => "make_invoice",
+            NwcScope::LookupInvoice => "lookup_invoice",
+            NwcScope::ListTransactions => "list_transactions",
+            NwcScope::GetBalance => "get_balance",
+        }
+    }
+
+    pub(crate) fn from_method(method: &str) -> Option<Self> {
+        match method {
+            "pay_invoice" => Some(NwcScope::PayInvoice),
+            "make_invoice" => Some(NwcScope::MakeInvoice),
+            "lookup_invoice" => Some(NwcScope::LookupInvoice),
+            "list_transactions" => Some(NwcScope::ListTransactions),
+            "get_balance" => Some(NwcScope::GetBalance),
+            _ => None,
+        }
+    }
+
+    /// The scopes connections get when none are given, connections
+    /// created before scopes existed could only pay invoices
+    pub fn default_scopes() -> Vec<NwcScope> {
+        vec![NwcScope::PayInvoice]
+    }
+}
+
+/// Why a NIP-47 request failed, sent back to the client as the error code
+#[derive(Debug)]
+enum NwcRequestError {
+    /// The connection doesn't have the scope for the method
+    Restricted,
+    NotImplemented,
+    InvalidParams,
+    Internal(MutinyError),
+}
+
+impl From<MutinyError> for NwcRequestError {
+    fn from(e: MutinyError) -> Self {
+        NwcRequestError::Internal(e)
+    }
+}
+
+impl NwcRequestError {
+    fn code(&self) -> &'static str {
+        match self {
+            NwcRequestError::Restricted => "RESTRICTED",
+            NwcRequestError::NotImplemented => "NOT_IMPLEMENTED",
+            NwcRequestError::InvalidParams => "OTHER",
+            NwcRequestError::Internal(MutinyError::NotFound) => "NOT_FOUND",
+            NwcRequestError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            NwcRequestError::Restricted => "This connection is not allowed to do that".to_string(),
+            NwcRequestError::NotImplemented => "Not implemented".to_string(),
+            NwcRequestError::InvalidParams => "Invalid request parameters".to_string(),
+            NwcRequestError::Internal(e) => e.to_string(),
+        }
+    }
+}
+
+/// Builds the JSON content of a NIP-47 response
+fn nwc_response(scope: NwcScope, result: Result<Value, NwcRequestError>) -> String {
+    let json = match result {
+        Ok(result) => json!({ "result_type": scope.method(), "result": result }),
+        Err(e) => json!({
+            "result_type": scope.method(),
+            "error": { "code": e.code(), "message": e.message() },
+        }),
+    };
+    json.to_string()
+}
+
+/// Converts an invoice to a NIP-47 transaction, amounts are in msats
+fn nwc_transaction(invoice: &MutinyInvoice) -> Value {
+    json!({
+        "type": if invoice.inbound { "incoming" } else { "outgoing" },
+        "invoice": invoice.bolt11.as_ref().map(|b| b.to_string()),
+        "description": invoice.description,
+        "payment_hash": invoice.payment_hash.to_hex(),
+        "preimage": invoice.preimage,
+        "amount": invoice.amount_sats.unwrap_or(0) * 1_000,
+        "fees_paid": invoice.fees_paid.unwrap_or(0) * 1_000,
+        "expires_at": invoice.expire,
+        "settled_at": invoice.paid.then_some(invoice.last_updated),
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct Profile {
     pub name: String,
@@ -51,6 +160,12 @@ pub(crate) struct Profile {
     /// Require approval before sending a payment
     #[serde(default)]
     pub spending_conditions: SpendingConditions,
+    /// The device that created the profile
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// What the connection is allowed to do
+    #[serde(default = "NwcScope::default_scopes")]
+    pub scopes: Vec<NwcScope>,
 }
 
 impl PartialOrd for Profile {
@@ -114,13 +229,110 @@ impl NostrWalletConnect {
             .pubkey(self.server_pubkey())
     }
 
-    /// Create Nostr Wallet Connect Info event
+    /// Create Nostr Wallet Connect Info event, listing the methods the connection can use
     pub fn create_nwc_info_event(&self) -> anyhow::Result<Event> {
-        let info = EventBuilder::new(Kind::WalletConnectInfo, "pay_invoice".to_string(), &[])
-            .to_event(&self.server_key)?;
+        let methods = self
+            .profile
+            .scopes
+            .iter()
+            .map(|s| s.method())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let info =
+            EventBuilder::new(Kind::WalletConnectInfo, methods, &[]).to_event(&self.server_key)?;
         Ok(info)
     }
 
+    /// Encrypts the response content to the client and builds the response event
+    fn build_response_event(&self, event: &Event, content: String) -> anyhow::Result<Event> {
+        let server_key = self.server_key.secret_key()?;
+        let encrypted = encrypt(&server_key, &self.client_key.public_key(), content)?;
+
+        let p_tag = Tag::PubKey(event.pubkey, None);
+        let e_tag = Tag::Event(event.id, None, None);
+        let response = EventBuilder::new(Kind::WalletConnectResponse, encrypted, &[p_tag, e_tag])
+            .to_event(&self.server_key)?;
+
+        Ok(response)
+    }
+
+    /// Handles the NIP-47 methods that don't spend, returning the response's result
+    async fn handle_read_request<S: MutinyStorage>(
+        &self,
+        scope: NwcScope,
+        params: &Value,
+        node_manager: &NodeManager<S>,
+    ) -> Result<Value, NwcRequestError> {
+        match scope {
+            NwcScope::PayInvoice => Err(NwcRequestError::NotImplemented),
+            NwcScope::GetBalance => {
+                let balance = node_manager.get_balance().await?;
+                Ok(json!({ "balance": balance.lightning * 1_000 }))
+            }
+            NwcScope::MakeInvoice => {
+                let msats = params["amount"]
+                    .as_u64()
+                    .ok_or(NwcRequestError::InvalidParams)?;
+                // lightning address servers commit to their LNURL metadata with a hash
+                let description_hash = match params["description_hash"].as_str() {
+                    Some(hash) => Some(
+                        sha256::Hash::from_str(hash).map_err(|_| NwcRequestError::InvalidParams)?,
+                    ),
+                    None => None,
+                };
+                let labels = vec![self.profile.name.clone()];
+                let invoice = node_manager
+                    .create_invoice_with_description_hash(
+                        Some(msats / 1_000),
+                        labels,
+                        None,
+                        description_hash,
+                        None,
+                    )
+                    .await?;
+                Ok(nwc_transaction(&invoice))
+            }
+            NwcScope::LookupInvoice => {
+                let hash = match (params["payment_hash"].as_str(), params["invoice"].as_str()) {
+                    (Some(hash), _) => {
+                        sha256::Hash::from_str(hash).map_err(|_| NwcRequestError::InvalidParams)?
+                    }
+                    (None, Some(invoice)) => *Bolt11Invoice::from_str(invoice)
+                        .map_err(|_| NwcRequestError::InvalidParams)?
+                        .payment_hash(),
+                    (None, None) => return Err(NwcRequestError::InvalidParams),
+                };
+                let invoice = node_manager.get_invoice_by_hash(&hash).await?;
+                Ok(nwc_transaction(&invoice))
+            }
+            NwcScope::ListTransactions => {
+                let from = params["from"].as_u64().unwrap_or(0);
+                let until = params["until"].as_u64().unwrap_or(u64::MAX);
+                let offset = params["offset"].as_u64().unwrap_or(0) as usize;
+                let limit = params["limit"].as_u64().unwrap_or(u64::MAX) as usize;
+                let unpaid = params["unpaid"].as_bool().unwrap_or(false);
+
+                let mut invoices = node_manager.list_invoices().await?;
+                // newest first
+                invoices.sort_by(|a, b| b.last_updated.cmp(&a.last_updated));
+                let transactions = invoices
+                    .iter()
+                    .filter(|i| i.paid || unpaid)
+                    .filter(|i| i.last_updated >= from && i.last_updated <= until)
+                    .filter(|i| match params["type"].as_str() {
+                        Some("incoming") => i.inbound,
+                        Some("outgoing") => !i.inbound,
+                        _ => true,
+                    })
+                    .skip(offset)
+                    .take(limit)
+                    .map(nwc_transaction)
+                    .collect::<Vec<_>>();
+                Ok(json!({ "transactions": transactions }))
+            }
+        }
+    }
+
     pub(crate) async fn pay_nwc_invoice<S: MutinyStorage>(
         &self,
         node_manager: &NodeManager<S>,
@@ -129,7 +341,7 @@ impl NostrWalletConnect {
     ) -> Result<Response, MutinyError> {
         let labels = vec![self.profile.name.clone()];
         match node_manager
-            .pay_invoice(from_node, invoice, None, labels)
+            .pay_invoice(from_node, invoice, None, labels, None, None, None)
             .await
         {
             Ok(inv) => {
@@ -148,6 +360,32 @@ impl NostrWalletConnect {
         }
     }
 
+    /// Saves a request to the connection's activity log, failing to do so
+    /// shouldn't stop us from responding so errors are only logged.
+    pub(crate) fn record_activity<S: MutinyStorage>(
+        &self,
+        node_manager: &NodeManager<S>,
+        event_id: EventId,
+        method: &str,
+        amount_msats: Option<u64>,
+        result: NwcActivityResult,
+    ) {
+        let activity = NwcActivity {
+            event_id: event_id.to_hex(),
+            app: self.profile.name.clone(),
+            method: method.to_string(),
+            amount_msats,
+            result,
+            timestamp: utils::now().as_secs(),
+        };
+        if let Err(e) = node_manager
+            .storage
+            .record_nwc_activity(self.profile.index, activity)
+        {
+            log_warn!(node_manager.logger, "Failed to record NWC activity: {e}");
+        }
+    }
+
     /// Handle a Nostr Wallet Connect request
     ///
     /// Returns a response event if one is needed and if the profile needs to be saved to disk
@@ -167,18 +405,63 @@ impl NostrWalletConnect {
             let server_key = self.server_key.secret_key()?;
 
             let decrypted = decrypt(&server_key, &client_pubkey, &event.content)?;
-            let req: Request = Request::from_json(decrypted)?;
-
-            // only respond to pay invoice requests
-            if req.method != Method::PayInvoice {
+            let raw: Value = serde_json::from_str(&decrypted)?;
+
+            // ignore methods we don't know about
+            let method = raw["method"].as_str().unwrap_or_default();
+            let Some(scope) = NwcScope::from_method(method) else {
+                let reason = "Unknown method".to_string();
+                let result = NwcActivityResult::Ignored { reason };
+                self.record_activity(node_manager, event.id, method, None, result);
                 return Ok((None, needs_save));
+            };
+            let amount_msats = raw["params"]["amount"].as_u64();
+
+            // the connection needs to have been given the scope for the method
+            let result = if !self.profile.scopes.contains(&scope) {
+                log_warn!(
+                    node_manager.logger,
+                    "NWC profile {} is not allowed to {}",
+                    self.profile.index,
+                    scope.method()
+                );
+                Some(Err(NwcRequestError::Restricted))
+            } else if scope != NwcScope::PayInvoice {
+                let result = self
+                    .handle_read_request(scope, &raw["params"], node_manager)
+                    .await;
+                Some(result)
+            } else {
+                None
+            };
+            if let Some(result) = result {
+                let activity = match &result {
+                    Ok(_) => NwcActivityResult::Success,
+                    Err(e) => NwcActivityResult::Failed {
+                        code: e.code().to_string(),
+                        message: e.message(),
+                    },
+                };
+                self.record_activity(node_manager, event.id, method, amount_msats, activity);
+
+                let content = nwc_response(scope, result);
+                let response = self.build_response_event(&event, content)?;
+                return Ok((Some(response), needs_save));
             }
 
+            let req: Request = Request::from_json(decrypted)?;
+
             let invoice = Bolt11Invoice::from_str(&req.params.invoice)
                 .map_err(|_| anyhow!("Failed to parse invoice"))?;
+            let amount_msats = invoice.amount_milli_satoshis();
+            let ignore = |reason: &str| NwcActivityResult::Ignored {
+                reason: reason.to_string(),
+            };
 
             // if the invoice has expired, skip it
             if invoice.would_expire(utils::now()) {
+                let result = ignore("Invoice expired");
+                self.record_activity(node_manager, event.id, method, amount_msats, result);
                 return Ok((None, needs_save));
             }
 
@@ -188,12 +471,16 @@ impl NostrWalletConnect {
                     node_manager.logger,
                     "NWC Invoice amount not set, cannot pay: {invoice}"
                 );
+                let result = ignore("Invoice has no amount");
+                self.record_activity(node_manager, event.id, method, amount_msats, result);
                 return Ok((None, needs_save));
             }
 
             // if we have already paid this invoice, skip it
             let node = node_manager.get_node(from_node).await?;
             if node.get_invoice(&invoice).is_ok_and(|i| i.paid) {
+                let result = ignore("Invoice already paid");
+                self.record_activity(node_manager, event.id, method, amount_msats, result);
                 return Ok((None, needs_save));
             }
             drop(node);
@@ -203,6 +490,8 @@ impl NostrWalletConnect {
                 SpendingConditions::SingleUse(mut single_use) => {
                     // check if we have already spent
                     if single_use.spent {
+                        let result = ignore("Connection already spent");
+                        self.record_activity(node_manager, event.id, method, amount_msats, result);
                         return Ok((None, needs_save));
                     }
 
@@ -252,14 +541,19 @@ impl NostrWalletConnect {
                         }
                     };
 
-                    let encrypted = encrypt(&server_key, &client_pubkey, content.as_json())?;
-
-                    let p_tag = Tag::PubKey(event.pubkey, None);
-                    let e_tag = Tag::Event(event.id, None, None);
-                    let response =
-                        EventBuilder::new(Kind::WalletConnectResponse, encrypted, &[p_tag, e_tag])
-                            .to_event(&self.server_key)?;
+                    let result = match content.error {
+                        Some(ref error) => NwcActivityResult::Failed {
+                            code: serde_json::to_value(&error.code)?
+                                .as_str()
+                                .unwrap_or_default()
+                                .to_string(),
+                            message: error.message.clone(),
+                        },
+                        None => NwcActivityResult::Success,
+                    };
+                    self.record_activity(node_manager, event.id, method, amount_msats, result);
 
+                    let response = self.build_response_event(&event, content.as_json())?;
                     return Ok((Some(response), needs_save));
                 }
                 SpendingConditions::RequireApproval => {
@@ -278,6 +572,8 @@ impl NostrWalletConnect {
 
                     if !current.contains(&pending) {
                         current.push(pending);
+                        let result = NwcActivityResult::PendingApproval;
+                        self.record_activity(node_manager, event.id, method, amount_msats, result);
 
                         node_manager
                             .storage
@@ -301,6 +597,8 @@ impl NostrWalletConnect {
             archived: self.profile.archived,
             nwc_uri: self.get_nwc_uri().expect("failed to get nwc uri"),
             spending_conditions: self.profile.spending_conditions.clone(),
+            device_id: self.profile.device_id.clone(),
+            scopes: self.profile.scopes.clone(),
         }
     }
 }
@@ -317,6 +615,12 @@ pub struct NwcProfile {
     pub nwc_uri: String,
     #[serde(default)]
     pub spending_conditions: SpendingConditions,
+    /// The device that created the profile
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// What the connection is allowed to do
+    #[serde(default = "NwcScope::default_scopes")]
+    pub scopes: Vec<NwcScope>,
 }
 
 impl NwcProfile {
@@ -328,6 +632,8 @@ impl NwcProfile {
             archived: self.archived,
             enabled: self.enabled,
             spending_conditions: self.spending_conditions.clone(),
+            device_id: self.device_id.clone(),
+            scopes: self.scopes.clone(),
         }
     }
 }
@@ -362,3 +668,66 @@ impl PendingNwcInvoice {
         self.invoice.would_expire(utils::now())
     }
 }
+
+pub(crate) const NWC_ACTIVITY_PREFIX: &str = "nwc_activity/";
+
+/// The most requests we remember for each connection, the oldest are dropped first
+const MAX_NWC_ACTIVITY: usize = 500;
+
+/// What happened with a NWC request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NwcActivityResult {
+    Success,
+    /// An error was returned to the app, the code is the NIP-47 error code
+    Failed {
+        code: String,
+        message: String,
+    },
+    /// The payment is waiting for the user to approve it
+    PendingApproval,
+    /// The user denied the payment
+    Denied,
+    /// No response was sent, like for an expired or already paid invoice
+    Ignored {
+        reason: String,
+    },
+}
+
+/// A request an app made over a NWC connection and what we did with it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NwcActivity {
+    /// The nostr event id of the request
+    pub event_id: String,
+    /// The name of the connection the app used
+    pub app: String,
+    pub method: String,
+    pub amount_msats: Option<u64>,
+    pub result: NwcActivityResult,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+pub trait NwcActivityStorage {
+    /// Gets the requests made over the connection with the given index, oldest first
+    fn get_nwc_activity(&self, index: u32) -> Result<Vec<NwcActivity>, MutinyError>;
+    fn record_nwc_activity(&self, index: u32, activity: NwcActivity) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> NwcActivityStorage for S {
+    fn get_nwc_activity(&self, index: u32) -> Result<Vec<NwcActivity>, MutinyError> {
+        let activity: Option<Vec<NwcActivity>> =
+            self.get_data(format!("{NWC_ACTIVITY_PREFIX}{index}"))?;
+        Ok(activity.unwrap_or_default())
+    }
+
+    fn record_nwc_activity(&self, index: u32, activity: NwcActivity) -> Result<(), MutinyError> {
+        let mut current = self.get_nwc_activity(index)?;
+        current.push(activity);
+        if current.len() > MAX_NWC_ACTIVITY {
+            let excess = current.len() - MAX_NWC_ACTIVITY;
+            current.drain(..excess);
+        }
+        self.set_data(format!("{NWC_ACTIVITY_PREFIX}{index}"), current, None)
+    }
+}
@@ -0,0 +1,414 @@
+use crate::error::MutinyError;
+use crate::nostr::relays::DEFAULT_RELAYS;
+use crate::nostr::NostrManager;
+use crate::slip39;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use anyhow::anyhow;
+use bip39::Mnemonic;
+use nostr::prelude::{decrypt, encrypt, XOnlyPublicKey};
+use nostr::{Event, EventBuilder, Filter, Keys, Kind, Tag};
+use nostr_sdk::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const SOCIAL_RECOVERY_KEY: &str = "social_recovery";
+pub const HELD_RECOVERY_SHARDS_KEY: &str = "held_recovery_shards";
+
+/// Messages sent as encrypted DMs between the wallet owner, their guardians,
+/// and the recovery key of a wallet being restored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecoveryMessage {
+    /// A shard given to a guardian to hold on to
+    RecoveryShard {
+        owner: XOnlyPublicKey,
+        version: u32,
+        share: String,
+    },
+    /// Tells a guardian they are no longer holding a shard for the owner
+    RecoveryRevoke { owner: XOnlyPublicKey, version: u32 },
+    /// A shard returned by a guardian to the owner's recovery key
+    RecoveryReturn {
+        owner: XOnlyPublicKey,
+        version: u32,
+        share: String,
+    },
+}
+
+/// The wallet owner's current social recovery setup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SocialRecoveryConfig {
+    pub threshold: u8,
+    pub guardians: Vec<XOnlyPublicKey>,
+    /// Incremented every time the shards are rotated
+    pub version: u32,
+    pub last_updated: u64,
+}
+
+/// A shard we are holding as a guardian for someone else
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeldShard {
+    pub owner: XOnlyPublicKey,
+    pub version: u32,
+    pub share: String,
+    pub received: u64,
+}
+
+pub(crate) fn create_dm<T: Serialize>(
+    keys: &Keys,
+    receiver: XOnlyPublicKey,
+    msg: &T,
+) -> anyhow::Result<Event> {
+    let content = serde_json::to_string(msg)?;
+    let encrypted = encrypt(&keys.secret_key()?, &receiver, content)?;
+    let p_tag = Tag::PubKey(receiver, None);
+    let event =
+        EventBuilder::new(Kind::EncryptedDirectMessage, encrypted, &[p_tag]).to_event(keys)?;
+    Ok(event)
+}
+
+/// Returns `None` if the event is not a DM of the given message type
+pub(crate) fn read_dm<T: DeserializeOwned>(keys: &Keys, event: &Event) -> Option<T> {
+    if event.kind != Kind::EncryptedDirectMessage || event.verify().is_err() {
+        return None;
+    }
+    let secret_key = keys.secret_key().ok()?;
+    let decrypted = decrypt(&secret_key, &event.pubkey, &event.content).ok()?;
+    serde_json::from_str(&decrypted).ok()
+}
+
+pub(crate) fn recovery_dm_filter(pubkey: XOnlyPublicKey) -> Filter {
+    Filter::new()
+        .kinds(vec![Kind::EncryptedDirectMessage])
+        .pubkey(pubkey)
+}
+
+/// Combines the shards returned to the recovery key, using the newest
+/// version of the shards we have received.
+pub fn combine_returned_shards(
+    recovery_keys: &Keys,
+    events: &[Event],
+) -> Result<Mnemonic, MutinyError> {
+    let returned: Vec<(u32, String)> = events
+        .iter()
+        .filter_map(|event| match read_dm(recovery_keys, event) {
+            Some(RecoveryMessage::RecoveryReturn { version, share, .. }) => Some((version, share)),
+            _ => None,
+        })
+        .collect();
+
+    let version = returned
+        .iter()
+        .map(|(v, _)| *v)
+        .max()
+        .ok_or(MutinyError::NotFound)?;
+    let mut shares: Vec<String> = returned
+        .into_iter()
+        .filter(|(v, _)| *v == version)
+        .map(|(_, share)| share)
+        .collect();
+    // guardians may return the same shard more than once
+    shares.sort();
+    shares.dedup();
+
+    slip39::combine_shares(&shares)
+}
+
+/// Fetches the shards guardians have returned to the recovery key from the
+/// given relays, or the default relays if none are given, and restores the
+/// mnemonic from them.
+pub async fn fetch_returned_shards(
+    recovery_keys: &Keys,
+    relays: Vec<String>,
+    timeout: Option<Duration>,
+) -> Result<Mnemonic, MutinyError> {
+    let relays = if relays.is_empty() {
+        DEFAULT_RELAYS.iter().map(|r| r.to_string()).collect()
+    } else {
+        relays
+    };
+
+    let client = Client::new(recovery_keys);
+    for url in relays {
+        #[cfg(target_arch = "wasm32")]
+        client.add_relay(url.as_str()).await?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        client.add_relay(url.as_str(), None).await?;
+    }
+
+    client.connect().await;
+
+    let filter = recovery_dm_filter(recovery_keys.public_key());
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    client.disconnect().await?;
+
+    combine_returned_shards(recovery_keys, &events)
+}
+
+impl<S: MutinyStorage> NostrManager<S> {
+    pub fn get_social_recovery_config(&self) -> Result<Option<SocialRecoveryConfig>, MutinyError> {
+        self.storage.get_data(SOCIAL_RECOVERY_KEY)
+    }
+
+    pub fn set_social_recovery_config(
+        &self,
+        config: SocialRecoveryConfig,
+    ) -> Result<(), MutinyError> {
+        self.storage.set_data(SOCIAL_RECOVERY_KEY, config, None)
+    }
+
+    /// Splits the mnemonic into shards for the given guardians and creates the
+    /// DMs to send them. If social recovery was already set up, the shards are
+    /// rotated and guardians that were removed are told to delete theirs.
+    ///
+    /// The returned config should only be saved once all the DMs were sent.
+    ///
+    /// Shards from previous versions can't be combined with new ones, but a
+    /// removed guardian could still cooperate with the others using old shards,
+    /// rotating does not make the old shards useless on their own.
+    pub fn create_recovery_shard_events(
+        &self,
+        mnemonic: &Mnemonic,
+        threshold: u8,
+        guardians: Vec<XOnlyPublicKey>,
+    ) -> anyhow::Result<(Vec<Event>, SocialRecoveryConfig)> {
+        let mut guardians = guardians;
+        guardians.sort();
+        guardians.dedup();
+        if guardians.is_empty() || guardians.len() > u8::MAX as usize {
+            return Err(MutinyError::InvalidArgumentsError.into());
+        }
+
+        let shares = slip39::split_mnemonic(mnemonic, threshold, guardians.len() as u8)?;
+
+        let owner = self.primary_key.public_key();
+        let previous = self.get_social_recovery_config()?;
+        let version = previous.as_ref().map(|c| c.version + 1).unwrap_or(0);
+
+        let mut events = Vec::with_capacity(guardians.len());
+        for (guardian, share) in guardians.iter().zip(shares) {
+            let msg = RecoveryMessage::RecoveryShard {
+                owner,
+                version,
+                share,
+            };
+            events.push(create_dm(&self.primary_key, *guardian, &msg)?);
+        }
+
+        if let Some(previous) = previous {
+            for removed in previous.guardians.iter().filter(|g| !guardians.contains(g)) {
+                let msg = RecoveryMessage::RecoveryRevoke { owner, version };
+                events.push(create_dm(&self.primary_key, *removed, &msg)?);
+            }
+        }
+
+        let config = SocialRecoveryConfig {
+            threshold,
+            guardians,
+            version,
+            last_updated: utils::now().as_secs(),
+        };
+
+        Ok((events, config))
+    }
+
+    pub fn get_held_recovery_shards(&self) -> Result<Vec<HeldShard>, MutinyError> {
+        let shards: Option<Vec<HeldShard>> = self.storage.get_data(HELD_RECOVERY_SHARDS_KEY)?;
+        Ok(shards.unwrap_or_default())
+    }
+
+    /// Handles a recovery DM sent to us as a guardian.
+    /// Returns true if the event was a recovery message.
+    pub fn handle_recovery_message(&self, event: &Event) -> Result<bool, MutinyError> {
+        let msg: RecoveryMessage = match read_dm(&self.primary_key, event) {
+            Some(msg) => msg,
+            None => return Ok(false),
+        };
+
+        let mut shards = self.get_held_recovery_shards()?;
+        match msg {
+            RecoveryMessage::RecoveryShard {
+                owner,
+                version,
+                share,
+            } => {
+                // only the owner can give us their shard
+                if owner != event.pubkey {
+                    return Ok(false);
+                }
+                if shards
+                    .iter()
+                    .any(|s| s.owner == owner && s.version >= version)
+                {
+                    return Ok(true);
+                }
+                shards.retain(|s| s.owner != owner);
+                shards.push(HeldShard {
+                    owner,
+                    version,
+                    share,
+                    received: utils::now().as_secs(),
+                });
+            }
+            RecoveryMessage::RecoveryRevoke { owner, version } => {
+                if owner != event.pubkey {
+                    return Ok(false);
+                }
+                shards.retain(|s| s.owner != owner || s.version >= version);
+            }
+            // returned shards are only read by the recovery key
+            RecoveryMessage::RecoveryReturn { .. } => return Ok(false),
+        }
+
+        self.storage
+            .set_data(HELD_RECOVERY_SHARDS_KEY, shards, None)?;
+        Ok(true)
+    }
+
+    /// Creates a DM returning the shard we hold for the owner to their recovery key.
+    pub fn create_shard_return_event(
+        &self,
+        owner: XOnlyPublicKey,
+        recovery_pubkey: XOnlyPublicKey,
+    ) -> anyhow::Result<Event> {
+        let shard = self
+            .get_held_recovery_shards()?
+            .into_iter()
+            .find(|s| s.owner == owner)
+            .ok_or_else(|| anyhow!("No shard held for {owner}"))?;
+
+        let msg = RecoveryMessage::RecoveryReturn {
+            owner,
+            version: shard.version,
+            share: shard.share,
+        };
+        create_dm(&self.primary_key, recovery_pubkey, &msg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generate_seed;
+    use crate::storage::MemoryStorage;
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use bitcoin::Network;
+
+    fn create_nostr_manager(mnemonic: &Mnemonic) -> NostrManager<MemoryStorage> {
+        let xprivkey =
+            ExtendedPrivKey::new_master(Network::Regtest, &mnemonic.to_seed("")).unwrap();
+        let storage = MemoryStorage::new(None, None, None);
+        NostrManager::from_mnemonic(xprivkey, storage).unwrap()
+    }
+
+    #[test]
+    fn test_social_recovery() {
+        let mnemonic = generate_seed(12).unwrap();
+        let owner = create_nostr_manager(&mnemonic);
+        let guardians: Vec<NostrManager<MemoryStorage>> = (0..3)
+            .map(|_| create_nostr_manager(&generate_seed(12).unwrap()))
+            .collect();
+        let guardian_keys: Vec<XOnlyPublicKey> = guardians
+            .iter()
+            .map(|g| g.primary_key.public_key())
+            .collect();
+
+        let (events, config) = owner
+            .create_recovery_shard_events(&mnemonic, 2, guardian_keys.clone())
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(config.version, 0);
+        assert_eq!(config.threshold, 2);
+
+        // nothing is saved until the shards were sent
+        assert!(owner.get_social_recovery_config().unwrap().is_none());
+        owner.set_social_recovery_config(config.clone()).unwrap();
+        assert_eq!(owner.get_social_recovery_config().unwrap(), Some(config));
+
+        // each guardian only can read their own shard
+        for guardian in guardians.iter() {
+            let handled = events
+                .iter()
+                .filter(|e| guardian.handle_recovery_message(e).unwrap())
+                .count();
+            assert_eq!(handled, 1);
+            assert_eq!(guardian.get_held_recovery_shards().unwrap().len(), 1);
+        }
+
+        // restore with 2 of the guardians
+        let recovery_keys = Keys::generate();
+        let owner_pubkey = owner.primary_key.public_key();
+        let returned: Vec<Event> = guardians[..2]
+            .iter()
+            .map(|g| {
+                g.create_shard_return_event(owner_pubkey, recovery_keys.public_key())
+                    .unwrap()
+            })
+            .collect();
+        assert!(combine_returned_shards(&recovery_keys, &returned[..1]).is_err());
+        let restored = combine_returned_shards(&recovery_keys, &returned).unwrap();
+        assert_eq!(restored, mnemonic);
+    }
+
+    #[test]
+    fn test_social_recovery_rotation() {
+        let mnemonic = generate_seed(12).unwrap();
+        let owner = create_nostr_manager(&mnemonic);
+        let guardians: Vec<NostrManager<MemoryStorage>> = (0..3)
+            .map(|_| create_nostr_manager(&generate_seed(12).unwrap()))
+            .collect();
+        let guardian_keys: Vec<XOnlyPublicKey> = guardians
+            .iter()
+            .map(|g| g.primary_key.public_key())
+            .collect();
+
+        let (events, config) = owner
+            .create_recovery_shard_events(&mnemonic, 2, guardian_keys.clone())
+            .unwrap();
+        owner.set_social_recovery_config(config).unwrap();
+        let removed = &guardians[2];
+        for event in events.iter() {
+            removed.handle_recovery_message(event).unwrap();
+        }
+        assert_eq!(removed.get_held_recovery_shards().unwrap().len(), 1);
+
+        // rotate to only the first 2 guardians
+        let (events, config) = owner
+            .create_recovery_shard_events(&mnemonic, 2, guardian_keys[..2].to_vec())
+            .unwrap();
+        // 2 new shards and 1 revoke
+        assert_eq!(events.len(), 3);
+        assert_eq!(config.version, 1);
+
+        for event in events.iter() {
+            removed.handle_recovery_message(event).unwrap();
+        }
+        assert!(removed.get_held_recovery_shards().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_recovery_shard_from_non_owner() {
+        let mnemonic = generate_seed(12).unwrap();
+        let owner = create_nostr_manager(&mnemonic);
+        let attacker = create_nostr_manager(&generate_seed(12).unwrap());
+        let guardian = create_nostr_manager(&generate_seed(12).unwrap());
+
+        // attacker tries to overwrite the owner's shard
+        let msg = RecoveryMessage::RecoveryShard {
+            owner: owner.primary_key.public_key(),
+            version: 100,
+            share: "fake".to_string(),
+        };
+        let event = create_dm(
+            &attacker.primary_key,
+            guardian.primary_key.public_key(),
+            &msg,
+        )
+        .unwrap();
+        assert!(!guardian.handle_recovery_message(&event).unwrap());
+        assert!(guardian.get_held_recovery_shards().unwrap().is_empty());
+    }
+}
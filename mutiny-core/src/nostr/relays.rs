@@ -0,0 +1,308 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use nostr_sdk::{Client, RelayStatus};
+use serde::{Deserialize, Serialize};
+
+pub const RELAY_POOL_KEY: &str = "nostr_relays";
+
+/// The relays we use until the user configures their own
+pub(crate) const DEFAULT_RELAYS: [&str; 2] =
+    ["wss://nostr.mutinywallet.com", "wss://relay.damus.io"];
+
+/// Seconds to wait before retrying a relay after its first failure, doubled for each failure after
+const BASE_BACKOFF_SECS: u64 = 5;
+/// The longest we will wait before retrying a relay
+pub(crate) const MAX_BACKOFF_SECS: u64 = 600;
+
+/// What we are connecting to relays for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayUse {
+    /// Fetching events, like NWC requests, contacts and recovery shards
+    Read,
+    /// Publishing events, like recovery shards
+    Write,
+}
+
+/// A relay in the user's relay pool, shared by NWC, DMs and contact syncing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayConfig {
+    pub url: String,
+    pub read: bool,
+    pub write: bool,
+    #[serde(default)]
+    pub stats: RelayStats,
+}
+
+impl RelayConfig {
+    fn new(url: &str, read: bool, write: bool) -> Self {
+        Self {
+            url: url.to_string(),
+            read,
+            write,
+            stats: RelayStats::default(),
+        }
+    }
+
+    fn used_for(&self, purpose: RelayUse) -> bool {
+        match purpose {
+            RelayUse::Read => self.read,
+            RelayUse::Write => self.write,
+        }
+    }
+}
+
+/// How reliably we have been able to connect to a relay
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayStats {
+    pub connections: u32,
+    pub failures: u32,
+    /// Failures since we last connected, this is what the reconnect backoff is based on
+    pub consecutive_failures: u32,
+    /// Unix timestamp in seconds of the last successful connection
+    pub last_connected: Option<u64>,
+    /// Unix timestamp in seconds of the last failed connection
+    pub last_failure: Option<u64>,
+}
+
+impl RelayStats {
+    /// Whether we have waited long enough since the last failure to try the relay again
+    pub(crate) fn ready(&self, now: u64) -> bool {
+        match self.last_failure {
+            Some(last_failure) if self.consecutive_failures > 0 => {
+                now >= last_failure + backoff_secs(self.consecutive_failures)
+            }
+            _ => true,
+        }
+    }
+
+    fn record(&mut self, connected: bool, now: u64) {
+        if connected {
+            self.connections += 1;
+            self.consecutive_failures = 0;
+            self.last_connected = Some(now);
+        } else {
+            self.failures += 1;
+            self.consecutive_failures += 1;
+            self.last_failure = Some(now);
+        }
+    }
+}
+
+/// Exponential backoff for the number of failures in a row, capped at [MAX_BACKOFF_SECS]
+pub(crate) fn backoff_secs(failures: u32) -> u64 {
+    if failures == 0 {
+        return 0;
+    }
+
+    let multiplier = 1u64 << (failures - 1).min(16);
+    BASE_BACKOFF_SECS
+        .saturating_mul(multiplier)
+        .min(MAX_BACKOFF_SECS)
+}
+
+/// Relays urls can come back from nostr-sdk with a trailing slash
+fn same_relay(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+pub trait RelayPoolStorage {
+    /// The configured relays, or the defaults if the user hasn't changed them
+    fn get_relay_pool(&self) -> Result<Vec<RelayConfig>, MutinyError>;
+    fn set_relay_pool(&self, relays: Vec<RelayConfig>) -> Result<(), MutinyError>;
+
+    /// Adds a relay to the pool, or updates its read and write flags if it is already there
+    fn add_relay(&self, url: &str, read: bool, write: bool) -> Result<(), MutinyError>;
+    fn remove_relay(&self, url: &str) -> Result<(), MutinyError>;
+
+    /// The urls of the relays to use for reading or writing, skipping relays
+    /// we are backing off from. If there are none, the defaults are used.
+    fn relay_urls(&self, purpose: RelayUse) -> Result<Vec<String>, MutinyError>;
+
+    /// Records whether or not we could connect to a relay
+    fn record_relay_status(&self, url: &str, connected: bool) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> RelayPoolStorage for S {
+    fn get_relay_pool(&self) -> Result<Vec<RelayConfig>, MutinyError> {
+        let relays: Option<Vec<RelayConfig>> = self.get_data(RELAY_POOL_KEY)?;
+        Ok(relays.unwrap_or_else(|| {
+            DEFAULT_RELAYS
+                .iter()
+                .map(|url| RelayConfig::new(url, true, true))
+                .collect()
+        }))
+    }
+
+    fn set_relay_pool(&self, relays: Vec<RelayConfig>) -> Result<(), MutinyError> {
+        self.set_data(RELAY_POOL_KEY, relays, None)
+    }
+
+    fn add_relay(&self, url: &str, read: bool, write: bool) -> Result<(), MutinyError> {
+        let parsed = url::Url::parse(url).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        if parsed.scheme() != "wss" && parsed.scheme() != "ws" {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let mut relays = self.get_relay_pool()?;
+        match relays.iter_mut().find(|r| same_relay(&r.url, url)) {
+            Some(relay) => {
+                relay.read = read;
+                relay.write = write;
+            }
+            None => relays.push(RelayConfig::new(url, read, write)),
+        }
+
+        self.set_relay_pool(relays)
+    }
+
+    fn remove_relay(&self, url: &str) -> Result<(), MutinyError> {
+        let mut relays = self.get_relay_pool()?;
+        let len = relays.len();
+        relays.retain(|r| !same_relay(&r.url, url));
+        if relays.len() == len {
+            return Err(MutinyError::NotFound);
+        }
+
+        self.set_relay_pool(relays)
+    }
+
+    fn relay_urls(&self, purpose: RelayUse) -> Result<Vec<String>, MutinyError> {
+        let relays: Vec<RelayConfig> = self
+            .get_relay_pool()?
+            .into_iter()
+            .filter(|r| r.used_for(purpose))
+            .collect();
+
+        if relays.is_empty() {
+            return Ok(DEFAULT_RELAYS.iter().map(|url| url.to_string()).collect());
+        }
+
+        let now = utils::now().as_secs();
+        let ready: Vec<String> = relays
+            .iter()
+            .filter(|r| r.stats.ready(now))
+            .map(|r| r.url.clone())
+            .collect();
+
+        // if we're backing off from all of them, try them all anyway
+        if ready.is_empty() {
+            return Ok(relays.into_iter().map(|r| r.url).collect());
+        }
+
+        Ok(ready)
+    }
+
+    fn record_relay_status(&self, url: &str, connected: bool) -> Result<(), MutinyError> {
+        let mut relays = self.get_relay_pool()?;
+        let Some(relay) = relays.iter_mut().find(|r| same_relay(&r.url, url)) else {
+            // not one of ours, could be a relay from an NWC profile
+            return Ok(());
+        };
+
+        relay.stats.record(connected, utils::now().as_secs());
+        self.set_relay_pool(relays)
+    }
+}
+
+/// Adds the pool's relays for the given use to the client, along with any `extra`
+/// relays, connects to them and records which ones we could connect to.
+pub(crate) async fn connect_relays<S: MutinyStorage>(
+    storage: &S,
+    client: &Client,
+    purpose: RelayUse,
+    extra: Vec<String>,
+) -> Result<(), MutinyError> {
+    let mut urls = storage.relay_urls(purpose)?;
+    urls.extend(extra);
+    urls.sort();
+    urls.dedup();
+
+    for url in urls {
+        #[cfg(target_arch = "wasm32")]
+        client.add_relay(url.as_str()).await?;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        client.add_relay(url.as_str(), None).await?;
+    }
+
+    client.connect().await;
+
+    for (url, relay) in client.relays().await {
+        let connected = relay.status().await == RelayStatus::Connected;
+        storage.record_relay_status(url.as_str(), connected)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff_secs(0), 0);
+        assert_eq!(backoff_secs(1), 5);
+        assert_eq!(backoff_secs(2), 10);
+        assert_eq!(backoff_secs(4), 40);
+        assert_eq!(backoff_secs(100), MAX_BACKOFF_SECS);
+
+        let mut stats = RelayStats::default();
+        assert!(stats.ready(1_000));
+        stats.record(false, 1_000);
+        stats.record(false, 1_000);
+        assert!(!stats.ready(1_009));
+        assert!(stats.ready(1_010));
+        stats.record(true, 1_010);
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(stats.failures, 2);
+        assert!(stats.ready(1_010));
+    }
+
+    #[test]
+    fn test_relay_pool() {
+        let storage = MemoryStorage::default();
+        assert_eq!(
+            storage.get_relay_pool().unwrap().len(),
+            DEFAULT_RELAYS.len()
+        );
+
+        assert!(storage
+            .add_relay("https://example.com", true, true)
+            .is_err());
+        storage
+            .add_relay("wss://relay.example.com", true, false)
+            .unwrap();
+        let pool = storage.get_relay_pool().unwrap();
+        assert_eq!(pool.len(), DEFAULT_RELAYS.len() + 1);
+
+        // updating a relay keeps a single entry
+        storage
+            .add_relay("wss://relay.example.com/", false, true)
+            .unwrap();
+        let pool = storage.get_relay_pool().unwrap();
+        assert_eq!(pool.len(), DEFAULT_RELAYS.len() + 1);
+        assert!(!pool.last().unwrap().read);
+
+        for url in DEFAULT_RELAYS {
+            storage.remove_relay(url).unwrap();
+        }
+        assert!(storage.remove_relay("wss://unknown.example.com").is_err());
+
+        let write = storage.relay_urls(RelayUse::Write).unwrap();
+        assert_eq!(write, vec!["wss://relay.example.com".to_string()]);
+        // no read relays configured so we fall back to the defaults
+        let read = storage.relay_urls(RelayUse::Read).unwrap();
+        assert_eq!(read.len(), DEFAULT_RELAYS.len());
+
+        // a failing relay is still used if it's the only one
+        storage
+            .record_relay_status("wss://relay.example.com/", false)
+            .unwrap();
+        let pool = storage.get_relay_pool().unwrap();
+        assert_eq!(pool[0].stats.failures, 1);
+        assert_eq!(storage.relay_urls(RelayUse::Write).unwrap(), write);
+    }
+}
@@ -0,0 +1,598 @@
+//! Receiving payments while the wallet is offline.
+//!
+//! Browser nodes are only online while their tab is open, so most payments to
+//! them would fail. Instead our LSP holds the HTLCs for us until we reconnect:
+//!
+//! 1. When we connect to the LSP we send `offline_receive_register`, asking it
+//!    to hold HTLCs for us for up to `max_hold_secs` while we are not connected.
+//! 2. When we reconnect, the LSP sends `held_htlcs` listing what it is holding.
+//! 3. We save those as pending receives and answer with `release_held_htlcs`
+//!    for the ones that pay our invoices, which the LSP then forwards as normal.
+//!
+//! Pending receives are kept until the payment is claimed or the LSP's hold expires.
+//!
+//! # Protocol
+//!
+//! Support is signalled with the optional feature bit [OFFLINE_RECEIVE_FEATURE_BIT]
+//! in the `init` message. We only send these messages to an LSP that sets it, and
+//! only take held HTLCs from one that does. The messages are odd custom lightning
+//! messages, so a peer that doesn't know them ignores them. Integers are big-endian.
+//!
+//! - `offline_receive_register` (type 48001): `u32 max_hold_secs`.
+//! - `held_htlcs` (type 48003): `u16 num_htlcs`, then for each one
+//!   `[32]byte payment_hash`, `u64 amount_msat` and `u64 hold_expiry`,
+//!   the unix time in seconds the LSP fails the HTLC back at.
+//! - `release_held_htlcs` (type 48005): `u16 num_hashes`, then `[32]byte payment_hash`
+//!   for each one.
+//!
+//! Lists have at most 1000 entries, a longer one fails to decode.
+
+use crate::error::MutinyError;
+use crate::event::HTLCStatus;
+use crate::ldkstorage::MutinyNodePersister;
+use crate::logging::MutinyLogger;
+use crate::storage::MutinyStorage;
+use crate::utils::{self, Mutex};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::PublicKey;
+use lightning::io::Read;
+use lightning::ln::features::{InitFeatures, NodeFeatures};
+use lightning::ln::msgs::{DecodeError, LightningError};
+use lightning::ln::peer_handler::CustomMessageHandler;
+use lightning::ln::wire::{CustomMessageReader, Type};
+use lightning::ln::PaymentHash;
+use lightning::util::ser::{Readable, Writeable, Writer};
+use lightning::{log_debug, log_info, log_warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+pub const OFFLINE_RECEIVE_REGISTER_TYPE: u16 = 48_001;
+pub const HELD_HTLCS_TYPE: u16 = 48_003;
+pub const RELEASE_HELD_HTLCS_TYPE: u16 = 48_005;
+
+/// The optional feature bit for offline receives, in the range for experimental features
+pub const OFFLINE_RECEIVE_FEATURE_BIT: usize = 729;
+
+/// The longest we ask the LSP to hold an HTLC for us
+pub const MAX_HOLD_SECS: u32 = 60 * 60 * 24;
+
+/// The most HTLCs listed in one message, keeps it well under the message size limit
+const MAX_HTLCS_PER_MESSAGE: u16 = 1_000;
+
+pub const PENDING_RECEIVE_PREFIX: &str = "pending_receive/";
+
+/// An HTLC the LSP is holding for us
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldHtlc {
+    pub payment_hash: [u8; 32],
+    pub amount_msat: u64,
+    /// Epoch time in seconds the LSP will fail the HTLC back at
+    pub hold_expiry: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OfflineReceiveMessage {
+    /// Asks the peer to hold HTLCs for us while we are offline
+    Register { max_hold_secs: u32 },
+    /// The HTLCs the peer is holding for us, sent when we reconnect
+    HeldHtlcs { htlcs: Vec<HeldHtlc> },
+    /// Asks the peer to forward the held HTLCs with these payment hashes
+    Release { payment_hashes: Vec<[u8; 32]> },
+}
+
+impl OfflineReceiveMessage {
+    /// Reads the message of the given type, returns None if it isn't one of ours
+    pub fn read<R: Read>(msg_type: u16, reader: &mut R) -> Result<Option<Self>, DecodeError> {
+        let msg = match msg_type {
+            OFFLINE_RECEIVE_REGISTER_TYPE => Self::Register {
+                max_hold_secs: Readable::read(reader)?,
+            },
+            HELD_HTLCS_TYPE => {
+                let len = read_len(reader)?;
+                let mut htlcs = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    htlcs.push(HeldHtlc {
+                        payment_hash: Readable::read(reader)?,
+                        amount_msat: Readable::read(reader)?,
+                        hold_expiry: Readable::read(reader)?,
+                    });
+                }
+                Self::HeldHtlcs { htlcs }
+            }
+            RELEASE_HELD_HTLCS_TYPE => {
+                let len = read_len(reader)?;
+                let mut payment_hashes = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    payment_hashes.push(Readable::read(reader)?);
+                }
+                Self::Release { payment_hashes }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(msg))
+    }
+}
+
+fn read_len<R: Read>(reader: &mut R) -> Result<u16, DecodeError> {
+    let len: u16 = Readable::read(reader)?;
+    if len > MAX_HTLCS_PER_MESSAGE {
+        return Err(DecodeError::InvalidValue);
+    }
+    Ok(len)
+}
+
+impl Type for OfflineReceiveMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            Self::Register { .. } => OFFLINE_RECEIVE_REGISTER_TYPE,
+            Self::HeldHtlcs { .. } => HELD_HTLCS_TYPE,
+            Self::Release { .. } => RELEASE_HELD_HTLCS_TYPE,
+        }
+    }
+}
+
+impl Writeable for OfflineReceiveMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), lightning::io::Error> {
+        match self {
+            Self::Register { max_hold_secs } => max_hold_secs.write(writer),
+            Self::HeldHtlcs { htlcs } => {
+                (htlcs.len() as u16).write(writer)?;
+                for htlc in htlcs {
+                    htlc.payment_hash.write(writer)?;
+                    htlc.amount_msat.write(writer)?;
+                    htlc.hold_expiry.write(writer)?;
+                }
+                Ok(())
+            }
+            Self::Release { payment_hashes } => {
+                (payment_hashes.len() as u16).write(writer)?;
+                for hash in payment_hashes {
+                    hash.write(writer)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingReceiveStatus {
+    /// We asked the LSP to forward the HTLC and are waiting for it
+    Releasing,
+    Received,
+    /// The LSP's hold ran out before we could take the payment
+    Expired,
+}
+
+/// A payment the LSP held for us while we were offline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingReceive {
+    pub payment_hash: sha256::Hash,
+    pub amount_msat: u64,
+    pub hold_expiry: u64,
+    pub status: PendingReceiveStatus,
+    /// Epoch time in seconds the LSP first told us about it
+    pub first_seen: u64,
+}
+
+fn pending_receive_key(payment_hash: &sha256::Hash) -> String {
+    format!("{PENDING_RECEIVE_PREFIX}{payment_hash}")
+}
+
+pub trait PendingReceiveStorage {
+    fn get_pending_receive(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<PendingReceive>, MutinyError>;
+
+    fn list_pending_receives(&self) -> Result<Vec<PendingReceive>, MutinyError>;
+
+    fn set_pending_receive(&self, pending: &PendingReceive) -> Result<(), MutinyError>;
+
+    /// Marks a pending receive as received, if there is one for the payment
+    fn mark_pending_receive_received(&self, payment_hash: &sha256::Hash)
+        -> Result<(), MutinyError>;
+
+    /// Marks the pending receives whose hold has run out as expired,
+    /// returns how many were.
+    fn expire_pending_receives(&self, now: u64) -> Result<usize, MutinyError>;
+}
+
+impl<S: MutinyStorage> PendingReceiveStorage for S {
+    fn get_pending_receive(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<PendingReceive>, MutinyError> {
+        self.get_data(pending_receive_key(payment_hash))
+    }
+
+    fn list_pending_receives(&self) -> Result<Vec<PendingReceive>, MutinyError> {
+        let map: HashMap<String, PendingReceive> = self.scan(PENDING_RECEIVE_PREFIX, None)?;
+        let mut pending: Vec<PendingReceive> = map.into_values().collect();
+        pending.sort_by_key(|p| p.first_seen);
+        Ok(pending)
+    }
+
+    fn set_pending_receive(&self, pending: &PendingReceive) -> Result<(), MutinyError> {
+        self.set_data(pending_receive_key(&pending.payment_hash), pending, None)
+    }
+
+    fn mark_pending_receive_received(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<(), MutinyError> {
+        if let Some(mut pending) = self.get_pending_receive(payment_hash)? {
+            pending.status = PendingReceiveStatus::Received;
+            self.set_pending_receive(&pending)?;
+        }
+        Ok(())
+    }
+
+    fn expire_pending_receives(&self, now: u64) -> Result<usize, MutinyError> {
+        let mut count = 0;
+        for mut pending in self.list_pending_receives()? {
+            if pending.status == PendingReceiveStatus::Releasing && pending.hold_expiry <= now {
+                pending.status = PendingReceiveStatus::Expired;
+                self.set_pending_receive(&pending)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Handles the offline receive messages with our LSP
+pub(crate) struct OfflineReceiveHandler<S: MutinyStorage> {
+    persister: Arc<MutinyNodePersister<S>>,
+    /// Only our LSP is trusted to hold HTLCs for us
+    lsp_pubkey: Option<PublicKey>,
+    /// Peers that signalled support when they last connected
+    supported_peers: Mutex<HashSet<PublicKey>>,
+    pending_msgs: Mutex<VecDeque<(PublicKey, OfflineReceiveMessage)>>,
+    logger: Arc<MutinyLogger>,
+}
+
+/// Whether the features have the offline receive bit, as required or optional
+pub(crate) fn supports_offline_receive(features: &InitFeatures) -> bool {
+    let required_bit = OFFLINE_RECEIVE_FEATURE_BIT & !1;
+    features
+        .le_flags()
+        .get(required_bit / 8)
+        .is_some_and(|byte| byte & (0b11 << (required_bit % 8)) != 0)
+}
+
+/// The features we set so peers know we speak the offline receive protocol
+pub(crate) fn offline_receive_init_features() -> InitFeatures {
+    let mut features = InitFeatures::empty();
+    features
+        .set_optional_custom_bit(OFFLINE_RECEIVE_FEATURE_BIT)
+        .expect("offline receive feature bit is an optional custom bit");
+    features
+}
+
+impl<S: MutinyStorage> OfflineReceiveHandler<S> {
+    pub fn new(
+        persister: Arc<MutinyNodePersister<S>>,
+        lsp_pubkey: Option<PublicKey>,
+        logger: Arc<MutinyLogger>,
+    ) -> Self {
+        Self {
+            persister,
+            lsp_pubkey,
+            supported_peers: Mutex::new(HashSet::new()),
+            pending_msgs: Mutex::new(VecDeque::new()),
+            logger,
+        }
+    }
+
+    fn queue(&self, node_id: PublicKey, msg: OfflineReceiveMessage) {
+        if let Ok(mut msgs) = self.pending_msgs.lock() {
+            msgs.push_back((node_id, msg));
+        }
+    }
+
+    fn is_supported(&self, node_id: &PublicKey) -> bool {
+        self.supported_peers
+            .lock()
+            .is_ok_and(|peers| peers.contains(node_id))
+    }
+
+    /// Asks our LSP to hold HTLCs for us if it supports it, called every time a
+    /// peer connects because the LSP forgets about us when we disconnect.
+    pub fn peer_connected(&self, their_node_id: &PublicKey, features: &InitFeatures) {
+        let supported = supports_offline_receive(features);
+        if let Ok(mut peers) = self.supported_peers.lock() {
+            if supported {
+                peers.insert(*their_node_id);
+            } else {
+                peers.remove(their_node_id);
+            }
+        }
+
+        if supported && self.lsp_pubkey.as_ref() == Some(their_node_id) {
+            self.queue(
+                *their_node_id,
+                OfflineReceiveMessage::Register {
+                    max_hold_secs: MAX_HOLD_SECS,
+                },
+            );
+        }
+    }
+
+    /// Saves the HTLCs that pay our invoices and asks the LSP to release them
+    fn handle_held_htlcs(
+        &self,
+        their_node_id: &PublicKey,
+        htlcs: Vec<HeldHtlc>,
+    ) -> Result<(), MutinyError> {
+        if self.lsp_pubkey.as_ref() != Some(their_node_id) {
+            log_warn!(
+                self.logger,
+                "Ignoring held HTLCs from {their_node_id}, it isn't our LSP"
+            );
+            return Ok(());
+        }
+        if !self.is_supported(their_node_id) {
+            log_warn!(
+                self.logger,
+                "Ignoring held HTLCs from {their_node_id}, it didn't signal support"
+            );
+            return Ok(());
+        }
+
+        let now = utils::now().as_secs();
+        let mut payment_hashes = vec![];
+        for htlc in htlcs {
+            if htlc.hold_expiry <= now {
+                continue;
+            }
+
+            // only take payments for invoices we made and haven't been paid yet
+            let unpaid = self
+                .persister
+                .read_payment_info(&PaymentHash(htlc.payment_hash), true, &self.logger)
+                .is_some_and(|info| info.status != HTLCStatus::Succeeded);
+            let payment_hash = sha256::Hash::from_inner(htlc.payment_hash);
+            if !unpaid {
+                log_debug!(
+                    self.logger,
+                    "LSP is holding an HTLC for unknown payment hash {payment_hash}"
+                );
+                continue;
+            }
+
+            let first_seen = self
+                .persister
+                .storage
+                .get_pending_receive(&payment_hash)?
+                .map_or(now, |p| p.first_seen);
+            self.persister
+                .storage
+                .set_pending_receive(&PendingReceive {
+                    payment_hash,
+                    amount_msat: htlc.amount_msat,
+                    hold_expiry: htlc.hold_expiry,
+                    status: PendingReceiveStatus::Releasing,
+                    first_seen,
+                })?;
+            payment_hashes.push(htlc.payment_hash);
+        }
+
+        if !payment_hashes.is_empty() {
+            log_info!(
+                self.logger,
+                "Asking LSP to release {} held HTLCs",
+                payment_hashes.len()
+            );
+            self.queue(
+                *their_node_id,
+                OfflineReceiveMessage::Release { payment_hashes },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: MutinyStorage> CustomMessageReader for OfflineReceiveHandler<S> {
+    type CustomMessage = OfflineReceiveMessage;
+
+    fn read<R: Read>(
+        &self,
+        msg_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        OfflineReceiveMessage::read(msg_type, buffer)
+    }
+}
+
+impl<S: MutinyStorage> CustomMessageHandler for OfflineReceiveHandler<S> {
+    fn handle_custom_message(
+        &self,
+        msg: OfflineReceiveMessage,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        match msg {
+            OfflineReceiveMessage::HeldHtlcs { htlcs } => {
+                if let Err(e) = self.handle_held_htlcs(sender_node_id, htlcs) {
+                    log_warn!(self.logger, "Failed to handle held HTLCs: {e}");
+                }
+            }
+            // we don't hold HTLCs for others
+            OfflineReceiveMessage::Register { .. } | OfflineReceiveMessage::Release { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+        match self.pending_msgs.lock() {
+            Ok(mut msgs) => msgs.drain(..).collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        NodeFeatures::empty()
+    }
+
+    fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
+        offline_receive_init_features()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{MillisatAmount, PaymentInfo};
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning::io::Cursor;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn roundtrip(msg: OfflineReceiveMessage) -> OfflineReceiveMessage {
+        let bytes = msg.encode();
+        OfflineReceiveMessage::read(msg.type_id(), &mut Cursor::new(bytes))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_offline_receive_messages() {
+        log!("test offline receive messages");
+
+        let msgs = vec![
+            OfflineReceiveMessage::Register {
+                max_hold_secs: MAX_HOLD_SECS,
+            },
+            OfflineReceiveMessage::HeldHtlcs {
+                htlcs: vec![HeldHtlc {
+                    payment_hash: [1; 32],
+                    amount_msat: 21_000,
+                    hold_expiry: 1_700_000_000,
+                }],
+            },
+            OfflineReceiveMessage::Release {
+                payment_hashes: vec![[1; 32], [2; 32]],
+            },
+        ];
+        for msg in msgs {
+            assert_eq!(roundtrip(msg.clone()), msg);
+        }
+
+        // other types aren't ours
+        assert_eq!(
+            OfflineReceiveMessage::read(48_007, &mut Cursor::new(vec![])).unwrap(),
+            None
+        );
+
+        // too many htlcs
+        let too_many = (MAX_HTLCS_PER_MESSAGE + 1).encode();
+        assert!(OfflineReceiveMessage::read(HELD_HTLCS_TYPE, &mut Cursor::new(too_many)).is_err());
+    }
+
+    #[test]
+    fn test_handle_held_htlcs() {
+        log!("test handle held htlcs");
+
+        let secp = Secp256k1::new();
+        let lsp = SecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp);
+        let other = SecretKey::from_slice(&[2; 32]).unwrap().public_key(&secp);
+
+        let storage = MemoryStorage::default();
+        let logger = Arc::new(MutinyLogger::default());
+        let persister = Arc::new(MutinyNodePersister::new(
+            "node".to_string(),
+            storage.clone(),
+            logger.clone(),
+        ));
+        let handler = OfflineReceiveHandler::new(persister.clone(), Some(lsp), logger);
+
+        // we register with the LSP when it connects, if it supports it
+        let supported = offline_receive_init_features();
+        assert!(supports_offline_receive(&supported));
+        assert!(!supports_offline_receive(&InitFeatures::empty()));
+        handler.peer_connected(&other, &supported);
+        assert!(handler.get_and_clear_pending_msg().is_empty());
+        handler.peer_connected(&lsp, &InitFeatures::empty());
+        assert!(handler.get_and_clear_pending_msg().is_empty());
+        handler.peer_connected(&lsp, &supported);
+        let msgs = handler.get_and_clear_pending_msg();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].0, lsp);
+
+        let known = [3; 32];
+        let info = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(Some(21_000)),
+            fee_paid_msat: None,
+            bolt11: None,
+            payee_pubkey: None,
+            order_id: None,
+            custom_tlvs: vec![],
+            failure: None,
+            last_update: 0,
+        };
+        persister
+            .persist_payment_info(&PaymentHash(known), &info, true)
+            .unwrap();
+
+        let expiry = utils::now().as_secs() + 3_600;
+        let htlcs = vec![
+            HeldHtlc {
+                payment_hash: known,
+                amount_msat: 21_000,
+                hold_expiry: expiry,
+            },
+            HeldHtlc {
+                payment_hash: [4; 32],
+                amount_msat: 1_000,
+                hold_expiry: expiry,
+            },
+        ];
+
+        // only the LSP can hold htlcs for us
+        handler
+            .handle_custom_message(
+                OfflineReceiveMessage::HeldHtlcs {
+                    htlcs: htlcs.clone(),
+                },
+                &other,
+            )
+            .unwrap();
+        assert!(handler.get_and_clear_pending_msg().is_empty());
+
+        handler
+            .handle_custom_message(OfflineReceiveMessage::HeldHtlcs { htlcs }, &lsp)
+            .unwrap();
+        let msgs = handler.get_and_clear_pending_msg();
+        assert_eq!(
+            msgs,
+            vec![(
+                lsp,
+                OfflineReceiveMessage::Release {
+                    payment_hashes: vec![known]
+                }
+            )]
+        );
+
+        let hash = sha256::Hash::from_inner(known);
+        let pending = storage.list_pending_receives().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].status, PendingReceiveStatus::Releasing);
+
+        storage.mark_pending_receive_received(&hash).unwrap();
+        assert_eq!(
+            storage.get_pending_receive(&hash).unwrap().unwrap().status,
+            PendingReceiveStatus::Received
+        );
+        // received payments don't expire
+        assert_eq!(storage.expire_pending_receives(expiry + 1).unwrap(), 0);
+    }
+}
@@ -7,14 +7,16 @@ use std::sync::{Arc, RwLock};
 use bdk::chain::{BlockId, ConfirmationTime};
 use bdk::psbt::PsbtUtils;
 use bdk::template::DescriptorTemplateOut;
+use bdk::wallet::AddressIndex;
 use bdk::{FeeRate, LocalUtxo, SignOptions, TransactionDetails, Wallet};
 use bdk_esplora::EsploraAsyncExt;
+use bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD;
 use bitcoin::psbt::PartiallySignedTransaction;
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
-use bitcoin::{Address, Network, OutPoint, Script, Transaction, Txid};
+use bitcoin::{Address, LockTime, Network, OutPoint, Script, Transaction, Txid};
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
 use lightning::util::logger::Logger;
-use lightning::{log_debug, log_error, log_warn};
+use lightning::{log_debug, log_error, log_info, log_warn};
 
 use crate::error::MutinyError;
 use crate::fees::MutinyFeeEstimator;
@@ -24,6 +26,9 @@ use crate::multiesplora::MultiEsploraClient;
 use crate::storage::{MutinyStorage, OnChainStorage};
 use crate::utils::{now, sleep};
 
+/// The gap limit used when rescanning, restored wallets may have skipped many addresses
+const RESCAN_STOP_GAP: usize = 100;
+
 #[derive(Clone)]
 pub struct OnChainWallet<S: MutinyStorage> {
     pub wallet: Arc<RwLock<Wallet<OnChainStorage<S>>>>,
@@ -126,7 +131,14 @@ impl<S: MutinyStorage> OnChainWallet<S> {
 
         let update = self
             .blockchain
-            .scan(&checkpoints, spks, txids, core::iter::empty(), 20, 5)
+            .scan(
+                &checkpoints,
+                spks,
+                txids,
+                core::iter::empty(),
+                20,
+                self.blockchain.parallel_requests(),
+            )
             .await?;
 
         // get new wallet lock for writing and apply the update
@@ -167,18 +179,108 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Err(MutinyError::WalletOperationFailed)
     }
 
+    /// Scans every address of the wallet for its history.
+    ///
+    /// Esplora can't filter an address's history by height and every address up to
+    /// the gap limit takes a request no matter how old it is, so the birthday can't
+    /// bound the scan. If the wallet was created at or after the current tip there
+    /// is no history to find though, and a regular sync is enough.
     pub async fn full_sync(&self) -> Result<(), MutinyError> {
+        if let Some(birthday) = self.storage.get_wallet_birthday()? {
+            if birthday >= self.blockchain.get_height().await? {
+                log_info!(
+                    self.logger,
+                    "Wallet was created at the tip, skipping full scan"
+                );
+                return self.sync().await;
+            }
+        }
+
+        self.full_scan(None, 20).await
+    }
+
+    /// Returns the height the wallet should be considered created at: the earliest
+    /// confirmed transaction we have, or the current tip if we don't have any.
+    pub(crate) async fn birthday_height(&self) -> Result<u32, MutinyError> {
+        let earliest = self
+            .list_transactions(false)?
+            .into_iter()
+            .filter_map(|t| match t.confirmation_time {
+                ConfirmationTime::Confirmed { height, .. } => Some(height),
+                ConfirmationTime::Unconfirmed { .. } => None,
+            })
+            .min();
+
+        match earliest {
+            Some(height) => Ok(height),
+            None => Ok(self.blockchain.get_height().await?),
+        }
+    }
+
+    /// Rescans the wallet's history with a larger gap limit, fetching again the blocks
+    /// at or above the given height or unix timestamp. Values below 500,000,000 are
+    /// treated as heights, like a transaction's lock time.
+    ///
+    /// This is useful when restoring an old seed whose history a regular
+    /// sync does not find. Every address's full history is still fetched.
+    pub async fn rescan_from(&self, height_or_timestamp: u64) -> Result<(), MutinyError> {
+        let height = if height_or_timestamp < LOCK_TIME_THRESHOLD as u64 {
+            height_or_timestamp as u32
+        } else {
+            self.height_at_time(height_or_timestamp).await?
+        };
+
+        log_info!(
+            self.logger,
+            "Rescanning on-chain wallet from block {height}"
+        );
+        self.full_scan(Some(height), RESCAN_STOP_GAP).await
+    }
+
+    /// Finds the height of the first block mined at or after the given unix timestamp,
+    /// minus a day of blocks because block times are not strictly increasing.
+    async fn height_at_time(&self, timestamp: u64) -> Result<u32, MutinyError> {
+        let mut low = 0;
+        let mut high = self.blockchain.get_height().await?;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let hash = self.blockchain.get_block_hash(mid).await?;
+            let header = self.blockchain.get_header_by_hash(&hash).await?;
+            if (header.time as u64) < timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        Ok(low.saturating_sub(144))
+    }
+
+    /// Scans every address of the wallet up to the given gap limit. If a height is given,
+    /// the checkpoints at or above it are not trusted and are fetched again.
+    async fn full_scan(
+        &self,
+        from_height: Option<u32>,
+        stop_gap: usize,
+    ) -> Result<(), MutinyError> {
         // get first wallet lock that only needs to read
         let (checkpoints, spks) = {
             if let Ok(wallet) = self.wallet.try_read() {
-                let checkpoints = wallet.checkpoints();
+                let checkpoints = match from_height {
+                    Some(height) => wallet
+                        .checkpoints()
+                        .range(..height)
+                        .map(|(h, hash)| (*h, *hash))
+                        .collect(),
+                    None => wallet.checkpoints().clone(),
+                };
                 let spks = wallet
                     .spks_of_all_keychains()
                     .into_iter()
                     .map(|(k, spks)| (k, spks))
                     .collect();
 
-                (checkpoints.clone(), spks)
+                (checkpoints, spks)
             } else {
                 log_error!(self.logger, "Could not get wallet lock to sync");
                 return Err(MutinyError::WalletOperationFailed);
@@ -192,8 +294,8 @@ impl<S: MutinyStorage> OnChainWallet<S> {
                 spks,
                 core::iter::empty(),
                 core::iter::empty(),
-                20,
-                5,
+                stop_gap,
+                self.blockchain.parallel_requests(),
             )
             .await?;
 
@@ -460,17 +562,29 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Ok(psbt)
     }
 
-    pub async fn send(
+    /// Creates and labels a signed transaction without broadcasting it
+    pub fn create_send_transaction(
         &self,
         destination_address: Address,
         amount: u64,
         labels: Vec<String>,
         fee_rate: Option<f32>,
-    ) -> Result<Txid, MutinyError> {
+    ) -> Result<Transaction, MutinyError> {
         let psbt = self.create_signed_psbt(destination_address, amount, fee_rate)?;
         self.label_psbt(&psbt, labels)?;
 
-        let raw_transaction = psbt.extract_tx();
+        Ok(psbt.extract_tx())
+    }
+
+    pub async fn send(
+        &self,
+        destination_address: Address,
+        amount: u64,
+        labels: Vec<String>,
+        fee_rate: Option<f32>,
+    ) -> Result<Txid, MutinyError> {
+        let raw_transaction =
+            self.create_send_transaction(destination_address, amount, labels, fee_rate)?;
         let txid = raw_transaction.txid();
 
         self.broadcast_transaction(raw_transaction.clone()).await?;
@@ -530,6 +644,86 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Ok(txid)
     }
 
+    /// Creates a signed transaction sweeping the whole wallet to the given script
+    /// that can't be included in a block until after the given unix timestamp.
+    pub(crate) fn create_timelocked_sweep_psbt(
+        &self,
+        spk: Script,
+        unlock_time: u32,
+        fee_rate: Option<f32>,
+    ) -> Result<PartiallySignedTransaction, MutinyError> {
+        let lock_time =
+            LockTime::from_time(unlock_time).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        let mut wallet = self.wallet.try_write()?;
+
+        let fee_rate = if let Some(rate) = fee_rate {
+            FeeRate::from_sat_per_vb(rate)
+        } else {
+            let sat_per_kwu = self
+                .fees
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+            FeeRate::from_sat_per_kwu(sat_per_kwu as f32)
+        };
+        let (mut psbt, details) = {
+            let mut builder = wallet.build_tx();
+            builder
+                .drain_wallet()
+                .drain_to(spk)
+                .nlocktime(lock_time)
+                .enable_rbf()
+                .fee_rate(fee_rate);
+            builder.finish()?
+        };
+        log_debug!(self.logger, "Transaction details: {details:#?}");
+        let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+        log_debug!(self.logger, "finalized: {finalized}");
+        Ok(psbt)
+    }
+
+    /// Spends the given utxos back to a new address in the wallet.
+    /// This invalidates any other transaction that was signed spending them.
+    pub(crate) async fn spend_to_self(
+        &self,
+        utxos: &[OutPoint],
+        fee_rate: Option<f32>,
+    ) -> Result<Txid, MutinyError> {
+        let psbt = {
+            let mut wallet = self.wallet.try_write()?;
+
+            let fee_rate = if let Some(rate) = fee_rate {
+                FeeRate::from_sat_per_vb(rate)
+            } else {
+                let sat_per_kwu = self
+                    .fees
+                    .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+                FeeRate::from_sat_per_kwu(sat_per_kwu as f32)
+            };
+            let spk = wallet
+                .get_address(AddressIndex::New)
+                .address
+                .script_pubkey();
+            let (mut psbt, details) = {
+                let mut builder = wallet.build_tx();
+                builder
+                    .manually_selected_only()
+                    .add_utxos(utxos)?
+                    .drain_to(spk)
+                    .enable_rbf()
+                    .fee_rate(fee_rate);
+                builder.finish()?
+            };
+            log_debug!(self.logger, "Transaction details: {details:#?}");
+            let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+            log_debug!(self.logger, "finalized: {finalized}");
+            psbt
+        };
+
+        let raw_transaction = psbt.extract_tx();
+        let txid = raw_transaction.txid();
+        self.broadcast_transaction(raw_transaction).await?;
+        Ok(txid)
+    }
+
     /// Creates a PSBT that spends all the selected utxos a given output.
     /// A fee rate is not specified because it should be precalculated
     /// in the output's amount.
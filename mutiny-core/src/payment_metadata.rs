@@ -0,0 +1,175 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::sha256;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+pub const PAYMENT_METADATA_PREFIX: &str = "payment_metadata/";
+
+/// The longest label, note or comment we keep, in characters
+pub const MAX_PAYMENT_NOTE_LEN: usize = 640;
+
+/// User supplied details about a lightning payment, kept alongside its history.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentMetadata {
+    /// A free-form label for the payment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The payer note included with a BOLT 12 payment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payer_note: Option<String>,
+    /// The comment sent to a LNURL-pay endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl PaymentMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.label.is_none()
+            && self.payer_note.is_none()
+            && self.comment.is_none()
+            && self.tags.is_empty()
+    }
+
+    /// Trims everything, drops blank values and duplicate tags,
+    /// and rejects notes that are too long.
+    pub(crate) fn normalize(self) -> Result<Self, MutinyError> {
+        let clean = |s: Option<String>| -> Result<Option<String>, MutinyError> {
+            match s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty()) {
+                Some(s) if s.chars().count() > MAX_PAYMENT_NOTE_LEN => {
+                    Err(MutinyError::InvalidArgumentsError)
+                }
+                s => Ok(s),
+            }
+        };
+
+        let mut tags: Vec<String> = vec![];
+        for tag in self.tags {
+            let tag = tag.trim().to_string();
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        Ok(Self {
+            label: clean(self.label)?,
+            payer_note: clean(self.payer_note)?,
+            comment: clean(self.comment)?,
+            tags,
+        })
+    }
+}
+
+fn payment_metadata_key(payment_hash: &sha256::Hash) -> String {
+    format!("{PAYMENT_METADATA_PREFIX}{payment_hash}")
+}
+
+pub trait PaymentMetadataStorage {
+    fn get_payment_metadata(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<PaymentMetadata, MutinyError>;
+
+    fn get_all_payment_metadata(
+        &self,
+    ) -> Result<HashMap<sha256::Hash, PaymentMetadata>, MutinyError>;
+
+    /// Replaces the metadata of a payment, empty metadata removes it
+    fn set_payment_metadata(
+        &self,
+        payment_hash: &sha256::Hash,
+        metadata: PaymentMetadata,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> PaymentMetadataStorage for S {
+    fn get_payment_metadata(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<PaymentMetadata, MutinyError> {
+        let metadata: Option<PaymentMetadata> =
+            self.get_data(payment_metadata_key(payment_hash))?;
+        Ok(metadata.unwrap_or_default())
+    }
+
+    fn get_all_payment_metadata(
+        &self,
+    ) -> Result<HashMap<sha256::Hash, PaymentMetadata>, MutinyError> {
+        let map: HashMap<String, PaymentMetadata> = self.scan(PAYMENT_METADATA_PREFIX, None)?;
+        Ok(map
+            .into_iter()
+            .filter_map(|(key, metadata)| {
+                let hash = key.strip_prefix(PAYMENT_METADATA_PREFIX)?;
+                Some((sha256::Hash::from_str(hash).ok()?, metadata))
+            })
+            .collect())
+    }
+
+    fn set_payment_metadata(
+        &self,
+        payment_hash: &sha256::Hash,
+        metadata: PaymentMetadata,
+    ) -> Result<(), MutinyError> {
+        let metadata = metadata.normalize()?;
+        let key = payment_metadata_key(payment_hash);
+        if metadata.is_empty() {
+            self.delete(&[key])
+        } else {
+            self.set_data(key, metadata, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_payment_metadata_storage() {
+        log!("test payment metadata storage");
+
+        let storage = MemoryStorage::default();
+        let hash = sha256::Hash::hash(&[1; 32]);
+        assert!(storage.get_payment_metadata(&hash).unwrap().is_empty());
+
+        let metadata = PaymentMetadata {
+            label: Some(" coffee ".to_string()),
+            payer_note: None,
+            comment: Some("thanks!".to_string()),
+            tags: vec!["food".to_string(), "food".to_string(), " ".to_string()],
+        };
+        storage.set_payment_metadata(&hash, metadata).unwrap();
+
+        let expected = PaymentMetadata {
+            label: Some("coffee".to_string()),
+            payer_note: None,
+            comment: Some("thanks!".to_string()),
+            tags: vec!["food".to_string()],
+        };
+        assert_eq!(storage.get_payment_metadata(&hash).unwrap(), expected);
+        let all = storage.get_all_payment_metadata().unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all.get(&hash), Some(&expected));
+
+        let too_long = PaymentMetadata {
+            label: Some("a".repeat(MAX_PAYMENT_NOTE_LEN + 1)),
+            ..Default::default()
+        };
+        assert!(storage.set_payment_metadata(&hash, too_long).is_err());
+
+        // clearing everything removes the record
+        storage
+            .set_payment_metadata(&hash, PaymentMetadata::default())
+            .unwrap();
+        assert!(storage.get_all_payment_metadata().unwrap().is_empty());
+    }
+}
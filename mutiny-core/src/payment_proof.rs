@@ -0,0 +1,203 @@
+use crate::error::MutinyError;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+use lightning::util::message_signing;
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the format of a proof changes
+pub const PAYMENT_PROOF_VERSION: u32 = 1;
+
+/// Proves an outgoing lightning payment was made: only the payee knows the
+/// preimage until it is paid, and the invoice is signed by the payee.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentProof {
+    pub version: u32,
+    pub invoice: Bolt11Invoice,
+    /// Hex encoded preimage, it hashes to the invoice's payment hash
+    pub preimage: String,
+    /// Ties the payment to our node, so the payer can be identified too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payer: Option<PayerSignature>,
+}
+
+/// A message signed by the paying node, in the same format as lnd's and
+/// core lightning's `signmessage`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayerSignature {
+    pub node_id: PublicKey,
+    pub message: String,
+    /// zbase32 encoded recoverable signature
+    pub signature: String,
+}
+
+/// What a valid [PaymentProof] proves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedPaymentProof {
+    pub payment_hash: sha256::Hash,
+    pub payee: PublicKey,
+    pub amount_msat: Option<u64>,
+    pub description: Option<String>,
+    /// The node that paid, if the proof was signed
+    pub payer: Option<PublicKey>,
+    pub message: Option<String>,
+}
+
+/// The message the payer actually signs, so a signature
+/// for one payment can't be passed off as one for another
+fn signed_message(payment_hash: &sha256::Hash, message: &str) -> String {
+    format!("mutiny payment proof {payment_hash}: {message}")
+}
+
+impl PaymentProof {
+    pub(crate) fn new(
+        invoice: Bolt11Invoice,
+        preimage: [u8; 32],
+        payer: Option<(&SecretKey, PublicKey, String)>,
+    ) -> Result<Self, MutinyError> {
+        let payer = match payer {
+            Some((secret_key, node_id, message)) => {
+                let to_sign = signed_message(invoice.payment_hash(), &message);
+                let signature = message_signing::sign(to_sign.as_bytes(), secret_key)
+                    .map_err(|_| MutinyError::InvalidPaymentProof)?;
+                Some(PayerSignature {
+                    node_id,
+                    message,
+                    signature,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            version: PAYMENT_PROOF_VERSION,
+            invoice,
+            preimage: preimage.to_hex(),
+            payer,
+        })
+    }
+}
+
+/// Checks the preimage pays the invoice, the invoice is signed by its payee
+/// and, if there is one, the payer's signature.
+pub fn verify_payment_proof(proof: &PaymentProof) -> Result<VerifiedPaymentProof, MutinyError> {
+    if proof.version > PAYMENT_PROOF_VERSION {
+        return Err(MutinyError::InvalidPaymentProof);
+    }
+
+    proof
+        .invoice
+        .check_signature()
+        .map_err(|_| MutinyError::InvalidPaymentProof)?;
+
+    let preimage: [u8; 32] =
+        FromHex::from_hex(&proof.preimage).map_err(|_| MutinyError::InvalidPaymentProof)?;
+    let payment_hash = *proof.invoice.payment_hash();
+    if sha256::Hash::hash(&preimage) != payment_hash {
+        return Err(MutinyError::InvalidPaymentProof);
+    }
+
+    if let Some(payer) = proof.payer.as_ref() {
+        let to_sign = signed_message(&payment_hash, &payer.message);
+        if !message_signing::verify(to_sign.as_bytes(), &payer.signature, &payer.node_id) {
+            return Err(MutinyError::InvalidPaymentProof);
+        }
+    }
+
+    let payee = proof
+        .invoice
+        .payee_pub_key()
+        .copied()
+        .unwrap_or_else(|| proof.invoice.recover_payee_pub_key());
+    let description = match proof.invoice.description() {
+        Bolt11InvoiceDescription::Direct(d) if !d.is_empty() => Some(d.to_string()),
+        _ => None,
+    };
+
+    Ok(VerifiedPaymentProof {
+        payment_hash,
+        payee,
+        amount_msat: proof.invoice.amount_milli_satoshis(),
+        description,
+        payer: proof.payer.as_ref().map(|p| p.node_id),
+        message: proof.payer.as_ref().map(|p| p.message.clone()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::Network;
+    use lightning_invoice::{Currency, InvoiceBuilder};
+    use std::time::Duration;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn create_invoice(preimage: [u8; 32], payee_key: &SecretKey) -> Bolt11Invoice {
+        let secp = Secp256k1::new();
+        InvoiceBuilder::new(Currency::from(Network::Regtest))
+            .description("coffee".to_string())
+            .payment_hash(sha256::Hash::hash(&preimage))
+            .payment_secret(lightning::ln::PaymentSecret([0; 32]))
+            .duration_since_epoch(Duration::from_secs(1_700_000_000))
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(21_000)
+            .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, payee_key))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_payment_proof() {
+        log!("test payment proof");
+
+        let secp = Secp256k1::new();
+        let payee_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let payer_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let payer_id = payer_key.public_key(&secp);
+        let preimage = [3; 32];
+        let invoice = create_invoice(preimage, &payee_key);
+
+        let proof = PaymentProof::new(
+            invoice.clone(),
+            preimage,
+            Some((&payer_key, payer_id, "order 42".to_string())),
+        )
+        .unwrap();
+        let verified = verify_payment_proof(&proof).unwrap();
+        assert_eq!(verified.payment_hash, *invoice.payment_hash());
+        assert_eq!(verified.payee, payee_key.public_key(&secp));
+        assert_eq!(verified.amount_msat, Some(21_000));
+        assert_eq!(verified.description, Some("coffee".to_string()));
+        assert_eq!(verified.payer, Some(payer_id));
+        assert_eq!(verified.message, Some("order 42".to_string()));
+
+        // survives a round trip through json
+        let json = serde_json::to_string(&proof).unwrap();
+        let decoded: PaymentProof = serde_json::from_str(&json).unwrap();
+        assert!(verify_payment_proof(&decoded).is_ok());
+
+        // the wrong preimage
+        let mut bad = proof.clone();
+        bad.preimage = [4; 32].to_hex();
+        assert!(verify_payment_proof(&bad).is_err());
+
+        // a signature can't be moved to another message
+        let mut bad = proof.clone();
+        bad.payer.as_mut().unwrap().message = "order 43".to_string();
+        assert!(verify_payment_proof(&bad).is_err());
+
+        // or claimed by another node
+        let mut bad = proof;
+        bad.payer.as_mut().unwrap().node_id = payee_key.public_key(&secp);
+        assert!(verify_payment_proof(&bad).is_err());
+
+        // unsigned proofs only prove the payment was made
+        let unsigned = PaymentProof::new(invoice, preimage, None).unwrap();
+        let verified = verify_payment_proof(&unsigned).unwrap();
+        assert_eq!(verified.payer, None);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::sha256;
+use lightning::ln::channelmanager::Retry;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const PAYMENT_RETRY_PREFIX: &str = "payment_retry/";
+pub const FEE_CAP_KEY: &str = "fee_cap";
+pub const MPP_CONFIG_KEY: &str = "mpp_config";
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 15;
+/// LDK's default for the most paths a payment is split over
+const DEFAULT_MAX_PARTS: u8 = 10;
+
+/// How hard to try to pay an invoice before giving up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// How many times to retry with a new route after a failed attempt
+    pub max_attempts: u32,
+    /// Stop retrying after this many seconds, otherwise retrying
+    /// continues until the attempts run out
+    pub timeout_secs: Option<u64>,
+    /// The most to pay in routing fees, in msats
+    pub max_fee_msat: Option<u64>,
+    /// The most to pay in routing fees, as a percent of the amount
+    pub max_fee_percent: Option<f64>,
+    /// Send the payment over a single path, even if the invoice allows splitting it
+    #[serde(default)]
+    pub disable_mpp: bool,
+    /// The most paths to split the payment over, overrides the MPP config
+    #[serde(default)]
+    pub max_parts: Option<u8>,
+    /// The smallest part to send in msats, overrides the MPP config
+    #[serde(default)]
+    pub min_part_msat: Option<u64>,
+    /// The most blocks our funds can be locked up for if the payment gets stuck.
+    /// A lower limit rules out longer routes, so the payment is more likely to fail.
+    #[serde(default)]
+    pub max_total_cltv_expiry_delta: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            timeout_secs: None,
+            max_fee_msat: None,
+            max_fee_percent: None,
+            disable_mpp: false,
+            max_parts: None,
+            min_part_msat: None,
+            max_total_cltv_expiry_delta: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        if self.timeout_secs == Some(0)
+            || !valid_percent(self.max_fee_percent)
+            || self.max_parts == Some(0)
+            || self.min_part_msat == Some(0)
+            || self.max_total_cltv_expiry_delta == Some(0)
+        {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn retry(&self) -> Retry {
+        Retry::Attempts(self.max_attempts as _)
+    }
+
+    /// The most to pay in routing fees for a payment of `amount_msat`,
+    /// the lower of the two caps if both are set
+    pub(crate) fn max_fee_msat(&self, amount_msat: u64) -> Option<u64> {
+        fee_cap_msat(self.max_fee_msat, self.max_fee_percent, amount_msat)
+    }
+}
+
+/// The most to pay in routing fees on any payment. A payment's retry
+/// policy can set a lower cap, the lowest of the caps is used.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FeeCap {
+    /// The most to pay in routing fees, in msats
+    pub max_fee_msat: Option<u64>,
+    /// The most to pay in routing fees, as a percent of the amount
+    pub max_fee_percent: Option<f64>,
+}
+
+impl FeeCap {
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        if !valid_percent(self.max_fee_percent) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    /// The most to pay in routing fees for a payment of `amount_msat`,
+    /// the lower of the two caps if both are set
+    pub(crate) fn max_fee_msat(&self, amount_msat: u64) -> Option<u64> {
+        fee_cap_msat(self.max_fee_msat, self.max_fee_percent, amount_msat)
+    }
+}
+
+/// How payments are split into multiple parts, so amounts larger than
+/// any one of our channels can still be sent
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MppConfig {
+    /// The most paths a payment is split over. By default this is LDK's
+    /// default, or one per channel if we have more channels than that.
+    pub max_parts: Option<u8>,
+    /// The smallest part to send, in msats. Small parts each pay the base fee
+    /// of every hop, so lots of them get expensive.
+    pub min_part_msat: Option<u64>,
+}
+
+impl MppConfig {
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        if self.max_parts == Some(0) || self.min_part_msat == Some(0) {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        Ok(())
+    }
+
+    /// The most paths to split a payment of `amount_msat` over, keeping every part
+    /// above the minimum size. The payment's retry policy overrides the config, and
+    /// `channels` is how many channels we can send over. Always at least one.
+    pub(crate) fn max_path_count(
+        &self,
+        amount_msat: u64,
+        policy: Option<&RetryPolicy>,
+        channels: usize,
+    ) -> u8 {
+        if policy.is_some_and(|p| p.disable_mpp) {
+            return 1;
+        }
+
+        let max_parts = policy
+            .and_then(|p| p.max_parts)
+            .or(self.max_parts)
+            .unwrap_or_else(|| channels.clamp(DEFAULT_MAX_PARTS as usize, u8::MAX as usize) as u8);
+        let min_part_msat = policy.and_then(|p| p.min_part_msat).or(self.min_part_msat);
+        let max_parts = match min_part_msat {
+            Some(min_part_msat) => (amount_msat / min_part_msat).min(max_parts as u64) as u8,
+            None => max_parts,
+        };
+        max_parts.max(1)
+    }
+}
+
+fn valid_percent(percent: Option<f64>) -> bool {
+    percent.map_or(true, |p| (0.0..=100.0).contains(&p))
+}
+
+fn fee_cap_msat(
+    max_fee_msat: Option<u64>,
+    max_fee_percent: Option<f64>,
+    amount_msat: u64,
+) -> Option<u64> {
+    let percent_cap = max_fee_percent.map(|p| (amount_msat as f64 * p / 100.0) as u64);
+    match (max_fee_msat, percent_cap) {
+        (Some(cap), Some(percent_cap)) => Some(cap.min(percent_cap)),
+        (cap, percent_cap) => cap.or(percent_cap),
+    }
+}
+
+/// The retry policy of an outgoing payment. It is kept while the payment
+/// is in flight so a reloaded wallet keeps to it for LDK's retries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentRetry {
+    pub payment_hash: sha256::Hash,
+    pub policy: RetryPolicy,
+    pub started_at: u64,
+}
+
+impl PaymentRetry {
+    fn key(&self) -> String {
+        payment_retry_key(&self.payment_hash)
+    }
+
+    /// Returns true if the payment has been retried for longer than the policy allows
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        self.policy
+            .timeout_secs
+            .is_some_and(|timeout| now >= self.started_at + timeout)
+    }
+}
+
+fn payment_retry_key(payment_hash: &sha256::Hash) -> String {
+    format!("{PAYMENT_RETRY_PREFIX}{payment_hash}")
+}
+
+pub trait PaymentRetryStorage {
+    fn get_payment_retry(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<PaymentRetry>, MutinyError>;
+    fn persist_payment_retry(&self, retry: PaymentRetry) -> Result<(), MutinyError>;
+    fn list_payment_retries(&self) -> Result<Vec<PaymentRetry>, MutinyError>;
+    fn delete_payment_retry(&self, payment_hash: &sha256::Hash) -> Result<(), MutinyError>;
+    /// Returns the fee cap for all payments, if one has been set
+    fn get_fee_cap(&self) -> Result<FeeCap, MutinyError>;
+    fn set_fee_cap(&self, fee_cap: FeeCap) -> Result<(), MutinyError>;
+    /// Returns how payments are split, the defaults if it hasn't been set
+    fn get_mpp_config(&self) -> Result<MppConfig, MutinyError>;
+    fn set_mpp_config(&self, config: MppConfig) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> PaymentRetryStorage for S {
+    fn get_payment_retry(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<Option<PaymentRetry>, MutinyError> {
+        self.get_data(payment_retry_key(payment_hash))
+    }
+
+    fn persist_payment_retry(&self, retry: PaymentRetry) -> Result<(), MutinyError> {
+        self.set_data(retry.key(), retry, None)
+    }
+
+    fn list_payment_retries(&self) -> Result<Vec<PaymentRetry>, MutinyError> {
+        let map: HashMap<String, PaymentRetry> = self.scan(PAYMENT_RETRY_PREFIX, None)?;
+        Ok(map.into_values().collect())
+    }
+
+    fn delete_payment_retry(&self, payment_hash: &sha256::Hash) -> Result<(), MutinyError> {
+        self.delete(&[payment_retry_key(payment_hash)])
+    }
+
+    fn get_fee_cap(&self) -> Result<FeeCap, MutinyError> {
+        let fee_cap: Option<FeeCap> = self.get_data(FEE_CAP_KEY)?;
+        Ok(fee_cap.unwrap_or_default())
+    }
+
+    fn set_fee_cap(&self, fee_cap: FeeCap) -> Result<(), MutinyError> {
+        fee_cap.validate()?;
+        self.set_data(FEE_CAP_KEY, fee_cap, None)
+    }
+
+    fn get_mpp_config(&self) -> Result<MppConfig, MutinyError> {
+        let config: Option<MppConfig> = self.get_data(MPP_CONFIG_KEY)?;
+        Ok(config.unwrap_or_default())
+    }
+
+    fn set_mpp_config(&self, config: MppConfig) -> Result<(), MutinyError> {
+        config.validate()?;
+        self.set_data(MPP_CONFIG_KEY, config, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_retry_policy_max_fee() {
+        log!("test retry policy max fee");
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.max_fee_msat(1_000_000), None);
+
+        let policy = RetryPolicy {
+            max_fee_msat: Some(5_000),
+            ..Default::default()
+        };
+        assert_eq!(policy.max_fee_msat(1_000_000), Some(5_000));
+
+        let policy = RetryPolicy {
+            max_fee_msat: Some(5_000),
+            max_fee_percent: Some(0.1),
+            ..Default::default()
+        };
+        assert_eq!(policy.max_fee_msat(1_000_000), Some(1_000));
+        assert_eq!(policy.max_fee_msat(10_000_000), Some(5_000));
+
+        let policy = RetryPolicy {
+            max_fee_percent: Some(101.0),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        let policy = RetryPolicy {
+            timeout_secs: Some(0),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        let policy = RetryPolicy {
+            max_total_cltv_expiry_delta: Some(0),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn test_payment_retry_storage() {
+        log!("test payment retry storage");
+
+        let storage = MemoryStorage::default();
+        let retry = PaymentRetry {
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            policy: RetryPolicy {
+                timeout_secs: Some(60),
+                ..Default::default()
+            },
+            started_at: 100,
+        };
+        assert!(!retry.is_expired(159));
+        assert!(retry.is_expired(160));
+
+        storage.persist_payment_retry(retry.clone()).unwrap();
+        assert_eq!(
+            storage.get_payment_retry(&retry.payment_hash).unwrap(),
+            Some(retry.clone())
+        );
+        assert_eq!(storage.list_payment_retries().unwrap(), vec![retry.clone()]);
+
+        storage.delete_payment_retry(&retry.payment_hash).unwrap();
+        assert!(storage.list_payment_retries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fee_cap_storage() {
+        log!("test fee cap storage");
+
+        let storage = MemoryStorage::default();
+        assert_eq!(storage.get_fee_cap().unwrap(), FeeCap::default());
+        assert_eq!(storage.get_fee_cap().unwrap().max_fee_msat(1_000_000), None);
+
+        let fee_cap = FeeCap {
+            max_fee_msat: Some(10_000),
+            max_fee_percent: Some(0.5),
+        };
+        storage.set_fee_cap(fee_cap.clone()).unwrap();
+        assert_eq!(storage.get_fee_cap().unwrap(), fee_cap);
+        assert_eq!(fee_cap.max_fee_msat(1_000_000), Some(5_000));
+        assert_eq!(fee_cap.max_fee_msat(10_000_000), Some(10_000));
+
+        let bad = FeeCap {
+            max_fee_percent: Some(-1.0),
+            ..Default::default()
+        };
+        assert!(storage.set_fee_cap(bad).is_err());
+        assert_eq!(storage.get_fee_cap().unwrap(), fee_cap);
+    }
+
+    #[test]
+    fn test_mpp_config() {
+        log!("test mpp config");
+
+        let storage = MemoryStorage::default();
+        let config = storage.get_mpp_config().unwrap();
+        assert_eq!(config, MppConfig::default());
+        assert_eq!(config.max_path_count(1_000_000, None, 2), DEFAULT_MAX_PARTS);
+        // with lots of small channels we can use every one of them
+        assert_eq!(config.max_path_count(1_000_000, None, 30), 30);
+
+        let config = MppConfig {
+            max_parts: Some(4),
+            min_part_msat: Some(100_000),
+        };
+        storage.set_mpp_config(config.clone()).unwrap();
+        assert_eq!(storage.get_mpp_config().unwrap(), config);
+        // parts are kept above the minimum size
+        assert_eq!(config.max_path_count(250_000, None, 30), 2);
+        assert_eq!(config.max_path_count(10_000_000, None, 30), 4);
+        // a payment smaller than the minimum part is still sent whole
+        assert_eq!(config.max_path_count(50_000, None, 30), 1);
+
+        // the payment's policy overrides the config
+        let policy = RetryPolicy {
+            max_parts: Some(8),
+            min_part_msat: Some(10_000),
+            ..Default::default()
+        };
+        assert_eq!(config.max_path_count(50_000, Some(&policy), 30), 5);
+        assert_eq!(config.max_path_count(10_000_000, Some(&policy), 30), 8);
+        let policy = RetryPolicy {
+            disable_mpp: true,
+            ..Default::default()
+        };
+        assert_eq!(config.max_path_count(10_000_000, Some(&policy), 30), 1);
+
+        let bad = MppConfig {
+            max_parts: Some(0),
+            min_part_msat: None,
+        };
+        assert!(storage.set_mpp_config(bad).is_err());
+        assert_eq!(storage.get_mpp_config().unwrap(), config);
+        let bad = RetryPolicy {
+            min_part_msat: Some(0),
+            ..Default::default()
+        };
+        assert!(bad.validate().is_err());
+    }
+}
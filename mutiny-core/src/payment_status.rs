@@ -0,0 +1,204 @@
+use crate::event::{HTLCStatus, PaymentInfo};
+use crate::utils;
+use bitcoin::hashes::hex::ToHex;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use lightning::events::PaymentFailureReason;
+use lightning::ln::channelmanager::RetryableSendFailure;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A state a payment moved to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PaymentState {
+    /// Waiting to be paid, or to be sent
+    Pending,
+    /// Sent and waiting on the recipient, or received and being claimed
+    InFlight,
+    /// A part of a multi-path payment reached the recipient
+    PartSucceeded {
+        amount_msat: u64,
+    },
+    /// A part of a payment failed, it is retried over another path if it can be
+    PartFailed {
+        amount_msat: u64,
+        short_channel_id: Option<u64>,
+    },
+    Settled {
+        preimage: Option<String>,
+        fee_msat: Option<u64>,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+impl PaymentState {
+    /// True if the payment won't change state again
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            PaymentState::Settled { .. } | PaymentState::Failed { .. }
+        )
+    }
+
+    pub(crate) fn failed(reason: Option<PaymentFailureReason>) -> Self {
+        PaymentState::Failed {
+            reason: failure_reason_message(reason).to_string(),
+        }
+    }
+
+    /// The state of a payment LDK refused to send
+    pub(crate) fn not_sent(failure: Option<&RetryableSendFailure>) -> Self {
+        Self::failed(send_failure_reason(failure))
+    }
+
+    /// The state of a saved payment, for subscribers that missed its earlier updates
+    pub(crate) fn from_payment_info(info: &PaymentInfo) -> Self {
+        match info.status {
+            HTLCStatus::Pending => PaymentState::Pending,
+            HTLCStatus::InFlight => PaymentState::InFlight,
+            HTLCStatus::Succeeded => PaymentState::Settled {
+                preimage: info.preimage.map(|p| p.to_hex()),
+                fee_msat: info.fee_paid_msat,
+            },
+            HTLCStatus::Failed => PaymentState::failed(None),
+        }
+    }
+}
+
+/// The reason for a payment LDK refused to send, matching the ones it gives
+/// for payments that failed after being sent
+pub(crate) fn send_failure_reason(
+    failure: Option<&RetryableSendFailure>,
+) -> Option<PaymentFailureReason> {
+    match failure {
+        Some(RetryableSendFailure::RouteNotFound) => Some(PaymentFailureReason::RouteNotFound),
+        Some(RetryableSendFailure::PaymentExpired) => Some(PaymentFailureReason::PaymentExpired),
+        Some(RetryableSendFailure::DuplicatePayment) | None => None,
+    }
+}
+
+/// A readable explanation of why LDK gave up on a payment
+pub(crate) fn failure_reason_message(reason: Option<PaymentFailureReason>) -> &'static str {
+    match reason {
+        Some(PaymentFailureReason::RecipientRejected) => "The recipient rejected the payment",
+        Some(PaymentFailureReason::UserAbandoned) => "The payment was abandoned",
+        Some(PaymentFailureReason::RetriesExhausted) => "Ran out of retries to find a route",
+        Some(PaymentFailureReason::PaymentExpired) => "The invoice expired",
+        Some(PaymentFailureReason::RouteNotFound) => "No route to the recipient was found",
+        Some(PaymentFailureReason::UnexpectedError) | None => "The payment failed",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentStatusUpdate {
+    pub payment_hash: String,
+    pub inbound: bool,
+    pub state: PaymentState,
+    /// The order the payment is for, if it was given one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<String>,
+    pub timestamp: u64,
+}
+
+/// The subscribers waiting on updates to payments, shared by all of our nodes
+#[derive(Default)]
+pub(crate) struct PaymentSubscriptions {
+    subscribers: utils::Mutex<HashMap<[u8; 32], Vec<UnboundedSender<PaymentStatusUpdate>>>>,
+}
+
+impl PaymentSubscriptions {
+    /// Returns a stream of the payment's updates, which ends once it is settled or failed
+    pub fn subscribe(&self, payment_hash: [u8; 32]) -> UnboundedReceiver<PaymentStatusUpdate> {
+        let (sender, receiver) = unbounded();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.entry(payment_hash).or_default().push(sender);
+        }
+        receiver
+    }
+
+    /// Sends the update to the payment's subscribers. Subscribers that went
+    /// away are dropped, and every subscriber is dropped after a final update.
+    pub fn notify(
+        &self,
+        payment_hash: &[u8; 32],
+        inbound: bool,
+        state: PaymentState,
+        order_id: Option<String>,
+    ) {
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            return;
+        };
+        let Some(senders) = subscribers.get_mut(payment_hash) else {
+            return;
+        };
+
+        let is_final = state.is_final();
+        let update = PaymentStatusUpdate {
+            payment_hash: payment_hash.to_hex(),
+            inbound,
+            state,
+            order_id,
+            timestamp: utils::now().as_secs(),
+        };
+        senders.retain(|s| s.unbounded_send(update.clone()).is_ok());
+
+        if is_final || senders.is_empty() {
+            subscribers.remove(payment_hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use futures::StreamExt;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    async fn test_payment_subscriptions() {
+        log!("test payment subscriptions");
+
+        let subscriptions = PaymentSubscriptions::default();
+        let hash = [1; 32];
+        let stream = subscriptions.subscribe(hash);
+        let mut other = subscriptions.subscribe(hash);
+
+        // updates to other payments aren't sent
+        subscriptions.notify(&[2; 32], false, PaymentState::InFlight, None);
+        subscriptions.notify(&hash, false, PaymentState::InFlight, None);
+        subscriptions.notify(
+            &hash,
+            false,
+            PaymentState::PartFailed {
+                amount_msat: 1_000,
+                short_channel_id: Some(42),
+            },
+            None,
+        );
+        other.close();
+        subscriptions.notify(&hash, false, PaymentState::failed(None), None);
+
+        let states: Vec<PaymentState> = stream.map(|u| u.state).collect().await;
+        assert_eq!(
+            states,
+            vec![
+                PaymentState::InFlight,
+                PaymentState::PartFailed {
+                    amount_msat: 1_000,
+                    short_channel_id: Some(42),
+                },
+                PaymentState::Failed {
+                    reason: "The payment failed".to_string()
+                },
+            ]
+        );
+
+        // the stream ended, so the payment is forgotten
+        assert!(subscriptions.subscribers.lock().unwrap().is_empty());
+    }
+}
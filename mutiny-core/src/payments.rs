@@ -0,0 +1,211 @@
+use crate::error::MutinyError;
+use crate::nodemanager::MutinyInvoice;
+use crate::uri::{parse_uri, strip_scheme, ParsedUri, UriIntent, LIGHTNING_SCHEME};
+use bitcoin::{Address, Network, Txid};
+use lightning::offers::offer::Offer;
+use lightning_invoice::Bolt11Invoice;
+use lnurl::lightning_address::LightningAddress;
+use lnurl::lnurl::LnUrl;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const BOLT12_PREFIX: &str = "lno1";
+
+/// Something that can be paid, in any of the formats a user might paste or scan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PaymentRequest {
+    Bolt11 {
+        invoice: Bolt11Invoice,
+    },
+    /// A BOLT 12 offer, these can be recognized but not paid yet
+    Bolt12 {
+        offer: String,
+    },
+    LnUrl {
+        lnurl: LnUrl,
+    },
+    LightningAddress {
+        address: LightningAddress,
+        lnurl: LnUrl,
+    },
+    OnChain {
+        address: Address,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        amount_sats: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    /// A BIP 21 URI with both an on-chain address and a lightning invoice
+    Unified {
+        address: Address,
+        invoice: Bolt11Invoice,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        amount_sats: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+impl PaymentRequest {
+    /// The amount the request asks for, if it has one
+    pub fn amount_sats(&self) -> Option<u64> {
+        match self {
+            PaymentRequest::Bolt11 { invoice } => {
+                invoice.amount_milli_satoshis().map(|m| m / 1_000)
+            }
+            PaymentRequest::OnChain { amount_sats, .. } => *amount_sats,
+            PaymentRequest::Unified { amount_sats, .. } => *amount_sats,
+            PaymentRequest::Bolt12 { .. }
+            | PaymentRequest::LnUrl { .. }
+            | PaymentRequest::LightningAddress { .. } => None,
+        }
+    }
+}
+
+/// How a unified payment was made
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UnifiedPayment {
+    Lightning {
+        invoice: MutinyInvoice,
+    },
+    OnChain {
+        txid: Txid,
+        /// Why paying over lightning failed, if it was tried first
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lightning_error: Option<String>,
+    },
+}
+
+/// Parses and validates a payment request against the given network, without
+/// any network requests. LNURLs are not called upon, so a LNURL-withdraw is
+/// returned as a [PaymentRequest::LnUrl] too.
+pub fn parse_payment_request(
+    request: &str,
+    network: Network,
+) -> Result<PaymentRequest, MutinyError> {
+    let request = request.trim();
+    let payload = strip_scheme(request, LIGHTNING_SCHEME).unwrap_or(request);
+    if strip_scheme(payload, BOLT12_PREFIX).is_some() {
+        Offer::from_str(payload).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        return Ok(PaymentRequest::Bolt12 {
+            offer: payload.to_string(),
+        });
+    }
+
+    match parse_uri(request, network)? {
+        ParsedUri::Intent(UriIntent::Pay {
+            address,
+            invoice,
+            amount_sats,
+            label,
+            message,
+        }) => match (address, invoice) {
+            (Some(address), Some(invoice)) => Ok(PaymentRequest::Unified {
+                address,
+                invoice,
+                amount_sats,
+                label,
+                message,
+            }),
+            (None, Some(invoice)) => Ok(PaymentRequest::Bolt11 { invoice }),
+            (Some(address), None) => Ok(PaymentRequest::OnChain {
+                address,
+                amount_sats,
+                label,
+                message,
+            }),
+            (None, None) => Err(MutinyError::InvalidArgumentsError),
+        },
+        ParsedUri::LnUrl {
+            lnurl,
+            lightning_address: Some(address),
+        } => Ok(PaymentRequest::LightningAddress { address, lnurl }),
+        ParsedUri::LnUrl {
+            lnurl,
+            lightning_address: None,
+        } => Ok(PaymentRequest::LnUrl { lnurl }),
+        // logins and wallet connections are not payments
+        ParsedUri::Intent(_) => Err(MutinyError::InvalidArgumentsError),
+    }
+}
+
+/// Returns true if a lightning payment failed without sending anything,
+/// so paying on-chain instead can't pay twice. A timed out payment
+/// can still complete, so it is not safe to fall back from.
+pub(crate) fn can_fall_back_on_chain(error: &MutinyError) -> bool {
+    matches!(
+        error,
+        MutinyError::RoutingFailed
+            | MutinyError::FeeCapExceeded
+            | MutinyError::InsufficientBalance
+            | MutinyError::ReserveAmountError
+            | MutinyError::NotRunning
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const ADDRESS: &str = "tb1qhgemzcaj5ehn7yvqq7wh0w2pkxg3ymq5yc0k8t";
+
+    #[test]
+    fn test_parse_payment_request() {
+        log!("test parse payment request");
+
+        let on_chain = parse_payment_request(ADDRESS, Network::Testnet).unwrap();
+        assert_eq!(
+            on_chain,
+            PaymentRequest::OnChain {
+                address: Address::from_str(ADDRESS).unwrap(),
+                amount_sats: None,
+                label: None,
+                message: None,
+            }
+        );
+
+        let uri = format!("bitcoin:{ADDRESS}?amount=0.0001");
+        let bip21 = parse_payment_request(&uri, Network::Testnet).unwrap();
+        assert_eq!(bip21.amount_sats(), Some(10_000));
+        assert!(matches!(bip21, PaymentRequest::OnChain { .. }));
+
+        let lnurl = LnUrl::from_url("https://mutinywallet.com/lnurlp/ben".to_string()).encode();
+        let parsed = parse_payment_request(&lnurl, Network::Testnet).unwrap();
+        assert!(matches!(parsed, PaymentRequest::LnUrl { .. }));
+
+        let address = parse_payment_request("ben@mutinywallet.com", Network::Testnet).unwrap();
+        match address {
+            PaymentRequest::LightningAddress { address, lnurl } => {
+                assert_eq!(lnurl, address.lnurl())
+            }
+            _ => panic!("expected lightning address"),
+        }
+
+        // bolt12 offers that don't parse are rejected rather than treated as something else
+        assert!(parse_payment_request("lno1qqqq", Network::Testnet).is_err());
+        assert!(parse_payment_request("lightning:lno1qqqq", Network::Testnet).is_err());
+
+        assert!(parse_payment_request("", Network::Testnet).is_err());
+        assert!(parse_payment_request("not a payment", Network::Testnet).is_err());
+    }
+
+    #[test]
+    fn test_can_fall_back_on_chain() {
+        log!("test can fall back on chain");
+
+        assert!(can_fall_back_on_chain(&MutinyError::RoutingFailed));
+        assert!(can_fall_back_on_chain(&MutinyError::InsufficientBalance));
+        assert!(!can_fall_back_on_chain(&MutinyError::PaymentTimeout));
+        assert!(!can_fall_back_on_chain(&MutinyError::NonUniquePaymentHash));
+    }
+}
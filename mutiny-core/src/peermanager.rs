@@ -1,5 +1,10 @@
+use crate::allowlist::PeerAllowlistStorage;
+use crate::gossip::{GossipRateLimiter, GossipStats, GossipVerdict};
 use crate::node::NetworkGraph;
+use crate::offline_receive::{OfflineReceiveHandler, OfflineReceiveMessage};
 use crate::storage::MutinyStorage;
+use crate::utils;
+use crate::utxo::EsploraUtxoLookup;
 use crate::{error::MutinyError, fees::MutinyFeeEstimator};
 use crate::{gossip, ldkstorage::PhantomChannelManager, logging::MutinyLogger};
 use crate::{gossip::read_peer_info, node::PubkeyConnectionInfo};
@@ -13,17 +18,20 @@ use std::{net::SocketAddr, sync::atomic::AtomicBool};
 
 use crate::networking::socket::{schedule_descriptor_read, MutinySocketDescriptor};
 use crate::scb::message_handler::SCBMessageHandler;
-use bitcoin::BlockHash;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::{BlockHash, Network};
 use lightning::events::{MessageSendEvent, MessageSendEventsProvider};
 use lightning::ln::features::{InitFeatures, NodeFeatures};
 use lightning::ln::msgs;
-use lightning::ln::msgs::{LightningError, RoutingMessageHandler};
-use lightning::ln::peer_handler::PeerHandleError;
+use lightning::ln::msgs::{ChannelReestablish, DecodeError, LightningError, RoutingMessageHandler};
+use lightning::ln::peer_handler::{CustomMessageHandler, PeerHandleError};
 use lightning::ln::peer_handler::{IgnoringMessageHandler, PeerManager as LdkPeerManager};
+use lightning::ln::wire::{CustomMessageReader, Type};
 use lightning::log_warn;
 use lightning::routing::gossip::NodeId;
-use lightning::routing::utxo::{UtxoLookup, UtxoLookupError, UtxoResult};
 use lightning::util::logger::Logger;
+use lightning::util::ser::{Writeable, Writer};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 
 #[cfg(target_arch = "wasm32")]
@@ -81,12 +89,8 @@ pub trait PeerManager {
 
     fn timer_tick_occurred(&self);
 
-    fn broadcast_node_announcement(
-        &self,
-        rgb: [u8; 3],
-        alias: [u8; 32],
-        addresses: Vec<NetAddress>,
-    );
+    /// Announces our node, the alias is truncated to fit if it is too long
+    fn broadcast_node_announcement(&self, rgb: [u8; 3], alias: &str, addresses: Vec<NetAddress>);
 }
 
 pub(crate) type PeerManagerImpl<S: MutinyStorage> = LdkPeerManager<
@@ -95,7 +99,7 @@ pub(crate) type PeerManagerImpl<S: MutinyStorage> = LdkPeerManager<
     Arc<GossipMessageHandler<S>>,
     Arc<IgnoringMessageHandler>,
     Arc<MutinyLogger>,
-    Arc<SCBMessageHandler>,
+    Arc<MutinyMessageHandler<S>>,
     Arc<PhantomKeysManager<S>>,
 >;
 
@@ -156,12 +160,8 @@ impl<S: MutinyStorage> PeerManager for PeerManagerImpl<S> {
         self.timer_tick_occurred()
     }
 
-    fn broadcast_node_announcement(
-        &self,
-        rgb: [u8; 3],
-        alias: [u8; 32],
-        addresses: Vec<NetAddress>,
-    ) {
+    fn broadcast_node_announcement(&self, rgb: [u8; 3], alias: &str, addresses: Vec<NetAddress>) {
+        let alias = gossip::encode_node_alias(alias);
         self.broadcast_node_announcement(rgb, alias, addresses)
     }
 }
@@ -170,22 +170,212 @@ impl<S: MutinyStorage> PeerManager for PeerManagerImpl<S> {
 pub struct GossipMessageHandler<S: MutinyStorage> {
     pub(crate) storage: S,
     pub(crate) network_graph: Arc<NetworkGraph>,
+    /// Our LSP, which is always allowed to connect
+    pub(crate) lsp_pubkey: Option<PublicKey>,
+    pub(crate) rate_limiter: Arc<utils::Mutex<GossipRateLimiter>>,
+    pub(crate) gossip_queries: Arc<utils::Mutex<GossipQueries>>,
+    /// Validates channel announcements from peers, they are trusted if not set
+    pub(crate) utxo_lookup: Option<Arc<EsploraUtxoLookup>>,
+    pub(crate) network: Network,
+    /// Asks our LSP to hold payments for us whenever it connects
+    pub(crate) offline_receive: Arc<OfflineReceiveHandler<S>>,
     pub(crate) logger: Arc<MutinyLogger>,
 }
 
-impl<S: MutinyStorage> MessageSendEventsProvider for GossipMessageHandler<S> {
-    fn get_and_clear_pending_msg_events(&self) -> Vec<MessageSendEvent> {
-        Vec::new()
+/// The most short channel ids we ask for in a single query
+const MAX_SCIDS_PER_QUERY: usize = 1_000;
+/// How long to wait before asking peers for a range of channels again
+const RANGE_QUERY_COOLDOWN_SECS: u64 = 600;
+
+/// Tracks the gossip queries we send to peers to fill in gaps in our network graph
+#[derive(Debug, Default)]
+pub(crate) struct GossipQueries {
+    /// Peers that said they answer gossip queries
+    query_peers: HashSet<PublicKey>,
+    /// Peers we're waiting on a channel range reply from
+    awaiting_range: HashSet<PublicKey>,
+    /// When we last asked for a range of channels
+    last_range_query: Option<u64>,
+    /// Short channel ids we still have to ask each peer for
+    queued_scids: HashMap<PublicKey, BTreeSet<u64>>,
+    /// Peers we're waiting on a reply_short_channel_ids_end from, we can't
+    /// send them another query until it arrives
+    awaiting_scids: HashSet<PublicKey>,
+    pending_msg_events: Vec<MessageSendEvent>,
+}
+
+impl GossipQueries {
+    /// Picks a connected peer that answers gossip queries, our LSP if it can
+    fn query_peer(
+        &self,
+        connected_peers: &[PublicKey],
+        lsp_pubkey: Option<PublicKey>,
+    ) -> Option<PublicKey> {
+        let can_query =
+            |peer: &PublicKey| connected_peers.contains(peer) && self.query_peers.contains(peer);
+        lsp_pubkey
+            .filter(can_query)
+            .or_else(|| connected_peers.iter().copied().find(can_query))
+    }
+
+    /// Queues short channel ids to ask the peer for, they are sent one query at a time
+    fn queue_scids(
+        &mut self,
+        peer: PublicKey,
+        chain_hash: BlockHash,
+        scids: impl IntoIterator<Item = u64>,
+    ) {
+        self.queued_scids.entry(peer).or_default().extend(scids);
+        if !self.awaiting_scids.contains(&peer) {
+            self.send_next_scid_query(peer, chain_hash);
+        }
+    }
+
+    /// Asks the peer for the next chunk of its queued short channel ids, if there are any
+    fn send_next_scid_query(&mut self, peer: PublicKey, chain_hash: BlockHash) {
+        let Some(queued) = self.queued_scids.get_mut(&peer) else {
+            self.awaiting_scids.remove(&peer);
+            return;
+        };
+        let short_channel_ids: Vec<u64> =
+            queued.iter().take(MAX_SCIDS_PER_QUERY).copied().collect();
+        for scid in short_channel_ids.iter() {
+            queued.remove(scid);
+        }
+        if queued.is_empty() {
+            self.queued_scids.remove(&peer);
+        }
+        if short_channel_ids.is_empty() {
+            self.awaiting_scids.remove(&peer);
+            return;
+        }
+
+        self.awaiting_scids.insert(peer);
+        self.pending_msg_events
+            .push(MessageSendEvent::SendShortIdsQuery {
+                node_id: peer,
+                msg: msgs::QueryShortChannelIds {
+                    chain_hash,
+                    short_channel_ids,
+                },
+            });
+    }
+}
+
+impl<S: MutinyStorage> GossipMessageHandler<S> {
+    pub(crate) fn gossip_stats(&self) -> GossipStats {
+        self.rate_limiter
+            .lock()
+            .map(|limiter| limiter.stats())
+            .unwrap_or_default()
+    }
+
+    /// Checks a gossip message against the rate limiter, logging anything dropped
+    fn check_gossip(
+        &self,
+        check: impl FnOnce(&mut GossipRateLimiter, u64) -> GossipVerdict,
+        short_channel_id: u64,
+    ) -> bool {
+        let now = utils::now().as_secs();
+        let verdict = match self.rate_limiter.lock() {
+            Ok(mut limiter) => check(&mut limiter, now),
+            Err(_) => GossipVerdict::Process,
+        };
+
+        match verdict {
+            GossipVerdict::Process => true,
+            GossipVerdict::Duplicate => false,
+            GossipVerdict::RateLimited => {
+                log_trace!(
+                    self.logger,
+                    "rate limited gossip for channel: {short_channel_id}"
+                );
+                false
+            }
+        }
     }
 }
 
-// I needed some type to implement RoutingMessageHandler, but I don't want to implement it
-// we don't need to lookup UTXOs, so we can just return an error
-// This should never actually be called because we are passing in None for the UTXO lookup
-struct ErroringUtxoLookup {}
-impl UtxoLookup for ErroringUtxoLookup {
-    fn get_utxo(&self, _genesis_hash: &BlockHash, _short_channel_id: u64) -> UtxoResult {
-        UtxoResult::Sync(Err(UtxoLookupError::UnknownTx))
+impl<S: MutinyStorage> GossipMessageHandler<S> {
+    /// Asks one of the given connected peers for the channels opened in a range of blocks.
+    /// Any we don't have are then requested from that peer.
+    /// The LSP is asked if it can be, returns false if no peer answers gossip queries
+    /// or we asked too recently.
+    pub(crate) fn query_channel_range(
+        &self,
+        connected_peers: &[PublicKey],
+        first_blocknum: u32,
+        number_of_blocks: u32,
+    ) -> bool {
+        let Ok(mut queries) = self.gossip_queries.lock() else {
+            return false;
+        };
+
+        let now = utils::now().as_secs();
+        if queries
+            .last_range_query
+            .is_some_and(|last| now.saturating_sub(last) < RANGE_QUERY_COOLDOWN_SECS)
+        {
+            return false;
+        }
+        let Some(peer) = queries.query_peer(connected_peers, self.lsp_pubkey) else {
+            return false;
+        };
+
+        log_debug!(
+            self.logger,
+            "querying {peer} for channels in blocks {first_blocknum} to {}",
+            first_blocknum + number_of_blocks
+        );
+        queries.last_range_query = Some(now);
+        queries.awaiting_range.insert(peer);
+        queries
+            .pending_msg_events
+            .push(MessageSendEvent::SendChannelRangeQuery {
+                node_id: peer,
+                msg: msgs::QueryChannelRange {
+                    chain_hash: genesis_block(self.network).block_hash(),
+                    first_blocknum,
+                    number_of_blocks,
+                },
+            });
+
+        true
+    }
+
+    /// Asks one of the given connected peers for the given channels, the ones we
+    /// don't have are queued to be sent once the peer answers any earlier query.
+    /// Returns false if no peer answers gossip queries.
+    pub(crate) fn query_short_channel_ids(
+        &self,
+        connected_peers: &[PublicKey],
+        short_channel_ids: Vec<u64>,
+    ) -> bool {
+        let Ok(mut queries) = self.gossip_queries.lock() else {
+            return false;
+        };
+        let Some(peer) = queries.query_peer(connected_peers, self.lsp_pubkey) else {
+            return false;
+        };
+
+        log_debug!(
+            self.logger,
+            "requesting {} channels from {peer}",
+            short_channel_ids.len()
+        );
+        let chain_hash = genesis_block(self.network).block_hash();
+        queries.queue_scids(peer, chain_hash, short_channel_ids);
+
+        true
+    }
+}
+
+impl<S: MutinyStorage> MessageSendEventsProvider for GossipMessageHandler<S> {
+    fn get_and_clear_pending_msg_events(&self) -> Vec<MessageSendEvent> {
+        match self.gossip_queries.lock() {
+            Ok(mut queries) => std::mem::take(&mut queries.pending_msg_events),
+            Err(_) => Vec::new(),
+        }
     }
 }
 
@@ -222,13 +412,40 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
         &self,
         msg: &msgs::ChannelAnnouncement,
     ) -> Result<bool, LightningError> {
-        // because we got the channel, may as well update our network graph
-        self.network_graph
-            .update_channel_from_announcement_no_lookup(msg)?;
+        let scid = msg.contents.short_channel_id;
+        let known = self
+            .network_graph
+            .read_only()
+            .channels()
+            .contains_key(&scid);
+        if !self.check_gossip(|l, now| l.check_channel_announcement(known, now), scid) {
+            return Ok(false);
+        }
+
+        // because we got the channel, may as well update our network graph,
+        // checking the funding output exists first if we can
+        match self.utxo_lookup {
+            Some(_) => self
+                .network_graph
+                .update_channel_from_announcement(msg, &self.utxo_lookup)?,
+            None => self
+                .network_graph
+                .update_channel_from_announcement_no_lookup(msg)?,
+        }
         Ok(false)
     }
 
     fn handle_channel_update(&self, msg: &msgs::ChannelUpdate) -> Result<bool, LightningError> {
+        let scid = msg.contents.short_channel_id;
+        let direction = msg.contents.flags & 1;
+        let timestamp = msg.contents.timestamp;
+        if !self.check_gossip(
+            |l, now| l.check_channel_update(scid, direction, timestamp, now),
+            scid,
+        ) {
+            return Ok(false);
+        }
+
         // because we got the update, may as well update our network graph
         self.network_graph.update_channel_unsigned(&msg.contents)?;
         Ok(false)
@@ -254,26 +471,96 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
 
     fn peer_connected(
         &self,
-        _their_node_id: &PublicKey,
-        _init: &msgs::Init,
-        _inbound: bool,
+        their_node_id: &PublicKey,
+        init: &msgs::Init,
+        inbound: bool,
     ) -> Result<(), ()> {
+        // in allowlist-only mode, drop anyone that isn't on the list
+        if !self
+            .storage
+            .is_peer_allowed(their_node_id, self.lsp_pubkey.as_ref())
+        {
+            log_debug!(
+                self.logger,
+                "disconnecting peer not in allowlist: {their_node_id} (inbound: {inbound})"
+            );
+            return Err(());
+        }
+
+        // remember who we can ask for missing channels
+        if let Ok(mut queries) = self.gossip_queries.lock() {
+            if init.features.supports_gossip_queries() {
+                queries.query_peers.insert(*their_node_id);
+            } else {
+                queries.query_peers.remove(their_node_id);
+            }
+            queries.awaiting_range.remove(their_node_id);
+            // anything we asked before a reconnect won't be answered
+            queries.awaiting_scids.remove(their_node_id);
+            queries.queued_scids.remove(their_node_id);
+        }
+
+        self.offline_receive
+            .peer_connected(their_node_id, &init.features);
+
         Ok(())
     }
 
     fn handle_reply_channel_range(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::ReplyChannelRange,
+        their_node_id: &PublicKey,
+        msg: msgs::ReplyChannelRange,
     ) -> Result<(), LightningError> {
+        let Ok(mut queries) = self.gossip_queries.lock() else {
+            return Ok(());
+        };
+
+        // ignore replies we didn't ask for
+        if !queries.awaiting_range.contains(their_node_id) {
+            return Ok(());
+        }
+        if msg.sync_complete {
+            queries.awaiting_range.remove(their_node_id);
+        }
+
+        let missing: Vec<u64> = {
+            let graph = self.network_graph.read_only();
+            msg.short_channel_ids
+                .into_iter()
+                .filter(|scid| !graph.channels().contains_key(scid))
+                .collect()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        log_debug!(
+            self.logger,
+            "requesting {} missing channels from {their_node_id}",
+            missing.len()
+        );
+        queries.queue_scids(*their_node_id, msg.chain_hash, missing);
+
         Ok(())
     }
 
     fn handle_reply_short_channel_ids_end(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::ReplyShortChannelIdsEnd,
+        their_node_id: &PublicKey,
+        msg: msgs::ReplyShortChannelIdsEnd,
     ) -> Result<(), LightningError> {
+        log_trace!(
+            self.logger,
+            "finished receiving queried channels from {their_node_id} (complete: {})",
+            msg.full_information
+        );
+
+        // only now can we ask the peer for more
+        if let Ok(mut queries) = self.gossip_queries.lock() {
+            if queries.awaiting_scids.contains(their_node_id) {
+                queries.send_next_scid_query(*their_node_id, msg.chain_hash);
+            }
+        }
         Ok(())
     }
 
@@ -302,7 +589,97 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
     }
 
     fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
-        InitFeatures::empty()
+        // lets peers know we'll ask them for channels instead of wanting all their gossip
+        let mut features = InitFeatures::empty();
+        features.set_gossip_queries_optional();
+        features
+    }
+}
+
+/// The custom messages we send to and receive from peers
+#[derive(Debug)]
+pub enum MutinyCustomMessage {
+    /// Sent to get a peer to force close a channel we recovered from a backup
+    ChannelReestablish(ChannelReestablish),
+    OfflineReceive(OfflineReceiveMessage),
+}
+
+impl Type for MutinyCustomMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            Self::ChannelReestablish(msg) => msg.type_id(),
+            Self::OfflineReceive(msg) => msg.type_id(),
+        }
+    }
+}
+
+impl Writeable for MutinyCustomMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), lightning::io::Error> {
+        match self {
+            Self::ChannelReestablish(msg) => msg.write(writer),
+            Self::OfflineReceive(msg) => msg.write(writer),
+        }
+    }
+}
+
+/// Combines our custom message handlers into the one the peer manager takes
+pub struct MutinyMessageHandler<S: MutinyStorage> {
+    pub(crate) scb: Arc<SCBMessageHandler>,
+    pub(crate) offline_receive: Arc<OfflineReceiveHandler<S>>,
+}
+
+impl<S: MutinyStorage> CustomMessageReader for MutinyMessageHandler<S> {
+    type CustomMessage = MutinyCustomMessage;
+
+    fn read<R: lightning::io::Read>(
+        &self,
+        msg_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        // the SCB handler only sends messages, so only offline receive ones are read
+        Ok(self
+            .offline_receive
+            .read(msg_type, buffer)?
+            .map(MutinyCustomMessage::OfflineReceive))
+    }
+}
+
+impl<S: MutinyStorage> CustomMessageHandler for MutinyMessageHandler<S> {
+    fn handle_custom_message(
+        &self,
+        msg: MutinyCustomMessage,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        match msg {
+            MutinyCustomMessage::ChannelReestablish(msg) => {
+                self.scb.handle_custom_message(msg, sender_node_id)
+            }
+            MutinyCustomMessage::OfflineReceive(msg) => self
+                .offline_receive
+                .handle_custom_message(msg, sender_node_id),
+        }
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+        let scb = self
+            .scb
+            .get_and_clear_pending_msg()
+            .into_iter()
+            .map(|(node_id, msg)| (node_id, MutinyCustomMessage::ChannelReestablish(msg)));
+        let offline_receive = self
+            .offline_receive
+            .get_and_clear_pending_msg()
+            .into_iter()
+            .map(|(node_id, msg)| (node_id, MutinyCustomMessage::OfflineReceive(msg)));
+        scb.chain(offline_receive).collect()
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        NodeFeatures::empty()
+    }
+
+    fn provided_init_features(&self, their_node_id: &PublicKey) -> InitFeatures {
+        self.offline_receive.provided_init_features(their_node_id)
     }
 }
 
@@ -423,3 +800,55 @@ fn try_parse_addr_string(addr: &str) -> (Option<SocketAddr>, Option<NetAddress>)
     });
     (socket_addr, net_addr)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn sent_scids(queries: &mut GossipQueries) -> Vec<Vec<u64>> {
+        std::mem::take(&mut queries.pending_msg_events)
+            .into_iter()
+            .map(|event| match event {
+                MessageSendEvent::SendShortIdsQuery { msg, .. } => msg.short_channel_ids,
+                _ => panic!("unexpected message"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_one_short_channel_ids_query_at_a_time() {
+        log!("test one short channel ids query at a time");
+
+        let secret = SecretKey::from_slice(&[1; 32]).unwrap();
+        let peer = PublicKey::from_secret_key(&Secp256k1::new(), &secret);
+        let chain_hash = genesis_block(Network::Regtest).block_hash();
+        let mut queries = GossipQueries::default();
+
+        queries.queue_scids(peer, chain_hash, (0..2_500).rev());
+        let sent = sent_scids(&mut queries);
+        assert_eq!(sent, vec![(0..1_000).collect::<Vec<u64>>()]);
+
+        // more channels while waiting on the reply are queued behind the rest
+        queries.queue_scids(peer, chain_hash, [5_000]);
+        assert!(sent_scids(&mut queries).is_empty());
+
+        queries.send_next_scid_query(peer, chain_hash);
+        assert_eq!(sent_scids(&mut queries)[0].len(), 1_000);
+        queries.send_next_scid_query(peer, chain_hash);
+        let sent = sent_scids(&mut queries);
+        assert_eq!(sent[0].len(), 501);
+        assert_eq!(sent[0].last(), Some(&5_000));
+        assert!(queries.awaiting_scids.contains(&peer));
+
+        // nothing left once the last reply arrives
+        queries.send_next_scid_query(peer, chain_hash);
+        assert!(sent_scids(&mut queries).is_empty());
+        assert!(!queries.awaiting_scids.contains(&peer));
+        assert!(queries.queued_scids.is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::error::MutinyError;
+use crate::nodemanager::MutinyInvoice;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const RECEIPT_PREFIX: &str = "receipt/";
+
+/// Bumped whenever a field is added or changed so frontends know what to render
+pub const RECEIPT_SCHEMA_VERSION: u32 = 2;
+
+/// We only know the fiat value of payments that settled recently,
+/// older ones would be priced at today's rate
+pub(crate) const RECEIPT_FIAT_WINDOW_SECS: u64 = 60 * 10;
+
+/// The fiat value of a payment at the time it settled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FiatValue {
+    /// ISO 4217 currency code
+    pub currency: String,
+    /// The value in cents
+    pub amount_cents: u64,
+}
+
+impl FiatValue {
+    pub(crate) fn from_usd_price(amount_sats: u64, btc_price: f32) -> Self {
+        let cents = amount_sats as f64 * btc_price as f64 / 1_000_000.0;
+        Self {
+            currency: "USD".to_string(),
+            amount_cents: cents.round() as u64,
+        }
+    }
+}
+
+/// A receipt for a settled lightning payment, in a stable schema
+/// that frontends can render or export as a PDF or wallet pass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub schema_version: u32,
+    /// Also the proof hash, the preimage hashes to it
+    pub payment_hash: sha256::Hash,
+    /// Hex encoded preimage, proves the payment was made
+    pub preimage: Option<String>,
+    pub inbound: bool,
+    /// Who was paid, if we know
+    pub payee: Option<PublicKey>,
+    pub amount_sats: u64,
+    /// Routing fees for outbound payments
+    pub fees_sats: Option<u64>,
+    pub fiat_value: Option<FiatValue>,
+    /// The invoice description, or the labels if it didn't have one
+    pub memo: Option<String>,
+    pub bolt11: Option<String>,
+    /// The order the payment was for, if it was given one
+    #[serde(default)]
+    pub order_id: Option<String>,
+    /// Unix timestamp in seconds of when the payment settled
+    pub settled_at: u64,
+}
+
+impl Receipt {
+    /// Creates a receipt for a paid invoice, the price is only used if it settled recently
+    pub(crate) fn from_invoice(
+        invoice: &MutinyInvoice,
+        btc_price: Option<f32>,
+        now: u64,
+    ) -> Option<Self> {
+        if !invoice.paid {
+            return None;
+        }
+
+        let amount_sats = invoice.amount_sats.unwrap_or(0);
+        let recent = now.saturating_sub(invoice.last_updated) <= RECEIPT_FIAT_WINDOW_SECS;
+        let fiat_value = btc_price
+            .filter(|_| recent)
+            .map(|price| FiatValue::from_usd_price(amount_sats, price));
+
+        let memo = match &invoice.description {
+            Some(description) => Some(description.clone()),
+            None if !invoice.labels.is_empty() => Some(invoice.labels.join(", ")),
+            None => None,
+        };
+
+        Some(Self {
+            schema_version: RECEIPT_SCHEMA_VERSION,
+            payment_hash: invoice.payment_hash,
+            preimage: invoice.preimage.clone(),
+            inbound: invoice.inbound,
+            payee: invoice.payee_pubkey,
+            amount_sats,
+            fees_sats: invoice.fees_paid,
+            fiat_value,
+            memo,
+            bolt11: invoice.bolt11.as_ref().map(|i| i.to_string()),
+            order_id: invoice.order_id.clone(),
+            settled_at: invoice.last_updated,
+        })
+    }
+
+    fn key(&self) -> String {
+        receipt_key(&self.payment_hash)
+    }
+}
+
+fn receipt_key(payment_hash: &sha256::Hash) -> String {
+    format!("{RECEIPT_PREFIX}{payment_hash}")
+}
+
+pub trait ReceiptStorage {
+    fn get_receipt(&self, payment_hash: &sha256::Hash) -> Result<Option<Receipt>, MutinyError>;
+    fn persist_receipt(&self, receipt: Receipt) -> Result<(), MutinyError>;
+    fn list_receipts(&self) -> Result<Vec<Receipt>, MutinyError>;
+}
+
+impl<S: MutinyStorage> ReceiptStorage for S {
+    fn get_receipt(&self, payment_hash: &sha256::Hash) -> Result<Option<Receipt>, MutinyError> {
+        self.get_data(receipt_key(payment_hash))
+    }
+
+    fn persist_receipt(&self, receipt: Receipt) -> Result<(), MutinyError> {
+        self.set_data(receipt.key(), receipt, None)
+    }
+
+    fn list_receipts(&self) -> Result<Vec<Receipt>, MutinyError> {
+        let map: HashMap<String, Receipt> = self.scan(RECEIPT_PREFIX, None)?;
+        let mut receipts: Vec<Receipt> = map.into_values().collect();
+        // newest first
+        receipts.sort_by(|a, b| b.settled_at.cmp(&a.settled_at));
+        Ok(receipts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payment_metadata::PaymentMetadata;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_invoice(paid: bool) -> MutinyInvoice {
+        MutinyInvoice {
+            bolt11: None,
+            description: None,
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            preimage: Some("00".repeat(32)),
+            payee_pubkey: None,
+            amount_sats: Some(10_000),
+            expire: 3_600,
+            paid,
+            fees_paid: Some(2),
+            inbound: false,
+            labels: vec!["coffee".to_string()],
+            order_id: None,
+            custom_tlvs: vec![],
+            metadata: PaymentMetadata::default(),
+            failure: None,
+            parts: vec![],
+            last_updated: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_receipt_from_invoice() {
+        log!("test receipt from invoice");
+
+        assert!(Receipt::from_invoice(&dummy_invoice(false), Some(30_000.0), 1_000).is_none());
+
+        let receipt = Receipt::from_invoice(&dummy_invoice(true), Some(30_000.0), 1_000).unwrap();
+        assert_eq!(receipt.schema_version, RECEIPT_SCHEMA_VERSION);
+        assert_eq!(receipt.amount_sats, 10_000);
+        assert_eq!(receipt.fees_sats, Some(2));
+        assert_eq!(receipt.memo, Some("coffee".to_string()));
+        assert_eq!(receipt.settled_at, 1_000);
+        let fiat = receipt.fiat_value.unwrap();
+        assert_eq!(fiat.currency, "USD");
+        assert_eq!(fiat.amount_cents, 300);
+
+        // settled too long ago to use today's price
+        let now = 1_000 + RECEIPT_FIAT_WINDOW_SECS + 1;
+        let receipt = Receipt::from_invoice(&dummy_invoice(true), Some(30_000.0), now).unwrap();
+        assert!(receipt.fiat_value.is_none());
+    }
+
+    #[test]
+    fn test_receipt_storage() {
+        log!("test receipt storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_receipts().unwrap().is_empty());
+
+        let old = Receipt::from_invoice(&dummy_invoice(true), None, 1_000).unwrap();
+        let mut invoice = dummy_invoice(true);
+        invoice.payment_hash = sha256::Hash::hash(&[1; 32]);
+        invoice.last_updated = 2_000;
+        let new = Receipt::from_invoice(&invoice, None, 2_000).unwrap();
+
+        storage.persist_receipt(old.clone()).unwrap();
+        storage.persist_receipt(new.clone()).unwrap();
+
+        assert_eq!(
+            storage.get_receipt(&old.payment_hash).unwrap(),
+            Some(old.clone())
+        );
+        assert_eq!(storage.list_receipts().unwrap(), vec![new, old]);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::error::MutinyError;
+use crate::lnurlpay::parse_lnurl_or_address;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const RECURRING_PAYMENTS_KEY: &str = "recurring_payments";
+/// Payments can't recur more often than this
+pub const MIN_RECURRING_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Who a recurring payment is sent to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecurringPaymentTarget {
+    /// A spontaneous payment to a node
+    Keysend(PublicKey),
+    /// A lightning address, like `bob@example.com`, or a LNURL-pay
+    LnAddress(String),
+}
+
+impl FromStr for RecurringPaymentTarget {
+    type Err = MutinyError;
+
+    /// Parses a node pubkey, lightning address or LNURL
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(pubkey) = PublicKey::from_str(s) {
+            return Ok(Self::Keysend(pubkey));
+        }
+        parse_lnurl_or_address(s)?;
+        Ok(Self::LnAddress(s.trim().to_string()))
+    }
+}
+
+/// What to do with the intervals that were missed while the node wasn't running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CatchUpPolicy {
+    /// Only pay for the current interval, missed ones are skipped
+    #[default]
+    Skip,
+    /// Pay for every missed interval in a single payment
+    Combine,
+    /// Pay for every missed interval separately, at most `max` at once
+    Each { max: u32 },
+}
+
+/// A payment that is made every interval while the node is running
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecurringPayment {
+    pub id: String,
+    pub target: RecurringPaymentTarget,
+    pub amount_sats: u64,
+    pub interval_secs: u64,
+    /// Unix timestamp the next payment is due at
+    pub next_payment: u64,
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub created_at: u64,
+    /// Unix timestamp of the last successful payment
+    pub last_paid: Option<u64>,
+    /// Why the last attempt failed, cleared once a payment succeeds
+    pub last_error: Option<String>,
+}
+
+impl RecurringPayment {
+    /// Number of intervals that are due at `now`, including missed ones
+    fn intervals_due(&self, now: u64) -> u64 {
+        if now < self.next_payment {
+            return 0;
+        }
+        (now - self.next_payment) / self.interval_secs + 1
+    }
+
+    /// The payments to make at `now`, as amounts in satoshis along with how
+    /// many intervals each one pays for. Empty if nothing is due.
+    pub(crate) fn due_payments(&self, now: u64) -> Vec<(u64, u64)> {
+        let due = self.intervals_due(now);
+        if due == 0 {
+            return vec![];
+        }
+
+        match self.catch_up {
+            // the skipped intervals are paid for with nothing
+            CatchUpPolicy::Skip => vec![(self.amount_sats, due)],
+            CatchUpPolicy::Combine => vec![(self.amount_sats * due, due)],
+            CatchUpPolicy::Each { max } => {
+                let count = due.min(max.max(1) as u64);
+                let mut payments = vec![(self.amount_sats, 1); count as usize];
+                // anything past the limit is skipped with the last payment
+                if let Some(last) = payments.last_mut() {
+                    last.1 += due - count;
+                }
+                payments
+            }
+        }
+    }
+
+    /// Records a successful payment covering the given number of intervals
+    pub(crate) fn paid(&mut self, intervals: u64, now: u64) {
+        self.next_payment += intervals * self.interval_secs;
+        self.last_paid = Some(now);
+        self.last_error = None;
+    }
+}
+
+pub trait RecurringPaymentStorage {
+    fn get_recurring_payments(&self) -> Result<Vec<RecurringPayment>, MutinyError>;
+    fn persist_recurring_payments(
+        &self,
+        payments: Vec<RecurringPayment>,
+    ) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> RecurringPaymentStorage for S {
+    fn get_recurring_payments(&self) -> Result<Vec<RecurringPayment>, MutinyError> {
+        let payments: Option<Vec<RecurringPayment>> = self.get_data(RECURRING_PAYMENTS_KEY)?;
+        Ok(payments.unwrap_or_default())
+    }
+
+    fn persist_recurring_payments(
+        &self,
+        payments: Vec<RecurringPayment>,
+    ) -> Result<(), MutinyError> {
+        self.set_data(RECURRING_PAYMENTS_KEY, payments, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const DAY: u64 = 60 * 60 * 24;
+
+    fn dummy_payment(catch_up: CatchUpPolicy) -> RecurringPayment {
+        RecurringPayment {
+            id: "rent".to_string(),
+            target: RecurringPaymentTarget::from_str("bob@example.com").unwrap(),
+            amount_sats: 1_000,
+            interval_secs: DAY,
+            next_payment: DAY,
+            catch_up,
+            labels: vec![],
+            created_at: 0,
+            last_paid: None,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_recurring_due_payments() {
+        log!("test recurring due payments");
+
+        let mut payment = dummy_payment(CatchUpPolicy::Skip);
+        assert!(payment.due_payments(DAY - 1).is_empty());
+        assert_eq!(payment.due_payments(DAY), vec![(1_000, 1)]);
+
+        // three intervals missed
+        let now = DAY * 3 + 10;
+        assert_eq!(payment.due_payments(now), vec![(1_000, 3)]);
+        payment.catch_up = CatchUpPolicy::Combine;
+        assert_eq!(payment.due_payments(now), vec![(3_000, 3)]);
+        payment.catch_up = CatchUpPolicy::Each { max: 5 };
+        assert_eq!(payment.due_payments(now), vec![(1_000, 1); 3]);
+        payment.catch_up = CatchUpPolicy::Each { max: 2 };
+        assert_eq!(payment.due_payments(now), vec![(1_000, 1), (1_000, 2)]);
+
+        // paying for every interval moves the next payment past now
+        payment.paid(3, now);
+        assert_eq!(payment.next_payment, DAY * 4);
+        assert_eq!(payment.last_paid, Some(now));
+        assert!(payment.due_payments(now).is_empty());
+    }
+
+    #[test]
+    fn test_recurring_target() {
+        log!("test recurring target");
+
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap());
+        assert_eq!(
+            RecurringPaymentTarget::from_str(&pubkey.to_string()).unwrap(),
+            RecurringPaymentTarget::Keysend(pubkey)
+        );
+        assert_eq!(
+            RecurringPaymentTarget::from_str("bob@example.com").unwrap(),
+            RecurringPaymentTarget::LnAddress("bob@example.com".to_string())
+        );
+        assert!(RecurringPaymentTarget::from_str("nope").is_err());
+    }
+
+    #[test]
+    fn test_recurring_storage() {
+        log!("test recurring storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_recurring_payments().unwrap().is_empty());
+
+        let payments = vec![dummy_payment(CatchUpPolicy::default())];
+        storage
+            .persist_recurring_payments(payments.clone())
+            .unwrap();
+        assert_eq!(storage.get_recurring_payments().unwrap(), payments);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::MutinyError;
 use crate::nodemanager::NodeManager;
+use crate::payment_retry::RetryPolicy;
 use crate::storage::MutinyStorage;
 use crate::utils;
 use crate::utils::sleep;
@@ -337,7 +338,14 @@ impl<S: MutinyStorage> RedshiftManager for NodeManager<S> {
 
             // get an invoice from the receiving node
             let invoice = match receiving_node
-                .create_invoice(Some(local_max_sats), vec!["Redshift".to_string()], None)
+                .create_invoice(
+                    Some(local_max_sats),
+                    vec!["Redshift".to_string()],
+                    None,
+                    None,
+                    None,
+                    None,
+                )
                 .await
             {
                 Ok(i) => i,
@@ -364,7 +372,7 @@ impl<S: MutinyStorage> RedshiftManager for NodeManager<S> {
             let label = format!("Redshift: {}", rs.id.to_hex());
             // make attempts to pay it
             match sending_node
-                .pay_invoice_with_timeout(&invoice, None, None, vec![label])
+                .pay_invoice_with_timeout(&invoice, None, None, vec![label], RetryPolicy::default())
                 .await
             {
                 Ok(i) => {
@@ -0,0 +1,211 @@
+use crate::allowlist::PEER_ALLOWLIST_KEY;
+use crate::error::MutinyError;
+use crate::inbound::INBOUND_POLICY_KEY;
+use crate::labels::{ADDRESS_LABELS_MAP_KEY, CONTACT_PREFIX, INVOICE_LABELS_MAP_KEY, LABEL_PREFIX};
+use crate::retention::RETENTION_POLICY_KEY;
+use crate::storage::MutinyStorage;
+use crate::sweep::SWEEP_DESTINATION_KEY;
+use crate::telemetry::TELEMETRY_OPT_IN_KEY;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const RESTORE_POINT_PREFIX: &str = "restore_point/";
+
+/// How often a restore point is created in the background
+pub(crate) const RESTORE_POINT_INTERVAL_SECS: u64 = 60 * 60 * 24;
+
+/// Only keep the most recent restore points
+const MAX_RESTORE_POINTS: usize = 7;
+
+/// Settings that are saved in restore points
+const RESTORE_POINT_KEYS: [&str; 7] = [
+    ADDRESS_LABELS_MAP_KEY,
+    INVOICE_LABELS_MAP_KEY,
+    TELEMETRY_OPT_IN_KEY,
+    RETENTION_POLICY_KEY,
+    SWEEP_DESTINATION_KEY,
+    INBOUND_POLICY_KEY,
+    PEER_ALLOWLIST_KEY,
+];
+
+/// Prefixes of the keys that are saved in restore points
+const RESTORE_POINT_PREFIXES: [&str; 2] = [LABEL_PREFIX, CONTACT_PREFIX];
+
+/// A snapshot of the wallet's non-channel state: settings, labels and contacts.
+///
+/// Channel and payment state is never included, rolling that back could lose funds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RestorePoint {
+    id: String,
+    created_at: u64,
+    data: HashMap<String, Value>,
+}
+
+/// Describes a restore point without its data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestorePointInfo {
+    pub id: String,
+    /// Epoch time in seconds of when the restore point was created
+    pub created_at: u64,
+    /// The number of stored items in the restore point
+    pub item_count: usize,
+}
+
+impl From<&RestorePoint> for RestorePointInfo {
+    fn from(point: &RestorePoint) -> Self {
+        Self {
+            id: point.id.clone(),
+            created_at: point.created_at,
+            item_count: point.data.len(),
+        }
+    }
+}
+
+fn restore_point_key(id: &str) -> String {
+    format!("{RESTORE_POINT_PREFIX}{id}")
+}
+
+pub trait RestorePointStorage {
+    /// Saves the current settings, labels and contacts as a new restore point
+    fn create_restore_point(&self, now: u64) -> Result<RestorePointInfo, MutinyError>;
+    /// Lists the restore points, newest first
+    fn list_restore_points(&self) -> Result<Vec<RestorePointInfo>, MutinyError>;
+    /// Rolls the settings, labels and contacts back to the given restore point.
+    ///
+    /// A restore point of the current state is created first
+    /// so the restore itself can be undone.
+    fn restore(&self, id: &str, now: u64) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> RestorePointStorage for S {
+    fn create_restore_point(&self, now: u64) -> Result<RestorePointInfo, MutinyError> {
+        let mut data = HashMap::new();
+        for key in RESTORE_POINT_KEYS {
+            if let Some(value) = self.get_data::<Value>(key)? {
+                data.insert(key.to_string(), value);
+            }
+        }
+        for prefix in RESTORE_POINT_PREFIXES {
+            data.extend(self.scan::<Value>(prefix, None)?);
+        }
+
+        let point = RestorePoint {
+            id: Uuid::new_v4().to_string(),
+            created_at: now,
+            data,
+        };
+        let info = RestorePointInfo::from(&point);
+        self.set_data(restore_point_key(&point.id), point, None)?;
+
+        // remove the oldest restore points
+        let points = self.list_restore_points()?;
+        if points.len() > MAX_RESTORE_POINTS {
+            let old: Vec<String> = points[MAX_RESTORE_POINTS..]
+                .iter()
+                .map(|p| restore_point_key(&p.id))
+                .collect();
+            self.delete(&old)?;
+        }
+
+        Ok(info)
+    }
+
+    fn list_restore_points(&self) -> Result<Vec<RestorePointInfo>, MutinyError> {
+        let points: HashMap<String, RestorePoint> = self.scan(RESTORE_POINT_PREFIX, None)?;
+        let mut infos: Vec<RestorePointInfo> =
+            points.values().map(RestorePointInfo::from).collect();
+        infos.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(infos)
+    }
+
+    fn restore(&self, id: &str, now: u64) -> Result<(), MutinyError> {
+        let point: RestorePoint = self
+            .get_data(restore_point_key(id))?
+            .ok_or(MutinyError::NotFound)?;
+
+        self.create_restore_point(now)?;
+
+        // remove anything that didn't exist when the restore point was made
+        let mut stale: Vec<String> = RESTORE_POINT_KEYS
+            .iter()
+            .map(|k| k.to_string())
+            .filter(|k| !point.data.contains_key(k))
+            .collect();
+        for prefix in RESTORE_POINT_PREFIXES {
+            stale.extend(
+                self.scan_keys(prefix, None)?
+                    .into_iter()
+                    .filter(|k| !point.data.contains_key(k)),
+            );
+        }
+        self.delete(&stale)?;
+
+        for (key, value) in point.data {
+            self.set_data(key, value, None)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::{Contact, LabelStorage};
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_restore_point() {
+        log!("test restore point");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_restore_points().unwrap().is_empty());
+
+        let contact = Contact {
+            name: "Satoshi".to_string(),
+            ..Default::default()
+        };
+        let id = storage.create_new_contact(contact.clone()).unwrap();
+        let point = storage.create_restore_point(100).unwrap();
+
+        // delete the contact by accident and add another
+        storage.delete(&[format!("{CONTACT_PREFIX}{id}")]).unwrap();
+        let other = Contact {
+            name: "Hal".to_string(),
+            ..Default::default()
+        };
+        storage.create_new_contact(other).unwrap();
+
+        storage.restore(&point.id, 200).unwrap();
+        let contacts = storage.get_contacts().unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts.get(&id), Some(&contact));
+
+        // restoring made a restore point of the state before it
+        let points = storage.list_restore_points().unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].created_at, 200);
+        assert_eq!(points[1], point);
+    }
+
+    #[test]
+    fn test_max_restore_points() {
+        log!("test max restore points");
+
+        let storage = MemoryStorage::default();
+        for i in 0..(MAX_RESTORE_POINTS as u64 + 2) {
+            storage.create_restore_point(i).unwrap();
+        }
+
+        let points = storage.list_restore_points().unwrap();
+        assert_eq!(points.len(), MAX_RESTORE_POINTS);
+        // the oldest were removed
+        assert_eq!(points.last().unwrap().created_at, 2);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use serde::{Deserialize, Serialize};
+
+pub const RETENTION_POLICY_KEY: &str = "retention_policy";
+pub const LAST_PRUNE_TIME_KEY: &str = "last_prune_time";
+
+/// How often the background pruner runs
+pub(crate) const PRUNE_INTERVAL_SECS: u64 = 60 * 60 * 24;
+
+/// How long to keep the full details of old payments and channels.
+///
+/// Once past the retention period, payments drop their HTLC timeline, received
+/// payments drop their invoice and channel closures drop their force close postmortem.
+/// A summary of each (amount, fees, status, preimage, close reason) is kept forever.
+/// Sent payments always keep their invoice so they can still be proven.
+///
+/// Nothing is pruned unless the user opts in by setting a retention period.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// How long to keep full details in seconds, `None` keeps them forever
+    pub detail_retention_secs: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Anything last updated before the returned time should be pruned
+    pub(crate) fn cutoff(&self, now: u64) -> Option<u64> {
+        self.detail_retention_secs
+            .map(|secs| now.saturating_sub(secs))
+    }
+}
+
+/// What a run of the pruner removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneSummary {
+    pub payments: usize,
+    pub channel_closures: usize,
+}
+
+pub trait RetentionStorage {
+    fn get_retention_policy(&self) -> Result<RetentionPolicy, MutinyError>;
+    fn set_retention_policy(&self, policy: RetentionPolicy) -> Result<(), MutinyError>;
+    fn get_last_prune_time(&self) -> Result<Option<u64>, MutinyError>;
+    fn set_last_prune_time(&self, time: u64) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> RetentionStorage for S {
+    fn get_retention_policy(&self) -> Result<RetentionPolicy, MutinyError> {
+        let policy: Option<RetentionPolicy> = self.get_data(RETENTION_POLICY_KEY)?;
+        Ok(policy.unwrap_or_default())
+    }
+
+    fn set_retention_policy(&self, policy: RetentionPolicy) -> Result<(), MutinyError> {
+        self.set_data(RETENTION_POLICY_KEY, policy, None)
+    }
+
+    fn get_last_prune_time(&self) -> Result<Option<u64>, MutinyError> {
+        self.get_data(LAST_PRUNE_TIME_KEY)
+    }
+
+    fn set_last_prune_time(&self, time: u64) -> Result<(), MutinyError> {
+        self.set_data(LAST_PRUNE_TIME_KEY, time, None)
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::MutinyError;
+use crate::status::Service;
+use crate::storage::MutinyStorage;
+use serde::{Deserialize, Serialize};
+
+pub const ROLLOUT_BUCKET_KEY: &str = "rollout_bucket";
+
+/// The number of buckets wallets are spread over, one per percent
+const ROLLOUT_BUCKETS: u8 = 100;
+
+/// An alternative endpoint for a service, used by a percentage of wallets.
+///
+/// Which wallets use it is decided by a bucket each wallet picks once and keeps,
+/// so raising the percentage only ever moves more wallets to the canary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanaryEndpoint {
+    pub service: Service,
+    pub url: String,
+    /// The percentage of wallets that use the canary, from 0 to 100
+    pub percent: u8,
+}
+
+/// The endpoint picked for a service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointSelection {
+    pub service: Service,
+    /// None when the service's default endpoint is used
+    pub url: Option<String>,
+    pub canary: bool,
+}
+
+/// Picks the canary for the service if the wallet's bucket is in its rollout,
+/// otherwise the primary endpoint.
+pub fn select_endpoint(
+    service: Service,
+    primary: Option<String>,
+    canaries: &[CanaryEndpoint],
+    bucket: u8,
+) -> EndpointSelection {
+    let canary = canaries
+        .iter()
+        .find(|c| c.service == service && !c.url.is_empty())
+        .filter(|c| bucket < c.percent.min(ROLLOUT_BUCKETS));
+
+    match canary {
+        Some(canary) => EndpointSelection {
+            service,
+            url: Some(canary.url.clone()),
+            canary: true,
+        },
+        None => EndpointSelection {
+            service,
+            url: primary,
+            canary: false,
+        },
+    }
+}
+
+pub trait RolloutStorage {
+    /// Returns the wallet's rollout bucket, picking one the first time
+    fn get_rollout_bucket(&self) -> Result<u8, MutinyError>;
+}
+
+impl<S: MutinyStorage> RolloutStorage for S {
+    fn get_rollout_bucket(&self) -> Result<u8, MutinyError> {
+        match self.get_data::<u8>(ROLLOUT_BUCKET_KEY)? {
+            Some(bucket) if bucket < ROLLOUT_BUCKETS => Ok(bucket),
+            _ => {
+                let bucket = bitcoin::secp256k1::rand::random::<u8>() % ROLLOUT_BUCKETS;
+                self.set_data(ROLLOUT_BUCKET_KEY, bucket, None)?;
+                Ok(bucket)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_select_endpoint() {
+        log!("test select endpoint");
+
+        let canaries = vec![CanaryEndpoint {
+            service: Service::Esplora,
+            url: "https://canary.example.com/api".to_string(),
+            percent: 10,
+        }];
+        let primary = Some("https://mempool.space/api".to_string());
+
+        let selection = select_endpoint(Service::Esplora, primary.clone(), &canaries, 9);
+        assert!(selection.canary);
+        assert_eq!(
+            selection.url,
+            Some("https://canary.example.com/api".to_string())
+        );
+
+        let selection = select_endpoint(Service::Esplora, primary.clone(), &canaries, 10);
+        assert!(!selection.canary);
+        assert_eq!(selection.url, primary);
+
+        // other services are left alone
+        let selection = select_endpoint(Service::Rgs, None, &canaries, 0);
+        assert!(!selection.canary);
+        assert_eq!(selection.url, None);
+    }
+
+    #[test]
+    fn test_rollout_bucket() {
+        log!("test rollout bucket");
+
+        let storage = MemoryStorage::default();
+        let bucket = storage.get_rollout_bucket().unwrap();
+        assert!(bucket < ROLLOUT_BUCKETS);
+
+        // the wallet keeps its bucket
+        for _ in 0..10 {
+            assert_eq!(storage.get_rollout_bucket().unwrap(), bucket);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use crate::logging::MutinyLogger;
+use crate::payment_retry::{MppConfig, PaymentRetryStorage, RetryPolicy};
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::channelmanager::{ChannelDetails, PaymentId};
+use lightning::ln::msgs::{ErrorAction, LightningError};
+use lightning::ln::PaymentHash;
+use lightning::routing::router::{InFlightHtlcs, Path, Route, RouteParameters, Router};
+use lightning::util::logger::Logger;
+use lightning::{log_debug, log_error};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Wraps a router to keep the routes of a payment under the fee cap of its retry policy
+/// and the fee cap set for all payments, and to split it and lock up funds for no more
+/// than the MPP config and its retry policy allow. LDK finds a new route for every
+/// retry, so this applies to all of them.
+///
+/// A retry only routes what is left of the payment, so the fees of the parts that
+/// are still in flight or have succeeded count against the cap as well.
+pub(crate) struct FeeCappedRouter<R: Router, S: MutinyStorage> {
+    router: R,
+    storage: S,
+    /// Payments that had a route rejected for being over their fee cap
+    over_cap: Mutex<HashSet<PaymentHash>>,
+    /// The paths of each payment that have been sent and haven't failed
+    routed: Mutex<HashMap<PaymentHash, Vec<Path>>>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<R: Router, S: MutinyStorage> FeeCappedRouter<R, S> {
+    pub(crate) fn new(router: R, storage: S, logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            router,
+            storage,
+            over_cap: Mutex::new(HashSet::new()),
+            routed: Mutex::new(HashMap::new()),
+            logger,
+        }
+    }
+
+    fn max_fee_msat(&self, payment_hash: &PaymentHash, amount_msat: u64) -> Option<u64> {
+        let hash = sha256::Hash::from_inner(payment_hash.0);
+        let payment_cap = match self.storage.get_payment_retry(&hash) {
+            Ok(retry) => retry.and_then(|r| r.policy.max_fee_msat(amount_msat)),
+            Err(e) => {
+                log_error!(self.logger, "Failed to read payment retry policy: {e}");
+                None
+            }
+        };
+        let global_cap = match self.storage.get_fee_cap() {
+            Ok(fee_cap) => fee_cap.max_fee_msat(amount_msat),
+            Err(e) => {
+                log_error!(self.logger, "Failed to read fee cap: {e}");
+                None
+            }
+        };
+
+        match (payment_cap, global_cap) {
+            (Some(payment_cap), Some(global_cap)) => Some(payment_cap.min(global_cap)),
+            (payment_cap, global_cap) => payment_cap.or(global_cap),
+        }
+    }
+
+    /// The route parameters with the limits of the payment's retry policy and the
+    /// MPP config: the paths it can be split over and how long its funds can be locked
+    fn limit_route_params(
+        &self,
+        route_params: &RouteParameters,
+        policy: Option<&RetryPolicy>,
+        channels: usize,
+    ) -> RouteParameters {
+        let config = self.storage.get_mpp_config().unwrap_or_else(|e| {
+            log_error!(self.logger, "Failed to read mpp config: {e}");
+            MppConfig::default()
+        });
+
+        let mut route_params = route_params.clone();
+        route_params.payment_params.max_path_count =
+            config.max_path_count(route_params.final_value_msat, policy, channels);
+        if let Some(max_cltv) = policy.and_then(|p| p.max_total_cltv_expiry_delta) {
+            let payment_params = &mut route_params.payment_params;
+            payment_params.max_total_cltv_expiry_delta =
+                payment_params.max_total_cltv_expiry_delta.min(max_cltv);
+        }
+        route_params
+    }
+
+    /// Finds a route for a payment that hasn't been started, with the limits of
+    /// `policy`, without checking it against the fee caps
+    pub(crate) fn find_route_with_policy(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: &[&ChannelDetails],
+        inflight_htlcs: InFlightHtlcs,
+        policy: &RetryPolicy,
+    ) -> Result<Route, LightningError> {
+        let route_params = self.limit_route_params(route_params, Some(policy), first_hops.len());
+        self.router
+            .find_route(payer, &route_params, Some(first_hops), inflight_htlcs)
+    }
+
+    /// Returns true if a route for the payment was rejected for being over its fee cap,
+    /// and forgets it. Used to tell why a payment could not find a route.
+    pub(crate) fn take_over_fee_cap(&self, payment_hash: &PaymentHash) -> bool {
+        self.over_cap
+            .lock()
+            .map(|mut over_cap| over_cap.remove(payment_hash))
+            .unwrap_or(false)
+    }
+
+    /// The payments with a path in flight over the channel, with the amount
+    /// each path sends through it
+    pub(crate) fn in_flight_over_channel(&self, short_channel_id: u64) -> Vec<(PaymentHash, u64)> {
+        let Ok(routed) = self.routed.lock() else {
+            return vec![];
+        };
+        routed
+            .iter()
+            .flat_map(|(payment_hash, paths)| {
+                paths
+                    .iter()
+                    .filter(|p| {
+                        p.hops.first().map(|h| h.short_channel_id) == Some(short_channel_id)
+                    })
+                    .map(|p| (*payment_hash, p.final_value_msat() + p.fee_msat()))
+            })
+            .collect()
+    }
+
+    /// Forgets a path of a payment that failed, its fees won't be paid
+    pub(crate) fn path_failed(&self, payment_hash: &PaymentHash, path: &Path) {
+        if let Ok(mut routed) = self.routed.lock() {
+            if let Some(paths) = routed.get_mut(payment_hash) {
+                if let Some(index) = paths.iter().position(|p| p == path) {
+                    paths.remove(index);
+                }
+            }
+        }
+    }
+
+    /// Forgets the paths of a payment that succeeded or failed
+    pub(crate) fn payment_finished(&self, payment_hash: &PaymentHash) {
+        if let Ok(mut routed) = self.routed.lock() {
+            routed.remove(payment_hash);
+        }
+    }
+}
+
+impl<R: Router, S: MutinyStorage> Router for FeeCappedRouter<R, S> {
+    fn find_route(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: Option<&[&ChannelDetails]>,
+        inflight_htlcs: InFlightHtlcs,
+    ) -> Result<Route, LightningError> {
+        self.router
+            .find_route(payer, route_params, first_hops, inflight_htlcs)
+    }
+
+    fn find_route_with_id(
+        &self,
+        payer: &PublicKey,
+        route_params: &RouteParameters,
+        first_hops: Option<&[&ChannelDetails]>,
+        inflight_htlcs: InFlightHtlcs,
+        payment_hash: PaymentHash,
+        payment_id: PaymentId,
+    ) -> Result<Route, LightningError> {
+        let hash = sha256::Hash::from_inner(payment_hash.0);
+        let retry = self.storage.get_payment_retry(&hash).unwrap_or_else(|e| {
+            log_error!(self.logger, "Failed to read payment retry policy: {e}");
+            None
+        });
+        let channels = first_hops.map_or(0, |hops| hops.len());
+        let route_params =
+            &self.limit_route_params(route_params, retry.as_ref().map(|r| &r.policy), channels);
+        let route = self.router.find_route_with_id(
+            payer,
+            route_params,
+            first_hops,
+            inflight_htlcs,
+            payment_hash,
+            payment_id,
+        )?;
+
+        let mut routed = self.routed.lock().map_err(|_| LightningError {
+            err: "Failed to get routed payments".to_string(),
+            action: ErrorAction::IgnoreError,
+        })?;
+        let paths = routed.entry(payment_hash).or_default();
+        let sent_msat: u64 = paths.iter().map(|p| p.final_value_msat()).sum();
+        let sent_fee_msat: u64 = paths.iter().map(|p| p.fee_msat()).sum();
+
+        let amount_msat = sent_msat + route_params.final_value_msat;
+        let max_fee_msat = self.max_fee_msat(&payment_hash, amount_msat);
+        if let Some(max_fee_msat) = max_fee_msat {
+            let fees = sent_fee_msat + route.get_total_fees();
+            if fees > max_fee_msat {
+                log_debug!(
+                    self.logger,
+                    "Route fee of {fees} msats is over the cap of {max_fee_msat} msats"
+                );
+                if let Ok(mut over_cap) = self.over_cap.lock() {
+                    over_cap.insert(payment_hash);
+                }
+                return Err(LightningError {
+                    err: format!("Route fee of {fees} msats is over the cap"),
+                    action: ErrorAction::IgnoreError,
+                });
+            }
+        }
+        paths.extend(route.paths.iter().cloned());
+
+        Ok(route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payment_retry::FeeCap;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+    use lightning::routing::router::{PaymentParameters, RouteHop};
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Returns the routes it was given, one for each request
+    struct TestRouter {
+        routes: Mutex<Vec<Route>>,
+    }
+
+    impl Router for TestRouter {
+        fn find_route(
+            &self,
+            _payer: &PublicKey,
+            _route_params: &RouteParameters,
+            _first_hops: Option<&[&ChannelDetails]>,
+            _inflight_htlcs: InFlightHtlcs,
+        ) -> Result<Route, LightningError> {
+            Ok(self.routes.lock().unwrap().remove(0))
+        }
+    }
+
+    fn pubkey(byte: u8) -> PublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret)
+    }
+
+    /// A path over a hop that charges `fee_msat` to the payee
+    fn path(short_channel_id: u64, fee_msat: u64, amount_msat: u64) -> Path {
+        let hop = |pubkey, short_channel_id, fee_msat| RouteHop {
+            pubkey,
+            node_features: NodeFeatures::empty(),
+            short_channel_id,
+            channel_features: ChannelFeatures::empty(),
+            fee_msat,
+            cltv_expiry_delta: 40,
+        };
+        Path {
+            hops: vec![
+                hop(pubkey(2), short_channel_id, fee_msat),
+                hop(pubkey(3), short_channel_id + 1, amount_msat),
+            ],
+            blinded_tail: None,
+        }
+    }
+
+    fn route(paths: Vec<Path>) -> Route {
+        Route {
+            paths,
+            payment_params: None,
+        }
+    }
+
+    #[test]
+    fn test_fee_cap_counts_sent_parts_on_retry() {
+        log!("test fee cap counts sent parts on retry");
+
+        let storage = MemoryStorage::default();
+        storage
+            .set_fee_cap(FeeCap {
+                max_fee_msat: Some(1_000),
+                max_fee_percent: None,
+            })
+            .unwrap();
+
+        let failed = path(1, 400, 50_000);
+        let routes = vec![
+            route(vec![failed.clone(), path(10, 400, 50_000)]),
+            // 700 msats is under the cap on its own, but not with what was already sent
+            route(vec![path(20, 700, 50_000)]),
+            route(vec![path(30, 500, 50_000)]),
+        ];
+        let router = FeeCappedRouter::new(
+            TestRouter {
+                routes: Mutex::new(routes),
+            },
+            storage,
+            Arc::new(MutinyLogger::default()),
+        );
+
+        let payment_hash = PaymentHash([1; 32]);
+        let payment_id = PaymentId(payment_hash.0);
+        let find_route = |amount_msat| {
+            let route_params = RouteParameters {
+                final_value_msat: amount_msat,
+                payment_params: PaymentParameters::from_node_id(pubkey(3), 40),
+            };
+            router.find_route_with_id(
+                &pubkey(1),
+                &route_params,
+                None,
+                InFlightHtlcs::new(),
+                payment_hash,
+                payment_id,
+            )
+        };
+
+        assert!(find_route(100_000).is_ok());
+
+        // one part fails and is retried
+        router.path_failed(&payment_hash, &failed);
+        assert!(find_route(50_000).is_err());
+        assert!(router.take_over_fee_cap(&payment_hash));
+        assert!(find_route(50_000).is_ok());
+
+        router.payment_finished(&payment_hash);
+        assert!(router.routed.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,511 @@
+//! A local JSON-RPC 2.0 control interface for headless native deployments.
+//!
+//! Requests are sent as text messages over a websocket. The first request
+//! on every connection must be `authenticate` with the configured token,
+//! any other request before that is rejected.
+
+use crate::error::MutinyError;
+use crate::lnrpc_json::handle_lnrpc_request;
+use crate::nodemanager::{CustomTlv, NodeManager};
+use crate::payment_metadata::PaymentMetadata;
+use crate::payment_retry::RetryPolicy;
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, OutPoint};
+use futures::{SinkExt, StreamExt};
+use lightning::{log_debug, log_error, log_info, log_warn, util::logger::Logger};
+use lightning_invoice::Bolt11Invoice;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+/// Returned when the underlying [NodeManager] call fails
+pub const SERVER_ERROR: i32 = -32000;
+/// Returned when the connection has not authenticated yet
+pub const UNAUTHORIZED: i32 = -32001;
+
+/// Items returned by `list_activity` when no limit is given
+const DEFAULT_ACTIVITY_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<MutinyError> for RpcError {
+    fn from(e: MutinyError) -> Self {
+        match e {
+            MutinyError::InvalidArgumentsError => RpcError::new(INVALID_PARAMS, e.to_string()),
+            _ => RpcError::new(SERVER_ERROR, e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(v) => (Some(v), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Per connection state, tracks whether the client has authenticated.
+pub(crate) struct RpcSession {
+    token: Arc<String>,
+    authenticated: bool,
+}
+
+impl RpcSession {
+    pub(crate) fn new(token: Arc<String>) -> Self {
+        Self {
+            token,
+            authenticated: false,
+        }
+    }
+
+    /// Parses a raw message into a request, handling authentication.
+    /// Returns `Err` with the response to send back if the request
+    /// should not be dispatched to the [NodeManager].
+    pub(crate) fn parse_message(&mut self, msg: &str) -> Result<RpcRequest, RpcResponse> {
+        let req: RpcRequest = serde_json::from_str(msg).map_err(|e| {
+            RpcResponse::new(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())))
+        })?;
+
+        if req.jsonrpc != JSONRPC_VERSION {
+            return Err(RpcResponse::new(
+                req.id,
+                Err(RpcError::new(
+                    INVALID_REQUEST,
+                    "unsupported jsonrpc version",
+                )),
+            ));
+        }
+
+        if req.method == "authenticate" {
+            let result = match param::<String>(&req.params, "token") {
+                Ok(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => {
+                    self.authenticated = true;
+                    Ok(Value::Bool(true))
+                }
+                Ok(_) => Err(RpcError::new(UNAUTHORIZED, "invalid token")),
+                Err(e) => Err(e),
+            };
+            return Err(RpcResponse::new(req.id, result));
+        }
+
+        if !self.authenticated {
+            return Err(RpcResponse::new(
+                req.id,
+                Err(RpcError::new(UNAUTHORIZED, "not authenticated")),
+            ));
+        }
+
+        Ok(req)
+    }
+}
+
+/// Compares the two slices without exiting early so the token
+/// can't be guessed by timing the responses.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+/// Gets a named parameter, missing parameters are treated as `null`
+/// so optional parameters can be omitted.
+pub(crate) fn param<T: DeserializeOwned>(params: &Value, name: &str) -> Result<T, RpcError> {
+    let value = params.get(name).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid param {name}: {e}")))
+}
+
+fn labels_param(params: &Value) -> Result<Vec<String>, RpcError> {
+    Ok(param::<Option<Vec<String>>>(params, "labels")?.unwrap_or_default())
+}
+
+fn invoice_param(params: &Value) -> Result<Bolt11Invoice, RpcError> {
+    let invoice: String = param(params, "invoice")?;
+    Bolt11Invoice::from_str(&invoice).map_err(|_| MutinyError::InvoiceInvalid.into())
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))
+}
+
+/// Uses the `from_node` param if given, otherwise the first node.
+pub(crate) async fn from_node_param<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+    params: &Value,
+) -> Result<PublicKey, RpcError> {
+    match param::<Option<PublicKey>>(params, "from_node")? {
+        Some(node) => Ok(node),
+        None => nm
+            .list_nodes()
+            .await?
+            .first()
+            .copied()
+            .ok_or_else(|| MutinyError::NotFound.into()),
+    }
+}
+
+/// Dispatches an authenticated request to the [NodeManager].
+/// If `lnrpc_methods` is set, the methods from [crate::lnrpc_json] are available as well.
+pub(crate) async fn handle_request<S: MutinyStorage>(
+    nm: &NodeManager<S>,
+    req: &RpcRequest,
+    lnrpc_methods: bool,
+) -> Result<Value, RpcError> {
+    let params = &req.params;
+    if lnrpc_methods {
+        if let Some(result) = handle_lnrpc_request(nm, &req.method, params).await {
+            return result;
+        }
+    }
+
+    match req.method.as_str() {
+        "get_network" => to_value(nm.get_network()),
+        "get_balance" => to_value(nm.get_balance().await?),
+        "get_new_address" => {
+            let address = nm.get_new_address(labels_param(params)?)?;
+            to_value(address.to_string())
+        }
+        "send_to_address" => {
+            let address: Address = param(params, "address")?;
+            let amount: u64 = param(params, "amount")?;
+            let fee_rate: Option<f32> = param(params, "fee_rate")?;
+            let idempotency_key: Option<String> = param(params, "idempotency_key")?;
+            let txid = nm
+                .send_to_address(
+                    address,
+                    amount,
+                    labels_param(params)?,
+                    fee_rate,
+                    idempotency_key,
+                )
+                .await?;
+            to_value(txid)
+        }
+        "list_onchain" => to_value(nm.list_onchain()?),
+        "get_activity" => to_value(nm.get_activity().await?),
+        "list_activity" => {
+            let before: Option<u64> = param(params, "before")?;
+            let limit: Option<usize> = param(params, "limit")?;
+            let label: Option<String> = param(params, "label")?;
+            to_value(
+                nm.list_activity(before, limit.unwrap_or(DEFAULT_ACTIVITY_PAGE_SIZE), label)
+                    .await?,
+            )
+        }
+        "list_nodes" => to_value(nm.list_nodes().await?),
+        "list_peers" => to_value(nm.list_peers().await?),
+        "connect_to_peer" => {
+            let from_node = from_node_param(nm, params).await?;
+            let connection_string: String = param(params, "connection_string")?;
+            let label: Option<String> = param(params, "label")?;
+            nm.connect_to_peer(&from_node, &connection_string, label)
+                .await?;
+            Ok(Value::Null)
+        }
+        "list_channels" => to_value(nm.list_channels().await?),
+        "open_channel" => {
+            let from_node = from_node_param(nm, params).await?;
+            let to_pubkey: Option<PublicKey> = param(params, "to_pubkey")?;
+            let amount: u64 = param(params, "amount")?;
+            let fee_rate: Option<f32> = param(params, "fee_rate")?;
+            let channel = nm
+                .open_channel(&from_node, to_pubkey, amount, fee_rate, None)
+                .await?;
+            to_value(channel)
+        }
+        "close_channel" => {
+            let outpoint: OutPoint = param(params, "outpoint")?;
+            let address: Option<Address> = param(params, "address")?;
+            let force: Option<bool> = param(params, "force")?;
+            let abandon: Option<bool> = param(params, "abandon")?;
+            nm.close_channel(
+                &outpoint,
+                address,
+                force.unwrap_or(false),
+                abandon.unwrap_or(false),
+            )
+            .await?;
+            Ok(Value::Null)
+        }
+        "create_invoice" => {
+            let amount: Option<u64> = param(params, "amount")?;
+            let order_id: Option<String> = param(params, "order_id")?;
+            let expiry_secs: Option<u32> = param(params, "expiry_secs")?;
+            let invoice = nm
+                .create_invoice(amount, labels_param(params)?, order_id, expiry_secs)
+                .await?;
+            to_value(invoice)
+        }
+        "pay_invoice" => {
+            let from_node = from_node_param(nm, params).await?;
+            let invoice = invoice_param(params)?;
+            let amt_sats: Option<u64> = param(params, "amount")?;
+            let idempotency_key: Option<String> = param(params, "idempotency_key")?;
+            let retry_policy: Option<RetryPolicy> = param(params, "retry_policy")?;
+            let metadata: Option<PaymentMetadata> = param(params, "metadata")?;
+            let payment = nm
+                .pay_invoice(
+                    &from_node,
+                    &invoice,
+                    amt_sats,
+                    labels_param(params)?,
+                    idempotency_key,
+                    retry_policy,
+                    metadata,
+                )
+                .await?;
+            to_value(payment)
+        }
+        "keysend" => {
+            let from_node = from_node_param(nm, params).await?;
+            let to_node: PublicKey = param(params, "to_node")?;
+            let amt_sats: u64 = param(params, "amount")?;
+            let idempotency_key: Option<String> = param(params, "idempotency_key")?;
+            let custom_tlvs: Option<Vec<CustomTlv>> = param(params, "custom_tlvs")?;
+            let payment = nm
+                .keysend(
+                    &from_node,
+                    to_node,
+                    amt_sats,
+                    labels_param(params)?,
+                    idempotency_key,
+                    custom_tlvs.unwrap_or_default(),
+                )
+                .await?;
+            to_value(payment)
+        }
+        "decode_invoice" => to_value(nm.decode_invoice(invoice_param(params)?).await?),
+        "get_invoice_by_hash" => {
+            let hash: sha256::Hash = param(params, "hash")?;
+            to_value(nm.get_invoice_by_hash(&hash).await?)
+        }
+        "label_payment" => {
+            let hash: sha256::Hash = param(params, "hash")?;
+            let metadata: PaymentMetadata = param(params, "metadata")?;
+            to_value(nm.label_payment(&hash, metadata).await?)
+        }
+        "list_invoices" => to_value(nm.list_invoices().await?),
+        "handle_uri" => {
+            let uri: String = param(params, "uri")?;
+            to_value(nm.handle_uri(&uri).await?)
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method not found: {method}"),
+        )),
+    }
+}
+
+/// Starts the control interface on the given address and serves
+/// connections until the [NodeManager] is stopped.
+///
+/// Setting `lnrpc_methods` also exposes the lnrpc-shaped methods, see [crate::lnrpc_json].
+///
+/// Connections are spawned with [tokio::task::spawn_local] so this
+/// must be run inside of a [tokio::task::LocalSet].
+pub async fn serve<S: MutinyStorage>(
+    nm: Arc<NodeManager<S>>,
+    addr: SocketAddr,
+    token: String,
+    lnrpc_methods: bool,
+) -> Result<(), MutinyError> {
+    if token.is_empty() {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| MutinyError::Other(e.into()))?;
+    log_info!(nm.logger, "RPC server listening on {addr}");
+
+    let token = Arc::new(token);
+    while !nm.stop.load(Ordering::Relaxed) {
+        // time out so we periodically check if we should stop
+        let (stream, peer_addr) =
+            match tokio::time::timeout(Duration::from_secs(1), listener.accept()).await {
+                Ok(Ok(conn)) => conn,
+                Ok(Err(e)) => {
+                    log_warn!(nm.logger, "Failed to accept RPC connection: {e}");
+                    continue;
+                }
+                Err(_) => continue,
+            };
+
+        log_debug!(nm.logger, "New RPC connection from {peer_addr}");
+        let nm = nm.clone();
+        let token = token.clone();
+        tokio::task::spawn_local(async move {
+            let logger = nm.logger.clone();
+            if let Err(e) = handle_connection(nm, stream, token, lnrpc_methods).await {
+                log_error!(logger, "RPC connection from {peer_addr} failed: {e}");
+            }
+        });
+    }
+
+    log_info!(nm.logger, "RPC server stopped");
+    Ok(())
+}
+
+async fn handle_connection<S: MutinyStorage>(
+    nm: Arc<NodeManager<S>>,
+    stream: TcpStream,
+    token: Arc<String>,
+    lnrpc_methods: bool,
+) -> Result<(), MutinyError> {
+    let mut ws = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(|e| MutinyError::Other(e.into()))?;
+    let mut session = RpcSession::new(token);
+
+    while let Some(msg) = ws.next().await {
+        let text = match msg.map_err(|e| MutinyError::Other(e.into()))? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            // pings are answered by tungstenite
+            _ => continue,
+        };
+
+        let response = match session.parse_message(&text) {
+            Ok(req) => {
+                log_debug!(nm.logger, "Handling RPC request: {}", req.method);
+                let result = handle_request(&nm, &req, lnrpc_methods).await;
+                RpcResponse::new(req.id, result)
+            }
+            Err(response) => response,
+        };
+
+        let json = serde_json::to_string(&response)?;
+        ws.send(Message::Text(json))
+            .await
+            .map_err(|e| MutinyError::Other(e.into()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(method: &str, params: Value) -> String {
+        json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}).to_string()
+    }
+
+    #[test]
+    fn test_rpc_requires_authentication() {
+        let mut session = RpcSession::new(Arc::new("secret".to_string()));
+
+        let res = session
+            .parse_message(&request("get_balance", Value::Null))
+            .unwrap_err();
+        assert_eq!(res.error.unwrap().code, UNAUTHORIZED);
+
+        let res = session
+            .parse_message(&request("authenticate", json!({"token": "wrong"})))
+            .unwrap_err();
+        assert_eq!(res.error.unwrap().code, UNAUTHORIZED);
+
+        let res = session
+            .parse_message(&request("authenticate", json!({"token": "secret"})))
+            .unwrap_err();
+        assert_eq!(res.result, Some(Value::Bool(true)));
+        assert_eq!(res.id, json!(1));
+
+        let req = session
+            .parse_message(&request("get_balance", Value::Null))
+            .unwrap();
+        assert_eq!(req.method, "get_balance");
+    }
+
+    #[test]
+    fn test_rpc_invalid_messages() {
+        let mut session = RpcSession::new(Arc::new("secret".to_string()));
+
+        let res = session.parse_message("not json").unwrap_err();
+        assert_eq!(res.error.unwrap().code, PARSE_ERROR);
+
+        let msg = json!({"jsonrpc": "1.0", "id": 1, "method": "get_balance"}).to_string();
+        let res = session.parse_message(&msg).unwrap_err();
+        assert_eq!(res.error.unwrap().code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_rpc_params() {
+        let params = json!({"amount": 1000, "labels": ["a"]});
+        assert_eq!(param::<u64>(&params, "amount").unwrap(), 1000);
+        assert_eq!(param::<Option<u64>>(&params, "missing").unwrap(), None);
+        assert_eq!(labels_param(&params).unwrap(), vec!["a".to_string()]);
+        assert!(labels_param(&Value::Null).unwrap().is_empty());
+
+        let err = param::<u64>(&params, "missing").unwrap_err();
+        assert_eq!(err.code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::{Address, OutPoint};
+use serde::{Deserialize, Serialize};
+
+pub const SCHEDULED_CLOSES_KEY: &str = "scheduled_closes";
+
+/// A cooperative channel close that waits for cheap on-chain fees
+/// or a specific time window before it happens.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledClose {
+    /// The funding outpoint of the channel to close
+    pub outpoint: OutPoint,
+    /// Only close once the normal priority fee rate is at or below this, in sat/vbyte
+    pub max_fee_rate: Option<f32>,
+    /// Unix timestamp before which we won't close
+    pub not_before: Option<u64>,
+    /// Unix timestamp after which we close regardless of fees
+    pub deadline: Option<u64>,
+    /// Where to send our funds, the on-chain wallet if not set
+    pub address: Option<Address>,
+    pub created_at: u64,
+}
+
+impl ScheduledClose {
+    /// Returns true if the channel should be closed now given
+    /// the current fee rate in sat/vbyte.
+    pub(crate) fn is_ready(&self, fee_rate: f32, now: u64) -> bool {
+        if self.not_before.is_some_and(|t| now < t) {
+            return false;
+        }
+        if self.deadline.is_some_and(|t| now >= t) {
+            return true;
+        }
+
+        self.max_fee_rate.map_or(true, |max| fee_rate <= max)
+    }
+}
+
+pub trait ScheduledCloseStorage {
+    fn get_scheduled_closes(&self) -> Result<Vec<ScheduledClose>, MutinyError>;
+    fn persist_scheduled_closes(&self, closes: Vec<ScheduledClose>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> ScheduledCloseStorage for S {
+    fn get_scheduled_closes(&self) -> Result<Vec<ScheduledClose>, MutinyError> {
+        let closes: Option<Vec<ScheduledClose>> = self.get_data(SCHEDULED_CLOSES_KEY)?;
+        Ok(closes.unwrap_or_default())
+    }
+
+    fn persist_scheduled_closes(&self, closes: Vec<ScheduledClose>) -> Result<(), MutinyError> {
+        self.set_data(SCHEDULED_CLOSES_KEY, closes, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dummy_close() -> ScheduledClose {
+        ScheduledClose {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            max_fee_rate: Some(5.0),
+            not_before: Some(100),
+            deadline: Some(1_000),
+            address: None,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_scheduled_close_is_ready() {
+        log!("test scheduled close is ready");
+
+        let close = dummy_close();
+
+        // too early, even with cheap fees
+        assert!(!close.is_ready(1.0, 50));
+        // fees too high
+        assert!(!close.is_ready(10.0, 500));
+        // fees low enough
+        assert!(close.is_ready(5.0, 500));
+        // past the deadline
+        assert!(close.is_ready(10.0, 1_000));
+
+        // no fee limit closes as soon as the window opens
+        let close = ScheduledClose {
+            max_fee_rate: None,
+            ..dummy_close()
+        };
+        assert!(!close.is_ready(10.0, 50));
+        assert!(close.is_ready(10.0, 100));
+    }
+
+    #[test]
+    fn test_scheduled_close_storage() {
+        log!("test scheduled close storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_scheduled_closes().unwrap().is_empty());
+
+        let closes = vec![dummy_close()];
+        storage.persist_scheduled_closes(closes.clone()).unwrap();
+        assert_eq!(storage.get_scheduled_closes().unwrap(), closes);
+    }
+}
@@ -0,0 +1,244 @@
+//! Signed requests to Mutiny's backend services.
+//!
+//! Each service gets its own key, derived from the seed for the service's host,
+//! so services can recognize a wallet across requests without an account and
+//! without being able to link it to the wallet's other services.
+//!
+//! A signed request carries three headers:
+//!
+//! - `X-Mutiny-Pubkey`: the hex compressed public key for the service
+//! - `X-Mutiny-Timestamp`: the epoch time in seconds the request was signed at
+//! - `X-Mutiny-Signature`: a DER hex ECDSA signature of the sha256 of
+//!   `mutiny-request\n<METHOD>\n<host><path>[?<query>]\n<timestamp>\n<hex sha256 of body>`
+//!
+//! Services should reject requests whose timestamp is too far from their clock.
+
+use crate::error::MutinyError;
+use crate::http_client::{HttpRequest, HttpResponse, MutinyHttpClient};
+use crate::utils;
+use async_trait::async_trait;
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{ecdsa, All, Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
+use std::str::FromStr;
+use std::sync::Arc;
+use url::Url;
+
+pub const PUBKEY_HEADER: &str = "X-Mutiny-Pubkey";
+pub const TIMESTAMP_HEADER: &str = "X-Mutiny-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Mutiny-Signature";
+
+/// How far a request's timestamp can be from the service's clock, in seconds
+pub const MAX_REQUEST_AGE_SECS: u64 = 300;
+
+const SERVICE_KEY_PATH: &str = "m/140'";
+
+/// Derives the per service keys and signs requests with them
+#[derive(Clone)]
+pub struct ServiceKeys {
+    xprivkey: ExtendedPrivKey,
+    context: Secp256k1<All>,
+}
+
+impl ServiceKeys {
+    pub fn new(xprivkey: ExtendedPrivKey) -> Self {
+        Self {
+            xprivkey,
+            context: Secp256k1::new(),
+        }
+    }
+
+    /// The key for a service, which only depends on the host of its url
+    pub(crate) fn secret_key(&self, url: &Url) -> Result<SecretKey, MutinyError> {
+        let host = url.host_str().ok_or(MutinyError::InvalidArgumentsError)?;
+
+        let base_path = DerivationPath::from_str(SERVICE_KEY_PATH)?;
+        let base = self.xprivkey.derive_priv(&self.context, &base_path)?;
+
+        // like LNURL-auth, hash the host into the rest of the path
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&base.private_key.secret_bytes());
+        engine.input(host.to_lowercase().as_bytes());
+        let hash = hmac::Hmac::<sha256::Hash>::from_engine(engine).into_inner();
+        let path: Vec<ChildNumber> = hash[..16]
+            .chunks(4)
+            .map(|c| {
+                ChildNumber::from_normal_idx(u32::from_be_bytes([c[0], c[1], c[2], c[3]]) >> 1)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let key = base.derive_priv(&self.context, &path)?;
+        Ok(key.private_key)
+    }
+
+    /// The public key a service will see for this wallet
+    pub fn public_key(&self, url: &str) -> Result<PublicKey, MutinyError> {
+        let url = Url::parse(url)?;
+        Ok(self.secret_key(&url)?.public_key(&self.context))
+    }
+
+    /// Adds the signature headers to a request, signed at the current time
+    pub fn sign_request(&self, request: HttpRequest) -> Result<HttpRequest, MutinyError> {
+        self.sign_request_at(request, utils::now().as_secs())
+    }
+
+    fn sign_request_at(
+        &self,
+        request: HttpRequest,
+        timestamp: u64,
+    ) -> Result<HttpRequest, MutinyError> {
+        let url = Url::parse(&request.url)?;
+        let secret_key = self.secret_key(&url)?;
+        let pubkey = secret_key.public_key(&self.context);
+
+        let msg = signing_message(&request, &url, timestamp)?;
+        let sig = self.context.sign_ecdsa(&msg, &secret_key);
+
+        Ok(request
+            .with_header(PUBKEY_HEADER, pubkey.to_string())
+            .with_header(TIMESTAMP_HEADER, timestamp.to_string())
+            .with_header(SIGNATURE_HEADER, sig.to_string()))
+    }
+}
+
+fn signing_message(
+    request: &HttpRequest,
+    url: &Url,
+    timestamp: u64,
+) -> Result<Message, MutinyError> {
+    let host = url.host_str().ok_or(MutinyError::InvalidArgumentsError)?;
+    let target = match url.query() {
+        Some(query) => format!("{host}{}?{query}", url.path()),
+        None => format!("{host}{}", url.path()),
+    };
+    let body_hash = sha256::Hash::hash(request.body.as_deref().unwrap_or_default());
+    let preimage = format!(
+        "mutiny-request\n{}\n{target}\n{timestamp}\n{body_hash}",
+        request.method.as_str()
+    );
+
+    let hash = sha256::Hash::hash(preimage.as_bytes());
+    Ok(Message::from_slice(&hash).expect("32 bytes, guaranteed by type"))
+}
+
+/// Checks the signature headers of a request, as a service would.
+/// Returns the public key that signed it.
+pub fn verify_signed_request(request: &HttpRequest, now: u64) -> Result<PublicKey, MutinyError> {
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .ok_or(MutinyError::InvalidArgumentsError)
+    };
+
+    let pubkey = PublicKey::from_str(header(PUBKEY_HEADER)?)
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+    let timestamp: u64 = header(TIMESTAMP_HEADER)?
+        .parse()
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+    if timestamp.abs_diff(now) > MAX_REQUEST_AGE_SECS {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+    let sig = ecdsa::Signature::from_str(header(SIGNATURE_HEADER)?)
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+    let url = Url::parse(&request.url)?;
+    let msg = signing_message(request, &url, timestamp)?;
+    Secp256k1::verification_only()
+        .verify_ecdsa(&msg, &sig, &pubkey)
+        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+    Ok(pubkey)
+}
+
+/// A [MutinyHttpClient] that signs every request before sending it with another client
+pub struct SignedHttpClient {
+    inner: Arc<dyn MutinyHttpClient>,
+    keys: ServiceKeys,
+}
+
+impl SignedHttpClient {
+    pub fn new(inner: Arc<dyn MutinyHttpClient>, keys: ServiceKeys) -> Self {
+        Self { inner, keys }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl MutinyHttpClient for SignedHttpClient {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, MutinyError> {
+        let request = self.keys.sign_request(request)?;
+        self.inner.send(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_seed;
+    use crate::test_utils::*;
+    use bitcoin::Network;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn create_keys() -> ServiceKeys {
+        let seed = generate_seed(12).unwrap().to_seed("");
+        ServiceKeys::new(ExtendedPrivKey::new_master(Network::Regtest, &seed).unwrap())
+    }
+
+    #[test]
+    fn test_service_keys() {
+        log!("test service keys");
+
+        let keys = create_keys();
+        let storage = keys
+            .public_key("https://storage.mutinywallet.com/v2/putObjects")
+            .unwrap();
+        let same_host = keys
+            .public_key("https://storage.mutinywallet.com/v2/getObject")
+            .unwrap();
+        let other_host = keys
+            .public_key("https://notify.mutinywallet.com/v1/register")
+            .unwrap();
+        assert_eq!(storage, same_host);
+        assert_ne!(storage, other_host);
+
+        // another seed gets other keys
+        let other_seed = create_keys()
+            .public_key("https://storage.mutinywallet.com")
+            .unwrap();
+        assert_ne!(storage, other_seed);
+    }
+
+    #[test]
+    fn test_signed_request() {
+        log!("test signed request");
+
+        let keys = create_keys();
+        let now = 1_700_000_000;
+        let body = serde_json::json!({ "store_id": "abc" });
+        let request =
+            HttpRequest::post_json("https://storage.mutinywallet.com/v2/putObjects", &body)
+                .unwrap();
+        let signed = keys.sign_request_at(request, now).unwrap();
+
+        let pubkey = verify_signed_request(&signed, now + 10).unwrap();
+        assert_eq!(
+            pubkey,
+            keys.public_key("https://storage.mutinywallet.com").unwrap()
+        );
+
+        // too old
+        assert!(verify_signed_request(&signed, now + MAX_REQUEST_AGE_SECS + 1).is_err());
+
+        // a changed body or url doesn't verify
+        let mut tampered = signed.clone();
+        tampered.body = Some(b"{}".to_vec());
+        assert!(verify_signed_request(&tampered, now).is_err());
+        let mut tampered = signed;
+        tampered.url = "https://storage.mutinywallet.com/v2/deleteObject".to_string();
+        assert!(verify_signed_request(&tampered, now).is_err());
+    }
+}
@@ -0,0 +1,725 @@
+//! SLIP-39 Shamir backups of the wallet's seed.
+//!
+//! The master secret that gets split is the BIP39 entropy of the wallet's
+//! mnemonic, so restoring from the shares gives back the same mnemonic.
+//! Note this means the shares do not derive the same wallet when used in
+//! a hardware wallet that treats the master secret as a BIP32 seed.
+//!
+//! See <https://github.com/satoshilabs/slips/blob/master/slip-0039.md>
+
+use crate::error::MutinyError;
+use bip39::Mnemonic;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use pbkdf2::pbkdf2;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+const RADIX_BITS: usize = 10;
+const ID_LENGTH_BITS: usize = 15;
+/// Identifier, extendable flag, iteration exponent, group index, group threshold,
+/// group count, member index and member threshold
+const HEADER_LENGTH_BITS: usize = 40;
+const CHECKSUM_LENGTH_WORDS: usize = 3;
+const MIN_STRENGTH_BYTES: usize = 16;
+const MIN_MNEMONIC_LENGTH_WORDS: usize = 20;
+const MAX_SHARE_COUNT: u8 = 16;
+
+const CUSTOMIZATION_STRING_ORIG: &[u8] = b"shamir";
+const CUSTOMIZATION_STRING_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+const DIGEST_LENGTH_BYTES: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+
+const BASE_ITERATION_COUNT: u32 = 10_000;
+const ROUND_COUNT: u8 = 4;
+/// Iteration exponent used for new shares, same as the reference implementation
+const ITERATION_EXPONENT: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+/// Splits the mnemonic's entropy into `count` SLIP-39 shares,
+/// any `threshold` of which can be used to restore it.
+pub fn split_mnemonic(
+    mnemonic: &Mnemonic,
+    threshold: u8,
+    count: u8,
+) -> Result<Vec<String>, MutinyError> {
+    let entropy = mnemonic.to_entropy();
+
+    let mut id_bytes = [0u8; 2];
+    getrandom::getrandom(&mut id_bytes).map_err(|_| MutinyError::SeedGenerationFailed)?;
+    let identifier = u16::from_be_bytes(id_bytes) & ((1 << ID_LENGTH_BITS) - 1);
+
+    let encrypted = encrypt(&entropy, b"", ITERATION_EXPONENT, identifier, true);
+    let shares = split_secret(threshold, count, &encrypted)?;
+
+    let mnemonics = shares
+        .into_iter()
+        .map(|(index, value)| {
+            encode_share(&Share {
+                identifier,
+                extendable: true,
+                iteration_exponent: ITERATION_EXPONENT,
+                group_index: 0,
+                group_threshold: 1,
+                group_count: 1,
+                index,
+                member_threshold: threshold,
+                value,
+            })
+        })
+        .collect();
+
+    Ok(mnemonics)
+}
+
+/// Restores a mnemonic from SLIP-39 shares created with [split_mnemonic].
+pub fn combine_shares(shares: &[String]) -> Result<Mnemonic, MutinyError> {
+    let entropy = combine_master_secret(shares, b"")?;
+    Mnemonic::from_entropy(&entropy).map_err(|_| MutinyError::InvalidMnemonic)
+}
+
+fn combine_master_secret(mnemonics: &[String], passphrase: &[u8]) -> Result<Vec<u8>, MutinyError> {
+    let shares = mnemonics
+        .iter()
+        .map(|m| decode_share(m))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = shares.first().ok_or(MutinyError::InvalidMnemonic)?;
+
+    // all shares need to be from the same split
+    if shares.iter().any(|s| {
+        s.identifier != first.identifier
+            || s.extendable != first.extendable
+            || s.iteration_exponent != first.iteration_exponent
+            || s.group_threshold != first.group_threshold
+            || s.group_count != first.group_count
+    }) {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+    for share in shares.iter() {
+        groups.entry(share.group_index).or_default().push(share);
+    }
+
+    let mut group_secrets = Vec::with_capacity(groups.len());
+    for (group_index, members) in groups {
+        let member_threshold = members[0].member_threshold;
+        if members
+            .iter()
+            .any(|s| s.member_threshold != member_threshold)
+        {
+            return Err(MutinyError::InvalidMnemonic);
+        }
+        // skip groups we don't have enough shares for
+        if members.len() < member_threshold as usize {
+            continue;
+        }
+
+        let member_shares: Vec<(u8, Vec<u8>)> = members
+            .iter()
+            .take(member_threshold as usize)
+            .map(|s| (s.index, s.value.clone()))
+            .collect();
+        let secret = recover_secret(member_threshold, &member_shares)?;
+        group_secrets.push((group_index, secret));
+    }
+
+    if group_secrets.len() < first.group_threshold as usize {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+    group_secrets.truncate(first.group_threshold as usize);
+
+    let encrypted = recover_secret(first.group_threshold, &group_secrets)?;
+    Ok(decrypt(
+        &encrypted,
+        passphrase,
+        first.iteration_exponent,
+        first.identifier,
+        first.extendable,
+    ))
+}
+
+fn customization_string(extendable: bool) -> &'static [u8] {
+    if extendable {
+        CUSTOMIZATION_STRING_EXTENDABLE
+    } else {
+        CUSTOMIZATION_STRING_ORIG
+    }
+}
+
+fn rs1024_polymod(values: impl Iterator<Item = u32>) -> u32 {
+    const GEN: [u32; 10] = [
+        0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009, 0x1C0C2412, 0x38086C24, 0x3090FC48,
+        0x21B1F890, 0x3F3F120,
+    ];
+
+    let mut chk: u32 = 1;
+    for v in values {
+        let b = chk >> 20;
+        chk = ((chk & 0xFFFFF) << 10) ^ v;
+        for (i, g) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn rs1024_create_checksum(extendable: bool, data: &[u16]) -> Vec<u16> {
+    let values = customization_string(extendable)
+        .iter()
+        .map(|c| *c as u32)
+        .chain(data.iter().map(|d| *d as u32))
+        .chain([0; CHECKSUM_LENGTH_WORDS]);
+    let polymod = rs1024_polymod(values) ^ 1;
+
+    (0..CHECKSUM_LENGTH_WORDS)
+        .rev()
+        .map(|i| ((polymod >> (RADIX_BITS * i)) & 1023) as u16)
+        .collect()
+}
+
+fn rs1024_verify_checksum(extendable: bool, data: &[u16]) -> bool {
+    let values = customization_string(extendable)
+        .iter()
+        .map(|c| *c as u32)
+        .chain(data.iter().map(|d| *d as u32));
+    rs1024_polymod(values) == 1
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: u64, len: usize) {
+    for i in (0..len).rev() {
+        bits.push((value >> i) & 1 == 1);
+    }
+}
+
+fn read_bits(bits: &[bool]) -> u64 {
+    bits.iter().fold(0, |acc, b| (acc << 1) | *b as u64)
+}
+
+fn encode_share(share: &Share) -> String {
+    let mut bits = Vec::new();
+    push_bits(&mut bits, share.identifier as u64, ID_LENGTH_BITS);
+    push_bits(&mut bits, share.extendable as u64, 1);
+    push_bits(&mut bits, share.iteration_exponent as u64, 4);
+    push_bits(&mut bits, share.group_index as u64, 4);
+    push_bits(&mut bits, (share.group_threshold - 1) as u64, 4);
+    push_bits(&mut bits, (share.group_count - 1) as u64, 4);
+    push_bits(&mut bits, share.index as u64, 4);
+    push_bits(&mut bits, (share.member_threshold - 1) as u64, 4);
+
+    // the value is left padded with zeros to a multiple of the radix
+    let padding = (RADIX_BITS - (share.value.len() * 8) % RADIX_BITS) % RADIX_BITS;
+    push_bits(&mut bits, 0, padding);
+    for byte in share.value.iter() {
+        push_bits(&mut bits, *byte as u64, 8);
+    }
+
+    let mut words: Vec<u16> = bits
+        .chunks(RADIX_BITS)
+        .map(|chunk| read_bits(chunk) as u16)
+        .collect();
+    let checksum = rs1024_create_checksum(share.extendable, &words);
+    words.extend(checksum);
+
+    words
+        .into_iter()
+        .map(|w| WORDLIST[w as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn decode_share(mnemonic: &str) -> Result<Share, MutinyError> {
+    let words = mnemonic
+        .split_whitespace()
+        .map(|word| {
+            WORDLIST
+                .binary_search(&word.to_lowercase().as_str())
+                .map(|i| i as u16)
+                .map_err(|_| MutinyError::InvalidMnemonic)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if words.len() < MIN_MNEMONIC_LENGTH_WORDS {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    let mut bits = Vec::with_capacity(words.len() * RADIX_BITS);
+    for word in words.iter() {
+        push_bits(&mut bits, *word as u64, RADIX_BITS);
+    }
+
+    let extendable = bits[ID_LENGTH_BITS];
+    if !rs1024_verify_checksum(extendable, &words) {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    let value_bits = &bits[HEADER_LENGTH_BITS..bits.len() - CHECKSUM_LENGTH_WORDS * RADIX_BITS];
+    let padding = value_bits.len() % 16;
+    if padding > 8 || value_bits[..padding].iter().any(|b| *b) {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+    let value: Vec<u8> = value_bits[padding..]
+        .chunks(8)
+        .map(|chunk| read_bits(chunk) as u8)
+        .collect();
+    if value.len() < MIN_STRENGTH_BYTES {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    let group_threshold = read_bits(&bits[24..28]) as u8 + 1;
+    let group_count = read_bits(&bits[28..32]) as u8 + 1;
+    if group_threshold > group_count {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    Ok(Share {
+        identifier: read_bits(&bits[..ID_LENGTH_BITS]) as u16,
+        extendable,
+        iteration_exponent: read_bits(&bits[16..20]) as u8,
+        group_index: read_bits(&bits[20..24]) as u8,
+        group_threshold,
+        group_count,
+        index: read_bits(&bits[32..36]) as u8,
+        member_threshold: read_bits(&bits[36..40]) as u8 + 1,
+        value,
+    })
+}
+
+/// Log and exp tables for GF(256) with the Rijndael polynomial
+fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+
+    let mut poly: u16 = 1;
+    for (i, e) in exp.iter_mut().enumerate() {
+        *e = poly as u8;
+        log[poly as usize] = i as u8;
+        // multiply by the generator x + 1
+        poly ^= poly << 1;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+    }
+
+    (exp, log)
+}
+
+/// Evaluates the polynomial going through the given points at `x`.
+fn interpolate(shares: &[(u8, Vec<u8>)], x: u8) -> Result<Vec<u8>, MutinyError> {
+    let len = shares.first().ok_or(MutinyError::InvalidMnemonic)?.1.len();
+    for (i, (xi, value)) in shares.iter().enumerate() {
+        if value.len() != len || shares[..i].iter().any(|(xj, _)| xj == xi) {
+            return Err(MutinyError::InvalidMnemonic);
+        }
+    }
+
+    if let Some((_, value)) = shares.iter().find(|(xi, _)| *xi == x) {
+        return Ok(value.clone());
+    }
+
+    let (exp, log) = gf_tables();
+    let log_prod: i32 = shares
+        .iter()
+        .map(|(xi, _)| log[(xi ^ x) as usize] as i32)
+        .sum();
+
+    let mut result = vec![0u8; len];
+    for (xi, value) in shares.iter() {
+        let log_basis = log_prod
+            - log[(xi ^ x) as usize] as i32
+            - shares
+                .iter()
+                .filter(|(xj, _)| xj != xi)
+                .map(|(xj, _)| log[(xj ^ xi) as usize] as i32)
+                .sum::<i32>();
+        let log_basis = log_basis.rem_euclid(255);
+
+        for (r, v) in result.iter_mut().zip(value.iter()) {
+            if *v != 0 {
+                *r ^= exp[((log[*v as usize] as i32 + log_basis) % 255) as usize];
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+fn create_digest(random_data: &[u8], secret: &[u8]) -> [u8; DIGEST_LENGTH_BYTES] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(random_data);
+    engine.input(secret);
+    let hmac = Hmac::<sha256::Hash>::from_engine(engine).into_inner();
+
+    let mut digest = [0u8; DIGEST_LENGTH_BYTES];
+    digest.copy_from_slice(&hmac[..DIGEST_LENGTH_BYTES]);
+    digest
+}
+
+fn split_secret(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+) -> Result<Vec<(u8, Vec<u8>)>, MutinyError> {
+    if threshold == 0 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+    // a 1-of-n split would just be n copies of the secret
+    if threshold == 1 && count > 1 {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+    if threshold == 1 {
+        return Ok(vec![(0, secret.to_vec())]);
+    }
+
+    let random_share_count = threshold - 2;
+    let mut shares = Vec::with_capacity(count as usize);
+    for i in 0..random_share_count {
+        let mut value = vec![0u8; secret.len()];
+        getrandom::getrandom(&mut value).map_err(|_| MutinyError::SeedGenerationFailed)?;
+        shares.push((i, value));
+    }
+
+    let mut random_part = vec![0u8; secret.len() - DIGEST_LENGTH_BYTES];
+    getrandom::getrandom(&mut random_part).map_err(|_| MutinyError::SeedGenerationFailed)?;
+    let mut digest = create_digest(&random_part, secret).to_vec();
+    digest.extend(random_part);
+
+    let mut base_shares = shares.clone();
+    base_shares.push((DIGEST_INDEX, digest));
+    base_shares.push((SECRET_INDEX, secret.to_vec()));
+
+    for i in random_share_count..count {
+        shares.push((i, interpolate(&base_shares, i)?));
+    }
+
+    Ok(shares)
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, MutinyError> {
+    if threshold == 1 {
+        return shares
+            .first()
+            .map(|(_, value)| value.clone())
+            .ok_or(MutinyError::InvalidMnemonic);
+    }
+
+    let secret = interpolate(shares, SECRET_INDEX)?;
+    let digest_share = interpolate(shares, DIGEST_INDEX)?;
+    let (digest, random_part) = digest_share.split_at(DIGEST_LENGTH_BYTES);
+    if digest != create_digest(random_part, &secret) {
+        return Err(MutinyError::InvalidMnemonic);
+    }
+
+    Ok(secret)
+}
+
+fn round_function(
+    round: u8,
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    salt: &[u8],
+    r: &[u8],
+) -> Vec<u8> {
+    let mut password = vec![round];
+    password.extend_from_slice(passphrase);
+    let mut full_salt = salt.to_vec();
+    full_salt.extend_from_slice(r);
+
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+    let mut out = vec![0u8; r.len()];
+    pbkdf2::<hmac::Hmac<Sha256>>(&password, &full_salt, iterations, &mut out);
+    out
+}
+
+fn feistel_salt(identifier: u16, extendable: bool) -> Vec<u8> {
+    if extendable {
+        vec![]
+    } else {
+        let mut salt = CUSTOMIZATION_STRING_ORIG.to_vec();
+        salt.extend_from_slice(&identifier.to_be_bytes());
+        salt
+    }
+}
+
+fn feistel(
+    secret: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    salt: &[u8],
+    rounds: impl Iterator<Item = u8>,
+) -> Vec<u8> {
+    let (l, r) = secret.split_at(secret.len() / 2);
+    let (mut l, mut r) = (l.to_vec(), r.to_vec());
+    for round in rounds {
+        let f = round_function(round, passphrase, iteration_exponent, salt, &r);
+        let new_r: Vec<u8> = l.iter().zip(f.iter()).map(|(a, b)| a ^ b).collect();
+        l = r;
+        r = new_r;
+    }
+
+    r.extend(l);
+    r
+}
+
+fn encrypt(
+    master_secret: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+) -> Vec<u8> {
+    let salt = feistel_salt(identifier, extendable);
+    feistel(
+        master_secret,
+        passphrase,
+        iteration_exponent,
+        &salt,
+        0..ROUND_COUNT,
+    )
+}
+
+fn decrypt(
+    encrypted: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+) -> Vec<u8> {
+    let salt = feistel_salt(identifier, extendable);
+    feistel(
+        encrypted,
+        passphrase,
+        iteration_exponent,
+        &salt,
+        (0..ROUND_COUNT).rev(),
+    )
+}
+
+const WORDLIST: [&str; 1024] = [
+    "academic", "acid", "acne", "acquire", "acrobat", "activity", "actress", "adapt", "adequate",
+    "adjust", "admit", "adorn", "adult", "advance", "advocate", "afraid", "again", "agency",
+    "agree", "aide", "aircraft", "airline", "airport", "ajar", "alarm", "album", "alcohol",
+    "alien", "alive", "alpha", "already", "alto", "aluminum", "always", "amazing", "ambition",
+    "amount", "amuse", "analysis", "anatomy", "ancestor", "ancient", "angel", "angry", "animal",
+    "answer", "antenna", "anxiety", "apart", "aquatic", "arcade", "arena", "argue", "armed",
+    "artist", "artwork", "aspect", "auction", "august", "aunt", "average", "aviation", "avoid",
+    "award", "away", "axis", "axle", "beam", "beard", "beaver", "become", "bedroom", "behavior",
+    "being", "believe", "belong", "benefit", "best", "beyond", "bike", "biology", "birthday",
+    "bishop", "black", "blanket", "blessing", "blimp", "blind", "blue", "body", "bolt", "boring",
+    "born", "both", "boundary", "bracelet", "branch", "brave", "breathe", "briefing", "broken",
+    "brother", "browser", "bucket", "budget", "building", "bulb", "bulge", "bumpy", "bundle",
+    "burden", "burning", "busy", "buyer", "cage", "calcium", "camera", "campus", "canyon",
+    "capacity", "capital", "capture", "carbon", "cards", "careful", "cargo", "carpet", "carve",
+    "category", "cause", "ceiling", "center", "ceramic", "champion", "change", "charity", "check",
+    "chemical", "chest", "chew", "chubby", "cinema", "civil", "class", "clay", "cleanup", "client",
+    "climate", "clinic", "clock", "clogs", "closet", "clothes", "club", "cluster", "coal",
+    "coastal", "coding", "column", "company", "corner", "costume", "counter", "course", "cover",
+    "cowboy", "cradle", "craft", "crazy", "credit", "cricket", "criminal", "crisis", "critical",
+    "crowd", "crucial", "crunch", "crush", "crystal", "cubic", "cultural", "curious", "curly",
+    "custody", "cylinder", "daisy", "damage", "dance", "darkness", "database", "daughter",
+    "deadline", "deal", "debris", "debut", "decent", "decision", "declare", "decorate", "decrease",
+    "deliver", "demand", "density", "deny", "depart", "depend", "depict", "deploy", "describe",
+    "desert", "desire", "desktop", "destroy", "detailed", "detect", "device", "devote", "diagnose",
+    "dictate", "diet", "dilemma", "diminish", "dining", "diploma", "disaster", "discuss",
+    "disease", "dish", "dismiss", "display", "distance", "dive", "divorce", "document", "domain",
+    "domestic", "dominant", "dough", "downtown", "dragon", "dramatic", "dream", "dress", "drift",
+    "drink", "drove", "drug", "dryer", "duckling", "duke", "duration", "dwarf", "dynamic", "early",
+    "earth", "easel", "easy", "echo", "eclipse", "ecology", "edge", "editor", "educate", "either",
+    "elbow", "elder", "election", "elegant", "element", "elephant", "elevator", "elite", "else",
+    "email", "emerald", "emission", "emperor", "emphasis", "employer", "empty", "ending",
+    "endless", "endorse", "enemy", "energy", "enforce", "engage", "enjoy", "enlarge", "entrance",
+    "envelope", "envy", "epidemic", "episode", "equation", "equip", "eraser", "erode", "escape",
+    "estate", "estimate", "evaluate", "evening", "evidence", "evil", "evoke", "exact", "example",
+    "exceed", "exchange", "exclude", "excuse", "execute", "exercise", "exhaust", "exotic",
+    "expand", "expect", "explain", "express", "extend", "extra", "eyebrow", "facility", "fact",
+    "failure", "faint", "fake", "false", "family", "famous", "fancy", "fangs", "fantasy", "fatal",
+    "fatigue", "favorite", "fawn", "fiber", "fiction", "filter", "finance", "findings", "finger",
+    "firefly", "firm", "fiscal", "fishing", "fitness", "flame", "flash", "flavor", "flea",
+    "flexible", "flip", "float", "floral", "fluff", "focus", "forbid", "force", "forecast",
+    "forget", "formal", "fortune", "forward", "founder", "fraction", "fragment", "frequent",
+    "freshman", "friar", "fridge", "friendly", "frost", "froth", "frozen", "fumes", "funding",
+    "furl", "fused", "galaxy", "game", "garbage", "garden", "garlic", "gasoline", "gather",
+    "general", "genius", "genre", "genuine", "geology", "gesture", "glad", "glance", "glasses",
+    "glen", "glimpse", "goat", "golden", "graduate", "grant", "grasp", "gravity", "gray",
+    "greatest", "grief", "grill", "grin", "grocery", "gross", "group", "grownup", "grumpy",
+    "guard", "guest", "guilt", "guitar", "gums", "hairy", "hamster", "hand", "hanger", "harvest",
+    "have", "havoc", "hawk", "hazard", "headset", "health", "hearing", "heat", "helpful", "herald",
+    "herd", "hesitate", "hobo", "holiday", "holy", "home", "hormone", "hospital", "hour", "huge",
+    "human", "humidity", "hunting", "husband", "hush", "husky", "hybrid", "idea", "identify",
+    "idle", "image", "impact", "imply", "improve", "impulse", "include", "income", "increase",
+    "index", "indicate", "industry", "infant", "inform", "inherit", "injury", "inmate", "insect",
+    "inside", "install", "intend", "intimate", "invasion", "involve", "iris", "island", "isolate",
+    "item", "ivory", "jacket", "jerky", "jewelry", "join", "judicial", "juice", "jump", "junction",
+    "junior", "junk", "jury", "justice", "kernel", "keyboard", "kidney", "kind", "kitchen",
+    "knife", "knit", "laden", "ladle", "ladybug", "lair", "lamp", "language", "large", "laser",
+    "laundry", "lawsuit", "leader", "leaf", "learn", "leaves", "lecture", "legal", "legend",
+    "legs", "lend", "length", "level", "liberty", "library", "license", "lift", "likely", "lilac",
+    "lily", "lips", "liquid", "listen", "literary", "living", "lizard", "loan", "lobe", "location",
+    "losing", "loud", "loyalty", "luck", "lunar", "lunch", "lungs", "luxury", "lying", "lyrics",
+    "machine", "magazine", "maiden", "mailman", "main", "makeup", "making", "mama", "manager",
+    "mandate", "mansion", "manual", "marathon", "march", "market", "marvel", "mason", "material",
+    "math", "maximum", "mayor", "meaning", "medal", "medical", "member", "memory", "mental",
+    "merchant", "merit", "method", "metric", "midst", "mild", "military", "mineral", "minister",
+    "miracle", "mixed", "mixture", "mobile", "modern", "modify", "moisture", "moment", "morning",
+    "mortgage", "mother", "mountain", "mouse", "move", "much", "mule", "multiple", "muscle",
+    "museum", "music", "mustang", "nail", "national", "necklace", "negative", "nervous", "network",
+    "news", "nuclear", "numb", "numerous", "nylon", "oasis", "obesity", "object", "observe",
+    "obtain", "ocean", "often", "olympic", "omit", "oral", "orange", "orbit", "order", "ordinary",
+    "organize", "ounce", "oven", "overall", "owner", "paces", "pacific", "package", "paid",
+    "painting", "pajamas", "pancake", "pants", "papa", "paper", "parcel", "parking", "party",
+    "patent", "patrol", "payment", "payroll", "peaceful", "peanut", "peasant", "pecan", "penalty",
+    "pencil", "percent", "perfect", "permit", "petition", "phantom", "pharmacy", "photo", "phrase",
+    "physics", "pickup", "picture", "piece", "pile", "pink", "pipeline", "pistol", "pitch",
+    "plains", "plan", "plastic", "platform", "playoff", "pleasure", "plot", "plunge", "practice",
+    "prayer", "preach", "predator", "pregnant", "premium", "prepare", "presence", "prevent",
+    "priest", "primary", "priority", "prisoner", "privacy", "prize", "problem", "process",
+    "profile", "program", "promise", "prospect", "provide", "prune", "public", "pulse", "pumps",
+    "punish", "puny", "pupal", "purchase", "purple", "python", "quantity", "quarter", "quick",
+    "quiet", "race", "racism", "radar", "railroad", "rainbow", "raisin", "random", "ranked",
+    "rapids", "raspy", "reaction", "realize", "rebound", "rebuild", "recall", "receiver",
+    "recover", "regret", "regular", "reject", "relate", "remember", "remind", "remove", "render",
+    "repair", "repeat", "replace", "require", "rescue", "research", "resident", "response",
+    "result", "retailer", "retreat", "reunion", "revenue", "review", "reward", "rhyme", "rhythm",
+    "rich", "rival", "river", "robin", "rocky", "romantic", "romp", "roster", "round", "royal",
+    "ruin", "ruler", "rumor", "sack", "safari", "salary", "salon", "salt", "satisfy", "satoshi",
+    "saver", "says", "scandal", "scared", "scatter", "scene", "scholar", "science", "scout",
+    "scramble", "screw", "script", "scroll", "seafood", "season", "secret", "security", "segment",
+    "senior", "shadow", "shaft", "shame", "shaped", "sharp", "shelter", "sheriff", "short",
+    "should", "shrimp", "sidewalk", "silent", "silver", "similar", "simple", "single", "sister",
+    "skin", "skunk", "slap", "slavery", "sled", "slice", "slim", "slow", "slush", "smart", "smear",
+    "smell", "smirk", "smith", "smoking", "smug", "snake", "snapshot", "sniff", "society",
+    "software", "soldier", "solution", "soul", "source", "space", "spark", "speak", "species",
+    "spelling", "spend", "spew", "spider", "spill", "spine", "spirit", "spit", "spray", "sprinkle",
+    "square", "squeeze", "stadium", "staff", "standard", "starting", "station", "stay", "steady",
+    "step", "stick", "stilt", "story", "strategy", "strike", "style", "subject", "submit", "sugar",
+    "suitable", "sunlight", "superior", "surface", "surprise", "survive", "sweater", "swimming",
+    "swing", "switch", "symbolic", "sympathy", "syndrome", "system", "tackle", "tactics",
+    "tadpole", "talent", "task", "taste", "taught", "taxi", "teacher", "teammate", "teaspoon",
+    "temple", "tenant", "tendency", "tension", "terminal", "testify", "texture", "thank", "that",
+    "theater", "theory", "therapy", "thorn", "threaten", "thumb", "thunder", "ticket", "tidy",
+    "timber", "timely", "ting", "tofu", "together", "tolerate", "total", "toxic", "tracks",
+    "traffic", "training", "transfer", "trash", "traveler", "treat", "trend", "trial", "tricycle",
+    "trip", "triumph", "trouble", "true", "trust", "twice", "twin", "type", "typical", "ugly",
+    "ultimate", "umbrella", "uncover", "undergo", "unfair", "unfold", "unhappy", "union",
+    "universe", "unkind", "unknown", "unusual", "unwrap", "upgrade", "upstairs", "username",
+    "usher", "usual", "valid", "valuable", "vampire", "vanish", "various", "vegan", "velvet",
+    "venture", "verdict", "verify", "very", "veteran", "vexed", "victim", "video", "view",
+    "vintage", "violence", "viral", "visitor", "visual", "vitamins", "vocal", "voice", "volume",
+    "voter", "voting", "walnut", "warmth", "warn", "watch", "wavy", "wealthy", "weapon", "webcam",
+    "welcome", "welfare", "western", "width", "wildlife", "window", "wine", "wireless", "wisdom",
+    "withdraw", "wits", "wolf", "woman", "work", "worthy", "wrap", "wrist", "writing", "wrote",
+    "year", "yelp", "yield", "yoga", "zero",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_seed;
+    use crate::test_utils::*;
+    use bitcoin::hashes::hex::ToHex;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn combine_hex(mnemonics: &[&str]) -> String {
+        let mnemonics: Vec<String> = mnemonics.iter().map(|m| m.to_string()).collect();
+        combine_master_secret(&mnemonics, b"TREZOR")
+            .unwrap()
+            .to_hex()
+    }
+
+    #[test]
+    fn test_wordlist() {
+        log!("test slip39 wordlist");
+
+        assert!(WORDLIST.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(WORDLIST[0], "academic");
+        assert_eq!(WORDLIST[1023], "zero");
+    }
+
+    #[test]
+    fn test_slip39_vectors() {
+        log!("test slip39 vectors");
+
+        // test vectors from the SLIP-39 reference implementation
+        assert_eq!(
+            combine_hex(&["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"]),
+            "bb54aac4b89dc868ba37d9cc21b2cece"
+        );
+        assert_eq!(
+            combine_hex(&[
+                "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+            ]),
+            "b43ceb7e57a0ea8766221624d01b0864"
+        );
+        assert_eq!(
+            combine_hex(&["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"]),
+            "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92"
+        );
+
+        // invalid checksum
+        let invalid = vec!["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney".to_string()];
+        assert!(combine_master_secret(&invalid, b"TREZOR").is_err());
+    }
+
+    #[test]
+    fn test_split_and_combine_mnemonic() {
+        log!("test split and combine mnemonic");
+
+        for words in [12, 24] {
+            let mnemonic = generate_seed(words).unwrap();
+            let shares = split_mnemonic(&mnemonic, 2, 3).unwrap();
+            assert_eq!(shares.len(), 3);
+
+            for pair in [[0, 1], [0, 2], [2, 1]] {
+                let subset = vec![shares[pair[0]].clone(), shares[pair[1]].clone()];
+                assert_eq!(combine_shares(&subset).unwrap(), mnemonic);
+            }
+
+            // not enough shares
+            assert!(combine_shares(&shares[..1]).is_err());
+        }
+
+        let mnemonic = generate_seed(12).unwrap();
+        let shares = split_mnemonic(&mnemonic, 1, 1).unwrap();
+        assert_eq!(combine_shares(&shares).unwrap(), mnemonic);
+    }
+
+    #[test]
+    fn test_split_mnemonic_invalid_args() {
+        log!("test split mnemonic invalid args");
+
+        let mnemonic = generate_seed(12).unwrap();
+        assert!(split_mnemonic(&mnemonic, 0, 3).is_err());
+        assert!(split_mnemonic(&mnemonic, 4, 3).is_err());
+        assert!(split_mnemonic(&mnemonic, 1, 3).is_err());
+        assert!(split_mnemonic(&mnemonic, 2, 17).is_err());
+    }
+
+    #[test]
+    fn test_shares_from_different_splits() {
+        log!("test shares from different splits");
+
+        let mnemonic = generate_seed(12).unwrap();
+        let a = split_mnemonic(&mnemonic, 2, 2).unwrap();
+        let b = split_mnemonic(&mnemonic, 2, 2).unwrap();
+        let mixed = vec![a[0].clone(), b[1].clone()];
+        assert!(combine_shares(&mixed).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::error::MutinyError;
+use crate::lnurlpay::parse_lnurl_or_address;
+use crate::nodemanager::{CustomTlv, MutinyInvoice};
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const SPLIT_PREFIX_KEY: &str = "split/";
+
+/// Where a share of a split payment is sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitDestination {
+    /// A spontaneous payment to a node
+    Keysend(PublicKey),
+    /// A lightning address, like `bob@example.com`, or a LNURL-pay
+    LnAddress(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitRecipient {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub destination: SplitDestination,
+    /// Share of the total amount, the shares of a split add up to 100
+    pub percent: u8,
+    /// Custom TLV records sent with keysend payments, such as podcast boost metadata
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_tlvs: Vec<CustomTlv>,
+}
+
+/// A list of recipients that a payment is split between,
+/// like the value block of a podcast or a nostr prism.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitTable {
+    pub id: String,
+    pub name: String,
+    pub recipients: Vec<SplitRecipient>,
+}
+
+impl SplitTable {
+    /// Checks the shares add up to 100 and every destination can be paid
+    pub(crate) fn validate(&self) -> Result<(), MutinyError> {
+        if self.id.is_empty() || self.recipients.is_empty() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        let total: u32 = self.recipients.iter().map(|r| r.percent as u32).sum();
+        if total != 100 {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        for recipient in self.recipients.iter() {
+            match &recipient.destination {
+                SplitDestination::Keysend(_) => {}
+                SplitDestination::LnAddress(address) => {
+                    parse_lnurl_or_address(address)?;
+                    // custom records can only be sent with keysend
+                    if !recipient.custom_tlvs.is_empty() {
+                        return Err(MutinyError::InvalidArgumentsError);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits the total between the recipients by their share. Sats lost to
+    /// rounding go one each to the recipients with the largest shares.
+    pub fn amounts(&self, total_sats: u64) -> Vec<u64> {
+        let mut amounts: Vec<u64> = self
+            .recipients
+            .iter()
+            .map(|r| total_sats * r.percent as u64 / 100)
+            .collect();
+
+        let mut remainder = total_sats - amounts.iter().sum::<u64>();
+        let mut order: Vec<usize> = (0..amounts.len()).collect();
+        order.sort_by_key(|i| std::cmp::Reverse(self.recipients[*i].percent));
+        for i in order.into_iter().cycle() {
+            if remainder == 0 {
+                break;
+            }
+            amounts[i] += 1;
+            remainder -= 1;
+        }
+
+        amounts
+    }
+}
+
+/// The result of paying one recipient of a split
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitPaymentResult {
+    pub recipient: SplitRecipient,
+    pub amount_sats: u64,
+    /// The payment, if it was made
+    pub payment: Option<MutinyInvoice>,
+    /// Why the recipient could not be paid
+    pub error: Option<String>,
+}
+
+impl SplitPaymentResult {
+    pub(crate) fn new(
+        recipient: SplitRecipient,
+        amount_sats: u64,
+        res: Result<MutinyInvoice, MutinyError>,
+    ) -> Self {
+        let (payment, error) = match res {
+            Ok(payment) => (Some(payment), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        Self {
+            recipient,
+            amount_sats,
+            payment,
+            error,
+        }
+    }
+}
+
+pub trait SplitStorage {
+    fn get_split(&self, id: &str) -> Result<Option<SplitTable>, MutinyError>;
+    fn list_splits(&self) -> Result<Vec<SplitTable>, MutinyError>;
+    fn save_split(&self, split: SplitTable) -> Result<(), MutinyError>;
+    fn delete_split(&self, id: &str) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> SplitStorage for S {
+    fn get_split(&self, id: &str) -> Result<Option<SplitTable>, MutinyError> {
+        self.get_data(format!("{SPLIT_PREFIX_KEY}{id}"))
+    }
+
+    fn list_splits(&self) -> Result<Vec<SplitTable>, MutinyError> {
+        let splits: HashMap<String, SplitTable> = self.scan(SPLIT_PREFIX_KEY, None)?;
+        let mut splits: Vec<SplitTable> = splits.into_values().collect();
+        splits.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(splits)
+    }
+
+    fn save_split(&self, split: SplitTable) -> Result<(), MutinyError> {
+        self.set_data(format!("{SPLIT_PREFIX_KEY}{}", split.id), split, None)
+    }
+
+    fn delete_split(&self, id: &str) -> Result<(), MutinyError> {
+        self.delete(&[format!("{SPLIT_PREFIX_KEY}{id}")])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn recipient(destination: SplitDestination, percent: u8) -> SplitRecipient {
+        SplitRecipient {
+            name: None,
+            destination,
+            percent,
+            custom_tlvs: vec![],
+        }
+    }
+
+    fn dummy_split() -> SplitTable {
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap());
+        SplitTable {
+            id: "podcast".to_string(),
+            name: "Podcast".to_string(),
+            recipients: vec![
+                recipient(SplitDestination::Keysend(pubkey), 50),
+                recipient(SplitDestination::LnAddress("host@example.com".into()), 30),
+                recipient(SplitDestination::LnAddress("guest@example.com".into()), 20),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_split_amounts() {
+        log!("test split amounts");
+
+        let split = dummy_split();
+        split.validate().unwrap();
+        assert_eq!(split.amounts(1_000), vec![500, 300, 200]);
+        // rounding leftovers go to the largest shares
+        assert_eq!(split.amounts(11), vec![6, 3, 2]);
+        assert_eq!(split.amounts(1), vec![1, 0, 0]);
+        assert_eq!(split.amounts(0), vec![0, 0, 0]);
+
+        let mut bad = split.clone();
+        bad.recipients[0].percent = 40;
+        assert!(bad.validate().is_err());
+
+        let mut bad = split.clone();
+        bad.recipients[1].destination = SplitDestination::LnAddress("not an address".into());
+        assert!(bad.validate().is_err());
+
+        let mut bad = split;
+        bad.recipients[1].custom_tlvs = vec![CustomTlv {
+            key: 7629169,
+            value: vec![1],
+        }];
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn test_split_storage() {
+        log!("test split storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.list_splits().unwrap().is_empty());
+
+        let split = dummy_split();
+        storage.save_split(split.clone()).unwrap();
+        assert_eq!(storage.get_split(&split.id).unwrap(), Some(split.clone()));
+        assert_eq!(storage.list_splits().unwrap(), vec![split.clone()]);
+
+        storage.delete_split(&split.id).unwrap();
+        assert_eq!(storage.get_split(&split.id).unwrap(), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Progress of loading the wallet, so a UI can show what is left while
+//! the network graph and each node's channel monitors are deserialized.
+//!
+//! Deserializing in parallel web workers is not supported. Workers need a wasm
+//! build with shared memory and atomics, which the wasm package isn't built with,
+//! and LDK's monitors and keys manager can't be moved between workers without it.
+//! Everything is deserialized on the thread that starts the wallet, so progress
+//! moves between nodes, while the thread yields, rather than per monitor.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Tracks startup as it happens, shared between the wallet and whoever is
+/// waiting on it. Set it with [crate::MutinyWalletConfig::with_startup_progress].
+#[derive(Debug, Default)]
+pub struct StartupProgress {
+    network_graph_loaded: AtomicBool,
+    nodes_total: AtomicUsize,
+    nodes_loaded: AtomicUsize,
+    monitors_loaded: AtomicUsize,
+}
+
+/// A snapshot of [StartupProgress]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartupProgressReport {
+    pub network_graph_loaded: bool,
+    /// How many nodes are being started, known once the node list is read
+    pub nodes_total: usize,
+    pub nodes_loaded: usize,
+    /// Channel monitors loaded across the nodes started so far
+    pub monitors_loaded: usize,
+}
+
+impl StartupProgressReport {
+    /// Whether the graph and every node have been loaded
+    pub fn is_done(&self) -> bool {
+        self.network_graph_loaded && self.nodes_loaded >= self.nodes_total
+    }
+}
+
+impl StartupProgress {
+    pub fn report(&self) -> StartupProgressReport {
+        StartupProgressReport {
+            network_graph_loaded: self.network_graph_loaded.load(Ordering::Relaxed),
+            nodes_total: self.nodes_total.load(Ordering::Relaxed),
+            nodes_loaded: self.nodes_loaded.load(Ordering::Relaxed),
+            monitors_loaded: self.monitors_loaded.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Clears the progress of a previous startup
+    pub fn reset(&self) {
+        self.network_graph_loaded.store(false, Ordering::Relaxed);
+        self.nodes_total.store(0, Ordering::Relaxed);
+        self.nodes_loaded.store(0, Ordering::Relaxed);
+        self.monitors_loaded.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn network_graph_loaded(&self) {
+        self.network_graph_loaded.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_nodes_total(&self, nodes: usize) {
+        self.nodes_total.store(nodes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn node_loaded(&self, monitors: usize) {
+        self.monitors_loaded.fetch_add(monitors, Ordering::Relaxed);
+        self.nodes_loaded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_startup_progress() {
+        log!("test startup progress");
+
+        let progress = StartupProgress::default();
+        assert!(!progress.report().is_done());
+
+        progress.network_graph_loaded();
+        progress.set_nodes_total(2);
+        progress.node_loaded(3);
+        let report = progress.report();
+        assert!(!report.is_done());
+        assert_eq!(report.nodes_loaded, 1);
+        assert_eq!(report.monitors_loaded, 3);
+
+        progress.node_loaded(1);
+        let report = progress.report();
+        assert!(report.is_done());
+        assert_eq!(report.monitors_loaded, 4);
+
+        progress.reset();
+        assert_eq!(progress.report(), StartupProgressReport::default());
+    }
+}
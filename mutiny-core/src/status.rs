@@ -0,0 +1,380 @@
+use crate::http_client::{HttpRequest, MutinyHttpClient};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+/// How often the service endpoints are checked
+pub const SERVICE_STATUS_CHECK_INTERVAL_SECS: u64 = 60 * 5;
+
+/// How many failed checks in a row before a service counts as down,
+/// so one dropped request doesn't turn features off
+pub const FAILURES_UNTIL_DOWN: u32 = 2;
+
+/// A backend the wallet depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    /// The websocket proxy browser nodes connect to peers through
+    Proxy,
+    Esplora,
+    /// Rapid gossip sync
+    Rgs,
+    Lsp,
+    /// Remote storage for backups
+    Storage,
+}
+
+impl Service {
+    /// The features that stop working when every endpoint of the service is down
+    pub fn features(&self) -> &'static [Feature] {
+        match self {
+            Service::Proxy => &[Feature::LightningPeers],
+            Service::Esplora => &[Feature::ChainSync, Feature::OnChainSend],
+            Service::Rgs => &[Feature::GossipSync],
+            Service::Lsp => &[Feature::JitChannels],
+            Service::Storage => &[Feature::RemoteBackup],
+        }
+    }
+}
+
+/// Something the wallet can do that depends on a backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    LightningPeers,
+    ChainSync,
+    OnChainSend,
+    GossipSync,
+    /// Receiving through the LSP, which opens channels just in time
+    JitChannels,
+    RemoteBackup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DegradationState {
+    /// Every service is up
+    Operational,
+    /// Some services are down, the features that need them are turned off
+    Degraded,
+    /// Every service is down, most likely we are offline
+    Offline,
+}
+
+/// The last known state of one service endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub service: Service,
+    pub url: String,
+    pub consecutive_failures: u32,
+    /// Epoch time in seconds of the last check, none if it was never checked
+    pub last_checked: Option<u64>,
+    pub last_healthy: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl ServiceStatus {
+    fn new(service: Service, url: String) -> Self {
+        Self {
+            service,
+            url,
+            consecutive_failures: 0,
+            last_checked: None,
+            last_healthy: None,
+            error: None,
+        }
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.consecutive_failures >= FAILURES_UNTIL_DOWN
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub state: DegradationState,
+    pub services: Vec<ServiceStatus>,
+    pub suppressed_features: Vec<Feature>,
+}
+
+/// An endpoint to check and the request to check it with
+#[derive(Debug, Clone)]
+pub(crate) struct ServiceEndpoint {
+    service: Service,
+    url: String,
+    request: HttpRequest,
+}
+
+impl ServiceEndpoint {
+    /// Checks the endpoint with a HEAD request
+    pub fn head(service: Service, url: &str) -> Self {
+        // the proxy only speaks websockets, but its host answers over https too
+        let check_url = url
+            .replacen("wss://", "https://", 1)
+            .replacen("ws://", "http://", 1);
+        let request = HttpRequest {
+            method: Method::HEAD,
+            ..HttpRequest::get(check_url)
+        };
+        Self {
+            service,
+            url: url.to_string(),
+            request,
+        }
+    }
+
+    /// Checks the endpoint with a GET request to `path`
+    pub fn get(service: Service, url: &str, path: &str) -> Self {
+        let base = url.strip_suffix('/').unwrap_or(url);
+        Self {
+            service,
+            url: url.to_string(),
+            request: HttpRequest::get(format!("{base}{path}")),
+        }
+    }
+}
+
+/// Keeps track of which services are up, and so which features should be turned off
+pub(crate) struct StatusMonitor {
+    endpoints: Vec<ServiceEndpoint>,
+    statuses: RwLock<Vec<ServiceStatus>>,
+    last_check: RwLock<Option<u64>>,
+}
+
+impl StatusMonitor {
+    pub fn new(endpoints: Vec<ServiceEndpoint>) -> Self {
+        let statuses = endpoints
+            .iter()
+            .map(|e| ServiceStatus::new(e.service, e.url.clone()))
+            .collect();
+        Self {
+            endpoints,
+            statuses: RwLock::new(statuses),
+            last_check: RwLock::new(None),
+        }
+    }
+
+    /// Records the result of checking an endpoint
+    pub fn record(&self, index: usize, result: Result<(), String>, now: u64) {
+        let Ok(mut statuses) = self.statuses.write() else {
+            return;
+        };
+        let Some(status) = statuses.get_mut(index) else {
+            return;
+        };
+
+        status.last_checked = Some(now);
+        match result {
+            Ok(()) => {
+                status.consecutive_failures = 0;
+                status.last_healthy = Some(now);
+                status.error = None;
+            }
+            Err(e) => {
+                status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                status.error = Some(e);
+            }
+        }
+    }
+
+    /// Checks every endpoint at once. A server error or no response counts as
+    /// a failure, any other response means the service is reachable.
+    pub async fn check_all(&self, client: &dyn MutinyHttpClient, now: u64) {
+        let futs = self
+            .endpoints
+            .iter()
+            .map(|e| client.send(e.request.clone()));
+        let results = futures::future::join_all(futs).await;
+
+        for (index, result) in results.into_iter().enumerate() {
+            let result = match result {
+                Ok(response) if response.status < 500 => Ok(()),
+                Ok(response) => Err(format!("Server error {}", response.status)),
+                Err(e) => Err(e.to_string()),
+            };
+            self.record(index, result, now);
+        }
+
+        if let Ok(mut last_check) = self.last_check.write() {
+            *last_check = Some(now);
+        }
+    }
+
+    /// Checks the endpoints if they haven't been checked recently
+    pub async fn check_if_necessary(&self, client: &dyn MutinyHttpClient, now: u64) {
+        let last_check = self.last_check.read().ok().and_then(|l| *l);
+        if last_check.map_or(true, |l| now >= l + SERVICE_STATUS_CHECK_INTERVAL_SECS) {
+            self.check_all(client, now).await;
+        }
+    }
+
+    fn down_services(statuses: &[ServiceStatus]) -> HashSet<Service> {
+        let services: HashSet<Service> = statuses.iter().map(|s| s.service).collect();
+        // a service is only down if none of its endpoints work
+        services
+            .into_iter()
+            .filter(|service| {
+                statuses
+                    .iter()
+                    .filter(|s| s.service == *service)
+                    .all(|s| s.is_down())
+            })
+            .collect()
+    }
+
+    pub fn report(&self) -> StatusReport {
+        let services = self.statuses.read().map(|s| s.clone()).unwrap_or_default();
+        let down = Self::down_services(&services);
+        let total = services.iter().map(|s| s.service).collect::<HashSet<_>>();
+
+        let state = if down.is_empty() {
+            DegradationState::Operational
+        } else if down.len() == total.len() {
+            DegradationState::Offline
+        } else {
+            DegradationState::Degraded
+        };
+
+        // keep the order of the endpoints so reports are stable
+        let mut suppressed_features: Vec<Feature> = vec![];
+        for status in services.iter().filter(|s| down.contains(&s.service)) {
+            for feature in status.service.features() {
+                if !suppressed_features.contains(feature) {
+                    suppressed_features.push(*feature);
+                }
+            }
+        }
+
+        StatusReport {
+            state,
+            services,
+            suppressed_features,
+        }
+    }
+
+    /// Returns true if the feature is turned off because its service is down
+    pub fn is_suppressed(&self, feature: Feature) -> bool {
+        let Ok(statuses) = self.statuses.read() else {
+            return false;
+        };
+        Self::down_services(&statuses)
+            .iter()
+            .any(|s| s.features().contains(&feature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MutinyError;
+    use crate::http_client::{HttpResponse, MockMutinyHttpClient};
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn create_monitor() -> StatusMonitor {
+        StatusMonitor::new(vec![
+            ServiceEndpoint::head(Service::Proxy, "wss://p.mutinywallet.com"),
+            ServiceEndpoint::get(
+                Service::Esplora,
+                "https://mempool.space/api",
+                "/blocks/tip/height",
+            ),
+            ServiceEndpoint::get(
+                Service::Esplora,
+                "https://blockstream.info/api",
+                "/blocks/tip/height",
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_service_endpoints() {
+        log!("test service endpoints");
+
+        let proxy = ServiceEndpoint::head(Service::Proxy, "wss://p.mutinywallet.com");
+        assert_eq!(proxy.request.method, Method::HEAD);
+        assert_eq!(proxy.request.url, "https://p.mutinywallet.com");
+        assert_eq!(proxy.url, "wss://p.mutinywallet.com");
+
+        let lsp = ServiceEndpoint::get(Service::Lsp, "https://lsp.example.com/", "/api/v1/info");
+        assert_eq!(lsp.request.url, "https://lsp.example.com/api/v1/info");
+    }
+
+    #[test]
+    fn test_degradation() {
+        log!("test degradation");
+
+        let monitor = create_monitor();
+        assert_eq!(monitor.report().state, DegradationState::Operational);
+
+        // one failure isn't enough
+        monitor.record(0, Err("timed out".to_string()), 1);
+        assert!(!monitor.is_suppressed(Feature::LightningPeers));
+
+        monitor.record(0, Err("timed out".to_string()), 2);
+        assert!(monitor.is_suppressed(Feature::LightningPeers));
+        let report = monitor.report();
+        assert_eq!(report.state, DegradationState::Degraded);
+        assert_eq!(report.suppressed_features, vec![Feature::LightningPeers]);
+
+        // one esplora being down isn't, the other still works
+        monitor.record(1, Err("timed out".to_string()), 2);
+        monitor.record(1, Err("timed out".to_string()), 3);
+        assert!(!monitor.is_suppressed(Feature::ChainSync));
+
+        monitor.record(2, Err("timed out".to_string()), 3);
+        monitor.record(2, Err("timed out".to_string()), 4);
+        assert!(monitor.is_suppressed(Feature::ChainSync));
+        assert_eq!(monitor.report().state, DegradationState::Offline);
+
+        // a successful check brings it back
+        monitor.record(0, Ok(()), 5);
+        assert!(!monitor.is_suppressed(Feature::LightningPeers));
+        let proxy = &monitor.report().services[0];
+        assert_eq!(proxy.last_healthy, Some(5));
+        assert_eq!(proxy.error, None);
+    }
+
+    #[test]
+    async fn test_check_all() {
+        log!("test check all");
+
+        let mut client = MockMutinyHttpClient::new();
+        client.expect_send().times(6).returning(|req| {
+            if req.url.contains("mempool.space") {
+                Err(MutinyError::ConnectionFailed)
+            } else if req.url.contains("blockstream") {
+                Ok(HttpResponse {
+                    status: 503,
+                    ..Default::default()
+                })
+            } else {
+                // a 404 still means the server is up
+                Ok(HttpResponse {
+                    status: 404,
+                    ..Default::default()
+                })
+            }
+        });
+
+        let monitor = create_monitor();
+        monitor.check_if_necessary(&client, 100).await;
+        // checked too recently to check again
+        monitor.check_if_necessary(&client, 101).await;
+        monitor.check_all(&client, 102).await;
+
+        let report = monitor.report();
+        assert_eq!(report.state, DegradationState::Degraded);
+        assert_eq!(
+            report.suppressed_features,
+            vec![Feature::ChainSync, Feature::OnChainSend]
+        );
+        assert_eq!(
+            report.services[2].error,
+            Some("Server error 503".to_string())
+        );
+    }
+}
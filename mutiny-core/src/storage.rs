@@ -1,3 +1,4 @@
+use crate::encoding::{binary_storage_supported, decode_value, encode_binary, encode_compressed};
 use crate::encrypt::{decrypt_with_password, encrypt, encryption_key_from_pass, Cipher};
 use crate::error::{MutinyError, MutinyStorageError};
 use crate::ldkstorage::CHANNEL_MANAGER_KEY;
@@ -17,6 +18,7 @@ use uuid::Uuid;
 pub const KEYCHAIN_STORE_KEY: &str = "bdk_keychain";
 pub(crate) const MNEMONIC_KEY: &str = "mnemonic";
 pub(crate) const NEED_FULL_SYNC_KEY: &str = "needs_full_sync";
+pub(crate) const WALLET_BIRTHDAY_KEY: &str = "wallet_birthday";
 pub const NODES_KEY: &str = "nodes";
 const FEE_ESTIMATES_KEY: &str = "fee_estimates";
 const FIRST_SYNC_KEY: &str = "first_sync";
@@ -71,6 +73,9 @@ pub fn decrypt_value(
 pub struct VersionedValue {
     pub version: u32,
     pub value: Value,
+    /// Hex checksum of the encoded value, for values that are checked on read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -136,6 +141,45 @@ pub trait MutinyStorage: Clone + Sized + 'static {
         self.set(key, json)
     }
 
+    /// Set a value in the storage in the compact binary format, for large
+    /// values like payments. It is read back with [MutinyStorage::get_data].
+    ///
+    /// This is saved as JSON until every device supports the binary format.
+    fn set_data_binary<T>(
+        &self,
+        key: impl AsRef<str>,
+        value: T,
+        version: Option<u32>,
+    ) -> Result<(), MutinyError>
+    where
+        T: Serialize,
+    {
+        if !binary_storage_supported(self)? {
+            return self.set_data(key, value, version);
+        }
+        self.set_data(key, encode_binary(&value)?, version)
+    }
+
+    /// Set a value in the storage in the binary format compressed, for large
+    /// values that compress well like the network graph. It is read back with
+    /// [MutinyStorage::get_data].
+    ///
+    /// This is saved as JSON until every device supports the binary format.
+    fn set_data_compressed<T>(
+        &self,
+        key: impl AsRef<str>,
+        value: T,
+        version: Option<u32>,
+    ) -> Result<(), MutinyError>
+    where
+        T: Serialize,
+    {
+        if !binary_storage_supported(self)? {
+            return self.set_data(key, value, version);
+        }
+        self.set_data(key, encode_compressed(&value)?, version)
+    }
+
     /// Get a value from the storage, use get_data if you want the value to be decrypted
     fn get<T>(&self, key: impl AsRef<str>) -> Result<Option<T>, MutinyError>
     where
@@ -150,7 +194,7 @@ pub trait MutinyStorage: Clone + Sized + 'static {
             None => Ok(None),
             Some(value) => {
                 let json: Value = decrypt_value(&key, value, self.password())?;
-                let data: T = serde_json::from_value(json)?;
+                let data: T = decode_value(json)?;
                 Ok(Some(data))
             }
         }
@@ -165,6 +209,12 @@ pub trait MutinyStorage: Clone + Sized + 'static {
     /// Stop the storage, this will be called when the application is shutting down
     fn stop(&self);
 
+    /// Waits until every write made so far is durably saved. Returns an error
+    /// if any of them failed, storage that saves synchronously returns right away.
+    async fn flush(&self) -> Result<(), MutinyError> {
+        Ok(())
+    }
+
     /// Check if the storage is connected
     fn connected(&self) -> Result<bool, MutinyError>;
 
@@ -202,6 +252,17 @@ pub trait MutinyStorage: Clone + Sized + 'static {
         self.get_data(MNEMONIC_KEY)
     }
 
+    /// Get the block height the wallet was created at, nothing
+    /// before it needs to be scanned when restoring the seed
+    fn get_wallet_birthday(&self) -> Result<Option<u32>, MutinyError> {
+        self.get_data(WALLET_BIRTHDAY_KEY)
+    }
+
+    /// Set the block height the wallet was created at
+    fn set_wallet_birthday(&self, height: u32) -> Result<(), MutinyError> {
+        self.set_data(WALLET_BIRTHDAY_KEY, height, None)
+    }
+
     fn change_password(
         &mut self,
         new: Option<String>,
@@ -627,6 +688,18 @@ mod tests {
         assert_eq!(Some(mnemonic), stored_mnemonic);
     }
 
+    #[test]
+    fn set_and_get_wallet_birthday() {
+        let test_name = "set_and_get_wallet_birthday";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        assert_eq!(storage.get_wallet_birthday().unwrap(), None);
+
+        storage.set_wallet_birthday(800_000).unwrap();
+        assert_eq!(storage.get_wallet_birthday().unwrap(), Some(800_000));
+    }
+
     #[test]
     async fn test_device_lock() {
         let test_name = "test_device_lock";
@@ -0,0 +1,187 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::{Address, OutPoint};
+use serde::{Deserialize, Serialize};
+
+pub const SWAP_OUT_POLICY_KEY: &str = "swap_out_policy";
+pub const SWAP_OUTS_KEY: &str = "swap_outs";
+
+/// Closing a channel with more than this many times the excess always asks
+/// the user first, even if the policy doesn't require confirmation
+const MAX_UNCONFIRMED_OVERSHOOT: u64 = 2;
+
+/// Moves lightning funds above a ceiling to cold storage.
+///
+/// We don't have a submarine swap provider, so funds are moved by cooperatively
+/// closing a channel to the cold storage address. This moves the whole channel
+/// balance, so the channel closest to the excess amount is picked. When even that
+/// channel holds far more than the excess the user is asked before it is closed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapOutPolicy {
+    /// The lightning balance to stay under, in sats
+    pub ceiling_sats: u64,
+    /// The cold storage address funds are sent to
+    pub address: Address,
+    /// Wait for the user to confirm each swap out instead of doing it right away
+    pub require_confirmation: bool,
+}
+
+impl SwapOutPolicy {
+    /// Whether the user has to confirm closing a channel with `amount_sats`
+    /// to move `excess_sats`, because of the policy or because it moves far more
+    pub(crate) fn needs_confirmation(&self, amount_sats: u64, excess_sats: u64) -> bool {
+        self.require_confirmation
+            || amount_sats > excess_sats.saturating_mul(MAX_UNCONFIRMED_OVERSHOOT)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapOutStatus {
+    /// Waiting for the user to confirm or decline
+    AwaitingConfirmation,
+    /// The channel is closing to the cold storage address
+    Completed,
+    Declined,
+    Failed {
+        reason: String,
+    },
+}
+
+/// A record of the policy moving funds, or asking to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapOut {
+    /// The funding outpoint of the channel that is closed
+    pub channel: OutPoint,
+    /// Our balance in the channel, what is moved on-chain before fees
+    pub amount_sats: u64,
+    /// How far our lightning balance was above the ceiling
+    pub excess_sats: u64,
+    pub address: Address,
+    pub created_at: u64,
+    pub status: SwapOutStatus,
+}
+
+/// Picks the channel to close to move at least `excess_sats`.
+/// This is the smallest channel that covers the excess, or the largest
+/// channel if none do, the rest is moved the next time the policy runs.
+pub(crate) fn pick_channel(
+    channels: &[(OutPoint, u64)],
+    excess_sats: u64,
+) -> Option<(OutPoint, u64)> {
+    let covering = channels
+        .iter()
+        .filter(|(_, balance)| *balance >= excess_sats)
+        .min_by_key(|(_, balance)| *balance);
+
+    covering
+        .or_else(|| channels.iter().max_by_key(|(_, balance)| *balance))
+        .filter(|(_, balance)| *balance > 0)
+        .copied()
+}
+
+pub trait SwapOutStorage {
+    fn get_swap_out_policy(&self) -> Result<Option<SwapOutPolicy>, MutinyError>;
+    fn set_swap_out_policy(&self, policy: Option<SwapOutPolicy>) -> Result<(), MutinyError>;
+    fn get_swap_outs(&self) -> Result<Vec<SwapOut>, MutinyError>;
+    fn persist_swap_outs(&self, swap_outs: Vec<SwapOut>) -> Result<(), MutinyError>;
+}
+
+impl<S: MutinyStorage> SwapOutStorage for S {
+    fn get_swap_out_policy(&self) -> Result<Option<SwapOutPolicy>, MutinyError> {
+        self.get_data(SWAP_OUT_POLICY_KEY)
+    }
+
+    fn set_swap_out_policy(&self, policy: Option<SwapOutPolicy>) -> Result<(), MutinyError> {
+        match policy {
+            Some(policy) => self.set_data(SWAP_OUT_POLICY_KEY, policy, None),
+            None => self.delete(&[SWAP_OUT_POLICY_KEY]),
+        }
+    }
+
+    fn get_swap_outs(&self) -> Result<Vec<SwapOut>, MutinyError> {
+        let swap_outs: Option<Vec<SwapOut>> = self.get_data(SWAP_OUTS_KEY)?;
+        Ok(swap_outs.unwrap_or_default())
+    }
+
+    fn persist_swap_outs(&self, swap_outs: Vec<SwapOut>) -> Result<(), MutinyError> {
+        self.set_data(SWAP_OUTS_KEY, swap_outs, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::all_zeros(), vout)
+    }
+
+    #[test]
+    fn test_pick_channel() {
+        log!("test pick channel");
+
+        let small = (outpoint(0), 50_000);
+        let large = (outpoint(1), 200_000);
+        let medium = (outpoint(2), 120_000);
+        let channels = vec![small, large, medium];
+
+        // smallest channel that covers the excess
+        assert_eq!(pick_channel(&channels, 100_000), Some(medium));
+        assert_eq!(pick_channel(&channels, 10_000), Some(small));
+        // nothing covers it, move as much as we can
+        assert_eq!(pick_channel(&channels, 500_000), Some(large));
+
+        assert_eq!(pick_channel(&[], 10_000), None);
+        assert_eq!(pick_channel(&[(outpoint(0), 0)], 10_000), None);
+    }
+
+    #[test]
+    fn test_swap_out_storage() {
+        log!("test swap out storage");
+
+        let storage = MemoryStorage::default();
+        assert!(storage.get_swap_out_policy().unwrap().is_none());
+        assert!(storage.get_swap_outs().unwrap().is_empty());
+
+        let address = Address::from_str("tb1qhgemzcaj5ehn7yvqq7wh0w2pkxg3ymq5yc0k8t").unwrap();
+        let policy = SwapOutPolicy {
+            ceiling_sats: 1_000_000,
+            address: address.clone(),
+            require_confirmation: true,
+        };
+        storage.set_swap_out_policy(Some(policy.clone())).unwrap();
+        assert_eq!(storage.get_swap_out_policy().unwrap(), Some(policy.clone()));
+
+        // closing a channel much bigger than the excess always needs confirmation
+        let policy = SwapOutPolicy {
+            require_confirmation: false,
+            ..policy
+        };
+        assert!(!policy.needs_confirmation(150_000, 100_000));
+        assert!(!policy.needs_confirmation(200_000, 100_000));
+        assert!(policy.needs_confirmation(200_001, 100_000));
+
+        let swap_outs = vec![SwapOut {
+            channel: outpoint(0),
+            amount_sats: 200_000,
+            excess_sats: 150_000,
+            address,
+            created_at: 0,
+            status: SwapOutStatus::AwaitingConfirmation,
+        }];
+        storage.persist_swap_outs(swap_outs.clone()).unwrap();
+        assert_eq!(storage.get_swap_outs().unwrap(), swap_outs);
+
+        storage.set_swap_out_policy(None).unwrap();
+        assert!(storage.get_swap_out_policy().unwrap().is_none());
+    }
+}
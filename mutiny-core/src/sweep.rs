@@ -0,0 +1,217 @@
+use crate::error::MutinyError;
+use crate::storage::MutinyStorage;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::{ChildNumber, ExtendedPubKey};
+use bitcoin::{Address, Network, OutPoint, Script};
+use serde::{Deserialize, Serialize};
+
+pub const SWEEP_DESTINATION_KEY: &str = "sweep_destination";
+pub const CHANNEL_SWEEP_DESTINATIONS_KEY: &str = "channel_sweep_destinations";
+
+/// Where the funds from closed channels are sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SweepDestination {
+    /// A new address in the on-chain wallet
+    #[default]
+    Internal,
+    /// The same external address every time
+    Address(Address),
+    /// A fresh native segwit address derived from an external xpub every time,
+    /// using the xpub's receive chain (`0/*`).
+    Xpub {
+        xpub: ExtendedPubKey,
+        next_index: u32,
+    },
+}
+
+impl SweepDestination {
+    /// Checks that the destination is for the given network
+    pub(crate) fn validate(&self, network: Network) -> Result<(), MutinyError> {
+        match self {
+            SweepDestination::Internal => Ok(()),
+            SweepDestination::Address(address) => {
+                if !address.is_valid_for_network(network) {
+                    return Err(MutinyError::IncorrectNetwork(address.network));
+                }
+                Ok(())
+            }
+            SweepDestination::Xpub { xpub, .. } => {
+                // test xpubs are used for every network besides mainnet
+                if (xpub.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+                    return Err(MutinyError::IncorrectNetwork(xpub.network));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the script to sweep to, `None` for the internal wallet.
+    /// For xpubs this advances to the next address.
+    pub(crate) fn next_script(&mut self) -> Result<Option<Script>, MutinyError> {
+        match self {
+            SweepDestination::Internal => Ok(None),
+            SweepDestination::Address(address) => Ok(Some(address.script_pubkey())),
+            SweepDestination::Xpub { xpub, next_index } => {
+                let path = [
+                    ChildNumber::from_normal_idx(0)?,
+                    ChildNumber::from_normal_idx(*next_index)?,
+                ];
+                let child = xpub.derive_pub(&Secp256k1::verification_only(), &path)?;
+                let address = Address::p2wpkh(&child.to_pub(), xpub.network)
+                    .map_err(|_| MutinyError::InvalidArgumentsError)?;
+                *next_index += 1;
+                Ok(Some(address.script_pubkey()))
+            }
+        }
+    }
+}
+
+pub trait SweepDestinationStorage {
+    fn get_sweep_destination(&self) -> Result<SweepDestination, MutinyError>;
+    fn set_sweep_destination(&self, destination: SweepDestination) -> Result<(), MutinyError>;
+    /// The addresses that specific channels should be swept to,
+    /// keyed by funding outpoint. These override the global destination.
+    fn get_channel_sweep_destinations(&self) -> Result<Vec<(OutPoint, Address)>, MutinyError>;
+    fn set_channel_sweep_destination(
+        &self,
+        funding_txo: OutPoint,
+        address: Option<Address>,
+    ) -> Result<(), MutinyError>;
+
+    /// Gets the script to sweep to from the global destination,
+    /// saving the next index for xpubs. `None` means the internal wallet.
+    fn next_sweep_script(&self) -> Result<Option<Script>, MutinyError> {
+        let mut destination = self.get_sweep_destination()?;
+        let script = destination.next_script()?;
+        if matches!(destination, SweepDestination::Xpub { .. }) {
+            self.set_sweep_destination(destination)?;
+        }
+
+        Ok(script)
+    }
+
+    /// Gets the script the next sweep would go to without advancing
+    /// xpub destinations, see [SweepDestinationStorage::sweep_script_used].
+    fn peek_sweep_script(&self) -> Result<Option<Script>, MutinyError> {
+        self.get_sweep_destination()?.next_script()
+    }
+
+    /// Moves an xpub destination past the given script once a sweep to it
+    /// has been broadcast. Does nothing if the destination has changed since.
+    fn sweep_script_used(&self, script: &Script) -> Result<(), MutinyError> {
+        let mut destination = self.get_sweep_destination()?;
+        if matches!(destination, SweepDestination::Xpub { .. })
+            && destination.next_script()?.as_ref() == Some(script)
+        {
+            self.set_sweep_destination(destination)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: MutinyStorage> SweepDestinationStorage for S {
+    fn get_sweep_destination(&self) -> Result<SweepDestination, MutinyError> {
+        let destination: Option<SweepDestination> = self.get_data(SWEEP_DESTINATION_KEY)?;
+        Ok(destination.unwrap_or_default())
+    }
+
+    fn set_sweep_destination(&self, destination: SweepDestination) -> Result<(), MutinyError> {
+        self.set_data(SWEEP_DESTINATION_KEY, destination, None)
+    }
+
+    fn get_channel_sweep_destinations(&self) -> Result<Vec<(OutPoint, Address)>, MutinyError> {
+        let destinations: Option<Vec<(OutPoint, Address)>> =
+            self.get_data(CHANNEL_SWEEP_DESTINATIONS_KEY)?;
+        Ok(destinations.unwrap_or_default())
+    }
+
+    fn set_channel_sweep_destination(
+        &self,
+        funding_txo: OutPoint,
+        address: Option<Address>,
+    ) -> Result<(), MutinyError> {
+        let mut destinations = self.get_channel_sweep_destinations()?;
+        destinations.retain(|(o, _)| *o != funding_txo);
+        if let Some(address) = address {
+            destinations.push((funding_txo, address));
+        }
+
+        self.set_data(CHANNEL_SWEEP_DESTINATIONS_KEY, destinations, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::ExtendedPrivKey;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_xpub_sweep_destination() {
+        log!("test xpub sweep destination");
+
+        let storage = MemoryStorage::default();
+        assert_eq!(storage.next_sweep_script().unwrap(), None);
+
+        let xpriv = ExtendedPrivKey::new_master(Network::Testnet, &[0; 32]).unwrap();
+        let xpub = ExtendedPubKey::from_priv(&Secp256k1::new(), &xpriv);
+        let destination = SweepDestination::Xpub {
+            xpub,
+            next_index: 0,
+        };
+        assert!(destination.validate(Network::Testnet).is_ok());
+        assert!(destination.validate(Network::Bitcoin).is_err());
+        storage.set_sweep_destination(destination).unwrap();
+
+        // every sweep gets a fresh address
+        let first = storage.next_sweep_script().unwrap().unwrap();
+        let second = storage.next_sweep_script().unwrap().unwrap();
+        assert_ne!(first, second);
+        assert!(first.is_v0_p2wpkh());
+        assert_eq!(
+            storage.get_sweep_destination().unwrap(),
+            SweepDestination::Xpub {
+                xpub,
+                next_index: 2
+            }
+        );
+
+        // peeking reuses the address until a sweep to it is broadcast
+        let peeked = storage.peek_sweep_script().unwrap().unwrap();
+        assert_eq!(storage.peek_sweep_script().unwrap().unwrap(), peeked);
+        storage.sweep_script_used(&first).unwrap();
+        assert_eq!(storage.peek_sweep_script().unwrap().unwrap(), peeked);
+        storage.sweep_script_used(&peeked).unwrap();
+        assert_ne!(storage.peek_sweep_script().unwrap().unwrap(), peeked);
+    }
+
+    #[test]
+    fn test_channel_sweep_destinations() {
+        log!("test channel sweep destinations");
+
+        let storage = MemoryStorage::default();
+        let funding_txo = OutPoint::new(Txid::all_zeros(), 0);
+        let address = Address::from_str("tb1qhgemzcaj5ehn7yvqq7wh0w2pkxg3ymq5yc0k8t").unwrap();
+
+        storage
+            .set_channel_sweep_destination(funding_txo, Some(address.clone()))
+            .unwrap();
+        assert_eq!(
+            storage.get_channel_sweep_destinations().unwrap(),
+            vec![(funding_txo, address)]
+        );
+
+        storage
+            .set_channel_sweep_destination(funding_txo, None)
+            .unwrap();
+        assert!(storage.get_channel_sweep_destinations().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::error::MutinyError;
+use crate::http_client::{HttpRequest, MutinyHttpClient};
+use crate::storage::MutinyStorage;
+use bitcoin::Network;
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+pub const TELEMETRY_STATS_KEY: &str = "telemetry_stats";
+pub const TELEMETRY_OPT_IN_KEY: &str = "telemetry_opt_in";
+
+/// How many samples of each duration we keep around to calculate medians
+const MAX_SAMPLES: usize = 100;
+/// Durations are rounded to this many milliseconds so they can't be used
+/// to fingerprint a user
+const DURATION_GRANULARITY_MS: u64 = 100;
+/// How often we upload a summary, if the user has opted in
+pub(crate) const TELEMETRY_UPLOAD_INTERVAL_SECS: u64 = 60 * 60 * 24;
+
+/// Held while the stats are read and written back, so concurrent payments
+/// and syncs don't overwrite each other's updates
+static TELEMETRY_STATS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Metrics that are aggregated locally on the device.
+/// These never leave the device unless the user opts in to telemetry,
+/// and even then only the [TelemetrySummary] is uploaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetryStats {
+    pub payments_attempted: u64,
+    pub payments_succeeded: u64,
+    /// Most recent successful payment times, in milliseconds
+    payment_times_ms: Vec<u64>,
+    /// Most recent sync times, in milliseconds
+    sync_times_ms: Vec<u64>,
+    /// Epoch time in seconds of when we last uploaded a summary
+    pub last_upload: u64,
+}
+
+impl TelemetryStats {
+    pub(crate) fn record_payment(&mut self, success: bool, duration: Duration) {
+        self.payments_attempted += 1;
+        if success {
+            self.payments_succeeded += 1;
+            push_sample(&mut self.payment_times_ms, duration);
+        }
+    }
+
+    pub(crate) fn record_sync(&mut self, duration: Duration) {
+        push_sample(&mut self.sync_times_ms, duration);
+    }
+
+    /// Creates an anonymized summary of the stats, this is what gets uploaded.
+    pub fn summary(&self, network: Network) -> TelemetrySummary {
+        let payment_success_rate = if self.payments_attempted == 0 {
+            None
+        } else {
+            // whole percentages only
+            Some(((self.payments_succeeded * 100) / self.payments_attempted) as u8)
+        };
+
+        TelemetrySummary {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            network,
+            payments_attempted: self.payments_attempted,
+            payment_success_rate,
+            median_payment_time_ms: median(&self.payment_times_ms),
+            median_sync_time_ms: median(&self.sync_times_ms),
+        }
+    }
+
+    /// Clears the aggregated stats after they have been uploaded.
+    pub(crate) fn reset(&mut self, last_upload: u64) {
+        *self = TelemetryStats {
+            last_upload,
+            ..Default::default()
+        };
+    }
+
+    /// Takes the stats to upload and clears them, so anything recorded
+    /// while the upload is in flight is kept for the next one.
+    pub(crate) fn take(&mut self, last_upload: u64) -> TelemetryStats {
+        let taken = self.clone();
+        self.reset(last_upload);
+        taken
+    }
+
+    /// Puts back stats that were taken for an upload that failed, ahead of
+    /// anything recorded since they were taken.
+    pub(crate) fn restore(&mut self, taken: TelemetryStats) {
+        self.payments_attempted += taken.payments_attempted;
+        self.payments_succeeded += taken.payments_succeeded;
+        self.payment_times_ms = merge_samples(taken.payment_times_ms, &self.payment_times_ms);
+        self.sync_times_ms = merge_samples(taken.sync_times_ms, &self.sync_times_ms);
+        self.last_upload = taken.last_upload;
+    }
+}
+
+/// An anonymized summary of the locally aggregated metrics.
+/// This does not contain any identifiers, amounts, or node information.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TelemetrySummary {
+    pub version: String,
+    pub network: Network,
+    pub payments_attempted: u64,
+    /// Percentage of payments that succeeded
+    pub payment_success_rate: Option<u8>,
+    pub median_payment_time_ms: Option<u64>,
+    pub median_sync_time_ms: Option<u64>,
+}
+
+fn push_sample(samples: &mut Vec<u64>, duration: Duration) {
+    let ms = duration.as_millis() as u64;
+    let rounded = (ms / DURATION_GRANULARITY_MS) * DURATION_GRANULARITY_MS;
+    samples.push(rounded);
+    if samples.len() > MAX_SAMPLES {
+        samples.remove(0);
+    }
+}
+
+/// Appends the newer samples to the older ones, keeping the most recent
+fn merge_samples(mut older: Vec<u64>, newer: &[u64]) -> Vec<u64> {
+    older.extend_from_slice(newer);
+    let excess = older.len().saturating_sub(MAX_SAMPLES);
+    older.drain(..excess);
+    older
+}
+
+fn median(samples: &[u64]) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    Some(sorted[sorted.len() / 2])
+}
+
+pub trait TelemetryStorage {
+    /// Returns if the user has opted in to uploading telemetry, defaults to false.
+    fn get_telemetry_opt_in(&self) -> Result<bool, MutinyError>;
+    fn set_telemetry_opt_in(&self, opt_in: bool) -> Result<(), MutinyError>;
+    fn get_telemetry_stats(&self) -> Result<TelemetryStats, MutinyError>;
+    fn persist_telemetry_stats(&self, stats: TelemetryStats) -> Result<(), MutinyError>;
+    /// Records the result of a payment attempt
+    fn record_payment_telemetry(
+        &self,
+        success: bool,
+        duration: Duration,
+    ) -> Result<(), MutinyError>;
+    /// Records how long a sync took
+    fn record_sync_telemetry(&self, duration: Duration) -> Result<(), MutinyError>;
+    /// Takes the stats to upload and clears them
+    fn take_telemetry_stats(&self, last_upload: u64) -> Result<TelemetryStats, MutinyError>;
+    /// Puts back stats taken with [TelemetryStorage::take_telemetry_stats]
+    /// when their upload failed
+    fn restore_telemetry_stats(&self, taken: TelemetryStats) -> Result<(), MutinyError>;
+    /// Reads the stats, updates them and writes them back without other
+    /// updates in between
+    fn update_telemetry_stats<T>(
+        &self,
+        update: impl FnOnce(&mut TelemetryStats) -> T,
+    ) -> Result<T, MutinyError>;
+}
+
+impl<S: MutinyStorage> TelemetryStorage for S {
+    fn get_telemetry_opt_in(&self) -> Result<bool, MutinyError> {
+        let opt_in: Option<bool> = self.get_data(TELEMETRY_OPT_IN_KEY)?;
+        Ok(opt_in.unwrap_or(false))
+    }
+
+    fn set_telemetry_opt_in(&self, opt_in: bool) -> Result<(), MutinyError> {
+        self.set_data(TELEMETRY_OPT_IN_KEY, opt_in, None)
+    }
+
+    fn get_telemetry_stats(&self) -> Result<TelemetryStats, MutinyError> {
+        let stats: Option<TelemetryStats> = self.get_data(TELEMETRY_STATS_KEY)?;
+        Ok(stats.unwrap_or_default())
+    }
+
+    fn persist_telemetry_stats(&self, stats: TelemetryStats) -> Result<(), MutinyError> {
+        self.set_data(TELEMETRY_STATS_KEY, stats, None)
+    }
+
+    fn record_payment_telemetry(
+        &self,
+        success: bool,
+        duration: Duration,
+    ) -> Result<(), MutinyError> {
+        self.update_telemetry_stats(|stats| stats.record_payment(success, duration))
+    }
+
+    fn record_sync_telemetry(&self, duration: Duration) -> Result<(), MutinyError> {
+        self.update_telemetry_stats(|stats| stats.record_sync(duration))
+    }
+
+    fn take_telemetry_stats(&self, last_upload: u64) -> Result<TelemetryStats, MutinyError> {
+        self.update_telemetry_stats(|stats| stats.take(last_upload))
+    }
+
+    fn restore_telemetry_stats(&self, taken: TelemetryStats) -> Result<(), MutinyError> {
+        self.update_telemetry_stats(|stats| stats.restore(taken))
+    }
+
+    fn update_telemetry_stats<T>(
+        &self,
+        update: impl FnOnce(&mut TelemetryStats) -> T,
+    ) -> Result<T, MutinyError> {
+        // the lock only guards the stats in storage, a panic can't leave it inconsistent
+        let _lock = TELEMETRY_STATS_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut stats = self.get_telemetry_stats()?;
+        let result = update(&mut stats);
+        self.persist_telemetry_stats(stats)?;
+        Ok(result)
+    }
+}
+
+/// Uploads a summary to the telemetry server
+pub(crate) async fn upload_telemetry_summary(
+    client: &dyn MutinyHttpClient,
+    url: &str,
+    summary: &TelemetrySummary,
+) -> Result<(), MutinyError> {
+    let response = client.send(HttpRequest::post_json(url, summary)?).await?;
+    if !response.is_success() {
+        return Err(MutinyError::Other(anyhow::anyhow!(
+            "Telemetry upload failed with status {}",
+            response.status
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_telemetry_summary() {
+        log!("test telemetry summary");
+
+        let mut stats = TelemetryStats::default();
+        assert_eq!(stats.summary(Network::Regtest).payment_success_rate, None);
+
+        stats.record_payment(true, Duration::from_millis(1_234));
+        stats.record_payment(true, Duration::from_millis(2_345));
+        stats.record_payment(true, Duration::from_millis(5_000));
+        stats.record_payment(false, Duration::from_millis(30_000));
+        stats.record_sync(Duration::from_millis(999));
+
+        let summary = stats.summary(Network::Regtest);
+        assert_eq!(summary.payments_attempted, 4);
+        assert_eq!(summary.payment_success_rate, Some(75));
+        // rounded down to the granularity
+        assert_eq!(summary.median_payment_time_ms, Some(2_300));
+        assert_eq!(summary.median_sync_time_ms, Some(900));
+
+        stats.reset(100);
+        assert_eq!(stats.last_upload, 100);
+        assert_eq!(stats.payments_attempted, 0);
+    }
+
+    #[test]
+    fn test_telemetry_samples_bounded() {
+        log!("test telemetry samples bounded");
+
+        let mut stats = TelemetryStats::default();
+        for i in 0..(MAX_SAMPLES as u64 * 2) {
+            stats.record_sync(Duration::from_secs(i));
+        }
+        assert_eq!(stats.sync_times_ms.len(), MAX_SAMPLES);
+        // only the most recent samples are kept
+        assert_eq!(stats.sync_times_ms[0], MAX_SAMPLES as u64 * 1_000);
+    }
+
+    #[test]
+    fn test_telemetry_storage() {
+        log!("test telemetry storage");
+
+        let storage = MemoryStorage::default();
+        assert!(!storage.get_telemetry_opt_in().unwrap());
+
+        storage.set_telemetry_opt_in(true).unwrap();
+        assert!(storage.get_telemetry_opt_in().unwrap());
+
+        storage
+            .record_payment_telemetry(true, Duration::from_secs(1))
+            .unwrap();
+        storage
+            .record_sync_telemetry(Duration::from_secs(2))
+            .unwrap();
+
+        let summary = storage
+            .get_telemetry_stats()
+            .unwrap()
+            .summary(Network::Regtest);
+        assert_eq!(summary.payments_attempted, 1);
+        assert_eq!(summary.payment_success_rate, Some(100));
+        assert_eq!(summary.median_payment_time_ms, Some(1_000));
+        assert_eq!(summary.median_sync_time_ms, Some(2_000));
+
+        let taken = storage.take_telemetry_stats(100).unwrap();
+        assert_eq!(taken.payments_attempted, 1);
+        let stats = storage.get_telemetry_stats().unwrap();
+        assert_eq!(stats.last_upload, 100);
+        assert_eq!(stats.payments_attempted, 0);
+
+        // recorded while the upload was in flight, then the upload failed
+        storage
+            .record_payment_telemetry(false, Duration::from_secs(3))
+            .unwrap();
+        storage.restore_telemetry_stats(taken).unwrap();
+        let stats = storage.get_telemetry_stats().unwrap();
+        assert_eq!(stats.last_upload, 0);
+        assert_eq!(stats.payments_attempted, 2);
+        assert_eq!(stats.payments_succeeded, 1);
+        assert_eq!(stats.payment_times_ms, vec![1_000]);
+        assert_eq!(stats.sync_times_ms, vec![2_000]);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::error::MutinyError;
+use crate::utils;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::util::amount::Denomination;
+use bitcoin::{Address, Amount, Network, XOnlyPublicKey};
+use lightning_invoice::Bolt11Invoice;
+use lnurl::lightning_address::LightningAddress;
+use lnurl::lnurl::LnUrl;
+use nostr::nips::nip47::NostrWalletConnectURI;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const BITCOIN_SCHEME: &str = "bitcoin:";
+pub(crate) const LIGHTNING_SCHEME: &str = "lightning:";
+const NWC_SCHEME: &str = "nostr+walletconnect:";
+/// Scheme used for Mutiny deep links, eg `mutiny:lnbc1...` or `mutiny://lnurl1...`.
+/// The payload of a deep link can be any of the other URIs we understand.
+pub const MUTINY_SCHEME: &str = "mutiny:";
+
+/// LUD-17 schemes, these are rewritten to https (or http for onion services)
+const LNURL_PAY_SCHEME: &str = "lnurlp://";
+const LNURL_WITHDRAW_SCHEME: &str = "lnurlw://";
+const LNURL_CHANNEL_SCHEME: &str = "lnurlc://";
+const LNURL_AUTH_SCHEME: &str = "keyauth://";
+
+/// What the frontend should do with a URI that was scanned or opened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UriIntent {
+    /// Pay to an on-chain address, a lightning invoice, or both (BIP 21 unified QR)
+    Pay {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        address: Option<Address>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        invoice: Option<Bolt11Invoice>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        amount_sats: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    /// Pay to a LNURL-pay or a lightning address
+    LnUrlPay {
+        lnurl: LnUrl,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lightning_address: Option<LightningAddress>,
+        min_sendable_msats: u64,
+        max_sendable_msats: u64,
+    },
+    /// Withdraw from a LNURL-withdraw
+    Withdraw {
+        lnurl: LnUrl,
+        min_withdrawable_msats: u64,
+        max_withdrawable_msats: u64,
+    },
+    /// Get a channel opened to us from a LNURL-channel service
+    Channel {
+        lnurl: LnUrl,
+        service: String,
+        node_pubkey: PublicKey,
+    },
+    /// Login with LNURL-auth
+    Auth { lnurl: LnUrl },
+    /// Connect to a wallet with nostr wallet connect
+    Connect {
+        uri: String,
+        pubkey: XOnlyPublicKey,
+        relay_url: String,
+    },
+}
+
+/// The result of parsing a URI without any network requests.
+/// LNURLs (other than LNURL-auth) need to be called upon to know what they are for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ParsedUri {
+    Intent(UriIntent),
+    LnUrl {
+        lnurl: LnUrl,
+        lightning_address: Option<LightningAddress>,
+    },
+}
+
+/// Parses and validates a URI against the given network.
+pub(crate) fn parse_uri(uri: &str, network: Network) -> Result<ParsedUri, MutinyError> {
+    let uri = uri.trim();
+    if uri.is_empty() {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+
+    if let Some(payload) = strip_scheme(uri, MUTINY_SCHEME) {
+        let payload = payload.trim_start_matches("//");
+        // don't allow deep links to wrap other deep links
+        if strip_scheme(payload, MUTINY_SCHEME).is_some() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        return parse_uri(payload, network);
+    }
+
+    if let Some(payload) = strip_scheme(uri, BITCOIN_SCHEME) {
+        return parse_bip21(payload, network).map(ParsedUri::Intent);
+    }
+
+    if strip_scheme(uri, NWC_SCHEME).is_some() {
+        let nwc =
+            NostrWalletConnectURI::from_str(uri).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        return Ok(ParsedUri::Intent(UriIntent::Connect {
+            uri: nwc.to_string(),
+            pubkey: nwc.public_key,
+            relay_url: nwc.relay_url.to_string(),
+        }));
+    }
+
+    let payload = strip_scheme(uri, LIGHTNING_SCHEME).unwrap_or(uri);
+    parse_lightning(payload, network).or_else(|e| {
+        // a bare on-chain address
+        match Address::from_str(payload) {
+            Ok(_) => parse_bip21(payload, network).map(ParsedUri::Intent),
+            Err(_) => Err(e),
+        }
+    })
+}
+
+/// Parses an invoice, LNURL or lightning address.
+fn parse_lightning(payload: &str, network: Network) -> Result<ParsedUri, MutinyError> {
+    if let Ok(invoice) = Bolt11Invoice::from_str(payload) {
+        validate_invoice(&invoice, network)?;
+        return Ok(ParsedUri::Intent(UriIntent::Pay {
+            address: None,
+            amount_sats: invoice.amount_milli_satoshis().map(|m| m / 1_000),
+            invoice: Some(invoice),
+            label: None,
+            message: None,
+        }));
+    }
+
+    if let Some(lnurl) = parse_lnurl(payload) {
+        if lnurl.is_lnurl_auth() {
+            return Ok(ParsedUri::Intent(UriIntent::Auth { lnurl }));
+        }
+        return Ok(ParsedUri::LnUrl {
+            lnurl,
+            lightning_address: None,
+        });
+    }
+
+    if let Ok(lightning_address) = LightningAddress::from_str(payload) {
+        return Ok(ParsedUri::LnUrl {
+            lnurl: lightning_address.lnurl(),
+            lightning_address: Some(lightning_address),
+        });
+    }
+
+    Err(MutinyError::InvalidArgumentsError)
+}
+
+/// Parses a bech32 LNURL or a LUD-17 url
+fn parse_lnurl(payload: &str) -> Option<LnUrl> {
+    if let Ok(lnurl) = LnUrl::from_str(payload) {
+        return Some(lnurl);
+    }
+
+    [
+        LNURL_PAY_SCHEME,
+        LNURL_WITHDRAW_SCHEME,
+        LNURL_CHANNEL_SCHEME,
+        LNURL_AUTH_SCHEME,
+    ]
+    .iter()
+    .find_map(|scheme| strip_scheme(payload, scheme))
+    .and_then(|rest| {
+        let host = rest.split(['/', '?']).next().unwrap_or_default();
+        let protocol = if host.ends_with(".onion") {
+            "http"
+        } else {
+            "https"
+        };
+        let url = format!("{protocol}://{rest}");
+        url::Url::parse(&url).ok().map(|_| LnUrl::from_url(url))
+    })
+}
+
+/// Parses the part of a BIP 21 URI after `bitcoin:`
+fn parse_bip21(payload: &str, network: Network) -> Result<UriIntent, MutinyError> {
+    let (address, query) = match payload.split_once('?') {
+        Some((address, query)) => (address, query),
+        None => (payload, ""),
+    };
+
+    let address = if address.is_empty() {
+        None
+    } else {
+        let address = Address::from_str(address).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        if !address.is_valid_for_network(network) {
+            return Err(MutinyError::IncorrectNetwork(address.network));
+        }
+        Some(address)
+    };
+
+    let mut amount_sats = None;
+    let mut label = None;
+    let mut message = None;
+    let mut invoice = None;
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        match key.to_lowercase().as_str() {
+            "amount" => {
+                let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                    .map_err(|_| MutinyError::BadAmountError)?;
+                amount_sats = Some(amount.to_sat());
+            }
+            "label" => label = Some(value.to_string()),
+            "message" => message = Some(value.to_string()),
+            "lightning" => {
+                let inv = Bolt11Invoice::from_str(&value)?;
+                validate_invoice(&inv, network)?;
+                invoice = Some(inv);
+            }
+            // BIP 21 requires failing on unknown required parameters
+            k if k.starts_with("req-") => return Err(MutinyError::InvalidArgumentsError),
+            _ => {}
+        }
+    }
+
+    if address.is_none() && invoice.is_none() {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+
+    // prefer the invoice amount if the uri did not specify one
+    let amount_sats = amount_sats.or_else(|| {
+        invoice
+            .as_ref()
+            .and_then(|i| i.amount_milli_satoshis())
+            .map(|m| m / 1_000)
+    });
+
+    Ok(UriIntent::Pay {
+        address,
+        invoice,
+        amount_sats,
+        label,
+        message,
+    })
+}
+
+fn validate_invoice(invoice: &Bolt11Invoice, network: Network) -> Result<(), MutinyError> {
+    if invoice.network() != network {
+        return Err(MutinyError::IncorrectNetwork(invoice.network()));
+    }
+    if invoice.would_expire(utils::now()) {
+        return Err(MutinyError::InvoiceInvalid);
+    }
+    Ok(())
+}
+
+/// Case insensitive scheme stripping
+pub(crate) fn strip_scheme<'a>(uri: &'a str, scheme: &str) -> Option<&'a str> {
+    if uri.len() >= scheme.len()
+        && uri.is_char_boundary(scheme.len())
+        && uri[..scheme.len()].eq_ignore_ascii_case(scheme)
+    {
+        Some(&uri[scheme.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const ADDRESS: &str = "tb1qhgemzcaj5ehn7yvqq7wh0w2pkxg3ymq5yc0k8t";
+
+    #[test]
+    fn test_parse_bip21() {
+        log!("test parse bip21");
+
+        let uri = format!("bitcoin:{ADDRESS}?amount=0.0001&label=coffee&message=thanks%20bro");
+        let parsed = parse_uri(&uri, Network::Testnet).unwrap();
+        assert_eq!(
+            parsed,
+            ParsedUri::Intent(UriIntent::Pay {
+                address: Some(Address::from_str(ADDRESS).unwrap()),
+                invoice: None,
+                amount_sats: Some(10_000),
+                label: Some("coffee".to_string()),
+                message: Some("thanks bro".to_string()),
+            })
+        );
+
+        // uppercase QR codes
+        let upper = format!("BITCOIN:{}?amount=0.0001", ADDRESS.to_uppercase());
+        assert!(matches!(
+            parse_uri(&upper, Network::Testnet).unwrap(),
+            ParsedUri::Intent(UriIntent::Pay {
+                amount_sats: Some(10_000),
+                ..
+            })
+        ));
+
+        // bare address
+        assert!(parse_uri(ADDRESS, Network::Testnet).is_ok());
+    }
+
+    #[test]
+    fn test_parse_bip21_invalid() {
+        log!("test parse bip21 invalid");
+
+        let wrong_network = parse_uri(&format!("bitcoin:{ADDRESS}"), Network::Bitcoin);
+        assert!(matches!(
+            wrong_network,
+            Err(MutinyError::IncorrectNetwork(Network::Testnet))
+        ));
+
+        let bad_amount = parse_uri(&format!("bitcoin:{ADDRESS}?amount=abc"), Network::Testnet);
+        assert!(matches!(bad_amount, Err(MutinyError::BadAmountError)));
+
+        let req_param = parse_uri(&format!("bitcoin:{ADDRESS}?req-foo=bar"), Network::Testnet);
+        assert!(matches!(req_param, Err(MutinyError::InvalidArgumentsError)));
+
+        assert!(parse_uri("bitcoin:", Network::Testnet).is_err());
+        assert!(parse_uri("not a uri", Network::Testnet).is_err());
+        assert!(parse_uri("", Network::Testnet).is_err());
+    }
+
+    #[test]
+    fn test_parse_lnurl() {
+        log!("test parse lnurl");
+
+        let lnurl = LnUrl::from_url("https://mutinywallet.com/lnurlp/ben".to_string()).encode();
+        let parsed = parse_uri(&lnurl, Network::Testnet).unwrap();
+        assert!(matches!(
+            parsed,
+            ParsedUri::LnUrl {
+                lightning_address: None,
+                ..
+            }
+        ));
+
+        let prefixed = parse_uri(&format!("lightning:{lnurl}"), Network::Testnet).unwrap();
+        assert_eq!(parsed, prefixed);
+
+        let lud17 = parse_uri("lnurlp://mutinywallet.com/pay", Network::Testnet).unwrap();
+        match lud17 {
+            ParsedUri::LnUrl { lnurl, .. } => {
+                assert_eq!(lnurl.url, "https://mutinywallet.com/pay")
+            }
+            _ => panic!("expected lnurl"),
+        }
+
+        let address = parse_uri("ben@mutinywallet.com", Network::Testnet).unwrap();
+        assert!(matches!(
+            address,
+            ParsedUri::LnUrl {
+                lightning_address: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_deep_link() {
+        log!("test parse deep link");
+
+        let uri = format!("bitcoin:{ADDRESS}");
+        let direct = parse_uri(&uri, Network::Testnet).unwrap();
+
+        let deep_link = parse_uri(&format!("mutiny:{uri}"), Network::Testnet).unwrap();
+        assert_eq!(direct, deep_link);
+
+        let deep_link = parse_uri(&format!("mutiny://{uri}"), Network::Testnet).unwrap();
+        assert_eq!(direct, deep_link);
+
+        // nested deep links are not allowed
+        assert!(parse_uri(&format!("mutiny:mutiny:{uri}"), Network::Testnet).is_err());
+    }
+}
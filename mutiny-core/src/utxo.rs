@@ -0,0 +1,146 @@
+use crate::logging::MutinyLogger;
+use crate::multiesplora::MultiEsploraClient;
+use crate::node::NetworkGraph;
+use crate::utils;
+use bitcoin::{BlockHash, TxOut};
+use lightning::routing::utxo::{UtxoFuture, UtxoLookup, UtxoLookupError, UtxoResult};
+use lightning::util::logger::Logger;
+use lightning::{log_debug, log_warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The most funding outputs we remember, the cache is cleared once it's full
+const MAX_CACHED_UTXOS: usize = 10_000;
+
+/// Looks up channel funding outputs with esplora so channel announcements
+/// received from peers can be validated before they go in our network graph.
+///
+/// Lookups happen in the background and the results are cached, so an
+/// announcement for the same channel doesn't cost more requests.
+pub(crate) struct EsploraUtxoLookup {
+    esplora: MultiEsploraClient,
+    network_graph: Arc<NetworkGraph>,
+    /// short channel id -> the funding output, `None` if it doesn't exist or was spent
+    cache: Arc<utils::Mutex<HashMap<u64, Option<TxOut>>>>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl EsploraUtxoLookup {
+    pub(crate) fn new(
+        esplora: MultiEsploraClient,
+        network_graph: Arc<NetworkGraph>,
+        logger: Arc<MutinyLogger>,
+    ) -> Self {
+        Self {
+            esplora,
+            network_graph,
+            cache: Arc::new(utils::Mutex::new(HashMap::new())),
+            logger,
+        }
+    }
+}
+
+impl UtxoLookup for EsploraUtxoLookup {
+    fn get_utxo(&self, _genesis_hash: &BlockHash, short_channel_id: u64) -> UtxoResult {
+        if let Some(cached) = self
+            .cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.get(&short_channel_id).cloned())
+        {
+            return UtxoResult::Sync(cached.ok_or(UtxoLookupError::UnknownTx));
+        }
+
+        let future = UtxoFuture::new();
+        let result_future = future.clone();
+        let esplora = self.esplora.clone();
+        let network_graph = self.network_graph.clone();
+        let cache = self.cache.clone();
+        let logger = self.logger.clone();
+        utils::spawn(async move {
+            let result = match lookup_funding_output(&esplora, short_channel_id).await {
+                Ok(txout) => {
+                    if let Ok(mut cache) = cache.lock() {
+                        if cache.len() >= MAX_CACHED_UTXOS {
+                            cache.clear();
+                        }
+                        cache.insert(short_channel_id, txout.clone());
+                    }
+                    if txout.is_none() {
+                        log_debug!(logger, "no funding output for channel {short_channel_id}");
+                    }
+                    txout.ok_or(UtxoLookupError::UnknownTx)
+                }
+                Err(e) => {
+                    // don't cache failed requests so we can try again later
+                    log_warn!(logger, "could not look up channel {short_channel_id}: {e}");
+                    Err(UtxoLookupError::UnknownTx)
+                }
+            };
+
+            future.resolve_without_forwarding(&network_graph, result);
+        });
+
+        UtxoResult::Async(result_future)
+    }
+}
+
+/// Finds the unspent funding output for a short channel id,
+/// which encodes the block height, transaction index and output index.
+async fn lookup_funding_output(
+    esplora: &MultiEsploraClient,
+    short_channel_id: u64,
+) -> Result<Option<TxOut>, esplora_client::Error> {
+    let (height, tx_index, vout) = split_scid(short_channel_id);
+
+    let block_hash = esplora.get_block_hash(height).await?;
+    let Some(txid) = esplora
+        .get_txid_at_block_index(&block_hash, tx_index)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(tx) = esplora.get_tx(&txid).await? else {
+        return Ok(None);
+    };
+    let Some(txout) = tx.output.get(vout as usize) else {
+        return Ok(None);
+    };
+
+    // the channel is closed if the funding output was spent
+    let spent = esplora
+        .get_output_status(&txid, vout as u64)
+        .await?
+        .is_some_and(|status| status.spent);
+    if spent {
+        return Ok(None);
+    }
+
+    Ok(Some(txout.clone()))
+}
+
+/// Splits a short channel id into its block height, transaction index and output index
+fn split_scid(short_channel_id: u64) -> (u32, usize, u16) {
+    let height = (short_channel_id >> 40) as u32;
+    let tx_index = ((short_channel_id >> 16) & 0xff_ffff) as usize;
+    let vout = (short_channel_id & 0xffff) as u16;
+    (height, tx_index, vout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_split_scid() {
+        log!("test split scid");
+
+        // 700000x1234x1
+        let scid = (700_000u64 << 40) | (1_234u64 << 16) | 1;
+        assert_eq!(split_scid(scid), (700_000, 1_234, 1));
+    }
+}
@@ -87,6 +87,11 @@ impl MutinyVssClient {
         }
     }
 
+    /// The url of the VSS server
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
     pub async fn put_objects(&self, items: Vec<VssKeyValueItem>) -> Result<(), MutinyError> {
         let url = Url::parse(&format!("{}/putObjects", self.url)).map_err(|e| {
             log_error!(self.logger, "Error parsing put objects url: {e}");
@@ -108,6 +113,23 @@ impl MutinyVssClient {
         Ok(())
     }
 
+    /// Deletes objects in the same request used to put them,
+    /// each is only deleted if its version still matches
+    pub async fn delete_objects(&self, items: Vec<KeyVersion>) -> Result<(), MutinyError> {
+        let url = Url::parse(&format!("{}/putObjects", self.url)).map_err(|e| {
+            log_error!(self.logger, "Error parsing put objects url: {e}");
+            MutinyError::Other(anyhow!("Error parsing put objects url: {e}"))
+        })?;
+
+        let body = json!({ "transaction_items": [], "delete_items": items });
+
+        self.auth_client
+            .request(Method::PUT, url, Some(body))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn get_object(&self, key: &str) -> Result<VssKeyValueItem, MutinyError> {
         let url = Url::parse(&format!("{}/getObject", self.url)).map_err(|e| {
             log_error!(self.logger, "Error parsing get objects url: {e}");
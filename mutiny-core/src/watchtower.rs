@@ -0,0 +1,963 @@
+//! Watch-only companion mode.
+//!
+//! A [Watchtower] loads only the channel monitors from storage and keeps them synced
+//! with the chain. It does not have the wallet's seed, so it can't spend funds,
+//! but the channel monitors will broadcast justice transactions if a counterparty
+//! broadcasts a revoked commitment transaction while the main device is offline.
+//! The justice transactions pay out to the main wallet's addresses.
+//!
+//! The monitors are read again before every sync, so it keeps up with the main
+//! device. Without access to the wallet's storage, a watchtower can be created
+//! from [WatchData] exported by the main device with `NodeManager::export_watch_data`.
+
+use crate::error::MutinyError;
+use crate::esplora::EsploraSyncClient;
+use crate::fees::MutinyFeeEstimator;
+use crate::ldkstorage::MutinyNodePersister;
+use crate::logging::MutinyLogger;
+use crate::multiesplora::MultiEsploraClient;
+use crate::onchain::get_esplora_url;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use crate::utils::sleep;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::{BlockHash, Network, Transaction};
+use lightning::chain::chaininterface::BroadcasterInterface;
+use lightning::chain::chainmonitor::{self, MonitorUpdateId, Persist};
+use lightning::chain::channelmonitor::{Balance, ChannelMonitor, ChannelMonitorUpdate};
+use lightning::chain::transaction::OutPoint;
+use lightning::chain::{ChannelMonitorUpdateStatus, Filter};
+use lightning::io::Cursor;
+use lightning::sign::{InMemorySigner, KeysManager, WriteableEcdsaChannelSigner};
+use lightning::util::logger::Logger;
+use lightning::util::ser::{ReadableArgs, Writeable};
+use lightning::{log_debug, log_error, log_info, log_warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub const WATCHTOWER_ALERTS_KEY: &str = "watchtower_alerts";
+pub const WATCHTOWER_ENABLED_KEY: &str = "watchtower_enabled";
+
+/// How often the watchtower syncs with the chain
+const WATCHTOWER_SYNC_INTERVAL_SECS: i32 = 60;
+
+/// A counterparty broadcast a revoked commitment transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreachAlert {
+    /// The uuid of the node the channel belongs to
+    pub node_id: String,
+    pub funding_txo: bitcoin::OutPoint,
+    /// Amount we can claim with justice transactions
+    pub amount_sats: u64,
+    /// Epoch time in seconds of when the breach was detected
+    pub timestamp: u64,
+}
+
+/// Whether the watchtower is running and what it found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchtowerStatus {
+    pub enabled: bool,
+    /// Number of channels being watched, zero while it isn't running
+    pub channel_count: usize,
+    pub alerts: Vec<BreachAlert>,
+}
+
+/// The channel monitors of every node, exported from the main device so a
+/// watchtower can watch them without the seed or the wallet's storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchData {
+    pub monitors: Vec<WatchedMonitor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedMonitor {
+    /// The uuid of the node the channel belongs to
+    pub node_id: String,
+    /// The hex encoded channel monitor
+    pub monitor: String,
+}
+
+impl WatchedMonitor {
+    pub(crate) fn new<Signer: WriteableEcdsaChannelSigner>(
+        node_id: String,
+        monitor: &ChannelMonitor<Signer>,
+    ) -> Self {
+        Self {
+            node_id,
+            monitor: monitor.encode().to_hex(),
+        }
+    }
+}
+
+/// Where the watchtower reads the channel monitors from
+enum MonitorSource {
+    /// The wallet's storage, synced from the main device
+    Storage,
+    /// Watch data given by the user, replaced with [Watchtower::update_watch_data]
+    WatchData(utils::Mutex<WatchData>),
+}
+
+/// The channel monitors being watched and what syncs them
+struct Watched<S: MutinyStorage> {
+    tx_sync: Arc<EsploraSyncClient<Arc<MutinyLogger>>>,
+    chain_monitor: Arc<WatchtowerChainMonitor<S>>,
+    /// Funding outpoint to the uuid of the node that owns the channel
+    channels: HashMap<OutPoint, String>,
+}
+
+/// Returns all the breaches the watchtower has detected
+pub(crate) fn get_breach_alerts<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<BreachAlert>, MutinyError> {
+    let alerts: Option<Vec<BreachAlert>> = storage.get_data(WATCHTOWER_ALERTS_KEY)?;
+    Ok(alerts.unwrap_or_default())
+}
+
+/// Whether the watchtower should run along with the wallet
+pub(crate) fn is_watchtower_enabled<S: MutinyStorage>(storage: &S) -> Result<bool, MutinyError> {
+    let enabled: Option<bool> = storage.get_data(WATCHTOWER_ENABLED_KEY)?;
+    Ok(enabled.unwrap_or(false))
+}
+
+type WatchtowerChainMonitor<S: MutinyStorage> = chainmonitor::ChainMonitor<
+    InMemorySigner,
+    Arc<dyn Filter + Send + Sync>,
+    Arc<WatchtowerBroadcaster>,
+    Arc<MutinyFeeEstimator<S>>,
+    Arc<MutinyLogger>,
+    Arc<WatchOnlyPersister>,
+>;
+
+/// Broadcasts transactions directly through esplora, we don't have an on-chain wallet.
+pub(crate) struct WatchtowerBroadcaster {
+    esplora: Arc<MultiEsploraClient>,
+    /// Every transaction uploaded to esplora, like justice transactions
+    uploaded: utils::Mutex<Vec<Transaction>>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl BroadcasterInterface for WatchtowerBroadcaster {
+    fn broadcast_transactions(&self, txs: &[&Transaction]) {
+        let txs_clone = txs
+            .iter()
+            .map(|tx| (*tx).clone())
+            .collect::<Vec<Transaction>>();
+        if let Ok(mut uploaded) = self.uploaded.lock() {
+            uploaded.extend(txs_clone.iter().cloned());
+        }
+        let esplora = self.esplora.clone();
+        let logger = self.logger.clone();
+        utils::spawn(async move {
+            for tx in txs_clone {
+                let txid = tx.txid();
+                match esplora.broadcast(&tx).await {
+                    Ok(_) => log_info!(logger, "Watchtower broadcast transaction {txid}"),
+                    Err(e) => log_warn!(logger, "Error broadcasting transaction {txid}: {e}"),
+                }
+            }
+        });
+    }
+}
+
+/// The main device owns the channel monitors, the watchtower never writes them back.
+pub(crate) struct WatchOnlyPersister;
+
+impl<ChannelSigner: WriteableEcdsaChannelSigner> Persist<ChannelSigner> for WatchOnlyPersister {
+    fn persist_new_channel(
+        &self,
+        _funding_txo: OutPoint,
+        _monitor: &ChannelMonitor<ChannelSigner>,
+        _update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        ChannelMonitorUpdateStatus::Completed
+    }
+
+    fn update_persisted_channel(
+        &self,
+        _funding_txo: OutPoint,
+        _update: Option<&ChannelMonitorUpdate>,
+        _monitor: &ChannelMonitor<ChannelSigner>,
+        _update_id: MonitorUpdateId,
+    ) -> ChannelMonitorUpdateStatus {
+        ChannelMonitorUpdateStatus::Completed
+    }
+}
+
+pub struct Watchtower<S: MutinyStorage> {
+    storage: S,
+    source: MonitorSource,
+    esplora: Arc<MultiEsploraClient>,
+    watched: utils::Mutex<Watched<S>>,
+    broadcaster: Arc<WatchtowerBroadcaster>,
+    fee_estimator: Arc<MutinyFeeEstimator<S>>,
+    keys_manager: Arc<KeysManager>,
+    stop: Arc<AtomicBool>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl<S: MutinyStorage> Watchtower<S> {
+    /// Creates a watchtower from the channel monitors in storage.
+    /// The storage should be synced from the main device, ie through VSS.
+    pub fn new(
+        storage: S,
+        network: Network,
+        user_esplora_url: Option<String>,
+        logger: Arc<MutinyLogger>,
+    ) -> Result<Self, MutinyError> {
+        let esplora_server_url = get_esplora_url(network, user_esplora_url);
+        let esplora = MultiEsploraClient::from_urls(&esplora_server_url)?;
+        Self::with_esplora(storage, Arc::new(esplora), logger)
+    }
+
+    /// Creates a watchtower from watch data exported by the main device, for
+    /// when it can't read the wallet's storage. The storage is only used to save
+    /// the breaches found and fee estimates, it doesn't need to be the wallet's.
+    pub fn from_watch_data(
+        data: WatchData,
+        storage: S,
+        network: Network,
+        user_esplora_url: Option<String>,
+        logger: Arc<MutinyLogger>,
+    ) -> Result<Self, MutinyError> {
+        let esplora_server_url = get_esplora_url(network, user_esplora_url);
+        let esplora = MultiEsploraClient::from_urls(&esplora_server_url)?;
+        let source = MonitorSource::WatchData(utils::Mutex::new(data));
+        Self::build(storage, source, Arc::new(esplora), logger)
+    }
+
+    /// Creates a watchtower that uses an existing esplora client, like the wallet's
+    pub(crate) fn with_esplora(
+        storage: S,
+        esplora: Arc<MultiEsploraClient>,
+        logger: Arc<MutinyLogger>,
+    ) -> Result<Self, MutinyError> {
+        Self::build(storage, MonitorSource::Storage, esplora, logger)
+    }
+
+    fn build(
+        storage: S,
+        source: MonitorSource,
+        esplora: Arc<MultiEsploraClient>,
+        logger: Arc<MutinyLogger>,
+    ) -> Result<Self, MutinyError> {
+        let fee_estimator = Arc::new(MutinyFeeEstimator::new(
+            storage.clone(),
+            esplora.clone(),
+            logger.clone(),
+        ));
+        let broadcaster = Arc::new(WatchtowerBroadcaster {
+            esplora: esplora.clone(),
+            uploaded: utils::Mutex::new(vec![]),
+            logger: logger.clone(),
+        });
+
+        // The channel monitors contain their own channel keys, the keys manager is only
+        // needed to deserialize them, so we can use random entropy instead of the seed.
+        let mut seed = [0u8; 32];
+        getrandom::getrandom(&mut seed).map_err(|_| MutinyError::SeedGenerationFailed)?;
+        let now = utils::now();
+        let keys_manager = Arc::new(KeysManager::new(&seed, now.as_secs(), now.subsec_nanos()));
+
+        let watched = Self::watch_nothing(&esplora, &broadcaster, &fee_estimator, &logger);
+        let watchtower = Self {
+            storage,
+            source,
+            esplora,
+            watched: utils::Mutex::new(watched),
+            broadcaster,
+            fee_estimator,
+            keys_manager,
+            stop: Arc::new(AtomicBool::new(false)),
+            logger,
+        };
+        watchtower.reload_monitors()?;
+        log_info!(
+            watchtower.logger,
+            "Watchtower watching {} channels",
+            watchtower.channel_count()
+        );
+
+        Ok(watchtower)
+    }
+
+    /// Reads the channel monitors to watch along with the uuid of their node
+    fn read_monitors(&self) -> Result<Vec<(String, ChannelMonitor<InMemorySigner>)>, MutinyError> {
+        let mut res = vec![];
+        match &self.source {
+            MonitorSource::Storage => {
+                let node_storage = self.storage.get_nodes()?;
+                for (uuid, index) in node_storage.nodes {
+                    if index.is_archived() {
+                        continue;
+                    }
+
+                    let persister = MutinyNodePersister::new(
+                        uuid.clone(),
+                        self.storage.clone(),
+                        self.logger.clone(),
+                    );
+                    let monitors = persister.read_channel_monitors(
+                        self.keys_manager.clone(),
+                        self.broadcaster.as_ref(),
+                        self.fee_estimator.as_ref(),
+                    )?;
+                    res.extend(monitors.into_iter().map(|(_, m)| (uuid.clone(), m)));
+                }
+            }
+            MonitorSource::WatchData(data) => {
+                let data = data
+                    .lock()
+                    .map_err(|_| MutinyError::WalletOperationFailed)?;
+                for watched in data.monitors.iter() {
+                    let bytes: Vec<u8> = FromHex::from_hex(&watched.monitor)
+                        .map_err(|_| MutinyError::InvalidArgumentsError)?;
+                    let (_, monitor) = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+                        &mut Cursor::new(bytes),
+                        (self.keys_manager.as_ref(), self.keys_manager.as_ref()),
+                    )
+                    .map_err(|_| MutinyError::InvalidArgumentsError)?;
+                    res.push((watched.node_id.clone(), monitor));
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Sets up a new chain monitor without any channels. It gets its own
+    /// sync client so the monitors it watches are brought up to the chain tip.
+    fn watch_nothing(
+        esplora: &MultiEsploraClient,
+        broadcaster: &Arc<WatchtowerBroadcaster>,
+        fee_estimator: &Arc<MutinyFeeEstimator<S>>,
+        logger: &Arc<MutinyLogger>,
+    ) -> Watched<S> {
+        let tx_sync = Arc::new(EsploraSyncClient::from_client(
+            esplora.clone(),
+            logger.clone(),
+        ));
+        let chain_monitor: Arc<WatchtowerChainMonitor<S>> =
+            Arc::new(chainmonitor::ChainMonitor::new(
+                Some(tx_sync.clone()),
+                broadcaster.clone(),
+                logger.clone(),
+                fee_estimator.clone(),
+                Arc::new(WatchOnlyPersister),
+            ));
+
+        Watched {
+            tx_sync,
+            chain_monitor,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Sets up a new chain monitor watching the given monitors
+    fn watch(&self, monitors: Vec<(String, ChannelMonitor<InMemorySigner>)>) -> Watched<S> {
+        let mut watched = Self::watch_nothing(
+            &self.esplora,
+            &self.broadcaster,
+            &self.fee_estimator,
+            &self.logger,
+        );
+        for (uuid, monitor) in monitors {
+            monitor.load_outputs_to_watch(&watched.tx_sync);
+            let funding_txo = monitor.get_funding_txo().0;
+            watched.chain_monitor.watch_channel(funding_txo, monitor);
+            watched.channels.insert(funding_txo, uuid);
+        }
+
+        watched
+    }
+
+    /// Reads the channel monitors again and starts watching them if the main device
+    /// opened channels or moved them to new states. Returns whether anything changed.
+    fn reload_monitors(&self) -> Result<bool, MutinyError> {
+        let monitors = self.read_monitors()?;
+
+        let mut watched = self
+            .watched
+            .lock()
+            .map_err(|_| MutinyError::WalletOperationFailed)?;
+        let changed = monitors.len() != watched.channels.len()
+            || monitors.iter().any(|(_, monitor)| {
+                let funding_txo = monitor.get_funding_txo().0;
+                let current = watched.chain_monitor.get_monitor(funding_txo);
+                current.map_or(true, |current| {
+                    current.get_latest_update_id() < monitor.get_latest_update_id()
+                })
+            });
+        if !changed {
+            return Ok(false);
+        }
+
+        *watched = self.watch(monitors);
+        log_debug!(
+            self.logger,
+            "Watchtower reloaded, watching {} channels",
+            watched.channels.len()
+        );
+        Ok(true)
+    }
+
+    /// Replaces the watch data of a watchtower created with [Watchtower::from_watch_data],
+    /// like after the main device exported it again.
+    pub fn update_watch_data(&self, data: WatchData) -> Result<(), MutinyError> {
+        let MonitorSource::WatchData(current) = &self.source else {
+            return Err(MutinyError::InvalidArgumentsError);
+        };
+        let old = std::mem::replace(
+            &mut *current
+                .lock()
+                .map_err(|_| MutinyError::WalletOperationFailed)?,
+            data,
+        );
+
+        // keep the old data if the new data can't be read
+        if let Err(e) = self.reload_monitors() {
+            if let Ok(mut current) = current.lock() {
+                *current = old;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The chain monitor of the channels currently being watched
+    #[cfg(test)]
+    fn chain_monitor(&self) -> Arc<WatchtowerChainMonitor<S>> {
+        self.watched.lock().unwrap().chain_monitor.clone()
+    }
+
+    /// Syncs the channel monitors with the chain, broadcasting
+    /// justice transactions if needed. Returns any new breaches.
+    pub async fn sync(&self) -> Result<Vec<BreachAlert>, MutinyError> {
+        // keep fees up to date so justice transactions confirm in time
+        if let Err(e) = self.fee_estimator.update_fee_estimates_if_necessary().await {
+            log_warn!(self.logger, "Failed to update fee estimates: {e}");
+        }
+
+        // pick up channels and states the main device saved since the last sync
+        if let Err(e) = self.reload_monitors() {
+            log_warn!(self.logger, "Failed to reload channel monitors: {e}");
+        }
+
+        let (tx_sync, chain_monitor) = {
+            let watched = self
+                .watched
+                .lock()
+                .map_err(|_| MutinyError::WalletOperationFailed)?;
+            (watched.tx_sync.clone(), watched.chain_monitor.clone())
+        };
+        tx_sync
+            .sync(vec![chain_monitor.deref()])
+            .await
+            .map_err(|_| MutinyError::ChainAccessFailed)?;
+
+        chain_monitor.rebroadcast_pending_claims();
+
+        self.check_for_breaches()
+    }
+
+    /// Looks for channels where the counterparty broadcast a revoked state
+    /// and saves an alert for any we haven't seen before.
+    fn check_for_breaches(&self) -> Result<Vec<BreachAlert>, MutinyError> {
+        let mut alerts = self.get_alerts()?;
+        let mut new_alerts = vec![];
+        let watched = self
+            .watched
+            .lock()
+            .map_err(|_| MutinyError::WalletOperationFailed)?;
+        for funding_txo in watched.chain_monitor.list_monitors() {
+            let Ok(monitor) = watched.chain_monitor.get_monitor(funding_txo) else {
+                continue;
+            };
+
+            let amount_sats: u64 = monitor
+                .get_claimable_balances()
+                .iter()
+                .filter(|b| matches!(b, Balance::CounterpartyRevokedOutputClaimable { .. }))
+                .map(|b| b.claimable_amount_satoshis())
+                .sum();
+
+            let outpoint = funding_txo.into_bitcoin_outpoint();
+            if amount_sats == 0 || alerts.iter().any(|a| a.funding_txo == outpoint) {
+                continue;
+            }
+
+            log_error!(
+                self.logger,
+                "Breach detected on channel {outpoint}, claiming {amount_sats} sats"
+            );
+
+            let alert = BreachAlert {
+                node_id: watched
+                    .channels
+                    .get(&funding_txo)
+                    .cloned()
+                    .unwrap_or_default(),
+                funding_txo: outpoint,
+                amount_sats,
+                timestamp: utils::now().as_secs(),
+            };
+            alerts.push(alert.clone());
+            new_alerts.push(alert);
+        }
+
+        if !new_alerts.is_empty() {
+            self.storage.set_data(WATCHTOWER_ALERTS_KEY, alerts, None)?;
+        }
+
+        Ok(new_alerts)
+    }
+
+    /// Returns all the breaches the watchtower has detected
+    pub fn get_alerts(&self) -> Result<Vec<BreachAlert>, MutinyError> {
+        get_breach_alerts(&self.storage)
+    }
+
+    /// The transactions the watchtower uploaded since it started, like justice transactions
+    pub fn uploaded_transactions(&self) -> Vec<Transaction> {
+        self.broadcaster
+            .uploaded
+            .lock()
+            .map(|uploaded| uploaded.clone())
+            .unwrap_or_default()
+    }
+
+    /// Number of channels the watchtower is watching
+    pub fn channel_count(&self) -> usize {
+        self.watched
+            .lock()
+            .map(|w| w.channels.len())
+            .unwrap_or_default()
+    }
+
+    /// Starts syncing in the background until [Watchtower::stop] is called.
+    pub fn start(watchtower: Arc<Watchtower<S>>) {
+        utils::spawn(async move {
+            loop {
+                if watchtower.stop.load(Ordering::Relaxed) {
+                    return;
+                }
+
+                match watchtower.sync().await {
+                    Ok(alerts) if !alerts.is_empty() => {
+                        log_warn!(
+                            watchtower.logger,
+                            "Watchtower found {} breaches",
+                            alerts.len()
+                        )
+                    }
+                    Ok(_) => log_debug!(watchtower.logger, "Watchtower synced"),
+                    Err(e) => log_error!(watchtower.logger, "Watchtower failed to sync: {e}"),
+                }
+
+                // sleep, checking graceful shutdown check each 1s.
+                for _ in 0..WATCHTOWER_SYNC_INTERVAL_SECS {
+                    if watchtower.stop.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    sleep(1_000).await;
+                }
+            }
+        });
+    }
+
+    pub fn stop(&self) {
+        self.stop.swap(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodemanager::{NodeIndex, NodeStorage};
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::PublicKey;
+    use bitcoin::{
+        BlockHash, BlockHeader, PackedLockTime, Script, Sequence, TxIn, TxMerkleNode, TxOut, Txid,
+        Witness,
+    };
+    use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+    use lightning::chain::{BestBlock, Confirm};
+    use lightning::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+    use lightning::ln::channelmanager::{ChainParameters, ChannelDetails, ChannelManager};
+    use lightning::ln::msgs::{ChannelMessageHandler, ErrorAction, Init, LightningError};
+    use lightning::routing::router::{InFlightHtlcs, Route, RouteParameters, Router};
+    use lightning::util::config::UserConfig;
+    use std::sync::atomic::AtomicU32;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const NODE_ID: &str = "test-node";
+
+    fn storage_with_node() -> MemoryStorage {
+        let storage = MemoryStorage::default();
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            NODE_ID.to_string(),
+            NodeIndex {
+                child_index: 0,
+                lsp: None,
+                archived: Some(false),
+            },
+        );
+        storage
+            .insert_nodes(NodeStorage { nodes, version: 0 })
+            .unwrap();
+        storage
+    }
+
+    struct TestFeeEstimator(AtomicU32);
+
+    impl FeeEstimator for TestFeeEstimator {
+        fn get_est_sat_per_1000_weight(&self, _: ConfirmationTarget) -> u32 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    struct TestBroadcaster;
+
+    impl BroadcasterInterface for TestBroadcaster {
+        fn broadcast_transactions(&self, _: &[&Transaction]) {}
+    }
+
+    /// The test nodes never pay each other
+    struct NoRouter;
+
+    impl Router for NoRouter {
+        fn find_route(
+            &self,
+            _: &PublicKey,
+            _: &RouteParameters,
+            _: Option<&[&ChannelDetails]>,
+            _: InFlightHtlcs,
+        ) -> Result<Route, LightningError> {
+            Err(LightningError {
+                err: "No routes in tests".to_string(),
+                action: ErrorAction::IgnoreError,
+            })
+        }
+    }
+
+    type TestChainMonitor<P> = chainmonitor::ChainMonitor<
+        InMemorySigner,
+        Arc<dyn Filter + Send + Sync>,
+        Arc<TestBroadcaster>,
+        Arc<TestFeeEstimator>,
+        Arc<MutinyLogger>,
+        Arc<P>,
+    >;
+
+    type TestChannelManager<P> = ChannelManager<
+        Arc<TestChainMonitor<P>>,
+        Arc<TestBroadcaster>,
+        Arc<KeysManager>,
+        Arc<KeysManager>,
+        Arc<KeysManager>,
+        Arc<TestFeeEstimator>,
+        Arc<NoRouter>,
+        Arc<MutinyLogger>,
+    >;
+
+    /// A lightning node without networking, messages are handed over directly
+    struct TestNode<P: Persist<InMemorySigner>> {
+        chain_monitor: Arc<TestChainMonitor<P>>,
+        channel_manager: TestChannelManager<P>,
+        logger: Arc<MutinyLogger>,
+    }
+
+    impl<P: Persist<InMemorySigner>> TestNode<P> {
+        fn new(seed: u8, persister: Arc<P>, fees: Arc<TestFeeEstimator>) -> Self {
+            let mut config = UserConfig::default();
+            config.channel_handshake_config.minimum_depth = 1;
+            config.channel_handshake_config.announced_channel = false;
+
+            let logger = Arc::new(MutinyLogger::default());
+            let keys_manager = Arc::new(KeysManager::new(&[seed; 32], 0, 0));
+            let broadcaster = Arc::new(TestBroadcaster);
+            let chain_monitor = Arc::new(chainmonitor::ChainMonitor::new(
+                None,
+                broadcaster.clone(),
+                logger.clone(),
+                fees.clone(),
+                persister,
+            ));
+            let channel_manager = ChannelManager::new(
+                fees,
+                chain_monitor.clone(),
+                broadcaster,
+                Arc::new(NoRouter),
+                logger.clone(),
+                keys_manager.clone(),
+                keys_manager.clone(),
+                keys_manager,
+                config,
+                ChainParameters {
+                    network: Network::Regtest,
+                    best_block: BestBlock::from_network(Network::Regtest),
+                },
+                0,
+            );
+
+            Self {
+                chain_monitor,
+                channel_manager,
+                logger,
+            }
+        }
+
+        fn pubkey(&self) -> PublicKey {
+            self.channel_manager.get_our_node_id()
+        }
+
+        fn confirm(&self, header: &BlockHeader, txs: &[&Transaction], height: u32) {
+            let txdata: Vec<(usize, &Transaction)> =
+                txs.iter().enumerate().map(|(i, tx)| (i + 1, *tx)).collect();
+            self.channel_manager
+                .transactions_confirmed(header, &txdata, height);
+            self.chain_monitor
+                .transactions_confirmed(header, &txdata, height);
+            self.channel_manager.best_block_updated(header, height);
+            self.chain_monitor.best_block_updated(header, height);
+        }
+
+        /// Creates the funding transaction LDK asked for, its input is never checked
+        fn fund_channel(&self, counterparty: &PublicKey) -> Transaction {
+            let funding = std::sync::Mutex::new(None);
+            self.channel_manager
+                .process_pending_events(&|event: Event| {
+                    if let Event::FundingGenerationReady {
+                        temporary_channel_id,
+                        channel_value_satoshis,
+                        output_script,
+                        ..
+                    } = event
+                    {
+                        *funding.lock().unwrap() =
+                            Some((temporary_channel_id, channel_value_satoshis, output_script));
+                    }
+                });
+            let (temporary_channel_id, value, script_pubkey) =
+                funding.into_inner().unwrap().unwrap();
+
+            let tx = Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: bitcoin::OutPoint::new(Txid::from_slice(&[1; 32]).unwrap(), 0),
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_vec(vec![vec![1]]),
+                }],
+                output: vec![TxOut {
+                    value,
+                    script_pubkey,
+                }],
+            };
+            self.channel_manager
+                .funding_transaction_generated(&temporary_channel_id, counterparty, tx.clone())
+                .unwrap();
+            tx
+        }
+
+        /// The commitment transaction this node would broadcast for the channel right now
+        fn commitment_tx(&self, funding_txo: OutPoint) -> Transaction {
+            self.chain_monitor
+                .get_monitor(funding_txo)
+                .unwrap()
+                .get_latest_holder_commitment_txn(&self.logger)
+                .remove(0)
+        }
+    }
+
+    fn block_header(height: u32) -> BlockHeader {
+        BlockHeader {
+            version: 2,
+            prev_blockhash: BlockHash::from_slice(&[height as u8; 32]).unwrap(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: height,
+            bits: 0,
+            nonce: 0,
+        }
+    }
+
+    fn connect<P: Persist<InMemorySigner>, Q: Persist<InMemorySigner>>(
+        a: &TestNode<P>,
+        b: &TestNode<Q>,
+    ) {
+        let init_a = Init {
+            features: a.channel_manager.provided_init_features(&b.pubkey()),
+            networks: None,
+            remote_network_address: None,
+        };
+        let init_b = Init {
+            features: b.channel_manager.provided_init_features(&a.pubkey()),
+            networks: None,
+            remote_network_address: None,
+        };
+        a.channel_manager
+            .peer_connected(&b.pubkey(), &init_b, false)
+            .unwrap();
+        b.channel_manager
+            .peer_connected(&a.pubkey(), &init_a, true)
+            .unwrap();
+    }
+
+    /// Hands the nodes each other's messages until neither has anything left to say
+    fn exchange_messages<P: Persist<InMemorySigner>, Q: Persist<InMemorySigner>>(
+        a: &TestNode<P>,
+        b: &TestNode<Q>,
+    ) {
+        loop {
+            let a_events = a.channel_manager.get_and_clear_pending_msg_events();
+            let b_events = b.channel_manager.get_and_clear_pending_msg_events();
+            if a_events.is_empty() && b_events.is_empty() {
+                return;
+            }
+            for event in a_events {
+                deliver(&a.pubkey(), &b.channel_manager, event);
+            }
+            for event in b_events {
+                deliver(&b.pubkey(), &a.channel_manager, event);
+            }
+        }
+    }
+
+    fn deliver(from: &PublicKey, to: &dyn ChannelMessageHandler, event: MessageSendEvent) {
+        match event {
+            MessageSendEvent::SendOpenChannel { msg, .. } => to.handle_open_channel(from, &msg),
+            MessageSendEvent::SendAcceptChannel { msg, .. } => to.handle_accept_channel(from, &msg),
+            MessageSendEvent::SendFundingCreated { msg, .. } => {
+                to.handle_funding_created(from, &msg)
+            }
+            MessageSendEvent::SendFundingSigned { msg, .. } => to.handle_funding_signed(from, &msg),
+            MessageSendEvent::SendChannelReady { msg, .. } => to.handle_channel_ready(from, &msg),
+            MessageSendEvent::SendRevokeAndACK { msg, .. } => to.handle_revoke_and_ack(from, &msg),
+            MessageSendEvent::UpdateHTLCs { updates, .. } => {
+                if let Some(update_fee) = updates.update_fee.as_ref() {
+                    to.handle_update_fee(from, update_fee);
+                }
+                to.handle_commitment_signed(from, &updates.commitment_signed);
+            }
+            // channel updates and the like don't matter between two nodes
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_create_watchtower_without_monitors() {
+        log!("test create watchtower without monitors");
+
+        let storage = storage_with_node();
+        let logger = Arc::new(MutinyLogger::default());
+        let watchtower = Watchtower::new(storage, Network::Regtest, None, logger).unwrap();
+
+        assert_eq!(watchtower.channel_count(), 0);
+        assert!(watchtower.get_alerts().unwrap().is_empty());
+        assert!(watchtower.check_for_breaches().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_revoked_state_uploads_justice_tx() {
+        log!("test revoked state uploads justice tx");
+
+        // our node saves its monitors to storage like the main device,
+        // the counterparty is the one that broadcasts a revoked state
+        let storage = storage_with_node();
+        let logger = Arc::new(MutinyLogger::default());
+        let fees = Arc::new(TestFeeEstimator(AtomicU32::new(1_000)));
+        let persister = Arc::new(MutinyNodePersister::new(
+            NODE_ID.to_string(),
+            storage.clone(),
+            logger.clone(),
+        ));
+        let ours = TestNode::new(1, persister, fees.clone());
+        let theirs = TestNode::new(2, Arc::new(WatchOnlyPersister), fees.clone());
+        connect(&ours, &theirs);
+
+        // started before the channel is opened
+        let watchtower = Watchtower::new(storage, Network::Regtest, None, logger.clone()).unwrap();
+        assert_eq!(watchtower.channel_count(), 0);
+
+        // open a channel that pushes them part of the balance
+        ours.channel_manager
+            .create_channel(theirs.pubkey(), 1_000_000, 400_000_000, 0, None)
+            .unwrap();
+        exchange_messages(&ours, &theirs);
+        let funding_tx = ours.fund_channel(&theirs.pubkey());
+        exchange_messages(&ours, &theirs);
+        ours.confirm(&block_header(1), &[&funding_tx], 1);
+        theirs.confirm(&block_header(1), &[&funding_tx], 1);
+        exchange_messages(&ours, &theirs);
+
+        let channels = ours.channel_manager.list_usable_channels();
+        assert_eq!(channels.len(), 1);
+        let funding_txo = channels[0].funding_txo.unwrap();
+
+        // raising the fee rate moves the channel to a new state, revoking the first one
+        let revoked_tx = theirs.commitment_tx(funding_txo);
+        fees.0.store(2_000, Ordering::Relaxed);
+        ours.channel_manager.timer_tick_occurred();
+        exchange_messages(&ours, &theirs);
+        assert_ne!(theirs.commitment_tx(funding_txo).txid(), revoked_tx.txid());
+
+        // the watchtower picks up the monitors saved to storage since it started
+        assert!(watchtower.reload_monitors().unwrap());
+        assert!(!watchtower.reload_monitors().unwrap());
+        assert_eq!(watchtower.channel_count(), 1);
+        assert!(watchtower.uploaded_transactions().is_empty());
+
+        // one without the wallet's storage watches the exported monitors instead
+        let data = WatchData {
+            monitors: vec![WatchedMonitor::new(
+                NODE_ID.to_string(),
+                &*ours.chain_monitor.get_monitor(funding_txo).unwrap(),
+            )],
+        };
+        let seedless = Watchtower::from_watch_data(
+            data,
+            MemoryStorage::default(),
+            Network::Regtest,
+            None,
+            logger,
+        )
+        .unwrap();
+        assert_eq!(seedless.channel_count(), 1);
+        assert!(seedless.update_watch_data(WatchData::default()).is_ok());
+        assert_eq!(seedless.channel_count(), 0);
+
+        // they broadcast the revoked state
+        let header = block_header(2);
+        let txdata = [(1, &revoked_tx)];
+        let chain_monitor = watchtower.chain_monitor();
+        chain_monitor.transactions_confirmed(&header, &txdata, 2);
+        chain_monitor.best_block_updated(&header, 2);
+
+        // and the justice transaction claiming it is uploaded
+        let justice_txs: Vec<Transaction> = watchtower
+            .uploaded_transactions()
+            .into_iter()
+            .filter(|tx| {
+                tx.input
+                    .iter()
+                    .any(|i| i.previous_output.txid == revoked_tx.txid())
+            })
+            .collect();
+        assert!(!justice_txs.is_empty());
+
+        let alerts = watchtower.check_for_breaches().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].node_id, NODE_ID);
+        assert_eq!(alerts[0].funding_txo, funding_txo.into_bitcoin_outpoint());
+        assert!(alerts[0].amount_sats > 0);
+        assert_eq!(watchtower.get_alerts().unwrap(), alerts);
+    }
+}
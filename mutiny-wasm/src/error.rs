@@ -70,6 +70,16 @@ pub enum MutinyJsError {
     /// No route for the given target could be found.
     #[error("Failed to find route.")]
     RoutingFailed,
+    /// Routes were found, but all of them had fees over the fee cap.
+    #[error("No route was found with fees under the fee cap.")]
+    FeeCapExceeded,
+    /// A payment proof's preimage, invoice or signature doesn't check out.
+    #[error("The payment proof is not valid.")]
+    InvalidPaymentProof,
+    /// The invoice was made by the node trying to pay it, pay it from another
+    /// of our nodes or use a transfer to move funds between nodes.
+    #[error("Cannot pay an invoice from the node that created it.")]
+    SelfPayment,
     /// A given peer info could not be parsed.
     #[error("Failed to parse the given peer information.")]
     PeerInfoParseFailed,
@@ -134,6 +144,21 @@ pub enum MutinyJsError {
     /// Incorrect password entered.
     #[error("Incorrect password entered.")]
     IncorrectPassword,
+    /// A request with the same idempotency key is still in progress.
+    #[error("A request with this idempotency key is already in progress.")]
+    IdempotencyKeyInUse,
+    /// Allowlist-only mode is on and the peer is not in the allowlist.
+    #[error("This peer is not in the allowlist.")]
+    PeerNotAllowed,
+    /// The remote backup has newer channel state than this device.
+    #[error("Another device has newer channel state, take over from it to continue.")]
+    StaleChannelState,
+    /// Channel state in storage failed its checksum.
+    #[error("Channel state in storage is corrupted, recover from a static channel backup.")]
+    CorruptedChannelState,
+    /// Could not look up or verify a NIP-05 identifier
+    #[error("Failed to verify the NIP-05 identifier.")]
+    Nip05Failure,
     /// Unknown error.
     #[error("Unknown Error")]
     UnknownError,
@@ -159,6 +184,9 @@ impl From<MutinyError> for MutinyJsError {
             MutinyError::LspFundingError => MutinyJsError::LspFundingError,
             MutinyError::LspConnectionError => MutinyJsError::LspConnectionError,
             MutinyError::RoutingFailed => MutinyJsError::RoutingFailed,
+            MutinyError::FeeCapExceeded => MutinyJsError::FeeCapExceeded,
+            MutinyError::InvalidPaymentProof => MutinyJsError::InvalidPaymentProof,
+            MutinyError::SelfPayment => MutinyJsError::SelfPayment,
             MutinyError::PeerInfoParseFailed => MutinyJsError::PeerInfoParseFailed,
             MutinyError::ChannelCreationFailed => MutinyJsError::ChannelCreationFailed,
             MutinyError::ChannelClosingFailed => MutinyJsError::ChannelClosingFailed,
@@ -179,6 +207,11 @@ impl From<MutinyError> for MutinyJsError {
             MutinyError::NostrError => MutinyJsError::NostrError,
             MutinyError::BitcoinPriceError => MutinyJsError::BitcoinPriceError,
             MutinyError::IncorrectPassword => MutinyJsError::IncorrectPassword,
+            MutinyError::IdempotencyKeyInUse => MutinyJsError::IdempotencyKeyInUse,
+            MutinyError::PeerNotAllowed => MutinyJsError::PeerNotAllowed,
+            MutinyError::StaleChannelState => MutinyJsError::StaleChannelState,
+            MutinyError::CorruptedChannelState { key: _ } => MutinyJsError::CorruptedChannelState,
+            MutinyError::Nip05Failure => MutinyJsError::Nip05Failure,
             MutinyError::Other(_) => MutinyJsError::UnknownError,
             MutinyError::SubscriptionClientNotConfigured => {
                 MutinyJsError::SubscriptionClientNotConfigured
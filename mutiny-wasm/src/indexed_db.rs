@@ -1,9 +1,11 @@
+use crate::utils::sleep;
 use anyhow::anyhow;
 use gloo_storage::{LocalStorage, Storage};
 use gloo_utils::format::JsValueSerdeExt;
 use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error};
-use log::error;
+use mutiny_core::crash::CRASH_REPORTS_KEY;
+use mutiny_core::devices::{DeviceRegistry, DEVICE_REGISTRY_KEY};
 use mutiny_core::encrypt::encryption_key_from_pass;
 use mutiny_core::logging::MutinyLogger;
 use mutiny_core::nodemanager::NodeStorage;
@@ -14,16 +16,44 @@ use mutiny_core::{
     encrypt::Cipher,
     error::{MutinyError, MutinyStorageError},
 };
-use rexie::{ObjectStore, Rexie, TransactionMode};
+use rexie::{ObjectStore, Rexie, Transaction, TransactionMode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, TryLockError};
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::spawn_local;
 
 pub(crate) const WALLET_DATABASE_NAME: &str = "wallet";
 pub(crate) const WALLET_OBJECT_STORE_NAME: &str = "wallet_store";
+/// How long writes are collected before they are saved to indexed db together
+const WRITE_BATCH_WINDOW_MS: i32 = 50;
+/// How often stopping and flushing check if a flush has finished
+const STOP_POLL_MS: i32 = 10;
+/// How many times a failed batch is tried again before giving up,
+/// the writes are kept and tried again with the next batch
+const MAX_WRITE_RETRIES: u32 = 5;
+
+/// Writes waiting to be saved to indexed db, only the latest value of each
+/// key is kept. A `None` value deletes the key.
+#[derive(Default)]
+struct PendingWrites {
+    writes: HashMap<String, Option<Value>>,
+    /// Whether a task is already saving the writes
+    flushing: bool,
+    /// Whether that task is still waiting for the batch window to end
+    waiting: bool,
+    /// Bumped for every task started, a waiting task that was taken over
+    /// by an immediate write or stopped sees it changed and does nothing
+    batch: u64,
+    /// Bumped for every call that queues writes
+    queued: u64,
+    /// The value of `queued` when the last saved batch was taken,
+    /// every write queued up to then is in indexed db
+    saved: u64,
+    /// Whether the last flush gave up after failing to save
+    failed: bool,
+}
 
 #[derive(Clone)]
 pub struct IndexedDbStorage {
@@ -34,6 +64,9 @@ pub struct IndexedDbStorage {
     /// This is a RwLock because we want to be able to read from it without blocking
     memory: Arc<RwLock<HashMap<String, Value>>>,
     pub(crate) indexed_db: Arc<RwLock<Option<Rexie>>>,
+    /// Writes are batched so bursts of them, like during payments,
+    /// are saved to indexed db in one transaction
+    pending: Arc<RwLock<PendingWrites>>,
     vss: Option<Arc<MutinyVssClient>>,
     logger: Arc<MutinyLogger>,
 }
@@ -56,42 +89,46 @@ impl IndexedDbStorage {
             cipher,
             memory,
             indexed_db,
+            pending: Arc::new(RwLock::new(PendingWrites::default())),
             vss,
             logger,
         })
     }
 
-    async fn save_to_indexed_db(
-        indexed_db: &Arc<RwLock<Option<Rexie>>>,
-        key: &str,
-        data: &Value,
-    ) -> Result<(), MutinyError> {
-        let tx = indexed_db
-            .try_write()
-            .map_err(|e| MutinyError::read_err(e.into()))
-            .and_then(|mut indexed_db_lock| {
-                if let Some(indexed_db) = &mut *indexed_db_lock {
-                    indexed_db
-                        .transaction(&[WALLET_OBJECT_STORE_NAME], TransactionMode::ReadWrite)
-                        .map_err(|e| {
-                            MutinyError::read_err(
-                                anyhow!("Failed to create indexed db transaction: {e}").into(),
-                            )
-                        })
-                } else {
-                    Err(MutinyError::read_err(MutinyStorageError::IndexedDBError))
-                }
-            })?;
+    fn write_transaction(indexed_db: &Rexie) -> Result<Transaction, MutinyError> {
+        indexed_db
+            .transaction(&[WALLET_OBJECT_STORE_NAME], TransactionMode::ReadWrite)
+            .map_err(|e| {
+                MutinyError::write_err(
+                    anyhow!("Failed to create indexed db transaction: {e}").into(),
+                )
+            })
+    }
 
+    /// Saves a batch of writes to indexed db in a single transaction
+    async fn save_batch(
+        tx: Transaction,
+        writes: &HashMap<String, Option<Value>>,
+    ) -> Result<(), MutinyError> {
         let store = tx.store(WALLET_OBJECT_STORE_NAME).map_err(|e| {
-            MutinyError::read_err(anyhow!("Failed to create indexed db store: {e}").into())
+            MutinyError::write_err(anyhow!("Failed to create indexed db store: {e}").into())
         })?;
 
-        // save to indexed db
-        store
-            .put(&JsValue::from_serde(&data)?, Some(&JsValue::from(key)))
-            .await
-            .map_err(|_| MutinyError::write_err(MutinyStorageError::IndexedDBError))?;
+        for (key, value) in writes {
+            let key = JsValue::from(key);
+            match value {
+                Some(value) => {
+                    store
+                        .put(&JsValue::from_serde(value)?, Some(&key))
+                        .await
+                        .map_err(|_| MutinyError::write_err(MutinyStorageError::IndexedDBError))?;
+                }
+                None => store
+                    .delete(&key)
+                    .await
+                    .map_err(|_| MutinyError::write_err(MutinyStorageError::IndexedDBError))?,
+            }
+        }
 
         tx.done()
             .await
@@ -100,50 +137,134 @@ impl IndexedDbStorage {
         Ok(())
     }
 
-    async fn delete_from_indexed_db(
-        indexed_db: &Arc<RwLock<Option<Rexie>>>,
-        keys: &[String],
+    /// Queues writes to be saved to indexed db. They are saved after
+    /// [WRITE_BATCH_WINDOW_MS] unless `immediate` is set, writes queued while
+    /// a batch is waiting are saved with it. An immediate write saves a
+    /// waiting batch right away.
+    fn queue_writes(
+        &self,
+        writes: impl IntoIterator<Item = (String, Option<Value>)>,
+        immediate: bool,
     ) -> Result<(), MutinyError> {
-        let tx = indexed_db
+        let mut pending = self
+            .pending
             .try_write()
-            .map_err(|e| {
-                error!("Failed to acquire indexed db lock: {e}");
-                MutinyError::read_err(e.into())
-            })
-            .and_then(|mut indexed_db_lock| {
-                if let Some(indexed_db) = &mut *indexed_db_lock {
-                    indexed_db
-                        .transaction(&[WALLET_OBJECT_STORE_NAME], TransactionMode::ReadWrite)
-                        .map_err(|e| {
-                            error!("Failed to create indexed db transaction: {e}");
-                            MutinyError::read_err(
-                                anyhow!("Failed to create indexed db transaction: {e}").into(),
-                            )
-                        })
-                } else {
-                    error!("No indexed db instance found");
-                    Err(MutinyError::read_err(MutinyStorageError::IndexedDBError))
+            .map_err(|e| MutinyError::write_err(e.into()))?;
+        pending.writes.extend(writes);
+        pending.queued += 1;
+        if pending.flushing && !(immediate && pending.waiting) {
+            return Ok(());
+        }
+        pending.flushing = true;
+        pending.waiting = !immediate;
+        pending.failed = false;
+        pending.batch += 1;
+        let batch = pending.batch;
+        drop(pending);
+
+        let indexed_db = self.indexed_db.clone();
+        let pending = self.pending.clone();
+        let logger = self.logger.clone();
+        spawn_local(async move {
+            if !immediate {
+                sleep(WRITE_BATCH_WINDOW_MS).await;
+                match pending.try_write() {
+                    Ok(mut pending) if pending.batch == batch => pending.waiting = false,
+                    // taken over by an immediate write, or stopped
+                    Ok(_) => return,
+                    Err(e) => log_error!(logger, "Failed to get pending writes: {e}"),
                 }
-            })?;
+            }
+            Self::flush_pending(&indexed_db, &pending, &logger).await;
+        });
 
-        let store = tx.store(WALLET_OBJECT_STORE_NAME).map_err(|e| {
-            error!("Failed to create indexed db store: {e}");
-            MutinyError::read_err(anyhow!("Failed to create indexed db store {e}").into())
-        })?;
+        Ok(())
+    }
 
-        // delete from indexed db
-        for key in keys {
-            store
-                .delete(&JsValue::from(key))
-                .await
-                .map_err(|_| MutinyError::write_err(MutinyStorageError::IndexedDBError))?;
-        }
+    /// Saves the pending writes until there are none left. Only one flush runs
+    /// at a time, so writes reach indexed db in the order they were made.
+    /// If the database was closed by [IndexedDbStorage::stop] the writes are
+    /// left for it to save.
+    ///
+    /// A batch that fails to save is put back and tried again, unless a newer
+    /// value was written for a key in the meantime. After [MAX_WRITE_RETRIES]
+    /// failures in a row the flush gives up and the writes wait for the next one.
+    async fn flush_pending(
+        indexed_db: &Arc<RwLock<Option<Rexie>>>,
+        pending: &Arc<RwLock<PendingWrites>>,
+        logger: &MutinyLogger,
+    ) {
+        let mut failures = 0;
+        loop {
+            let (writes, queued, tx) = {
+                let mut pending = match pending.try_write() {
+                    Ok(pending) => pending,
+                    // the writes are still good after a panic elsewhere
+                    Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                    // keep flushing, nothing else would save the writes
+                    Err(TryLockError::WouldBlock) => {
+                        log_error!(logger, "Pending writes are locked, retrying");
+                        sleep(WRITE_BATCH_WINDOW_MS).await;
+                        continue;
+                    }
+                };
+                if pending.writes.is_empty() {
+                    pending.saved = pending.queued;
+                    pending.flushing = false;
+                    return;
+                }
 
-        tx.done()
-            .await
-            .map_err(|_| MutinyError::write_err(MutinyStorageError::IndexedDBError))?;
+                let tx = match indexed_db.try_read().as_deref() {
+                    Ok(Some(indexed_db)) => Self::write_transaction(indexed_db),
+                    Ok(None) => {
+                        pending.flushing = false;
+                        return;
+                    }
+                    Err(_) => Err(MutinyError::read_err(MutinyStorageError::IndexedDBError)),
+                };
+                pending.waiting = false;
+                (std::mem::take(&mut pending.writes), pending.queued, tx)
+            };
 
-        Ok(())
+            let res = match tx {
+                Ok(tx) => Self::save_batch(tx, &writes).await,
+                Err(e) => Err(e),
+            };
+            let mut pending = loop {
+                match pending.try_write() {
+                    Ok(pending) => break pending,
+                    Err(TryLockError::Poisoned(e)) => break e.into_inner(),
+                    Err(TryLockError::WouldBlock) => {
+                        log_error!(logger, "Pending writes are locked, retrying");
+                        sleep(WRITE_BATCH_WINDOW_MS).await;
+                    }
+                }
+            };
+            match res {
+                Ok(()) => {
+                    failures = 0;
+                    pending.saved = pending.saved.max(queued);
+                }
+                Err(e) => {
+                    failures += 1;
+                    let keys: Vec<&String> = writes.keys().collect();
+                    log_error!(
+                        logger,
+                        "Failed to save ({keys:?}) to indexed db, attempt {failures}: {e}"
+                    );
+                    for (key, value) in writes {
+                        pending.writes.entry(key).or_insert(value);
+                    }
+                    if failures > MAX_WRITE_RETRIES {
+                        pending.failed = true;
+                        pending.flushing = false;
+                        return;
+                    }
+                    drop(pending);
+                    sleep(WRITE_BATCH_WINDOW_MS * failures as i32).await;
+                }
+            }
+        }
     }
 
     pub(crate) async fn read_all(
@@ -202,6 +323,7 @@ impl IndexedDbStorage {
         log_debug!(logger, "Reading from local storage");
         let local_storage = LocalStorage::raw();
         let length = LocalStorage::length();
+        let mut stale_keys = vec![];
         for index in 0..length {
             let key_opt: Option<String> = local_storage.key(index).unwrap();
 
@@ -212,9 +334,16 @@ impl IndexedDbStorage {
                 if write_to_local_storage(&key) {
                     let value: Value = LocalStorage::get(&key).unwrap();
                     map.set(key, value)?;
+                } else if key.starts_with(MONITOR_UPDATES_PREFIX_KEY) {
+                    // older versions mirrored monitor updates here, they are
+                    // also in indexed db so just free up the space
+                    stale_keys.push(key);
                 }
             }
         }
+        for key in stale_keys {
+            LocalStorage::delete(key);
+        }
 
         match vss {
             None => {
@@ -224,8 +353,25 @@ impl IndexedDbStorage {
             Some(vss) => {
                 log_debug!(logger, "Reading from vss");
                 let keys = vss.list_key_versions(None).await?;
+
+                // monitor updates that are in a monitor saved to vss are not needed anymore
+                let stale = stale_vss_monitor_updates(&keys);
+                if !stale.is_empty() {
+                    log_debug!(
+                        logger,
+                        "Deleting {} stale monitor updates from vss",
+                        stale.len()
+                    );
+                    if let Err(e) = vss.delete_objects(stale.clone()).await {
+                        log_error!(
+                            logger,
+                            "Failed to delete stale monitor updates from vss: {e}"
+                        );
+                    }
+                }
+
                 let mut futs = vec![];
-                for kv in keys {
+                for kv in keys.into_iter().filter(|kv| !stale.contains(kv)) {
                     futs.push(Self::handle_vss_key(kv, vss, &map, logger));
                 }
                 let results = futures::future::join_all(futs).await;
@@ -289,14 +435,31 @@ impl IndexedDbStorage {
                     }
                 }
             }
+            DEVICE_REGISTRY_KEY => {
+                // we can get version from the device registry, so we should compare
+                match current.get_data::<DeviceRegistry>(&kv.key)? {
+                    Some(local) => {
+                        if local.version < kv.version {
+                            let obj = vss.get_object(&kv.key).await?;
+                            if serde_json::from_value::<DeviceRegistry>(obj.value.clone()).is_ok() {
+                                return Ok(Some((kv.key, obj.value)));
+                            }
+                        }
+                    }
+                    None => {
+                        let obj = vss.get_object(&kv.key).await?;
+                        return Ok(Some((kv.key, obj.value)));
+                    }
+                }
+            }
             key => {
                 if key.starts_with(MONITORS_PREFIX_KEY) {
                     // we can get versions from monitors, so we should compare
-                    match current.get::<Vec<u8>>(&kv.key)? {
+                    match current.get::<ChecksummedBytes>(&kv.key)? {
                         Some(bytes) => {
                             // check first byte is 1, then take u64 from next 8 bytes
                             let current_version =
-                                u64::from_be_bytes(bytes[1..9].try_into().unwrap());
+                                u64::from_be_bytes(bytes.data()[1..9].try_into().unwrap());
                             // if the current version is less than the version from vss, then we want to use the vss version
                             if current_version < kv.version as u64 {
                                 let obj = vss.get_object(&kv.key).await?;
@@ -308,6 +471,12 @@ impl IndexedDbStorage {
                             return Ok(Some((kv.key, obj.value)));
                         }
                     }
+                } else if key.starts_with(MONITOR_UPDATES_PREFIX_KEY) {
+                    // monitor updates never change once written, only restore missing ones
+                    if current.get::<ChecksummedBytes>(&kv.key)?.is_none() {
+                        let obj = vss.get_object(&kv.key).await?;
+                        return Ok(Some((kv.key, obj.value)));
+                    }
                 } else if key.starts_with(CHANNEL_MANAGER_KEY) {
                     // we can get versions from channel manager, so we should compare
                     match current.get_data::<VersionedValue>(&kv.key)? {
@@ -388,14 +557,25 @@ fn used_once(key: &str) -> bool {
 /// This is because indexed db is not always reliable.
 ///
 /// We need to do this for the channel manager and channel monitors.
+/// Crash reports are also written here because they are saved from a panic
+/// hook where we can't wait for indexed db.
+///
+/// Monitor updates are not, there can be many of them per channel and they
+/// would fill up the local storage quota.
 fn write_to_local_storage(key: &str) -> bool {
     match key {
         str if str.starts_with(CHANNEL_MANAGER_KEY) => true,
         str if str.starts_with(MONITORS_PREFIX_KEY) => true,
+        CRASH_REPORTS_KEY => true,
         _ => false,
     }
 }
 
+/// Channel state is flushed to indexed db right away instead of being batched.
+fn write_immediately(key: &str) -> bool {
+    write_to_local_storage(key) || key.starts_with(MONITOR_UPDATES_PREFIX_KEY)
+}
+
 impl MutinyStorage for IndexedDbStorage {
     fn password(&self) -> Option<&str> {
         self.password.as_deref()
@@ -418,15 +598,11 @@ impl MutinyStorage for IndexedDbStorage {
             source: MutinyStorageError::SerdeError { source: e },
         })?;
 
-        let indexed_db = self.indexed_db.clone();
-        let key_clone = key.clone();
-        let data_clone = data.clone();
-        let logger = self.logger.clone();
-        spawn_local(async move {
-            if let Err(e) = Self::save_to_indexed_db(&indexed_db, &key_clone, &data_clone).await {
-                log_error!(logger, "Failed to save ({key_clone}) to indexed db: {e}");
-            };
-        });
+        // channel state is saved right away, most of it is also written to local
+        // storage before we return so it is durable before LDK is told it was persisted,
+        // monitor updates are only in indexed db so LDK is told after a flush
+        let immediate = write_immediately(&key);
+        self.queue_writes([(key.clone(), Some(data.clone()))], immediate)?;
 
         // Some values we want to write to local storage as well as indexed db
         if write_to_local_storage(&key) {
@@ -484,17 +660,7 @@ impl MutinyStorage for IndexedDbStorage {
     fn delete(&self, keys: &[impl AsRef<str>]) -> Result<(), MutinyError> {
         let keys: Vec<String> = keys.iter().map(|k| k.as_ref().to_string()).collect();
 
-        let indexed_db = self.indexed_db.clone();
-        let keys_clone = keys.clone();
-        let logger = self.logger.clone();
-        spawn_local(async move {
-            if let Err(e) = Self::delete_from_indexed_db(&indexed_db, &keys_clone).await {
-                log_error!(
-                    logger,
-                    "Failed to delete ({keys_clone:?}) from indexed db: {e}"
-                );
-            }
-        });
+        self.queue_writes(keys.iter().map(|k| (k.clone(), None)), false)?;
 
         let mut map = self
             .memory
@@ -536,7 +702,61 @@ impl MutinyStorage for IndexedDbStorage {
     fn stop(&self) {
         if let Ok(mut indexed_db_lock) = self.indexed_db.try_write() {
             if let Some(indexed_db) = indexed_db_lock.take() {
-                indexed_db.close();
+                let pending = self.pending.clone();
+                let logger = self.logger.clone();
+                spawn_local(async move {
+                    // a flush saving a batch stops after it, wait for it so
+                    // the database isn't closed while it is being written to
+                    while pending
+                        .try_read()
+                        .is_ok_and(|pending| pending.flushing && !pending.waiting)
+                    {
+                        sleep(STOP_POLL_MS).await;
+                    }
+
+                    // save the writes that are still waiting before closing
+                    let writes = pending
+                        .try_write()
+                        .map(|mut pending| {
+                            pending.flushing = false;
+                            pending.waiting = false;
+                            pending.batch += 1;
+                            std::mem::take(&mut pending.writes)
+                        })
+                        .unwrap_or_default();
+                    if !writes.is_empty() {
+                        let res = match Self::write_transaction(&indexed_db) {
+                            Ok(tx) => Self::save_batch(tx, &writes).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = res {
+                            log_error!(logger, "Failed to save writes to indexed db: {e}");
+                        }
+                    }
+                    indexed_db.close();
+                });
+            }
+        }
+    }
+
+    async fn flush(&self) -> Result<(), MutinyError> {
+        let target = {
+            let pending = self
+                .pending
+                .try_read()
+                .map_err(|e| MutinyError::read_err(e.into()))?;
+            pending.queued
+        };
+        // save anything waiting for the batch window right away
+        self.queue_writes([], true)?;
+
+        loop {
+            match self.pending.try_read() {
+                Ok(pending) if pending.saved >= target => return Ok(()),
+                Ok(pending) if !pending.flushing => {
+                    return Err(MutinyError::write_err(MutinyStorageError::IndexedDBError))
+                }
+                _ => sleep(STOP_POLL_MS).await,
             }
         }
     }
@@ -637,9 +857,11 @@ mod tests {
     use crate::utils::sleep;
     use crate::utils::test::log;
     use bip39::Mnemonic;
+    use gloo_storage::{LocalStorage, Storage};
     use mutiny_core::storage::MutinyStorage;
+    use mutiny_core::MONITOR_UPDATES_PREFIX_KEY;
     use mutiny_core::{encrypt::encryption_key_from_pass, logging::MutinyLogger};
-    use serde_json::json;
+    use serde_json::{json, Value};
     use std::str::FromStr;
     use std::sync::Arc;
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
@@ -705,6 +927,82 @@ mod tests {
         IndexedDbStorage::clear().await.unwrap();
     }
 
+    #[test]
+    async fn test_batched_writes() {
+        let test_name = "test_batched_writes";
+        log!("{test_name}");
+
+        let logger = Arc::new(MutinyLogger::default());
+        let storage = IndexedDbStorage::new(None, None, None, logger)
+            .await
+            .unwrap();
+
+        // only the last write of each key in a batch is saved
+        storage.set("test_key", "first").unwrap();
+        storage.set("test_key", "second").unwrap();
+        storage.set("test_key2", "value").unwrap();
+        storage.delete(&["test_key2"]).unwrap();
+
+        // wait for the batch to be persisted
+        sleep(1_000).await;
+        storage.reload_from_indexed_db().await.unwrap();
+        let result: Option<String> = storage.get("test_key").unwrap();
+        assert_eq!(result, Some("second".to_string()));
+        let result: Option<String> = storage.get("test_key2").unwrap();
+        assert_eq!(result, None);
+
+        // clear the storage to clean up
+        IndexedDbStorage::clear().await.unwrap();
+    }
+
+    #[test]
+    async fn test_flush() {
+        let test_name = "test_flush";
+        log!("{test_name}");
+
+        let logger = Arc::new(MutinyLogger::default());
+        let storage = IndexedDbStorage::new(None, None, None, logger)
+            .await
+            .unwrap();
+
+        // nothing to wait for
+        storage.flush().await.unwrap();
+
+        // batched writes are saved without waiting for the batch window
+        storage.set("test_key", "value").unwrap();
+        storage.flush().await.unwrap();
+        storage.reload_from_indexed_db().await.unwrap();
+        let result: Option<String> = storage.get("test_key").unwrap();
+        assert_eq!(result, Some("value".to_string()));
+
+        // clear the storage to clean up
+        IndexedDbStorage::clear().await.unwrap();
+    }
+
+    #[test]
+    async fn test_monitor_updates_not_in_local_storage() {
+        let test_name = "test_monitor_updates_not_in_local_storage";
+        log!("{test_name}");
+
+        let logger = Arc::new(MutinyLogger::default());
+        let storage = IndexedDbStorage::new(None, None, None, logger)
+            .await
+            .unwrap();
+
+        let key = format!("{MONITOR_UPDATES_PREFIX_KEY}test_1");
+        storage.set(&key, vec![1u8, 2, 3]).unwrap();
+        assert!(LocalStorage::get::<Value>(&key).is_err());
+
+        // still saved to indexed db right away
+        sleep(1_000).await;
+        storage.reload_from_indexed_db().await.unwrap();
+        let result: Option<Vec<u8>> = storage.get(&key).unwrap();
+        assert_eq!(result, Some(vec![1, 2, 3]));
+
+        // clear the storage to clean up
+        IndexedDbStorage::clear().await.unwrap();
+    }
+
     #[test]
     async fn test_import() {
         let test_name = "test_import";
@@ -22,27 +22,48 @@ use bitcoin::consensus::deserialize;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::PublicKey;
-use bitcoin::util::bip32::ExtendedPrivKey;
+use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
 use bitcoin::{Address, Network, OutPoint, Transaction, Txid};
+use futures::StreamExt;
 use gloo_utils::format::JsValueSerdeExt;
 use lightning::routing::gossip::NodeId;
 use lightning_invoice::Bolt11Invoice;
 use lnurl::lnurl::LnUrl;
+use mutiny_core::alerts::AlertCondition;
+use mutiny_core::allowlist::PeerAllowlist;
 use mutiny_core::auth::MutinyAuthClient;
+use mutiny_core::channel_policy::ChannelRule;
+use mutiny_core::history_import::ImportSource;
+use mutiny_core::inbound::{InboundHtlcLimits, InboundPolicy};
 use mutiny_core::lnurlauth::AuthManager;
-use mutiny_core::nostr::nwc::SpendingConditions;
+use mutiny_core::nostr::nwc::{NwcScope, SpendingConditions};
+use mutiny_core::payment_metadata::PaymentMetadata;
+use mutiny_core::payment_proof::PaymentProof;
+use mutiny_core::payment_retry::{FeeCap, MppConfig, RetryPolicy};
+use mutiny_core::recurring::{CatchUpPolicy, RecurringPaymentTarget};
 use mutiny_core::redshift::RedshiftManager;
 use mutiny_core::redshift::RedshiftRecipient;
+use mutiny_core::retention::RetentionPolicy;
+use mutiny_core::rollout::{select_endpoint, CanaryEndpoint, RolloutStorage};
 use mutiny_core::scb::EncryptedSCB;
-use mutiny_core::storage::MutinyStorage;
+use mutiny_core::splits::SplitTable;
+use mutiny_core::status::Service;
+use mutiny_core::storage::{MemoryStorage, MutinyStorage};
+use mutiny_core::swap_out::SwapOutPolicy;
+use mutiny_core::sweep::SweepDestination;
 use mutiny_core::vss::MutinyVssClient;
+use mutiny_core::watchtower::{WatchData, Watchtower};
 use mutiny_core::{encrypt::encryption_key_from_pass, generate_seed, nostr::nwc::NwcProfile};
-use mutiny_core::{labels::LabelStorage, nodemanager::NodeManager};
+use mutiny_core::{
+    labels::LabelStorage,
+    nodemanager::{CustomTlv, NodeManager},
+};
 use mutiny_core::{logging::MutinyLogger, nostr::ProfileType};
 use nostr::key::XOnlyPublicKey;
-use nostr::prelude::FromBech32;
+use nostr::prelude::{FromBech32, ToBech32};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{
     collections::HashMap,
     sync::atomic::{AtomicBool, Ordering},
@@ -83,6 +104,11 @@ impl MutinyWallet {
         scorer_url: Option<String>,
         do_not_connect_peers: Option<bool>,
         skip_device_lock: Option<bool>,
+        telemetry_url: Option<String>,
+        validate_gossip: Option<bool>,
+        force_takeover: Option<bool>,
+        sync_parallel_requests: Option<usize>,
+        canary_endpoints: JsValue, /* Option<Vec<CanaryEndpoint>> */
     ) -> Result<MutinyWallet, MutinyJsError> {
         utils::set_panic_hook();
         let logger = Arc::new(MutinyLogger::default());
@@ -118,6 +144,20 @@ impl MutinyWallet {
             },
         };
 
+        let canary_endpoints: Vec<CanaryEndpoint> = canary_endpoints
+            .into_serde::<Option<Vec<CanaryEndpoint>>>()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?
+            .unwrap_or_default();
+
+        // remote storage is needed before the wallet starts, so pick its endpoint here
+        let storage_url = select_endpoint(
+            Service::Storage,
+            storage_url,
+            &canary_endpoints,
+            storage.get_rollout_bucket()?,
+        )
+        .url;
+
         let seed = mnemonic.to_seed("");
         let xprivkey = ExtendedPrivKey::new_master(network, &seed).unwrap();
 
@@ -170,10 +210,47 @@ impl MutinyWallet {
             config = config.with_do_not_connect_peers();
         }
 
+        if let Some(url) = telemetry_url {
+            config = config.with_telemetry_url(url);
+        }
+
+        if let Some(true) = validate_gossip {
+            config = config.with_gossip_validation();
+        }
+
+        if let Some(true) = force_takeover {
+            config = config.with_forced_takeover();
+        }
+
+        if let Some(parallel_requests) = sync_parallel_requests {
+            config = config.with_sync_parallel_requests(parallel_requests);
+        }
+
+        for canary in canary_endpoints {
+            config = config.with_canary_endpoint(canary);
+        }
+
+        let startup_progress = utils::startup_progress();
+        startup_progress.reset();
+        config = config.with_startup_progress(startup_progress);
+
         let inner = mutiny_core::MutinyWallet::new(storage, config).await?;
+
+        let node_manager = inner.node_manager.clone();
+        utils::set_crash_reporter(move |info| {
+            let _ = node_manager.save_crash_report(info);
+        });
+
         Ok(MutinyWallet { mnemonic, inner })
     }
 
+    /// Returns how far along loading the wallet is, can be called while
+    /// the wallet is still being created to show startup progress.
+    #[wasm_bindgen]
+    pub fn get_startup_progress() -> Result<JsValue /* StartupProgressReport */, MutinyJsError> {
+        Ok(JsValue::from_serde(&utils::startup_progress().report())?)
+    }
+
     /// Returns if there is a saved wallet in storage.
     /// This is checked by seeing if a mnemonic seed exists in storage.
     #[wasm_bindgen]
@@ -194,6 +271,24 @@ impl MutinyWallet {
         NodeManager::has_node_manager(storage)
     }
 
+    /// Returns the channel state another device has newer than this one, found when
+    /// creating the wallet failed with a stale channel state error. Show these to the
+    /// user before creating the wallet again with `force_takeover` set.
+    #[wasm_bindgen]
+    pub async fn get_state_conflicts(
+        password: Option<String>,
+    ) -> Result<JsValue /* Vec<StateConflict> */, MutinyJsError> {
+        let logger = Arc::new(MutinyLogger::default());
+        let cipher = password
+            .as_ref()
+            .filter(|p| !p.is_empty())
+            .map(|p| encryption_key_from_pass(p))
+            .transpose()?;
+        let storage = IndexedDbStorage::new(password, cipher, None, logger).await?;
+        let conflicts = NodeManager::get_state_conflicts(storage)?;
+        Ok(JsValue::from_serde(&conflicts)?)
+    }
+
     /// Starts up all the nodes again.
     /// Not needed after [NodeManager]'s `new()` function.
     #[wasm_bindgen]
@@ -225,12 +320,39 @@ impl MutinyWallet {
         self.mnemonic.to_string()
     }
 
+    /// Returns the block height the wallet was created at, if it is known.
+    /// Restoring with it only skips the scan for a wallet with no history yet,
+    /// esplora has no way to limit the scan of an older wallet to its birthday.
+    #[wasm_bindgen]
+    pub fn get_wallet_birthday(&self) -> Result<Option<u32>, MutinyJsError> {
+        Ok(self.inner.node_manager.get_wallet_birthday()?)
+    }
+
+    /// Splits the mnemonic seed into `count` SLIP-39 shares,
+    /// any `threshold` of which can be used to restore the wallet.
+    #[wasm_bindgen]
+    pub fn export_seed_shares(
+        &self,
+        threshold: u8,
+        count: u8,
+    ) -> Result<JsValue /* Vec<String> */, MutinyJsError> {
+        let shares = mutiny_core::slip39::split_mnemonic(&self.mnemonic, threshold, count)?;
+        Ok(JsValue::from_serde(&shares)?)
+    }
+
     /// Returns the network of the wallet.
     #[wasm_bindgen]
     pub fn get_network(&self) -> String {
         self.inner.node_manager.get_network().to_string()
     }
 
+    /// Returns a counter that goes up every time the wallet's state changes.
+    /// Cached data read at an older generation should be refreshed.
+    #[wasm_bindgen]
+    pub fn get_generation(&self) -> u64 {
+        self.inner.node_manager.generation()
+    }
+
     /// Gets a new bitcoin address from the wallet.
     /// Will generate a new address on every call.
     ///
@@ -306,6 +428,9 @@ impl MutinyWallet {
     /// The amount is in satoshis and the fee rate is in sat/vbyte.
     ///
     /// If a fee rate is not provided, one will be used from the fee estimator.
+    ///
+    /// If an idempotency key is given and was already used, the original
+    /// transaction id is returned instead of sending again.
     #[wasm_bindgen]
     pub async fn send_to_address(
         &self,
@@ -313,6 +438,7 @@ impl MutinyWallet {
         amount: u64,
         labels: JsValue, /* Vec<String> */
         fee_rate: Option<f32>,
+        idempotency_key: Option<String>,
     ) -> Result<String, MutinyJsError> {
         let send_to = Address::from_str(&destination_address)?;
         let labels: Vec<String> = labels
@@ -321,7 +447,7 @@ impl MutinyWallet {
         Ok(self
             .inner
             .node_manager
-            .send_to_address(send_to, amount, labels, fee_rate)
+            .send_to_address(send_to, amount, labels, fee_rate, idempotency_key)
             .await?
             .to_string())
     }
@@ -543,8 +669,12 @@ impl MutinyWallet {
     }
 
     /// Creates a lightning invoice. The amount should be in satoshis.
-    /// If no amount is provided, the invoice will be created with no amount.
+    /// If no amount is provided, the invoice will be created with no amount
+    /// and the payer picks how much to send. With an LSP this needs an existing
+    /// channel with it that we can receive over.
     /// If no description is provided, the invoice will be created with no description.
+    /// An order id can be given to link the invoice to an external reference.
+    /// The invoice expires after `expiry_secs`, an hour if not given.
     ///
     /// If the manager has more than one node it will create a phantom invoice.
     /// If there is only one node it will create an invoice just for that node.
@@ -553,6 +683,31 @@ impl MutinyWallet {
         &self,
         amount: Option<u64>,
         labels: JsValue, /* Vec<String> */
+        order_id: Option<String>,
+        expiry_secs: Option<u32>,
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .create_invoice(amount, labels, order_id, expiry_secs)
+            .await?
+            .into())
+    }
+
+    /// Creates a phantom invoice that any of our nodes with a channel can receive,
+    /// so the payment still arrives when one of them is out of inbound capacity.
+    /// The LSP isn't used. An order id can be given to link the invoice to an external
+    /// reference. The invoice expires after `expiry_secs`, an hour if not given.
+    #[wasm_bindgen]
+    pub async fn create_phantom_invoice(
+        &self,
+        amount: Option<u64>,
+        labels: JsValue, /* Vec<String> */
+        order_id: Option<String>,
+        expiry_secs: Option<u32>,
     ) -> Result<MutinyInvoice, MutinyJsError> {
         let labels: Vec<String> = labels
             .into_serde()
@@ -560,37 +715,212 @@ impl MutinyWallet {
         Ok(self
             .inner
             .node_manager
-            .create_invoice(amount, labels)
+            .create_phantom_invoice(amount, labels, order_id, expiry_secs)
             .await?
             .into())
     }
 
+    /// Creates a hold invoice for a payment hash, the payment is held until
+    /// it is settled with the preimage or cancelled.
+    /// The payment has to arrive over an existing channel.
+    #[wasm_bindgen]
+    pub async fn create_hold_invoice(
+        &self,
+        amount: Option<u64>,
+        payment_hash: String,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<JsValue /* HoldInvoice */, MutinyJsError> {
+        let payment_hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .create_hold_invoice(amount, payment_hash, labels)
+                .await?,
+        )?)
+    }
+
+    /// Settles a hold invoice with the hex encoded preimage of its payment hash.
+    #[wasm_bindgen]
+    pub async fn settle_hold_invoice(
+        &self,
+        preimage: String,
+    ) -> Result<JsValue /* HoldInvoice */, MutinyJsError> {
+        let preimage: [u8; 32] =
+            FromHex::from_hex(&preimage).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .settle_hold_invoice(preimage)
+                .await?,
+        )?)
+    }
+
+    /// Cancels a hold invoice, failing its payment back to the payer.
+    #[wasm_bindgen]
+    pub async fn cancel_hold_invoice(
+        &self,
+        payment_hash: String,
+    ) -> Result<JsValue /* HoldInvoice */, MutinyJsError> {
+        let payment_hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .cancel_hold_invoice(payment_hash)
+                .await?,
+        )?)
+    }
+
+    /// Lists all the hold invoices, newest first.
+    #[wasm_bindgen]
+    pub fn list_hold_invoices(&self) -> Result<JsValue /* Vec<HoldInvoice> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_hold_invoices()?,
+        )?)
+    }
+
     /// Pays a lightning invoice from the selected node.
     /// An amount should only be provided if the invoice does not have an amount.
     /// The amount should be in satoshis.
+    ///
+    /// If an idempotency key is given and was already used, the current state
+    /// of the original payment is returned instead of paying again.
+    ///
+    /// The retry policy limits the attempts, how long to keep retrying and the fees paid,
+    /// and how the payment is split over multiple paths. The defaults are used if it is
+    /// undefined.
+    ///
+    /// The metadata, a label, notes and tags, is saved with the payment if given.
     #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
     pub async fn pay_invoice(
         &self,
         from_node: String,
         invoice_str: String,
         amt_sats: Option<u64>,
         labels: JsValue, /* Vec<String> */
+        idempotency_key: Option<String>,
+        retry_policy: JsValue, /* Option<RetryPolicy> */
+        metadata: JsValue,     /* Option<PaymentMetadata> */
     ) -> Result<MutinyInvoice, MutinyJsError> {
         let from_node = PublicKey::from_str(&from_node)?;
         let invoice = Bolt11Invoice::from_str(&invoice_str)?;
         let labels: Vec<String> = labels
             .into_serde()
             .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let retry_policy: Option<RetryPolicy> = retry_policy
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let metadata: Option<PaymentMetadata> = metadata
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .node_manager
-            .pay_invoice(&from_node, &invoice, amt_sats, labels)
+            .pay_invoice(
+                &from_node,
+                &invoice,
+                amt_sats,
+                labels,
+                idempotency_key,
+                retry_policy,
+                metadata,
+            )
             .await?
             .into())
     }
 
+    /// Pays a batch of lightning invoices concurrently across the available
+    /// outbound liquidity. Invoices must have an amount.
+    ///
+    /// Returns a result for every invoice in the order they were given.
+    ///
+    /// The retry policy is used for every invoice, the defaults are used if it
+    /// is undefined.
+    #[wasm_bindgen]
+    pub async fn pay_invoices(
+        &self,
+        invoices: JsValue,     /* Vec<String> */
+        labels: JsValue,       /* Vec<String> */
+        retry_policy: JsValue, /* Option<RetryPolicy> */
+    ) -> Result<JsValue /* Vec<BatchPaymentResult> */, MutinyJsError> {
+        let invoices: Vec<String> = invoices
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let retry_policy: Option<RetryPolicy> = retry_policy
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .pay_invoices(invoices, labels, retry_policy)
+                .await?,
+        )?)
+    }
+
+    /// Saves a split table of recipients and their percentage shares,
+    /// replacing any with the same id. The shares must add up to 100.
+    #[wasm_bindgen]
+    pub fn save_split(&self, split: JsValue /* SplitTable */) -> Result<(), MutinyJsError> {
+        let split: SplitTable = split
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.node_manager.save_split(split)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn list_splits(&self) -> Result<JsValue /* Vec<SplitTable> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_splits()?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn delete_split(&self, id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.delete_split(&id)?)
+    }
+
+    /// Pays a total amount split between the recipients of a split table.
+    ///
+    /// Returns a result for every recipient, so recipients that failed can be retried.
+    #[wasm_bindgen]
+    pub async fn pay_split(
+        &self,
+        from_node: String,
+        split_id: String,
+        total_amount_sats: u64,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<JsValue /* Vec<SplitPaymentResult> */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .pay_split(&from_node, &split_id, total_amount_sats, labels)
+                .await?,
+        )?)
+    }
+
     /// Sends a spontaneous payment to a node from the selected node.
     /// The amount should be in satoshis.
+    ///
+    /// If an idempotency key is given and was already used, the current state
+    /// of the original payment is returned instead of paying again.
+    ///
+    /// Custom TLV records, such as podcast boost metadata, are sent with the
+    /// payment if given. Keys must be 65536 or above.
     #[wasm_bindgen]
     pub async fn keysend(
         &self,
@@ -598,33 +928,115 @@ impl MutinyWallet {
         to_node: String,
         amt_sats: u64,
         labels: JsValue, /* Vec<String> */
+        idempotency_key: Option<String>,
+        custom_tlvs: JsValue, /* Option<Vec<CustomTlv>> */
     ) -> Result<MutinyInvoice, MutinyJsError> {
         let from_node = PublicKey::from_str(&from_node)?;
         let to_node = PublicKey::from_str(&to_node)?;
         let labels: Vec<String> = labels
             .into_serde()
             .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let custom_tlvs: Option<Vec<CustomTlv>> = custom_tlvs
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .node_manager
-            .keysend(&from_node, to_node, amt_sats, labels)
+            .keysend(
+                &from_node,
+                to_node,
+                amt_sats,
+                labels,
+                idempotency_key,
+                custom_tlvs.unwrap_or_default(),
+            )
             .await?
             .into())
     }
 
-    /// Decodes a lightning invoice into useful information.
-    /// Will return an error if the invoice is for a different network.
+    /// Moves funds from one of our nodes to another over lightning.
+    /// The amount should be in satoshis.
     #[wasm_bindgen]
-    pub async fn decode_invoice(&self, invoice: String) -> Result<MutinyInvoice, MutinyJsError> {
-        let invoice = Bolt11Invoice::from_str(&invoice)?;
+    pub async fn transfer_between_nodes(
+        &self,
+        from_node: String,
+        to_node: String,
+        amt_sats: u64,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let to_node = PublicKey::from_str(&to_node)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .node_manager
-            .decode_invoice(invoice)
+            .transfer_between_nodes(&from_node, &to_node, amt_sats, labels)
             .await?
             .into())
     }
 
+    /// Probes a route to a node from the selected node without paying anything.
+    /// Returns the estimated fee and the probability that a payment of
+    /// the amount succeeds, which is useful to check before a large payment.
+    #[wasm_bindgen]
+    pub async fn probe_route(
+        &self,
+        from_node: String,
+        destination: String,
+        amt_sats: u64,
+    ) -> Result<JsValue /* ProbeResult */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let destination = PublicKey::from_str(&destination)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .probe_route(&from_node, destination, amt_sats)
+                .await?,
+        )?)
+    }
+
+    /// Estimates paying an invoice with the given retry policy without sending anything.
+    /// Returns the fee, the chance it succeeds and the most blocks our funds could be
+    /// locked up for, so the tradeoff of a lower `max_total_cltv_expiry_delta` can be shown.
+    #[wasm_bindgen]
+    pub async fn estimate_payment(
+        &self,
+        from_node: String,
+        invoice_str: String,
+        amt_sats: Option<u64>,
+        retry_policy: JsValue, /* Option<RetryPolicy> */
+    ) -> Result<JsValue /* PaymentEstimate */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let invoice = Bolt11Invoice::from_str(&invoice_str)?;
+        let retry_policy: Option<RetryPolicy> = retry_policy
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .estimate_payment(&from_node, &invoice, amt_sats, retry_policy)
+                .await?,
+        )?)
+    }
+
+    /// Decodes a lightning invoice into its amount, description, payee,
+    /// expiry, route hints and features.
+    /// Will return an error if the invoice is for a different network.
+    #[wasm_bindgen]
+    pub async fn decode_invoice(
+        &self,
+        invoice: String,
+    ) -> Result<JsValue /* DecodedInvoice */, MutinyJsError> {
+        let invoice = Bolt11Invoice::from_str(&invoice)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.decode_invoice(invoice).await?,
+        )?)
+    }
+
     /// Calls upon a LNURL to get the parameters for it.
     /// This contains what kind of LNURL it is (pay, withdrawal, auth, etc).
     #[wasm_bindgen]
@@ -642,13 +1054,17 @@ impl MutinyWallet {
         lnurl: String,
         amount_sats: u64,
         zap_npub: Option<String>,
-        labels: JsValue, /* Vec<String> */
+        labels: JsValue,   /* Vec<String> */
+        metadata: JsValue, /* Option<PaymentMetadata> */
     ) -> Result<MutinyInvoice, MutinyJsError> {
         let from_node = PublicKey::from_str(&from_node)?;
         let lnurl = LnUrl::from_str(&lnurl)?;
         let labels: Vec<String> = labels
             .into_serde()
             .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let metadata: Option<PaymentMetadata> = metadata
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
 
         let zap_npub = match zap_npub.filter(|z| !z.is_empty()) {
             Some(z) => {
@@ -660,7 +1076,30 @@ impl MutinyWallet {
         Ok(self
             .inner
             .node_manager
-            .lnurl_pay(&from_node, &lnurl, amount_sats, zap_npub, labels)
+            .lnurl_pay(&from_node, &lnurl, amount_sats, zap_npub, labels, metadata)
+            .await?
+            .into())
+    }
+
+    /// Pays a LNURL-pay endpoint or a lightning address, like bob@example.com.
+    /// The amount should be in satoshis.
+    #[wasm_bindgen]
+    pub async fn pay_lnurl(
+        &self,
+        from_node: String,
+        lnurl_or_address: String,
+        amount_sats: u64,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        Ok(self
+            .inner
+            .node_manager
+            .pay_lnurl(&from_node, &lnurl_or_address, amount_sats, labels)
             .await?
             .into())
     }
@@ -681,23 +1120,154 @@ impl MutinyWallet {
             .await?)
     }
 
-    /// Authenticates with a LNURL-auth for the given profile.
+    /// Lists the LNURL withdrawals we have made, newest first, so the status
+    /// of a pending withdrawal can be polled.
     #[wasm_bindgen]
-    pub async fn lnurl_auth(&self, lnurl: String) -> Result<(), MutinyJsError> {
+    pub async fn list_lnurl_withdrawals(
+        &self,
+    ) -> Result<JsValue /* Vec<LnUrlWithdrawal> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_lnurl_withdrawals().await?,
+        )?)
+    }
+
+    /// Calls upon a LNURL-channel and asks the service to open a channel to the selected node.
+    /// Returns the request, which is pending until the service opens the channel.
+    #[wasm_bindgen]
+    pub async fn lnurl_channel(
+        &self,
+        self_node_pubkey: String,
+        lnurl: String,
+        private: bool,
+    ) -> Result<JsValue /* LnUrlChannelRequest */, MutinyJsError> {
+        let self_node_pubkey = PublicKey::from_str(&self_node_pubkey)?;
         let lnurl = LnUrl::from_str(&lnurl)?;
-        Ok(self.inner.node_manager.lnurl_auth(lnurl).await?)
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .lnurl_channel(&self_node_pubkey, &lnurl, private)
+                .await?,
+        )?)
     }
 
-    /// Gets an invoice from the node manager.
-    /// This includes sent and received invoices.
+    /// Lists the channels we have requested from LNURL-channel services, newest first,
+    /// with the service each channel came from.
     #[wasm_bindgen]
-    pub async fn get_invoice(&self, invoice: String) -> Result<MutinyInvoice, MutinyJsError> {
-        let invoice = Bolt11Invoice::from_str(&invoice)?;
-        Ok(self.inner.node_manager.get_invoice(&invoice).await?.into())
+    pub fn list_lnurl_channels(
+        &self,
+    ) -> Result<JsValue /* Vec<LnUrlChannelRequest> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_lnurl_channels()?,
+        )?)
     }
 
-    /// Gets an invoice from the node manager.
-    /// This includes sent and received invoices.
+    /// Authenticates with a LNURL-auth for the given profile.
+    #[wasm_bindgen]
+    pub async fn lnurl_auth(&self, lnurl: String) -> Result<(), MutinyJsError> {
+        let lnurl = LnUrl::from_str(&lnurl)?;
+        Ok(self.inner.node_manager.lnurl_auth(lnurl).await?)
+    }
+
+    /// The public key the backend service at this url authenticates the wallet by.
+    /// It is the same for every url on the same host.
+    #[wasm_bindgen]
+    pub fn get_service_pubkey(&self, url: String) -> Result<String, MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .get_service_pubkey(&url)?
+            .to_string())
+    }
+
+    /// Parses a scanned or opened URI and returns what to do with it.
+    /// Understands BIP 21, invoices, LNURLs, lightning addresses,
+    /// nostr wallet connect and Mutiny deep links.
+    #[wasm_bindgen]
+    pub async fn handle_uri(&self, uri: String) -> Result<JsValue /* UriIntent */, MutinyJsError> {
+        let intent = self.inner.node_manager.handle_uri(&uri).await?;
+        Ok(JsValue::from_serde(&intent)?)
+    }
+
+    /// Parses a BOLT 11 invoice, BOLT 12 offer, LNURL, lightning address,
+    /// on-chain address or BIP 21 URI into what it asks to be paid.
+    #[wasm_bindgen]
+    pub fn parse_payment_request(
+        &self,
+        request: String,
+    ) -> Result<JsValue /* PaymentRequest */, MutinyJsError> {
+        let request = self.inner.node_manager.parse_payment_request(&request)?;
+        Ok(JsValue::from_serde(&request)?)
+    }
+
+    /// Pays any payment request, preferring lightning and paying on-chain
+    /// if a unified BIP 21 URI could not be paid over lightning.
+    /// The amount is only used if the request doesn't have one.
+    #[wasm_bindgen]
+    pub async fn pay_unified(
+        &self,
+        from_node: String,
+        request: String,
+        amount_sats: Option<u64>,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<JsValue /* UnifiedPayment */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        let payment = self
+            .inner
+            .node_manager
+            .pay_unified(&from_node, &request, amount_sats, labels)
+            .await?;
+        Ok(JsValue::from_serde(&payment)?)
+    }
+
+    /// Gets the lifecycle of each HTLC of a payment, such as which channel it
+    /// went through and when it was committed, fulfilled, or failed.
+    #[wasm_bindgen]
+    pub async fn get_payment_htlcs(
+        &self,
+        payment_hash: String,
+    ) -> Result<JsValue /* Vec<PaymentHtlc> */, MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_payment_htlcs(&hash).await?,
+        )?)
+    }
+
+    /// Calls `callback` with every state change of a payment, starting with its
+    /// current state, until the payment is settled or failed.
+    #[wasm_bindgen]
+    pub async fn subscribe_payment(
+        &self,
+        payment_hash: String,
+        callback: js_sys::Function,
+    ) -> Result<(), MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        let mut updates = self.inner.node_manager.subscribe_payment(&hash).await?;
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(update) = updates.next().await {
+                let Ok(value) = JsValue::from_serde(&update) else {
+                    continue;
+                };
+                let _ = callback.call1(&JsValue::NULL, &value);
+            }
+        });
+        Ok(())
+    }
+
+    /// Gets an invoice from the node manager.
+    /// This includes sent and received invoices.
+    #[wasm_bindgen]
+    pub async fn get_invoice(&self, invoice: String) -> Result<MutinyInvoice, MutinyJsError> {
+        let invoice = Bolt11Invoice::from_str(&invoice)?;
+        Ok(self.inner.node_manager.get_invoice(&invoice).await?.into())
+    }
+
+    /// Gets an invoice from the node manager.
+    /// This includes sent and received invoices.
     #[wasm_bindgen]
     pub async fn get_invoice_by_hash(&self, hash: String) -> Result<MutinyInvoice, MutinyJsError> {
         let hash: sha256::Hash = sha256::Hash::from_str(&hash)?;
@@ -709,6 +1279,26 @@ impl MutinyWallet {
             .into())
     }
 
+    /// Sets the label, payer note, comment and tags of a lightning payment,
+    /// replacing what it had. Empty metadata clears them.
+    #[wasm_bindgen]
+    pub async fn label_payment(
+        &self,
+        hash: String,
+        metadata: JsValue, /* PaymentMetadata */
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&hash)?;
+        let metadata: PaymentMetadata = metadata
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .label_payment(&hash, metadata)
+            .await?
+            .into())
+    }
+
     /// Gets an invoice from the node manager.
     /// This includes sent and received invoices.
     #[wasm_bindgen]
@@ -801,6 +1391,76 @@ impl MutinyWallet {
             .into())
     }
 
+    /// The largest channel we can open with our entire on-chain balance,
+    /// the balance minus the on-chain fee to open it.
+    #[wasm_bindgen]
+    pub fn max_channel_amount(&self) -> Result<u64, MutinyJsError> {
+        Ok(self.inner.node_manager.max_channel_amount()?)
+    }
+
+    /// Opens a single channel with our entire on-chain balance.
+    #[wasm_bindgen]
+    pub async fn open_channel_max(
+        &self,
+        from_node: String,
+        to_pubkey: Option<String>,
+    ) -> Result<MutinyChannel, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+
+        let to_pubkey = match to_pubkey {
+            Some(pubkey_str) if !pubkey_str.trim().is_empty() => {
+                Some(PublicKey::from_str(&pubkey_str)?)
+            }
+            _ => None,
+        };
+
+        Ok(self
+            .inner
+            .node_manager
+            .open_channel_max(&from_node, to_pubkey)
+            .await?
+            .into())
+    }
+
+    /// Starts a channel open paid for from another wallet.
+    ///
+    /// Returns the address and amount (channel size plus the on-chain fee) the
+    /// other wallet has to pay. The channel opens once the payment confirms.
+    #[wasm_bindgen]
+    pub async fn open_channel_external(
+        &self,
+        from_node: String,
+        to_pubkey: Option<String>,
+        amount: u64,
+    ) -> Result<JsValue /* ExternalFunding */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+
+        let to_pubkey = match to_pubkey {
+            Some(pubkey_str) if !pubkey_str.trim().is_empty() => {
+                Some(PublicKey::from_str(&pubkey_str)?)
+            }
+            _ => None,
+        };
+
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .open_channel_external(&from_node, to_pubkey, amount)
+                .await?,
+        )?)
+    }
+
+    /// Lists the channel opens paid for from other wallets and their status.
+    #[wasm_bindgen]
+    pub fn list_external_fundings(
+        &self,
+    ) -> Result<JsValue /* Vec<ExternalFunding> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_external_fundings()?,
+        )?)
+    }
+
     /// Closes a channel with the given outpoint.
     ///
     /// If force is true, the channel will be force closed.
@@ -810,250 +1470,1224 @@ impl MutinyWallet {
     /// This should only be used if the channel will never actually be opened.
     ///
     /// If both force and abandon are true, an error will be returned.
+    ///
+    /// Our funds are sent to the given address, or to the
+    /// configured sweep destination if one is not given.
     #[wasm_bindgen]
     pub async fn close_channel(
         &self,
         outpoint: String,
         force: bool,
         abandon: bool,
+        address: Option<String>,
     ) -> Result<(), MutinyJsError> {
         let outpoint: OutPoint =
             OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let address = address.map(|a| Address::from_str(&a)).transpose()?;
         Ok(self
             .inner
             .node_manager
-            .close_channel(&outpoint, None, force, abandon)
+            .close_channel(&outpoint, address, force, abandon)
             .await?)
     }
 
-    /// Lists all the channels for all the nodes in the node manager.
-    #[wasm_bindgen]
-    pub async fn list_channels(&self) -> Result<JsValue /* Vec<MutinyChannel> */, MutinyJsError> {
-        Ok(JsValue::from_serde(
-            &self.inner.node_manager.list_channels().await?,
-        )?)
-    }
-
-    /// Takes an encrypted static channel backup and recovers the channels from it.
-    /// If the backup is encrypted with a different key than the current key, it will fail.
+    /// Sets where the funds from closed channels are sent by default.
+    ///
+    /// Either an address to always send to, or an xpub to derive a fresh
+    /// address from each time. If neither is given, funds go to this wallet.
     #[wasm_bindgen]
-    pub async fn recover_from_static_channel_backup(
+    pub async fn set_sweep_destination(
         &self,
-        scb: String,
+        address: Option<String>,
+        xpub: Option<String>,
     ) -> Result<(), MutinyJsError> {
-        let scb = EncryptedSCB::from_str(&scb).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
-        self.inner
+        let destination = match (address, xpub) {
+            (None, None) => SweepDestination::Internal,
+            (Some(address), None) => SweepDestination::Address(Address::from_str(&address)?),
+            (None, Some(xpub)) => SweepDestination::Xpub {
+                xpub: ExtendedPubKey::from_str(&xpub)
+                    .map_err(|_| MutinyJsError::InvalidArgumentsError)?,
+                next_index: 0,
+            },
+            (Some(_), Some(_)) => return Err(MutinyJsError::InvalidArgumentsError),
+        };
+        Ok(self
+            .inner
             .node_manager
-            .recover_from_static_channel_backup(scb)
-            .await?;
-        Ok(())
+            .set_sweep_destination(destination)
+            .await?)
     }
 
-    /// Creates a static channel backup for all the nodes in the node manager.
-    /// The backup is encrypted with the SCB key.
+    /// Gets where the funds from closed channels are sent by default.
     #[wasm_bindgen]
-    pub async fn create_static_channel_backup(&self) -> Result<String, MutinyJsError> {
-        let scb = self
-            .inner
-            .node_manager
-            .create_static_channel_backup()
-            .await?;
-        Ok(scb.to_string())
+    pub fn get_sweep_destination(&self) -> Result<JsValue /* SweepDestination */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_sweep_destination()?,
+        )?)
     }
 
-    /// Lists all the peers for all the nodes in the node manager.
+    /// Schedules a cooperative close of the channel with the given outpoint.
+    ///
+    /// The channel is closed once the normal priority fee rate drops to
+    /// `at_fee_below` (in sat/vbyte) between `not_before` and `deadline`
+    /// (unix timestamps). At the deadline it is closed regardless of fees.
     #[wasm_bindgen]
-    pub async fn list_peers(&self) -> Result<JsValue /* Vec<MutinyPeer> */, MutinyJsError> {
+    pub async fn schedule_close(
+        &self,
+        outpoint: String,
+        at_fee_below: Option<f32>,
+        not_before: Option<u64>,
+        deadline: Option<u64>,
+        address: Option<String>,
+    ) -> Result<JsValue /* ScheduledClose */, MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let address = address.map(|a| Address::from_str(&a)).transpose()?;
         Ok(JsValue::from_serde(
-            &self.inner.node_manager.list_peers().await?,
+            &self
+                .inner
+                .node_manager
+                .schedule_close(outpoint, at_fee_below, not_before, deadline, address)
+                .await?,
         )?)
     }
 
-    /// Returns all the on-chain and lightning activity from the wallet.
+    /// Cancels a scheduled close for the channel with the given outpoint.
     #[wasm_bindgen]
-    pub async fn get_activity(&self) -> Result<JsValue /* Vec<ActivityItem> */, MutinyJsError> {
-        // get activity from the node manager
-        let activity = self.inner.node_manager.get_activity().await?;
-        let mut activity: Vec<ActivityItem> = activity.into_iter().map(|a| a.into()).collect();
-
-        // add contacts to the activity
-        let contacts = self.inner.node_manager.get_contacts()?;
-        for a in activity.iter_mut() {
-            // find labels that have a contact and add them to the item
-            for label in a.labels.iter() {
-                if let Some(contact) = contacts.get(label) {
-                    a.contacts.push(Contact::from(contact.clone()));
-                }
-            }
-            // remove labels that have a contact to prevent duplicates
-            a.labels.retain(|l| !contacts.contains_key(l));
-        }
+    pub fn cancel_scheduled_close(&self, outpoint: String) -> Result<(), MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.node_manager.cancel_scheduled_close(&outpoint)?)
+    }
 
-        Ok(JsValue::from_serde(&activity)?)
+    /// Lists the channel closes that are waiting to happen.
+    #[wasm_bindgen]
+    pub fn list_scheduled_closes(
+        &self,
+    ) -> Result<JsValue /* Vec<ScheduledClose> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_scheduled_closes()?,
+        )?)
     }
 
-    /// Initiates a redshift
+    /// Replaces the channel rules, which are run on every channel in the background.
+    /// An empty list turns the rules off.
     #[wasm_bindgen]
-    pub async fn init_redshift(
+    pub fn set_channel_rules(
         &self,
-        outpoint: String,
-        lightning_recipient_pubkey: Option<String>,
-        lightning_recipient_connection_string: Option<String>,
-        onchain_recipient: Option<String>,
-    ) -> Result<Redshift, MutinyJsError> {
-        let outpoint: OutPoint =
-            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
-        let introduction_node = match lightning_recipient_pubkey.clone() {
-            Some(p) => Some(PublicKey::from_str(&p)?),
-            None => None,
-        };
-        let redshift_recipient = match (lightning_recipient_pubkey, onchain_recipient) {
-            (Some(_), Some(_)) => {
-                return Err(MutinyJsError::InvalidArgumentsError);
-            }
-            (Some(l), None) => {
-                let l = PublicKey::from_str(&l)?;
-                RedshiftRecipient::Lightning(l)
-            }
-            (None, Some(o)) => {
-                let o = Address::from_str(&o)?;
-                RedshiftRecipient::OnChain(Some(o))
-            }
-            (None, None) => RedshiftRecipient::OnChain(None),
-        };
-        Ok(self
-            .inner
-            .node_manager
-            .init_redshift(
-                outpoint,
-                redshift_recipient,
-                introduction_node,
-                lightning_recipient_connection_string.as_deref(),
-            )
-            .await?
-            .into())
+        rules: JsValue, /* Vec<ChannelRule> */
+    ) -> Result<(), MutinyJsError> {
+        let rules: Vec<ChannelRule> = rules
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.node_manager.set_channel_rules(rules)?)
     }
 
-    /// Get all redshift attempts for a given utxo
     #[wasm_bindgen]
-    pub fn get_redshift(&self, id: String) -> Result<Option<Redshift>, MutinyJsError> {
-        let id: [u8; 16] =
-            FromHex::from_hex(&id).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
-        Ok(self.inner.node_manager.get_redshift(&id)?.map(|r| r.into()))
+    pub fn list_channel_rules(&self) -> Result<JsValue /* Vec<ChannelRule> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_channel_rules()?,
+        )?)
     }
 
-    pub fn get_address_labels(
+    /// Shows which channels the rules would act on right now without doing anything.
+    /// Previews the saved rules if none are given.
+    #[wasm_bindgen]
+    pub async fn preview_channel_rules(
         &self,
-    ) -> Result<JsValue /* Map<Address, Vec<String>> */, MutinyJsError> {
+        rules: JsValue, /* Option<Vec<ChannelRule>> */
+    ) -> Result<JsValue /* Vec<RuleMatch> */, MutinyJsError> {
+        let rules: Option<Vec<ChannelRule>> = rules
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(JsValue::from_serde(
-            &self.inner.node_manager.get_address_labels()?,
+            &self.inner.node_manager.preview_channel_rules(rules).await?,
         )?)
     }
 
-    /// Set the labels for an address, replacing any existing labels
-    /// If you want to do not want to replace any existing labels, use `get_address_labels` to get the existing labels,
-    /// add the new labels, and then use `set_address_labels` to set the new labels
-    pub fn set_address_labels(
+    /// The actions the channel rules have taken, newest first.
+    #[wasm_bindgen]
+    pub fn get_channel_rule_log(&self) -> Result<JsValue /* Vec<RuleExecution> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_channel_rule_log()?,
+        )?)
+    }
+
+    /// Creates a payment made every `interval_secs` while the wallet is running.
+    /// `to` is a node pubkey to keysend to, a lightning address or a LNURL.
+    ///
+    /// The catch-up policy decides what happens to intervals missed while the
+    /// wallet wasn't running, missed intervals are skipped if it is undefined.
+    #[wasm_bindgen]
+    pub fn create_recurring_payment(
+        &self,
+        to: String,
+        amount_sats: u64,
+        interval_secs: u64,
+        start: Option<u64>,
+        catch_up: JsValue, /* Option<CatchUpPolicy> */
+        labels: JsValue,   /* Vec<String> */
+    ) -> Result<JsValue /* RecurringPayment */, MutinyJsError> {
+        let target = RecurringPaymentTarget::from_str(&to)?;
+        let catch_up: Option<CatchUpPolicy> = catch_up
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.create_recurring_payment(
+                target,
+                amount_sats,
+                interval_secs,
+                start,
+                catch_up.unwrap_or_default(),
+                labels,
+            )?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn cancel_recurring_payment(&self, id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.cancel_recurring_payment(&id)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn list_recurring_payments(
+        &self,
+    ) -> Result<JsValue /* Vec<RecurringPayment> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_recurring_payments()?,
+        )?)
+    }
+
+    /// Moves lightning funds above `ceiling_sats` to the cold storage address
+    /// by cooperatively closing channels. With `require_confirmation` each
+    /// swap out waits for [MutinyWallet::confirm_swap_out], without it only the
+    /// ones that close a channel with far more than the excess do.
+    #[wasm_bindgen]
+    pub fn set_swap_out_policy(
+        &self,
+        ceiling_sats: u64,
+        address: String,
+        require_confirmation: bool,
+    ) -> Result<(), MutinyJsError> {
+        let address = Address::from_str(&address)?;
+        Ok(self
+            .inner
+            .node_manager
+            .set_swap_out_policy(Some(SwapOutPolicy {
+                ceiling_sats,
+                address,
+                require_confirmation,
+            }))?)
+    }
+
+    /// Turns off the swap out policy.
+    #[wasm_bindgen]
+    pub fn disable_swap_out(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.set_swap_out_policy(None)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_swap_out_policy(
+        &self,
+    ) -> Result<JsValue /* Option<SwapOutPolicy> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_swap_out_policy()?,
+        )?)
+    }
+
+    /// Lists every time the swap out policy moved funds or asked to.
+    #[wasm_bindgen]
+    pub fn list_swap_outs(&self) -> Result<JsValue /* Vec<SwapOut> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_swap_outs()?,
+        )?)
+    }
+
+    /// Confirms the swap out waiting on the channel with the given outpoint.
+    #[wasm_bindgen]
+    pub async fn confirm_swap_out(
+        &self,
+        outpoint: String,
+    ) -> Result<JsValue /* SwapOut */, MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.confirm_swap_out(&outpoint).await?,
+        )?)
+    }
+
+    /// Declines the swap out waiting on the channel with the given outpoint.
+    #[wasm_bindgen]
+    pub fn decline_swap_out(&self, outpoint: String) -> Result<(), MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.node_manager.decline_swap_out(&outpoint)?)
+    }
+
+    /// Lists all the channels for all the nodes in the node manager.
+    #[wasm_bindgen]
+    pub async fn list_channels(&self) -> Result<JsValue /* Vec<MutinyChannel> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_channels().await?,
+        )?)
+    }
+
+    /// Returns every recorded change to the wallet's balances, oldest first,
+    /// each tagged with the reason it happened.
+    #[wasm_bindgen]
+    pub fn get_balance_changes(&self) -> Result<JsValue /* Vec<BalanceChange> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_balance_changes()?,
+        )?)
+    }
+
+    /// Returns the outputs from force closed channels that are not claimable yet,
+    /// with the block heights they become claimable at and the current chain tip.
+    #[wasm_bindgen]
+    pub async fn get_pending_close_status(
+        &self,
+    ) -> Result<JsValue /* PendingCloseStatus */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_pending_close_status().await?,
+        )?)
+    }
+
+    /// Returns the internal state of a channel for debugging.
+    /// The channel can be given by its channel id or user channel id, in hex.
+    #[wasm_bindgen]
+    pub async fn debug_channel(
+        &self,
+        channel_id: String,
+    ) -> Result<JsValue /* ChannelDebugInfo */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.debug_channel(&channel_id).await?,
+        )?)
+    }
+
+    /// Takes an encrypted static channel backup and recovers the channels from it.
+    /// If the backup is encrypted with a different key than the current key, it will fail.
+    #[wasm_bindgen]
+    pub async fn recover_from_static_channel_backup(
+        &self,
+        scb: String,
+    ) -> Result<(), MutinyJsError> {
+        let scb = EncryptedSCB::from_str(&scb).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        self.inner
+            .node_manager
+            .recover_from_static_channel_backup(scb)
+            .await?;
+        Ok(())
+    }
+
+    /// Creates a static channel backup for all the nodes in the node manager.
+    /// The backup is encrypted with the SCB key.
+    #[wasm_bindgen]
+    pub async fn create_static_channel_backup(&self) -> Result<String, MutinyJsError> {
+        let scb = self
+            .inner
+            .node_manager
+            .create_static_channel_backup()
+            .await?;
+        Ok(scb.to_string())
+    }
+
+    /// Returns the connection string other nodes can use to reach the given node,
+    /// along with a payload to show as a QR code.
+    #[wasm_bindgen]
+    pub async fn get_connection_info(
+        &self,
+        from_node: String,
+    ) -> Result<JsValue /* ConnectionInfo */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .get_connection_info(&from_node)
+                .await?,
+        )?)
+    }
+
+    /// Sets which channels other nodes can open to us.
+    /// If `allowed_peers` is set, only channels from those pubkeys are accepted.
+    #[wasm_bindgen]
+    pub fn set_inbound_policy(
+        &self,
+        accept_channels: bool,
+        min_channel_size_sats: Option<u64>,
+        allowed_peers: JsValue, /* Option<Vec<String>> */
+    ) -> Result<(), MutinyJsError> {
+        let allowed_peers: Option<Vec<String>> = allowed_peers
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let allowed_peers = allowed_peers
+            .map(|peers| {
+                peers
+                    .iter()
+                    .map(|p| PublicKey::from_str(p))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        Ok(self.inner.node_manager.set_inbound_policy(InboundPolicy {
+            accept_channels,
+            min_channel_size_sats,
+            allowed_peers,
+        })?)
+    }
+
+    /// Gets which channels other nodes can open to us.
+    #[wasm_bindgen]
+    pub fn get_inbound_policy(&self) -> Result<JsValue /* InboundPolicy */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inbound_policy()?,
+        )?)
+    }
+
+    /// Sets the limits on payments to us: the smallest payment in msats, the most
+    /// unresolved payments a peer can have to us, and whether to reject dust payments.
+    /// Rejecting dust means payments under 354 sats, such as small zaps, can't be received.
+    ///
+    /// The HTLC limit is also put in the handshake of new channels. Existing channels
+    /// don't get that, and channels opened to us only get it after a restart.
+    #[wasm_bindgen]
+    pub fn set_inbound_htlc_limits(
+        &self,
+        min_payment_msat: Option<u64>,
+        max_accepted_htlcs: Option<u16>,
+        reject_dust_htlcs: bool,
+    ) -> Result<(), MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .set_inbound_htlc_limits(InboundHtlcLimits {
+                min_payment_msat,
+                max_accepted_htlcs,
+                reject_dust_htlcs,
+            })?)
+    }
+
+    /// Gets the limits on payments to us.
+    #[wasm_bindgen]
+    pub fn get_inbound_htlc_limits(
+        &self,
+    ) -> Result<JsValue /* InboundHtlcLimits */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inbound_htlc_limits()?,
+        )?)
+    }
+
+    /// Gets how many payments to us were accepted, and how many were rejected by each limit.
+    #[wasm_bindgen]
+    pub fn get_inbound_htlc_counters(
+        &self,
+    ) -> Result<JsValue /* InboundHtlcCounters */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inbound_htlc_counters()?,
+        )?)
+    }
+
+    /// Sets the inbound capacity to keep, in sats. A warning is emitted when
+    /// it drops below this. Setting `undefined` disables the warnings.
+    #[wasm_bindgen]
+    pub fn set_inbound_capacity_threshold(
+        &self,
+        threshold_sats: Option<u64>,
+    ) -> Result<(), MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .set_inbound_capacity_threshold(threshold_sats)?)
+    }
+
+    /// Reports how much we can receive over lightning and how to get more
+    /// when it is below the inbound capacity threshold.
+    #[wasm_bindgen]
+    pub async fn inbound_capacity_report(
+        &self,
+    ) -> Result<JsValue /* InboundCapacityReport */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.inbound_capacity_report().await?,
+        )?)
+    }
+
+    /// Returns the low inbound capacity warnings emitted since they were last cleared.
+    #[wasm_bindgen]
+    pub fn get_inbound_capacity_warnings(
+        &self,
+    ) -> Result<JsValue /* Vec<InboundCapacityWarning> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inbound_capacity_warnings()?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_inbound_capacity_warnings(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.clear_inbound_capacity_warnings()?)
+    }
+
+    /// Sets the peer allowlist. When enabled, the node only connects to and accepts
+    /// connections from its LSP and the given peers, for a maximally private node.
+    #[wasm_bindgen]
+    pub async fn set_peer_allowlist(
+        &self,
+        enabled: bool,
+        peers: JsValue, /* Vec<String> */
+    ) -> Result<(), MutinyJsError> {
+        let peers: Vec<String> = peers
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let peers = peers
+            .iter()
+            .map(|p| PublicKey::from_str(p))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self
+            .inner
+            .node_manager
+            .set_peer_allowlist(PeerAllowlist { enabled, peers })
+            .await?)
+    }
+
+    /// Gets the peer allowlist.
+    #[wasm_bindgen]
+    pub fn get_peer_allowlist(&self) -> Result<JsValue /* PeerAllowlist */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_peer_allowlist()?,
+        )?)
+    }
+
+    /// Imports the payment history exported from another wallet.
+    /// The source can be "lnd", "bluewallet" or "phoenix".
+    ///
+    /// Returns the number of newly imported payments.
+    #[wasm_bindgen]
+    pub fn import_payment_history(
+        &self,
+        source: String,
+        data: String,
+    ) -> Result<usize, MutinyJsError> {
+        let source = ImportSource::from_str(&source)?;
+        Ok(self
+            .inner
+            .node_manager
+            .import_payment_history(source, &data)?)
+    }
+
+    /// Lists every device that has run this wallet.
+    #[wasm_bindgen]
+    pub fn list_devices(&self) -> Result<JsValue /* Vec<DeviceInfo> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_devices()?,
+        )?)
+    }
+
+    /// Revokes another device, the NWC connections it created are disabled
+    /// and their budgets reset here now, and on every other device as it starts.
+    #[wasm_bindgen]
+    pub fn revoke_device(&self, device_id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.revoke_device(&device_id)?)
+    }
+
+    /// Lists the nostr relays used for NWC, DMs and contact syncing,
+    /// along with their connection stats.
+    #[wasm_bindgen]
+    pub fn list_relays(&self) -> Result<JsValue /* Vec<RelayConfig> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_relays()?,
+        )?)
+    }
+
+    /// Adds a relay to the relay pool, or changes whether it is read from and written to.
+    #[wasm_bindgen]
+    pub fn add_relay(&self, url: String, read: bool, write: bool) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.add_relay(&url, read, write)?)
+    }
+
+    /// Removes a relay from the relay pool.
+    #[wasm_bindgen]
+    pub fn remove_relay(&self, url: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.remove_relay(&url)?)
+    }
+
+    /// Lists all the peers for all the nodes in the node manager.
+    #[wasm_bindgen]
+    pub async fn list_peers(&self) -> Result<JsValue /* Vec<MutinyPeer> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_peers().await?,
+        )?)
+    }
+
+    /// Returns all the on-chain and lightning activity from the wallet.
+    /// Items include a display name and icon when one has been resolved,
+    /// missing or stale ones are looked up in the background.
+    #[wasm_bindgen]
+    pub async fn get_activity(&self) -> Result<JsValue /* Vec<ActivityItem> */, MutinyJsError> {
+        // get activity from the node manager
+        let activity = self.inner.node_manager.get_enriched_activity().await?;
+        let mut activity: Vec<ActivityItem> = activity.into_iter().map(|a| a.into()).collect();
+        self.add_activity_contacts(&mut activity)?;
+
+        Ok(JsValue::from_serde(&activity)?)
+    }
+
+    /// Returns a page of activity, newest first, with up to `limit` items
+    /// last updated before `before`. Pass the `last_updated` of the final
+    /// item of a page to get the next one.
+    /// With a `label`, only activity for that label or contact is returned.
+    #[wasm_bindgen]
+    pub async fn list_activity(
+        &self,
+        before: Option<u64>,
+        limit: u32,
+        label: Option<String>,
+    ) -> Result<JsValue /* Vec<ActivityItem> */, MutinyJsError> {
+        let activity = self
+            .inner
+            .node_manager
+            .list_activity(before, limit as usize, label)
+            .await?;
+        let mut activity: Vec<ActivityItem> = activity.into_iter().map(|a| a.into()).collect();
+        self.add_activity_contacts(&mut activity)?;
+
+        Ok(JsValue::from_serde(&activity)?)
+    }
+
+    fn add_activity_contacts(&self, activity: &mut [ActivityItem]) -> Result<(), MutinyJsError> {
+        let contacts = self.inner.node_manager.get_contacts()?;
+        for a in activity.iter_mut() {
+            // find labels that have a contact and add them to the item
+            for label in a.labels.iter() {
+                if let Some(contact) = contacts.get(label) {
+                    a.contacts.push(Contact::from(contact.clone()));
+                }
+            }
+            // remove labels that have a contact to prevent duplicates
+            a.labels.retain(|l| !contacts.contains_key(l));
+        }
+        Ok(())
+    }
+
+    /// Initiates a redshift
+    #[wasm_bindgen]
+    pub async fn init_redshift(
+        &self,
+        outpoint: String,
+        lightning_recipient_pubkey: Option<String>,
+        lightning_recipient_connection_string: Option<String>,
+        onchain_recipient: Option<String>,
+    ) -> Result<Redshift, MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let introduction_node = match lightning_recipient_pubkey.clone() {
+            Some(p) => Some(PublicKey::from_str(&p)?),
+            None => None,
+        };
+        let redshift_recipient = match (lightning_recipient_pubkey, onchain_recipient) {
+            (Some(_), Some(_)) => {
+                return Err(MutinyJsError::InvalidArgumentsError);
+            }
+            (Some(l), None) => {
+                let l = PublicKey::from_str(&l)?;
+                RedshiftRecipient::Lightning(l)
+            }
+            (None, Some(o)) => {
+                let o = Address::from_str(&o)?;
+                RedshiftRecipient::OnChain(Some(o))
+            }
+            (None, None) => RedshiftRecipient::OnChain(None),
+        };
+        Ok(self
+            .inner
+            .node_manager
+            .init_redshift(
+                outpoint,
+                redshift_recipient,
+                introduction_node,
+                lightning_recipient_connection_string.as_deref(),
+            )
+            .await?
+            .into())
+    }
+
+    /// Get all redshift attempts for a given utxo
+    #[wasm_bindgen]
+    pub fn get_redshift(&self, id: String) -> Result<Option<Redshift>, MutinyJsError> {
+        let id: [u8; 16] =
+            FromHex::from_hex(&id).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.node_manager.get_redshift(&id)?.map(|r| r.into()))
+    }
+
+    pub fn get_address_labels(
+        &self,
+    ) -> Result<JsValue /* Map<Address, Vec<String>> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_address_labels()?,
+        )?)
+    }
+
+    /// Set the labels for an address, replacing any existing labels
+    /// If you want to do not want to replace any existing labels, use `get_address_labels` to get the existing labels,
+    /// add the new labels, and then use `set_address_labels` to set the new labels
+    pub fn set_address_labels(
+        &self,
+        address: String,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<(), MutinyJsError> {
+        let address = Address::from_str(&address)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .set_address_labels(address, labels)?)
+    }
+
+    pub fn get_invoice_labels(
+        &self,
+    ) -> Result<JsValue /* Map<Invoice, Vec<String>> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_invoice_labels()?,
+        )?)
+    }
+
+    /// Set the labels for an invoice, replacing any existing labels
+    /// If you want to do not want to replace any existing labels, use `get_invoice_labels` to get the existing labels,
+    /// add the new labels, and then use `set_invoice_labels` to set the new labels
+    pub fn set_invoice_labels(
+        &self,
+        invoice: String,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<(), MutinyJsError> {
+        let invoice = Bolt11Invoice::from_str(&invoice)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .set_invoice_labels(invoice, labels)?)
+    }
+
+    pub fn get_contacts(&self) -> Result<JsValue /* Map<String, Contact>*/, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .get_contacts()?
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect::<HashMap<String, Contact>>(),
+        )?)
+    }
+
+    pub fn get_contact(&self, label: String) -> Result<Option<TagItem>, MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .get_contact(&label)?
+            .map(|c| (label, c).into()))
+    }
+
+    /// Create a new contact from an existing label and returns the new identifying label
+    pub fn create_contact_from_label(
+        &self,
+        label: String,
+        contact: Contact,
+    ) -> Result<String, MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .create_contact_from_label(label, contact.into())?)
+    }
+
+    pub fn create_new_contact(&self, contact: Contact) -> Result<String, MutinyJsError> {
+        Ok(self.inner.node_manager.create_new_contact(contact.into())?)
+    }
+
+    pub fn archive_contact(&self, id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.archive_contact(id)?)
+    }
+
+    pub fn edit_contact(&self, id: String, contact: Contact) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.edit_contact(id, contact.into())?)
+    }
+
+    /// Creates a contact from a NIP-05 identifier, like bob@example.com,
+    /// using the npub it points to. Returns the new contact's id.
+    pub async fn create_contact_from_nip05(
+        &self,
+        nip05: String,
+        name: Option<String>,
+    ) -> Result<String, MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .create_contact_from_nip05(nip05, name)
+            .await?)
+    }
+
+    /// Checks that a contact's NIP-05 identifier still points to their npub.
+    pub async fn verify_contact_nip05(
+        &self,
+        id: String,
+    ) -> Result<JsValue /* Option<Nip05Status> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.verify_contact_nip05(&id).await?,
+        )?)
+    }
+
+    /// Checks the NIP-05 identifiers of all contacts,
+    /// returning the ids of those that no longer verify.
+    pub async fn verify_contacts_nip05(&self) -> Result<JsValue /* Vec<String> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.verify_contacts_nip05().await?,
+        )?)
+    }
+
+    pub fn get_tag_items(&self) -> Result<JsValue /* Vec<TagItem> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .get_tag_items()?
+                .into_iter()
+                .map(|t| t.into())
+                .collect::<Vec<TagItem>>(),
+        )?)
+    }
+
+    /// Gets the current bitcoin price in USD.
+    #[wasm_bindgen]
+    pub async fn get_bitcoin_price(&self) -> Result<f32, MutinyJsError> {
+        Ok(self.inner.node_manager.get_bitcoin_price().await?)
+    }
+
+    /// Returns the crash reports saved from previous panics.
+    /// These contain recent logs and a summary of the wallet state, no secrets.
+    #[wasm_bindgen]
+    pub fn get_crash_reports(&self) -> Result<JsValue /* Vec<CrashReport> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_crash_reports()?,
+        )?)
+    }
+
+    /// Deletes all of the saved crash reports.
+    #[wasm_bindgen]
+    pub fn clear_crash_reports(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.clear_crash_reports()?)
+    }
+
+    /// Sets up a dead-man switch that sweeps the on-chain wallet to the heir's
+    /// address unless the user checks in within the interval.
+    ///
+    /// If the heir's npub is given, the sweep transaction is sent to them every time
+    /// it changes. Otherwise the sweep transaction in the returned plan should be given to the heir.
+    #[wasm_bindgen]
+    pub async fn setup_inheritance(
+        &self,
+        heir_address: String,
+        heir_npub: Option<String>,
+        check_in_interval_secs: u64,
+        fee_rate: Option<f32>,
+    ) -> Result<JsValue /* InheritancePlan */, MutinyJsError> {
+        let heir_address = Address::from_str(&heir_address)?;
+        let heir_npub = heir_npub
+            .map(|npub| XOnlyPublicKey::from_bech32(&npub))
+            .transpose()?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .setup_inheritance(heir_address, heir_npub, check_in_interval_secs, fee_rate)
+                .await?,
+        )?)
+    }
+
+    /// Checks in to push back the inheritance sweep, this costs an on-chain fee.
+    #[wasm_bindgen]
+    pub async fn inheritance_check_in(
+        &self,
+        fee_rate: Option<f32>,
+    ) -> Result<JsValue /* InheritancePlan */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .inheritance_check_in(fee_rate)
+                .await?,
+        )?)
+    }
+
+    /// Cancels the inheritance plan, invalidating the signed sweep transaction.
+    #[wasm_bindgen]
+    pub async fn cancel_inheritance(&self, fee_rate: Option<f32>) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.cancel_inheritance(fee_rate).await?)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_inheritance_plan(
+        &self,
+    ) -> Result<JsValue /* Option<InheritancePlan> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inheritance_plan()?,
+        )?)
+    }
+
+    /// Returns the reminders to check in that have been emitted since they were last cleared.
+    #[wasm_bindgen]
+    pub fn get_inheritance_reminders(
+        &self,
+    ) -> Result<JsValue /* Vec<InheritanceReminder> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_inheritance_reminders()?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_inheritance_reminders(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.clear_inheritance_reminders()?)
+    }
+
+    /// Fetches the sweep transactions other users have sent us as their heir.
+    /// They are broadcast once they unlock.
+    #[wasm_bindgen]
+    pub async fn sync_inheritance_sweeps(&self) -> Result<(), MutinyJsError> {
+        self.inner
+            .sync_inheritance_sweeps(Some(Duration::from_secs(10)))
+            .await?;
+        Ok(())
+    }
+
+    /// Get the sweep transactions we are holding as an heir
+    #[wasm_bindgen]
+    pub fn get_held_sweeps(&self) -> Result<JsValue /* Vec<HeldSweep> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_held_sweeps()?,
+        )?)
+    }
+
+    /// Adds an alert that fires when its condition is met. The condition is one of
+    /// `{ type: "balance_below", sats }`, `{ type: "payment_failure_rate_above", percent }`
+    /// or `{ type: "channel_offline_for", hours }`.
+    #[wasm_bindgen]
+    pub fn add_alert_rule(
+        &self,
+        condition: JsValue, /* AlertCondition */
+    ) -> Result<JsValue /* AlertRule */, MutinyJsError> {
+        let condition: AlertCondition = condition
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.add_alert_rule(condition)?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_alert_rule(&self, id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.remove_alert_rule(&id)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn list_alert_rules(&self) -> Result<JsValue /* Vec<AlertRule> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_alert_rules()?,
+        )?)
+    }
+
+    /// Returns the alerts that have fired since they were last cleared.
+    #[wasm_bindgen]
+    pub fn get_alert_events(&self) -> Result<JsValue /* Vec<AlertEvent> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_alert_events()?,
+        )?)
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_alert_events(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.clear_alert_events()?)
+    }
+
+    /// Gets the receipt for a settled payment, generating it if needed.
+    /// Receipts have a `schema_version` so they can be rendered or exported consistently.
+    #[wasm_bindgen]
+    pub async fn get_receipt(
+        &self,
+        payment_hash: String,
+    ) -> Result<JsValue /* Receipt */, MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_receipt(&hash).await?,
+        )?)
+    }
+
+    /// Lists the receipts that have been generated, newest first.
+    #[wasm_bindgen]
+    pub fn list_receipts(&self) -> Result<JsValue /* Vec<Receipt> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_receipts()?,
+        )?)
+    }
+
+    /// Lists the payments our LSP held for us while the wallet was offline.
+    #[wasm_bindgen]
+    pub fn list_pending_receives(
+        &self,
+    ) -> Result<JsValue /* Vec<PendingReceive> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_pending_receives()?,
+        )?)
+    }
+
+    /// Creates a proof of payment for a settled outgoing payment.
+    /// With a message, the proof is also signed by the node that paid.
+    #[wasm_bindgen]
+    pub async fn create_payment_proof(
+        &self,
+        payment_hash: String,
+        message: Option<String>,
+    ) -> Result<JsValue /* PaymentProof */, MutinyJsError> {
+        let hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .create_payment_proof(&hash, message)
+                .await?,
+        )?)
+    }
+
+    /// Verifies a payment proof, whichever wallet made it.
+    #[wasm_bindgen]
+    pub fn verify_payment_proof(
+        &self,
+        proof: JsValue, /* PaymentProof */
+    ) -> Result<JsValue /* VerifiedPaymentProof */, MutinyJsError> {
+        let proof: PaymentProof = proof
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.verify_payment_proof(&proof)?,
+        )?)
+    }
+
+    /// Returns the proofs for all the times a channel partner broadcast an old
+    /// channel state and we claimed their funds with justice transactions.
+    #[wasm_bindgen]
+    pub fn get_justice_proofs(&self) -> Result<JsValue /* Vec<JusticeProof> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_justice_proofs()?,
+        )?)
+    }
+
+    /// Returns a plain text proof of a justice claim that can be shared.
+    #[wasm_bindgen]
+    pub fn export_justice_proof(&self, funding_txo: String) -> Result<String, MutinyJsError> {
+        let funding_txo =
+            OutPoint::from_str(&funding_txo).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let proof = self
+            .inner
+            .node_manager
+            .get_justice_proofs()?
+            .into_iter()
+            .find(|p| p.funding_txo == funding_txo)
+            .ok_or(MutinyJsError::NotFound)?;
+        Ok(proof.to_shareable_string())
+    }
+
+    /// Turns the watchtower on or off. It watches the channels synced to this
+    /// device and claims the funds of a channel partner that broadcasts an old
+    /// channel state. The setting is kept when the wallet restarts.
+    #[wasm_bindgen]
+    pub async fn set_watchtower_enabled(&self, enabled: bool) -> Result<(), MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .set_watchtower_enabled(enabled)
+            .await?)
+    }
+
+    /// Gets whether the watchtower is on, how many channels it watches
+    /// and the breaches it has detected.
+    #[wasm_bindgen]
+    pub async fn get_watchtower_status(
+        &self,
+    ) -> Result<JsValue /* WatchtowerStatus */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_watchtower_status().await?,
+        )?)
+    }
+
+    /// Exports the channel monitors for a [MutinyWatchtower] on a device
+    /// without the seed. Export again after channels open or make payments.
+    #[wasm_bindgen]
+    pub async fn export_watch_data(&self) -> Result<JsValue /* WatchData */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.export_watch_data().await?,
+        )?)
+    }
+
+    /// Sets the most to pay in routing fees on any payment, in msats and/or
+    /// as a percent of the amount. Unset values don't cap the fee.
+    #[wasm_bindgen]
+    pub async fn set_fee_cap(
         &self,
-        address: String,
-        labels: JsValue, /* Vec<String> */
+        max_fee_msat: Option<u64>,
+        max_fee_percent: Option<f64>,
     ) -> Result<(), MutinyJsError> {
-        let address = Address::from_str(&address)?;
-        let labels: Vec<String> = labels
-            .into_serde()
-            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .node_manager
-            .set_address_labels(address, labels)?)
+            .set_fee_cap(FeeCap {
+                max_fee_msat,
+                max_fee_percent,
+            })
+            .await?)
     }
 
-    pub fn get_invoice_labels(
-        &self,
-    ) -> Result<JsValue /* Map<Invoice, Vec<String>> */, MutinyJsError> {
+    /// Returns the most to pay in routing fees on any payment.
+    #[wasm_bindgen]
+    pub fn get_fee_cap(&self) -> Result<JsValue /* FeeCap */, MutinyJsError> {
         Ok(JsValue::from_serde(
-            &self.inner.node_manager.get_invoice_labels()?,
+            &self.inner.node_manager.get_fee_cap()?,
         )?)
     }
 
-    /// Set the labels for an invoice, replacing any existing labels
-    /// If you want to do not want to replace any existing labels, use `get_invoice_labels` to get the existing labels,
-    /// add the new labels, and then use `set_invoice_labels` to set the new labels
-    pub fn set_invoice_labels(
+    /// Sets how payments are split over multiple paths: the most parts to split a
+    /// payment into, and the smallest part in msats. Unset, the most parts is based
+    /// on how many channels we have. A payment's retry policy can override these,
+    /// or set `disable_mpp` to send it over a single path.
+    #[wasm_bindgen]
+    pub async fn set_mpp_config(
         &self,
-        invoice: String,
-        labels: JsValue, /* Vec<String> */
+        max_parts: Option<u8>,
+        min_part_msat: Option<u64>,
     ) -> Result<(), MutinyJsError> {
-        let invoice = Bolt11Invoice::from_str(&invoice)?;
-        let labels: Vec<String> = labels
-            .into_serde()
-            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .node_manager
-            .set_invoice_labels(invoice, labels)?)
+            .set_mpp_config(MppConfig {
+                max_parts,
+                min_part_msat,
+            })
+            .await?)
     }
 
-    pub fn get_contacts(&self) -> Result<JsValue /* Map<String, Contact>*/, MutinyJsError> {
+    /// Returns how payments are split over multiple paths.
+    #[wasm_bindgen]
+    pub fn get_mpp_config(&self) -> Result<JsValue /* MppConfig */, MutinyJsError> {
         Ok(JsValue::from_serde(
-            &self
-                .inner
-                .node_manager
-                .get_contacts()?
-                .into_iter()
-                .map(|(k, v)| (k, v.into()))
-                .collect::<HashMap<String, Contact>>(),
+            &self.inner.node_manager.get_mpp_config()?,
         )?)
     }
 
-    pub fn get_contact(&self, label: String) -> Result<Option<TagItem>, MutinyJsError> {
-        Ok(self
-            .inner
-            .node_manager
-            .get_contact(&label)?
-            .map(|c| (label, c).into()))
+    /// Sets whether the user has opted in to uploading anonymized telemetry.
+    #[wasm_bindgen]
+    pub fn set_telemetry_opt_in(&self, opt_in: bool) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.set_telemetry_opt_in(opt_in)?)
     }
 
-    /// Create a new contact from an existing label and returns the new identifying label
-    pub fn create_contact_from_label(
+    /// Saves the current settings, labels and contacts as a restore point.
+    #[wasm_bindgen]
+    pub fn create_restore_point(&self) -> Result<JsValue /* RestorePointInfo */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.create_restore_point()?,
+        )?)
+    }
+
+    /// Lists the saved restore points, newest first.
+    #[wasm_bindgen]
+    pub fn list_restore_points(
         &self,
-        label: String,
-        contact: Contact,
-    ) -> Result<String, MutinyJsError> {
+    ) -> Result<JsValue /* Vec<RestorePointInfo> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_restore_points()?,
+        )?)
+    }
+
+    /// Rolls the settings, labels and contacts back to the given restore point.
+    /// Channels and payments are not affected.
+    #[wasm_bindgen]
+    pub async fn restore(&self, restore_point_id: String) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.restore(&restore_point_id).await?)
+    }
+
+    /// Sets how long to keep the full details of old payments and channels, in seconds.
+    /// A summary of each is always kept. If not set, details are kept forever.
+    #[wasm_bindgen]
+    pub async fn set_retention_policy(
+        &self,
+        detail_retention_secs: Option<u64>,
+    ) -> Result<(), MutinyJsError> {
         Ok(self
             .inner
             .node_manager
-            .create_contact_from_label(label, contact.into())?)
+            .set_retention_policy(RetentionPolicy {
+                detail_retention_secs,
+            })
+            .await?)
     }
 
-    pub fn create_new_contact(&self, contact: Contact) -> Result<String, MutinyJsError> {
-        Ok(self.inner.node_manager.create_new_contact(contact.into())?)
+    /// Gets how long the full details of old payments and channels are kept.
+    #[wasm_bindgen]
+    pub fn get_retention_policy(&self) -> Result<JsValue /* RetentionPolicy */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_retention_policy()?,
+        )?)
     }
 
-    pub fn archive_contact(&self, id: String) -> Result<(), MutinyJsError> {
-        Ok(self.inner.node_manager.archive_contact(id)?)
+    /// Drops the full details of payments and channels that are older
+    /// than the retention policy allows. This also runs once a day in the background.
+    #[wasm_bindgen]
+    pub async fn prune_old_data(&self) -> Result<JsValue /* PruneSummary */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.prune_old_data().await?,
+        )?)
     }
 
-    pub fn edit_contact(&self, id: String, contact: Contact) -> Result<(), MutinyJsError> {
-        Ok(self.inner.node_manager.edit_contact(id, contact.into())?)
+    /// Returns whether the user has opted in to uploading anonymized telemetry.
+    #[wasm_bindgen]
+    pub fn get_telemetry_opt_in(&self) -> Result<bool, MutinyJsError> {
+        Ok(self.inner.node_manager.get_telemetry_opt_in()?)
     }
 
-    pub fn get_tag_items(&self) -> Result<JsValue /* Vec<TagItem> */, MutinyJsError> {
+    /// Returns the anonymized telemetry summary that would be uploaded if opted in.
+    #[wasm_bindgen]
+    pub fn get_telemetry_summary(&self) -> Result<JsValue /* TelemetrySummary */, MutinyJsError> {
         Ok(JsValue::from_serde(
-            &self
-                .inner
-                .node_manager
-                .get_tag_items()?
-                .into_iter()
-                .map(|t| t.into())
-                .collect::<Vec<TagItem>>(),
+            &self.inner.node_manager.get_telemetry_summary()?,
         )?)
     }
 
-    /// Gets the current bitcoin price in USD.
+    /// Returns how long the most recent successful sync took, if there has been one.
     #[wasm_bindgen]
-    pub async fn get_bitcoin_price(&self) -> Result<f32, MutinyJsError> {
-        Ok(self.inner.node_manager.get_bitcoin_price().await?)
+    pub fn get_sync_metrics(&self) -> Result<JsValue /* Option<SyncMetrics> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_sync_metrics(),
+        )?)
+    }
+
+    /// Returns the endpoint picked for each service, and whether it is a canary.
+    #[wasm_bindgen]
+    pub fn get_endpoint_selections(
+        &self,
+    ) -> Result<JsValue /* Vec<EndpointSelection> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_endpoint_selections(),
+        )?)
+    }
+
+    /// Returns the status of the backend services the wallet uses, whether the
+    /// wallet is degraded, and which features are turned off because of it.
+    #[wasm_bindgen]
+    pub fn get_service_status(&self) -> Result<JsValue /* StatusReport */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_service_status(),
+        )?)
     }
 
     /// Exports the current state of the node manager to a json object.
@@ -1075,20 +2709,82 @@ impl MutinyWallet {
         Ok(JsValue::from_serde(&self.inner.nostr.profiles())?)
     }
 
-    /// Create a nostr wallet connect profile
+    /// Gets every request an app made over the nostr wallet connect profile
+    /// with the given index, oldest first.
+    #[wasm_bindgen]
+    pub fn get_nwc_activity(
+        &self,
+        index: u32,
+    ) -> Result<JsValue /* Vec<NwcActivity> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.nostr.get_nwc_activity(index)?,
+        )?)
+    }
+
+    /// Create a nostr wallet connect profile.
+    ///
+    /// The scopes are the NIP-47 methods the connection can use, like "get_balance".
+    /// If none are given the connection can only pay invoices.
     #[wasm_bindgen]
     pub async fn create_nwc_profile(
         &self,
         name: String,
+        scopes: JsValue, /* Option<Vec<String>> */
     ) -> Result<models::NwcProfile, MutinyJsError> {
+        let scopes: Option<Vec<NwcScope>> = scopes
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
         Ok(self
             .inner
             .nostr
-            .create_new_nwc_profile(ProfileType::Normal { name }, SpendingConditions::default())
+            .create_new_nwc_profile(
+                ProfileType::Normal { name },
+                SpendingConditions::default(),
+                scopes.unwrap_or_else(NwcScope::default_scopes),
+            )
             .await?
             .into())
     }
 
+    /// Gets the NWC URI a self-hosted lightning address server uses to fetch invoices.
+    /// The connection can only create and look up invoices, never spend.
+    #[wasm_bindgen]
+    pub async fn get_lightning_address_connection(&self) -> Result<String, MutinyJsError> {
+        Ok(self.inner.nostr.get_lightning_address_connection().await?)
+    }
+
+    /// Pairs with a lightning address server so we can receive at `username@<server domain>`.
+    /// The server fetches invoices from the wallet while it is open.
+    #[wasm_bindgen]
+    pub fn pair_lightning_address(
+        &self,
+        server_url: String,
+        username: String,
+    ) -> Result<JsValue /* LightningAddressPairing */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .pair_lightning_address(server_url, username)?,
+        )?)
+    }
+
+    /// Gets the lightning address server we are paired with and whether the address is claimed.
+    #[wasm_bindgen]
+    pub fn get_lightning_address_pairing(
+        &self,
+    ) -> Result<JsValue /* Option<LightningAddressPairing> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_lightning_address_pairing()?,
+        )?)
+    }
+
+    /// Stops providing invoices to the lightning address server.
+    #[wasm_bindgen]
+    pub fn unpair_lightning_address(&self) -> Result<(), MutinyJsError> {
+        Ok(self.inner.node_manager.unpair_lightning_address()?)
+    }
+
     /// Edits a nostr wallet connect profile
     #[wasm_bindgen]
     pub async fn edit_nwc_profile(
@@ -1224,6 +2920,79 @@ impl MutinyWallet {
         Ok(())
     }
 
+    /// Splits the seed into shards and sends them to the given guardians' npubs,
+    /// any `threshold` of them can help restore the wallet.
+    ///
+    /// Calling this again with a different set of guardians rotates the shards.
+    #[wasm_bindgen]
+    pub async fn setup_social_recovery(
+        &self,
+        threshold: u8,
+        guardians: JsValue, /* Vec<String> */
+    ) -> Result<(), MutinyJsError> {
+        let guardians: Vec<String> = guardians
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let guardians = guardians
+            .iter()
+            .map(|npub| XOnlyPublicKey::from_bech32(npub))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.inner
+            .setup_social_recovery(threshold, guardians)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the current social recovery setup, if there is one
+    #[wasm_bindgen]
+    pub fn get_social_recovery_config(
+        &self,
+    ) -> Result<JsValue /* Option<SocialRecoveryConfig> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.nostr.get_social_recovery_config()?,
+        )?)
+    }
+
+    /// Fetches the recovery shards other users have sent us to hold as their guardian.
+    #[wasm_bindgen]
+    pub async fn sync_recovery_shards(&self) -> Result<(), MutinyJsError> {
+        self.inner
+            .sync_recovery_shards(Some(Duration::from_secs(10)))
+            .await?;
+        Ok(())
+    }
+
+    /// Get the recovery shards we are holding for other users
+    #[wasm_bindgen]
+    pub fn get_held_recovery_shards(&self) -> Result<JsValue /* Vec<HeldShard> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.nostr.get_held_recovery_shards()?,
+        )?)
+    }
+
+    /// Sends the shard we hold for the owner's npub to their recovery npub.
+    #[wasm_bindgen]
+    pub async fn return_recovery_shard(
+        &self,
+        owner: String,
+        recovery_npub: String,
+    ) -> Result<(), MutinyJsError> {
+        let owner = XOnlyPublicKey::from_bech32(&owner)?;
+        let recovery_npub = XOnlyPublicKey::from_bech32(&recovery_npub)?;
+        self.inner
+            .return_recovery_shard(owner, recovery_npub)
+            .await?;
+        Ok(())
+    }
+
+    /// Gets the size of the network graph and counters for the gossip our peers have sent us.
+    #[wasm_bindgen]
+    pub async fn graph_stats(&self) -> Result<JsValue /* GraphStats */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.graph_stats().await,
+        )?)
+    }
+
     /// Resets the scorer and network graph. This can be useful if you get stuck in a bad state.
     #[wasm_bindgen]
     pub async fn reset_router(&self) -> Result<(), MutinyJsError> {
@@ -1241,6 +3010,19 @@ impl MutinyWallet {
         Ok(self.inner.reset_onchain_tracker().await?)
     }
 
+    /// Rescans the on-chain wallet's history starting at the given block height or
+    /// unix timestamp. Values below 500,000,000 are treated as heights.
+    ///
+    /// This is for restoring an old seed whose history is missing after the restore.
+    #[wasm_bindgen]
+    pub async fn rescan_from(&self, height_or_timestamp: u64) -> Result<(), MutinyJsError> {
+        Ok(self
+            .inner
+            .node_manager
+            .rescan_from(height_or_timestamp)
+            .await?)
+    }
+
     /// Exports the current state of the node manager to a json object.
     #[wasm_bindgen]
     pub async fn export_json(password: Option<String>) -> Result<String, MutinyJsError> {
@@ -1281,10 +3063,14 @@ impl MutinyWallet {
     ///
     /// Backup the state beforehand. Does not restore lightning data.
     /// Should refresh or restart afterwards. Wallet should be stopped.
+    ///
+    /// If the wallet's birthday height is given and it is at the current tip,
+    /// the restore skips scanning for the wallet's history.
     #[wasm_bindgen]
     pub async fn restore_mnemonic(
         m: String,
         password: Option<String>,
+        birthday: Option<u32>,
     ) -> Result<(), MutinyJsError> {
         let logger = Arc::new(MutinyLogger::default());
         let cipher = password
@@ -1296,11 +3082,71 @@ impl MutinyWallet {
         mutiny_core::MutinyWallet::<IndexedDbStorage>::restore_mnemonic(
             storage,
             Mnemonic::from_str(&m).map_err(|_| MutinyJsError::InvalidMnemonic)?,
+            birthday,
         )
         .await?;
         Ok(())
     }
 
+    /// Restore's the mnemonic from SLIP-39 shares after deleting the previous state.
+    ///
+    /// Backup the state beforehand. Does not restore lightning data.
+    /// Should refresh or restart afterwards. Wallet should be stopped.
+    #[wasm_bindgen]
+    pub async fn restore_from_shares(
+        shares: JsValue, /* Vec<String> */
+        password: Option<String>,
+    ) -> Result<(), MutinyJsError> {
+        let shares: Vec<String> = shares
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let mnemonic = mutiny_core::slip39::combine_shares(&shares)?;
+        MutinyWallet::restore_mnemonic(mnemonic.to_string(), password, None).await
+    }
+
+    /// Generates a temporary key for guardians to return recovery shards to.
+    /// The secret key needs to be kept until the wallet is restored,
+    /// the npub is what gets given to the guardians.
+    #[wasm_bindgen]
+    pub fn generate_recovery_key() -> Result<JsValue /* (String, String) */, MutinyJsError> {
+        let keys = nostr::Keys::generate();
+        let secret = keys
+            .secret_key()
+            .map_err(|_| MutinyJsError::NostrError)?
+            .display_secret()
+            .to_string();
+        let npub = keys
+            .public_key()
+            .to_bech32()
+            .map_err(|_| MutinyJsError::NostrError)?;
+        Ok(JsValue::from_serde(&(secret, npub))?)
+    }
+
+    /// Restores the mnemonic from the shards guardians have returned to the
+    /// recovery key, after deleting the previous state.
+    ///
+    /// The shards are fetched from the given relays, the ones the guardians use,
+    /// or the default relays if none are given.
+    ///
+    /// Backup the state beforehand. Does not restore lightning data.
+    /// Should refresh or restart afterwards. Wallet should be stopped.
+    #[wasm_bindgen]
+    pub async fn restore_from_social_recovery(
+        recovery_secret: String,
+        relays: Vec<String>,
+        password: Option<String>,
+    ) -> Result<(), MutinyJsError> {
+        let recovery_keys = nostr::Keys::from_sk_str(&recovery_secret)
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let mnemonic = mutiny_core::nostr::recovery::fetch_returned_shards(
+            &recovery_keys,
+            relays,
+            Some(Duration::from_secs(10)),
+        )
+        .await?;
+        MutinyWallet::restore_mnemonic(mnemonic.to_string(), password, None).await
+    }
+
     #[wasm_bindgen]
     pub async fn change_password(
         &mut self,
@@ -1335,6 +3181,75 @@ impl MutinyWallet {
     }
 }
 
+/// A watchtower for a device without the seed, it watches the channels in
+/// watch data exported with [MutinyWallet::export_watch_data] and claims the
+/// funds of a channel partner that broadcasts an old channel state.
+///
+/// Nothing is saved, it is all kept in memory. The watch data has to be given
+/// again after a page reload, and the alerts found before the reload are gone.
+#[wasm_bindgen]
+pub struct MutinyWatchtower {
+    inner: Arc<Watchtower<MemoryStorage>>,
+}
+
+#[wasm_bindgen]
+impl MutinyWatchtower {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        watch_data: JsValue, /* WatchData */
+        network_str: Option<String>,
+        user_esplora_url: Option<String>,
+    ) -> Result<MutinyWatchtower, MutinyJsError> {
+        let network: Network = network_str
+            .map(|s| s.parse().expect("Invalid network"))
+            .unwrap_or(Network::Bitcoin);
+        let data: WatchData = watch_data
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let logger = Arc::new(MutinyLogger::default());
+
+        let inner = Arc::new(Watchtower::from_watch_data(
+            data,
+            MemoryStorage::default(),
+            network,
+            user_esplora_url,
+            logger,
+        )?);
+        Watchtower::start(inner.clone());
+        Ok(MutinyWatchtower { inner })
+    }
+
+    /// Replaces the watch data with a newer export from the main device
+    #[wasm_bindgen]
+    pub fn update_watch_data(
+        &self,
+        watch_data: JsValue, /* WatchData */
+    ) -> Result<(), MutinyJsError> {
+        let data: WatchData = watch_data
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self.inner.update_watch_data(data)?)
+    }
+
+    /// Number of channels being watched
+    #[wasm_bindgen]
+    pub fn channel_count(&self) -> usize {
+        self.inner.channel_count()
+    }
+
+    /// The breaches found since the watchtower started, these are lost on a page reload
+    /// so they should be saved by the caller if they need to be kept
+    #[wasm_bindgen]
+    pub fn get_alerts(&self) -> Result<JsValue /* Vec<BreachAlert> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.get_alerts()?)?)
+    }
+
+    #[wasm_bindgen]
+    pub fn stop(&self) {
+        self.inner.stop();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::test::*;
@@ -1366,6 +3281,11 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            JsValue::NULL,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -1399,6 +3319,11 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            JsValue::NULL,
         )
         .await
         .unwrap();
@@ -1435,6 +3360,11 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            JsValue::NULL,
         )
         .await
         .expect("mutiny wallet should initialize");
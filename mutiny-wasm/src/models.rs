@@ -8,7 +8,9 @@ use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription};
 use lnurl::lightning_address::LightningAddress;
 use lnurl::lnurl::LnUrl;
 use mutiny_core::labels::Contact as MutinyContact;
-use mutiny_core::nostr::nwc::SpendingConditions;
+use mutiny_core::nostr::nip05::{Nip05Identity, Nip05Status};
+use mutiny_core::nostr::nwc::{NwcScope, SpendingConditions};
+use mutiny_core::payment_metadata::PaymentMetadata;
 use mutiny_core::redshift::{RedshiftRecipient, RedshiftStatus};
 use mutiny_core::*;
 use serde::{Deserialize, Serialize};
@@ -24,6 +26,7 @@ pub enum ActivityType {
     Lightning,
     ChannelOpen,
     ChannelClose,
+    External,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -36,6 +39,9 @@ pub struct ActivityItem {
     pub(crate) labels: Vec<String>,
     pub(crate) contacts: Vec<Contact>,
     pub last_updated: Option<u64>,
+    pub(crate) display_name: Option<String>,
+    pub(crate) icon_url: Option<String>,
+    pub(crate) order_id: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -59,6 +65,21 @@ impl ActivityItem {
     pub fn contacts(&self) -> JsValue /* Vec<Contact> */ {
         JsValue::from_serde(&self.contacts).unwrap()
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn display_name(&self) -> Option<String> {
+        self.display_name.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn icon_url(&self) -> Option<String> {
+        self.icon_url.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn order_id(&self) -> Option<String> {
+        self.order_id.clone()
+    }
 }
 
 impl From<nodemanager::ActivityItem> for ActivityItem {
@@ -73,6 +94,7 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             }
             nodemanager::ActivityItem::Lightning(_) => ActivityType::Lightning,
             nodemanager::ActivityItem::ChannelClosed(_) => ActivityType::ChannelClose,
+            nodemanager::ActivityItem::External(_) => ActivityType::External,
         };
 
         let id = match a {
@@ -81,6 +103,7 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             nodemanager::ActivityItem::ChannelClosed(ref c) => {
                 c.user_channel_id.map(|c| c.to_hex()).unwrap_or_default()
             }
+            nodemanager::ActivityItem::External(ref p) => p.id.clone(),
         };
 
         let (inbound, amount_sats) = match a {
@@ -95,6 +118,12 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             }
             nodemanager::ActivityItem::Lightning(ref ln) => (ln.inbound, ln.amount_sats),
             nodemanager::ActivityItem::ChannelClosed(_) => (false, None),
+            nodemanager::ActivityItem::External(ref p) => (p.inbound, Some(p.amount_sats)),
+        };
+
+        let order_id = match a {
+            nodemanager::ActivityItem::Lightning(ref ln) => ln.order_id.clone(),
+            _ => None,
         };
 
         ActivityItem {
@@ -105,6 +134,19 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             labels: a.labels(),
             contacts: vec![],
             last_updated: a.last_updated(),
+            display_name: None,
+            icon_url: None,
+            order_id,
+        }
+    }
+}
+
+impl From<enrichment::EnrichedActivityItem> for ActivityItem {
+    fn from(e: enrichment::EnrichedActivityItem) -> Self {
+        ActivityItem {
+            display_name: e.display_name,
+            icon_url: e.icon_url,
+            ..e.item.into()
         }
     }
 }
@@ -124,6 +166,11 @@ pub struct MutinyInvoice {
     pub inbound: bool,
     pub last_updated: u64,
     labels: Vec<String>,
+    order_id: Option<String>,
+    custom_tlvs: Vec<nodemanager::CustomTlv>,
+    metadata: PaymentMetadata,
+    failure: Option<nodemanager::PaymentFailure>,
+    parts: Vec<nodemanager::PaymentHtlc>,
 }
 
 #[wasm_bindgen]
@@ -162,6 +209,31 @@ impl MutinyInvoice {
     pub fn labels(&self) -> JsValue /* Vec<String> */ {
         JsValue::from_serde(&self.labels).unwrap()
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn order_id(&self) -> Option<String> {
+        self.order_id.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn custom_tlvs(&self) -> JsValue /* Vec<CustomTlv> */ {
+        JsValue::from_serde(&self.custom_tlvs).unwrap()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn metadata(&self) -> JsValue /* PaymentMetadata */ {
+        JsValue::from_serde(&self.metadata).unwrap()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn failure(&self) -> JsValue /* Option<PaymentFailure> */ {
+        JsValue::from_serde(&self.failure).unwrap()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn parts(&self) -> JsValue /* Vec<PaymentHtlc> */ {
+        JsValue::from_serde(&self.parts).unwrap()
+    }
 }
 
 impl From<nodemanager::MutinyInvoice> for MutinyInvoice {
@@ -179,6 +251,11 @@ impl From<nodemanager::MutinyInvoice> for MutinyInvoice {
             inbound: m.inbound,
             last_updated: m.last_updated,
             labels: m.labels,
+            order_id: m.order_id,
+            custom_tlvs: m.custom_tlvs,
+            metadata: m.metadata,
+            failure: m.failure,
+            parts: m.parts,
         }
     }
 }
@@ -300,6 +377,7 @@ pub struct ChannelClosure {
     node_id: Option<PublicKey>,
     reason: String,
     pub timestamp: u64,
+    postmortem: Option<nodemanager::ForceClosePostmortem>,
 }
 
 #[wasm_bindgen]
@@ -323,6 +401,12 @@ impl ChannelClosure {
     pub fn reason(&self) -> String {
         self.reason.clone()
     }
+
+    /// A human-readable explanation of what happened, only set for force closes
+    #[wasm_bindgen(getter)]
+    pub fn postmortem(&self) -> Option<String> {
+        self.postmortem.as_ref().map(|p| p.summary())
+    }
 }
 
 impl PartialOrd for ChannelClosure {
@@ -344,6 +428,7 @@ impl From<nodemanager::ChannelClosure> for ChannelClosure {
             node_id: c.node_id,
             reason: c.reason,
             timestamp: c.timestamp,
+            postmortem: c.postmortem,
         }
     }
 }
@@ -730,6 +815,8 @@ pub struct Contact {
     lnurl: Option<LnUrl>,
     #[serde(skip_serializing_if = "Option::is_none")]
     image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nip05: Option<Nip05Identity>,
     pub last_used: u64,
 }
 
@@ -755,6 +842,7 @@ impl Contact {
             ln_address,
             lnurl,
             image_url,
+            nip05: None,
             last_used: utils::now().as_secs(),
         })
     }
@@ -783,6 +871,22 @@ impl Contact {
     pub fn lnurl(&self) -> Option<String> {
         self.lnurl.clone().map(|a| a.to_string())
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn nip05(&self) -> Option<String> {
+        self.nip05.as_ref().map(|n| n.address.clone())
+    }
+
+    /// Whether the NIP-05 identifier points to this contact's npub,
+    /// "broken" means it did before and no longer does.
+    #[wasm_bindgen(getter)]
+    pub fn nip05_status(&self) -> Option<String> {
+        self.nip05.as_ref().map(|n| match n.status {
+            Nip05Status::Unverified => "unverified".to_string(),
+            Nip05Status::Verified => "verified".to_string(),
+            Nip05Status::Broken => "broken".to_string(),
+        })
+    }
 }
 
 impl From<Contact> for MutinyContact {
@@ -796,6 +900,7 @@ impl From<Contact> for MutinyContact {
             lnurl: c.lnurl,
             archived: Some(false),
             image_url: c.image_url,
+            nip05: c.nip05,
             last_used: c.last_used,
         }
     }
@@ -815,6 +920,7 @@ impl From<MutinyContact> for Contact {
             ln_address: c.ln_address,
             lnurl: c.lnurl,
             image_url: c.image_url,
+            nip05: c.nip05,
             last_used: c.last_used,
         }
     }
@@ -833,6 +939,7 @@ pub struct NwcProfile {
     pub require_approval: bool,
     spending_conditions: SpendingConditions,
     nwc_uri: String,
+    scopes: Vec<NwcScope>,
 }
 
 #[wasm_bindgen]
@@ -857,6 +964,11 @@ impl NwcProfile {
         self.nwc_uri.clone()
     }
 
+    #[wasm_bindgen(getter)]
+    pub fn scopes(&self) -> JsValue /* Vec<String> */ {
+        JsValue::from_serde(&self.scopes).unwrap()
+    }
+
     #[wasm_bindgen]
     pub fn sharable_url(&self, url_prefix: String) -> Option<String> {
         match self.spending_conditions {
@@ -894,6 +1006,7 @@ impl From<nostr::nwc::NwcProfile> for NwcProfile {
             require_approval,
             spending_conditions: value.spending_conditions,
             nwc_uri: value.nwc_uri,
+            scopes: value.scopes,
         }
     }
 }
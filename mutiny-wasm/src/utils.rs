@@ -1,17 +1,50 @@
 use core::time::Duration;
 use instant::SystemTime;
 use log::{debug, Level};
+use mutiny_core::startup::StartupProgress;
+use std::cell::RefCell;
+use std::panic::PanicInfo;
+use std::sync::{Arc, Once};
 use wasm_bindgen::prelude::*;
 
+thread_local! {
+    /// Called on panic to save a crash report, set once the wallet is loaded.
+    static CRASH_REPORTER: RefCell<Option<Box<dyn Fn(&PanicInfo)>>> = RefCell::new(None);
+    /// Progress of the wallet currently starting up, readable before it has loaded.
+    static STARTUP_PROGRESS: Arc<StartupProgress> = Arc::new(StartupProgress::default());
+}
+
 pub fn set_panic_hook() {
-    // When the `console_error_panic_hook` feature is enabled, we can call the
-    // `set_panic_hook` function at least once during initialization, and then
-    // we will get better error messages if our code ever panics.
-    //
-    // For more details see
-    // https://github.com/rustwasm/console_error_panic_hook#readme
-    #[cfg(feature = "console_error_panic_hook")]
-    console_error_panic_hook::set_once();
+    static SET_HOOK: Once = Once::new();
+    SET_HOOK.call_once(|| {
+        std::panic::set_hook(Box::new(|info| {
+            // When the `console_error_panic_hook` feature is enabled,
+            // we will get better error messages if our code ever panics.
+            //
+            // For more details see
+            // https://github.com/rustwasm/console_error_panic_hook#readme
+            #[cfg(feature = "console_error_panic_hook")]
+            console_error_panic_hook::hook(info);
+
+            let _ = CRASH_REPORTER.try_with(|reporter| {
+                if let Ok(reporter) = reporter.try_borrow() {
+                    if let Some(reporter) = reporter.as_ref() {
+                        reporter(info);
+                    }
+                }
+            });
+        }));
+    });
+}
+
+/// Sets the function used to save a crash report when we panic.
+pub fn set_crash_reporter(reporter: impl Fn(&PanicInfo) + 'static) {
+    CRASH_REPORTER.with(|r| *r.borrow_mut() = Some(Box::new(reporter)));
+}
+
+/// The startup progress shared with the wallet being loaded
+pub fn startup_progress() -> Arc<StartupProgress> {
+    STARTUP_PROGRESS.with(|p| p.clone())
 }
 
 #[wasm_bindgen(start)]